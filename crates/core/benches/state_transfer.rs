@@ -0,0 +1,82 @@
+//! Compares the state hand-off paths used by the node against the previous, copying, ones
+//! for large (50MB) contract states.
+//!
+//! Run with `cargo bench --bench state_transfer`.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use freenet::dev_tool::{StateStore, Storage};
+use freenet_stdlib::prelude::*;
+
+const STATE_SIZE: usize = 50 * 1024 * 1024;
+
+fn large_state() -> WrappedState {
+    WrappedState::new((0..STATE_SIZE).map(|i| i as u8).collect())
+}
+
+/// Reading a stored state, as done for every get and update of the contract.
+///
+/// States held by the memory cache of the store are handed out without being copied, the
+/// others are read back from the storage.
+fn state_store(c: &mut Criterion) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+    let open = |name: &str, cache_size: usize| {
+        let path = dir.path().join(name);
+        rt.block_on(async {
+            std::fs::create_dir_all(&path).unwrap();
+            let storage = Storage::new(&path).await.unwrap();
+            let mut store = StateStore::new(storage, cache_size as u32).unwrap();
+            store
+                .store(key, large_state(), Parameters::from(vec![]))
+                .await
+                .unwrap();
+            store
+        })
+    };
+    let cached = open("cached", 2 * STATE_SIZE);
+    let uncached = open("uncached", 1024);
+
+    let mut group = c.benchmark_group("state_store");
+    group.throughput(Throughput::Bytes(STATE_SIZE as u64));
+    group.sample_size(10);
+    group.bench_function("get_from_storage", |b| {
+        b.iter(|| black_box(rt.block_on(uncached.get(&key)).unwrap()))
+    });
+    group.bench_function("get_shared", |b| {
+        b.iter(|| black_box(rt.block_on(cached.get(&key)).unwrap()))
+    });
+    group.finish();
+}
+
+/// Writing a full state update into the (here simulated) wasm instance memory.
+fn wasm_write(c: &mut Criterion) {
+    let updates = [UpdateData::State(State::from(
+        large_state().as_ref().to_vec(),
+    ))];
+    let size = bincode::serialized_size(&updates[..]).unwrap() as usize;
+    let mut group = c.benchmark_group("wasm_write");
+    group.throughput(Throughput::Bytes(STATE_SIZE as u64));
+    group.sample_size(10);
+    group.bench_function("intermediate_buffer", |b| {
+        b.iter_batched_ref(
+            || vec![0u8; size],
+            |linear_mem| {
+                let serialized = bincode::serialize(&updates[..]).unwrap();
+                linear_mem.copy_from_slice(&serialized);
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("serialize_in_place", |b| {
+        b.iter_batched_ref(
+            || vec![0u8; size],
+            |linear_mem| bincode::serialize_into(&mut linear_mem[..], &updates[..]).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, state_store, wasm_write);
+criterion_main!(benches);
@@ -0,0 +1,17 @@
+//! Exports the messages of a session recorded by a node as seeds for the `peer_message` target.
+//!
+//! Usage: `cargo run --bin extract_seeds -- <recording> [<seeds dir>]`
+
+use std::path::PathBuf;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args_os().skip(1);
+    let recording = PathBuf::from(args.next().ok_or("missing the recording to extract from")?);
+    let dir = args
+        .next()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("seeds/peer_message"));
+    let written = freenet::fuzzing::seeds_from_recording(&recording, &dir)?;
+    println!("Wrote {written} new seeds to {}", dir.display());
+    Ok(())
+}
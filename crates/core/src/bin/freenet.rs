@@ -1,12 +1,15 @@
 use anyhow::Context;
 use clap::Parser;
 use freenet::{
-    config::{Config, ConfigArgs},
-    local_node::{Executor, NodeConfig, OperationMode},
+    config::{
+        check_config_file, Config, ConfigArgs, ConfigCommand, NodeCommand, ServiceDefinition,
+        ServicePlatform,
+    },
+    local_node::{replay_session, restore_snapshot, Executor, NodeConfig, OperationMode},
     run_local_node, run_network_node,
     server::serve_gateway,
 };
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc};
 
 async fn run(config: Config) -> anyhow::Result<()> {
     match config.mode {
@@ -17,7 +20,7 @@ async fn run(config: Config) -> anyhow::Result<()> {
 
 async fn run_local(config: Config) -> anyhow::Result<()> {
     tracing::info!("Starting freenet node in local mode");
-    let socket = config.ws_api;
+    let socket = config.ws_api.clone();
 
     let executor = Executor::from_config(Arc::new(config), None)
         .await
@@ -31,7 +34,9 @@ async fn run_local(config: Config) -> anyhow::Result<()> {
 async fn run_network(config: Config) -> anyhow::Result<()> {
     tracing::info!("Starting freenet node in network mode");
 
-    let clients = serve_gateway(config.ws_api).await;
+    let clients = serve_gateway(config.ws_api.clone())
+        .await
+        .with_context(|| "failed while serving the gateway")?;
     tracing::info!("Initializing node configuration");
 
     let node_config = NodeConfig::new(config)
@@ -46,6 +51,55 @@ async fn run_network(config: Config) -> anyhow::Result<()> {
     run_network_node(node).await
 }
 
+fn check_config(config: &ConfigArgs, file: Option<PathBuf>) -> anyhow::Result<()> {
+    let file = match file {
+        Some(file) => file,
+        None => match config.config_file()? {
+            Some(file) => file,
+            None => {
+                println!("No configuration file found");
+                return Ok(());
+            }
+        },
+    };
+    let diagnostics =
+        check_config_file(&file).with_context(|| format!("failed reading {}", file.display()))?;
+    if diagnostics.is_empty() {
+        println!("{}: configuration is valid", file.display());
+        return Ok(());
+    }
+    for diagnostic in &diagnostics {
+        eprintln!("{}: {diagnostic}", file.display());
+    }
+    anyhow::bail!("found {} problems in the configuration", diagnostics.len())
+}
+
+fn install_service(
+    config: &Config,
+    platform: Option<ServicePlatform>,
+    output: Option<PathBuf>,
+    print: bool,
+) -> anyhow::Result<()> {
+    let platform = platform.unwrap_or_else(ServicePlatform::current);
+    let executable = std::env::current_exe().context("failed locating the node executable")?;
+    let definition = ServiceDefinition::new(config, executable).render(platform);
+    if print {
+        print!("{definition}");
+        return Ok(());
+    }
+    let Some(path) = output.or_else(|| platform.install_path()) else {
+        anyhow::bail!("no default location for {platform:?} services, set it with --output");
+    };
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, definition)
+        .with_context(|| format!("failed writing {}", path.display()))?;
+    println!("Service definition written to {}", path.display());
+    println!("Enable it with: {}", platform.enable_instructions(&path));
+    Ok(())
+}
+
 fn main() -> anyhow::Result<()> {
     freenet::config::set_logger(None, None);
     let rt = tokio::runtime::Builder::new_multi_thread()
@@ -58,13 +112,53 @@ fn main() -> anyhow::Result<()> {
         .build()
         .unwrap();
     rt.block_on(async move {
-        let config = ConfigArgs::parse();
+        let mut config = ConfigArgs::parse();
         if config.version {
             println!("Freenet version: {}", config.current_version());
             return Ok(());
         }
+        let command = config.command.take();
+        if let Some(NodeCommand::Config {
+            command: ConfigCommand::Check { file },
+        }) = command
+        {
+            return check_config(&config, file);
+        }
         let config = config.build().await?;
-        run(config).await
+        match command {
+            Some(NodeCommand::Restore { snapshot }) => {
+                let restored = restore_snapshot(&config, &snapshot).await?;
+                println!("Restored {restored} contract states from {snapshot:?}");
+                Ok(())
+            }
+            Some(NodeCommand::Replay { recording }) => {
+                let report = replay_session(config, &recording).await?;
+                println!(
+                    "Replayed {} messages as {} (location: {:?})",
+                    report.steps.len(),
+                    report.peer,
+                    report.location
+                );
+                let start = report.steps.first().map(|step| step.received_at);
+                for step in report.steps {
+                    let elapsed = start
+                        .map(|start| (step.received_at - start).num_milliseconds())
+                        .unwrap_or_default();
+                    println!("+{elapsed}ms {} -> {}", step.from, step.message);
+                    for (peer, msg) in step.sent {
+                        println!("    sent to {peer}: {msg}");
+                    }
+                }
+                Ok(())
+            }
+            Some(NodeCommand::InstallService {
+                platform,
+                output,
+                print,
+            }) => install_service(&config, platform, output, print),
+            Some(NodeCommand::Config { .. }) => unreachable!("handled before building the config"),
+            None => run(config).await,
+        }
     })?;
     Ok(())
 }
@@ -10,7 +10,7 @@ use freenet_stdlib::{
 };
 use futures::stream::FuturesUnordered;
 use futures::{future::BoxFuture, FutureExt, StreamExt};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
 use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
@@ -18,15 +18,23 @@ use std::{convert::Infallible, fmt::Debug};
 use tracing::Instrument;
 
 use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::sync::{
+    mpsc::{self, UnboundedSender},
+    watch,
+};
+use tokio::task::AbortHandle;
 
+use crate::config::AccessLogConfig;
 use crate::contract::{ClientResponsesReceiver, ContractHandlerEvent};
 use crate::message::{NodeEvent, QueryResult};
-use crate::node::OpManager;
+use crate::node::{load_shedding::QueuedEvents, OpManager};
 use crate::operations::{get, put, update, OpError};
 use crate::{config::GlobalExecutor, contract::StoreResponse};
 
 pub(crate) mod combinator;
+pub(crate) mod limits;
+pub(crate) mod node_queries;
+pub(crate) mod scoped_tokens;
 #[cfg(feature = "websocket")]
 pub(crate) mod websocket;
 
@@ -160,6 +168,23 @@ pub trait ClientEventsProxy {
         id: ClientId,
         response: Result<HostResponse, ClientError>,
     ) -> BoxFuture<Result<(), ClientError>>;
+
+    /// Takes the receiving end of the queries the client applications make to the node itself,
+    /// if the proxy accepts any.
+    fn node_queries(&mut self) -> Option<node_queries::NodeQueries> {
+        None
+    }
+
+    /// The events queued in the contract handler the proxy sheds load on, if it does.
+    fn queued_events(&self) -> Option<QueuedEvents> {
+        None
+    }
+
+    /// Takes the sending end of the access log policy of the proxy, if it logs requests, so the
+    /// node can apply the runtime settings to it.
+    fn access_log(&mut self) -> Option<watch::Sender<AccessLogConfig>> {
+        None
+    }
 }
 
 /// Process client events.
@@ -173,6 +198,8 @@ where
     ClientEv: ClientEventsProxy + Send + 'static,
 {
     let mut results = FuturesUnordered::new();
+    // requests being processed for each client, so they can be cancelled if the client goes away
+    let mut in_flight: HashMap<ClientId, Vec<AbortHandle>> = HashMap::new();
     loop {
         tokio::select! {
             client_request = client_events.recv() => {
@@ -194,7 +221,21 @@ where
                     }
                 };
                 let cli_id = req.client_id;
-                let res = process_open_request(req, op_manager.clone()).await;
+                if let ClientRequest::Disconnect { cause } = &*req.request {
+                    if let Some(cause) = cause {
+                        tracing::debug!(%cli_id, "client disconnected: {cause}");
+                    }
+                    for task in in_flight.remove(&cli_id).into_iter().flatten() {
+                        task.abort();
+                    }
+                    // nobody is left waiting for the operations the client started
+                    node_controller.send(NodeEvent::CancelClientTransactions(cli_id)).await.ok();
+                    continue;
+                }
+                let (abort_handle, res) = process_open_request(req, op_manager.clone()).await;
+                let client_tasks = in_flight.entry(cli_id).or_default();
+                client_tasks.retain(|task| !task.is_finished());
+                client_tasks.push(abort_handle);
                 results.push(async move {
                     match res.await {
                         Ok(Some(Either::Left(res))) => (cli_id, Ok(Some(res))),
@@ -209,6 +250,10 @@ where
                             tracing::debug!("client disconnected");
                             (cli_id, Err(ClientError::from(ErrorKind::Disconnect)))
                         }
+                        Err(Error::Panic(err)) if err.is_cancelled() => {
+                            tracing::debug!(%cli_id, "client request cancelled");
+                            (cli_id, Ok(None))
+                        }
                         Err(err) => (cli_id, Err(ErrorKind::OperationError { cause: format!("{err}").into() }.into())),
                     }
                 });
@@ -277,7 +322,10 @@ enum Error {
 async fn process_open_request(
     mut request: OpenRequest<'static>,
     op_manager: Arc<OpManager>,
-) -> BoxFuture<'static, Result<Option<Either<QueryResult, mpsc::Receiver<QueryResult>>>, Error>> {
+) -> (
+    AbortHandle,
+    BoxFuture<'static, Result<Option<Either<QueryResult, mpsc::Receiver<QueryResult>>>, Error>>,
+) {
     let (callback_tx, callback_rx) = if matches!(
         &*request.request,
         ClientRequest::NodeQueries(_) | ClientRequest::ContractOp(ContractRequest::Get { .. })
@@ -315,6 +363,30 @@ async fn process_open_request(
                             "Received put from user event",
                         );
 
+                        // rejected here rather than at the peers the contract is forwarded to
+                        match op_manager
+                            .notify_contract_handler(ContractHandlerEvent::ValidatePutQuery {
+                                contract: contract.clone(),
+                                state: state.clone(),
+                                related_contracts: related_contracts.clone(),
+                            })
+                            .await
+                        {
+                            Ok(ContractHandlerEvent::ValidatePutResponse {
+                                result: Ok(()),
+                                ..
+                            }) => {}
+                            Ok(ContractHandlerEvent::ValidatePutResponse {
+                                key,
+                                result: Err(err),
+                            }) => {
+                                tracing::debug!(contract = %key, "Rejected put: {err}");
+                                return Err(Error::Executor(err));
+                            }
+                            Err(err) => return Err(Error::Contract(err)),
+                            Ok(_) => return Err(Error::Op(OpError::UnexpectedOpState)),
+                        }
+
                         let op = put::start_op(
                             contract,
                             related_contracts,
@@ -349,10 +421,21 @@ async fn process_open_request(
 
                         let related_contracts = RelatedContracts::default();
 
+                        let update = match data {
+                            UpdateData::State(state) => {
+                                Either::Left(WrappedState::new(state.into_bytes()))
+                            }
+                            UpdateData::Delta(delta) => Either::Right(delta),
+                            _ => {
+                                tracing::error!(%key, "unsupported update data kind");
+                                return Err(OpError::UnexpectedOpState.into());
+                            }
+                        };
+
                         let new_state = match op_manager
                             .notify_contract_handler(ContractHandlerEvent::UpdateQuery {
                                 key,
-                                data,
+                                update,
                                 related_contracts: related_contracts.clone(),
                             })
                             .await
@@ -396,6 +479,9 @@ async fn process_open_request(
                             return Err(Error::Disconnected);
                         };
 
+                        // upgraded contracts are redirected to their latest version
+                        let key = get::latest_version(&op_manager, key).await;
+
                         let (state, contract) = match op_manager
                             .notify_contract_handler(ContractHandlerEvent::GetQuery {
                                 key,
@@ -556,19 +642,24 @@ async fn process_open_request(
         Ok(None)
     };
 
-    GlobalExecutor::spawn(fut.instrument(tracing::info_span!(
+    let task = GlobalExecutor::spawn(fut.instrument(tracing::info_span!(
         parent: tracing::Span::current(),
         "process_client_request"
-    )))
-    .map(|res| match res {
-        Ok(Ok(res)) => Ok(res),
-        Ok(Err(err)) => Err(err),
-        Err(err) => {
-            tracing::error!("Error processing client request: {}", err);
-            Err(Error::from(err))
-        }
-    })
-    .boxed()
+    )));
+    let abort_handle = task.abort_handle();
+    let res = task
+        .map(|res| match res {
+            Ok(Ok(res)) => Ok(res),
+            Ok(Err(err)) => Err(err),
+            Err(err) => {
+                if !err.is_cancelled() {
+                    tracing::error!("Error processing client request: {}", err);
+                }
+                Err(Error::from(err))
+            }
+        })
+        .boxed();
+    (abort_handle, res)
 }
 
 pub(crate) mod test {
@@ -19,6 +19,10 @@ pub struct ClientEventsCombinator<const N: usize> {
     /// receiving end of the different client applications from the node
     clients: [Sender<(ClientId, HostResult)>; N],
     /// a map of the individual protocols, external, sending client events ids to an internal list of ids
+    ///
+    /// The node only ever sees the internal ids: the transactions of a client, and their
+    /// cancellation once it disconnects, are tracked by them. Disconnections are requests too, so
+    /// they are remapped like any other.
     external_clients: [HashMap<ClientId, ClientId>; N],
     /// a map of the external id to which protocol it belongs (represented by the index in the array)
     /// and the original id (reverse of indexes)
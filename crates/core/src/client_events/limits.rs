@@ -0,0 +1,114 @@
+//! Enforcement of the maximum sizes of the payloads sent by clients.
+
+use freenet_stdlib::{
+    client_api::{ClientRequest, ContractRequest},
+    prelude::*,
+};
+
+use crate::config::PayloadLimits;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Payload {
+    Message,
+    Body,
+    ContractCode,
+    State,
+    Delta,
+}
+
+impl std::fmt::Display for Payload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Payload::Message => write!(f, "message"),
+            Payload::Body => write!(f, "request body"),
+            Payload::ContractCode => write!(f, "contract code"),
+            Payload::State => write!(f, "state"),
+            Payload::Delta => write!(f, "delta"),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("payload too large: {payload} of {size} bytes exceeds the maximum of {max} bytes")]
+pub(crate) struct PayloadTooLarge {
+    pub payload: Payload,
+    pub size: usize,
+    pub max: usize,
+}
+
+impl PayloadLimits {
+    pub(crate) fn check(&self, payload: Payload, size: usize) -> Result<(), PayloadTooLarge> {
+        let max = match payload {
+            Payload::Message => self.max_message_size,
+            Payload::Body => self.max_body_size,
+            Payload::ContractCode => self.max_contract_size,
+            Payload::State => self.max_state_size,
+            Payload::Delta => self.max_delta_size,
+        };
+        if size > max {
+            return Err(PayloadTooLarge { payload, size, max });
+        }
+        Ok(())
+    }
+
+    /// Checks the contract code, state and delta carried by a client request.
+    pub(crate) fn check_request(&self, request: &ClientRequest<'_>) -> Result<(), PayloadTooLarge> {
+        let ClientRequest::ContractOp(request) = request else {
+            return Ok(());
+        };
+        match request {
+            ContractRequest::Put {
+                contract, state, ..
+            } => {
+                if let ContractContainer::Wasm(ContractWasmAPIVersion::V1(contract)) = contract {
+                    self.check(Payload::ContractCode, contract.code().data().len())?;
+                }
+                self.check(Payload::State, state.size())
+            }
+            ContractRequest::Update { data, .. } => match data {
+                UpdateData::State(state) | UpdateData::RelatedState { state, .. } => {
+                    self.check(Payload::State, state.size())
+                }
+                UpdateData::Delta(delta) | UpdateData::RelatedDelta { delta, .. } => {
+                    self.check(Payload::Delta, delta.size())
+                }
+                UpdateData::StateAndDelta { state, delta }
+                | UpdateData::RelatedStateAndDelta { state, delta, .. } => {
+                    self.check(Payload::State, state.size())?;
+                    self.check(Payload::Delta, delta.size())
+                }
+                _ => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oversized_update() {
+        let limits = PayloadLimits {
+            max_delta_size: 8,
+            ..Default::default()
+        };
+        let update = |delta: Vec<u8>| -> ClientRequest<'static> {
+            ContractRequest::Update {
+                key: ContractKey::from(ContractInstanceId::new([1; 32])),
+                data: UpdateData::Delta(StateDelta::from(delta)),
+            }
+            .into()
+        };
+        assert_eq!(limits.check_request(&update(vec![0; 8])), Ok(()));
+        assert_eq!(
+            limits.check_request(&update(vec![0; 9])),
+            Err(PayloadTooLarge {
+                payload: Payload::Delta,
+                size: 9,
+                max: 8
+            })
+        );
+    }
+}
@@ -0,0 +1,233 @@
+//! Requests from client applications answered by the node itself, for the information the
+//! client protocol has no request for, like the update journal of a contract.
+//!
+//! Client proxies hand the receiving end of their queries to the node when it starts, see
+//! [`ClientEventsProxy::node_queries`](super::ClientEventsProxy::node_queries). Until then, or if
+//! the node never takes them, queries time out.
+
+use std::{sync::Arc, time::Duration};
+
+use freenet_stdlib::prelude::{ContractInstanceId, ContractKey};
+use futures::{stream, StreamExt};
+use tokio::sync::{mpsc, oneshot, watch};
+
+use crate::{
+    client_events::AuthToken,
+    config::GlobalExecutor,
+    contract::{
+        storages::{
+            index::Resolution,
+            quota::{self, PinnedContract},
+        },
+        ContractHandlerEvent, JournalEntry,
+    },
+    message::{NodeEvent, Transaction},
+    node::{diagnostics::NodeDiagnostics, gossip::GossipChannel, OpManager},
+    operations::get::{self, ContractHead},
+    wasm_runtime::SecretsConsent,
+};
+
+/// Queries waiting for the node to pick them up.
+const MAX_PENDING_QUERIES: usize = 64;
+/// Time after which a query the node didn't answer fails.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub(crate) enum NodeQueryKind {
+    /// The last `last` updates applied to the state of a contract.
+    UpdateJournal { key: ContractKey, last: usize },
+    /// Whether the contracts are known to the network, without fetching them.
+    ContractHeads { keys: Vec<ContractKey> },
+    /// SDP offer of a browser joining the network as a peer over WebRTC, with the auth token of
+    /// the request.
+    WebRtcOffer { offer: String, token: AuthToken },
+    /// Subscription to the diagnostics published by the node.
+    Diagnostics,
+    /// Pins or unpins a contract in the node.
+    Pin { id: ContractInstanceId, pin: bool },
+    /// The contracts pinned in the node.
+    PinnedContracts,
+    /// The stored contracts whose encoded id starts with a prefix.
+    ResolvePrefix { prefix: String },
+    /// Cancels a transaction in progress in the node.
+    Cancel { tx: Transaction },
+    /// The gossip channel of the node, to publish and subscribe to its topics.
+    Gossip,
+    /// Prompts asking the user to let applications read their namespace of delegate secrets.
+    SecretsConsents,
+    /// Answers a consent prompt.
+    ResolveSecretsConsent { request_id: u32, allowed: bool },
+}
+
+#[derive(Debug)]
+pub(crate) enum NodeQueryResult {
+    UpdateJournal(Vec<JournalEntry>),
+    ContractHeads(Vec<ContractHead>),
+    /// SDP answer to the offer of a browser peer.
+    WebRtcAnswer(String),
+    Diagnostics(watch::Receiver<Option<NodeDiagnostics>>),
+    /// Whether the pinned status of the contract changed.
+    Pinned(bool),
+    PinnedContracts(Vec<PinnedContract>),
+    Resolution(Resolution),
+    /// The cancellation was handed to the node.
+    Cancelled,
+    Gossip(Arc<GossipChannel>),
+    SecretsConsents(Vec<SecretsConsent>),
+    /// Whether the consent prompt was pending.
+    SecretsConsentResolved(bool),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum NodeQueryError {
+    #[error("node unavailable")]
+    NodeUnavailable,
+    #[error("the node didn't answer in time")]
+    Timeout,
+    #[error("{0}")]
+    Failed(String),
+}
+
+pub(crate) struct NodeQuery {
+    kind: NodeQueryKind,
+    response: oneshot::Sender<Result<NodeQueryResult, NodeQueryError>>,
+}
+
+/// Receiving end of the queries of a client proxy, taken by the node serving them.
+pub struct NodeQueries(mpsc::Receiver<NodeQuery>);
+
+/// Sending end of the queries, shared by the endpoints of a client proxy.
+#[derive(Clone)]
+pub(crate) struct NodeQuerySender(mpsc::Sender<NodeQuery>);
+
+pub(crate) fn channel() -> (NodeQuerySender, NodeQueries) {
+    let (tx, rx) = mpsc::channel(MAX_PENDING_QUERIES);
+    (NodeQuerySender(tx), NodeQueries(rx))
+}
+
+impl NodeQuerySender {
+    pub async fn query(&self, kind: NodeQueryKind) -> Result<NodeQueryResult, NodeQueryError> {
+        let (response, result) = oneshot::channel();
+        let query = async {
+            self.0
+                .send(NodeQuery { kind, response })
+                .await
+                .map_err(|_| NodeQueryError::NodeUnavailable)?;
+            result.await.map_err(|_| NodeQueryError::NodeUnavailable)?
+        };
+        tokio::time::timeout(QUERY_TIMEOUT, query)
+            .await
+            .unwrap_or(Err(NodeQueryError::Timeout))
+    }
+}
+
+/// Answers the queries of the client proxies until all of them are closed.
+pub(crate) async fn serve(op_manager: Arc<OpManager>, queries: Vec<NodeQueries>) {
+    let mut queries = stream::select_all(queries.into_iter().map(|NodeQueries(rx)| {
+        stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|query| (query, rx))
+        })
+        .boxed()
+    }));
+    while let Some(NodeQuery { kind, response }) = queries.next().await {
+        let op_manager = op_manager.clone();
+        GlobalExecutor::spawn(async move {
+            let _ = response.send(answer(&op_manager, kind).await);
+        });
+    }
+}
+
+async fn answer(
+    op_manager: &Arc<OpManager>,
+    kind: NodeQueryKind,
+) -> Result<NodeQueryResult, NodeQueryError> {
+    match kind {
+        NodeQueryKind::UpdateJournal { key, last } => match op_manager
+            .notify_contract_handler(ContractHandlerEvent::UpdateJournalQuery { key, last })
+            .await
+            .map_err(|err| NodeQueryError::Failed(err.to_string()))?
+        {
+            ContractHandlerEvent::UpdateJournalResponse { entries, .. } => entries
+                .map(NodeQueryResult::UpdateJournal)
+                .map_err(|err| NodeQueryError::Failed(err.to_string())),
+            other => Err(NodeQueryError::Failed(format!(
+                "unexpected contract handler response: {other}"
+            ))),
+        },
+        NodeQueryKind::ContractHeads { keys } => {
+            futures::future::try_join_all(keys.into_iter().map(|key| get::probe(op_manager, key)))
+                .await
+                .map(NodeQueryResult::ContractHeads)
+                .map_err(|err| NodeQueryError::Failed(err.to_string()))
+        }
+        NodeQueryKind::WebRtcOffer { offer, token } => {
+            let signaling = op_manager.webrtc.get().ok_or_else(|| {
+                NodeQueryError::Failed("browser peers are not accepted by this node".to_owned())
+            })?;
+            signaling
+                .accept_offer(offer, token)
+                .await
+                .map(NodeQueryResult::WebRtcAnswer)
+                .map_err(|err| NodeQueryError::Failed(err.to_string()))
+        }
+        NodeQueryKind::Diagnostics => {
+            Ok(NodeQueryResult::Diagnostics(op_manager.health.subscribe()))
+        }
+        NodeQueryKind::Pin { id, pin } => quota::request_pin(op_manager.clone(), id, pin)
+            .await
+            .map(NodeQueryResult::Pinned)
+            .map_err(|err| NodeQueryError::Failed(err.to_string())),
+        NodeQueryKind::PinnedContracts => quota::pinned_contracts(op_manager)
+            .await
+            .map(NodeQueryResult::PinnedContracts)
+            .map_err(|err| NodeQueryError::Failed(err.to_string())),
+        NodeQueryKind::ResolvePrefix { prefix } => match op_manager
+            .notify_contract_handler(ContractHandlerEvent::ResolvePrefixQuery { prefix })
+            .await
+            .map_err(|err| NodeQueryError::Failed(err.to_string()))?
+        {
+            ContractHandlerEvent::ResolvePrefixResponse { resolution, .. } => {
+                Ok(NodeQueryResult::Resolution(resolution))
+            }
+            other => Err(NodeQueryError::Failed(format!(
+                "unexpected contract handler response: {other}"
+            ))),
+        },
+        NodeQueryKind::Cancel { tx } => op_manager
+            .notify_node_event(NodeEvent::CancelTransaction(tx))
+            .await
+            .map(|()| NodeQueryResult::Cancelled)
+            .map_err(|err| NodeQueryError::Failed(err.to_string())),
+        NodeQueryKind::Gossip => Ok(NodeQueryResult::Gossip(op_manager.gossip.clone())),
+        NodeQueryKind::SecretsConsents => match op_manager
+            .notify_contract_handler(ContractHandlerEvent::SecretsConsentsQuery)
+            .await
+            .map_err(|err| NodeQueryError::Failed(err.to_string()))?
+        {
+            ContractHandlerEvent::SecretsConsentsResponse { pending } => {
+                Ok(NodeQueryResult::SecretsConsents(pending))
+            }
+            other => Err(NodeQueryError::Failed(format!(
+                "unexpected contract handler response: {other}"
+            ))),
+        },
+        NodeQueryKind::ResolveSecretsConsent {
+            request_id,
+            allowed,
+        } => match op_manager
+            .notify_contract_handler(ContractHandlerEvent::ResolveSecretsConsentQuery {
+                request_id,
+                allowed,
+            })
+            .await
+            .map_err(|err| NodeQueryError::Failed(err.to_string()))?
+        {
+            ContractHandlerEvent::ResolveSecretsConsentResponse { resolved, .. } => resolved
+                .map(NodeQueryResult::SecretsConsentResolved)
+                .map_err(|err| NodeQueryError::Failed(err.to_string())),
+            other => Err(NodeQueryError::Failed(format!(
+                "unexpected contract handler response: {other}"
+            ))),
+        },
+    }
+}
@@ -0,0 +1,382 @@
+//! Auth tokens delegated by web apps to the contract UIs they embed.
+//!
+//! The gateway assigns each web app a token tied to its contract. A composite app can mint
+//! sub-tokens out of its own, restricted to some contracts and operations, and hand them to the
+//! frames it embeds instead of sharing its own token. Sub-tokens can in turn be delegated, but
+//! only with a subset of their own scope and never outliving it.
+//!
+//! Tokens the gateway doesn't know about, or no longer knows about, are rejected. Requests
+//! without a token are only accepted from clients on the local machine, and are not restricted.
+//!
+//! The number of tokens is bounded: past the limit, assigning a token evicts the one closest to
+//! expiring, and delegating fails.
+
+use std::{
+    collections::HashSet,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use freenet_stdlib::{
+    client_api::{ClientRequest, ContractRequest},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+
+use super::AuthToken;
+
+/// Prefix of delegated tokens.
+const SCOPED_TOKEN_PREFIX: &str = "scoped-";
+/// Lifetime of the tokens assigned by the gateway to web apps.
+pub(crate) const ASSIGNED_TOKEN_TTL: Duration = Duration::from_secs(60 * 60 * 24);
+/// Maximum lifetime of a delegated token.
+pub(crate) const MAX_DELEGATED_TOKEN_TTL: Duration = Duration::from_secs(60 * 60 * 24);
+/// Maximum number of live tokens, assigned and delegated.
+const MAX_TOKENS: usize = 10_000;
+/// Maximum number of live tokens delegated directly from the same token.
+const MAX_DELEGATED_PER_TOKEN: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum TokenOperation {
+    Get,
+    Put,
+    Update,
+    Subscribe,
+    Delegate,
+    /// Pin a contract in the node, see [`quota`](crate::contract::storages::quota).
+    Pin,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub(crate) enum TokenError {
+    #[error("unknown or expired token")]
+    UnknownToken,
+    #[error("the token is not allowed to {0:?} contract {1}")]
+    ContractNotAllowed(TokenOperation, ContractInstanceId),
+    #[error("the token is not allowed to perform {0:?} operations")]
+    OperationNotAllowed(TokenOperation),
+    #[error("the request is not allowed for delegated tokens")]
+    RequestNotAllowed,
+    #[error("a token can only be delegated a subset of its own scope")]
+    ScopeEscalation,
+    #[error("too many delegated tokens")]
+    TooManyTokens,
+    #[error("the token was not assigned to the web app of contract {0}")]
+    NotOwner(ContractInstanceId),
+}
+
+#[derive(Debug, Clone)]
+enum Scope {
+    /// Token assigned by the gateway to the web app of a contract, unrestricted.
+    Assigned { contract: ContractInstanceId },
+    Delegated {
+        contracts: HashSet<ContractInstanceId>,
+        operations: HashSet<TokenOperation>,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct TokenEntry {
+    scope: Scope,
+    parent: Option<AuthToken>,
+    expires: Instant,
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct TokenScopes(Arc<DashMap<AuthToken, TokenEntry>>);
+
+impl TokenScopes {
+    /// Registers a token assigned by the gateway to the web app of `contract`, which can be used
+    /// to delegate sub-tokens.
+    pub fn assign(&self, token: AuthToken, contract: ContractInstanceId) {
+        self.purge_expired();
+        if self.0.len() >= MAX_TOKENS {
+            self.evict_closest_to_expiry();
+        }
+        self.0.insert(
+            token,
+            TokenEntry {
+                scope: Scope::Assigned { contract },
+                parent: None,
+                expires: Instant::now() + ASSIGNED_TOKEN_TTL,
+            },
+        );
+    }
+
+    /// Mints a sub-token of `parent` restricted to the given contracts and operations.
+    pub fn delegate(
+        &self,
+        parent: &AuthToken,
+        contracts: HashSet<ContractInstanceId>,
+        operations: HashSet<TokenOperation>,
+        ttl: Duration,
+    ) -> Result<(AuthToken, Duration), TokenError> {
+        self.purge_expired();
+        let now = Instant::now();
+        let parent_entry = self
+            .0
+            .get(parent)
+            .filter(|entry| entry.expires > now)
+            .ok_or(TokenError::UnknownToken)?
+            .clone();
+        if let Scope::Delegated {
+            contracts: allowed_contracts,
+            operations: allowed_operations,
+        } = &parent_entry.scope
+        {
+            if !contracts.is_subset(allowed_contracts) || !operations.is_subset(allowed_operations)
+            {
+                return Err(TokenError::ScopeEscalation);
+            }
+        }
+        let delegated = self
+            .0
+            .iter()
+            .filter(|entry| entry.parent.as_ref() == Some(parent))
+            .count();
+        if delegated >= MAX_DELEGATED_PER_TOKEN || self.0.len() >= MAX_TOKENS {
+            return Err(TokenError::TooManyTokens);
+        }
+        let expires = (now + ttl.min(MAX_DELEGATED_TOKEN_TTL)).min(parent_entry.expires);
+        let token = AuthToken::from(format!("{SCOPED_TOKEN_PREFIX}{}", AuthToken::generate()));
+        self.0.insert(
+            token.clone(),
+            TokenEntry {
+                scope: Scope::Delegated {
+                    contracts,
+                    operations,
+                },
+                parent: Some(parent.clone()),
+                expires,
+            },
+        );
+        Ok((token, expires.duration_since(now)))
+    }
+
+    /// Revokes a token along with all the tokens delegated from it.
+    pub fn revoke(&self, token: &AuthToken) {
+        let mut revoked = vec![token.clone()];
+        while let Some(token) = revoked.pop() {
+            self.0.remove(&token);
+            revoked.extend(
+                self.0
+                    .iter()
+                    .filter(|entry| entry.parent.as_ref() == Some(&token))
+                    .map(|entry| entry.key().clone()),
+            );
+        }
+    }
+
+    /// Checks whether a request made with the given token is within the scope of the token.
+    pub fn authorize(
+        &self,
+        token: Option<&AuthToken>,
+        request: &ClientRequest<'_>,
+    ) -> Result<(), TokenError> {
+        let (operation, contract) = match request {
+            ClientRequest::ContractOp(ContractRequest::Get { key, .. }) => {
+                (TokenOperation::Get, Some(*key.id()))
+            }
+            ClientRequest::ContractOp(ContractRequest::Put { contract, .. }) => {
+                (TokenOperation::Put, Some(*contract.key().id()))
+            }
+            ClientRequest::ContractOp(ContractRequest::Update { key, .. }) => {
+                (TokenOperation::Update, Some(*key.id()))
+            }
+            ClientRequest::ContractOp(ContractRequest::Subscribe { key, .. }) => {
+                (TokenOperation::Subscribe, Some(*key.id()))
+            }
+            ClientRequest::DelegateOp(_) => (TokenOperation::Delegate, None),
+            ClientRequest::Authenticate { .. }
+            | ClientRequest::Disconnect { .. }
+            | ClientRequest::Close => return Ok(()),
+            _ => return self.authorize_operation(token, None),
+        };
+        self.authorize_operation(token, Some((operation, contract)))
+    }
+
+    /// Checks whether the token allows an operation, on the given contract if any.
+    ///
+    /// Without an operation, only tokens which aren't restricted to some operations are allowed.
+    /// Requests without a token are allowed, since only local clients can make them.
+    pub fn authorize_operation(
+        &self,
+        token: Option<&AuthToken>,
+        operation: Option<(TokenOperation, Option<ContractInstanceId>)>,
+    ) -> Result<(), TokenError> {
+        let Some(token) = token else {
+            return Ok(());
+        };
+        let entry = self
+            .0
+            .get(token)
+            .filter(|entry| entry.expires > Instant::now());
+        let (contracts, operations) = match entry.as_ref().map(|entry| &entry.scope) {
+            Some(Scope::Delegated {
+                contracts,
+                operations,
+            }) => (contracts, operations),
+            Some(Scope::Assigned { .. }) => return Ok(()),
+            None => return Err(TokenError::UnknownToken),
+        };
+        let Some((operation, contract)) = operation else {
+            return Err(TokenError::RequestNotAllowed);
+        };
+        if !operations.contains(&operation) {
+            return Err(TokenError::OperationNotAllowed(operation));
+        }
+        match contract {
+            Some(contract) if !contracts.contains(&contract) => {
+                Err(TokenError::ContractNotAllowed(operation, contract))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks whether the token was assigned to the web app of the contract, which is then
+    /// trusted to be published by its author.
+    ///
+    /// As for any request, requests without a token are allowed, since only local clients can
+    /// make them. Delegated tokens never are.
+    pub fn authorize_owner(
+        &self,
+        token: Option<&AuthToken>,
+        contract: &ContractInstanceId,
+    ) -> Result<(), TokenError> {
+        let Some(token) = token else {
+            return Ok(());
+        };
+        let entry = self
+            .0
+            .get(token)
+            .filter(|entry| entry.expires > Instant::now())
+            .ok_or(TokenError::UnknownToken)?;
+        match &entry.scope {
+            Scope::Assigned { contract: assigned } if assigned == contract => Ok(()),
+            _ => Err(TokenError::NotOwner(*contract)),
+        }
+    }
+
+    fn purge_expired(&self) {
+        let now = Instant::now();
+        self.0.retain(|_, entry| entry.expires > now);
+    }
+
+    fn evict_closest_to_expiry(&self) {
+        let closest = self
+            .0
+            .iter()
+            .min_by_key(|entry| entry.expires)
+            .map(|entry| entry.key().clone());
+        if let Some(token) = closest {
+            self.revoke(&token);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(id: ContractInstanceId) -> ClientRequest<'static> {
+        ContractRequest::Get {
+            key: ContractKey::from(id),
+            return_contract_code: false,
+        }
+        .into()
+    }
+
+    #[test]
+    fn delegated_scope() {
+        let scopes = TokenScopes::default();
+        let (app, embedded, other) = (
+            ContractInstanceId::new([1; 32]),
+            ContractInstanceId::new([2; 32]),
+            ContractInstanceId::new([3; 32]),
+        );
+        let parent = AuthToken::generate();
+        scopes.assign(parent.clone(), app);
+        assert_eq!(scopes.authorize(Some(&parent), &get(other)), Ok(()));
+        assert_eq!(scopes.authorize_owner(Some(&parent), &app), Ok(()));
+        assert_eq!(
+            scopes.authorize_owner(Some(&parent), &other),
+            Err(TokenError::NotOwner(other))
+        );
+
+        let (sub_token, _) = scopes
+            .delegate(
+                &parent,
+                [embedded].into(),
+                [TokenOperation::Get].into(),
+                Duration::from_secs(60),
+            )
+            .unwrap();
+        assert_eq!(scopes.authorize(Some(&sub_token), &get(embedded)), Ok(()));
+        assert_eq!(
+            scopes.authorize_owner(Some(&sub_token), &embedded),
+            Err(TokenError::NotOwner(embedded))
+        );
+        assert_eq!(
+            scopes.authorize(Some(&sub_token), &get(app)),
+            Err(TokenError::ContractNotAllowed(TokenOperation::Get, app))
+        );
+
+        // sub-tokens can't widen their scope
+        assert_eq!(
+            scopes.delegate(
+                &sub_token,
+                [embedded].into(),
+                [TokenOperation::Get, TokenOperation::Update].into(),
+                Duration::from_secs(60),
+            ),
+            Err(TokenError::ScopeEscalation)
+        );
+        let (nested, _) = scopes
+            .delegate(
+                &sub_token,
+                [embedded].into(),
+                [TokenOperation::Get].into(),
+                Duration::from_secs(60),
+            )
+            .unwrap();
+
+        scopes.revoke(&parent);
+        assert_eq!(
+            scopes.authorize(Some(&nested), &get(embedded)),
+            Err(TokenError::UnknownToken)
+        );
+    }
+
+    #[test]
+    fn unknown_tokens_are_rejected() {
+        let scopes = TokenScopes::default();
+        let contract = ContractInstanceId::new([1; 32]);
+        assert_eq!(
+            scopes.authorize(Some(&AuthToken::generate()), &get(contract)),
+            Err(TokenError::UnknownToken)
+        );
+        assert_eq!(scopes.authorize(None, &get(contract)), Ok(()));
+    }
+
+    #[test]
+    fn delegation_is_bounded() {
+        let scopes = TokenScopes::default();
+        let contract = ContractInstanceId::new([1; 32]);
+        let parent = AuthToken::generate();
+        scopes.assign(parent.clone(), contract);
+        let delegate = || {
+            scopes.delegate(
+                &parent,
+                [contract].into(),
+                [TokenOperation::Get].into(),
+                Duration::from_secs(60),
+            )
+        };
+        for _ in 0..MAX_DELEGATED_PER_TOKEN {
+            delegate().unwrap();
+        }
+        assert_eq!(delegate(), Err(TokenError::TooManyTokens));
+    }
+}
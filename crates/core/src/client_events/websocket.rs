@@ -11,7 +11,7 @@ use axum::{
     },
     http::StatusCode,
     response::{IntoResponse, Response},
-    routing::get,
+    routing::{delete, get, post, put},
     Extension, Router,
 };
 use freenet_stdlib::{
@@ -22,19 +22,35 @@ use futures::{future::BoxFuture, stream::SplitSink, FutureExt, SinkExt, StreamEx
 use headers::Header;
 use serde::Deserialize;
 use tokio::sync::{mpsc, Mutex};
+use ulid::Ulid;
 
 use crate::{
-    client_events::AuthToken,
-    server::{ClientConnection, HostCallbackResult},
+    client_events::{scoped_tokens::TokenScopes, AuthToken},
+    config::PayloadLimits,
+    node::load_shedding::{self, LoadShedder},
+    server::{errors::WebSocketApiError, ClientConnection, HostCallbackResult},
     util::EncodingProtocol,
 };
 
-use super::{ClientError, ClientEventsProxy, ClientId, HostResult, OpenRequest};
-
+use super::{
+    node_queries::{self, NodeQueries},
+    ClientError, ClientEventsProxy, ClientId, HostResult, OpenRequest,
+};
+use resume::{ClientSession, ResumeParams, SessionStatus, SuspendedSessions};
+
+mod diagnostics;
+mod gossip;
+mod multiplex;
+mod queries;
+mod resume;
+mod sse;
+mod upload;
 mod v1;
 
+/// Sends the requests of the clients to the node, shedding the expensive ones while it is
+/// overloaded.
 #[derive(Clone)]
-struct WebSocketRequest(mpsc::Sender<ClientConnection>);
+struct WebSocketRequest(mpsc::Sender<ClientConnection>, LoadShedder);
 
 impl std::ops::Deref for WebSocketRequest {
     type Target = mpsc::Sender<ClientConnection>;
@@ -47,13 +63,17 @@ impl std::ops::Deref for WebSocketRequest {
 pub(crate) struct WebSocketProxy {
     proxy_server_request: mpsc::Receiver<ClientConnection>,
     response_channels: HashMap<ClientId, mpsc::UnboundedSender<HostCallbackResult>>,
+    node_queries: Option<NodeQueries>,
 }
 
 const PARALLELISM: usize = 10; // TODO: get this from config, or whatever optimal way
 
 impl WebSocketProxy {
-    pub fn as_router(server_routing: Router) -> (Self, Router) {
-        WebSocketProxy::as_router_v1(server_routing)
+    /// Returns the router serving the client API, along with the gateway ones; the WebRTC
+    /// signalling endpoint is only served if `webrtc` peers are accepted by the node. Expensive
+    /// requests are rejected while `shedder` tells the node is overloaded.
+    pub fn as_router(server_routing: Router, webrtc: bool, shedder: LoadShedder) -> (Self, Router) {
+        WebSocketProxy::as_router_v1(server_routing, webrtc, shedder)
     }
 
     async fn internal_proxy_recv(
@@ -194,6 +214,16 @@ async fn connection_info(
         }
     };
 
+    // only clients on this machine can use the API without a token, remote ones get theirs with
+    // the web app they load
+    let local_client = req
+        .extensions()
+        .get::<axum::extract::ConnectInfo<std::net::SocketAddr>>()
+        .is_some_and(|info| info.0.ip().is_loopback());
+    if auth_token.is_none() && !local_client && !is_public_path(req.uri().path()) {
+        return (StatusCode::UNAUTHORIZED, "missing auth token").into_response();
+    }
+
     tracing::debug!(
         "establishing connection with encoding protocol: {encoding_protoc}, authenticated: {auth}",
         auth = auth_token.is_some()
@@ -204,128 +234,196 @@ async fn connection_info(
     next.run(req).await
 }
 
+/// Paths remote clients can request without a token: the web apps served by the gateway, which
+/// hand them their token.
+fn is_public_path(path: &str) -> bool {
+    path == "/v1" || path.starts_with("/v1/contract/web/")
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn websocket_commands(
     ws: WebSocketUpgrade,
+    Query(ResumeParams { resume_token }): Query<ResumeParams>,
     Extension(auth_token): Extension<Option<AuthToken>>,
     Extension(encoding_protoc): Extension<EncodingProtocol>,
     Extension(rs): Extension<WebSocketRequest>,
+    Extension(scopes): Extension<TokenScopes>,
+    Extension(limits): Extension<PayloadLimits>,
+    Extension(sessions): Extension<SuspendedSessions>,
 ) -> axum::response::Response {
+    let resumable = resume_token.map(|token| (token, sessions));
     let on_upgrade = move |ws: WebSocket| async move {
         tracing::debug!(protoc = ?ws.protocol(), "websocket connection established");
-        if let Err(error) = websocket_interface(rs.clone(), auth_token, encoding_protoc, ws).await {
+        if let Err(error) = websocket_interface(
+            rs.clone(),
+            auth_token,
+            encoding_protoc,
+            scopes,
+            limits,
+            resumable,
+            ws,
+        )
+        .await
+        {
             tracing::error!("{error}");
         }
     };
-    ws.on_upgrade(on_upgrade)
+    ws.max_message_size(limits.max_message_size)
+        .max_frame_size(limits.max_message_size)
+        .on_upgrade(on_upgrade)
 }
 
 async fn websocket_interface(
     request_sender: WebSocketRequest,
     mut auth_token: Option<AuthToken>,
     encoding_protoc: EncodingProtocol,
+    scopes: TokenScopes,
+    limits: PayloadLimits,
+    resumable: Option<(Ulid, SuspendedSessions)>,
     ws: WebSocket,
 ) -> anyhow::Result<()> {
-    let (mut response_rx, client_id) = new_client_connection(&request_sender).await?;
+    let resumed = match &resumable {
+        Some((token, sessions)) => sessions.resume(*token, auth_token.as_ref()).await,
+        None => None,
+    };
+    let resumed_backlog = resumed.as_ref().map(|session| session.backlog.len());
+    let mut session = match resumed {
+        Some(session) => {
+            tracing::debug!(cli_id = %session.client_id, "resumed client session");
+            session
+        }
+        None => ClientSession::new(&request_sender).await?,
+    };
+    let client_id = session.client_id;
     let (mut server_sink, mut client_stream) = ws.split();
-    let contract_updates: Arc<Mutex<VecDeque<(_, mpsc::UnboundedReceiver<HostResult>)>>> =
-        Arc::new(Mutex::new(VecDeque::new()));
-    loop {
-        let contract_updates_cp = contract_updates.clone();
-        let listeners_task = async move {
-            loop {
-                let mut lock = contract_updates_cp.lock().await;
-                let active_listeners = &mut *lock;
-                for _ in 0..active_listeners.len() {
-                    if let Some((key, mut listener)) = active_listeners.pop_front() {
-                        match listener.try_recv() {
-                            Ok(r) => {
-                                active_listeners.push_back((key, listener));
-                                return Ok(r);
-                            }
-                            Err(mpsc::error::TryRecvError::Empty) => {
-                                active_listeners.push_back((key, listener));
-                            }
-                            Err(err @ mpsc::error::TryRecvError::Disconnected) => {
-                                tracing::debug!(err = ?err, "listener channel disconnected");
-                                return Err(anyhow::anyhow!(err));
+    let result: anyhow::Result<()> = async {
+        if resumable.is_some() {
+            let status = match resumed_backlog {
+                Some(missed) => SessionStatus::Resumed { missed },
+                None => SessionStatus::New,
+            };
+            server_sink.send(status.to_message()).await?;
+        }
+        for response in session.backlog.drain(..) {
+            let serialized_res = encode_host_result(response, encoding_protoc)?;
+            server_sink.send(Message::Binary(serialized_res)).await?;
+        }
+        let response_rx = &mut session.response_rx;
+        let contract_updates = &session.contract_updates;
+        loop {
+            let contract_updates_cp = contract_updates.clone();
+            let listeners_task = async move {
+                loop {
+                    let mut lock = contract_updates_cp.lock().await;
+                    let active_listeners = &mut *lock;
+                    for _ in 0..active_listeners.len() {
+                        if let Some((key, mut listener)) = active_listeners.pop_front() {
+                            match listener.try_recv() {
+                                Ok(r) => {
+                                    active_listeners.push_back((key, listener));
+                                    return Ok(r);
+                                }
+                                Err(mpsc::error::TryRecvError::Empty) => {
+                                    active_listeners.push_back((key, listener));
+                                }
+                                Err(err @ mpsc::error::TryRecvError::Disconnected) => {
+                                    tracing::debug!(err = ?err, "listener channel disconnected");
+                                    return Err(anyhow::anyhow!(err));
+                                }
                             }
                         }
                     }
+                    std::mem::drop(lock);
+                    tokio::time::sleep(Duration::from_millis(10)).await;
                 }
-                std::mem::drop(lock);
-                tokio::time::sleep(Duration::from_millis(10)).await;
-            }
-        };
+            };
 
-        let client_req_task = async {
-            let next_msg = match client_stream
-                .next()
+            let client_req_task = async {
+                let next_msg = match client_stream
+                    .next()
+                    .await
+                    .ok_or_else::<ClientError, _>(|| ErrorKind::Disconnect.into())
+                {
+                    Err(err) => {
+                        tracing::debug!(err = %err, "client channel error");
+                        return Err(Some(err.into()));
+                    }
+                    Ok(v) => v,
+                };
+                process_client_request(
+                    client_id,
+                    next_msg,
+                    &request_sender,
+                    &mut auth_token,
+                    encoding_protoc,
+                    &scopes,
+                    limits,
+                )
                 .await
-                .ok_or_else::<ClientError, _>(|| ErrorKind::Disconnect.into())
-            {
-                Err(err) => {
-                    tracing::debug!(err = %err, "client channel error");
-                    return Err(Some(err.into()));
-                }
-                Ok(v) => v,
             };
-            process_client_request(
-                client_id,
-                next_msg,
-                &request_sender,
-                &mut auth_token,
-                encoding_protoc,
-            )
-            .await
-        };
 
-        tokio::select! { biased;
-            msg = async { process_host_response(response_rx.recv().await, client_id, encoding_protoc, &mut server_sink).await } => {
-                let active_listeners = contract_updates.clone();
-                if let Some(NewSubscription { key, callback }) = msg? {
-                    tracing::debug!(cli_id = %client_id, contract = %key, "added new notification listener");
-                    let active_listeners = &mut *active_listeners.lock().await;
-                    active_listeners.push_back((key, callback));
+            tokio::select! { biased;
+                msg = async { process_host_response(response_rx.recv().await, client_id, encoding_protoc, &mut server_sink).await } => {
+                    let active_listeners = contract_updates.clone();
+                    if let Some(NewSubscription { key, callback }) = msg? {
+                        tracing::debug!(cli_id = %client_id, contract = %key, "added new notification listener");
+                        let active_listeners = &mut *active_listeners.lock().await;
+                        active_listeners.push_back((key, callback));
+                    }
                 }
-            }
-            process_client_request = client_req_task => {
-                match process_client_request {
-                    Ok(Some(error)) => {
-                        server_sink.send(error).await.inspect_err(|err| {
-                            tracing::debug!(err = %err, "error sending message to client");
-                        })?;
+                process_client_request = client_req_task => {
+                    match process_client_request {
+                        Ok(Some(error)) => {
+                            server_sink.send(error).await.inspect_err(|err| {
+                                tracing::debug!(err = %err, "error sending message to client");
+                            })?;
+                        }
+                        Ok(None) => continue,
+                        Err(None) => {
+                            tracing::debug!("client channel closed on request");
+                            // let the node cancel any in-flight requests from this client
+                            let _ = request_sender
+                                .send(ClientConnection::Request {
+                                    client_id,
+                                    req: Box::new(ClientRequest::Disconnect { cause: None }),
+                                    auth_token: None,
+                                })
+                                .await;
+                            let _ = server_sink.send(Message::Close(None)).await;
+                            return Ok(())
+                        },
+                        Err(Some(err)) => {
+                            tracing::debug!(err = %err, "client channel error on request");
+                            return Err(err)
+                        },
                     }
-                    Ok(None) => continue,
-                    Err(None) => {
-                        tracing::debug!("client channel closed on request");
-                        let _ = server_sink.send(Message::Close(None)).await;
-                        return Ok(())
-                    },
-                    Err(Some(err)) => {
-                        tracing::debug!(err = %err, "client channel error on request");
-                        return Err(err)
-                    },
                 }
-            }
-            response = listeners_task => {
-                let response = response?;
-                match &response {
-                    Ok(res) => tracing::debug!(response = %res, cli_id = %client_id, "sending notification"),
-                    Err(err) => tracing::debug!(response = %err, cli_id = %client_id, "sending notification error"),
+                response = listeners_task => {
+                    let response = response?;
+                    match &response {
+                        Ok(res) => tracing::debug!(response = %res, cli_id = %client_id, "sending notification"),
+                        Err(err) => tracing::debug!(response = %err, cli_id = %client_id, "sending notification error"),
+                    }
+                    let serialized_res = encode_host_result(response, encoding_protoc)?;
+                    server_sink.send(Message::Binary(serialized_res)).await.inspect_err(|err| {
+                        tracing::debug!(err = %err, "error sending message to client");
+                    })?;
                 }
-                let serialized_res = match encoding_protoc {
-                    EncodingProtocol::Flatbuffers => match response {
-                        Ok(res) => res.into_fbs_bytes()?,
-                        Err(err) => err.into_fbs_bytes()?,
-                    },
-                    EncodingProtocol::Native => bincode::serialize(&response)?,
-                };
-                server_sink.send(Message::Binary(serialized_res)).await.inspect_err(|err| {
-                    tracing::debug!(err = %err, "error sending message to client");
-                })?;
             }
         }
     }
+    .await;
+
+    match (result, resumable, auth_token) {
+        // the connection was lost without the client closing it, keep the session around
+        // in case it reconnects
+        (Err(err), Some((token, sessions)), Some(auth_token)) => {
+            tracing::debug!(cli_id = %client_id, %err, "connection lost, suspending client session");
+            sessions.suspend(token, session, auth_token, request_sender);
+            Ok(())
+        }
+        (result, ..) => result,
+    }
 }
 
 async fn new_client_connection(
@@ -354,9 +452,11 @@ struct NewSubscription {
 async fn process_client_request(
     client_id: ClientId,
     msg: Result<Message, axum::Error>,
-    request_sender: &mpsc::Sender<ClientConnection>,
+    request_sender: &WebSocketRequest,
     auth_token: &mut Option<AuthToken>,
     encoding_protoc: EncodingProtocol,
+    scopes: &TokenScopes,
+    limits: PayloadLimits,
 ) -> Result<Option<Message>, Option<anyhow::Error>> {
     let msg = match msg {
         Ok(Message::Binary(data)) => data,
@@ -370,28 +470,20 @@ async fn process_client_request(
         Err(err) => return Err(Some(err.into())),
     };
 
-    // Try to deserialize the ClientRequest message
-    let req = {
-        match encoding_protoc {
-            EncodingProtocol::Flatbuffers => match ClientRequest::try_decode_fbs(&msg) {
-                Ok(decoded) => decoded.into_owned(),
-                Err(err) => return Ok(Some(Message::Binary(err.into_fbs_bytes()))),
-            },
-            EncodingProtocol::Native => match bincode::deserialize::<ClientRequest>(&msg) {
-                Ok(decoded) => decoded.into_owned(),
-                Err(err) => {
-                    let result_error = bincode::serialize(&Err::<HostResponse, ClientError>(
-                        ErrorKind::DeserializationError {
-                            cause: format!("{err}").into(),
-                        }
-                        .into(),
-                    ))
-                    .map_err(|err| Some(err.into()))?;
-                    return Ok(Some(Message::Binary(result_error)));
-                }
-            },
-        }
+    let req = match decode_client_request(&msg, encoding_protoc).map_err(Some)? {
+        Ok(req) => req,
+        Err(error) => return Ok(Some(Message::Binary(error))),
     };
+    if let Err(error) = validate_request(
+        scopes,
+        limits,
+        &request_sender.1,
+        auth_token.as_ref(),
+        &req,
+        encoding_protoc,
+    ) {
+        return Ok(Some(Message::Binary(error.map_err(Some)?)));
+    }
     if let ClientRequest::Authenticate { token } = &req {
         *auth_token = Some(AuthToken::from(token.clone()));
     }
@@ -408,6 +500,93 @@ async fn process_client_request(
     Ok(None)
 }
 
+/// Decodes a client request.
+///
+/// If the request is malformed, the encoded error to send back to the client is returned instead.
+pub(crate) fn decode_client_request(
+    msg: &[u8],
+    encoding_protoc: EncodingProtocol,
+) -> anyhow::Result<Result<ClientRequest<'static>, Vec<u8>>> {
+    match encoding_protoc {
+        EncodingProtocol::Flatbuffers => match ClientRequest::try_decode_fbs(msg) {
+            Ok(decoded) => Ok(Ok(decoded.into_owned())),
+            Err(err) => Ok(Err(err.into_fbs_bytes())),
+        },
+        EncodingProtocol::Native => match bincode::deserialize::<ClientRequest>(msg) {
+            Ok(decoded) => Ok(Ok(decoded.into_owned())),
+            Err(err) => {
+                let result_error = bincode::serialize(&Err::<HostResponse, ClientError>(
+                    ErrorKind::DeserializationError {
+                        cause: format!("{err}").into(),
+                    }
+                    .into(),
+                ))?;
+                Ok(Err(result_error))
+            }
+        },
+    }
+}
+
+/// Checks the request is within the scope of the token it was made with, and that its payloads
+/// are within the configured limits.
+///
+/// If it's not, the encoded error to send back to the client is returned. Its cause is the same
+/// JSON body the HTTP endpoints answer with, so clients can tell an oversized payload, a
+/// forbidden request or an overloaded node apart.
+fn validate_request(
+    scopes: &TokenScopes,
+    limits: PayloadLimits,
+    shedder: &LoadShedder,
+    auth_token: Option<&AuthToken>,
+    req: &ClientRequest<'_>,
+    encoding_protoc: EncodingProtocol,
+) -> Result<(), anyhow::Result<Vec<u8>>> {
+    check_request(scopes, limits, shedder, auth_token, req).map_err(|error| {
+        tracing::debug!(%error, req = %req, "rejected client request");
+        encode_host_result(
+            Err(ErrorKind::OperationError {
+                cause: error.to_json().into(),
+            }
+            .into()),
+            encoding_protoc,
+        )
+    })
+}
+
+fn check_request(
+    scopes: &TokenScopes,
+    limits: PayloadLimits,
+    shedder: &LoadShedder,
+    auth_token: Option<&AuthToken>,
+    req: &ClientRequest<'_>,
+) -> Result<(), WebSocketApiError> {
+    limits
+        .check_request(req)
+        .map_err(WebSocketApiError::PayloadTooLarge)?;
+    scopes
+        .authorize(auth_token, req)
+        .map_err(|err| WebSocketApiError::Forbidden {
+            error_cause: err.to_string(),
+        })?;
+    if load_shedding::is_expensive(req) {
+        shedder.check().map_err(WebSocketApiError::Overloaded)?;
+    }
+    Ok(())
+}
+
+fn encode_host_result(
+    result: HostResult,
+    encoding_protoc: EncodingProtocol,
+) -> anyhow::Result<Vec<u8>> {
+    Ok(match encoding_protoc {
+        EncodingProtocol::Flatbuffers => match result {
+            Ok(res) => res.into_fbs_bytes()?,
+            Err(err) => err.into_fbs_bytes()?,
+        },
+        EncodingProtocol::Native => bincode::serialize(&result)?,
+    })
+}
+
 async fn process_host_response(
     msg: Option<HostCallbackResult>,
     client_id: ClientId,
@@ -439,13 +618,7 @@ async fn process_host_response(
                     Err(err)
                 }
             };
-            let serialized_res = match encoding_protoc {
-                EncodingProtocol::Flatbuffers => match result {
-                    Ok(res) => res.into_fbs_bytes()?,
-                    Err(err) => err.into_fbs_bytes()?,
-                },
-                EncodingProtocol::Native => bincode::serialize(&result)?,
-            };
+            let serialized_res = encode_host_result(result, encoding_protoc)?;
             tx.send(Message::Binary(serialized_res)).await?;
             Ok(None)
         }
@@ -513,4 +686,67 @@ impl ClientEventsProxy for WebSocketProxy {
         }
         .boxed()
     }
+
+    fn node_queries(&mut self) -> Option<NodeQueries> {
+        self.node_queries.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn decode_malformed_requests(msg in prop::collection::vec(any::<u8>(), 0..1024)) {
+            for encoding in [EncodingProtocol::Native, EncodingProtocol::Flatbuffers] {
+                // malformed requests get an error for the client back, they never panic
+                prop_assert!(decode_client_request(&msg, encoding).is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn decode_requests() -> anyhow::Result<()> {
+        let disconnect = bincode::serialize(&ClientRequest::Disconnect { cause: None })?;
+        assert!(matches!(
+            decode_client_request(&disconnect, EncodingProtocol::Native)?,
+            Ok(ClientRequest::Disconnect { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn rejections_are_typed() -> anyhow::Result<()> {
+        let limits = PayloadLimits {
+            max_delta_size: 8,
+            ..Default::default()
+        };
+        let update: ClientRequest<'static> = ContractRequest::Update {
+            key: ContractKey::from(ContractInstanceId::new([1; 32])),
+            data: UpdateData::Delta(StateDelta::from(vec![0; 9])),
+        }
+        .into();
+        let error = validate_request(
+            &TokenScopes::default(),
+            limits,
+            &LoadShedder::default(),
+            None,
+            &update,
+            EncodingProtocol::Native,
+        )
+        .unwrap_err()?;
+        let Err(error) = bincode::deserialize::<HostResult>(&error)? else {
+            panic!("expected an error");
+        };
+        let ErrorKind::OperationError { cause } = error.kind() else {
+            panic!("unexpected error: {error}");
+        };
+        let body: serde_json::Value = serde_json::from_str(&cause)?;
+        assert_eq!(body["code"], "payload-too-large");
+        assert_eq!(body["retryable"], false);
+        Ok(())
+    }
 }
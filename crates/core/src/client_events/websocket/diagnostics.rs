@@ -0,0 +1,64 @@
+//! Subscription to the diagnostics of the node.
+//!
+//! Each time the connectivity, peer count or subscription staleness of the node changes, the
+//! new [`NodeDiagnostics`] are sent as a JSON text message. The current diagnostics are sent as
+//! soon as the connection is open, if the node has already reported them.
+
+use tokio::sync::watch;
+
+use super::*;
+use crate::{
+    client_events::node_queries::{NodeQueryKind, NodeQueryResult, NodeQuerySender},
+    node::diagnostics::NodeDiagnostics,
+    server::errors::WebSocketApiError,
+};
+
+pub(super) async fn websocket_diagnostics(
+    ws: WebSocketUpgrade,
+    Extension(queries): Extension<NodeQuerySender>,
+) -> Result<axum::response::Response, WebSocketApiError> {
+    let reports = match queries.query(NodeQueryKind::Diagnostics).await? {
+        NodeQueryResult::Diagnostics(reports) => reports,
+        other => {
+            return Err(WebSocketApiError::NodeError {
+                error_cause: format!("unexpected node query result: {other:?}"),
+            })
+        }
+    };
+    Ok(ws.on_upgrade(|ws: WebSocket| async move {
+        if let Err(error) = diagnostics_interface(ws, reports).await {
+            tracing::debug!("diagnostics connection closed: {error}");
+        }
+    }))
+}
+
+async fn diagnostics_interface(
+    ws: WebSocket,
+    mut reports: watch::Receiver<Option<NodeDiagnostics>>,
+) -> anyhow::Result<()> {
+    let (mut server_sink, mut client_stream) = ws.split();
+    reports.mark_changed();
+    loop {
+        tokio::select! {
+            changed = reports.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                let report: Option<NodeDiagnostics> = reports.borrow_and_update().clone();
+                if let Some(report) = report {
+                    server_sink
+                        .send(Message::Text(serde_json::to_string(&report)?))
+                        .await?;
+                }
+            }
+            msg = client_stream.next() => match msg {
+                Some(Ok(Message::Ping(ping))) => server_sink.send(Message::Pong(ping)).await?,
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => {}
+                Some(Err(err)) => return Err(err.into()),
+            }
+        }
+    }
+    let _ = server_sink.send(Message::Close(None)).await;
+    Ok(())
+}
@@ -0,0 +1,107 @@
+//! Ephemeral application messages broadcast through the gossip channel.
+//!
+//! `/v1/gossip/{topic}` subscribes to the topic with the given name. Each binary or text message
+//! sent by the client is published to the topic, and the messages published by others are sent
+//! to it as binary messages. Messages the node refuses, because it didn't join the gossip channel
+//! or they are over the size or rate limits, are answered with a text message holding the error
+//! as JSON.
+//!
+//! Delegated tokens, restricted to some operations or contracts, can't use the channel.
+
+use axum::extract::Path;
+use tokio::sync::broadcast;
+
+use super::*;
+use crate::{
+    client_events::node_queries::{NodeQueryKind, NodeQueryResult, NodeQuerySender},
+    node::gossip::{GossipChannel, GossipError, TopicKey},
+    server::errors::WebSocketApiError,
+};
+
+/// Messages published through the connection recently, not echoed back to it.
+const MAX_OWN_MESSAGES: usize = 64;
+
+pub(super) async fn websocket_gossip(
+    ws: WebSocketUpgrade,
+    Path(topic): Path<String>,
+    Extension(auth_token): Extension<Option<AuthToken>>,
+    Extension(scopes): Extension<TokenScopes>,
+    Extension(queries): Extension<NodeQuerySender>,
+) -> Result<axum::response::Response, WebSocketApiError> {
+    scopes
+        .authorize_operation(auth_token.as_ref(), None)
+        .map_err(|err| WebSocketApiError::Forbidden {
+            error_cause: err.to_string(),
+        })?;
+    let channel = match queries.query(NodeQueryKind::Gossip).await? {
+        NodeQueryResult::Gossip(channel) => channel,
+        other => {
+            return Err(WebSocketApiError::NodeError {
+                error_cause: format!("unexpected node query result: {other:?}"),
+            })
+        }
+    };
+    let topic = TopicKey::from_name(&topic);
+    Ok(ws.on_upgrade(move |ws: WebSocket| async move {
+        if let Err(error) = gossip_interface(ws, channel, topic).await {
+            tracing::debug!(%topic, "gossip connection closed: {error}");
+        }
+    }))
+}
+
+async fn gossip_interface(
+    ws: WebSocket,
+    channel: Arc<GossipChannel>,
+    topic: TopicKey,
+) -> anyhow::Result<()> {
+    let (mut server_sink, mut client_stream) = ws.split();
+    let mut messages = match channel.subscribe(topic) {
+        Ok(messages) => messages,
+        Err(error) => {
+            server_sink.send(error_message(error)).await?;
+            let _ = server_sink.send(Message::Close(None)).await;
+            return Ok(());
+        }
+    };
+    let mut own = VecDeque::with_capacity(MAX_OWN_MESSAGES);
+    loop {
+        tokio::select! {
+            msg = messages.recv() => match msg {
+                Ok(msg) if own.contains(&msg.id) => {}
+                Ok(msg) => server_sink.send(Message::Binary(msg.payload.clone())).await?,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    tracing::debug!(%topic, missed, "gossip subscriber lagging behind");
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            msg = client_stream.next() => {
+                let payload = match msg {
+                    Some(Ok(Message::Binary(payload))) => payload,
+                    Some(Ok(Message::Text(text))) => text.into_bytes(),
+                    Some(Ok(Message::Ping(ping))) => {
+                        server_sink.send(Message::Pong(ping)).await?;
+                        continue;
+                    }
+                    Some(Ok(Message::Pong(_))) => continue,
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Err(err)) => return Err(err.into()),
+                };
+                match channel.publish(topic, payload) {
+                    Ok(id) => {
+                        if own.len() == MAX_OWN_MESSAGES {
+                            own.pop_front();
+                        }
+                        own.push_back(id);
+                    }
+                    Err(error) => server_sink.send(error_message(error)).await?,
+                }
+            }
+        }
+    }
+    let _ = server_sink.send(Message::Close(None)).await;
+    Ok(())
+}
+
+fn error_message(error: GossipError) -> Message {
+    Message::Text(serde_json::json!({ "error": error.to_string() }).to_string())
+}
@@ -0,0 +1,437 @@
+//! Multiplexing of several logical client sessions over a single WebSocket connection.
+//!
+//! Browser applications embedding several independent components would otherwise open one
+//! connection per component, quickly exhausting the connection limits of the browser.
+//!
+//! Multiplexed messages are always bincode encoded. A client opens a session with
+//! [`MultiplexMessage::Open`] using an id of its choosing, and then sends client requests
+//! (encoded with the connection encoding protocol) tagged with that id. Each session is
+//! registered as a separate client of the node, so it has its own auth token and its own set of
+//! subscriptions, and responses and notifications are tagged with the id of the session they
+//! belong to. Closing the connection closes all of its sessions.
+//!
+//! Responses and notifications of every session are queued for the connection in a bounded
+//! queue, so a client which doesn't keep up applies backpressure to its sessions instead of
+//! piling up messages in memory.
+
+use serde::Serialize;
+use tokio::task::{JoinHandle, JoinSet};
+
+use super::*;
+
+/// Maximum number of sessions open at the same time on a single connection.
+const MAX_SESSIONS: usize = 64;
+/// Responses and notifications waiting to be sent to the connection, across all its sessions.
+const MAX_QUEUED_RESPONSES: usize = 256;
+
+type HostSender = mpsc::Sender<(SessionId, HostResult)>;
+
+pub(crate) type SessionId = u32;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) enum MultiplexMessage {
+    /// Opens a new session; if no token is given, the one the connection was opened with is used.
+    Open {
+        session: SessionId,
+        auth_token: Option<String>,
+    },
+    Request {
+        session: SessionId,
+        data: Vec<u8>,
+    },
+    Close {
+        session: SessionId,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) enum MultiplexResponse {
+    Opened {
+        session: SessionId,
+    },
+    /// A response or notification for the session, encoded with the connection encoding protocol.
+    Response {
+        session: SessionId,
+        data: Vec<u8>,
+    },
+    Closed {
+        session: SessionId,
+    },
+    Error {
+        session: SessionId,
+        cause: String,
+    },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum MultiplexError {
+    #[error("session {0} is already open")]
+    AlreadyOpen(SessionId),
+    #[error("unknown session {0}")]
+    UnknownSession(SessionId),
+    #[error("too many open sessions, the maximum is {MAX_SESSIONS}")]
+    TooManySessions,
+    #[error(transparent)]
+    Client(#[from] ClientError),
+}
+
+struct Session {
+    client_id: ClientId,
+    auth_token: Option<AuthToken>,
+    /// Forwards the responses and notifications of the session to the connection.
+    forwarder: JoinHandle<()>,
+}
+
+impl Session {
+    async fn close(self, request_sender: &WebSocketRequest) {
+        self.forwarder.abort();
+        // let the node cancel any in-flight requests from this session
+        let _ = request_sender
+            .send(ClientConnection::Request {
+                client_id: self.client_id,
+                req: Box::new(ClientRequest::Disconnect { cause: None }),
+                auth_token: None,
+            })
+            .await;
+    }
+}
+
+pub(super) async fn websocket_multiplex(
+    ws: WebSocketUpgrade,
+    Extension(auth_token): Extension<Option<AuthToken>>,
+    Extension(encoding_protoc): Extension<EncodingProtocol>,
+    Extension(rs): Extension<WebSocketRequest>,
+    Extension(scopes): Extension<TokenScopes>,
+    Extension(limits): Extension<PayloadLimits>,
+) -> axum::response::Response {
+    let on_upgrade = move |ws: WebSocket| async move {
+        if let Err(error) =
+            multiplex_interface(rs, auth_token, encoding_protoc, scopes, limits, ws).await
+        {
+            tracing::error!("{error}");
+        }
+    };
+    ws.max_message_size(limits.max_message_size)
+        .max_frame_size(limits.max_message_size)
+        .on_upgrade(on_upgrade)
+}
+
+async fn multiplex_interface(
+    request_sender: WebSocketRequest,
+    auth_token: Option<AuthToken>,
+    encoding_protoc: EncodingProtocol,
+    scopes: TokenScopes,
+    limits: PayloadLimits,
+    ws: WebSocket,
+) -> anyhow::Result<()> {
+    let (mut server_sink, mut client_stream) = ws.split();
+    let (host_tx, mut host_rx) = mpsc::channel(MAX_QUEUED_RESPONSES);
+    let mut sessions: HashMap<SessionId, Session> = HashMap::new();
+    let result = loop {
+        tokio::select! {
+            Some((session, result)) = host_rx.recv() => {
+                let reply = MultiplexResponse::Response {
+                    session,
+                    data: encode_host_result(result, encoding_protoc)?,
+                };
+                server_sink.send(Message::Binary(bincode::serialize(&reply)?)).await?;
+            }
+            msg = client_stream.next() => {
+                let data = match msg {
+                    Some(Ok(Message::Binary(data))) => data,
+                    Some(Ok(Message::Ping(ping))) => {
+                        server_sink.send(Message::Pong(ping)).await?;
+                        continue;
+                    }
+                    Some(Ok(Message::Close(_))) | None => break Ok(()),
+                    Some(Ok(m)) => {
+                        tracing::debug!(msg = ?m, "received random message");
+                        continue;
+                    }
+                    Some(Err(err)) => break Err(err.into()),
+                };
+                let msg = match bincode::deserialize::<MultiplexMessage>(&data) {
+                    Ok(msg) => msg,
+                    Err(err) => {
+                        tracing::debug!("malformed multiplexed message: {err}");
+                        continue;
+                    }
+                };
+                let session = match &msg {
+                    MultiplexMessage::Open { session, .. }
+                    | MultiplexMessage::Request { session, .. }
+                    | MultiplexMessage::Close { session } => *session,
+                };
+                let reply = match msg {
+                    MultiplexMessage::Open {
+                        session,
+                        auth_token: session_token,
+                    } => {
+                        let token = session_token
+                            .map(AuthToken::from)
+                            .or_else(|| auth_token.clone());
+                        open_session(&request_sender, &mut sessions, session, token, &host_tx)
+                            .await
+                            .map(|_| Some(MultiplexResponse::Opened { session }))
+                    }
+                    MultiplexMessage::Request { session, data } => {
+                        let error = session_request(
+                            &request_sender,
+                            &mut sessions,
+                            session,
+                            &data,
+                            encoding_protoc,
+                            &scopes,
+                            limits,
+                        )
+                        .await?;
+                        error.map(|error| {
+                            error.map(|data| MultiplexResponse::Response { session, data })
+                        })
+                    }
+                    MultiplexMessage::Close { session } => match sessions.remove(&session) {
+                        Some(open) => {
+                            open.close(&request_sender).await;
+                            tracing::debug!(session, "multiplexed session closed");
+                            Ok(Some(MultiplexResponse::Closed { session }))
+                        }
+                        None => Err(MultiplexError::UnknownSession(session)),
+                    },
+                };
+                let reply = match reply {
+                    Ok(Some(reply)) => reply,
+                    Ok(None) => continue,
+                    Err(err) => {
+                        tracing::debug!(session, "multiplexed session error: {err}");
+                        MultiplexResponse::Error {
+                            session,
+                            cause: format!("{err}"),
+                        }
+                    }
+                };
+                server_sink.send(Message::Binary(bincode::serialize(&reply)?)).await?;
+            }
+        }
+    };
+    for (_, session) in sessions.drain() {
+        session.close(&request_sender).await;
+    }
+    let _ = server_sink.send(Message::Close(None)).await;
+    result
+}
+
+async fn open_session(
+    request_sender: &WebSocketRequest,
+    sessions: &mut HashMap<SessionId, Session>,
+    session: SessionId,
+    auth_token: Option<AuthToken>,
+    host_tx: &HostSender,
+) -> Result<(), MultiplexError> {
+    if sessions.contains_key(&session) {
+        return Err(MultiplexError::AlreadyOpen(session));
+    }
+    if sessions.len() >= MAX_SESSIONS {
+        return Err(MultiplexError::TooManySessions);
+    }
+    let (response_rx, client_id) = new_client_connection(request_sender).await?;
+    tracing::debug!(session, cli_id = %client_id, "multiplexed session opened");
+    let forwarder = tokio::spawn(forward_session(session, response_rx, host_tx.clone()));
+    sessions.insert(
+        session,
+        Session {
+            client_id,
+            auth_token,
+            forwarder,
+        },
+    );
+    Ok(())
+}
+
+/// Forwards a request to the node on behalf of a session.
+///
+/// If the request is malformed, the encoded error to send back to the session is returned.
+async fn session_request(
+    request_sender: &WebSocketRequest,
+    sessions: &mut HashMap<SessionId, Session>,
+    session: SessionId,
+    data: &[u8],
+    encoding_protoc: EncodingProtocol,
+    scopes: &TokenScopes,
+    limits: PayloadLimits,
+) -> anyhow::Result<Result<Option<Vec<u8>>, MultiplexError>> {
+    let Some(open) = sessions.get_mut(&session) else {
+        return Ok(Err(MultiplexError::UnknownSession(session)));
+    };
+    let req = match decode_client_request(data, encoding_protoc)? {
+        Ok(req) => req,
+        Err(error) => return Ok(Ok(Some(error))),
+    };
+    if let Err(error) = validate_request(
+        scopes,
+        limits,
+        &request_sender.1,
+        open.auth_token.as_ref(),
+        &req,
+        encoding_protoc,
+    ) {
+        return Ok(Ok(Some(error?)));
+    }
+    if let ClientRequest::Authenticate { token } = &req {
+        open.auth_token = Some(AuthToken::from(token.clone()));
+    }
+    tracing::debug!(req = %req, session, "received multiplexed client request");
+    request_sender
+        .send(ClientConnection::Request {
+            client_id: open.client_id,
+            req: Box::new(req),
+            auth_token: open.auth_token.clone(),
+        })
+        .await?;
+    Ok(Ok(None))
+}
+
+/// Tags the responses and notifications of a session with its id and forwards them to the
+/// connection, until the session is closed.
+async fn forward_session(
+    session: SessionId,
+    mut response_rx: mpsc::UnboundedReceiver<HostCallbackResult>,
+    host_tx: HostSender,
+) {
+    // dropping the set once the session is closed stops forwarding its notifications
+    let mut subscriptions = JoinSet::new();
+    loop {
+        match response_rx.recv().await {
+            Some(HostCallbackResult::Result { result, .. }) => {
+                if host_tx.send((session, result)).await.is_err() {
+                    break;
+                }
+            }
+            Some(HostCallbackResult::SubscriptionChannel {
+                key, mut callback, ..
+            }) => {
+                tracing::debug!(session, contract = %key, "added new notification listener");
+                let host_tx = host_tx.clone();
+                subscriptions.spawn(async move {
+                    while let Some(notification) = callback.recv().await {
+                        if host_tx.send((session, notification)).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            Some(HostCallbackResult::NewId { .. }) => {}
+            None => {
+                tracing::debug!(session, "node dropped multiplexed session");
+                let _ = host_tx
+                    .send((session, Err(ErrorKind::NodeUnavailable.into())))
+                    .await;
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stands in for the node: assigns client ids and hands the callbacks of every new client
+    /// connection to the test, and reports the clients which disconnect.
+    fn fake_node() -> (
+        WebSocketRequest,
+        mpsc::UnboundedReceiver<mpsc::UnboundedSender<HostCallbackResult>>,
+        mpsc::UnboundedReceiver<ClientId>,
+    ) {
+        let (request_sender, mut requests) = mpsc::channel(8);
+        let (callbacks_tx, callbacks_rx) = mpsc::unbounded_channel();
+        let (disconnected_tx, disconnected_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(request) = requests.recv().await {
+                match request {
+                    ClientConnection::NewConnection { callbacks, .. } => {
+                        let _ = callbacks.send(HostCallbackResult::NewId {
+                            id: ClientId::next(),
+                        });
+                        let _ = callbacks_tx.send(callbacks);
+                    }
+                    ClientConnection::Request { client_id, req, .. } => {
+                        if matches!(*req, ClientRequest::Disconnect { .. }) {
+                            let _ = disconnected_tx.send(client_id);
+                        }
+                    }
+                }
+            }
+        });
+        (
+            WebSocketRequest(request_sender, LoadShedder::default()),
+            callbacks_rx,
+            disconnected_rx,
+        )
+    }
+
+    #[tokio::test]
+    async fn session_limits() -> anyhow::Result<()> {
+        let (request_sender, _callbacks, mut disconnected) = fake_node();
+        let (host_tx, _host_rx) = mpsc::channel(MAX_QUEUED_RESPONSES);
+        let mut sessions = HashMap::new();
+
+        open_session(&request_sender, &mut sessions, 0, None, &host_tx).await?;
+        assert!(matches!(
+            open_session(&request_sender, &mut sessions, 0, None, &host_tx).await,
+            Err(MultiplexError::AlreadyOpen(0))
+        ));
+        for session in 1..MAX_SESSIONS as SessionId {
+            open_session(&request_sender, &mut sessions, session, None, &host_tx).await?;
+        }
+        assert!(matches!(
+            open_session(
+                &request_sender,
+                &mut sessions,
+                MAX_SESSIONS as SessionId,
+                None,
+                &host_tx
+            )
+            .await,
+            Err(MultiplexError::TooManySessions)
+        ));
+
+        // closing a session disconnects its client from the node
+        let closed = sessions.remove(&0).unwrap();
+        let client_id = closed.client_id;
+        closed.close(&request_sender).await;
+        assert_eq!(disconnected.recv().await, Some(client_id));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn responses_are_tagged_and_bounded() -> anyhow::Result<()> {
+        let (request_sender, mut callbacks, _disconnected) = fake_node();
+        let (host_tx, mut host_rx) = mpsc::channel(1);
+        let mut sessions = HashMap::new();
+        open_session(&request_sender, &mut sessions, 7, None, &host_tx).await?;
+        let node = callbacks.recv().await.unwrap();
+        let id = sessions[&7].client_id;
+
+        for _ in 0..3 {
+            node.send(HostCallbackResult::Result {
+                id,
+                result: Ok(HostResponse::Ok),
+            })?;
+        }
+        // only as many responses as fit in the queue are taken from the node until it is drained
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(host_rx.len(), 1);
+        for _ in 0..3 {
+            let (session, result) = host_rx.recv().await.unwrap();
+            assert_eq!(session, 7);
+            assert!(matches!(result, Ok(HostResponse::Ok)));
+        }
+
+        // the session gets notified if the node drops it
+        drop(node);
+        let (session, result) = host_rx.recv().await.unwrap();
+        assert_eq!(session, 7);
+        assert!(result.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,365 @@
+//! Client endpoints answered by the node itself rather than through the client protocol.
+//!
+//! - `GET /v1/contract/{key}/journal?last={n}`: the last updates applied to the state of the
+//!   contract, as JSON. The token of the connection must be allowed to get the contract.
+//! - `GET /v1/contract/{key}/head`: whether the contract is cached by this node or the peers
+//!   closest to it, and the size and hash of its state, without fetching it.
+//! - `POST /v1/contracts/head`: the same for a JSON list of contract keys, probed concurrently.
+//!   The token must be allowed to get every one of them.
+//! - `PUT /v1/contract/{key}/pin`: pins the contract, so the node never evicts it and keeps it
+//!   in sync with the network; `DELETE` unpins it. The token must be allowed to pin it.
+//! - `GET /v1/contracts/pinned`: the contracts pinned in the node, for unrestricted tokens only.
+//! - `GET /v1/contract/resolve/{prefix}`: the contract stored by the node whose id starts with
+//!   the prefix, or the candidates to pick from, as an `ambiguous-contract` error, if several do.
+//!   The token must be allowed to get the contract, and be unrestricted to list candidates.
+//! - `DELETE /v1/transaction/{tx}`: cancels a transaction in progress in the node, for
+//!   unrestricted tokens only. The peers it awaits a response from are told to drop it too.
+//! - `PUT /v1/delegate/consent/{request_id}`: allows an application to read its namespace of
+//!   the secrets of a delegate, answering the prompt the runtime sent to its client connection
+//!   the first time it asked for them; `DELETE` denies it. Only the owning UI of the application
+//!   can answer, with the token assigned to its web app, or a local client without a token.
+//! - `POST /v1/webrtc/offer`: answers the SDP offer of a browser joining the network as a peer
+//!   over WebRTC. Only served by gateways accepting browser peers, and requires an unrestricted
+//!   auth token.
+
+use axum::{extract::Path, Json};
+use serde::Serialize;
+
+use super::*;
+use crate::{
+    client_events::{
+        node_queries::{NodeQueryKind, NodeQueryResult, NodeQuerySender},
+        scoped_tokens::TokenOperation,
+    },
+    contract::{
+        storages::{index::ContractCandidate, quota::PinSource},
+        JournalEntry, DEFAULT_JOURNAL_ENTRIES,
+    },
+    message::Transaction,
+    operations::get::{ContractHead, MAX_PROBED_CONTRACTS},
+    server::{errors::WebSocketApiError, path_handlers},
+};
+
+#[derive(Deserialize)]
+pub(super) struct JournalParams {
+    last: Option<usize>,
+}
+
+pub(super) async fn update_journal(
+    Path(key): Path<String>,
+    Query(params): Query<JournalParams>,
+    Extension(auth_token): Extension<Option<AuthToken>>,
+    Extension(scopes): Extension<TokenScopes>,
+    Extension(queries): Extension<NodeQuerySender>,
+) -> Result<Json<Vec<JournalEntry>>, WebSocketApiError> {
+    let key = parse_key(key)?;
+    authorize(&scopes, auth_token.as_ref(), TokenOperation::Get, &key)?;
+    let last = params.last.unwrap_or(DEFAULT_JOURNAL_ENTRIES);
+    match queries
+        .query(NodeQueryKind::UpdateJournal { key, last })
+        .await?
+    {
+        NodeQueryResult::UpdateJournal(entries) => Ok(Json(entries)),
+        other => Err(unexpected(other)),
+    }
+}
+
+pub(super) async fn contract_head(
+    Path(key): Path<String>,
+    Extension(auth_token): Extension<Option<AuthToken>>,
+    Extension(scopes): Extension<TokenScopes>,
+    Extension(queries): Extension<NodeQuerySender>,
+) -> Result<Json<ContractHead>, WebSocketApiError> {
+    let key = parse_key(key)?;
+    authorize(&scopes, auth_token.as_ref(), TokenOperation::Get, &key)?;
+    match queries
+        .query(NodeQueryKind::ContractHeads { keys: vec![key] })
+        .await?
+    {
+        NodeQueryResult::ContractHeads(mut heads) if heads.len() == 1 => Ok(Json(heads.remove(0))),
+        other => Err(unexpected(other)),
+    }
+}
+
+pub(super) async fn contracts_head(
+    Extension(auth_token): Extension<Option<AuthToken>>,
+    Extension(scopes): Extension<TokenScopes>,
+    Extension(queries): Extension<NodeQuerySender>,
+    Json(keys): Json<Vec<String>>,
+) -> Result<Json<Vec<ContractHead>>, WebSocketApiError> {
+    let keys = parse_keys(keys)?;
+    for key in &keys {
+        authorize(&scopes, auth_token.as_ref(), TokenOperation::Get, key)?;
+    }
+    match queries.query(NodeQueryKind::ContractHeads { keys }).await? {
+        NodeQueryResult::ContractHeads(heads) => Ok(Json(heads)),
+        other => Err(unexpected(other)),
+    }
+}
+
+pub(super) async fn pin_contract(
+    Path(key): Path<String>,
+    Extension(auth_token): Extension<Option<AuthToken>>,
+    Extension(scopes): Extension<TokenScopes>,
+    Extension(queries): Extension<NodeQuerySender>,
+) -> Result<StatusCode, WebSocketApiError> {
+    let key = parse_key(key)?;
+    authorize(&scopes, auth_token.as_ref(), TokenOperation::Pin, &key)?;
+    match queries
+        .query(NodeQueryKind::Pin {
+            id: *key.id(),
+            pin: true,
+        })
+        .await?
+    {
+        NodeQueryResult::Pinned(true) => Ok(StatusCode::CREATED),
+        NodeQueryResult::Pinned(false) => Ok(StatusCode::OK),
+        other => Err(unexpected(other)),
+    }
+}
+
+pub(super) async fn unpin_contract(
+    Path(key): Path<String>,
+    Extension(auth_token): Extension<Option<AuthToken>>,
+    Extension(scopes): Extension<TokenScopes>,
+    Extension(queries): Extension<NodeQuerySender>,
+) -> Result<StatusCode, WebSocketApiError> {
+    let key = parse_key(key)?;
+    authorize(&scopes, auth_token.as_ref(), TokenOperation::Pin, &key)?;
+    match queries
+        .query(NodeQueryKind::Pin {
+            id: *key.id(),
+            pin: false,
+        })
+        .await?
+    {
+        NodeQueryResult::Pinned(true) => Ok(StatusCode::NO_CONTENT),
+        NodeQueryResult::Pinned(false) => Err(WebSocketApiError::InvalidParam {
+            error_cause: format!("contract {key} is not pinned at runtime"),
+        }),
+        other => Err(unexpected(other)),
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(super) struct PinnedContractInfo {
+    contract: String,
+    source: PinSource,
+}
+
+pub(super) async fn pinned_contracts(
+    Extension(auth_token): Extension<Option<AuthToken>>,
+    Extension(scopes): Extension<TokenScopes>,
+    Extension(queries): Extension<NodeQuerySender>,
+) -> Result<Json<Vec<PinnedContractInfo>>, WebSocketApiError> {
+    scopes
+        .authorize_operation(auth_token.as_ref(), None)
+        .map_err(|err| WebSocketApiError::Forbidden {
+            error_cause: err.to_string(),
+        })?;
+    match queries.query(NodeQueryKind::PinnedContracts).await? {
+        NodeQueryResult::PinnedContracts(pinned) => Ok(Json(
+            pinned
+                .into_iter()
+                .map(|pinned| PinnedContractInfo {
+                    contract: pinned.id.to_string(),
+                    source: pinned.source,
+                })
+                .collect(),
+        )),
+        other => Err(unexpected(other)),
+    }
+}
+
+pub(super) async fn resolve_contract(
+    Path(prefix): Path<String>,
+    Extension(auth_token): Extension<Option<AuthToken>>,
+    Extension(scopes): Extension<TokenScopes>,
+    Extension(queries): Extension<NodeQuerySender>,
+) -> Result<Json<ContractCandidate>, WebSocketApiError> {
+    match path_handlers::resolve_prefix(&queries, &prefix).await {
+        Ok(contract) => {
+            authorize(
+                &scopes,
+                auth_token.as_ref(),
+                TokenOperation::Get,
+                &contract.key,
+            )?;
+            Ok(Json(contract))
+        }
+        Err(err @ WebSocketApiError::AmbiguousContract { .. }) => {
+            scopes
+                .authorize_operation(auth_token.as_ref(), None)
+                .map_err(|err| WebSocketApiError::Forbidden {
+                    error_cause: err.to_string(),
+                })?;
+            Err(err)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+pub(super) async fn cancel_transaction(
+    Path(tx): Path<String>,
+    Extension(auth_token): Extension<Option<AuthToken>>,
+    Extension(scopes): Extension<TokenScopes>,
+    Extension(queries): Extension<NodeQuerySender>,
+) -> Result<StatusCode, WebSocketApiError> {
+    scopes
+        .authorize_operation(auth_token.as_ref(), None)
+        .map_err(|err| WebSocketApiError::Forbidden {
+            error_cause: err.to_string(),
+        })?;
+    let tx = tx
+        .parse::<Transaction>()
+        .map_err(|err| WebSocketApiError::InvalidParam {
+            error_cause: format!("invalid transaction id: {err}"),
+        })?;
+    match queries.query(NodeQueryKind::Cancel { tx }).await? {
+        NodeQueryResult::Cancelled => Ok(StatusCode::ACCEPTED),
+        other => Err(unexpected(other)),
+    }
+}
+
+pub(super) async fn allow_secrets_consent(
+    Path(request_id): Path<u32>,
+    Extension(auth_token): Extension<Option<AuthToken>>,
+    Extension(scopes): Extension<TokenScopes>,
+    Extension(queries): Extension<NodeQuerySender>,
+) -> Result<StatusCode, WebSocketApiError> {
+    resolve_secrets_consent(&queries, &scopes, auth_token.as_ref(), request_id, true).await
+}
+
+pub(super) async fn deny_secrets_consent(
+    Path(request_id): Path<u32>,
+    Extension(auth_token): Extension<Option<AuthToken>>,
+    Extension(scopes): Extension<TokenScopes>,
+    Extension(queries): Extension<NodeQuerySender>,
+) -> Result<StatusCode, WebSocketApiError> {
+    resolve_secrets_consent(&queries, &scopes, auth_token.as_ref(), request_id, false).await
+}
+
+async fn resolve_secrets_consent(
+    queries: &NodeQuerySender,
+    scopes: &TokenScopes,
+    auth_token: Option<&AuthToken>,
+    request_id: u32,
+    allowed: bool,
+) -> Result<StatusCode, WebSocketApiError> {
+    let not_pending = || WebSocketApiError::InvalidParam {
+        error_cause: format!("no pending consent request {request_id}"),
+    };
+    let consent = match queries.query(NodeQueryKind::SecretsConsents).await? {
+        NodeQueryResult::SecretsConsents(pending) => pending
+            .into_iter()
+            .find(|consent| consent.request_id == request_id)
+            .ok_or_else(not_pending)?,
+        other => return Err(unexpected(other)),
+    };
+    let app = ContractInstanceId::try_from(consent.application).map_err(|err| {
+        WebSocketApiError::NodeError {
+            error_cause: format!("invalid application of consent request {request_id}: {err}"),
+        }
+    })?;
+    // the application asking for the secrets is not the one answering, its owning UI is
+    scopes
+        .authorize_owner(auth_token, &app)
+        .map_err(|err| WebSocketApiError::Forbidden {
+            error_cause: err.to_string(),
+        })?;
+    match queries
+        .query(NodeQueryKind::ResolveSecretsConsent {
+            request_id,
+            allowed,
+        })
+        .await?
+    {
+        NodeQueryResult::SecretsConsentResolved(true) => Ok(StatusCode::NO_CONTENT),
+        NodeQueryResult::SecretsConsentResolved(false) => Err(not_pending()),
+        other => Err(unexpected(other)),
+    }
+}
+
+pub(super) async fn webrtc_offer(
+    Extension(auth_token): Extension<Option<AuthToken>>,
+    Extension(scopes): Extension<TokenScopes>,
+    Extension(queries): Extension<NodeQuerySender>,
+    offer: String,
+) -> Result<Response, WebSocketApiError> {
+    let auth_token = auth_token.ok_or_else(|| WebSocketApiError::Forbidden {
+        error_cause: "missing auth token".to_owned(),
+    })?;
+    scopes
+        .authorize_operation(Some(&auth_token), None)
+        .map_err(|err| WebSocketApiError::Forbidden {
+            error_cause: err.to_string(),
+        })?;
+    match queries
+        .query(NodeQueryKind::WebRtcOffer {
+            offer,
+            token: auth_token,
+        })
+        .await?
+    {
+        NodeQueryResult::WebRtcAnswer(answer) => Ok((
+            [(axum::http::header::CONTENT_TYPE, "application/sdp")],
+            answer,
+        )
+            .into_response()),
+        other => Err(unexpected(other)),
+    }
+}
+
+fn parse_keys(keys: Vec<String>) -> Result<Vec<ContractKey>, WebSocketApiError> {
+    if keys.len() > MAX_PROBED_CONTRACTS {
+        return Err(WebSocketApiError::InvalidParam {
+            error_cause: format!("at most {MAX_PROBED_CONTRACTS} contracts can be probed at once"),
+        });
+    }
+    keys.into_iter().map(parse_key).collect()
+}
+
+fn unexpected(result: NodeQueryResult) -> WebSocketApiError {
+    WebSocketApiError::NodeError {
+        error_cause: format!("unexpected node query result: {result:?}"),
+    }
+}
+
+fn parse_key(key: String) -> Result<ContractKey, WebSocketApiError> {
+    ContractKey::from_id(key).map_err(|err| WebSocketApiError::InvalidParam {
+        error_cause: format!("invalid contract key: {err}"),
+    })
+}
+
+fn authorize(
+    scopes: &TokenScopes,
+    auth_token: Option<&AuthToken>,
+    operation: TokenOperation,
+    key: &ContractKey,
+) -> Result<(), WebSocketApiError> {
+    scopes
+        .authorize_operation(auth_token, Some((operation, Some(*key.id()))))
+        .map_err(|err| WebSocketApiError::Forbidden {
+            error_cause: err.to_string(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probed_contracts_limit() {
+        let key = ContractKey::from(ContractInstanceId::new([1; 32])).to_string();
+        assert_eq!(parse_keys(vec![key.clone(); 2]).unwrap().len(), 2);
+        assert!(matches!(
+            parse_keys(vec![key; MAX_PROBED_CONTRACTS + 1]),
+            Err(WebSocketApiError::InvalidParam { .. })
+        ));
+        assert!(matches!(
+            parse_keys(vec!["not a key".to_owned()]),
+            Err(WebSocketApiError::InvalidParam { .. })
+        ));
+    }
+}
@@ -0,0 +1,384 @@
+//! Resumable client sessions.
+//!
+//! A client can open the command endpoint with a `resumeToken` query parameter (a ULID it
+//! generates). If the connection is then lost abruptly, instead of disconnecting the client
+//! from the node the gateway suspends its session for [`RESUME_WINDOW`]: subscriptions are kept
+//! alive, and the responses and update notifications received in the meantime are buffered.
+//!
+//! Only sessions opened with an auth token can be resumed, and only by reconnecting with the
+//! same auth token: the resume token travels in the URL, so it alone must not be enough to take
+//! over the session of someone else.
+//!
+//! When the client reconnects with both tokens it gets back the same session: the buffered
+//! messages are replayed before any new one, so the client only needs to apply the missed
+//! updates instead of re-subscribing and fetching the full state. Before anything else, clients
+//! connecting with a resume token get a text message telling them whether the session was
+//! resumed, see [`SessionStatus`].
+//!
+//! The session is dropped, and the client disconnected from the node, if it's not resumed in
+//! time or if more than [`MAX_BACKLOG`] messages would be buffered; the client then starts over
+//! as with a regular new connection.
+
+use std::task::{Context, Poll};
+
+use serde::Serialize;
+use tokio::{sync::oneshot, task::JoinHandle};
+use ulid::Ulid;
+
+use super::*;
+
+/// Time a suspended session is kept around waiting for the client to reconnect.
+pub(super) const RESUME_WINDOW: Duration = Duration::from_secs(30);
+/// Maximum number of messages buffered for a suspended session.
+pub(super) const MAX_BACKLOG: usize = 256;
+
+pub(super) type Listeners =
+    Arc<Mutex<VecDeque<(ContractKey, mpsc::UnboundedReceiver<HostResult>)>>>;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct ResumeParams {
+    pub resume_token: Option<Ulid>,
+}
+
+/// First message, as JSON text, of the connections opened with a resume token.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(tag = "session", rename_all = "lowercase")]
+pub(super) enum SessionStatus {
+    /// The previous session was resumed, the `missed` messages it buffered follow.
+    Resumed { missed: usize },
+    /// A new session was started, subscriptions must be made again.
+    New,
+}
+
+impl SessionStatus {
+    pub fn to_message(&self) -> Message {
+        Message::Text(serde_json::to_string(self).expect("serializable status"))
+    }
+}
+
+/// State of the connection of a client with the node.
+pub(super) struct ClientSession {
+    pub client_id: ClientId,
+    pub response_rx: mpsc::UnboundedReceiver<HostCallbackResult>,
+    pub contract_updates: Listeners,
+    /// Messages received while the session was suspended, pending to be sent to the client.
+    pub backlog: VecDeque<HostResult>,
+}
+
+impl ClientSession {
+    pub async fn new(request_sender: &WebSocketRequest) -> Result<Self, ClientError> {
+        let (response_rx, client_id) = new_client_connection(request_sender).await?;
+        Ok(Self {
+            client_id,
+            response_rx,
+            contract_updates: Arc::new(Mutex::new(VecDeque::new())),
+            backlog: VecDeque::new(),
+        })
+    }
+}
+
+struct SuspendedSession {
+    client_id: ClientId,
+    auth_token: AuthToken,
+    resume: oneshot::Sender<()>,
+    parked: JoinHandle<Option<ClientSession>>,
+}
+
+#[derive(Clone, Default)]
+pub(super) struct SuspendedSessions(Arc<parking_lot::Mutex<HashMap<Ulid, SuspendedSession>>>);
+
+impl SuspendedSessions {
+    /// Takes back the session suspended under the token, if it's still alive.
+    ///
+    /// The session is only handed over to a client with the same auth token it was suspended with.
+    pub async fn resume(
+        &self,
+        resume_token: Ulid,
+        auth_token: Option<&AuthToken>,
+    ) -> Option<ClientSession> {
+        let suspended = {
+            let mut sessions = self.0.lock();
+            match sessions.get(&resume_token) {
+                Some(suspended) if Some(&suspended.auth_token) == auth_token => {
+                    sessions.remove(&resume_token)?
+                }
+                Some(_) => {
+                    tracing::debug!(%resume_token, "resume token used with a different auth token");
+                    return None;
+                }
+                None => return None,
+            }
+        };
+        let _ = suspended.resume.send(());
+        suspended.parked.await.ok().flatten()
+    }
+
+    /// Keeps the session alive while the client reconnects, buffering the messages it receives.
+    pub fn suspend(
+        &self,
+        resume_token: Ulid,
+        session: ClientSession,
+        auth_token: AuthToken,
+        request_sender: WebSocketRequest,
+    ) {
+        let session_id = session.client_id;
+        let (resume, resume_rx) = oneshot::channel();
+        let parked = tokio::spawn(park(
+            session,
+            resume_rx,
+            resume_token,
+            self.clone(),
+            request_sender,
+        ));
+        let previous = self.0.lock().insert(
+            resume_token,
+            SuspendedSession {
+                client_id: session_id,
+                auth_token,
+                resume,
+                parked,
+            },
+        );
+        if let Some(previous) = previous {
+            // dropping the resume sender makes the previous session expire right away
+            drop(previous.resume);
+        }
+    }
+}
+
+async fn park(
+    mut session: ClientSession,
+    mut resume_rx: oneshot::Receiver<()>,
+    resume_token: Ulid,
+    sessions: SuspendedSessions,
+    request_sender: WebSocketRequest,
+) -> Option<ClientSession> {
+    let expiration = tokio::time::sleep(RESUME_WINDOW);
+    tokio::pin!(expiration);
+    let mut listeners = std::mem::take(&mut *session.contract_updates.lock().await);
+    let mut backlog_full = false;
+    while !backlog_full {
+        // messages received before the client reconnected are buffered before handing the
+        // session over
+        tokio::select! {
+            biased;
+            msg = session.response_rx.recv() => match msg {
+                Some(HostCallbackResult::Result { result, .. }) => {
+                    backlog_full = !buffer(&mut session.backlog, result);
+                }
+                Some(HostCallbackResult::SubscriptionChannel { key, callback, .. }) => {
+                    listeners.push_back((key, callback));
+                }
+                Some(HostCallbackResult::NewId { .. }) => {}
+                None => break,
+            },
+            update = std::future::poll_fn(|cx| poll_listeners(&mut listeners, cx)) => {
+                backlog_full = !buffer(&mut session.backlog, update);
+            }
+            resumed = &mut resume_rx => {
+                if resumed.is_ok() {
+                    *session.contract_updates.lock().await = listeners;
+                    return Some(session);
+                }
+                break;
+            }
+            _ = &mut expiration => {
+                tracing::debug!(cli_id = %session.client_id, "suspended session expired");
+                break;
+            }
+        }
+    }
+    if backlog_full {
+        tracing::debug!(cli_id = %session.client_id, "suspended session backlog full");
+    }
+
+    {
+        let mut suspended = sessions.0.lock();
+        if suspended
+            .get(&resume_token)
+            .is_some_and(|s| s.client_id == session.client_id)
+        {
+            suspended.remove(&resume_token);
+        }
+    }
+    let _ = request_sender
+        .send(ClientConnection::Request {
+            client_id: session.client_id,
+            req: Box::new(ClientRequest::Disconnect { cause: None }),
+            auth_token: None,
+        })
+        .await;
+    None
+}
+
+/// Receives the next update notification of any subscription, dropping the listeners of the
+/// subscriptions that are over.
+fn poll_listeners(
+    listeners: &mut VecDeque<(ContractKey, mpsc::UnboundedReceiver<HostResult>)>,
+    cx: &mut Context<'_>,
+) -> Poll<HostResult> {
+    let mut i = 0;
+    while i < listeners.len() {
+        match listeners[i].1.poll_recv(cx) {
+            Poll::Ready(Some(update)) => return Poll::Ready(update),
+            Poll::Ready(None) => {
+                listeners.remove(i);
+            }
+            Poll::Pending => i += 1,
+        }
+    }
+    Poll::Pending
+}
+
+/// Buffers a message for the client, unless the backlog is already full.
+fn buffer(backlog: &mut VecDeque<HostResult>, msg: HostResult) -> bool {
+    if backlog.len() >= MAX_BACKLOG {
+        return false;
+    }
+    backlog.push_back(msg);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> (ClientSession, mpsc::UnboundedSender<HostCallbackResult>) {
+        let (tx, response_rx) = mpsc::unbounded_channel();
+        let session = ClientSession {
+            client_id: ClientId::next(),
+            response_rx,
+            contract_updates: Arc::new(Mutex::new(VecDeque::new())),
+            backlog: VecDeque::new(),
+        };
+        (session, tx)
+    }
+
+    fn failure(client_id: ClientId) -> HostCallbackResult {
+        HostCallbackResult::Result {
+            id: client_id,
+            result: Err(ErrorKind::FailedOperation.into()),
+        }
+    }
+
+    #[tokio::test]
+    async fn resume_replays_backlog() {
+        let (request_sender, _requests) = mpsc::channel(1);
+        let sessions = SuspendedSessions::default();
+        let token = Ulid::new();
+        let owner = AuthToken::from("owner".to_owned());
+        let (session, tx) = session();
+        let client_id = session.client_id;
+        sessions.suspend(
+            token,
+            session,
+            owner.clone(),
+            WebSocketRequest(request_sender, LoadShedder::default()),
+        );
+
+        tx.send(failure(client_id)).unwrap();
+
+        // the resume token alone is not enough to take over the session
+        let other = AuthToken::from("other".to_owned());
+        assert!(sessions.resume(token, Some(&other)).await.is_none());
+        assert!(sessions.resume(token, None).await.is_none());
+        let resumed = sessions.resume(token, Some(&owner)).await.unwrap();
+        assert_eq!(resumed.client_id, client_id);
+        assert_eq!(resumed.backlog.len(), 1);
+        assert!(sessions.resume(token, Some(&owner)).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn buffers_subscription_updates() {
+        let (request_sender, _requests) = mpsc::channel(1);
+        let sessions = SuspendedSessions::default();
+        let token = Ulid::new();
+        let owner = AuthToken::from("owner".to_owned());
+        let (session, tx) = session();
+        let client_id = session.client_id;
+        sessions.suspend(
+            token,
+            session,
+            owner.clone(),
+            WebSocketRequest(request_sender, LoadShedder::default()),
+        );
+
+        let (updates, callback) = mpsc::unbounded_channel();
+        tx.send(HostCallbackResult::SubscriptionChannel {
+            id: client_id,
+            key: ContractKey::from(ContractInstanceId::new([1; 32])),
+            callback,
+        })
+        .unwrap();
+        updates
+            .send(Err(ErrorKind::FailedOperation.into()))
+            .unwrap();
+
+        let resumed = sessions.resume(token, Some(&owner)).await.unwrap();
+        assert_eq!(resumed.backlog.len(), 1);
+        assert_eq!(resumed.contract_updates.lock().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn backlog_is_bounded() {
+        let (request_sender, mut requests) = mpsc::channel(1);
+        let sessions = SuspendedSessions::default();
+        let owner = AuthToken::from("owner".to_owned());
+
+        // a full backlog can still be resumed
+        let token = Ulid::new();
+        let (session, tx) = session();
+        let client_id = session.client_id;
+        sessions.suspend(
+            token,
+            session,
+            owner.clone(),
+            WebSocketRequest(request_sender.clone(), LoadShedder::default()),
+        );
+        for _ in 0..MAX_BACKLOG {
+            tx.send(failure(client_id)).unwrap();
+        }
+        let resumed = sessions.resume(token, Some(&owner)).await.unwrap();
+        assert_eq!(resumed.backlog.len(), MAX_BACKLOG);
+
+        // one more message drops the session and disconnects the client
+        let token = Ulid::new();
+        let (session, tx) = session();
+        let client_id = session.client_id;
+        sessions.suspend(
+            token,
+            session,
+            owner.clone(),
+            WebSocketRequest(request_sender, LoadShedder::default()),
+        );
+        for _ in 0..=MAX_BACKLOG {
+            tx.send(failure(client_id)).unwrap();
+        }
+        let Some(ClientConnection::Request {
+            client_id: id, req, ..
+        }) = requests.recv().await
+        else {
+            panic!("expected the client to be disconnected");
+        };
+        assert_eq!(id, client_id);
+        assert!(matches!(*req, ClientRequest::Disconnect { .. }));
+        assert!(sessions.resume(token, Some(&owner)).await.is_none());
+    }
+
+    #[test]
+    fn session_status() {
+        let status = |status: SessionStatus| match status.to_message() {
+            Message::Text(text) => serde_json::from_str::<serde_json::Value>(&text).unwrap(),
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            status(SessionStatus::Resumed { missed: 3 }),
+            serde_json::json!({ "session": "resumed", "missed": 3 })
+        );
+        assert_eq!(
+            status(SessionStatus::New),
+            serde_json::json!({ "session": "new" })
+        );
+    }
+}
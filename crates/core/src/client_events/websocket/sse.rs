@@ -0,0 +1,710 @@
+//! Server-sent events fallback for contract update subscriptions.
+//!
+//! Clients which can't use WebSockets, such as the ones behind proxies dropping upgraded
+//! connections or simple scripts, can subscribe with `GET /v1/contract/{key}/updates`. The
+//! update notifications of the contract are streamed as `update` events, whose data is the JSON
+//! encoded update, and failures as `error` events.
+//!
+//! The subscription outlives the connection: if it's lost, the notifications keep being
+//! buffered for [`RESUME_WINDOW`]. Every event carries an id made of the id of the subscription
+//! and its position in it, which clients send back in the `Last-Event-ID` header when
+//! reconnecting (browsers do so on their own), and the events after it are replayed, including
+//! the ones sent right before the connection was lost which the client may have missed.
+//!
+//! Only the last [`MAX_BACKLOG`] events are kept. Clients which missed older ones, or whose
+//! subscription expired, are sent the updates the node applied to the contract since their last
+//! event from the update journal, as `journal` events whose data is the JSON encoded journal
+//! entry. If the journal is disabled or doesn't go back that far, they get a `reset` event
+//! instead, telling them to fetch the current state.
+//!
+//! Each client, told apart by its auth token or, for the local clients without one, by its
+//! address, can hold up to [`MAX_STREAMS_PER_CLIENT`] subscriptions streamed over up to
+//! [`MAX_CONNECTIONS_PER_CLIENT`] connections, and the gateway up to [`MAX_STREAMS`] and
+//! [`MAX_CONNECTIONS`] overall. Requests over them are rejected with a
+//! `too-many-requests` error.
+
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+use chrono::{DateTime, Utc};
+
+use axum::{
+    extract::{ConnectInfo, Path},
+    http::HeaderMap,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use futures::Stream;
+use tokio::sync::watch;
+
+use super::{
+    resume::{MAX_BACKLOG, RESUME_WINDOW},
+    *,
+};
+use crate::{
+    client_events::node_queries::{NodeQueryKind, NodeQueryResult, NodeQuerySender},
+    contract::JournalEntry,
+    server::errors::WebSocketApiError,
+};
+
+const LAST_EVENT_ID: &str = "last-event-id";
+/// Interval at which subscriptions without connections are checked for expiration.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Subscriptions held by the gateway at most.
+const MAX_STREAMS: usize = 1024;
+/// Connections streaming subscriptions at most.
+const MAX_CONNECTIONS: usize = 2048;
+const MAX_STREAMS_PER_CLIENT: usize = 16;
+const MAX_CONNECTIONS_PER_CLIENT: usize = 32;
+/// Journal entries replayed at most to a resuming client.
+const MAX_REPLAYED_ENTRIES: usize = MAX_BACKLOG;
+
+/// Sequence number, name, data, and time an event was pushed at.
+type StoredEvent = (u64, &'static str, String, DateTime<Utc>);
+
+/// Client the subscriptions count against.
+#[derive(Clone, PartialEq, Eq)]
+enum StreamClient {
+    Token(AuthToken),
+    /// Clients without token, each connection of which is told apart by its remote address.
+    Address(Option<SocketAddr>),
+}
+
+impl StreamClient {
+    fn new(auth_token: Option<&AuthToken>, address: Option<SocketAddr>) -> Self {
+        match auth_token {
+            Some(token) => Self::Token(token.clone()),
+            None => Self::Address(address),
+        }
+    }
+}
+
+/// Subscription of a client to the updates of a contract.
+struct UpdateStream {
+    id: Ulid,
+    key: ContractKey,
+    auth_token: Option<AuthToken>,
+    client: StreamClient,
+    /// The most recent events, oldest first, along with their sequence number.
+    events: parking_lot::Mutex<VecDeque<StoredEvent>>,
+    /// Changed when events are pushed, closed once the subscription is over.
+    changes: watch::Receiver<()>,
+    /// Connections the events are being streamed over.
+    readers: AtomicUsize,
+    last_read: parking_lot::Mutex<Instant>,
+}
+
+impl UpdateStream {
+    fn new(
+        key: ContractKey,
+        auth_token: Option<AuthToken>,
+        client: StreamClient,
+    ) -> (Self, watch::Sender<()>) {
+        let (changed, changes) = watch::channel(());
+        let stream = Self {
+            id: Ulid::new(),
+            key,
+            auth_token,
+            client,
+            events: parking_lot::Mutex::new(VecDeque::new()),
+            changes,
+            readers: AtomicUsize::new(0),
+            last_read: parking_lot::Mutex::new(Instant::now()),
+        };
+        (stream, changed)
+    }
+
+    fn push(&self, name: &'static str, data: String) {
+        let mut events = self.events.lock();
+        let seq = events.back().map_or(0, |(seq, ..)| seq + 1);
+        events.push_back((seq, name, data, Utc::now()));
+        if events.len() > MAX_BACKLOG {
+            events.pop_front();
+        }
+    }
+
+    /// The events from the given sequence number on, and whether some of the ones before them
+    /// were dropped already. The sequence number is advanced past the returned events.
+    fn since(&self, next: &mut u64) -> (bool, Vec<StoredEvent>) {
+        let events = self.events.lock();
+        let dropped = events.front().is_some_and(|(oldest, ..)| *next < *oldest);
+        let unsent = events
+            .iter()
+            .filter(|(seq, ..)| *seq >= *next)
+            .cloned()
+            .collect::<Vec<_>>();
+        if let Some((last, ..)) = unsent.last() {
+            *next = last + 1;
+        }
+        (dropped, unsent)
+    }
+
+    /// Time the oldest event still kept was pushed at, if some before the given sequence number
+    /// were dropped already.
+    fn dropped_before(&self, next: u64) -> Option<DateTime<Utc>> {
+        self.events
+            .lock()
+            .front()
+            .filter(|(oldest, ..)| next < *oldest)
+            .map(|(.., pushed_at)| *pushed_at)
+    }
+
+    /// Sequence number of the oldest event still kept.
+    fn oldest(&self) -> u64 {
+        self.events.lock().front().map_or(0, |(seq, ..)| *seq)
+    }
+
+    fn is_idle(&self) -> bool {
+        self.readers.load(Ordering::Relaxed) == 0
+            && self.last_read.lock().elapsed() >= RESUME_WINDOW
+    }
+
+    /// Id of the event preceding the given sequence number, pushed at the given time, from which
+    /// the stream is resumed.
+    fn event_id(&self, next: u64, pushed_at: DateTime<Utc>) -> String {
+        format!("{}:{next}:{}", self.id, pushed_at.timestamp_millis())
+    }
+}
+
+/// The last event a client received, as sent back when reconnecting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct LastEvent {
+    stream: Ulid,
+    /// Sequence number to resume from.
+    next: u64,
+    pushed_at: DateTime<Utc>,
+}
+
+fn parse_event_id(id: &str) -> Option<LastEvent> {
+    let mut parts = id.splitn(3, ':');
+    let stream = parts.next()?.parse().ok()?;
+    let next = parts.next()?.parse().ok()?;
+    let pushed_at = DateTime::from_timestamp_millis(parts.next()?.parse().ok()?)?;
+    Some(LastEvent {
+        stream,
+        next,
+        pushed_at,
+    })
+}
+
+/// The journal entries applied after the last event of a client, and before `until` if given,
+/// unless the journal may be missing some of them.
+fn missed_entries(
+    mut entries: Vec<JournalEntry>,
+    since: DateTime<Utc>,
+    until: Option<DateTime<Utc>>,
+) -> Option<Vec<JournalEntry>> {
+    // the journal only covers the gap if it goes back past the last event received
+    if !entries
+        .first()
+        .is_some_and(|entry| entry.applied_at <= since)
+    {
+        return None;
+    }
+    entries.retain(|entry| {
+        entry.applied_at > since && until.map_or(true, |until| entry.applied_at < until)
+    });
+    Some(entries)
+}
+
+fn event_data(result: HostResult) -> Option<(&'static str, String)> {
+    let error = |message: String| {
+        (
+            "error",
+            serde_json::json!({ "message": message }).to_string(),
+        )
+    };
+    match result {
+        Ok(HostResponse::ContractResponse(ContractResponse::UpdateNotification {
+            update, ..
+        })) => Some(match serde_json::to_string(&update) {
+            Ok(data) => ("update", data),
+            Err(err) => error(format!("failed encoding update: {err}")),
+        }),
+        Ok(_) => None,
+        Err(err) => Some(error(err.to_string())),
+    }
+}
+
+/// A connection the events of a subscription are streamed over.
+struct Reader {
+    stream: Arc<UpdateStream>,
+    changes: watch::Receiver<()>,
+    /// Sequence number of the next event to send.
+    next: u64,
+    pending: VecDeque<Event>,
+}
+
+impl Reader {
+    fn new(stream: Arc<UpdateStream>, next: u64) -> Self {
+        stream.readers.fetch_add(1, Ordering::Relaxed);
+        Self {
+            changes: stream.changes.clone(),
+            stream,
+            next,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Tells the client to fetch the current state, as it missed the events before the given
+    /// sequence number.
+    fn reset(&mut self, next: u64) {
+        self.pending.push_back(
+            Event::default()
+                .id(self.stream.event_id(next, Utc::now()))
+                .event("reset")
+                .data(r#"{"message":"missed updates, the current state has to be fetched"}"#),
+        );
+    }
+
+    /// Sends the client the journal entries of the updates it missed, the events before the given
+    /// sequence number.
+    fn replay(&mut self, next: u64, entries: Vec<JournalEntry>) {
+        for entry in entries {
+            let event = Event::default()
+                .id(self.stream.event_id(next, entry.applied_at))
+                .event("journal");
+            match event.json_data(&entry) {
+                Ok(event) => self.pending.push_back(event),
+                Err(err) => {
+                    tracing::debug!(stream = %self.stream.id, %err, "failed encoding journal entry");
+                    self.pending.clear();
+                    self.reset(next);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn take_events(&mut self) {
+        let (dropped, events) = self.stream.since(&mut self.next);
+        if let (true, Some((oldest, ..))) = (dropped, events.first()) {
+            tracing::debug!(stream = %self.stream.id, "missed events while streaming");
+            self.reset(*oldest);
+        }
+        for (seq, name, data, pushed_at) in events {
+            self.pending.push_back(
+                Event::default()
+                    .id(self.stream.event_id(seq + 1, pushed_at))
+                    .event(name)
+                    .data(data),
+            );
+        }
+    }
+
+    fn into_events(self) -> impl Stream<Item = Result<Event, Infallible>> {
+        futures::stream::unfold(self, |mut reader| async move {
+            loop {
+                if let Some(event) = reader.pending.pop_front() {
+                    return Some((Ok(event), reader));
+                }
+                reader.changes.borrow_and_update();
+                reader.take_events();
+                if reader.pending.is_empty() && reader.changes.changed().await.is_err() {
+                    // the subscription is over, send whatever was pushed before it ended
+                    reader.take_events();
+                    if reader.pending.is_empty() {
+                        return None;
+                    }
+                }
+            }
+        })
+    }
+}
+
+impl Drop for Reader {
+    fn drop(&mut self) {
+        *self.stream.last_read.lock() = Instant::now();
+        self.stream.readers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Clone, Default)]
+pub(super) struct UpdateStreams(Arc<parking_lot::Mutex<HashMap<Ulid, Arc<UpdateStream>>>>);
+
+/// Checks a client can hold one more connection, and one more subscription if `new_stream`.
+fn check_capacity(
+    streams: &HashMap<Ulid, Arc<UpdateStream>>,
+    client: &StreamClient,
+    new_stream: bool,
+) -> Result<(), WebSocketApiError> {
+    let too_many = |error_cause: &str| {
+        Err(WebSocketApiError::TooManyRequests {
+            error_cause: error_cause.to_owned(),
+        })
+    };
+    let connections = |streams: &mut dyn Iterator<Item = &Arc<UpdateStream>>| {
+        streams
+            .map(|stream| stream.readers.load(Ordering::Relaxed))
+            .sum::<usize>()
+    };
+    let own = streams
+        .values()
+        .filter(|stream| stream.client == *client)
+        .collect::<Vec<_>>();
+    if new_stream && streams.len() >= MAX_STREAMS {
+        return too_many("the gateway holds too many update streams");
+    }
+    if new_stream && own.len() >= MAX_STREAMS_PER_CLIENT {
+        return too_many("the client holds too many update streams");
+    }
+    if connections(&mut streams.values()) >= MAX_CONNECTIONS {
+        return too_many("the gateway streams too many updates");
+    }
+    if connections(&mut own.into_iter()) >= MAX_CONNECTIONS_PER_CLIENT {
+        return too_many("the client streams too many updates");
+    }
+    Ok(())
+}
+
+impl UpdateStreams {
+    /// Connects to the subscription with the given id, as long as it was opened for the same
+    /// contract and with the same auth token. The connection counts against the client which
+    /// opened it.
+    fn resume(
+        &self,
+        id: Ulid,
+        key: &ContractKey,
+        auth_token: Option<&AuthToken>,
+        next: u64,
+    ) -> Result<Option<Reader>, WebSocketApiError> {
+        let streams = self.0.lock();
+        let Some(stream) = streams
+            .get(&id)
+            .filter(|stream| stream.key == *key && stream.auth_token.as_ref() == auth_token)
+        else {
+            return Ok(None);
+        };
+        check_capacity(&streams, &stream.client, false)?;
+        Ok(Some(Reader::new(stream.clone(), next)))
+    }
+
+    /// Subscribes to the contract, connecting to the new subscription.
+    async fn open(
+        &self,
+        subscribe: ClientRequest<'static>,
+        key: ContractKey,
+        auth_token: Option<AuthToken>,
+        address: Option<SocketAddr>,
+        request_sender: WebSocketRequest,
+    ) -> Result<Reader, WebSocketApiError> {
+        let client = StreamClient::new(auth_token.as_ref(), address);
+        let (stream, changed) = UpdateStream::new(key, auth_token.clone(), client.clone());
+        let stream = Arc::new(stream);
+        let reader = {
+            let mut streams = self.0.lock();
+            check_capacity(&streams, &client, true)?;
+            streams.insert(stream.id, stream.clone());
+            Reader::new(stream.clone(), 0)
+        };
+        let subscribed = async {
+            let (response_rx, client_id) = new_client_connection(&request_sender).await?;
+            request_sender
+                .send(ClientConnection::Request {
+                    client_id,
+                    req: Box::new(subscribe),
+                    auth_token,
+                })
+                .await
+                .map_err(|_| ErrorKind::NodeUnavailable)?;
+            Ok::<_, ClientError>((response_rx, client_id))
+        };
+        let (response_rx, client_id) = match subscribed.await {
+            Ok(subscribed) => subscribed,
+            Err(err) => {
+                self.0.lock().remove(&stream.id);
+                return Err(WebSocketApiError::NodeError {
+                    error_cause: err.to_string(),
+                });
+            }
+        };
+        tokio::spawn(pump(
+            stream,
+            changed,
+            client_id,
+            response_rx,
+            self.clone(),
+            request_sender,
+        ));
+        Ok(reader)
+    }
+}
+
+/// Pushes the notifications of the subscription to its stream, until no connection has read it
+/// for longer than the resume window.
+async fn pump(
+    stream: Arc<UpdateStream>,
+    changed: watch::Sender<()>,
+    client_id: ClientId,
+    mut response_rx: mpsc::UnboundedReceiver<HostCallbackResult>,
+    streams: UpdateStreams,
+    request_sender: WebSocketRequest,
+) {
+    let mut updates: Option<mpsc::UnboundedReceiver<HostResult>> = None;
+    let mut idle_check = tokio::time::interval(IDLE_CHECK_INTERVAL);
+    loop {
+        let result = tokio::select! {
+            msg = response_rx.recv() => match msg {
+                Some(HostCallbackResult::SubscriptionChannel { callback, .. }) => {
+                    updates = Some(callback);
+                    continue;
+                }
+                Some(HostCallbackResult::Result { result, .. }) => result,
+                Some(HostCallbackResult::NewId { .. }) => continue,
+                None => break,
+            },
+            update = async { updates.as_mut()?.recv().await }, if updates.is_some() => match update {
+                Some(update) => update,
+                None => break,
+            },
+            _ = idle_check.tick() => {
+                if stream.is_idle() {
+                    tracing::debug!(stream = %stream.id, "update stream expired");
+                    break;
+                }
+                continue;
+            }
+        };
+        if let Some((name, data)) = event_data(result) {
+            stream.push(name, data);
+            changed.send_replace(());
+        }
+    }
+
+    streams.0.lock().remove(&stream.id);
+    let _ = request_sender
+        .send(ClientConnection::Request {
+            client_id,
+            req: Box::new(ClientRequest::Disconnect { cause: None }),
+            auth_token: None,
+        })
+        .await;
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn contract_updates(
+    Path(key): Path<String>,
+    headers: HeaderMap,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    Extension(auth_token): Extension<Option<AuthToken>>,
+    Extension(rs): Extension<WebSocketRequest>,
+    Extension(scopes): Extension<TokenScopes>,
+    Extension(streams): Extension<UpdateStreams>,
+    Extension(queries): Extension<NodeQuerySender>,
+) -> Result<Response, WebSocketApiError> {
+    let key = ContractKey::from_id(key).map_err(|err| WebSocketApiError::InvalidParam {
+        error_cause: format!("invalid contract key: {err}"),
+    })?;
+    let subscribe = ClientRequest::from(ContractRequest::Subscribe { key, summary: None });
+    scopes
+        .authorize(auth_token.as_ref(), &subscribe)
+        .map_err(|err| WebSocketApiError::Forbidden {
+            error_cause: err.to_string(),
+        })?;
+
+    let last_event = headers
+        .get(LAST_EVENT_ID)
+        .and_then(|id| id.to_str().ok())
+        .and_then(parse_event_id);
+    let resumed = match last_event {
+        Some(last) => streams
+            .resume(last.stream, &key, auth_token.as_ref(), last.next)?
+            .map(|reader| (reader, last)),
+        None => None,
+    };
+    let reader = match resumed {
+        Some((mut reader, last)) => {
+            tracing::debug!(stream = %last.stream, next = last.next, "resumed update stream");
+            if let Some(until) = reader.stream.dropped_before(last.next) {
+                let next = reader.stream.oldest();
+                match journal_since(&queries, key, last.pushed_at, Some(until)).await {
+                    Some(entries) => {
+                        reader.next = next;
+                        reader.replay(next, entries);
+                    }
+                    None => tracing::debug!(stream = %last.stream, "missed events on resumption"),
+                }
+            }
+            reader
+        }
+        None => {
+            let address = connect_info.map(|ConnectInfo(address)| address);
+            let mut reader = streams
+                .open(subscribe, key, auth_token, address, rs)
+                .await?;
+            if let Some(last) = last_event {
+                match journal_since(&queries, key, last.pushed_at, None).await {
+                    Some(entries) => reader.replay(0, entries),
+                    None => reader.reset(0),
+                }
+            }
+            reader
+        }
+    };
+    Ok(Sse::new(reader.into_events())
+        .keep_alive(KeepAlive::default())
+        .into_response())
+}
+
+/// The updates applied to the contract since the given time, and before `until` if given, from
+/// the update journal of the node, unless it may be missing some.
+async fn journal_since(
+    queries: &NodeQuerySender,
+    key: ContractKey,
+    since: DateTime<Utc>,
+    until: Option<DateTime<Utc>>,
+) -> Option<Vec<JournalEntry>> {
+    let query = NodeQueryKind::UpdateJournal {
+        key,
+        last: MAX_REPLAYED_ENTRIES,
+    };
+    match queries.query(query).await {
+        Ok(NodeQueryResult::UpdateJournal(entries)) => missed_entries(entries, since, until),
+        Ok(other) => {
+            tracing::debug!(%key, "unexpected node query result: {other:?}");
+            None
+        }
+        Err(err) => {
+            tracing::debug!(%key, %err, "update journal unavailable");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resume_from_last_event() {
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let (stream, _changed) = UpdateStream::new(key, None, StreamClient::new(None, None));
+        for i in 0..3 {
+            stream.push("update", i.to_string());
+        }
+
+        let pushed_at = DateTime::from_timestamp_millis(1_700_000_000_000).unwrap();
+        let last = parse_event_id(&stream.event_id(1, pushed_at)).unwrap();
+        assert_eq!(
+            last,
+            LastEvent {
+                stream: stream.id,
+                next: 1,
+                pushed_at
+            }
+        );
+        let mut next = last.next;
+        assert_eq!(stream.dropped_before(next), None);
+        let (dropped, events) = stream.since(&mut next);
+        assert!(!dropped);
+        assert_eq!(
+            events.iter().map(|(seq, ..)| *seq).collect::<Vec<_>>(),
+            [1, 2]
+        );
+        assert_eq!(next, 3);
+        assert!(stream.since(&mut next).1.is_empty());
+
+        // the oldest events are dropped once the backlog is full
+        for i in 3..=MAX_BACKLOG {
+            stream.push("update", i.to_string());
+        }
+        assert_eq!(stream.dropped_before(0), Some(stream.events.lock()[0].3));
+        assert_eq!(stream.oldest(), 1);
+        let mut next = 0;
+        let (dropped, events) = stream.since(&mut next);
+        assert!(dropped);
+        assert_eq!(events.len(), MAX_BACKLOG);
+        assert_eq!(events[0].0, 1);
+
+        assert_eq!(parse_event_id("not-an-id"), None);
+        assert_eq!(parse_event_id(&format!("{}:1", stream.id)), None);
+        assert_eq!(parse_event_id(&format!("{}:x:0", stream.id)), None);
+    }
+
+    #[test]
+    fn replay_missed_updates_from_journal() {
+        let at = |secs| DateTime::from_timestamp(secs, 0).unwrap();
+        let entry = |secs| JournalEntry {
+            applied_at: at(secs),
+            updates: vec![],
+            previous_state_hash: String::new(),
+            state_hash: String::new(),
+            state_size: 0,
+        };
+        let journal = (1..=5).map(entry).collect::<Vec<_>>();
+        let replayed = |entries: Option<Vec<JournalEntry>>| {
+            entries.map(|entries| {
+                entries
+                    .iter()
+                    .map(|entry| entry.applied_at.timestamp())
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(
+            replayed(missed_entries(journal.clone(), at(2), None)),
+            Some(vec![3, 4, 5])
+        );
+        assert_eq!(
+            replayed(missed_entries(journal.clone(), at(2), Some(at(5)))),
+            Some(vec![3, 4])
+        );
+        // the journal doesn't go back to the last event received
+        assert_eq!(replayed(missed_entries(journal, at(0), None)), None);
+        assert_eq!(replayed(missed_entries(vec![], at(0), None)), None);
+    }
+
+    #[test]
+    fn cap_update_streams() {
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let token = AuthToken::generate();
+        let client = StreamClient::new(Some(&token), None);
+        let mut streams = HashMap::new();
+        let mut readers = vec![];
+        for _ in 0..MAX_STREAMS_PER_CLIENT {
+            check_capacity(&streams, &client, true).unwrap();
+            let (stream, _changed) = UpdateStream::new(key, Some(token.clone()), client.clone());
+            let stream = Arc::new(stream);
+            streams.insert(stream.id, stream.clone());
+            readers.push(Reader::new(stream, 0));
+        }
+        assert!(matches!(
+            check_capacity(&streams, &client, true),
+            Err(WebSocketApiError::TooManyRequests { .. })
+        ));
+        // other clients are still served
+        check_capacity(&streams, &StreamClient::new(None, None), true).unwrap();
+
+        let stream = streams.values().next().unwrap().clone();
+        while readers.len() < MAX_CONNECTIONS_PER_CLIENT {
+            check_capacity(&streams, &client, false).unwrap();
+            readers.push(Reader::new(stream.clone(), 0));
+        }
+        assert!(matches!(
+            check_capacity(&streams, &client, false),
+            Err(WebSocketApiError::TooManyRequests { .. })
+        ));
+        readers.pop();
+        check_capacity(&streams, &client, false).unwrap();
+    }
+
+    #[test]
+    fn cap_anonymous_streams_per_address() {
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let script = StreamClient::new(None, Some(([127, 0, 0, 1], 50_000).into()));
+        let mut streams = HashMap::new();
+        for _ in 0..MAX_STREAMS_PER_CLIENT {
+            let (stream, _changed) = UpdateStream::new(key, None, script.clone());
+            streams.insert(stream.id, Arc::new(stream));
+        }
+        assert!(matches!(
+            check_capacity(&streams, &script, true),
+            Err(WebSocketApiError::TooManyRequests { .. })
+        ));
+        // other clients without token are still served
+        let other = StreamClient::new(None, Some(([127, 0, 0, 1], 50_001).into()));
+        check_capacity(&streams, &other, true).unwrap();
+    }
+}
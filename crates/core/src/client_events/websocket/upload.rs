@@ -0,0 +1,524 @@
+//! Chunked upload protocol for client requests too large to be sent in a single frame
+//! (e.g. contract PUTs with a big initial state).
+//!
+//! Upload messages are always bincode encoded. The client opens an upload with
+//! [`UploadMessage::Begin`], streams the serialized client request (encoded with the
+//! connection encoding protocol) in sequential chunks and finally sends
+//! [`UploadMessage::Commit`], at which point the request is reassembled and forwarded to the
+//! node as if it had been received through the command endpoint.
+//!
+//! Uploads belong to the client connection which opened them. When that connection drops the
+//! upload is kept around, so the client can reconnect with the same auth token, send `Begin`
+//! again with the same id and resume from the returned chunk index. Uploads opened without a
+//! token can't be resumed by another connection.
+
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use ulid::Ulid;
+
+use super::*;
+
+/// Maximum size of a single chunk.
+const MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+/// Room left for the request encoding on top of the contract code and state.
+const UPLOAD_ENCODING_OVERHEAD: u64 = 64 * 1024;
+/// Maximum number of bytes reserved across the pending uploads of an auth token, including the
+/// uploads detached from their connection. Clients without a token share a single budget.
+const MAX_CLIENT_PENDING_BYTES: u64 = 512 * 1024 * 1024;
+/// Maximum number of bytes reserved across all pending uploads.
+const MAX_PENDING_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+/// Time after which an upload without any activity is discarded.
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(60 * 10);
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) enum UploadMessage {
+    Begin {
+        upload_id: Ulid,
+        total_size: u64,
+    },
+    Chunk {
+        upload_id: Ulid,
+        index: u32,
+        data: Vec<u8>,
+    },
+    Commit {
+        upload_id: Ulid,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) enum UploadResponse {
+    /// The upload is open; the client must continue sending from `next_chunk`.
+    Accepted {
+        upload_id: Ulid,
+        next_chunk: u32,
+    },
+    ChunkReceived {
+        upload_id: Ulid,
+        next_chunk: u32,
+    },
+    Error {
+        cause: String,
+    },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum UploadError {
+    #[error("upload of {size} bytes exceeds the maximum of {max} bytes")]
+    TooLarge { size: u64, max: u64 },
+    #[error("unknown upload {0}")]
+    UnknownUpload(Ulid),
+    #[error("upload {upload_id} was started with a different size")]
+    SizeMismatch { upload_id: Ulid },
+    #[error("expected chunk {expected}, received {received}")]
+    OutOfOrder { expected: u32, received: u32 },
+    #[error("upload incomplete, received {received} of {expected} bytes")]
+    Incomplete { received: u64, expected: u64 },
+    #[error("{pending} bytes already pending, the maximum is {max} bytes")]
+    TooManyPendingBytes { pending: u64, max: u64 },
+    #[error("malformed upload message: {0}")]
+    Malformed(String),
+}
+
+/// Maximum size of a reassembled upload: a put carrying the biggest contract and state allowed.
+fn max_upload_size(limits: &PayloadLimits) -> u64 {
+    (limits.max_contract_size as u64)
+        .saturating_add(limits.max_state_size as u64)
+        .saturating_add(UPLOAD_ENCODING_OVERHEAD)
+}
+
+struct PendingUpload {
+    /// Connection currently driving the upload, `None` once it has been dropped.
+    owner: Option<ClientId>,
+    auth_token: Option<AuthToken>,
+    total_size: u64,
+    data: Vec<u8>,
+    next_chunk: u32,
+    last_activity: Instant,
+}
+
+#[derive(Clone, Default)]
+pub(super) struct PendingUploads(Arc<parking_lot::Mutex<HashMap<Ulid, PendingUpload>>>);
+
+impl PendingUploads {
+    /// Opens a new upload or resumes an existing one, returning the next expected chunk index.
+    ///
+    /// An upload can only be resumed by the client owning it or, once that client has
+    /// disconnected, by a new connection authenticated with the same token. Anonymous uploads
+    /// die with their connection.
+    fn begin(
+        &self,
+        client_id: ClientId,
+        auth_token: Option<&AuthToken>,
+        upload_id: Ulid,
+        total_size: u64,
+        max_size: u64,
+    ) -> Result<u32, UploadError> {
+        if total_size > max_size {
+            return Err(UploadError::TooLarge {
+                size: total_size,
+                max: max_size,
+            });
+        }
+        let mut uploads = self.0.lock();
+        uploads.retain(|_, upload| upload.last_activity.elapsed() < UPLOAD_TIMEOUT);
+        if let Some(upload) = uploads.get_mut(&upload_id) {
+            let resumable = match upload.owner {
+                Some(owner) => owner == client_id,
+                None => upload.auth_token.is_some() && upload.auth_token.as_ref() == auth_token,
+            };
+            if !resumable {
+                return Err(UploadError::UnknownUpload(upload_id));
+            }
+            if upload.total_size != total_size {
+                return Err(UploadError::SizeMismatch { upload_id });
+            }
+            upload.owner = Some(client_id);
+            upload.last_activity = Instant::now();
+            return Ok(upload.next_chunk);
+        }
+        // budgets follow the token rather than the connection, so reconnecting doesn't reset them
+        let (pending, client_pending) =
+            uploads
+                .values()
+                .fold((0u64, 0u64), |(pending, client_pending), upload| {
+                    let client_pending = if upload.auth_token.as_ref() == auth_token {
+                        client_pending + upload.total_size
+                    } else {
+                        client_pending
+                    };
+                    (pending + upload.total_size, client_pending)
+                });
+        if client_pending + total_size > MAX_CLIENT_PENDING_BYTES {
+            return Err(UploadError::TooManyPendingBytes {
+                pending: client_pending,
+                max: MAX_CLIENT_PENDING_BYTES,
+            });
+        }
+        if pending + total_size > MAX_PENDING_BYTES {
+            return Err(UploadError::TooManyPendingBytes {
+                pending,
+                max: MAX_PENDING_BYTES,
+            });
+        }
+        uploads.insert(
+            upload_id,
+            PendingUpload {
+                owner: Some(client_id),
+                auth_token: auth_token.cloned(),
+                total_size,
+                data: Vec::new(),
+                next_chunk: 0,
+                last_activity: Instant::now(),
+            },
+        );
+        Ok(0)
+    }
+
+    /// Detaches the uploads of a client which disconnected, so they can be resumed later.
+    fn release(&self, client_id: ClientId) {
+        for upload in self.0.lock().values_mut() {
+            if upload.owner == Some(client_id) {
+                upload.owner = None;
+            }
+        }
+    }
+
+    /// Appends a chunk to the upload, returning the next expected chunk index.
+    ///
+    /// Chunks already received are acknowledged without being appended again, so clients
+    /// can safely retransmit after a reconnection.
+    fn chunk(
+        &self,
+        client_id: ClientId,
+        upload_id: Ulid,
+        index: u32,
+        data: Vec<u8>,
+    ) -> Result<u32, UploadError> {
+        if data.len() > MAX_CHUNK_SIZE {
+            return Err(UploadError::TooLarge {
+                size: data.len() as u64,
+                max: MAX_CHUNK_SIZE as u64,
+            });
+        }
+        let mut uploads = self.0.lock();
+        let upload = uploads
+            .get_mut(&upload_id)
+            .filter(|upload| upload.owner == Some(client_id))
+            .ok_or(UploadError::UnknownUpload(upload_id))?;
+        upload.last_activity = Instant::now();
+        if index < upload.next_chunk {
+            return Ok(upload.next_chunk);
+        }
+        if index > upload.next_chunk {
+            return Err(UploadError::OutOfOrder {
+                expected: upload.next_chunk,
+                received: index,
+            });
+        }
+        let size = (upload.data.len() + data.len()) as u64;
+        if size > upload.total_size {
+            return Err(UploadError::TooLarge {
+                size,
+                max: upload.total_size,
+            });
+        }
+        upload.data.extend_from_slice(&data);
+        upload.next_chunk += 1;
+        Ok(upload.next_chunk)
+    }
+
+    /// Completes the upload, returning the reassembled payload.
+    fn commit(&self, client_id: ClientId, upload_id: Ulid) -> Result<Vec<u8>, UploadError> {
+        let mut uploads = self.0.lock();
+        let upload = uploads
+            .get(&upload_id)
+            .filter(|upload| upload.owner == Some(client_id))
+            .ok_or(UploadError::UnknownUpload(upload_id))?;
+        if upload.data.len() as u64 != upload.total_size {
+            return Err(UploadError::Incomplete {
+                received: upload.data.len() as u64,
+                expected: upload.total_size,
+            });
+        }
+        let upload = uploads.remove(&upload_id).expect("upload exists");
+        Ok(upload.data)
+    }
+}
+
+pub(super) async fn websocket_upload(
+    ws: WebSocketUpgrade,
+    Extension(auth_token): Extension<Option<AuthToken>>,
+    Extension(encoding_protoc): Extension<EncodingProtocol>,
+    Extension(rs): Extension<WebSocketRequest>,
+    Extension(uploads): Extension<PendingUploads>,
+    Extension(scopes): Extension<TokenScopes>,
+    Extension(limits): Extension<PayloadLimits>,
+) -> axum::response::Response {
+    let on_upgrade = move |ws: WebSocket| async move {
+        if let Err(error) =
+            upload_interface(rs, auth_token, encoding_protoc, uploads, scopes, limits, ws).await
+        {
+            tracing::error!("{error}");
+        }
+    };
+    let max_frame_size = MAX_CHUNK_SIZE + UPLOAD_ENCODING_OVERHEAD as usize;
+    ws.max_message_size(max_frame_size)
+        .max_frame_size(max_frame_size)
+        .on_upgrade(on_upgrade)
+}
+
+async fn upload_interface(
+    request_sender: WebSocketRequest,
+    mut auth_token: Option<AuthToken>,
+    encoding_protoc: EncodingProtocol,
+    uploads: PendingUploads,
+    scopes: TokenScopes,
+    limits: PayloadLimits,
+    ws: WebSocket,
+) -> anyhow::Result<()> {
+    let (mut response_rx, client_id) = new_client_connection(&request_sender).await?;
+    let result = upload_loop(
+        &request_sender,
+        &mut response_rx,
+        client_id,
+        &mut auth_token,
+        encoding_protoc,
+        &uploads,
+        &scopes,
+        limits,
+        ws,
+    )
+    .await;
+    uploads.release(client_id);
+    let _ = request_sender
+        .send(ClientConnection::Request {
+            client_id,
+            req: Box::new(ClientRequest::Disconnect { cause: None }),
+            auth_token: None,
+        })
+        .await;
+    result
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn upload_loop(
+    request_sender: &WebSocketRequest,
+    response_rx: &mut mpsc::UnboundedReceiver<HostCallbackResult>,
+    client_id: ClientId,
+    auth_token: &mut Option<AuthToken>,
+    encoding_protoc: EncodingProtocol,
+    uploads: &PendingUploads,
+    scopes: &TokenScopes,
+    limits: PayloadLimits,
+    ws: WebSocket,
+) -> anyhow::Result<()> {
+    let (mut server_sink, mut client_stream) = ws.split();
+    while let Some(msg) = client_stream.next().await {
+        let data = match msg? {
+            Message::Binary(data) => data,
+            Message::Close(_) => break,
+            Message::Ping(ping) => {
+                server_sink.send(Message::Pong(ping)).await?;
+                continue;
+            }
+            m => {
+                tracing::debug!(msg = ?m, "received random message");
+                continue;
+            }
+        };
+        let reply = match bincode::deserialize::<UploadMessage>(&data) {
+            Ok(UploadMessage::Begin {
+                upload_id,
+                total_size,
+            }) => uploads
+                .begin(
+                    client_id,
+                    auth_token.as_ref(),
+                    upload_id,
+                    total_size,
+                    max_upload_size(&limits),
+                )
+                .map(|next_chunk| UploadResponse::Accepted {
+                    upload_id,
+                    next_chunk,
+                }),
+            Ok(UploadMessage::Chunk {
+                upload_id,
+                index,
+                data,
+            }) => uploads
+                .chunk(client_id, upload_id, index, data)
+                .map(|next_chunk| UploadResponse::ChunkReceived {
+                    upload_id,
+                    next_chunk,
+                }),
+            Ok(UploadMessage::Commit { upload_id }) => match uploads.commit(client_id, upload_id) {
+                Ok(payload) => {
+                    tracing::debug!(%upload_id, size = payload.len(), "upload completed");
+                    match process_client_request(
+                        client_id,
+                        Ok(Message::Binary(payload)),
+                        request_sender,
+                        auth_token,
+                        encoding_protoc,
+                        scopes,
+                        limits,
+                    )
+                    .await
+                    {
+                        Ok(Some(error)) => server_sink.send(error).await?,
+                        Ok(None) => {
+                            process_host_response(
+                                response_rx.recv().await,
+                                client_id,
+                                encoding_protoc,
+                                &mut server_sink,
+                            )
+                            .await?;
+                        }
+                        Err(None) => break,
+                        Err(Some(err)) => return Err(err),
+                    }
+                    continue;
+                }
+                Err(err) => Err(err),
+            },
+            Err(err) => Err(UploadError::Malformed(format!("{err}"))),
+        };
+        let reply = reply.unwrap_or_else(|err| {
+            tracing::debug!(cli_id = %client_id, "upload error: {err}");
+            UploadResponse::Error {
+                cause: format!("{err}"),
+            }
+        });
+        server_sink
+            .send(Message::Binary(bincode::serialize(&reply)?))
+            .await?;
+    }
+    let _ = server_sink.send(Message::Close(None)).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX: u64 = 1024;
+
+    #[test]
+    fn reassemble_and_resume() {
+        let uploads = PendingUploads::default();
+        let cli = ClientId::next();
+        let id = Ulid::new();
+        assert_eq!(uploads.begin(cli, None, id, 6, MAX).unwrap(), 0);
+        assert_eq!(uploads.chunk(cli, id, 0, vec![1, 2, 3]).unwrap(), 1);
+
+        // resuming returns the next missing chunk and duplicates are ignored
+        assert_eq!(uploads.begin(cli, None, id, 6, MAX).unwrap(), 1);
+        assert_eq!(uploads.chunk(cli, id, 0, vec![1, 2, 3]).unwrap(), 1);
+        assert!(matches!(
+            uploads.chunk(cli, id, 2, vec![4]),
+            Err(UploadError::OutOfOrder {
+                expected: 1,
+                received: 2
+            })
+        ));
+        assert!(matches!(
+            uploads.commit(cli, id),
+            Err(UploadError::Incomplete { .. })
+        ));
+
+        assert_eq!(uploads.chunk(cli, id, 1, vec![4, 5, 6]).unwrap(), 2);
+        assert_eq!(uploads.commit(cli, id).unwrap(), vec![1, 2, 3, 4, 5, 6]);
+        assert!(matches!(
+            uploads.commit(cli, id),
+            Err(UploadError::UnknownUpload(_))
+        ));
+    }
+
+    #[test]
+    fn size_limits() {
+        let uploads = PendingUploads::default();
+        let cli = ClientId::next();
+        let id = Ulid::new();
+        assert!(matches!(
+            uploads.begin(cli, None, id, MAX + 1, MAX),
+            Err(UploadError::TooLarge { .. })
+        ));
+        uploads.begin(cli, None, id, 2, MAX).unwrap();
+        assert!(matches!(
+            uploads.chunk(cli, id, 0, vec![0; 3]),
+            Err(UploadError::TooLarge { size: 3, max: 2 })
+        ));
+        assert!(matches!(
+            uploads.begin(cli, None, id, 3, MAX),
+            Err(UploadError::SizeMismatch { .. })
+        ));
+        assert!(matches!(
+            uploads.begin(cli, None, Ulid::new(), MAX_CLIENT_PENDING_BYTES, u64::MAX),
+            Err(UploadError::TooManyPendingBytes { .. })
+        ));
+    }
+
+    #[test]
+    fn uploads_belong_to_their_client() {
+        let uploads = PendingUploads::default();
+        let token = AuthToken::generate();
+        let (owner, other) = (ClientId::next(), ClientId::next());
+        let id = Ulid::new();
+        uploads.begin(owner, Some(&token), id, 2, MAX).unwrap();
+        assert!(matches!(
+            uploads.chunk(other, id, 0, vec![1]),
+            Err(UploadError::UnknownUpload(_))
+        ));
+        assert!(matches!(
+            uploads.begin(other, Some(&token), id, 2, MAX),
+            Err(UploadError::UnknownUpload(_))
+        ));
+
+        // once the owner disconnects only a client with the same token can take over
+        uploads.chunk(owner, id, 0, vec![1]).unwrap();
+        uploads.release(owner);
+        assert!(matches!(
+            uploads.begin(other, None, id, 2, MAX),
+            Err(UploadError::UnknownUpload(_))
+        ));
+        assert_eq!(uploads.begin(other, Some(&token), id, 2, MAX).unwrap(), 1);
+        uploads.chunk(other, id, 1, vec![2]).unwrap();
+        assert_eq!(uploads.commit(other, id).unwrap(), vec![1, 2]);
+
+        // anonymous uploads can't be taken over
+        let anonymous = Ulid::new();
+        uploads.begin(owner, None, anonymous, 2, MAX).unwrap();
+        uploads.release(owner);
+        assert!(matches!(
+            uploads.begin(other, None, anonymous, 2, MAX),
+            Err(UploadError::UnknownUpload(_))
+        ));
+    }
+
+    #[test]
+    fn budgets_survive_reconnections() {
+        let uploads = PendingUploads::default();
+        let token = AuthToken::generate();
+        let half = MAX_CLIENT_PENDING_BYTES / 2;
+        for token in [Some(&token), None] {
+            let first = ClientId::next();
+            uploads
+                .begin(first, token, Ulid::new(), half, u64::MAX)
+                .unwrap();
+            uploads.release(first);
+            // orphaned uploads keep counting for the next connection
+            let second = ClientId::next();
+            uploads
+                .begin(second, token, Ulid::new(), half, u64::MAX)
+                .unwrap();
+            assert!(matches!(
+                uploads.begin(second, token, Ulid::new(), 1, u64::MAX),
+                Err(UploadError::TooManyPendingBytes { .. })
+            ));
+        }
+    }
+}
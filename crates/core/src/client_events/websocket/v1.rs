@@ -1,17 +1,60 @@
 use super::*;
 
 impl WebSocketProxy {
-    pub fn as_router_v1(server_routing: Router) -> (Self, Router) {
+    pub fn as_router_v1(
+        server_routing: Router,
+        webrtc: bool,
+        shedder: LoadShedder,
+    ) -> (Self, Router) {
         let (proxy_request_sender, proxy_server_request) = mpsc::channel(PARALLELISM);
+        let (query_sender, node_queries) = node_queries::channel();
 
-        let router = server_routing
+        let mut router = server_routing
             .route("/v1/contract/command", get(websocket_commands))
-            .layer(Extension(WebSocketRequest(proxy_request_sender)))
+            .route("/v1/contract/upload", get(upload::websocket_upload))
+            .route(
+                "/v1/contract/multiplex",
+                get(multiplex::websocket_multiplex),
+            )
+            .route("/v1/contract/:key/updates", get(sse::contract_updates))
+            .route("/v1/contract/:key/journal", get(queries::update_journal))
+            .route("/v1/contract/:key/head", get(queries::contract_head))
+            .route("/v1/contracts/head", post(queries::contracts_head))
+            .route(
+                "/v1/contract/:key/pin",
+                put(queries::pin_contract).delete(queries::unpin_contract),
+            )
+            .route("/v1/contracts/pinned", get(queries::pinned_contracts))
+            .route(
+                "/v1/contract/resolve/:prefix",
+                get(queries::resolve_contract),
+            )
+            .route("/v1/transaction/:tx", delete(queries::cancel_transaction))
+            .route(
+                "/v1/delegate/consent/:request_id",
+                put(queries::allow_secrets_consent).delete(queries::deny_secrets_consent),
+            )
+            .route("/v1/gossip/:topic", get(gossip::websocket_gossip))
+            .route(
+                "/v1/node/diagnostics",
+                get(diagnostics::websocket_diagnostics),
+            );
+        if webrtc {
+            router = router.route("/v1/webrtc/offer", post(queries::webrtc_offer));
+        }
+        let router = router
+            .layer(Extension(upload::PendingUploads::default()))
+            .layer(Extension(sse::UpdateStreams::default()))
+            .layer(Extension(TokenScopes::default()))
+            .layer(Extension(resume::SuspendedSessions::default()))
+            .layer(Extension(WebSocketRequest(proxy_request_sender, shedder)))
+            .layer(Extension(query_sender))
             .layer(axum::middleware::from_fn(connection_info));
         (
             WebSocketProxy {
                 proxy_server_request,
                 response_channels: HashMap::new(),
+                node_queries: Some(node_queries),
             },
             router,
         )
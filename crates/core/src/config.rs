@@ -10,7 +10,6 @@ use std::{
 };
 
 use anyhow::Context;
-use directories::ProjectDirs;
 use either::Either;
 use itertools::Itertools;
 use once_cell::sync::Lazy;
@@ -18,10 +17,27 @@ use pkcs8::DecodePublicKey;
 use serde::{Deserialize, Serialize};
 use tokio::runtime::Runtime;
 
-use crate::{dev_tool::PeerId, local_node::OperationMode, transport::TransportKeypair};
+use crate::{
+    dev_tool::PeerId, local_node::OperationMode, transport::TransportKeypair,
+    wasm_runtime::WasmEngine,
+};
 
+mod bootstrap;
+mod check;
+mod dirs;
+mod reload;
 mod secret;
+mod service;
+pub use bootstrap::{BootstrapArgs, BootstrapConfig};
+pub use check::{check_config_file, ConfigDiagnostic};
+pub(crate) use dirs::default_webs_dir;
+pub use dirs::NodeDirs;
+#[cfg(unix)]
+pub(crate) use reload::reload_on_hangup;
+pub(crate) use reload::{follow_access_log, CacheSizes, RuntimeConfig};
+pub use reload::{ReloadError, RuntimeSettings, RuntimeSettingsUpdate};
 pub use secret::*;
+pub use service::{ServiceDefinition, ServicePlatform};
 
 /// Default maximum number of connections for the peer.
 pub const DEFAULT_MAX_CONNECTIONS: usize = 20;
@@ -46,8 +62,6 @@ pub(crate) const PCK_VERSION: &str = env!("CARGO_PKG_VERSION");
 // Initialize the executor once.
 static ASYNC_RT: Lazy<Option<Runtime>> = Lazy::new(GlobalExecutor::initialize_async_rt);
 
-const QUALIFIER: &str = "";
-const ORGANIZATION: &str = "The Freenet Project Inc";
 const APPLICATION: &str = "Freenet";
 
 const FREENET_GATEWAYS_INDEX: &str = "https://freenet.org/keys/gateways.toml";
@@ -70,9 +84,43 @@ pub struct ConfigArgs {
     #[arg(long, env = "LOG_LEVEL")]
     pub log_level: Option<tracing::log::LevelFilter>,
 
+    /// Number of applied updates to keep per contract in the update journal.
+    /// The journal is disabled if not set.
+    #[arg(long, env = "UPDATE_JOURNAL_RETENTION")]
+    pub update_journal_retention: Option<usize>,
+
+    /// Compiler backend used to run contracts and delegates, default is singlepass.
+    #[arg(long, value_enum, env = "WASM_ENGINE")]
+    pub wasm_engine: Option<WasmEngine>,
+
+    /// Length, in seconds, of the time quantum contracts can read, default is 60.
+    /// Must be the same for every peer in the network.
+    #[arg(long, env = "CONTRACT_TIME_EPOCH")]
+    pub contract_time_epoch: Option<u64>,
+
+    /// Number of peers closest to a contract location which should cache it, default is 3.
+    /// Contracts may suggest their own replication factor.
+    #[arg(long, env = "REPLICATION_FACTOR")]
+    pub replication_factor: Option<usize>,
+
     #[command(flatten)]
     pub config_paths: ConfigPathsArgs,
 
+    #[command(flatten)]
+    pub admin_api: AdminApiArgs,
+
+    #[command(flatten)]
+    pub snapshots: SnapshotArgs,
+
+    #[command(flatten)]
+    pub contract_policy: ContractPolicyArgs,
+
+    #[command(flatten)]
+    pub storage_budget: StorageBudgetArgs,
+
+    #[command(flatten)]
+    pub bootstrap: BootstrapArgs,
+
     /// An arbitrary identifier for the node, mostly for debugging or testing purposes.
     #[arg(long, hide = true)]
     pub id: Option<String>,
@@ -80,6 +128,55 @@ pub struct ConfigArgs {
     /// Show the version of the application.
     #[arg(long, short)]
     pub version: bool,
+
+    #[command(subcommand)]
+    pub command: Option<NodeCommand>,
+}
+
+/// Maintenance commands which run against the node data instead of starting the node.
+#[derive(clap::Subcommand, Debug, Clone)]
+pub enum NodeCommand {
+    /// Restore the contract state store from a snapshot. The node must not be running.
+    Restore {
+        /// Path to the snapshot file.
+        #[arg(long)]
+        snapshot: PathBuf,
+    },
+    /// Replay a session recorded by a node through the admin API, printing the messages the node
+    /// sends while handling each recorded message.
+    Replay {
+        /// Path to the recording file.
+        #[arg(long)]
+        recording: PathBuf,
+    },
+    /// Generate the definition running the node as a service of the platform, with the current
+    /// configuration and data directories.
+    InstallService {
+        /// Service manager the definition is for, the one of the current platform by default.
+        #[arg(long, value_enum)]
+        platform: Option<ServicePlatform>,
+        /// Path the definition is written to, where the service manager looks for it by default.
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// Print the definition instead of writing it.
+        #[arg(long)]
+        print: bool,
+    },
+    /// Configuration file commands.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(clap::Subcommand, Debug, Clone)]
+pub enum ConfigCommand {
+    /// Check the configuration file, reporting every problem found along with its line.
+    Check {
+        /// Path to the configuration file, the one in the configuration directory by default.
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
 }
 
 impl Default for ConfigArgs {
@@ -89,24 +186,42 @@ impl Default for ConfigArgs {
             network_api: NetworkArgs {
                 address: Some(default_listening_address()),
                 network_port: Some(default_network_api_port()),
+                additional_addresses: None,
                 public_address: None,
                 public_port: None,
                 is_gateway: false,
+                role: None,
                 skip_load_from_network: true,
                 ignore_protocol_checking: false,
                 gateways: None,
                 location: None,
                 bandwidth_limit: None,
+                min_connections: None,
+                max_connections: None,
+                webrtc: false,
+                compression: Default::default(),
+                gossip: Default::default(),
             },
             ws_api: WebsocketApiArgs {
                 address: Some(default_listening_address()),
                 ws_api_port: Some(default_http_gateway_port()),
+                ..Default::default()
             },
             secrets: Default::default(),
             log_level: Some(tracing::log::LevelFilter::Info),
+            update_journal_retention: None,
+            wasm_engine: None,
+            contract_time_epoch: None,
+            replication_factor: None,
             config_paths: Default::default(),
+            admin_api: Default::default(),
+            snapshots: Default::default(),
+            contract_policy: Default::default(),
+            storage_budget: Default::default(),
+            bootstrap: Default::default(),
             id: None,
             version: false,
+            command: None,
         }
     }
 }
@@ -116,40 +231,47 @@ impl ConfigArgs {
         PCK_VERSION
     }
 
-    fn read_config(dir: &PathBuf) -> std::io::Result<Option<Config>> {
+    /// The configuration file in the directory, if any.
+    fn find_config_file(dir: &Path) -> std::io::Result<Option<PathBuf>> {
         if !dir.exists() {
             return Ok(None);
         }
         let mut read_dir = std::fs::read_dir(dir)?;
-        let config_args: Option<(String, String)> = read_dir.find_map(|e| {
-            if let Ok(e) = e {
-                if e.path().is_dir() {
-                    return None;
-                }
-                let filename = e.file_name().to_string_lossy().into_owned();
-                let ext = filename.rsplit('.').next().map(|s| s.to_owned());
-                if let Some(ext) = ext {
-                    if filename.starts_with("config") {
-                        match ext.as_str() {
-                            "toml" => {
-                                tracing::info!("Found configuration file: {filename}.{ext}");
-                                return Some((filename, ext));
-                            }
-                            "json" => {
-                                return Some((filename, ext));
-                            }
-                            _ => {}
-                        }
-                    }
-                }
+        let path = read_dir.find_map(|e| {
+            let e = e.ok()?;
+            if e.path().is_dir() {
+                return None;
+            }
+            let filename = e.file_name().to_string_lossy().into_owned();
+            let ext = filename.rsplit('.').next()?;
+            if filename.starts_with("config") && matches!(ext, "toml" | "json") {
+                tracing::info!("Found configuration file: {filename}");
+                return Some(e.path());
             }
-
             None
         });
+        Ok(path)
+    }
+
+    /// The configuration file which would be read when building the configuration, if any.
+    pub fn config_file(&self) -> std::io::Result<Option<PathBuf>> {
+        let dir = match &self.config_paths.config_dir {
+            Some(dir) => dir.clone(),
+            None => match ConfigPathsArgs::default_dirs(self.id.as_deref())? {
+                Either::Left(defaults) => defaults.config_dir().to_path_buf(),
+                Either::Right(dir) => dir,
+            },
+        };
+        Self::find_config_file(&dir)
+    }
 
-        match config_args {
-            Some((filename, ext)) => {
-                let path = dir.join(filename).with_extension(&ext);
+    fn read_config(dir: &PathBuf) -> std::io::Result<Option<Config>> {
+        match Self::find_config_file(dir)? {
+            Some(path) => {
+                let ext = path
+                    .extension()
+                    .map(|ext| ext.to_string_lossy().into_owned())
+                    .unwrap_or_default();
                 tracing::info!("Reading configuration file: {path:?}",);
                 match ext.as_str() {
                     "toml" => {
@@ -189,10 +311,10 @@ impl ConfigArgs {
     }
 
     /// Parse the command line arguments and return the configuration.
+    ///
+    /// Settings are layered: the ones in the configuration file are overridden by the environment
+    /// variables, which are overridden by the command line arguments.
     pub async fn build(mut self) -> anyhow::Result<Config> {
-        // Validate gateway configuration
-        self.network_api.validate()?;
-
         let cfg = if let Some(path) = self.config_paths.config_dir.as_ref() {
             if !path.exists() {
                 return Err(anyhow::Error::new(std::io::Error::new(
@@ -207,8 +329,8 @@ impl ConfigArgs {
             let (config, data) = {
                 match ConfigPathsArgs::default_dirs(self.id.as_deref())? {
                     Either::Left(defaults) => (
-                        defaults.config_local_dir().to_path_buf(),
-                        defaults.data_local_dir().to_path_buf(),
+                        defaults.config_dir().to_path_buf(),
+                        defaults.data_dir().to_path_buf(),
                     ),
                     Either::Right(dir) => (dir.clone(), dir),
                 }
@@ -230,14 +352,107 @@ impl ConfigArgs {
             self.mode.get_or_insert(cfg.mode);
             self.ws_api.address.get_or_insert(cfg.ws_api.address);
             self.ws_api.ws_api_port.get_or_insert(cfg.ws_api.port);
+            if let Some(certificate) = cfg.ws_api.tls_certificate {
+                self.ws_api.tls_certificate.get_or_insert(certificate);
+            }
+            if let Some(private_key) = cfg.ws_api.tls_private_key {
+                self.ws_api.tls_private_key.get_or_insert(private_key);
+            }
+            if let Some(acme) = cfg.ws_api.acme {
+                self.ws_api.domain.get_or_insert(acme.domain);
+                if let Some(contact) = acme.contact {
+                    self.ws_api.acme_contact.get_or_insert(contact);
+                }
+                self.ws_api.acme_directory.get_or_insert(acme.directory);
+                self.ws_api.acme_http_port.get_or_insert(acme.http_port);
+            }
+            if self.ws_api.virtual_hosts.is_none() && !cfg.ws_api.virtual_hosts.is_empty() {
+                self.ws_api.virtual_hosts = Some(cfg.ws_api.virtual_hosts);
+            }
+            self.ws_api.limits.merge(cfg.ws_api.limits);
+            self.ws_api.cors.merge(cfg.ws_api.cors);
+            self.ws_api.access_log.merge(cfg.ws_api.access_log);
+            self.ws_api.load_shedding.merge(cfg.ws_api.load_shedding);
             self.log_level.get_or_insert(cfg.log_level);
+            if let Some(retention) = cfg.update_journal_retention {
+                self.update_journal_retention.get_or_insert(retention);
+            }
+            if !cfg.network_api.additional_addresses.is_empty() {
+                self.network_api
+                    .additional_addresses
+                    .get_or_insert(cfg.network_api.additional_addresses);
+            }
+            self.network_api
+                .address
+                .get_or_insert(cfg.network_api.address);
+            self.network_api
+                .network_port
+                .get_or_insert(cfg.network_api.port);
+            if let Some(address) = cfg.network_api.public_address {
+                self.network_api.public_address.get_or_insert(address);
+            }
+            if let Some(port) = cfg.network_api.public_port {
+                self.network_api.public_port.get_or_insert(port);
+            }
+            self.network_api.is_gateway |= cfg.is_gateway;
+            if !self.network_api.is_gateway {
+                self.network_api.role.get_or_insert(cfg.role());
+            }
+            if let Some(location) = cfg.location {
+                self.network_api.location.get_or_insert(location);
+            }
+            self.network_api.webrtc |= cfg.network_api.webrtc;
+            if let Some(limit) = cfg.network_api.bandwidth_limit {
+                self.network_api.bandwidth_limit.get_or_insert(limit);
+            }
+            if let Some(min) = cfg.network_api.min_connections {
+                self.network_api.min_connections.get_or_insert(min);
+            }
+            if let Some(max) = cfg.network_api.max_connections {
+                self.network_api.max_connections.get_or_insert(max);
+            }
+            self.network_api
+                .compression
+                .merge(cfg.network_api.compression);
+            self.network_api.gossip.merge(cfg.network_api.gossip);
+            self.wasm_engine.get_or_insert(cfg.wasm_engine);
+            self.contract_time_epoch
+                .get_or_insert(cfg.contract_time_epoch);
+            self.replication_factor
+                .get_or_insert(cfg.replication_factor);
             self.config_paths.merge(cfg.config_paths.as_ref().clone());
+            if let Some(port) = cfg.admin_api.port {
+                self.admin_api.admin_api_port.get_or_insert(port);
+            }
+            if let Some(interval) = cfg.snapshots.interval {
+                self.snapshots.snapshot_interval.get_or_insert(interval);
+            }
+            self.snapshots
+                .snapshots_to_keep
+                .get_or_insert(cfg.snapshots.keep);
+            self.contract_policy.merge(cfg.contract_policy);
+            self.storage_budget.merge(cfg.storage_budget);
+            self.bootstrap.merge(cfg.bootstrap);
+        }
+
+        // Validate gateway configuration
+        self.network_api.validate()?;
+        let role = self.network_api.role()?;
+        let is_gateway = role.is_gateway();
+        if is_gateway {
+            // gateways are reached on the port they listen to unless told otherwise
+            self.network_api.public_port = self
+                .network_api
+                .public_port
+                .or(self.network_api.network_port);
         }
 
-        let mode = self.mode.unwrap_or(OperationMode::Network);
+        let mode = self.mode.unwrap_or_else(default_operation_mode);
         let config_paths = self.config_paths.build(self.id.as_deref())?;
 
         let secrets = self.secrets.build()?;
+        self.ws_api.check_tls()?;
+        let limits = self.ws_api.limits.build()?;
 
         let peer_id = self
             .network_api
@@ -251,7 +466,7 @@ impl ConfigArgs {
             });
         let gateways_file = config_paths.config_dir.join("gateways.toml");
 
-        let remotely_loaded_gateways = if !self.network_api.skip_load_from_network {
+        let mut remotely_loaded_gateways = if !self.network_api.skip_load_from_network {
             load_gateways_from_index(FREENET_GATEWAYS_INDEX, &config_paths.secrets_dir)
                 .await
                 .inspect_err(|error| {
@@ -269,6 +484,7 @@ impl ConfigArgs {
                         address: Address::HostAddress(cfg.address),
                         public_key_path: cfg.public_key_path,
                         location: cfg.location,
+                        alternative_addresses: vec![],
                     })
                 })
                 .try_collect()?;
@@ -276,6 +492,15 @@ impl ConfigArgs {
         } else {
             Gateways::default()
         };
+        let bootstrap = self.bootstrap.build();
+        remotely_loaded_gateways.merge_and_deduplicate(
+            bootstrap::discover(
+                &bootstrap,
+                &config_paths.config_dir,
+                &config_paths.secrets_dir,
+            )
+            .await,
+        );
         let mut gateways = match File::open(&*gateways_file) {
             Ok(mut file) => {
                 let mut content = String::new();
@@ -316,24 +541,116 @@ impl ConfigArgs {
                     .network_api
                     .network_port
                     .unwrap_or_else(default_network_api_port),
+                additional_addresses: self.network_api.additional_addresses.unwrap_or_default(),
                 public_address: self.network_api.public_address,
                 public_port: self.network_api.public_port,
                 ignore_protocol_version: self.network_api.ignore_protocol_checking,
                 bandwidth_limit: self.network_api.bandwidth_limit,
+                min_connections: Some(
+                    self.network_api
+                        .min_connections
+                        .unwrap_or_else(|| default_min_connections(role)),
+                ),
+                max_connections: Some(
+                    self.network_api
+                        .max_connections
+                        .unwrap_or_else(|| default_max_connections(role)),
+                ),
+                webrtc: self.network_api.webrtc,
+                compression: self.network_api.compression.build(),
+                gossip: self.network_api.gossip.build(),
             },
-            ws_api: WebsocketApiConfig {
-                // the websocket API is always local
-                address: self.ws_api.address.unwrap_or_else(default_local_address),
-                port: self
-                    .ws_api
-                    .ws_api_port
-                    .unwrap_or(default_http_gateway_port()),
+            ws_api: {
+                let acme = self.ws_api.domain.map(|domain| AcmeConfig {
+                    domain,
+                    contact: self.ws_api.acme_contact,
+                    directory: self
+                        .ws_api
+                        .acme_directory
+                        .unwrap_or_else(default_acme_directory),
+                    http_port: self
+                        .ws_api
+                        .acme_http_port
+                        .unwrap_or(default_acme_http_port()),
+                });
+                // certificates obtained through ACME are stored in the data directory
+                let certificates_dir = config_paths.data_dir.join("certificates");
+                WebsocketApiConfig {
+                    // the websocket API is always local, unless it serves a public domain
+                    address: self.ws_api.address.unwrap_or_else(|| {
+                        if acme.is_some() {
+                            default_listening_address()
+                        } else {
+                            default_local_address()
+                        }
+                    }),
+                    port: self.ws_api.ws_api_port.unwrap_or_else(|| {
+                        if acme.is_some() {
+                            default_https_gateway_port()
+                        } else {
+                            default_http_gateway_port()
+                        }
+                    }),
+                    tls_certificate: self.ws_api.tls_certificate.or_else(|| {
+                        let acme = acme.as_ref()?;
+                        Some(certificates_dir.join(format!("{}.crt", acme.domain)))
+                    }),
+                    tls_private_key: self.ws_api.tls_private_key.or_else(|| {
+                        let acme = acme.as_ref()?;
+                        Some(certificates_dir.join(format!("{}.key", acme.domain)))
+                    }),
+                    virtual_hosts: self
+                        .ws_api
+                        .virtual_hosts
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|mut vhost| {
+                            if acme.is_some() && vhost.tls().is_none() {
+                                vhost.tls_certificate =
+                                    Some(certificates_dir.join(format!("{}.crt", vhost.host)));
+                                vhost.tls_private_key =
+                                    Some(certificates_dir.join(format!("{}.key", vhost.host)));
+                            }
+                            vhost
+                        })
+                        .collect(),
+                    acme,
+                    limits,
+                    cors: self.ws_api.cors.build(),
+                    access_log: self.ws_api.access_log.build(),
+                    load_shedding: self.ws_api.load_shedding.build(),
+                    // only gateways accept browser peers
+                    webrtc: is_gateway && self.network_api.webrtc,
+                    webs_dir: config_paths.webs_dir(),
+                }
             },
             secrets,
             log_level: self.log_level.unwrap_or(tracing::log::LevelFilter::Info),
+            update_journal_retention: self.update_journal_retention,
+            wasm_engine: self.wasm_engine.unwrap_or_default(),
+            contract_time_epoch: self
+                .contract_time_epoch
+                .unwrap_or_else(default_contract_time_epoch),
+            replication_factor: self
+                .replication_factor
+                .unwrap_or_else(default_replication_factor),
             config_paths: Arc::new(config_paths),
+            admin_api: AdminApiConfig {
+                port: self.admin_api.admin_api_port,
+            },
+            snapshots: SnapshotConfig {
+                interval: self.snapshots.snapshot_interval,
+                keep: self
+                    .snapshots
+                    .snapshots_to_keep
+                    .unwrap_or_else(default_snapshots_to_keep),
+            },
+            contract_policy: self.contract_policy.build(),
+            storage_budget: self.storage_budget.build(),
+            bootstrap,
             gateways: gateways.gateways.clone(),
-            is_gateway: self.network_api.is_gateway,
+            is_gateway,
+            role: Some(role),
             location: self.network_api.location,
         };
 
@@ -398,9 +715,10 @@ mod serde_log_level_filter {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, schemars::JsonSchema)]
 pub struct Config {
     /// Node operation mode.
+    #[serde(default = "default_operation_mode")]
     pub mode: OperationMode,
     #[serde(flatten)]
     pub network_api: NetworkApiConfig,
@@ -408,19 +726,63 @@ pub struct Config {
     pub ws_api: WebsocketApiConfig,
     #[serde(flatten)]
     pub secrets: Secrets,
-    #[serde(with = "serde_log_level_filter")]
+    #[serde(default = "default_log_level", with = "serde_log_level_filter")]
+    #[schemars(schema_with = "check::log_level_schema")]
     pub log_level: tracing::log::LevelFilter,
+    /// Number of applied updates to keep per contract in the update journal, disabled if not set.
+    #[serde(
+        rename = "update-journal-retention",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub update_journal_retention: Option<usize>,
+    /// Compiler backend used to run contracts and delegates.
+    #[serde(default, rename = "wasm-engine")]
+    pub wasm_engine: WasmEngine,
+    /// Length, in seconds, of the time quantum contracts can read.
+    #[serde(
+        default = "default_contract_time_epoch",
+        rename = "contract-time-epoch"
+    )]
+    pub contract_time_epoch: u64,
+    /// Number of peers closest to a contract location which should cache it.
+    #[serde(default = "default_replication_factor", rename = "replication-factor")]
+    pub replication_factor: usize,
     #[serde(flatten)]
     config_paths: Arc<ConfigPaths>,
+    #[serde(flatten)]
+    pub admin_api: AdminApiConfig,
+    #[serde(flatten)]
+    pub snapshots: SnapshotConfig,
+    #[serde(flatten)]
+    pub contract_policy: ContractPolicyConfig,
+    #[serde(flatten)]
+    pub storage_budget: StorageBudgetConfig,
+    #[serde(flatten)]
+    pub bootstrap: BootstrapConfig,
     #[serde(skip)]
     pub(crate) peer_id: Option<PeerId>,
     #[serde(skip)]
     pub(crate) gateways: Vec<GatewayConfig>,
+    #[serde(default)]
     pub(crate) is_gateway: bool,
+    /// Derived from `is_gateway` when not set, as in the configurations written before nodes had
+    /// roles, see [`Config::role`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) role: Option<NodeRole>,
+    #[schemars(range(min = 0.0, max = 1.0))]
     pub(crate) location: Option<f64>,
 }
 
 impl Config {
+    /// Role of the node, gateways being the only nodes told apart when it isn't set.
+    pub fn role(&self) -> NodeRole {
+        self.role.unwrap_or(if self.is_gateway {
+            NodeRole::Gateway
+        } else {
+            NodeRole::default()
+        })
+    }
+
     pub fn transport_keypair(&self) -> &TransportKeypair {
         self.secrets.transport_keypair()
     }
@@ -446,6 +808,19 @@ pub struct NetworkArgs {
     #[serde(rename = "network-port", skip_serializing_if = "Option::is_none")]
     pub network_port: Option<u16>,
 
+    /// Additional addresses to bind the network listener to on the same port, e.g. to listen
+    /// on both IPv4 and IPv6, or on several interfaces.
+    #[arg(
+        long = "additional-network-addresses",
+        env = "ADDITIONAL_NETWORK_ADDRESSES",
+        value_delimiter = ','
+    )]
+    #[serde(
+        rename = "additional-network-addresses",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub additional_addresses: Option<Vec<IpAddr>>,
+
     /// Public address for the network. Required for gateways.
     #[arg(long = "public-network-address", env = "PUBLIC_NETWORK_ADDRESS")]
     #[serde(
@@ -467,6 +842,14 @@ pub struct NetworkArgs {
     #[arg(long)]
     pub is_gateway: bool,
 
+    /// Role of the node in the network: `gateway`, `peer` (the default) or `light`.
+    /// Light nodes only send the requests of their clients through the gateways they connect
+    /// to, without routing requests of other peers nor caching contracts on their behalf.
+    /// `--is-gateway` is a shorthand for the gateway role.
+    #[arg(long, value_enum, env = "NODE_ROLE")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<NodeRole>,
+
     /// Skips loading gateway configurations from the network and merging it with existing one.
     #[arg(long)]
     pub skip_load_from_network: bool,
@@ -486,6 +869,32 @@ pub struct NetworkArgs {
     /// Hard limit the bandwidth usage for upstream traffic.
     #[arg(long)]
     pub bandwidth_limit: Option<usize>,
+
+    /// Number of connections under which the node accepts every peer, default is 25, or 1 for
+    /// light nodes.
+    #[arg(long, env = "MIN_CONNECTIONS")]
+    #[serde(rename = "min-connections", skip_serializing_if = "Option::is_none")]
+    pub min_connections: Option<usize>,
+
+    /// Maximum number of connections to other peers, default is 200, 400 for gateways and 3
+    /// for light nodes.
+    #[arg(long, env = "MAX_CONNECTIONS")]
+    #[serde(rename = "max-connections", skip_serializing_if = "Option::is_none")]
+    pub max_connections: Option<usize>,
+
+    /// Accepts browser peers over WebRTC data channels, signalled through the HTTP gateway.
+    /// Only available for gateways.
+    #[arg(long, env = "WEBRTC")]
+    #[serde(default)]
+    pub webrtc: bool,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub compression: CompressionArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub gossip: GossipArgs,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -503,7 +912,7 @@ pub struct InlineGwConfig {
 
 impl NetworkArgs {
     pub(crate) fn validate(&self) -> anyhow::Result<()> {
-        if self.is_gateway {
+        if self.role()?.is_gateway() {
             // For gateways, require both public address and port
             if self.public_address.is_none() {
                 return Err(anyhow::anyhow!(
@@ -516,9 +925,66 @@ impl NetworkArgs {
         }
         Ok(())
     }
+
+    /// Role of the node, `--is-gateway` standing for the gateway role.
+    pub(crate) fn role(&self) -> anyhow::Result<NodeRole> {
+        match (self.role, self.is_gateway) {
+            (Some(role), true) if !role.is_gateway() => {
+                Err(anyhow::anyhow!("Gateway nodes can't have the {role} role"))
+            }
+            (_, true) => Ok(NodeRole::Gateway),
+            (role, false) => Ok(role.unwrap_or_default()),
+        }
+    }
+}
+
+/// Part a node plays in the network.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    Serialize,
+    Deserialize,
+    clap::ValueEnum,
+    schemars::JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeRole {
+    /// Entry point of the network, accepting the connections of joining peers.
+    Gateway,
+    /// Routes the requests of other peers and caches the contracts close to its location.
+    #[default]
+    Peer,
+    /// Only sends the requests of its clients, through the gateways it is connected to.
+    Light,
+}
+
+impl NodeRole {
+    pub const fn is_gateway(self) -> bool {
+        matches!(self, NodeRole::Gateway)
+    }
+
+    /// Whether the node routes requests and stores contracts on behalf of other peers.
+    pub const fn serves_network(self) -> bool {
+        !matches!(self, NodeRole::Light)
+    }
+}
+
+impl std::fmt::Display for NodeRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NodeRole::Gateway => write!(f, "gateway"),
+            NodeRole::Peer => write!(f, "peer"),
+            NodeRole::Light => write!(f, "light"),
+        }
+    }
 }
 
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
 pub struct NetworkApiConfig {
     /// Address to listen to locally
     #[serde(default = "default_listening_address", rename = "network-address")]
@@ -528,6 +994,14 @@ pub struct NetworkApiConfig {
     #[serde(default = "default_network_api_port", rename = "network-port")]
     pub port: u16,
 
+    /// Additional addresses to listen to locally, on the same port
+    #[serde(
+        default,
+        rename = "additional-network-addresses",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub additional_addresses: Vec<IpAddr>,
+
     /// Public external address for the network, mandatory for gateways.
     #[serde(
         rename = "public_network_address",
@@ -545,6 +1019,236 @@ pub struct NetworkApiConfig {
 
     /// Hard limit the bandwidth usage for upstream traffic.
     pub bandwidth_limit: Option<usize>,
+
+    /// Number of connections under which the node accepts every peer
+    #[serde(rename = "min-connections", skip_serializing_if = "Option::is_none")]
+    pub min_connections: Option<usize>,
+
+    /// Maximum number of connections to other peers
+    #[serde(rename = "max-connections", skip_serializing_if = "Option::is_none")]
+    pub max_connections: Option<usize>,
+
+    /// Whether browser peers are accepted over WebRTC.
+    #[serde(default)]
+    pub webrtc: bool,
+
+    /// Compression of the messages sent to other peers
+    #[serde(flatten)]
+    pub compression: CompressionConfig,
+
+    /// Broadcast channel for ephemeral application messages
+    #[serde(flatten)]
+    pub gossip: GossipConfig,
+}
+
+#[derive(clap::Parser, Debug, Default, Copy, Clone, Serialize, Deserialize)]
+pub struct GossipArgs {
+    /// Joins the gossip channel, delivering the ephemeral messages broadcast by applications to
+    /// local clients and relaying them to other peers.
+    #[arg(long, env = "GOSSIP")]
+    #[serde(default)]
+    pub gossip: bool,
+
+    /// Maximum size, in bytes, of the gossip messages, default is 4 KiB
+    #[arg(long, env = "GOSSIP_MAX_MESSAGE_SIZE")]
+    #[serde(
+        rename = "gossip-max-message-size",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub gossip_max_message_size: Option<usize>,
+
+    /// Maximum number of gossip messages per second delivered and relayed for each topic,
+    /// default is 10
+    #[arg(long, env = "GOSSIP_TOPIC_RATE")]
+    #[serde(rename = "gossip-topic-rate", skip_serializing_if = "Option::is_none")]
+    pub gossip_topic_rate: Option<u32>,
+}
+
+impl GossipArgs {
+    fn merge(&mut self, other: GossipConfig) {
+        self.gossip |= other.enabled;
+        self.gossip_max_message_size
+            .get_or_insert(other.max_message_size);
+        self.gossip_topic_rate.get_or_insert(other.topic_rate);
+    }
+
+    fn build(self) -> GossipConfig {
+        let default = GossipConfig::default();
+        GossipConfig {
+            enabled: self.gossip,
+            max_message_size: self
+                .gossip_max_message_size
+                .unwrap_or(default.max_message_size),
+            topic_rate: self.gossip_topic_rate.unwrap_or(default.topic_rate),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct GossipConfig {
+    #[serde(default, rename = "gossip")]
+    pub enabled: bool,
+    #[serde(
+        default = "default_gossip_max_message_size",
+        rename = "gossip-max-message-size"
+    )]
+    pub max_message_size: usize,
+    /// Messages per second delivered and relayed for each topic.
+    #[serde(default = "default_gossip_topic_rate", rename = "gossip-topic-rate")]
+    pub topic_rate: u32,
+}
+
+impl Default for GossipConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_message_size: default_gossip_max_message_size(),
+            topic_rate: default_gossip_topic_rate(),
+        }
+    }
+}
+
+fn default_gossip_max_message_size() -> usize {
+    4 * 1024
+}
+
+fn default_gossip_topic_rate() -> u32 {
+    10
+}
+
+#[derive(clap::Parser, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct CompressionArgs {
+    /// Codecs messages sent to other peers can be compressed with, by order of preference.
+    /// Default is zstd,lz4.
+    #[arg(long, env = "COMPRESSION_CODECS", value_delimiter = ',')]
+    #[serde(rename = "compression-codecs", skip_serializing_if = "Option::is_none")]
+    pub compression_codecs: Option<Vec<Codec>>,
+
+    /// Minimum size, in bytes, of the messages compressed, default is 1 KiB
+    #[arg(long, env = "COMPRESSION_THRESHOLD")]
+    #[serde(
+        rename = "compression-threshold",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub compression_threshold: Option<usize>,
+
+    /// Operations whose messages are never compressed, e.g. because their payloads usually are
+    /// compressed already.
+    #[arg(long, env = "UNCOMPRESSED_OPERATIONS", value_delimiter = ',')]
+    #[serde(
+        rename = "uncompressed-operations",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub uncompressed_operations: Option<Vec<OperationKind>>,
+
+    /// Disables the compression of messages sent to other peers.
+    #[arg(long, env = "DISABLE_COMPRESSION")]
+    #[serde(default, rename = "disable-compression")]
+    pub disable_compression: bool,
+}
+
+impl CompressionArgs {
+    fn merge(&mut self, other: CompressionConfig) {
+        if other.codecs.is_empty() {
+            self.disable_compression = true;
+        }
+        self.compression_codecs.get_or_insert(other.codecs);
+        self.compression_threshold.get_or_insert(other.threshold);
+        self.uncompressed_operations
+            .get_or_insert(other.uncompressed_operations);
+    }
+
+    fn build(self) -> CompressionConfig {
+        let defaults = CompressionConfig::default();
+        CompressionConfig {
+            codecs: if self.disable_compression {
+                vec![]
+            } else {
+                self.compression_codecs.unwrap_or(defaults.codecs)
+            },
+            threshold: self.compression_threshold.unwrap_or(defaults.threshold),
+            uncompressed_operations: self.uncompressed_operations.unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Serialize,
+    Deserialize,
+    clap::ValueEnum,
+    schemars::JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    Zstd,
+    Lz4,
+}
+
+/// Operations messages exchanged between peers belong to.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Serialize,
+    Deserialize,
+    clap::ValueEnum,
+    schemars::JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum OperationKind {
+    Connect,
+    Put,
+    Get,
+    Subscribe,
+    Update,
+}
+
+/// Compression of the messages sent to other peers.
+///
+/// The codec used with each peer is negotiated when connecting, as the first one of `codecs`
+/// supported by both of them.
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct CompressionConfig {
+    /// Codecs supported, by order of preference; compression is disabled if empty
+    #[serde(default = "default_compression_codecs", rename = "compression-codecs")]
+    pub codecs: Vec<Codec>,
+    /// Minimum size of the messages compressed
+    #[serde(
+        default = "default_compression_threshold",
+        rename = "compression-threshold"
+    )]
+    pub threshold: usize,
+    /// Operations whose messages are never compressed
+    #[serde(default, rename = "uncompressed-operations")]
+    pub uncompressed_operations: Vec<OperationKind>,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            codecs: default_compression_codecs(),
+            threshold: default_compression_threshold(),
+            uncompressed_operations: vec![],
+        }
+    }
+}
+
+fn default_compression_codecs() -> Vec<Codec> {
+    vec![Codec::Zstd, Codec::Lz4]
+}
+
+#[inline]
+const fn default_compression_threshold() -> usize {
+    1024
 }
 
 mod port_allocation;
@@ -554,7 +1258,7 @@ pub fn default_network_api_port() -> u16 {
     find_available_port().unwrap_or(31337) // Fallback to 31337 if we can't find a random port
 }
 
-#[derive(clap::Parser, Debug, Default, Copy, Clone, Serialize, Deserialize)]
+#[derive(clap::Parser, Debug, Default, Clone, Serialize, Deserialize)]
 pub struct WebsocketApiArgs {
     /// Address to bind to for the websocket API, default is 0.0.0.0
     #[arg(
@@ -569,46 +1273,629 @@ pub struct WebsocketApiArgs {
     #[arg(long, env = "WS_API_PORT")]
     #[serde(rename = "ws-api-port", skip_serializing_if = "Option::is_none")]
     pub ws_api_port: Option<u16>,
+
+    /// Path to the PEM encoded certificate chain used to serve the HTTP gateway over HTTPS
+    #[arg(long, env = "TLS_CERTIFICATE")]
+    #[serde(rename = "tls-certificate", skip_serializing_if = "Option::is_none")]
+    pub tls_certificate: Option<PathBuf>,
+
+    /// Path to the PEM encoded private key of the TLS certificate
+    #[arg(long, env = "TLS_PRIVATE_KEY")]
+    #[serde(rename = "tls-private-key", skip_serializing_if = "Option::is_none")]
+    pub tls_private_key: Option<PathBuf>,
+
+    /// Public domain of the gateway. When set, a certificate for it is obtained and renewed
+    /// from an ACME provider (Let's Encrypt by default) and the gateway is served over HTTPS
+    #[arg(long, env = "DOMAIN")]
+    #[serde(rename = "domain", skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+
+    /// Contact email registered with the ACME provider
+    #[arg(long, env = "ACME_CONTACT")]
+    #[serde(rename = "acme-contact", skip_serializing_if = "Option::is_none")]
+    pub acme_contact: Option<String>,
+
+    /// Directory URL of the ACME provider, default is Let's Encrypt production
+    #[arg(long, env = "ACME_DIRECTORY")]
+    #[serde(rename = "acme-directory", skip_serializing_if = "Option::is_none")]
+    pub acme_directory: Option<String>,
+
+    /// Port the ACME HTTP-01 challenges are served on, default is 80
+    #[arg(long, env = "ACME_HTTP_PORT")]
+    #[serde(rename = "acme-http-port", skip_serializing_if = "Option::is_none")]
+    pub acme_http_port: Option<u16>,
+
+    /// Domains serving the web app of a contract at their root, as `host=contract` pairs,
+    /// e.g. `app.example.org=<contract instance id>`
+    #[arg(long = "virtual-host", env = "VIRTUAL_HOSTS", value_delimiter = ',')]
+    #[serde(rename = "virtual-hosts", skip_serializing_if = "Option::is_none")]
+    pub virtual_hosts: Option<Vec<VirtualHost>>,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub limits: PayloadLimitsArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub cors: CorsArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub access_log: AccessLogArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub load_shedding: LoadSheddingArgs,
 }
 
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
-pub struct WebsocketApiConfig {
-    /// Address to bind to
-    #[serde(default = "default_listening_address", rename = "ws-api-address")]
-    pub address: IpAddr,
+impl WebsocketApiArgs {
+    /// Fails if only one of a certificate and its private key is configured, for the gateway or
+    /// any of the virtual hosts, rather than silently serving plain HTTP.
+    fn check_tls(&self) -> anyhow::Result<()> {
+        if self.tls_certificate.is_some() != self.tls_private_key.is_some() {
+            anyhow::bail!("both the TLS certificate and private key of the gateway must be set");
+        }
+        for vhost in self.virtual_hosts.iter().flatten() {
+            if vhost.tls_certificate.is_some() != vhost.tls_private_key.is_some() {
+                anyhow::bail!(
+                    "both the TLS certificate and private key of `{}` must be set",
+                    vhost.host
+                );
+            }
+        }
+        Ok(())
+    }
+}
 
-    /// Port to expose api on
-    #[serde(default = "default_http_gateway_port", rename = "ws-api-port")]
-    pub port: u16,
+#[derive(clap::Parser, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct CorsArgs {
+    /// Origins of the web apps hosted elsewhere allowed to call the gateway, e.g.
+    /// `https://app.example.org`, or `*` for any. Cross-origin requests are rejected if not set
+    #[arg(long, env = "CORS_ALLOWED_ORIGINS", value_delimiter = ',')]
+    #[serde(
+        rename = "cors-allowed-origins",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub cors_allowed_origins: Option<Vec<String>>,
+
+    /// Methods allowed in cross-origin requests, default is GET,POST
+    #[arg(long, env = "CORS_ALLOWED_METHODS", value_delimiter = ',')]
+    #[serde(
+        rename = "cors-allowed-methods",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub cors_allowed_methods: Option<Vec<String>>,
+
+    /// Allows cross-origin requests to carry credentials, such as cookies
+    #[arg(long, env = "CORS_ALLOW_CREDENTIALS")]
+    #[serde(default, rename = "cors-allow-credentials")]
+    pub cors_allow_credentials: bool,
 }
 
-impl From<SocketAddr> for WebsocketApiConfig {
-    fn from(addr: SocketAddr) -> Self {
-        Self {
-            address: addr.ip(),
-            port: addr.port(),
+impl CorsArgs {
+    fn merge(&mut self, other: CorsConfig) {
+        if self.cors_allowed_origins.is_none() && !other.allowed_origins.is_empty() {
+            self.cors_allowed_origins = Some(other.allowed_origins);
+        }
+        self.cors_allowed_methods
+            .get_or_insert(other.allowed_methods);
+        self.cors_allow_credentials |= other.allow_credentials;
+    }
+
+    fn build(self) -> CorsConfig {
+        CorsConfig {
+            allowed_origins: self.cors_allowed_origins.unwrap_or_default(),
+            allowed_methods: self
+                .cors_allowed_methods
+                .unwrap_or_else(default_cors_allowed_methods),
+            allow_credentials: self.cors_allow_credentials,
         }
     }
 }
 
-impl Default for WebsocketApiConfig {
-    #[inline]
+/// Cross-origin policy of the gateway endpoints.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct CorsConfig {
+    #[serde(
+        default,
+        rename = "cors-allowed-origins",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub allowed_origins: Vec<String>,
+    #[serde(
+        default = "default_cors_allowed_methods",
+        rename = "cors-allowed-methods"
+    )]
+    pub allowed_methods: Vec<String>,
+    #[serde(default, rename = "cors-allow-credentials")]
+    pub allow_credentials: bool,
+}
+
+impl Default for CorsConfig {
     fn default() -> Self {
         Self {
-            address: default_listening_address(),
-            port: default_http_gateway_port(),
+            allowed_origins: vec![],
+            allowed_methods: default_cors_allowed_methods(),
+            allow_credentials: false,
         }
     }
 }
 
-#[inline]
-const fn default_listening_address() -> IpAddr {
-    IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+fn default_cors_allowed_methods() -> Vec<String> {
+    vec!["GET".to_owned(), "POST".to_owned()]
 }
 
-#[inline]
-const fn default_local_address() -> IpAddr {
-    IpAddr::V4(Ipv4Addr::LOCALHOST)
+#[derive(clap::Parser, Debug, Default, Copy, Clone, Serialize, Deserialize)]
+pub struct AccessLogArgs {
+    /// Logs the requests to the HTTP gateway, with the client addresses hashed, under the
+    /// `freenet::access` tracing target
+    #[arg(long, env = "ACCESS_LOG")]
+    #[serde(rename = "access-log", skip_serializing_if = "Option::is_none")]
+    pub access_log: Option<bool>,
+
+    /// Share of the requests logged, from 0 to 1, default is 1
+    #[arg(long, env = "ACCESS_LOG_SAMPLE_RATE")]
+    #[serde(
+        rename = "access-log-sample-rate",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub access_log_sample_rate: Option<f64>,
+}
+
+impl AccessLogArgs {
+    fn merge(&mut self, other: AccessLogConfig) {
+        self.access_log.get_or_insert(other.enabled);
+        self.access_log_sample_rate.get_or_insert(other.sample_rate);
+    }
+
+    fn build(self) -> AccessLogConfig {
+        AccessLogConfig {
+            enabled: self.access_log.unwrap_or(false),
+            sample_rate: self
+                .access_log_sample_rate
+                .unwrap_or_else(default_access_log_sample_rate),
+        }
+    }
+}
+
+/// Access logs of the HTTP gateway.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct AccessLogConfig {
+    #[serde(default, rename = "access-log")]
+    pub enabled: bool,
+    #[serde(
+        default = "default_access_log_sample_rate",
+        rename = "access-log-sample-rate"
+    )]
+    #[schemars(range(min = 0.0, max = 1.0))]
+    pub sample_rate: f64,
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sample_rate: default_access_log_sample_rate(),
+        }
+    }
+}
+
+fn default_access_log_sample_rate() -> f64 {
+    1.0
+}
+
+#[derive(clap::Parser, Debug, Default, Copy, Clone, Serialize, Deserialize)]
+pub struct LoadSheddingArgs {
+    /// Number of contract events queued or being processed over which requests executing
+    /// contracts are rejected, default is 768
+    #[arg(long, env = "SHED_LOAD_QUEUED_EVENTS")]
+    #[serde(
+        rename = "shed-load-queued-events",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub shed_load_queued_events: Option<usize>,
+
+    /// Available system memory, in bytes, under which requests executing contracts are rejected.
+    /// Not checked if not set
+    #[arg(long, env = "SHED_LOAD_FREE_MEMORY")]
+    #[serde(
+        rename = "shed-load-free-memory",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub shed_load_free_memory: Option<u64>,
+
+    /// Seconds clients are told to wait before retrying rejected requests, default is 5
+    #[arg(long, env = "SHED_LOAD_RETRY_AFTER")]
+    #[serde(
+        rename = "shed-load-retry-after",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub shed_load_retry_after: Option<u64>,
+}
+
+impl LoadSheddingArgs {
+    fn merge(&mut self, other: LoadSheddingConfig) {
+        self.shed_load_queued_events
+            .get_or_insert(other.max_queued_events);
+        if let Some(min) = other.min_free_memory {
+            self.shed_load_free_memory.get_or_insert(min);
+        }
+        self.shed_load_retry_after.get_or_insert(other.retry_after);
+    }
+
+    fn build(self) -> LoadSheddingConfig {
+        let default = LoadSheddingConfig::default();
+        LoadSheddingConfig {
+            max_queued_events: self
+                .shed_load_queued_events
+                .unwrap_or(default.max_queued_events),
+            min_free_memory: self.shed_load_free_memory,
+            retry_after: self.shed_load_retry_after.unwrap_or(default.retry_after),
+        }
+    }
+}
+
+/// Thresholds over which the expensive client requests are rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct LoadSheddingConfig {
+    #[serde(
+        default = "default_shed_load_queued_events",
+        rename = "shed-load-queued-events"
+    )]
+    pub max_queued_events: usize,
+    #[serde(
+        default,
+        rename = "shed-load-free-memory",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub min_free_memory: Option<u64>,
+    /// Seconds clients are told to wait before retrying.
+    #[serde(
+        default = "default_shed_load_retry_after",
+        rename = "shed-load-retry-after"
+    )]
+    pub retry_after: u64,
+}
+
+impl Default for LoadSheddingConfig {
+    fn default() -> Self {
+        Self {
+            max_queued_events: default_shed_load_queued_events(),
+            min_free_memory: None,
+            retry_after: default_shed_load_retry_after(),
+        }
+    }
+}
+
+fn default_shed_load_queued_events() -> usize {
+    768
+}
+
+fn default_shed_load_retry_after() -> u64 {
+    5
+}
+
+#[derive(clap::Parser, Debug, Default, Copy, Clone, Serialize, Deserialize)]
+pub struct PayloadLimitsArgs {
+    /// Maximum size, in bytes, of the messages received through the websocket API, default is 64 MiB
+    #[arg(long, env = "MAX_MESSAGE_SIZE")]
+    #[serde(rename = "max-message-size", skip_serializing_if = "Option::is_none")]
+    pub max_message_size: Option<usize>,
+
+    /// Maximum size, in bytes, of the HTTP request bodies received by the gateway, default is 1 MiB
+    #[arg(long, env = "MAX_BODY_SIZE")]
+    #[serde(rename = "max-body-size", skip_serializing_if = "Option::is_none")]
+    pub max_body_size: Option<usize>,
+
+    /// Maximum size, in bytes, of the contract code put by clients, default is 16 MiB
+    #[arg(long, env = "MAX_CONTRACT_SIZE")]
+    #[serde(rename = "max-contract-size", skip_serializing_if = "Option::is_none")]
+    pub max_contract_size: Option<usize>,
+
+    /// Maximum size, in bytes, of the contract states put or updated by clients, or received from peers, default is 32 MiB
+    #[arg(long, env = "MAX_STATE_SIZE")]
+    #[serde(rename = "max-state-size", skip_serializing_if = "Option::is_none")]
+    pub max_state_size: Option<usize>,
+
+    /// Maximum size, in bytes, of the state deltas sent by clients, default is 16 MiB
+    #[arg(long, env = "MAX_DELTA_SIZE")]
+    #[serde(rename = "max-delta-size", skip_serializing_if = "Option::is_none")]
+    pub max_delta_size: Option<usize>,
+}
+
+impl PayloadLimitsArgs {
+    fn merge(&mut self, other: PayloadLimits) {
+        self.max_message_size.get_or_insert(other.max_message_size);
+        self.max_body_size.get_or_insert(other.max_body_size);
+        self.max_contract_size
+            .get_or_insert(other.max_contract_size);
+        self.max_state_size.get_or_insert(other.max_state_size);
+        self.max_delta_size.get_or_insert(other.max_delta_size);
+    }
+
+    fn build(self) -> anyhow::Result<PayloadLimits> {
+        let defaults = PayloadLimits::default();
+        let limits = PayloadLimits {
+            max_message_size: self.max_message_size.unwrap_or(defaults.max_message_size),
+            max_body_size: self.max_body_size.unwrap_or(defaults.max_body_size),
+            max_contract_size: self.max_contract_size.unwrap_or(defaults.max_contract_size),
+            max_state_size: self.max_state_size.unwrap_or(defaults.max_state_size),
+            max_delta_size: self.max_delta_size.unwrap_or(defaults.max_delta_size),
+        };
+        // a put carries both the contract and its state in a single message
+        if limits
+            .max_contract_size
+            .saturating_add(limits.max_state_size)
+            > limits.max_message_size
+        {
+            anyhow::bail!(
+                "max-contract-size plus max-state-size ({} bytes) must not exceed max-message-size ({} bytes)",
+                limits.max_contract_size.saturating_add(limits.max_state_size),
+                limits.max_message_size
+            );
+        }
+        if limits.max_delta_size > limits.max_message_size {
+            anyhow::bail!(
+                "max-delta-size ({} bytes) must not exceed max-message-size ({} bytes)",
+                limits.max_delta_size,
+                limits.max_message_size
+            );
+        }
+        Ok(limits)
+    }
+}
+
+/// Maximum sizes of the payloads accepted from clients.
+///
+/// The state limit also applies to the states received from other peers.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct PayloadLimits {
+    #[serde(default = "default_max_message_size", rename = "max-message-size")]
+    pub max_message_size: usize,
+    #[serde(default = "default_max_body_size", rename = "max-body-size")]
+    pub max_body_size: usize,
+    #[serde(default = "default_max_contract_size", rename = "max-contract-size")]
+    pub max_contract_size: usize,
+    #[serde(default = "default_max_state_size", rename = "max-state-size")]
+    pub max_state_size: usize,
+    #[serde(default = "default_max_delta_size", rename = "max-delta-size")]
+    pub max_delta_size: usize,
+}
+
+impl Default for PayloadLimits {
+    fn default() -> Self {
+        Self {
+            max_message_size: default_max_message_size(),
+            max_body_size: default_max_body_size(),
+            max_contract_size: default_max_contract_size(),
+            max_state_size: default_max_state_size(),
+            max_delta_size: default_max_delta_size(),
+        }
+    }
+}
+
+#[inline]
+const fn default_max_message_size() -> usize {
+    64 * 1024 * 1024
+}
+
+#[inline]
+const fn default_max_body_size() -> usize {
+    1024 * 1024
+}
+
+#[inline]
+const fn default_max_contract_size() -> usize {
+    16 * 1024 * 1024
+}
+
+#[inline]
+const fn default_max_state_size() -> usize {
+    32 * 1024 * 1024
+}
+
+#[inline]
+const fn default_max_delta_size() -> usize {
+    16 * 1024 * 1024
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct WebsocketApiConfig {
+    /// Address to bind to
+    #[serde(default = "default_listening_address", rename = "ws-api-address")]
+    pub address: IpAddr,
+
+    /// Port to expose api on
+    #[serde(default = "default_http_gateway_port", rename = "ws-api-port")]
+    pub port: u16,
+
+    /// Certificate chain the gateway is served over HTTPS with, if any
+    #[serde(rename = "tls-certificate", skip_serializing_if = "Option::is_none")]
+    pub tls_certificate: Option<PathBuf>,
+
+    /// Private key of the TLS certificate
+    #[serde(rename = "tls-private-key", skip_serializing_if = "Option::is_none")]
+    pub tls_private_key: Option<PathBuf>,
+
+    /// ACME settings, if the certificate is managed by the node
+    #[serde(flatten)]
+    pub acme: Option<AcmeConfig>,
+
+    /// Domains serving the web app of a contract at their root
+    #[serde(
+        default,
+        rename = "virtual-hosts",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub virtual_hosts: Vec<VirtualHost>,
+
+    /// Maximum sizes of the payloads accepted from clients
+    #[serde(flatten)]
+    pub limits: PayloadLimits,
+
+    /// Cross-origin policy of the gateway endpoints
+    #[serde(flatten)]
+    pub cors: CorsConfig,
+
+    /// Access logs of the gateway
+    #[serde(flatten)]
+    pub access_log: AccessLogConfig,
+
+    /// Thresholds over which expensive requests are rejected
+    #[serde(flatten)]
+    pub load_shedding: LoadSheddingConfig,
+
+    /// Whether the WebRTC signalling endpoint is served, derived from the network settings
+    #[serde(skip)]
+    pub webrtc: bool,
+
+    /// Directory web apps are unpacked to, derived from the cache directory of the node
+    #[serde(skip, default = "default_webs_dir")]
+    pub webs_dir: PathBuf,
+}
+
+impl WebsocketApiConfig {
+    /// Certificate chain and private key paths, if the gateway is served over HTTPS.
+    pub fn tls(&self) -> Option<(&Path, &Path)> {
+        Some((
+            self.tls_certificate.as_deref()?,
+            self.tls_private_key.as_deref()?,
+        ))
+    }
+
+    /// Whether the gateway is served over HTTPS, for its own domain or any virtual host.
+    pub fn serves_tls(&self) -> bool {
+        self.tls().is_some() || self.virtual_hosts.iter().any(|vhost| vhost.tls().is_some())
+    }
+}
+
+impl From<SocketAddr> for WebsocketApiConfig {
+    fn from(addr: SocketAddr) -> Self {
+        Self {
+            address: addr.ip(),
+            port: addr.port(),
+            tls_certificate: None,
+            tls_private_key: None,
+            acme: None,
+            virtual_hosts: vec![],
+            limits: PayloadLimits::default(),
+            cors: CorsConfig::default(),
+            access_log: AccessLogConfig::default(),
+            load_shedding: LoadSheddingConfig::default(),
+            webrtc: false,
+            webs_dir: default_webs_dir(),
+        }
+    }
+}
+
+impl Default for WebsocketApiConfig {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            address: default_listening_address(),
+            port: default_http_gateway_port(),
+            tls_certificate: None,
+            tls_private_key: None,
+            acme: None,
+            virtual_hosts: vec![],
+            limits: PayloadLimits::default(),
+            cors: CorsConfig::default(),
+            access_log: AccessLogConfig::default(),
+            load_shedding: LoadSheddingConfig::default(),
+            webrtc: false,
+            webs_dir: default_webs_dir(),
+        }
+    }
+}
+
+/// A domain serving the web app of a contract at its root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct VirtualHost {
+    /// Hostname, matched against the `Host` of the requests and the server name of TLS clients
+    pub host: String,
+
+    /// Instance id of the contract whose web app is served
+    pub contract: String,
+
+    /// Certificate chain served for the host, obtained through ACME if managed by the node
+    #[serde(rename = "tls-certificate", skip_serializing_if = "Option::is_none")]
+    pub tls_certificate: Option<PathBuf>,
+
+    /// Private key of the host certificate
+    #[serde(rename = "tls-private-key", skip_serializing_if = "Option::is_none")]
+    pub tls_private_key: Option<PathBuf>,
+}
+
+impl VirtualHost {
+    /// Certificate chain and private key paths, if the host has its own certificate.
+    pub fn tls(&self) -> Option<(&Path, &Path)> {
+        Some((
+            self.tls_certificate.as_deref()?,
+            self.tls_private_key.as_deref()?,
+        ))
+    }
+}
+
+impl std::str::FromStr for VirtualHost {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (host, contract) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected `host=contract`, got `{s}`"))?;
+        let host = host.trim().trim_end_matches('.').to_ascii_lowercase();
+        if host.is_empty() || contract.trim().is_empty() {
+            return Err(format!("expected `host=contract`, got `{s}`"));
+        }
+        Ok(Self {
+            host,
+            contract: contract.trim().to_owned(),
+            tls_certificate: None,
+            tls_private_key: None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct AcmeConfig {
+    /// Domain the certificate is issued for
+    pub domain: String,
+
+    /// Contact email registered with the provider
+    #[serde(rename = "acme-contact", skip_serializing_if = "Option::is_none")]
+    pub contact: Option<String>,
+
+    /// Directory URL of the provider
+    #[serde(default = "default_acme_directory", rename = "acme-directory")]
+    pub directory: String,
+
+    /// Port the HTTP-01 challenges are served on
+    #[serde(default = "default_acme_http_port", rename = "acme-http-port")]
+    pub http_port: u16,
+}
+
+fn default_acme_directory() -> String {
+    "https://acme-v02.api.letsencrypt.org/directory".to_string()
+}
+
+#[inline]
+const fn default_acme_http_port() -> u16 {
+    80
+}
+
+#[inline]
+const fn default_https_gateway_port() -> u16 {
+    443
+}
+
+#[inline]
+const fn default_listening_address() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+}
+
+#[inline]
+const fn default_local_address() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::LOCALHOST)
 }
 
 #[inline]
@@ -616,6 +1903,281 @@ const fn default_http_gateway_port() -> u16 {
     50509
 }
 
+#[derive(clap::Parser, Debug, Default, Copy, Clone, Serialize, Deserialize)]
+pub struct AdminApiArgs {
+    /// Port to expose the admin API on. The admin API only listens on localhost
+    /// and is disabled unless a port is set. Requests must carry the token written
+    /// to the `admin-token` file of the config directory as a bearer token.
+    #[arg(long, env = "ADMIN_API_PORT")]
+    #[serde(rename = "admin-api-port", skip_serializing_if = "Option::is_none")]
+    pub admin_api_port: Option<u16>,
+}
+
+#[derive(Debug, Default, Copy, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct AdminApiConfig {
+    /// Port to expose the admin API on, disabled if not set
+    #[serde(rename = "admin-api-port", skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+}
+
+#[derive(clap::Parser, Debug, Default, Copy, Clone, Serialize, Deserialize)]
+pub struct SnapshotArgs {
+    /// Interval, in seconds, between automatic contract state snapshots. Disabled if not set.
+    #[arg(long, env = "SNAPSHOT_INTERVAL")]
+    #[serde(rename = "snapshot-interval", skip_serializing_if = "Option::is_none")]
+    pub snapshot_interval: Option<u64>,
+
+    /// Number of contract state snapshots to retain, default is 5
+    #[arg(long, env = "SNAPSHOTS_TO_KEEP")]
+    #[serde(rename = "snapshots-to-keep", skip_serializing_if = "Option::is_none")]
+    pub snapshots_to_keep: Option<usize>,
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct SnapshotConfig {
+    /// Interval, in seconds, between automatic snapshots
+    #[serde(rename = "snapshot-interval", skip_serializing_if = "Option::is_none")]
+    pub interval: Option<u64>,
+
+    /// Number of snapshots to retain
+    #[serde(default = "default_snapshots_to_keep", rename = "snapshots-to-keep")]
+    pub keep: usize,
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        Self {
+            interval: None,
+            keep: default_snapshots_to_keep(),
+        }
+    }
+}
+
+#[inline]
+const fn default_contract_time_epoch() -> u64 {
+    crate::wasm_runtime::DEFAULT_TIME_EPOCH.as_secs()
+}
+
+#[inline]
+const fn default_replication_factor() -> usize {
+    crate::ring::DEFAULT_REPLICATION_FACTOR
+}
+
+fn default_operation_mode() -> OperationMode {
+    OperationMode::Network
+}
+
+fn default_log_level() -> tracing::log::LevelFilter {
+    tracing::log::LevelFilter::Info
+}
+
+pub(crate) const fn default_min_connections(role: NodeRole) -> usize {
+    match role {
+        NodeRole::Gateway | NodeRole::Peer => crate::ring::Ring::DEFAULT_MIN_CONNECTIONS,
+        NodeRole::Light => crate::ring::Ring::LIGHT_MIN_CONNECTIONS,
+    }
+}
+
+/// Gateways are the entry point of joining peers, so they keep more connections, while light
+/// nodes only need a few to reach the network.
+pub(crate) const fn default_max_connections(role: NodeRole) -> usize {
+    match role {
+        NodeRole::Gateway => 2 * crate::ring::Ring::DEFAULT_MAX_CONNECTIONS,
+        NodeRole::Peer => crate::ring::Ring::DEFAULT_MAX_CONNECTIONS,
+        NodeRole::Light => crate::ring::Ring::LIGHT_MAX_CONNECTIONS,
+    }
+}
+
+#[inline]
+const fn default_snapshots_to_keep() -> usize {
+    crate::contract::storages::snapshot::DEFAULT_SNAPSHOTS_TO_KEEP
+}
+
+#[derive(clap::Parser, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ContractPolicyArgs {
+    /// Contract instance ids or code hashes this node is allowed to execute. If neither allowed
+    /// contracts nor trusted publishers are set, any contract which is not denied is executed.
+    #[arg(
+        long = "allow-contract",
+        env = "ALLOWED_CONTRACTS",
+        value_delimiter = ','
+    )]
+    #[serde(rename = "allowed-contracts", skip_serializing_if = "Option::is_none")]
+    pub allowed_contracts: Option<Vec<String>>,
+
+    /// Contract instance ids or code hashes this node will never execute.
+    #[arg(
+        long = "deny-contract",
+        env = "DENIED_CONTRACTS",
+        value_delimiter = ','
+    )]
+    #[serde(rename = "denied-contracts", skip_serializing_if = "Option::is_none")]
+    pub denied_contracts: Option<Vec<String>>,
+
+    /// Public keys (PEM files) of publishers whose signed contracts this node executes.
+    #[arg(
+        long = "trusted-publisher",
+        env = "TRUSTED_PUBLISHERS",
+        value_delimiter = ','
+    )]
+    #[serde(rename = "trusted-publishers", skip_serializing_if = "Option::is_none")]
+    pub trusted_publishers: Option<Vec<PathBuf>>,
+
+    /// Store and forward the state of contracts flagged by the policy without ever executing them,
+    /// instead of refusing them.
+    #[arg(long, env = "METADATA_ONLY")]
+    #[serde(rename = "metadata-only", default)]
+    pub metadata_only: bool,
+}
+
+impl ContractPolicyArgs {
+    fn merge(&mut self, other: ContractPolicyConfig) {
+        if self.allowed_contracts.is_none() && !other.allowed_contracts.is_empty() {
+            self.allowed_contracts = Some(other.allowed_contracts);
+        }
+        if self.denied_contracts.is_none() && !other.denied_contracts.is_empty() {
+            self.denied_contracts = Some(other.denied_contracts);
+        }
+        if self.trusted_publishers.is_none() && !other.trusted_publishers.is_empty() {
+            self.trusted_publishers = Some(other.trusted_publishers);
+        }
+        self.metadata_only |= other.metadata_only;
+    }
+
+    fn build(self) -> ContractPolicyConfig {
+        ContractPolicyConfig {
+            allowed_contracts: self.allowed_contracts.unwrap_or_default(),
+            denied_contracts: self.denied_contracts.unwrap_or_default(),
+            trusted_publishers: self.trusted_publishers.unwrap_or_default(),
+            metadata_only: self.metadata_only,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct ContractPolicyConfig {
+    /// Contract instance ids or code hashes allowed to be executed
+    #[serde(
+        default,
+        rename = "allowed-contracts",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub allowed_contracts: Vec<String>,
+
+    /// Contract instance ids or code hashes which are never executed
+    #[serde(
+        default,
+        rename = "denied-contracts",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub denied_contracts: Vec<String>,
+
+    /// Public keys of the publishers whose signed contracts are executed
+    #[serde(
+        default,
+        rename = "trusted-publishers",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub trusted_publishers: Vec<PathBuf>,
+
+    /// Store and forward flagged contracts instead of refusing them
+    #[serde(default, rename = "metadata-only")]
+    pub metadata_only: bool,
+}
+
+#[derive(clap::Parser, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct StorageBudgetArgs {
+    /// Maximum number of bytes used by the state of the cached contracts. Once over it, the
+    /// least recently used contracts which are not subscribed to nor pinned are evicted.
+    /// Unlimited if not set.
+    #[arg(long, env = "MAX_STORAGE_SIZE")]
+    #[serde(rename = "max-storage-size", skip_serializing_if = "Option::is_none")]
+    pub max_storage_size: Option<u64>,
+
+    /// Contract instance ids which are never evicted to honor the storage budget.
+    #[arg(long = "pin-contract", env = "PINNED_CONTRACTS", value_delimiter = ',')]
+    #[serde(rename = "pinned-contracts", skip_serializing_if = "Option::is_none")]
+    pub pinned_contracts: Option<Vec<String>>,
+
+    /// Maximum number of bytes of contract states kept in memory, default is 10 MB
+    #[arg(long, env = "STATE_CACHE_SIZE")]
+    #[serde(rename = "state-cache-size", skip_serializing_if = "Option::is_none")]
+    pub state_cache_size: Option<u64>,
+
+    /// Maximum number of bytes of contract and delegate code kept in memory, default is 10 MiB
+    #[arg(long, env = "CODE_CACHE_SIZE")]
+    #[serde(rename = "code-cache-size", skip_serializing_if = "Option::is_none")]
+    pub code_cache_size: Option<u64>,
+}
+
+impl StorageBudgetArgs {
+    fn merge(&mut self, other: StorageBudgetConfig) {
+        if let Some(max_size) = other.max_storage_size {
+            self.max_storage_size.get_or_insert(max_size);
+        }
+        if self.pinned_contracts.is_none() && !other.pinned_contracts.is_empty() {
+            self.pinned_contracts = Some(other.pinned_contracts);
+        }
+        self.state_cache_size.get_or_insert(other.state_cache_size);
+        self.code_cache_size.get_or_insert(other.code_cache_size);
+    }
+
+    fn build(self) -> StorageBudgetConfig {
+        StorageBudgetConfig {
+            max_storage_size: self.max_storage_size,
+            pinned_contracts: self.pinned_contracts.unwrap_or_default(),
+            state_cache_size: self
+                .state_cache_size
+                .unwrap_or_else(default_state_cache_size),
+            code_cache_size: self.code_cache_size.unwrap_or_else(default_code_cache_size),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct StorageBudgetConfig {
+    /// Maximum number of bytes used by the state of the cached contracts, unlimited if not set
+    #[serde(rename = "max-storage-size", skip_serializing_if = "Option::is_none")]
+    pub max_storage_size: Option<u64>,
+
+    /// Contract instance ids which are never evicted
+    #[serde(
+        default,
+        rename = "pinned-contracts",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub pinned_contracts: Vec<String>,
+
+    /// Maximum number of bytes of contract states kept in memory
+    #[serde(default = "default_state_cache_size", rename = "state-cache-size")]
+    pub state_cache_size: u64,
+
+    /// Maximum number of bytes of contract and delegate code kept in memory
+    #[serde(default = "default_code_cache_size", rename = "code-cache-size")]
+    pub code_cache_size: u64,
+}
+
+impl Default for StorageBudgetConfig {
+    fn default() -> Self {
+        Self {
+            max_storage_size: None,
+            pinned_contracts: vec![],
+            state_cache_size: default_state_cache_size(),
+            code_cache_size: default_code_cache_size(),
+        }
+    }
+}
+
+#[inline]
+const fn default_state_cache_size() -> u64 {
+    10_000_000
+}
+
+#[inline]
+const fn default_code_cache_size() -> u64 {
+    10 * 1024 * 1024
+}
+
 #[derive(clap::Parser, Default, Debug, Clone, Serialize, Deserialize)]
 pub struct ConfigPathsArgs {
     /// The configuration directory.
@@ -628,11 +2190,15 @@ pub struct ConfigPathsArgs {
 
 impl ConfigPathsArgs {
     fn merge(&mut self, other: ConfigPaths) {
-        self.config_dir.get_or_insert(other.config_dir);
-        self.data_dir.get_or_insert(other.data_dir);
+        if !other.config_dir.as_os_str().is_empty() {
+            self.config_dir.get_or_insert(other.config_dir);
+        }
+        if !other.data_dir.as_os_str().is_empty() {
+            self.data_dir.get_or_insert(other.data_dir);
+        }
     }
 
-    fn default_dirs(id: Option<&str>) -> std::io::Result<Either<ProjectDirs, PathBuf>> {
+    fn default_dirs(id: Option<&str>) -> std::io::Result<Either<NodeDirs, PathBuf>> {
         // if id is set, most likely we are running tests or in simulated mode
         let default_dir: Either<_, _> = if cfg!(any(test, debug_assertions)) || id.is_some() {
             Either::Right(std::env::temp_dir().join(if let Some(id) = id {
@@ -641,10 +2207,7 @@ impl ConfigPathsArgs {
                 "freenet".into()
             }))
         } else {
-            Either::Left(
-                ProjectDirs::from(QUALIFIER, ORGANIZATION, APPLICATION)
-                    .ok_or(std::io::ErrorKind::NotFound)?,
-            )
+            Either::Left(NodeDirs::resolve()?)
         };
         Ok(default_dir)
     }
@@ -704,8 +2267,17 @@ impl ConfigPathsArgs {
                 Ok(defaults.config_dir().to_path_buf())
             })?;
 
+        // the default data directory has a cache directory of its own, others keep it inside
+        let cache_dir = match Self::default_dirs(id)? {
+            Either::Left(defaults) if defaults.data_dir() == app_data_dir => {
+                defaults.cache_dir().to_path_buf()
+            }
+            _ => app_data_dir.join("cache"),
+        };
+
         Ok(ConfigPaths {
             config_dir,
+            cache_dir,
             data_dir: app_data_dir,
             contracts_dir,
             delegates_dir,
@@ -716,14 +2288,25 @@ impl ConfigPathsArgs {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
 pub struct ConfigPaths {
+    // only the data and config directories are read from configuration files, the rest are
+    // always derived from the data directory
+    #[serde(default)]
     contracts_dir: PathBuf,
+    #[serde(default)]
     delegates_dir: PathBuf,
+    #[serde(default)]
     secrets_dir: PathBuf,
+    #[serde(default)]
     db_dir: PathBuf,
+    #[serde(default)]
     event_log: PathBuf,
+    #[serde(default)]
+    cache_dir: PathBuf,
+    #[serde(default)]
     data_dir: PathBuf,
+    #[serde(default)]
     config_dir: PathBuf,
 }
 
@@ -768,6 +2351,16 @@ impl ConfigPaths {
         self.config_dir.clone()
     }
 
+    /// Directory of the files the node can rebuild at any time.
+    pub fn cache_dir(&self) -> PathBuf {
+        self.cache_dir.clone()
+    }
+
+    /// Directory the web apps served by the gateway are unpacked to.
+    pub fn webs_dir(&self) -> PathBuf {
+        self.cache_dir.join("webs")
+    }
+
     pub fn secrets_dir(&self, mode: OperationMode) -> PathBuf {
         match mode {
             OperationMode::Local => self.secrets_dir.join("local"),
@@ -791,6 +2384,22 @@ impl ConfigPaths {
         }
     }
 
+    pub fn snapshots_dir(&self, mode: OperationMode) -> PathBuf {
+        match mode {
+            OperationMode::Local => self.data_dir.join("snapshots").join("local"),
+            OperationMode::Network => self.data_dir.join("snapshots"),
+        }
+    }
+
+    pub fn recordings_dir(&self) -> PathBuf {
+        self.data_dir.join("recordings")
+    }
+
+    /// File holding the token required by the admin API, regenerated on every start.
+    pub fn admin_token_path(&self) -> PathBuf {
+        self.config_dir.join("admin-token")
+    }
+
     pub fn with_event_log(mut self, event_log: PathBuf) -> Self {
         self.event_log = event_log;
         self
@@ -869,6 +2478,18 @@ impl Config {
     pub fn config_dir(&self) -> PathBuf {
         self.config_paths.config_dir()
     }
+
+    pub fn snapshots_dir(&self) -> PathBuf {
+        self.config_paths.snapshots_dir(self.mode)
+    }
+
+    pub fn recordings_dir(&self) -> PathBuf {
+        self.config_paths.recordings_dir()
+    }
+
+    pub fn admin_token_path(&self) -> PathBuf {
+        self.config_paths.admin_token_path()
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Default)]
@@ -904,6 +2525,11 @@ pub struct GatewayConfig {
     /// Optional location of the gateway.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub location: Option<f64>,
+
+    /// Other addresses the gateway is reachable at (e.g. an IPv6 address besides an IPv4 one).
+    /// Peers connect through the one matching the address families they listen on.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alternative_addresses: Vec<Address>,
 }
 
 impl PartialEq for GatewayConfig {
@@ -1046,6 +2672,57 @@ mod tests {
         let _: Config = toml::from_str(&serialized).unwrap();
     }
 
+    #[test]
+    fn node_role_from_args() {
+        let mut args = NetworkArgs::default();
+        assert_eq!(args.role().unwrap(), NodeRole::Peer);
+        args.role = Some(NodeRole::Light);
+        assert_eq!(args.role().unwrap(), NodeRole::Light);
+        assert_eq!(
+            default_max_connections(NodeRole::Light),
+            crate::ring::Ring::LIGHT_MAX_CONNECTIONS
+        );
+
+        // gateways can't be given an other role
+        args.is_gateway = true;
+        assert!(args.role().is_err());
+        args.role = None;
+        assert_eq!(args.role().unwrap(), NodeRole::Gateway);
+    }
+
+    #[test]
+    fn tls_certificate_and_key_go_together() {
+        let mut args = WebsocketApiArgs::default();
+        assert!(args.check_tls().is_ok());
+        args.tls_certificate = Some("gateway.crt".into());
+        assert!(args.check_tls().is_err());
+        args.tls_private_key = Some("gateway.key".into());
+        assert!(args.check_tls().is_ok());
+
+        let mut vhost: VirtualHost = "app.example.org=contract".parse().unwrap();
+        vhost.tls_private_key = Some("app.key".into());
+        args.virtual_hosts = Some(vec![vhost]);
+        assert!(args.check_tls().is_err());
+    }
+
+    #[test]
+    fn payload_limits_fit_in_a_message() {
+        assert!(PayloadLimitsArgs::default().build().is_ok());
+        let state_too_large = PayloadLimitsArgs {
+            max_state_size: Some(default_max_message_size()),
+            ..Default::default()
+        };
+        assert!(state_too_large.build().is_err());
+        let delta_too_large = PayloadLimitsArgs {
+            max_message_size: Some(1024),
+            max_contract_size: Some(512),
+            max_state_size: Some(512),
+            max_delta_size: Some(2048),
+            ..Default::default()
+        };
+        assert!(delta_too_large.build().is_err());
+    }
+
     #[tokio::test]
     async fn test_load_gateways_from_index() {
         let server = Server::run();
@@ -1099,11 +2776,13 @@ mod tests {
                     ),
                     public_key_path: PathBuf::from("path/to/key"),
                     location: None,
+                    alternative_addresses: vec![],
                 },
                 GatewayConfig {
                     address: Address::Hostname("technic.locut.us".to_string()),
                     public_key_path: PathBuf::from("path/to/key"),
                     location: None,
+                    alternative_addresses: vec![],
                 },
             ],
         };
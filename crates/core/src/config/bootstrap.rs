@@ -0,0 +1,683 @@
+//! Discovery of the gateways to bootstrap from, besides the ones configured locally.
+//!
+//! Gateways can be published in DNS, either as TXT records of the form
+//! `freenet-gw addr=<host:port> key=<base58 DER public key> sig=<base58 signature>` on the
+//! bootstrap domain, or as SRV records under `_freenet._udp.<domain>` whose targets have a
+//! `freenet-gw key=<...> sig=<...>` TXT record.
+//!
+//! They can also be listed in a TOML bootstrap file fetched over HTTPS. The signature is fetched
+//! from `<url>.sig`, and is a PKCS#1 v1.5 signature of the blake3 hash of the file. Files are
+//! never fetched over plain HTTP, except from the loopback interface.
+//!
+//! Both sources must be signed by the configured signer key. DNS records are signed as the
+//! record `freenet-gw addr=<host:port> key=<...>` would be, taking for SRV records the target and
+//! port of the SRV record as address, so the signature binds the gateway key to its address.
+//! Records without a valid signature are ignored.
+//!
+//! The gateways last discovered are cached in the config directory, so a node can restart while
+//! the bootstrap sources are unreachable. Discovery never prevents a node from starting: sources
+//! which fail or take too long are skipped, and if none succeeds the cached gateways are used.
+
+use std::{
+    fs,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use hickory_resolver::TokioAsyncResolver;
+use pkcs8::{DecodePublicKey, EncodePublicKey, LineEnding};
+use rsa::{Pkcs1v15Sign, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+
+use super::{Address, GatewayConfig, Gateways};
+
+const TXT_RECORD_TAG: &str = "freenet-gw";
+const SRV_PREFIX: &str = "_freenet._udp";
+const CACHE_FILE: &str = "bootstrap-gateways.toml";
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Maximum time fetching a bootstrap file or its signature.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(20);
+const MAX_FILE_SIZE: usize = 1024 * 1024;
+const MAX_SIGNATURE_SIZE: usize = 4096;
+const MAX_REDIRECTS: usize = 5;
+
+#[derive(clap::Parser, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct BootstrapArgs {
+    /// Domains whose DNS TXT and SRV records list gateways to bootstrap from. Records must be
+    /// signed by the bootstrap signer.
+    #[arg(long, env = "BOOTSTRAP_DNS", value_delimiter = ',')]
+    #[serde(rename = "bootstrap-dns", skip_serializing_if = "Option::is_none")]
+    pub bootstrap_dns: Option<Vec<String>>,
+
+    /// HTTPS URLs of signed bootstrap files listing gateways to bootstrap from.
+    #[arg(long = "bootstrap-url", env = "BOOTSTRAP_URLS", value_delimiter = ',')]
+    #[serde(rename = "bootstrap-urls", skip_serializing_if = "Option::is_none")]
+    pub bootstrap_urls: Option<Vec<String>>,
+
+    /// Public key (PEM file) bootstrap files and DNS gateway records must be signed with.
+    #[arg(long, env = "BOOTSTRAP_SIGNER")]
+    #[serde(rename = "bootstrap-signer", skip_serializing_if = "Option::is_none")]
+    pub bootstrap_signer: Option<PathBuf>,
+}
+
+impl BootstrapArgs {
+    pub(super) fn merge(&mut self, other: BootstrapConfig) {
+        if self.bootstrap_dns.is_none() && !other.dns.is_empty() {
+            self.bootstrap_dns = Some(other.dns);
+        }
+        if self.bootstrap_urls.is_none() && !other.urls.is_empty() {
+            self.bootstrap_urls = Some(other.urls);
+        }
+        if self.bootstrap_signer.is_none() {
+            self.bootstrap_signer = other.signer;
+        }
+    }
+
+    pub(super) fn build(self) -> BootstrapConfig {
+        BootstrapConfig {
+            dns: self.bootstrap_dns.unwrap_or_default(),
+            urls: self.bootstrap_urls.unwrap_or_default(),
+            signer: self.bootstrap_signer,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct BootstrapConfig {
+    /// Domains listing gateways in their DNS records
+    #[serde(
+        default,
+        rename = "bootstrap-dns",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub dns: Vec<String>,
+
+    /// URLs of signed bootstrap files
+    #[serde(
+        default,
+        rename = "bootstrap-urls",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub urls: Vec<String>,
+
+    /// Public key bootstrap files and DNS gateway records must be signed with
+    #[serde(rename = "bootstrap-signer", skip_serializing_if = "Option::is_none")]
+    pub signer: Option<PathBuf>,
+}
+
+impl BootstrapConfig {
+    fn is_enabled(&self) -> bool {
+        !self.dns.is_empty() || !self.urls.is_empty()
+    }
+}
+
+#[derive(Debug)]
+struct DiscoveredGateway {
+    address: Address,
+    alternative_addresses: Vec<Address>,
+    public_key: RsaPublicKey,
+}
+
+impl DiscoveredGateway {
+    /// Stores the public key of the gateway, which is referenced by path from its configuration.
+    fn into_config(self, pub_keys_dir: &Path) -> anyhow::Result<GatewayConfig> {
+        let der = self.public_key.to_public_key_der()?;
+        let key_hash = blake3::hash(der.as_bytes());
+        let path = pub_keys_dir.join(format!(
+            "gateway-{}.pem",
+            bs58::encode(&key_hash.as_bytes()[..16]).into_string()
+        ));
+        fs::write(&path, self.public_key.to_public_key_pem(LineEnding::LF)?)?;
+        Ok(GatewayConfig {
+            address: self.address,
+            public_key_path: path,
+            location: None,
+            alternative_addresses: self.alternative_addresses,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct BootstrapCache {
+    resolved_at: DateTime<Utc>,
+    gateways: Vec<GatewayConfig>,
+}
+
+/// Discovers gateways from every configured bootstrap source.
+///
+/// Falls back to the gateways last discovered if none could be discovered now.
+pub(super) async fn discover(
+    config: &BootstrapConfig,
+    config_dir: &Path,
+    pub_keys_dir: &Path,
+) -> Gateways {
+    if !config.is_enabled() {
+        return Gateways::default();
+    }
+    let mut discovered = vec![];
+
+    match load_signer(config.signer.as_deref()) {
+        Ok(signer) => {
+            if !config.dns.is_empty() {
+                match TokioAsyncResolver::tokio_from_system_conf() {
+                    Ok(resolver) => {
+                        for domain in &config.dns {
+                            match from_dns(&resolver, domain, &signer).await {
+                                Ok(gateways) => discovered.extend(gateways),
+                                Err(err) => {
+                                    tracing::warn!(
+                                        "Failed discovering gateways from DNS at {domain}: {err}"
+                                    )
+                                }
+                            }
+                        }
+                    }
+                    Err(err) => {
+                        tracing::warn!("Failed loading the system DNS configuration: {err}")
+                    }
+                }
+            }
+
+            if !config.urls.is_empty() {
+                match http_client() {
+                    Ok(client) => {
+                        for url in &config.urls {
+                            match from_signed_file(&client, url, &signer).await {
+                                Ok(gateways) => discovered.extend(gateways),
+                                Err(err) => {
+                                    tracing::warn!(
+                                        "Failed loading gateways from bootstrap file at {url}: {err}"
+                                    )
+                                }
+                            }
+                        }
+                    }
+                    Err(err) => tracing::warn!("Ignoring bootstrap files: {err}"),
+                }
+            }
+        }
+        Err(err) => tracing::warn!("Ignoring bootstrap sources: {err}"),
+    }
+
+    let cache_file = config_dir.join(CACHE_FILE);
+    if discovered.is_empty() {
+        tracing::warn!("No gateways discovered, using the last known bootstrap gateways");
+        return load_cache(&cache_file).unwrap_or_else(|err| {
+            tracing::warn!("Failed loading the last known bootstrap gateways: {err}");
+            Gateways::default()
+        });
+    }
+    if let Err(err) = fs::create_dir_all(pub_keys_dir) {
+        tracing::warn!("Failed creating the gateway public keys directory: {err}");
+    }
+    let gateways = discovered
+        .into_iter()
+        .filter_map(|gateway| {
+            gateway
+                .into_config(pub_keys_dir)
+                .inspect_err(|err| tracing::warn!("Failed storing gateway public key: {err}"))
+                .ok()
+        })
+        .collect();
+    let gateways = Gateways { gateways };
+    if let Err(err) = save_cache(&cache_file, &gateways) {
+        tracing::warn!("Failed caching the discovered bootstrap gateways: {err}");
+    }
+    tracing::info!(
+        "Discovered {} gateways from bootstrap sources",
+        gateways.gateways.len()
+    );
+    gateways
+}
+
+fn load_signer(path: Option<&Path>) -> anyhow::Result<RsaPublicKey> {
+    let path = path.ok_or_else(|| {
+        anyhow::anyhow!("a bootstrap signer public key is required to discover gateways")
+    })?;
+    RsaPublicKey::from_public_key_pem(&fs::read_to_string(path)?)
+        .map_err(|err| anyhow::anyhow!("failed loading bootstrap signer key: {err}"))
+}
+
+fn load_cache(path: &Path) -> anyhow::Result<Gateways> {
+    let Ok(content) = fs::read_to_string(path) else {
+        return Ok(Gateways::default());
+    };
+    let cache: BootstrapCache = toml::from_str(&content)?;
+    tracing::info!(
+        "Loaded {} cached bootstrap gateways resolved at {}",
+        cache.gateways.len(),
+        cache.resolved_at
+    );
+    Ok(Gateways {
+        gateways: cache.gateways,
+    })
+}
+
+fn save_cache(path: &Path, gateways: &Gateways) -> anyhow::Result<()> {
+    let cache = BootstrapCache {
+        resolved_at: Utc::now(),
+        gateways: gateways.gateways.clone(),
+    };
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, toml::to_string(&cache)?)?;
+    Ok(())
+}
+
+struct TxtRecord {
+    /// The address as written in the record.
+    addr: Option<String>,
+    /// The public key as written in the record.
+    key: String,
+    public_key: RsaPublicKey,
+    signature: Option<Vec<u8>>,
+}
+
+impl TxtRecord {
+    fn address(&self) -> Option<Address> {
+        self.addr.as_deref().map(|addr| {
+            addr.parse::<SocketAddr>()
+                .map(Address::HostAddress)
+                .unwrap_or_else(|_| Address::Hostname(addr.to_owned()))
+        })
+    }
+
+    /// Checks the record is signed by the signer for a gateway at the given address.
+    fn verify(&self, addr: &str, signer: &RsaPublicKey) -> anyhow::Result<()> {
+        let signature = self
+            .signature
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("unsigned gateway record"))?;
+        let signed = signed_record(addr, &self.key);
+        signer
+            .verify(
+                Pkcs1v15Sign::new_unprefixed(),
+                blake3::hash(signed.as_bytes()).as_bytes(),
+                signature,
+            )
+            .map_err(|_| anyhow::anyhow!("invalid gateway record signature"))
+    }
+}
+
+/// The record the signature of a gateway record is made over.
+fn signed_record(addr: &str, key: &str) -> String {
+    format!("{TXT_RECORD_TAG} addr={addr} key={key}")
+}
+
+/// Parses a `freenet-gw addr=<host:port> key=<base58 DER public key> sig=<base58 signature>`
+/// record.
+///
+/// Returns `None` for records which are not gateway records.
+fn parse_txt(record: &str) -> anyhow::Result<Option<TxtRecord>> {
+    let mut fields = record.split_whitespace();
+    if fields.next() != Some(TXT_RECORD_TAG) {
+        return Ok(None);
+    }
+    let mut addr = None;
+    let mut public_key = None;
+    let mut signature = None;
+    for field in fields {
+        match field.split_once('=') {
+            Some(("addr", value)) => addr = Some(value.to_owned()),
+            Some(("key", key)) => {
+                let der = bs58::decode(key).into_vec()?;
+                public_key = Some((
+                    key.to_owned(),
+                    RsaPublicKey::from_public_key_der(&der)
+                        .map_err(|err| anyhow::anyhow!("invalid gateway public key: {err}"))?,
+                ));
+            }
+            Some(("sig", sig)) => signature = Some(bs58::decode(sig).into_vec()?),
+            _ => {}
+        }
+    }
+    let (key, public_key) =
+        public_key.ok_or_else(|| anyhow::anyhow!("gateway record without public key"))?;
+    Ok(Some(TxtRecord {
+        addr,
+        key,
+        public_key,
+        signature,
+    }))
+}
+
+async fn txt_records(resolver: &TokioAsyncResolver, name: &str) -> anyhow::Result<Vec<TxtRecord>> {
+    let lookup = resolver.txt_lookup(name).await?;
+    Ok(lookup
+        .iter()
+        .filter_map(|txt| {
+            let record: String = txt
+                .txt_data()
+                .iter()
+                .map(|data| String::from_utf8_lossy(data))
+                .collect();
+            parse_txt(&record)
+                .inspect_err(|err| tracing::warn!("Invalid gateway record at {name}: {err}"))
+                .ok()
+                .flatten()
+        })
+        .collect())
+}
+
+async fn from_dns(
+    resolver: &TokioAsyncResolver,
+    domain: &str,
+    signer: &RsaPublicKey,
+) -> anyhow::Result<Vec<DiscoveredGateway>> {
+    let domain = domain.trim_end_matches('.');
+    let txt = txt_records(resolver, &format!("{domain}.")).await;
+    let srv = resolver.srv_lookup(format!("{SRV_PREFIX}.{domain}.")).await;
+    if let (Err(err), Err(_)) = (&txt, &srv) {
+        anyhow::bail!("{err}");
+    }
+
+    let mut gateways: Vec<_> = txt
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|record| {
+            let address = record.address()?;
+            if let Err(err) = record.verify(record.addr.as_deref()?, signer) {
+                tracing::warn!("Ignoring gateway record at {domain}: {err}");
+                return None;
+            }
+            Some(DiscoveredGateway {
+                address,
+                alternative_addresses: vec![],
+                public_key: record.public_key,
+            })
+        })
+        .collect();
+
+    if let Ok(srv) = srv {
+        let mut records: Vec<_> = srv.iter().collect();
+        records.sort_by_key(|record| (record.priority(), std::cmp::Reverse(record.weight())));
+        for record in records {
+            let target = record.target().to_utf8();
+            let addr = format!("{}:{}", target.trim_end_matches('.'), record.port());
+            let Some(key_record) = txt_records(resolver, &target)
+                .await
+                .ok()
+                .and_then(|records| records.into_iter().next())
+            else {
+                tracing::warn!("No gateway public key record found at {target}");
+                continue;
+            };
+            if let Err(err) = key_record.verify(&addr, signer) {
+                tracing::warn!("Ignoring gateway record at {target}: {err}");
+                continue;
+            }
+            gateways.push(DiscoveredGateway {
+                address: Address::Hostname(addr),
+                alternative_addresses: vec![],
+                public_key: key_record.public_key,
+            });
+        }
+    }
+    Ok(gateways)
+}
+
+#[derive(Deserialize)]
+struct BootstrapFile {
+    gateways: Vec<BootstrapFileEntry>,
+}
+
+#[derive(Deserialize)]
+struct BootstrapFileEntry {
+    address: Address,
+    /// Public key of the gateway in PEM format.
+    public_key: String,
+    #[serde(default)]
+    alternative_addresses: Vec<Address>,
+}
+
+/// Client fetching bootstrap files, which gives up on slow sources and never follows redirects
+/// to plain HTTP.
+fn http_client() -> anyhow::Result<reqwest::Client> {
+    let redirects = reqwest::redirect::Policy::custom(|attempt| {
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            attempt.error("too many redirects")
+        } else if let Err(err) = check_scheme(attempt.url()) {
+            attempt.error(err.to_string())
+        } else {
+            attempt.follow()
+        }
+    });
+    Ok(reqwest::Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(FETCH_TIMEOUT)
+        .redirect(redirects)
+        .build()?)
+}
+
+async fn from_signed_file(
+    client: &reqwest::Client,
+    url: &str,
+    signer: &RsaPublicKey,
+) -> anyhow::Result<Vec<DiscoveredGateway>> {
+    let url = reqwest::Url::parse(url)?;
+    check_scheme(&url)?;
+    let content = fetch(client, url.clone(), MAX_FILE_SIZE).await?;
+    let signature_url = reqwest::Url::parse(&format!("{url}.sig"))?;
+    let signature = fetch(client, signature_url, MAX_SIGNATURE_SIZE).await?;
+    parse_signed_file(&content, &signature, signer)
+}
+
+/// Fetches a response of at most `max_size` bytes.
+async fn fetch(
+    client: &reqwest::Client,
+    url: reqwest::Url,
+    max_size: usize,
+) -> anyhow::Result<Vec<u8>> {
+    let mut response = client.get(url).send().await?.error_for_status()?;
+    if response
+        .content_length()
+        .is_some_and(|size| size > max_size as u64)
+    {
+        anyhow::bail!("response larger than {max_size} bytes");
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > max_size {
+            anyhow::bail!("response larger than {max_size} bytes");
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// Only allows fetching bootstrap files over HTTPS, or plain HTTP from the loopback interface.
+fn check_scheme(url: &reqwest::Url) -> anyhow::Result<()> {
+    let is_loopback = url.host_str().is_some_and(|host| {
+        host == "localhost"
+            || host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse::<IpAddr>()
+                .is_ok_and(|ip| ip.is_loopback())
+    });
+    match url.scheme() {
+        "https" => Ok(()),
+        "http" if is_loopback => Ok(()),
+        scheme => anyhow::bail!("bootstrap files must be served over HTTPS, not {scheme}"),
+    }
+}
+
+fn parse_signed_file(
+    content: &[u8],
+    signature: &[u8],
+    signer: &RsaPublicKey,
+) -> anyhow::Result<Vec<DiscoveredGateway>> {
+    signer
+        .verify(
+            Pkcs1v15Sign::new_unprefixed(),
+            blake3::hash(content).as_bytes(),
+            signature,
+        )
+        .map_err(|_| anyhow::anyhow!("invalid bootstrap file signature"))?;
+    let file: BootstrapFile = toml::from_str(std::str::from_utf8(content)?)?;
+    file.gateways
+        .into_iter()
+        .map(|entry| {
+            let public_key = RsaPublicKey::from_public_key_pem(&entry.public_key)
+                .map_err(|err| anyhow::anyhow!("invalid gateway public key: {err}"))?;
+            Ok(DiscoveredGateway {
+                address: entry.address,
+                alternative_addresses: entry.alternative_addresses,
+                public_key,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use httptest::{matchers::*, responders::*, Expectation, Server};
+    use rsa::RsaPrivateKey;
+
+    use super::*;
+
+    fn key() -> RsaPrivateKey {
+        RsaPrivateKey::new(&mut rand::thread_rng(), 512).unwrap()
+    }
+
+    #[test]
+    fn gateway_txt_records() {
+        let signer = key();
+        let key = key().to_public_key();
+        let der = bs58::encode(key.to_public_key_der().unwrap().as_bytes()).into_string();
+        let sign = |addr: &str| {
+            let signed = signed_record(addr, &der);
+            let signature = signer
+                .sign(
+                    Pkcs1v15Sign::new_unprefixed(),
+                    blake3::hash(signed.as_bytes()).as_bytes(),
+                )
+                .unwrap();
+            bs58::encode(signature).into_string()
+        };
+
+        let sig = sign("10.0.0.1:31337");
+        let record = parse_txt(&format!(
+            "freenet-gw addr=10.0.0.1:31337 key={der} sig={sig}"
+        ))
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            record.address(),
+            Some(Address::HostAddress(([10, 0, 0, 1], 31337).into()))
+        );
+        assert_eq!(record.public_key, key);
+        let signer_key = signer.to_public_key();
+        record.verify("10.0.0.1:31337", &signer_key).unwrap();
+        // the signature binds the key to the address
+        assert!(record.verify("10.0.0.2:31337", &signer_key).is_err());
+        assert!(record
+            .verify("10.0.0.1:31337", &self::key().to_public_key())
+            .is_err());
+
+        // records of SRV targets are signed for the address of the SRV record
+        let sig = sign("gw.example.org:31337");
+        let record = parse_txt(&format!("freenet-gw key={der} sig={sig}"))
+            .unwrap()
+            .unwrap();
+        assert!(record.address().is_none());
+        record.verify("gw.example.org:31337", &signer_key).unwrap();
+
+        let unsigned = parse_txt(&format!("freenet-gw addr=10.0.0.1:31337 key={der}"))
+            .unwrap()
+            .unwrap();
+        assert!(unsigned.verify("10.0.0.1:31337", &signer_key).is_err());
+
+        assert!(parse_txt("v=spf1 -all").unwrap().is_none());
+        assert!(parse_txt("freenet-gw addr=gw.example.org:31337").is_err());
+    }
+
+    #[test]
+    fn bootstrap_url_scheme() {
+        let check = |url: &str| check_scheme(&reqwest::Url::parse(url).unwrap());
+        assert!(check("https://bootstrap.example.org/gateways.toml").is_ok());
+        assert!(check("http://127.0.0.1:8080/gateways.toml").is_ok());
+        assert!(check("http://localhost/gateways.toml").is_ok());
+        assert!(check("http://[::1]/gateways.toml").is_ok());
+        assert!(check("http://bootstrap.example.org/gateways.toml").is_err());
+        assert!(check("ftp://bootstrap.example.org/gateways.toml").is_err());
+    }
+
+    #[tokio::test]
+    async fn signed_file_and_cache() -> anyhow::Result<()> {
+        let signer = key();
+        let gateway_key = key().to_public_key().to_public_key_pem(LineEnding::LF)?;
+        let content = format!(
+            r#"
+            [[gateways]]
+            address = {{ hostname = "gw.example.org:31337" }}
+            public_key = """{gateway_key}"""
+            "#
+        );
+        let signature = signer.sign(
+            Pkcs1v15Sign::new_unprefixed(),
+            blake3::hash(content.as_bytes()).as_bytes(),
+        )?;
+
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::path("/bootstrap.toml"))
+                .respond_with(status_code(200).body(content.clone())),
+        );
+        server.expect(
+            Expectation::matching(request::path("/bootstrap.toml.sig"))
+                .respond_with(status_code(200).body(signature.clone())),
+        );
+
+        let dir = tempfile::tempdir()?;
+        let signer_path = dir.path().join("signer.pem");
+        fs::write(
+            &signer_path,
+            signer.to_public_key().to_public_key_pem(LineEnding::LF)?,
+        )?;
+        let mut config = BootstrapConfig {
+            dns: vec![],
+            urls: vec![server.url_str("/bootstrap.toml")],
+            signer: Some(signer_path),
+        };
+        let pub_keys_dir = dir.path().join("keys");
+        let gateways = discover(&config, dir.path(), &pub_keys_dir).await;
+        assert_eq!(gateways.gateways.len(), 1);
+        assert_eq!(
+            gateways.gateways[0].address,
+            Address::Hostname("gw.example.org:31337".to_owned())
+        );
+        assert!(gateways.gateways[0].public_key_path.exists());
+
+        // tampered files are rejected
+        let mut tampered = content.clone().into_bytes();
+        tampered.push(b'\n');
+        assert!(parse_signed_file(&tampered, &signature, &signer.to_public_key()).is_err());
+
+        // with the source unreachable the last discovered gateways are used
+        config.urls = vec![server.url_str("/missing.toml")];
+        server.expect(
+            Expectation::matching(request::path("/missing.toml")).respond_with(status_code(404)),
+        );
+        let cached = discover(&config, dir.path(), &pub_keys_dir).await;
+        assert_eq!(cached.gateways, gateways.gateways);
+
+        // nor do oversized files
+        config.urls = vec![server.url_str("/huge.toml")];
+        server.expect(
+            Expectation::matching(request::path("/huge.toml"))
+                .respond_with(status_code(200).body(vec![b'#'; MAX_FILE_SIZE + 1])),
+        );
+        let cached = discover(&config, dir.path(), &pub_keys_dir).await;
+        assert_eq!(cached.gateways, gateways.gateways);
+
+        // nor does a missing signer prevent using them
+        config.signer = Some(dir.path().join("missing.pem"));
+        let cached = discover(&config, dir.path(), &pub_keys_dir).await;
+        assert_eq!(cached.gateways, gateways.gateways);
+        Ok(())
+    }
+}
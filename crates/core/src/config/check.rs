@@ -0,0 +1,552 @@
+//! Validation of configuration files, reporting every problem found at once along with the line
+//! it is at, as done by `freenet config check`.
+//!
+//! TOML files are checked against the schema of the settings first: unknown settings, which are
+//! otherwise ignored, and values of the wrong type or out of range. The schema is derived from
+//! [`Config`], so it can't miss a setting. The settings which are well formed are then checked for
+//! consistency, e.g. that gateways have a public address.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+};
+
+use schemars::{
+    gen::{SchemaGenerator, SchemaSettings},
+    schema::{InstanceType, Schema, SchemaObject, SingleOrVec},
+};
+use toml::Spanned;
+
+use super::*;
+
+/// A problem found in a configuration file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigDiagnostic {
+    /// Line of the file the problem is at, if it can be tied to one.
+    pub line: Option<usize>,
+    /// The setting with the problem, if any.
+    pub key: Option<String>,
+    pub message: String,
+}
+
+impl Display for ConfigDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "line {line}: ")?;
+        }
+        if let Some(key) = &self.key {
+            write!(f, "{key}: ")?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Kind {
+    Bool,
+    Integer {
+        max: u64,
+    },
+    Float {
+        min: f64,
+        max: f64,
+    },
+    String,
+    IpAddr,
+    OneOf(Vec<String>),
+    LogLevel,
+    List(Box<Kind>),
+    Table,
+    /// Settings whose schema doesn't tell what they can be set to.
+    Any,
+}
+
+/// Format of the schema of log levels, which are matched case insensitively.
+const LOG_LEVEL_FORMAT: &str = "log-level";
+
+/// Every setting which can be set in a configuration file, derived from the schema of [`Config`].
+static SCHEMA: Lazy<BTreeMap<String, Kind>> = Lazy::new(|| {
+    let generator = SchemaSettings::draft07()
+        .with(|settings| settings.inline_subschemas = true)
+        .into_generator();
+    generator
+        .into_root_schema_for::<Config>()
+        .schema
+        .object
+        .map(|object| {
+            object
+                .properties
+                .iter()
+                .map(|(name, schema)| (name.clone(), Kind::of(schema)))
+                .collect()
+        })
+        .unwrap_or_default()
+});
+
+/// Schema of log levels, set in [`Config`] through `serde_log_level_filter`.
+pub(super) fn log_level_schema(_: &mut SchemaGenerator) -> Schema {
+    SchemaObject {
+        instance_type: Some(InstanceType::String.into()),
+        format: Some(LOG_LEVEL_FORMAT.to_owned()),
+        ..Default::default()
+    }
+    .into()
+}
+
+impl Kind {
+    fn of(schema: &Schema) -> Kind {
+        let Schema::Object(schema) = schema else {
+            return Kind::Any;
+        };
+        if schema.format.as_deref() == Some(LOG_LEVEL_FORMAT) {
+            return Kind::LogLevel;
+        }
+        if let Some(values) = &schema.enum_values {
+            return Kind::OneOf(
+                values
+                    .iter()
+                    .filter_map(|value| value.as_str().map(str::to_owned))
+                    .collect(),
+            );
+        }
+        if let Some(subschemas) = &schema.subschemas {
+            // optional values, and enums with documented variants, each variant being a schema
+            let mut kinds = subschemas
+                .one_of
+                .iter()
+                .chain(&subschemas.any_of)
+                .flatten()
+                .filter(|schema| !is_null(schema))
+                .map(Kind::of);
+            let Some(first) = kinds.next() else {
+                return Kind::Any;
+            };
+            return kinds
+                .try_fold(first, |kind, other| match (kind, other) {
+                    (Kind::OneOf(mut options), Kind::OneOf(other)) => {
+                        options.extend(other);
+                        Some(Kind::OneOf(options))
+                    }
+                    _ => None,
+                })
+                .unwrap_or(Kind::Any);
+        }
+        let instance_type = match &schema.instance_type {
+            Some(SingleOrVec::Single(instance_type)) => Some(**instance_type),
+            Some(SingleOrVec::Vec(types)) => types
+                .iter()
+                .copied()
+                .find(|instance_type| *instance_type != InstanceType::Null),
+            None => None,
+        };
+        match instance_type {
+            Some(InstanceType::Boolean) => Kind::Bool,
+            Some(InstanceType::Integer) => Kind::Integer {
+                max: match schema.format.as_deref() {
+                    Some("uint8") => u8::MAX as u64,
+                    Some("uint16") => u16::MAX as u64,
+                    Some("uint32") => u32::MAX as u64,
+                    // the largest integer of TOML files
+                    _ => i64::MAX as u64,
+                },
+            },
+            Some(InstanceType::Number) => {
+                let range = schema.number.as_deref();
+                Kind::Float {
+                    min: range.and_then(|range| range.minimum).unwrap_or(f64::MIN),
+                    max: range.and_then(|range| range.maximum).unwrap_or(f64::MAX),
+                }
+            }
+            Some(InstanceType::String) if schema.format.as_deref() == Some("ip") => Kind::IpAddr,
+            Some(InstanceType::String) => Kind::String,
+            Some(InstanceType::Array) => {
+                let items = schema
+                    .array
+                    .as_deref()
+                    .and_then(|array| match &array.items {
+                        Some(SingleOrVec::Single(items)) => Some(Kind::of(items)),
+                        _ => None,
+                    });
+                Kind::List(Box::new(items.unwrap_or(Kind::Any)))
+            }
+            Some(InstanceType::Object) => Kind::Table,
+            _ => Kind::Any,
+        }
+    }
+
+    fn check(&self, value: &toml::Value) -> Result<(), String> {
+        match (self, value) {
+            (Kind::Any, _) => Ok(()),
+            (Kind::Bool, toml::Value::Boolean(_)) => Ok(()),
+            (Kind::Integer { max }, toml::Value::Integer(int)) => {
+                if *int < 0 || *int as u64 > *max {
+                    Err(format!("expected a number between 0 and {max}, got {int}"))
+                } else {
+                    Ok(())
+                }
+            }
+            (Kind::Float { min, max }, toml::Value::Float(_) | toml::Value::Integer(_)) => {
+                let float = value
+                    .as_float()
+                    .unwrap_or_else(|| value.as_integer().unwrap() as f64);
+                if float < *min || float > *max {
+                    Err(format!(
+                        "expected a number between {min} and {max}, got {float}"
+                    ))
+                } else {
+                    Ok(())
+                }
+            }
+            (Kind::String, toml::Value::String(_)) => Ok(()),
+            (Kind::IpAddr, toml::Value::String(addr)) => addr
+                .parse::<IpAddr>()
+                .map(|_| ())
+                .map_err(|_| format!("`{addr}` is not an IP address")),
+            (Kind::OneOf(options), toml::Value::String(option)) => {
+                if options.contains(option) {
+                    Ok(())
+                } else {
+                    Err(format!("`{option}` is not one of: {}", options.join(", ")))
+                }
+            }
+            (Kind::LogLevel, toml::Value::String(level)) => {
+                const LEVELS: &[&str] = &["off", "error", "warn", "info", "debug", "trace"];
+                if LEVELS.iter().any(|l| l.eq_ignore_ascii_case(level.trim())) {
+                    Ok(())
+                } else {
+                    Err(format!("`{level}` is not one of: {}", LEVELS.join(", ")))
+                }
+            }
+            (Kind::Table, toml::Value::Table(_)) => Ok(()),
+            (Kind::List(kind), toml::Value::Array(values)) => {
+                values.iter().enumerate().try_for_each(|(i, value)| {
+                    kind.check(value).map_err(|err| format!("item {i}: {err}"))
+                })
+            }
+            (kind, value) => Err(format!(
+                "expected {}, got {}",
+                kind.describe(),
+                value.type_str()
+            )),
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            Kind::Bool => "a boolean",
+            Kind::Integer { .. } => "an integer",
+            Kind::Float { .. } => "a float",
+            Kind::String | Kind::OneOf(_) | Kind::LogLevel => "a string",
+            Kind::IpAddr => "an IP address",
+            Kind::List(_) => "a list",
+            Kind::Table => "a table",
+            Kind::Any => "any value",
+        }
+    }
+}
+
+/// Checks the configuration file, returning every problem found, if any.
+pub fn check_config_file(path: &Path) -> std::io::Result<Vec<ConfigDiagnostic>> {
+    let content = fs::read_to_string(path)?;
+    let diagnostics = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => check_json(&content),
+        _ => check_toml(&content),
+    };
+    Ok(diagnostics)
+}
+
+fn check_toml(content: &str) -> Vec<ConfigDiagnostic> {
+    let line_of = |offset: usize| content[..offset.min(content.len())].matches('\n').count() + 1;
+    let document = match toml::from_str::<BTreeMap<Spanned<String>, Spanned<toml::Value>>>(content)
+    {
+        Ok(document) => document,
+        Err(err) => {
+            return vec![ConfigDiagnostic {
+                line: err.span().map(|span| line_of(span.start)),
+                key: None,
+                message: err.message().to_owned(),
+            }]
+        }
+    };
+    let key_lines: HashMap<&str, usize> = document
+        .keys()
+        .map(|key| (key.get_ref().as_str(), line_of(key.span().start)))
+        .collect();
+
+    let mut diagnostics = vec![];
+    let mut malformed = false;
+    for (key, value) in &document {
+        let (line, message) = match SCHEMA.get(key.get_ref()) {
+            Some(kind) => match kind.check(value.get_ref()) {
+                Ok(()) => continue,
+                Err(message) => {
+                    malformed = true;
+                    (line_of(value.span().start), message)
+                }
+            },
+            None => (line_of(key.span().start), "unknown setting".to_owned()),
+        };
+        diagnostics.push(ConfigDiagnostic {
+            line: Some(line),
+            key: Some(key.get_ref().clone()),
+            message,
+        });
+    }
+
+    // the consistency checks need the settings to be well formed
+    if !malformed {
+        match toml::from_str::<Config>(content) {
+            Ok(config) => diagnostics.extend(check_consistency(&config).into_iter().map(
+                |(key, message)| ConfigDiagnostic {
+                    line: key_lines.get(key).copied(),
+                    key: Some(key.to_owned()),
+                    message,
+                },
+            )),
+            Err(err) => diagnostics.push(ConfigDiagnostic {
+                line: err.span().map(|span| line_of(span.start)),
+                key: None,
+                message: err.message().to_owned(),
+            }),
+        }
+    }
+    diagnostics.sort_by_key(|d| d.line);
+    diagnostics
+}
+
+fn check_json(content: &str) -> Vec<ConfigDiagnostic> {
+    match serde_json::from_str::<Config>(content) {
+        Ok(config) => check_consistency(&config)
+            .into_iter()
+            .map(|(key, message)| ConfigDiagnostic {
+                line: None,
+                key: Some(key.to_owned()),
+                message,
+            })
+            .collect(),
+        Err(err) => vec![ConfigDiagnostic {
+            line: Some(err.line()),
+            key: None,
+            message: err.to_string(),
+        }],
+    }
+}
+
+/// Problems with the combination of settings, along with the key of the setting at fault.
+fn check_consistency(config: &Config) -> Vec<(&'static str, String)> {
+    let mut problems = RuntimeSettings::from_config(config).problems();
+    if config.is_gateway && config.network_api.public_address.is_none() {
+        problems.push((
+            "is_gateway",
+            "gateways must set their public network address (public_network_address)".into(),
+        ));
+    }
+    if config.is_gateway && !config.role().is_gateway() {
+        problems.push((
+            "role",
+            format!("gateways can't have the {} role", config.role()),
+        ));
+    }
+    if config.ws_api.tls_certificate.is_some() != config.ws_api.tls_private_key.is_some() {
+        let missing = if config.ws_api.tls_certificate.is_some() {
+            "tls-private-key"
+        } else {
+            "tls-certificate"
+        };
+        problems.push((
+            missing,
+            "TLS requires both a certificate and a private key".into(),
+        ));
+    }
+    if config.replication_factor == 0 {
+        problems.push((
+            "replication-factor",
+            "the replication factor must be greater than 0".into(),
+        ));
+    }
+    if config.contract_time_epoch == 0 {
+        problems.push((
+            "contract-time-epoch",
+            "the contract time epoch must be greater than 0".into(),
+        ));
+    }
+    if config.snapshots.interval.is_some() && config.snapshots.keep == 0 {
+        problems.push((
+            "snapshots-to-keep",
+            "at least one snapshot must be kept when taking them".into(),
+        ));
+    }
+    let files = [
+        (
+            "transport_keypair",
+            config.secrets.transport_keypair_path.as_ref(),
+        ),
+        ("nonce", config.secrets.nonce_path.as_ref()),
+        ("cipher", config.secrets.cipher_path.as_ref()),
+        ("tls-certificate", config.ws_api.tls_certificate.as_ref()),
+        ("tls-private-key", config.ws_api.tls_private_key.as_ref()),
+        ("bootstrap-signer", config.bootstrap.signer.as_ref()),
+    ]
+    .into_iter()
+    .chain(
+        config
+            .contract_policy
+            .trusted_publishers
+            .iter()
+            .map(|path| ("trusted-publishers", Some(path))),
+    );
+    for (key, path) in files {
+        // certificates obtained through ACME may not exist yet
+        let acme_certificate = config.ws_api.acme.is_some() && key.starts_with("tls-");
+        if let Some(path) = path.filter(|path| !path.exists() && !acme_certificate) {
+            problems.push((key, format!("file {} not found", path.display())));
+        }
+    }
+    problems
+}
+
+fn is_null(schema: &Schema) -> bool {
+    matches!(
+        schema,
+        Schema::Object(SchemaObject {
+            instance_type: Some(SingleOrVec::Single(instance_type)),
+            ..
+        }) if **instance_type == InstanceType::Null
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schema_from_config() {
+        let kind = |key: &str| SCHEMA.get(key).cloned();
+        assert_eq!(
+            kind("network-port"),
+            Some(Kind::Integer {
+                max: u16::MAX as u64
+            })
+        );
+        assert_eq!(
+            kind("max-storage-size"),
+            Some(Kind::Integer {
+                max: i64::MAX as u64
+            })
+        );
+        assert_eq!(kind("log_level"), Some(Kind::LogLevel));
+        assert_eq!(
+            kind("role"),
+            Some(Kind::OneOf(vec![
+                "gateway".to_owned(),
+                "peer".to_owned(),
+                "light".to_owned()
+            ]))
+        );
+        assert_eq!(
+            kind("access-log-sample-rate"),
+            Some(Kind::Float { min: 0.0, max: 1.0 })
+        );
+        assert_eq!(kind("public_network_address"), Some(Kind::IpAddr));
+        assert_eq!(
+            kind("cors-allowed-origins"),
+            Some(Kind::List(Box::new(Kind::String)))
+        );
+        assert_eq!(
+            kind("virtual-hosts"),
+            Some(Kind::List(Box::new(Kind::Table)))
+        );
+        assert_eq!(kind("cors-allow-credentials"), Some(Kind::Bool));
+        // settings which can't be set in files
+        assert_eq!(kind("peer_id"), None);
+        assert_eq!(kind("ignore_protocol_version"), None);
+    }
+
+    #[test]
+    fn report_every_problem() {
+        let content = r#"
+mode = "network"
+network-port = 70000
+max-conections = 10
+log_level = "loud"
+webrtc = true
+"#;
+        let diagnostics = check_toml(content);
+        let found = diagnostics
+            .iter()
+            .map(|d| (d.line, d.key.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                (Some(3), Some("network-port")),
+                (Some(4), Some("max-conections")),
+                (Some(5), Some("log_level")),
+            ]
+        );
+
+        let diagnostics = check_toml("mode = \"network\"\nnetwork-port = \n");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line, Some(2));
+    }
+
+    #[test]
+    fn consistency() {
+        let content = r#"
+is_gateway = true
+min-connections = 20
+max-connections = 10
+tls-certificate = "/nonexistent/cert.pem"
+"#;
+        let diagnostics = check_toml(content);
+        let found = diagnostics
+            .iter()
+            .map(|d| (d.line, d.key.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                (None, Some("tls-private-key")),
+                (Some(2), Some("is_gateway")),
+                (Some(3), Some("min-connections")),
+                (Some(5), Some("tls-certificate")),
+            ]
+        );
+        assert!(check_toml("mode = \"local\"\nlog_level = \"warn\"\n").is_empty());
+    }
+
+    #[test]
+    fn derive_role_of_legacy_gateways() {
+        let legacy = "is_gateway = true\npublic_network_address = \"1.2.3.4\"\n";
+        assert!(check_toml(legacy).is_empty());
+        let config: Config = toml::from_str(legacy).unwrap();
+        assert_eq!(config.role(), NodeRole::Gateway);
+
+        let diagnostics = check_toml(&format!("{legacy}role = \"light\"\n"));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].key.as_deref(), Some("role"));
+        assert_eq!(diagnostics[0].line, Some(3));
+    }
+
+    #[tokio::test]
+    async fn schema_covers_every_setting() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = ConfigArgs {
+            mode: Some(OperationMode::Local),
+            network_api: NetworkArgs {
+                skip_load_from_network: true,
+                bandwidth_limit: Some(1024),
+                ..Default::default()
+            },
+            config_paths: ConfigPathsArgs {
+                config_dir: Some(dir.path().to_path_buf()),
+                data_dir: Some(dir.path().to_path_buf()),
+            },
+            ..Default::default()
+        };
+        let config = args.build().await.unwrap();
+        let serialized = toml::to_string(&config).unwrap();
+        let diagnostics = check_toml(&serialized);
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+    }
+}
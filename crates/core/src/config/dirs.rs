@@ -0,0 +1,151 @@
+//! Platform specific locations of the node directories.
+//!
+//! - Linux and other unixes: the XDG base directories, e.g. `~/.config/freenet`,
+//!   `~/.local/share/freenet` and `~/.cache/freenet`, or `/etc/freenet`, `/var/lib/freenet` and
+//!   `/var/cache/freenet` for users without a home directory, such as system users.
+//! - macOS: `~/Library/Application Support/Freenet`, and `~/Library/Caches/Freenet` for the cache.
+//! - Windows: `%ProgramData%\Freenet`, shared by every user so the node can run as a service.
+//!
+//! Previous versions used the per user directories of macOS and Windows, e.g.
+//! `%LocalAppData%\The Freenet Project Inc\Freenet`. Those are moved to the current ones the
+//! first time the node runs, or kept being used if they can't be moved.
+
+use super::*;
+
+const QUALIFIER: &str = "";
+const ORGANIZATION: &str = "The Freenet Project Inc";
+
+/// Default configuration, data and cache directories of the node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeDirs {
+    config_dir: PathBuf,
+    data_dir: PathBuf,
+    cache_dir: PathBuf,
+}
+
+impl NodeDirs {
+    #[cfg(target_os = "macos")]
+    pub fn resolve() -> std::io::Result<Self> {
+        let base = directories::BaseDirs::new().ok_or(std::io::ErrorKind::NotFound)?;
+        let data_dir = base.data_dir().join(APPLICATION);
+        Ok(Self {
+            config_dir: data_dir.clone(),
+            data_dir,
+            cache_dir: base.cache_dir().join(APPLICATION),
+        }
+        .migrate())
+    }
+
+    #[cfg(windows)]
+    pub fn resolve() -> std::io::Result<Self> {
+        let root = std::env::var_os("ProgramData")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(r"C:\ProgramData"))
+            .join(APPLICATION);
+        Ok(Self {
+            config_dir: root.join("config"),
+            data_dir: root.join("data"),
+            cache_dir: root.join("cache"),
+        }
+        .migrate())
+    }
+
+    #[cfg(not(any(target_os = "macos", windows)))]
+    pub fn resolve() -> std::io::Result<Self> {
+        match directories::ProjectDirs::from(QUALIFIER, ORGANIZATION, APPLICATION) {
+            Some(dirs) => Ok(Self {
+                config_dir: dirs.config_dir().to_path_buf(),
+                data_dir: dirs.data_dir().to_path_buf(),
+                cache_dir: dirs.cache_dir().to_path_buf(),
+            }),
+            None => {
+                let name = APPLICATION.to_lowercase();
+                Ok(Self {
+                    config_dir: Path::new("/etc").join(&name),
+                    data_dir: Path::new("/var/lib").join(&name),
+                    cache_dir: Path::new("/var/cache").join(name),
+                })
+            }
+        }
+    }
+
+    /// Moves the directories of previous versions to these ones, unless the node already ran with
+    /// them. The cache is left behind, as it can be rebuilt.
+    #[cfg(any(target_os = "macos", windows))]
+    fn migrate(self) -> Self {
+        let Some(legacy) = directories::ProjectDirs::from(QUALIFIER, ORGANIZATION, APPLICATION)
+        else {
+            return self;
+        };
+        Self {
+            data_dir: migrate_dir(legacy.data_local_dir(), self.data_dir),
+            config_dir: migrate_dir(legacy.config_local_dir(), self.config_dir),
+            cache_dir: self.cache_dir,
+        }
+    }
+
+    pub fn config_dir(&self) -> &Path {
+        &self.config_dir
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    pub fn cache_dir(&self) -> &Path {
+        &self.cache_dir
+    }
+}
+
+/// Directory web apps are unpacked to by gateways not built from the node configuration.
+pub(crate) fn default_webs_dir() -> PathBuf {
+    std::env::temp_dir()
+        .join(APPLICATION.to_lowercase())
+        .join("webs")
+}
+
+/// `dir`, after moving the `legacy` one to it if it doesn't exist yet. If it can't be moved, the
+/// legacy one is returned so the node keeps its identity and contracts.
+#[cfg(any(target_os = "macos", windows))]
+fn migrate_dir(legacy: &Path, dir: PathBuf) -> PathBuf {
+    if dir.exists() || !legacy.exists() {
+        return dir;
+    }
+    let moved = dir
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| fs::rename(legacy, &dir));
+    match moved {
+        Ok(()) => {
+            tracing::info!(from = %legacy.display(), to = %dir.display(), "Moved the directory of a previous version");
+            dir
+        }
+        Err(error) => {
+            tracing::warn!(%error, dir = %legacy.display(), "Failed moving the directory of a previous version, using it in place");
+            legacy.to_path_buf()
+        }
+    }
+}
+
+#[cfg(all(test, any(target_os = "macos", windows)))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrate_legacy_dirs() {
+        let root = tempfile::tempdir().unwrap();
+        let legacy = root.path().join("legacy");
+        fs::create_dir_all(&legacy).unwrap();
+        fs::write(legacy.join("config.toml"), "mode = \"network\"").unwrap();
+
+        let dir = migrate_dir(&legacy, root.path().join("current").join("config"));
+        assert_eq!(dir, root.path().join("current").join("config"));
+        assert!(dir.join("config.toml").exists());
+        assert!(!legacy.exists());
+
+        // the current directory is kept once the node ran with it
+        fs::create_dir_all(&legacy).unwrap();
+        assert_eq!(migrate_dir(&legacy, dir.clone()), dir);
+        assert!(legacy.exists());
+    }
+}
@@ -0,0 +1,525 @@
+//! Settings which can be changed while the node runs, without dropping the connections to other
+//! peers nor to clients.
+//!
+//! A reload re-reads the configuration file, when the node gets a SIGHUP or through the admin
+//! API, which can also change the settings directly. The new settings are validated as a whole
+//! and either applied entirely or rejected, leaving the running ones untouched. Any other setting
+//! changed in the configuration file requires a restart.
+
+use parking_lot::Mutex;
+use serde::Deserializer;
+use tokio::sync::watch;
+
+use super::*;
+use crate::{ring::ConnectionManager, transport::BandwidthLimit};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReloadError {
+    #[error("invalid settings: {0}")]
+    Invalid(String),
+    #[error("no configuration file found in {0}")]
+    MissingConfig(PathBuf),
+    #[error("failed reading the configuration: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("failed changing the log level: {0}")]
+    LogLevel(anyhow::Error),
+}
+
+/// The runtime-tunable settings of the node.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuntimeSettings {
+    #[serde(with = "serde_log_level_filter")]
+    pub log_level: tracing::log::LevelFilter,
+    /// Upstream bandwidth limit, in bytes, unlimited if not set.
+    pub bandwidth_limit: Option<usize>,
+    /// Storage budget of the cached contracts, in bytes, unlimited if not set.
+    pub max_storage_size: Option<u64>,
+    /// Bytes of contract states kept in memory.
+    pub state_cache_size: u64,
+    /// Bytes of contract and delegate code kept in memory.
+    pub code_cache_size: u64,
+    pub min_connections: usize,
+    pub max_connections: usize,
+    /// Whether the requests to the HTTP gateway are logged.
+    pub access_log: bool,
+    /// Share of the gateway requests logged, from 0 to 1.
+    pub access_log_sample_rate: f64,
+}
+
+impl RuntimeSettings {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            log_level: config.log_level,
+            bandwidth_limit: config.network_api.bandwidth_limit,
+            max_storage_size: config.storage_budget.max_storage_size,
+            state_cache_size: config.storage_budget.state_cache_size,
+            code_cache_size: config.storage_budget.code_cache_size,
+            min_connections: config
+                .network_api
+                .min_connections
+                .unwrap_or_else(|| default_min_connections(config.role())),
+            max_connections: config
+                .network_api
+                .max_connections
+                .unwrap_or_else(|| default_max_connections(config.role())),
+            access_log: config.ws_api.access_log.enabled,
+            access_log_sample_rate: config.ws_api.access_log.sample_rate,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ReloadError> {
+        match self.problems().into_iter().next() {
+            Some((_, problem)) => Err(ReloadError::Invalid(problem)),
+            None => Ok(()),
+        }
+    }
+
+    /// Every invalid setting, along with its key in configuration files.
+    pub(crate) fn problems(&self) -> Vec<(&'static str, String)> {
+        let mut problems = vec![];
+        if self.bandwidth_limit == Some(0) {
+            problems.push((
+                "bandwidth_limit",
+                "the bandwidth limit must be greater than 0, unset it to disable it".into(),
+            ));
+        }
+        if self.max_storage_size == Some(0) {
+            problems.push((
+                "max-storage-size",
+                "the storage budget must be greater than 0, unset it to disable it".into(),
+            ));
+        }
+        if self.state_cache_size == 0 {
+            problems.push((
+                "state-cache-size",
+                "the state cache size must be greater than 0".into(),
+            ));
+        }
+        if self.code_cache_size == 0 {
+            problems.push((
+                "code-cache-size",
+                "the code cache size must be greater than 0".into(),
+            ));
+        }
+        if self.max_connections == 0 {
+            problems.push((
+                "max-connections",
+                "the maximum number of connections must be greater than 0".into(),
+            ));
+        }
+        if self.min_connections > self.max_connections {
+            problems.push((
+                "min-connections",
+                format!(
+                    "the minimum number of connections ({}) is over the maximum ({})",
+                    self.min_connections, self.max_connections
+                ),
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.access_log_sample_rate) {
+            problems.push((
+                "access-log-sample-rate",
+                format!(
+                    "the access log sample rate ({}) must be between 0 and 1",
+                    self.access_log_sample_rate
+                ),
+            ));
+        }
+        problems
+    }
+
+    /// The settings with the changes of the update.
+    pub fn updated(mut self, update: RuntimeSettingsUpdate) -> Self {
+        if let Some(log_level) = update.log_level {
+            self.log_level = log_level;
+        }
+        if let Some(limit) = update.bandwidth_limit {
+            self.bandwidth_limit = limit;
+        }
+        if let Some(max_size) = update.max_storage_size {
+            self.max_storage_size = max_size;
+        }
+        if let Some(size) = update.state_cache_size {
+            self.state_cache_size = size;
+        }
+        if let Some(size) = update.code_cache_size {
+            self.code_cache_size = size;
+        }
+        if let Some(min) = update.min_connections {
+            self.min_connections = min;
+        }
+        if let Some(max) = update.max_connections {
+            self.max_connections = max;
+        }
+        if let Some(enabled) = update.access_log {
+            self.access_log = enabled;
+        }
+        if let Some(rate) = update.access_log_sample_rate {
+            self.access_log_sample_rate = rate;
+        }
+        self
+    }
+}
+
+/// Changes to the runtime settings, the ones not set are left as they are.
+///
+/// The limits can be removed by setting them to `null`.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuntimeSettingsUpdate {
+    #[serde(default, deserialize_with = "optional_log_level")]
+    pub log_level: Option<tracing::log::LevelFilter>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub bandwidth_limit: Option<Option<usize>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub max_storage_size: Option<Option<u64>>,
+    #[serde(default)]
+    pub state_cache_size: Option<u64>,
+    #[serde(default)]
+    pub code_cache_size: Option<u64>,
+    #[serde(default)]
+    pub min_connections: Option<usize>,
+    #[serde(default)]
+    pub max_connections: Option<usize>,
+    #[serde(default)]
+    pub access_log: Option<bool>,
+    #[serde(default)]
+    pub access_log_sample_rate: Option<f64>,
+}
+
+impl From<RuntimeSettings> for RuntimeSettingsUpdate {
+    fn from(settings: RuntimeSettings) -> Self {
+        Self {
+            log_level: Some(settings.log_level),
+            bandwidth_limit: Some(settings.bandwidth_limit),
+            max_storage_size: Some(settings.max_storage_size),
+            state_cache_size: Some(settings.state_cache_size),
+            code_cache_size: Some(settings.code_cache_size),
+            min_connections: Some(settings.min_connections),
+            max_connections: Some(settings.max_connections),
+            access_log: Some(settings.access_log),
+            access_log_sample_rate: Some(settings.access_log_sample_rate),
+        }
+    }
+}
+
+fn optional_log_level<'de, D>(
+    deserializer: D,
+) -> Result<Option<tracing::log::LevelFilter>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|level| serde_log_level_filter::parse_log_level_str::<D>(&level))
+        .transpose()
+}
+
+/// Tells apart a field set to `null` from a missing one.
+fn nullable<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// The runtime settings of a node, and the handles to the components which use them.
+pub(crate) struct RuntimeConfig {
+    /// Also serializes the reloads, so they are applied one at a time.
+    current: Mutex<RuntimeSettings>,
+    config_dir: PathBuf,
+    bandwidth_limit: BandwidthLimit,
+    connections: ConnectionManager,
+    cache_sizes: watch::Sender<CacheSizes>,
+    access_log: watch::Sender<AccessLogConfig>,
+}
+
+/// Sizes of the memory caches of the contract handler, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct CacheSizes {
+    pub state: u64,
+    pub code: u64,
+}
+
+impl From<&RuntimeSettings> for CacheSizes {
+    fn from(settings: &RuntimeSettings) -> Self {
+        Self {
+            state: settings.state_cache_size,
+            code: settings.code_cache_size,
+        }
+    }
+}
+
+impl From<&RuntimeSettings> for AccessLogConfig {
+    fn from(settings: &RuntimeSettings) -> Self {
+        Self {
+            enabled: settings.access_log,
+            sample_rate: settings.access_log_sample_rate,
+        }
+    }
+}
+
+impl RuntimeConfig {
+    pub fn new(config: &Config, connections: ConnectionManager) -> Self {
+        let settings = RuntimeSettings {
+            // the connection limits may have been set when building the node
+            min_connections: connections.min_connections(),
+            max_connections: connections.max_connections(),
+            ..RuntimeSettings::from_config(config)
+        };
+        Self::with_settings(settings, config.config_dir(), connections)
+    }
+
+    fn with_settings(
+        settings: RuntimeSettings,
+        config_dir: PathBuf,
+        connections: ConnectionManager,
+    ) -> Self {
+        Self {
+            bandwidth_limit: BandwidthLimit::new(settings.bandwidth_limit),
+            cache_sizes: watch::channel(CacheSizes::from(&settings)).0,
+            access_log: watch::channel(AccessLogConfig::from(&settings)).0,
+            current: Mutex::new(settings),
+            config_dir,
+            connections,
+        }
+    }
+
+    pub fn settings(&self) -> RuntimeSettings {
+        self.current.lock().clone()
+    }
+
+    /// The bandwidth limit used by the transport.
+    pub fn bandwidth_limit(&self) -> BandwidthLimit {
+        self.bandwidth_limit.clone()
+    }
+
+    /// The sizes of the memory caches, which the contract handler is resized to on every change.
+    pub fn cache_sizes(&self) -> watch::Receiver<CacheSizes> {
+        self.cache_sizes.subscribe()
+    }
+
+    /// The access log policy, which the client API servers follow.
+    pub fn access_log(&self) -> watch::Receiver<AccessLogConfig> {
+        self.access_log.subscribe()
+    }
+
+    /// Validates and applies the changes, returning the new settings. Nothing is changed if the
+    /// settings are not valid or can't be applied.
+    pub fn apply(&self, update: RuntimeSettingsUpdate) -> Result<RuntimeSettings, ReloadError> {
+        let mut current = self.current.lock();
+        let settings = current.clone().updated(update);
+        settings.validate()?;
+        if settings == *current {
+            return Ok(settings);
+        }
+        // the only step which can fail goes first, so there is nothing to roll back
+        if settings.log_level != current.log_level {
+            set_log_level(settings.log_level)?;
+        }
+        self.bandwidth_limit.set(settings.bandwidth_limit);
+        self.connections
+            .set_connection_limits(settings.min_connections, settings.max_connections);
+        self.cache_sizes.send_replace(CacheSizes::from(&settings));
+        self.access_log
+            .send_replace(AccessLogConfig::from(&settings));
+        // the storage budget is read from the current settings every time it is enforced
+        tracing::info!(?settings, "Applied new runtime settings");
+        *current = settings.clone();
+        Ok(settings)
+    }
+
+    /// Applies the runtime settings of the configuration file.
+    pub fn reload_from_file(&self) -> Result<RuntimeSettings, ReloadError> {
+        let config = ConfigArgs::read_config(&self.config_dir)?
+            .ok_or_else(|| ReloadError::MissingConfig(self.config_dir.clone()))?;
+        let settings = self.apply(RuntimeSettings::from_config(&config).into())?;
+        tracing::info!("Reloaded the configuration, other changes to it require a restart");
+        Ok(settings)
+    }
+}
+
+fn set_log_level(level: tracing::log::LevelFilter) -> Result<(), ReloadError> {
+    #[cfg(feature = "trace")]
+    {
+        use tracing::level_filters::LevelFilter;
+
+        let level = match level {
+            tracing::log::LevelFilter::Off => LevelFilter::OFF,
+            tracing::log::LevelFilter::Error => LevelFilter::ERROR,
+            tracing::log::LevelFilter::Warn => LevelFilter::WARN,
+            tracing::log::LevelFilter::Info => LevelFilter::INFO,
+            tracing::log::LevelFilter::Debug => LevelFilter::DEBUG,
+            tracing::log::LevelFilter::Trace => LevelFilter::TRACE,
+        };
+        crate::tracing::tracer::set_level(level).map_err(ReloadError::LogLevel)
+    }
+    #[cfg(not(feature = "trace"))]
+    {
+        let _ = level;
+        Err(ReloadError::LogLevel(anyhow::anyhow!(
+            "the log level can only be changed by nodes built with the trace feature"
+        )))
+    }
+}
+
+/// Applies the access log settings to the policy of a client API server every time they change.
+pub(crate) async fn follow_access_log(
+    runtime: Arc<RuntimeConfig>,
+    policy: watch::Sender<AccessLogConfig>,
+) {
+    let mut access_log = runtime.access_log();
+    while access_log.changed().await.is_ok() {
+        policy.send_replace(*access_log.borrow_and_update());
+    }
+}
+
+/// Reloads the configuration file every time the node gets a SIGHUP.
+#[cfg(unix)]
+pub(crate) async fn reload_on_hangup(runtime: Arc<RuntimeConfig>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(error) => {
+            tracing::error!(%error, "Failed listening for SIGHUP, reloads are only available through the admin API");
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        tracing::info!("Got SIGHUP, reloading the configuration");
+        if let Err(error) = runtime.reload_from_file() {
+            tracing::error!(%error, "Rejected the reloaded configuration");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn runtime_config() -> RuntimeConfig {
+        let connections =
+            ConnectionManager::default_with_key(TransportKeypair::new().public().clone());
+        let settings = RuntimeSettings {
+            log_level: tracing::log::LevelFilter::Info,
+            bandwidth_limit: None,
+            max_storage_size: Some(1024),
+            state_cache_size: 1024,
+            code_cache_size: 1024,
+            min_connections: connections.min_connections(),
+            max_connections: connections.max_connections(),
+            access_log: false,
+            access_log_sample_rate: 1.0,
+        };
+        RuntimeConfig::with_settings(settings, PathBuf::new(), connections)
+    }
+
+    #[test]
+    fn apply_or_reject() {
+        let runtime = runtime_config();
+        let cache_sizes = runtime.cache_sizes();
+        let update: RuntimeSettingsUpdate = serde_json::from_str(
+            r#"{"bandwidth_limit": 500000, "max_storage_size": null, "state_cache_size": 4096, "min_connections": 2, "max_connections": 4, "access_log": true}"#,
+        )
+        .unwrap();
+        let settings = runtime.apply(update).unwrap();
+        assert!(cache_sizes.has_changed().unwrap());
+        assert_eq!(
+            *cache_sizes.borrow(),
+            CacheSizes {
+                state: 4096,
+                code: 1024
+            }
+        );
+        assert_eq!(settings.bandwidth_limit, Some(500_000));
+        assert_eq!(settings.max_storage_size, None);
+        assert_eq!(settings.log_level, tracing::log::LevelFilter::Info);
+        assert_eq!(runtime.bandwidth_limit().get(), Some(500_000));
+        assert_eq!(runtime.connections.min_connections(), 2);
+        assert_eq!(runtime.connections.max_connections(), 4);
+        assert!(settings.access_log);
+        assert!(runtime.access_log().borrow().enabled);
+
+        // the valid changes of a rejected update are not applied either
+        let update: RuntimeSettingsUpdate =
+            serde_json::from_str(r#"{"bandwidth_limit": null, "min_connections": 5}"#).unwrap();
+        assert!(matches!(
+            runtime.apply(update),
+            Err(ReloadError::Invalid(_))
+        ));
+        assert_eq!(runtime.settings(), settings);
+        assert_eq!(runtime.bandwidth_limit().get(), Some(500_000));
+        assert_eq!(runtime.connections.min_connections(), 2);
+
+        let update: RuntimeSettingsUpdate =
+            serde_json::from_str(r#"{"access_log_sample_rate": 1.5}"#).unwrap();
+        assert!(runtime.apply(update).is_err());
+        let update: RuntimeSettingsUpdate =
+            serde_json::from_str(r#"{"code_cache_size": 0}"#).unwrap();
+        assert!(runtime.apply(update).is_err());
+
+        #[cfg(not(feature = "trace"))]
+        {
+            let update: RuntimeSettingsUpdate =
+                serde_json::from_str(r#"{"log_level": "debug", "bandwidth_limit": 1000}"#).unwrap();
+            assert!(matches!(
+                runtime.apply(update),
+                Err(ReloadError::LogLevel(_))
+            ));
+            assert_eq!(runtime.settings(), settings);
+        }
+
+        assert!(serde_json::from_str::<RuntimeSettingsUpdate>(r#"{"log_level": "loud"}"#).is_err());
+        assert!(
+            serde_json::from_str::<RuntimeSettingsUpdate>(r#"{"network_port": 1234}"#).is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn reload_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = ConfigArgs {
+            mode: Some(OperationMode::Local),
+            network_api: NetworkArgs {
+                skip_load_from_network: true,
+                ..Default::default()
+            },
+            config_paths: ConfigPathsArgs {
+                config_dir: Some(dir.path().to_path_buf()),
+                data_dir: Some(dir.path().to_path_buf()),
+            },
+            ..Default::default()
+        };
+        // persists the configuration file
+        let mut config = args.build().await.unwrap();
+        let connections =
+            ConnectionManager::default_with_key(TransportKeypair::new().public().clone());
+        let runtime = RuntimeConfig::new(&config, connections);
+
+        if cfg!(feature = "trace") {
+            config.log_level = tracing::log::LevelFilter::Warn;
+        }
+        config.network_api.bandwidth_limit = Some(100_000);
+        config.network_api.min_connections = Some(1);
+        config.network_api.max_connections = Some(3);
+        config.storage_budget.max_storage_size = Some(4096);
+        config.storage_budget.state_cache_size = 1_000_000;
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, toml::to_string(&config).unwrap()).unwrap();
+        let settings = runtime.reload_from_file().unwrap();
+        assert_eq!(settings, RuntimeSettings::from_config(&config));
+        assert_eq!(runtime.connections.max_connections(), 3);
+
+        config.network_api.min_connections = Some(4);
+        std::fs::write(&path, toml::to_string(&config).unwrap()).unwrap();
+        assert!(runtime.reload_from_file().is_err());
+        assert_eq!(runtime.settings(), settings);
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            runtime.reload_from_file(),
+            Err(ReloadError::MissingConfig(_))
+        ));
+    }
+}
@@ -113,7 +113,9 @@ impl SecretArgs {
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+#[derive(
+    Debug, Clone, Eq, PartialEq, serde::Serialize, serde::Deserialize, schemars::JsonSchema,
+)]
 pub struct Secrets {
     #[serde(skip)]
     pub transport_keypair: TransportKeypair,
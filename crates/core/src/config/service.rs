@@ -0,0 +1,240 @@
+//! Definitions running the node as a service of the platform, as generated by
+//! `freenet install-service`: a systemd user unit on Linux, a launchd agent on macOS and a
+//! [WinSW](https://github.com/winsw/winsw) service definition on Windows, since the node does not
+//! implement the Windows service control protocol itself.
+//!
+//! WinSW is not shipped with the node. Its `WinSW-x64.exe` release binary, version 2.12 or later,
+//! is downloaded from the WinSW releases and saved next to the generated definition, named after
+//! it: WinSW reads the XML file with the name of its own executable, so `freenet-service.xml` is
+//! run by `freenet-service.exe`. Installed that way, it registers itself as the service, starts
+//! the node and restarts it whenever it fails.
+
+use super::*;
+
+const SERVICE_NAME: &str = "freenet";
+const LAUNCHD_LABEL: &str = "org.freenet.node";
+/// Where the WinSW binary running the node as a Windows service is downloaded from.
+const WINSW_RELEASES: &str = "https://github.com/winsw/winsw/releases";
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServicePlatform {
+    Systemd,
+    Launchd,
+    Windows,
+}
+
+impl ServicePlatform {
+    pub fn current() -> Self {
+        if cfg!(target_os = "macos") {
+            Self::Launchd
+        } else if cfg!(windows) {
+            Self::Windows
+        } else {
+            Self::Systemd
+        }
+    }
+
+    /// Where the definition is usually installed, if it depends on the user.
+    pub fn install_path(self) -> Option<PathBuf> {
+        let home = directories::BaseDirs::new()?.home_dir().to_path_buf();
+        match self {
+            Self::Systemd => Some(
+                home.join(".config/systemd/user")
+                    .join(format!("{SERVICE_NAME}.service")),
+            ),
+            Self::Launchd => Some(
+                home.join("Library/LaunchAgents")
+                    .join(format!("{LAUNCHD_LABEL}.plist")),
+            ),
+            Self::Windows => None,
+        }
+    }
+
+    /// Commands enabling the service once the definition is installed at the path.
+    pub fn enable_instructions(self, path: &Path) -> String {
+        match self {
+            Self::Systemd => format!(
+                "systemctl --user daemon-reload && systemctl --user enable --now {SERVICE_NAME}"
+            ),
+            Self::Launchd => format!("launchctl load -w {}", path.display()),
+            Self::Windows => {
+                // WinSW reads the definition named after its own executable
+                let wrapper = path.with_extension("exe");
+                format!(
+                    "download WinSW-x64.exe (2.12 or later) from {WINSW_RELEASES} and save it as {}, then run: \"{}\" install && \"{}\" start",
+                    wrapper.display(),
+                    wrapper.display(),
+                    wrapper.display()
+                )
+            }
+        }
+    }
+}
+
+/// The command run by the service: the node binary along with the directories it uses.
+#[derive(Debug, Clone)]
+pub struct ServiceDefinition {
+    pub executable: PathBuf,
+    pub config_dir: PathBuf,
+    pub data_dir: PathBuf,
+}
+
+impl ServiceDefinition {
+    pub fn new(config: &Config, executable: PathBuf) -> Self {
+        let paths = config.paths();
+        Self {
+            executable,
+            config_dir: paths.config_dir(),
+            data_dir: paths.data_dir.clone(),
+        }
+    }
+
+    fn arguments(&self) -> [String; 4] {
+        [
+            "--config-dir".into(),
+            self.config_dir.display().to_string(),
+            "--data-dir".into(),
+            self.data_dir.display().to_string(),
+        ]
+    }
+
+    pub fn render(&self, platform: ServicePlatform) -> String {
+        match platform {
+            ServicePlatform::Systemd => self.systemd_unit(),
+            ServicePlatform::Launchd => self.launchd_plist(),
+            ServicePlatform::Windows => self.winsw_definition(),
+        }
+    }
+
+    fn systemd_unit(&self) -> String {
+        // arguments are quoted so paths can contain spaces, and % escaped since it introduces
+        // specifiers in units
+        let quote = |arg: &str| {
+            format!(
+                "\"{}\"",
+                arg.replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace('%', "%%")
+            )
+        };
+        let command = std::iter::once(self.executable.display().to_string())
+            .chain(self.arguments())
+            .map(|arg| quote(&arg))
+            .collect::<Vec<_>>()
+            .join(" ");
+        format!(
+            "[Unit]
+Description=Freenet node
+Wants=network-online.target
+After=network-online.target
+
+[Service]
+ExecStart={command}
+ExecReload=/bin/kill -HUP $MAINPID
+Restart=on-failure
+RestartSec=10
+
+[Install]
+WantedBy=default.target
+"
+        )
+    }
+
+    fn launchd_plist(&self) -> String {
+        let arguments = std::iter::once(self.executable.display().to_string())
+            .chain(self.arguments())
+            .map(|arg| format!("        <string>{}</string>\n", xml_escape(&arg)))
+            .collect::<String>();
+        let log = xml_escape(&self.data_dir.join("freenet.log").display().to_string());
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{LAUNCHD_LABEL}</string>
+    <key>ProgramArguments</key>
+    <array>
+{arguments}    </array>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <dict>
+        <key>SuccessfulExit</key>
+        <false/>
+    </dict>
+    <key>StandardOutPath</key>
+    <string>{log}</string>
+    <key>StandardErrorPath</key>
+    <string>{log}</string>
+</dict>
+</plist>
+"#
+        )
+    }
+
+    fn winsw_definition(&self) -> String {
+        let arguments = self
+            .arguments()
+            .iter()
+            .map(|arg| format!("\"{arg}\""))
+            .collect::<Vec<_>>()
+            .join(" ");
+        format!(
+            r#"<service>
+  <id>{SERVICE_NAME}</id>
+  <name>Freenet</name>
+  <description>Freenet node</description>
+  <executable>{}</executable>
+  <arguments>{}</arguments>
+  <startmode>Automatic</startmode>
+  <onfailure action="restart" delay="10 sec"/>
+  <logpath>{}</logpath>
+</service>
+"#,
+            xml_escape(&self.executable.display().to_string()),
+            xml_escape(&arguments),
+            xml_escape(&self.data_dir.join("logs").display().to_string()),
+        )
+    }
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_definitions() {
+        let definition = ServiceDefinition {
+            executable: PathBuf::from("/opt/freenet/bin/freenet"),
+            config_dir: PathBuf::from("/home/user/.config/freenet"),
+            data_dir: PathBuf::from("/home/user/My Data & 100%"),
+        };
+
+        let unit = definition.render(ServicePlatform::Systemd);
+        assert!(unit.contains(
+            r#"ExecStart="/opt/freenet/bin/freenet" "--config-dir" "/home/user/.config/freenet" "--data-dir" "/home/user/My Data & 100%%""#
+        ));
+
+        let plist = definition.render(ServicePlatform::Launchd);
+        assert!(plist.contains("<string>/home/user/My Data &amp; 100%</string>"));
+        assert!(plist.contains("<string>/opt/freenet/bin/freenet</string>"));
+
+        let winsw = definition.render(ServicePlatform::Windows);
+        assert!(winsw.contains(
+            "<arguments>&quot;--config-dir&quot; &quot;/home/user/.config/freenet&quot;"
+        ));
+        let instructions =
+            ServicePlatform::Windows.enable_instructions(Path::new("services/freenet-service.xml"));
+        assert!(instructions.contains(WINSW_RELEASES));
+        assert!(instructions.contains("\"services/freenet-service.exe\" install"));
+    }
+}
@@ -10,14 +10,16 @@ mod handler;
 pub mod storages;
 
 pub(crate) use executor::{
-    executor_channel, mock_runtime::MockRuntime, Callback, ExecutorToEventLoopChannel,
-    NetworkEventListenerHalve, UpsertResult,
+    executor_channel,
+    journal::{JournalEntry, DEFAULT_JOURNAL_ENTRIES},
+    mock_runtime::MockRuntime,
+    Callback, ExecutorToEventLoopChannel, NetworkEventListenerHalve, UpsertResult,
 };
 pub(crate) use handler::{
     client_responses_channel, contract_handler_channel, in_memory::MemoryContractHandler,
-    ClientResponsesReceiver, ClientResponsesSender, ContractHandler, ContractHandlerChannel,
-    ContractHandlerEvent, NetworkContractHandler, SenderHalve, StoreResponse, WaitingResolution,
-    WaitingTransaction,
+    replay::ReplayContractHandler, ClientResponsesReceiver, ClientResponsesSender, ContractHandler,
+    ContractHandlerChannel, ContractHandlerEvent, NetworkContractHandler, SenderHalve,
+    StoreResponse, WaitingResolution, WaitingTransaction,
 };
 
 pub use executor::{Executor, ExecutorError, OperationMode};
@@ -127,19 +129,12 @@ where
             }
             ContractHandlerEvent::UpdateQuery {
                 key,
-                data,
+                update,
                 related_contracts,
             } => {
-                let update_value: Either<WrappedState, StateDelta<'static>> = match data {
-                    freenet_stdlib::prelude::UpdateData::State(state) => {
-                        Either::Left(WrappedState::from(state.into_bytes()))
-                    }
-                    freenet_stdlib::prelude::UpdateData::Delta(delta) => Either::Right(delta),
-                    _ => unreachable!(),
-                };
                 let update_result = contract_handler
                     .executor()
-                    .upsert_contract_state(key, update_value, related_contracts, None)
+                    .upsert_contract_state(key, update, related_contracts, None)
                     .instrument(tracing::info_span!("upsert_contract_state", %key))
                     .await;
 
@@ -189,6 +184,225 @@ where
                         tracing::debug!(%error, "shutting down contract handler");
                     })?;
             }
+            ContractHandlerEvent::UpdateJournalQuery { key, last } => {
+                let entries = contract_handler.executor().update_journal(key, last);
+                contract_handler
+                    .channel()
+                    .send_to_sender(
+                        id,
+                        ContractHandlerEvent::UpdateJournalResponse { key, entries },
+                    )
+                    .await
+                    .inspect_err(|error| {
+                        tracing::debug!(%error, "shutting down contract handler");
+                    })?;
+            }
+            ContractHandlerEvent::SummaryQuery { key } => {
+                let summary = contract_handler
+                    .executor()
+                    .summarize_contract_state(key)
+                    .instrument(tracing::info_span!("summarize_contract_state", %key))
+                    .await
+                    .inspect_err(|err| {
+                        tracing::warn!("Error while summarizing contract state: {err}");
+                    });
+                contract_handler
+                    .channel()
+                    .send_to_sender(id, ContractHandlerEvent::SummaryResponse { key, summary })
+                    .await
+                    .inspect_err(|error| {
+                        tracing::debug!(%error, "shutting down contract handler");
+                    })?;
+            }
+            ContractHandlerEvent::DeltaQuery { key, summary } => {
+                let delta = contract_handler
+                    .executor()
+                    .contract_state_delta(key, summary)
+                    .instrument(tracing::info_span!("contract_state_delta", %key))
+                    .await
+                    .inspect_err(|err| {
+                        tracing::warn!("Error while computing contract state delta: {err}");
+                    });
+                contract_handler
+                    .channel()
+                    .send_to_sender(id, ContractHandlerEvent::DeltaResponse { key, delta })
+                    .await
+                    .inspect_err(|error| {
+                        tracing::debug!(%error, "shutting down contract handler");
+                    })?;
+            }
+            ContractHandlerEvent::SnapshotQuery { snapshot_dir, keep } => {
+                // the snapshot is taken here, in order with the other events, but writing it
+                // out can take a while so it is not awaited in the handler loop
+                let snapshot = contract_handler
+                    .executor()
+                    .create_snapshot(snapshot_dir, keep);
+                let response = contract_handler.channel().detach_response(id)?;
+                tokio::spawn(
+                    async move {
+                        let result = snapshot.await.inspect_err(|err| {
+                            tracing::warn!("Error while creating state snapshot: {err}");
+                        });
+                        response.send(ContractHandlerEvent::SnapshotResponse { result });
+                    }
+                    .instrument(tracing::info_span!("create_snapshot")),
+                );
+            }
+            ContractHandlerEvent::StorageBudgetQuery {
+                subscribed,
+                max_size,
+            } => {
+                let evicted = contract_handler
+                    .executor()
+                    .enforce_storage_budget(subscribed, max_size)
+                    .instrument(tracing::info_span!("enforce_storage_budget"))
+                    .await
+                    .inspect_err(|err| {
+                        tracing::warn!("Error while enforcing the storage budget: {err}");
+                    });
+                contract_handler
+                    .channel()
+                    .send_to_sender(id, ContractHandlerEvent::StorageBudgetResponse { evicted })
+                    .await
+                    .inspect_err(|error| {
+                        tracing::debug!(%error, "shutting down contract handler");
+                    })?;
+            }
+            ContractHandlerEvent::PinQuery { id: contract, pin } => {
+                let changed = contract_handler
+                    .executor()
+                    .pin_contract(contract, pin)
+                    .await;
+                if let Ok(true) = changed {
+                    tracing::info!(%contract, %pin, "Updated pinned contract");
+                }
+                contract_handler
+                    .channel()
+                    .send_to_sender(
+                        id,
+                        ContractHandlerEvent::PinResponse {
+                            id: contract,
+                            changed,
+                        },
+                    )
+                    .await
+                    .inspect_err(|error| {
+                        tracing::debug!(%error, "shutting down contract handler");
+                    })?;
+            }
+            ContractHandlerEvent::PinnedContractsQuery => {
+                let pinned = contract_handler.executor().pinned_contracts();
+                contract_handler
+                    .channel()
+                    .send_to_sender(id, ContractHandlerEvent::PinnedContractsResponse { pinned })
+                    .await
+                    .inspect_err(|error| {
+                        tracing::debug!(%error, "shutting down contract handler");
+                    })?;
+            }
+            ContractHandlerEvent::ResizeCachesQuery {
+                state_cache_size,
+                code_cache_size,
+            } => {
+                contract_handler
+                    .executor()
+                    .resize_caches(state_cache_size, code_cache_size);
+                contract_handler
+                    .channel()
+                    .send_to_sender(id, ContractHandlerEvent::ResizeCachesResponse)
+                    .await
+                    .inspect_err(|error| {
+                        tracing::debug!(%error, "shutting down contract handler");
+                    })?;
+            }
+            ContractHandlerEvent::SecretsConsentsQuery => {
+                let pending = contract_handler.executor().secrets_consents();
+                contract_handler
+                    .channel()
+                    .send_to_sender(
+                        id,
+                        ContractHandlerEvent::SecretsConsentsResponse { pending },
+                    )
+                    .await
+                    .inspect_err(|error| {
+                        tracing::debug!(%error, "shutting down contract handler");
+                    })?;
+            }
+            ContractHandlerEvent::ResolveSecretsConsentQuery {
+                request_id,
+                allowed,
+            } => {
+                let resolved = contract_handler
+                    .executor()
+                    .resolve_secrets_consent(request_id, allowed);
+                contract_handler
+                    .channel()
+                    .send_to_sender(
+                        id,
+                        ContractHandlerEvent::ResolveSecretsConsentResponse {
+                            request_id,
+                            resolved,
+                        },
+                    )
+                    .await
+                    .inspect_err(|error| {
+                        tracing::debug!(%error, "shutting down contract handler");
+                    })?;
+            }
+            ContractHandlerEvent::SuccessorQuery { id: contract } => {
+                let successor = contract_handler.executor().contract_successor(&contract);
+                contract_handler
+                    .channel()
+                    .send_to_sender(
+                        id,
+                        ContractHandlerEvent::SuccessorResponse {
+                            id: contract,
+                            successor,
+                        },
+                    )
+                    .await
+                    .inspect_err(|error| {
+                        tracing::debug!(%error, "shutting down contract handler");
+                    })?;
+            }
+            ContractHandlerEvent::ResolvePrefixQuery { prefix } => {
+                let resolution = contract_handler.executor().resolve_prefix(&prefix);
+                contract_handler
+                    .channel()
+                    .send_to_sender(
+                        id,
+                        ContractHandlerEvent::ResolvePrefixResponse { prefix, resolution },
+                    )
+                    .await
+                    .inspect_err(|error| {
+                        tracing::debug!(%error, "shutting down contract handler");
+                    })?;
+            }
+            ContractHandlerEvent::ValidatePutQuery {
+                contract,
+                state,
+                related_contracts,
+            } => {
+                let key = contract.key();
+                let result = contract_handler
+                    .executor()
+                    .validate_put(contract, state, related_contracts)
+                    .instrument(tracing::info_span!("validate_put", %key))
+                    .await;
+                if result.is_ok() {
+                    contract_handler.executor().pin_local_put(*key.id()).await;
+                }
+                contract_handler
+                    .channel()
+                    .send_to_sender(
+                        id,
+                        ContractHandlerEvent::ValidatePutResponse { key, result },
+                    )
+                    .await
+                    .inspect_err(|error| {
+                        tracing::debug!(%error, "shutting down contract handler");
+                    })?;
+            }
             _ => unreachable!(),
         }
     }
@@ -204,4 +418,6 @@ pub(crate) enum ContractError {
     IOError(#[from] std::io::Error),
     #[error("no response received from handler")]
     NoEvHandlerResponse,
+    #[error("too many pending requests for contract {0}")]
+    TooManyPendingRequests(ContractKey),
 }
@@ -19,28 +19,60 @@ use freenet_stdlib::prelude::*;
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::{self};
 
-use super::storages::Storage;
+use super::storages::{
+    index::Resolution,
+    quota::{PinnedContract, StorageBudget},
+    Storage,
+};
 use crate::config::Config;
 use crate::message::Transaction;
 use crate::node::OpManager;
 use crate::operations::get::GetResult;
 use crate::operations::{OpEnum, OpError};
 use crate::wasm_runtime::{
-    ContractExecError, ContractRuntimeInterface, ContractStore, DelegateRuntimeInterface,
-    DelegateStore, Runtime, SecretsStore, StateStore, StateStoreError,
+    ContractExecError, ContractRuntimeInterface, ContractStateSource, ContractStore,
+    DelegateRuntimeInterface, DelegateStore, PutRejection, Runtime, RuntimeConfig, SecretsConsent,
+    SecretsStore, StateStorage, StateStore, StateStoreError,
 };
 use crate::{
     client_events::{ClientId, HostResult},
     operations::{self, Operation},
 };
 
+pub(crate) mod journal;
 pub(super) mod mock_runtime;
+pub(crate) mod policy;
 pub(super) mod runtime;
+pub(crate) mod upgrade;
+
+use journal::{JournalEntry, UpdateJournal};
+use policy::{ContractPolicy, PolicyRefusal};
+use upgrade::ContractUpgrades;
+
+/// Runs a synchronous, potentially long running, contract runtime invocation.
+///
+/// On a multi-threaded runtime the current worker hands off its queued tasks to the rest of
+/// the pool and becomes a blocking thread for the duration of the call, so WASM execution does
+/// not stall the reactor.
+///
+/// This doesn't make contracts run concurrently: the contract handler still processes its
+/// events one at a time, waiting for each invocation to complete. Its queue is bounded, overall
+/// and per contract, and events whose requester went away are skipped before executing them,
+/// but an invocation already running is never cancelled.
+fn run_blocking<T>(f: impl FnOnce() -> T) -> T {
+    use tokio::runtime::{Handle, RuntimeFlavor};
+    match Handle::try_current().map(|h| h.runtime_flavor()) {
+        Ok(RuntimeFlavor::MultiThread) => tokio::task::block_in_place(f),
+        _ => f(),
+    }
+}
 
 #[derive(Debug)]
 pub struct ExecutorError {
     inner: Either<Box<RequestError>, anyhow::Error>,
     fatal: bool,
+    /// Set when the request was refused by the node policy rather than failing.
+    refusal: Option<PolicyRefusal>,
 }
 
 enum InnerOpError {
@@ -55,6 +87,7 @@ impl ExecutorError {
         Self {
             inner: Either::Right(error.into()),
             fatal: false,
+            refusal: None,
         }
     }
 
@@ -63,6 +96,7 @@ impl ExecutorError {
         Self {
             inner: Either::Right(anyhow::anyhow!("internal error")),
             fatal: false,
+            refusal: None,
         }
     }
 
@@ -70,6 +104,7 @@ impl ExecutorError {
         Self {
             inner: Either::Left(Box::new(error.into())),
             fatal: false,
+            refusal: None,
         }
     }
 
@@ -146,6 +181,10 @@ impl ExecutorError {
         self.fatal
     }
 
+    pub(crate) fn policy_refusal(&self) -> Option<&PolicyRefusal> {
+        self.refusal.as_ref()
+    }
+
     pub fn unwrap_request(self) -> RequestError {
         match self.inner {
             Either::Left(err) => *err,
@@ -159,6 +198,7 @@ impl From<RequestError> for ExecutorError {
         Self {
             inner: Either::Left(Box::new(value)),
             fatal: false,
+            refusal: None,
         }
     }
 }
@@ -172,18 +212,38 @@ impl Display for ExecutorError {
     }
 }
 
+impl From<PolicyRefusal> for ExecutorError {
+    fn from(refusal: PolicyRefusal) -> Self {
+        let key = refusal.key();
+        let cause = refusal.to_string().into();
+        let mut error = match refusal {
+            PolicyRefusal::ExecutionDisabled(_) | PolicyRefusal::UntrustedState(_) => {
+                ExecutorError::request(StdContractError::Update { key, cause })
+            }
+            PolicyRefusal::Denied(_) | PolicyRefusal::NotAllowed(_) => {
+                ExecutorError::request(StdContractError::Put { key, cause })
+            }
+        };
+        error.refusal = Some(refusal);
+        error
+    }
+}
+
 impl From<Box<RequestError>> for ExecutorError {
     fn from(value: Box<RequestError>) -> Self {
         Self {
             inner: Either::Left(value),
             fatal: false,
+            refusal: None,
         }
     }
 }
 
 type Response = Result<HostResponse, ExecutorError>;
 
-#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(
+    clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema,
+)]
 #[serde(rename_all = "snake_case")]
 pub enum OperationMode {
     /// Run the node in local-only mode. Useful for development purposes.
@@ -470,6 +530,90 @@ pub(crate) trait ContractExecutor: Send + 'static {
         notification_ch: tokio::sync::mpsc::UnboundedSender<HostResult>,
         summary: Option<StateSummary<'_>>,
     ) -> Result<(), Box<RequestError>>;
+
+    /// Returns up to the `last` most recent entries of the update journal for the contract.
+    fn update_journal(
+        &mut self,
+        key: ContractKey,
+        last: usize,
+    ) -> Result<Vec<JournalEntry>, ExecutorError>;
+
+    /// Summarizes the current state of the contract, `None` if this node does not execute it.
+    fn summarize_contract_state(
+        &mut self,
+        key: ContractKey,
+    ) -> impl Future<Output = Result<Option<StateSummary<'static>>, ExecutorError>> + Send;
+
+    /// Computes the changes missing from a state given its summary, `None` if the summarized
+    /// state is in sync with the current state of the contract.
+    fn contract_state_delta(
+        &mut self,
+        key: ContractKey,
+        summary: StateSummary<'static>,
+    ) -> impl Future<Output = Result<Option<StateDelta<'static>>, ExecutorError>> + Send;
+
+    /// Takes a snapshot of the state store and writes it into `snapshot_dir`, returning the
+    /// path of the new snapshot file.
+    ///
+    /// The snapshot contains the state store as of the call; writing it out is done by the
+    /// returned future, which doesn't borrow the executor so the handler can keep serving
+    /// other events meanwhile.
+    fn create_snapshot(
+        &mut self,
+        snapshot_dir: PathBuf,
+        keep: usize,
+    ) -> impl Future<Output = Result<PathBuf, ExecutorError>> + Send + 'static;
+
+    /// Evicts the least recently used contracts over the storage budget, except the pinned ones,
+    /// the ones local clients are subscribed to and the `subscribed` ones. Returns the evicted
+    /// contracts and the bytes freed by each.
+    ///
+    /// The budget is updated to `max_size` first.
+    fn enforce_storage_budget(
+        &mut self,
+        subscribed: HashSet<ContractInstanceId>,
+        max_size: Option<u64>,
+    ) -> impl Future<Output = Result<Vec<(ContractKey, u64)>, ExecutorError>> + Send;
+
+    /// Pins or unpins a contract, returning whether its pinned status changed.
+    fn pin_contract(
+        &mut self,
+        id: ContractInstanceId,
+        pin: bool,
+    ) -> impl Future<Output = Result<bool, ExecutorError>> + Send;
+
+    /// Pins a contract put by a local client, so it's never evicted.
+    fn pin_local_put(&mut self, id: ContractInstanceId) -> impl Future<Output = ()> + Send;
+
+    fn pinned_contracts(&self) -> Vec<PinnedContract>;
+
+    /// Changes the max number of bytes of states, and of code, kept in memory.
+    fn resize_caches(&mut self, state_cache_size: u64, code_cache_size: u64);
+
+    /// Latest version of a contract upgraded to a new version, `None` if it was not upgraded.
+    fn contract_successor(&self, id: &ContractInstanceId) -> Option<ContractKey>;
+
+    /// Resolves a prefix of the encoded id of a stored contract.
+    fn resolve_prefix(&self, prefix: &str) -> Resolution;
+
+    /// Prompts asking the user to let applications read their namespace of delegate secrets.
+    fn secrets_consents(&self) -> Vec<SecretsConsent>;
+
+    /// Answers a consent prompt on behalf of the user, returning whether it was pending.
+    fn resolve_secrets_consent(
+        &mut self,
+        request_id: u32,
+        allowed: bool,
+    ) -> Result<bool, ExecutorError>;
+
+    /// Checks a contract put by a client can run and accepts its initial state, without storing
+    /// it, so the put is rejected before being propagated otherwise.
+    fn validate_put(
+        &mut self,
+        contract: ContractContainer,
+        state: WrappedState,
+        related_contracts: RelatedContracts<'static>,
+    ) -> impl Future<Output = Result<(), ExecutorError>> + Send;
 }
 
 /// A WASM executor which will run any contracts, delegates, etc. registered.
@@ -487,10 +631,50 @@ pub struct Executor<R = Runtime> {
     subscriber_summaries: HashMap<ContractKey, HashMap<ClientId, Option<StateSummary<'static>>>>,
     /// Attested contract instances for a given delegate.
     delegate_attested_ids: HashMap<DelegateKey, Vec<ContractInstanceId>>,
+    /// Journal of the updates applied to each contract, if enabled.
+    update_journal: Option<UpdateJournal>,
+    /// Which contracts this node is willing to execute.
+    policy: ContractPolicy,
+    /// Maximum disk usage of the contract states, and the contracts pinned by the operator.
+    storage_budget: StorageBudget,
+    /// Contracts upgraded to a new version.
+    upgrades: ContractUpgrades,
+    /// Initial states validated before their put was propagated.
+    validated_puts: ValidatedPuts,
 
     event_loop_channel: Option<ExecutorToEventLoopChannel<ExecutorHalve>>,
 }
 
+/// Initial states of contracts validated by the node a put originates from, so the contract isn't
+/// run again when the node stores them once the put succeeded.
+///
+/// For metadata-only contracts, which can't be validated, these are the only states from the
+/// network which are trusted: the ones put by the clients of the node.
+#[derive(Default)]
+struct ValidatedPuts(std::collections::VecDeque<(ContractKey, blake3::Hash)>);
+
+impl ValidatedPuts {
+    /// Puts remembered at most, the oldest ones are validated again when stored.
+    const MAX_PUTS: usize = 64;
+
+    fn insert(&mut self, key: ContractKey, state: &WrappedState) {
+        if self.0.len() >= Self::MAX_PUTS {
+            self.0.pop_front();
+        }
+        self.0.push_back((key, blake3::hash(state.as_ref())));
+    }
+
+    /// Whether the state was validated for the contract, forgetting it if so.
+    fn take(&mut self, key: &ContractKey, state: &WrappedState) -> bool {
+        let hash = blake3::hash(state.as_ref());
+        let Some(pos) = self.0.iter().position(|entry| *entry == (*key, hash)) else {
+            return false;
+        };
+        self.0.remove(pos);
+        true
+    }
+}
+
 impl<R> Executor<R> {
     pub async fn new(
         state_store: StateStore<Storage>,
@@ -508,10 +692,100 @@ impl<R> Executor<R> {
             update_notifications: HashMap::default(),
             subscriber_summaries: HashMap::default(),
             delegate_attested_ids: HashMap::default(),
+            update_journal: None,
+            policy: ContractPolicy::default(),
+            storage_budget: StorageBudget::default(),
+            upgrades: ContractUpgrades::default(),
+            validated_puts: ValidatedPuts::default(),
             event_loop_channel,
         })
     }
 
+    /// Enables the update journal, keeping up to `retention` entries per contract.
+    pub(crate) fn with_update_journal(mut self, retention: Option<usize>) -> Self {
+        self.update_journal = retention.map(UpdateJournal::new);
+        self
+    }
+
+    pub(crate) fn with_policy(mut self, policy: ContractPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub(crate) fn with_storage_budget(mut self, storage_budget: StorageBudget) -> Self {
+        self.storage_budget = storage_budget;
+        self
+    }
+
+    pub(crate) fn with_upgrades(mut self, upgrades: ContractUpgrades) -> Self {
+        self.upgrades = upgrades;
+        self
+    }
+
+    async fn update_pin(&mut self, id: ContractInstanceId, pin: bool) -> bool {
+        if pin {
+            self.storage_budget.pin(id).await
+        } else {
+            self.storage_budget.unpin(&id).await
+        }
+    }
+
+    async fn evict_contracts(
+        &mut self,
+        subscribed: &HashSet<ContractInstanceId>,
+        max_size: Option<u64>,
+    ) -> Result<Vec<(ContractKey, u64)>, ExecutorError> {
+        self.storage_budget.set_max_size(max_size);
+        let locally_subscribed = self
+            .update_notifications
+            .iter()
+            .filter(|(_, channels)| !channels.is_empty())
+            .map(|(key, _)| *key.id())
+            .collect::<HashSet<_>>();
+        let evictions = self
+            .storage_budget
+            .select_evictions(&self.state_store.usage(), |id| {
+                subscribed.contains(id) || locally_subscribed.contains(id)
+            });
+        let mut evicted = Vec::with_capacity(evictions.len());
+        for (id, size) in evictions {
+            let key = ContractKey::from(id);
+            self.state_store
+                .remove(&key)
+                .await
+                .map_err(ExecutorError::other)?;
+            self.update_notifications.remove(&key);
+            self.subscriber_summaries.remove(&key);
+            if let Some(journal) = &mut self.update_journal {
+                journal.remove(&key);
+            }
+            evicted.push((key, size));
+        }
+        Ok(evicted)
+    }
+
+    fn journal_updates(
+        &mut self,
+        key: &ContractKey,
+        last: usize,
+    ) -> Result<Vec<JournalEntry>, ExecutorError> {
+        let Some(journal) = &self.update_journal else {
+            return Err(ExecutorError::other(anyhow::anyhow!(
+                "update journal is not enabled in this node"
+            )));
+        };
+        Ok(journal.last(key, last))
+    }
+
+    fn write_snapshot(
+        &self,
+        snapshot_dir: PathBuf,
+        keep: usize,
+    ) -> impl Future<Output = Result<PathBuf, ExecutorError>> + Send + 'static {
+        let written = self.state_store.write_snapshot(snapshot_dir, keep);
+        async move { written.await.map_err(ExecutorError::other) }
+    }
+
     pub fn test_data_dir(identifier: &str) -> PathBuf {
         std::env::temp_dir().join(format!("freenet-executor-{identifier}"))
     }
@@ -527,14 +801,15 @@ impl<R> Executor<R> {
         ),
         anyhow::Error,
     > {
-        const MAX_SIZE: i64 = 10 * 1024 * 1024;
-        const MAX_MEM_CACHE: u32 = 10_000_000;
+        let budget = &config.storage_budget;
+        let state_cache_size = budget.state_cache_size.min(u32::MAX as u64) as u32;
+        let code_cache_size = budget.code_cache_size.min(i64::MAX as u64) as i64;
 
         let state_store =
-            StateStore::new(Storage::new(&config.db_dir()).await?, MAX_MEM_CACHE).unwrap();
-        let contract_store = ContractStore::new(config.contracts_dir(), MAX_SIZE)?;
+            StateStore::new(Storage::new(&config.db_dir()).await?, state_cache_size).unwrap();
+        let contract_store = ContractStore::new(config.contracts_dir(), code_cache_size)?;
 
-        let delegate_store = DelegateStore::new(config.delegates_dir(), MAX_SIZE)?;
+        let delegate_store = DelegateStore::new(config.delegates_dir(), code_cache_size)?;
 
         let secret_store = SecretsStore::new(config.secrets_dir(), config.secrets.clone())?;
 
@@ -0,0 +1,320 @@
+//! Bounded, in-memory, journal of the updates applied to each contract.
+//!
+//! Helps app developers debug state divergence between nodes, by exposing which updates
+//! a node applied and the resulting state after each one.
+//!
+//! Besides the entries kept per contract, the journal is bounded by the total size of its
+//! entries, dropping the oldest entries of any contract first.
+
+use std::collections::{HashMap, VecDeque};
+
+use chrono::{DateTime, Utc};
+use freenet_stdlib::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Number of journal entries returned when not specified in the request.
+pub(crate) const DEFAULT_JOURNAL_ENTRIES: usize = 10;
+/// Maximum size of all the journal entries kept by the node.
+const MAX_JOURNAL_BYTES: usize = 32 * 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct JournalEntry {
+    pub applied_at: DateTime<Utc>,
+    pub updates: Vec<AppliedUpdate>,
+    /// blake3 hash of the state before applying the updates, base58 encoded
+    pub previous_state_hash: String,
+    /// blake3 hash of the resulting state, base58 encoded
+    pub state_hash: String,
+    pub state_size: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub(crate) enum AppliedUpdate {
+    /// A full state replacement; only its hash is recorded.
+    State {
+        hash: String,
+        size: usize,
+    },
+    Delta {
+        delta: Vec<u8>,
+    },
+    StateAndDelta {
+        hash: String,
+        delta: Vec<u8>,
+    },
+    RelatedState {
+        related_to: String,
+        hash: String,
+    },
+    RelatedDelta {
+        related_to: String,
+        delta: Vec<u8>,
+    },
+    RelatedStateAndDelta {
+        related_to: String,
+        hash: String,
+        delta: Vec<u8>,
+    },
+}
+
+fn hash(bytes: &[u8]) -> String {
+    bs58::encode(blake3::hash(bytes).as_bytes()).into_string()
+}
+
+impl JournalEntry {
+    /// Approximate memory used by the entry.
+    fn size(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.previous_state_hash.len()
+            + self.state_hash.len()
+            + self.updates.iter().map(AppliedUpdate::size).sum::<usize>()
+    }
+}
+
+impl AppliedUpdate {
+    fn size(&self) -> usize {
+        let contents = match self {
+            AppliedUpdate::State { hash, .. } => hash.len(),
+            AppliedUpdate::Delta { delta } => delta.len(),
+            AppliedUpdate::StateAndDelta { hash, delta } => hash.len() + delta.len(),
+            AppliedUpdate::RelatedState { related_to, hash } => related_to.len() + hash.len(),
+            AppliedUpdate::RelatedDelta { related_to, delta } => related_to.len() + delta.len(),
+            AppliedUpdate::RelatedStateAndDelta {
+                related_to,
+                hash,
+                delta,
+            } => related_to.len() + hash.len() + delta.len(),
+        };
+        std::mem::size_of::<Self>() + contents
+    }
+
+    fn from_update(update: &UpdateData<'_>) -> Option<Self> {
+        let applied = match update {
+            UpdateData::State(state) => AppliedUpdate::State {
+                hash: hash(state.as_ref()),
+                size: state.size(),
+            },
+            UpdateData::Delta(delta) => AppliedUpdate::Delta {
+                delta: delta.as_ref().to_vec(),
+            },
+            UpdateData::StateAndDelta { state, delta } => AppliedUpdate::StateAndDelta {
+                hash: hash(state.as_ref()),
+                delta: delta.as_ref().to_vec(),
+            },
+            UpdateData::RelatedState { related_to, state } => AppliedUpdate::RelatedState {
+                related_to: related_to.to_string(),
+                hash: hash(state.as_ref()),
+            },
+            UpdateData::RelatedDelta { related_to, delta } => AppliedUpdate::RelatedDelta {
+                related_to: related_to.to_string(),
+                delta: delta.as_ref().to_vec(),
+            },
+            UpdateData::RelatedStateAndDelta {
+                related_to,
+                state,
+                delta,
+            } => AppliedUpdate::RelatedStateAndDelta {
+                related_to: related_to.to_string(),
+                hash: hash(state.as_ref()),
+                delta: delta.as_ref().to_vec(),
+            },
+            _ => return None,
+        };
+        Some(applied)
+    }
+}
+
+pub(crate) struct UpdateJournal {
+    /// Maximum number of entries kept per contract
+    retention: usize,
+    /// Maximum size of all the entries
+    max_bytes: usize,
+    bytes: usize,
+    /// Entries of each contract, tagged with their sequence number, oldest first
+    entries: HashMap<ContractKey, VecDeque<(u64, JournalEntry)>>,
+    /// Sequence numbers of every entry recorded, oldest first. Entries dropped by the
+    /// per contract retention, or with their contract, are skipped when reaching the front.
+    order: VecDeque<(u64, ContractKey)>,
+    next_seq: u64,
+}
+
+impl UpdateJournal {
+    pub fn new(retention: usize) -> Self {
+        Self::with_max_bytes(retention, MAX_JOURNAL_BYTES)
+    }
+
+    fn with_max_bytes(retention: usize, max_bytes: usize) -> Self {
+        Self {
+            retention: retention.max(1),
+            max_bytes,
+            bytes: 0,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            next_seq: 0,
+        }
+    }
+
+    pub fn record(
+        &mut self,
+        key: &ContractKey,
+        updates: &[UpdateData<'_>],
+        previous_state: &WrappedState,
+        new_state: &WrappedState,
+    ) {
+        let entry = JournalEntry {
+            applied_at: Utc::now(),
+            updates: updates
+                .iter()
+                .filter_map(AppliedUpdate::from_update)
+                .collect(),
+            previous_state_hash: hash(previous_state.as_ref()),
+            state_hash: hash(new_state.as_ref()),
+            state_size: new_state.size(),
+        };
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.bytes += entry.size();
+        let entries = self.entries.entry(*key).or_default();
+        if entries.len() == self.retention {
+            if let Some((_, dropped)) = entries.pop_front() {
+                self.bytes -= dropped.size();
+            }
+        }
+        entries.push_back((seq, entry));
+        self.order.push_back((seq, *key));
+        self.drop_oldest();
+    }
+
+    /// Drops the whole journal of the contract, e.g. once the contract is removed.
+    pub fn remove(&mut self, key: &ContractKey) {
+        if let Some(entries) = self.entries.remove(key) {
+            self.bytes -= entries.iter().map(|(_, e)| e.size()).sum::<usize>();
+        }
+    }
+
+    /// Drops the oldest entries until back within the size limit, and forgets the sequence
+    /// numbers of the entries already dropped when they pile up.
+    fn drop_oldest(&mut self) {
+        while self.bytes > self.max_bytes {
+            let Some((seq, key)) = self.order.pop_front() else {
+                break;
+            };
+            let Some(entries) = self.entries.get_mut(&key) else {
+                continue;
+            };
+            if entries.front().is_some_and(|(front, _)| *front == seq) {
+                if let Some((_, dropped)) = entries.pop_front() {
+                    self.bytes -= dropped.size();
+                }
+                if entries.is_empty() {
+                    self.entries.remove(&key);
+                }
+            }
+        }
+        let live = self.entries.values().map(VecDeque::len).sum::<usize>();
+        if self.order.len() > 2 * live.max(self.retention) {
+            let entries = &self.entries;
+            self.order.retain(|(seq, key)| {
+                entries
+                    .get(key)
+                    .is_some_and(|entries| entries.iter().any(|(s, _)| s == seq))
+            });
+        }
+    }
+
+    /// Returns up to the `last` most recent entries for the contract, oldest first.
+    pub fn last(&self, key: &ContractKey, last: usize) -> Vec<JournalEntry> {
+        self.entries
+            .get(key)
+            .map(|entries| {
+                let skip = entries.len().saturating_sub(last);
+                entries
+                    .iter()
+                    .skip(skip)
+                    .map(|(_, entry)| entry.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounded_retention() {
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let mut journal = UpdateJournal::new(3);
+        let mut previous = WrappedState::new(vec![0]);
+        for i in 1..=5u8 {
+            let new = WrappedState::new(vec![i]);
+            journal.record(
+                &key,
+                &[UpdateData::Delta(StateDelta::from(vec![i]))],
+                &previous,
+                &new,
+            );
+            previous = new;
+        }
+
+        let entries = journal.last(&key, 10);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(
+            entries[0].updates,
+            vec![AppliedUpdate::Delta { delta: vec![3] }]
+        );
+        assert_eq!(entries[2].state_hash, hash(&[5]));
+        assert_eq!(entries[2].previous_state_hash, hash(&[4]));
+
+        let entries = journal.last(&key, 1);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            entries[0].updates,
+            vec![AppliedUpdate::Delta { delta: vec![5] }]
+        );
+
+        let other = ContractKey::from(ContractInstanceId::new([2; 32]));
+        assert!(journal.last(&other, 10).is_empty());
+
+        journal.remove(&key);
+        assert!(journal.last(&key, 10).is_empty());
+        assert_eq!(journal.bytes, 0);
+    }
+
+    #[test]
+    fn bounded_size() {
+        let first = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let second = ContractKey::from(ContractInstanceId::new([2; 32]));
+        let record = |journal: &mut UpdateJournal, key: &ContractKey, i: u8| {
+            journal.record(
+                key,
+                &[UpdateData::Delta(StateDelta::from(vec![i; 1024]))],
+                &WrappedState::new(vec![i]),
+                &WrappedState::new(vec![i + 1]),
+            )
+        };
+        let mut probe = UpdateJournal::new(10);
+        record(&mut probe, &first, 0);
+        // hashes differ slightly in length once encoded, leave some room for it
+        let max_bytes = probe.bytes * 3 + probe.bytes / 2;
+
+        let mut journal = UpdateJournal::with_max_bytes(10, max_bytes);
+        record(&mut journal, &first, 0);
+        record(&mut journal, &second, 1);
+        record(&mut journal, &first, 2);
+        record(&mut journal, &second, 3);
+        assert!(journal.bytes <= max_bytes);
+        // the oldest entry, of the first contract, went first
+        assert_eq!(journal.last(&first, 10).len(), 1);
+        assert_eq!(journal.last(&second, 10).len(), 2);
+
+        for i in 0..100 {
+            record(&mut journal, &first, i);
+        }
+        assert!(journal.bytes <= max_bytes);
+        assert!(journal.last(&second, 10).is_empty());
+        assert!(journal.order.len() <= 2 * 10);
+    }
+}
@@ -105,6 +105,15 @@ impl ContractExecutor for Executor<MockRuntime> {
                     .map_err(ExecutorError::other)?;
                 Ok(UpsertResult::Updated(incoming_state))
             }
+            (Either::Right(delta), None) => {
+                // deltas computed by the mock runtime are full states
+                let incoming_state = WrappedState::new(delta.as_ref().to_vec());
+                self.state_store
+                    .update(&key, incoming_state.clone())
+                    .await
+                    .map_err(ExecutorError::other)?;
+                Ok(UpsertResult::Updated(incoming_state))
+            }
             (update, contract) => unreachable!("{update:?}, {contract:?}"),
         }
     }
@@ -118,6 +127,109 @@ impl ContractExecutor for Executor<MockRuntime> {
     ) -> Result<(), Box<RequestError>> {
         Ok(())
     }
+
+    fn update_journal(
+        &mut self,
+        key: ContractKey,
+        last: usize,
+    ) -> Result<Vec<JournalEntry>, ExecutorError> {
+        self.journal_updates(&key, last)
+    }
+
+    async fn summarize_contract_state(
+        &mut self,
+        key: ContractKey,
+    ) -> Result<Option<StateSummary<'static>>, ExecutorError> {
+        let Ok(state) = self.state_store.get(&key).await else {
+            return Ok(None);
+        };
+        Ok(Some(StateSummary::from(
+            blake3::hash(state.as_ref()).as_bytes().to_vec(),
+        )))
+    }
+
+    async fn contract_state_delta(
+        &mut self,
+        key: ContractKey,
+        summary: StateSummary<'static>,
+    ) -> Result<Option<StateDelta<'static>>, ExecutorError> {
+        let Ok(state) = self.state_store.get(&key).await else {
+            return Ok(None);
+        };
+        if blake3::hash(state.as_ref()).as_bytes() == summary.as_ref() {
+            return Ok(None);
+        }
+        Ok(Some(StateDelta::from(state.as_ref().to_vec())))
+    }
+
+    fn create_snapshot(
+        &mut self,
+        snapshot_dir: PathBuf,
+        keep: usize,
+    ) -> impl Future<Output = Result<PathBuf, ExecutorError>> + Send + 'static {
+        self.write_snapshot(snapshot_dir, keep)
+    }
+
+    async fn enforce_storage_budget(
+        &mut self,
+        subscribed: HashSet<ContractInstanceId>,
+        max_size: Option<u64>,
+    ) -> Result<Vec<(ContractKey, u64)>, ExecutorError> {
+        self.evict_contracts(&subscribed, max_size).await
+    }
+
+    async fn pin_contract(
+        &mut self,
+        id: ContractInstanceId,
+        pin: bool,
+    ) -> Result<bool, ExecutorError> {
+        Ok(self.update_pin(id, pin).await)
+    }
+
+    async fn pin_local_put(&mut self, id: ContractInstanceId) {
+        self.storage_budget.pin_local_put(id).await
+    }
+
+    fn pinned_contracts(&self) -> Vec<PinnedContract> {
+        self.storage_budget.pinned()
+    }
+
+    fn resize_caches(&mut self, state_cache_size: u64, code_cache_size: u64) {
+        self.state_store.set_cache_size(state_cache_size);
+        self.runtime
+            .contract_store
+            .set_cache_size(code_cache_size.min(i64::MAX as u64) as i64);
+    }
+
+    fn contract_successor(&self, id: &ContractInstanceId) -> Option<ContractKey> {
+        self.upgrades.successor(id)
+    }
+
+    fn resolve_prefix(&self, prefix: &str) -> Resolution {
+        self.state_store.resolve_prefix(prefix)
+    }
+
+    fn secrets_consents(&self) -> Vec<SecretsConsent> {
+        vec![]
+    }
+
+    fn resolve_secrets_consent(
+        &mut self,
+        _request_id: u32,
+        _allowed: bool,
+    ) -> Result<bool, ExecutorError> {
+        Ok(false)
+    }
+
+    async fn validate_put(
+        &mut self,
+        _contract: ContractContainer,
+        _state: WrappedState,
+        _related_contracts: RelatedContracts<'static>,
+    ) -> Result<(), ExecutorError> {
+        // the mock runtime does not execute contracts
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -151,4 +263,51 @@ mod test {
         assert_eq!(counter, 1);
         Ok(())
     }
+
+    async fn mock_executor(dir: &std::path::Path) -> anyhow::Result<Executor<MockRuntime>> {
+        let state_store_path = dir.join("state_store");
+        std::fs::create_dir_all(&state_store_path)?;
+        let contract_store = ContractStore::new(dir.join("contracts"), u16::MAX as i64)?;
+        let state_store =
+            StateStore::new(Storage::new(&state_store_path).await?, u16::MAX as u32).unwrap();
+        Executor::new(
+            state_store,
+            || Ok(()),
+            OperationMode::Local,
+            MockRuntime { contract_store },
+            None,
+        )
+        .await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn sync_diverged_state() -> anyhow::Result<()> {
+        let (dir_a, dir_b) = (tempfile::tempdir()?, tempfile::tempdir()?);
+        let mut a = mock_executor(dir_a.path()).await?;
+        let mut b = mock_executor(dir_b.path()).await?;
+        let contract = ContractContainer::Wasm(ContractWasmAPIVersion::V1(WrappedContract::new(
+            Arc::new(ContractCode::from(vec![0, 1, 2, 3])),
+            Parameters::from(vec![4, 5]),
+        )));
+        let key = contract.key();
+        for (executor, state) in [(&mut a, vec![1]), (&mut b, vec![1, 2])] {
+            executor
+                .upsert_contract_state(
+                    key,
+                    Either::Left(WrappedState::new(state)),
+                    RelatedContracts::default(),
+                    Some(contract.clone()),
+                )
+                .await?;
+        }
+
+        let summary = a.summarize_contract_state(key).await?.unwrap();
+        let delta = b.contract_state_delta(key, summary).await?.unwrap();
+        a.upsert_contract_state(key, Either::Right(delta), RelatedContracts::default(), None)
+            .await?;
+
+        let summary = a.summarize_contract_state(key).await?.unwrap();
+        assert!(b.contract_state_delta(key, summary).await?.is_none());
+        Ok(())
+    }
 }
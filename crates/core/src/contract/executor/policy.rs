@@ -0,0 +1,386 @@
+//! Execution policy for contract code.
+//!
+//! Gateways and other public nodes may not want to execute arbitrary contract code. The policy
+//! decides, per contract, whether the node runs the contract, only stores and forwards its state
+//! without ever executing it (metadata-only), or refuses it altogether.
+//!
+//! Contracts can be matched by instance id or code hash, and publishers can sign their contract
+//! code: the signature is embedded in the `freenet-signature` custom section of the WASM module,
+//! and is a PKCS#1 v1.5 signature of the blake3 hash of the module without that section.
+//!
+//! The states of metadata-only contracts can't be validated, so only the states put or updated
+//! by the clients of the node are stored; states received from other peers are refused.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use freenet_stdlib::prelude::*;
+use rsa::{pkcs8::DecodePublicKey, Pkcs1v15Sign, RsaPublicKey};
+
+use crate::{config::ContractPolicyConfig, util::wasm_sections::find_custom_section};
+
+const SIGNATURE_SECTION: &[u8] = b"freenet-signature";
+/// Maximum number of contracts whose decision is cached.
+const MAX_CACHED_DECISIONS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PolicyDecision {
+    Execute,
+    /// Store and forward the contract state, but never execute the contract code.
+    MetadataOnly,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub(crate) enum PolicyRefusal {
+    #[error("contract {0} is denied by the node policy")]
+    Denied(ContractKey),
+    #[error("contract {0} is not allowed by the node policy")]
+    NotAllowed(ContractKey),
+    #[error("contract {0} is not executed by this node, only full states are accepted")]
+    ExecutionDisabled(ContractKey),
+    #[error(
+        "contract {0} is not executed by this node, only states from its clients are accepted"
+    )]
+    UntrustedState(ContractKey),
+}
+
+impl PolicyRefusal {
+    pub fn key(&self) -> ContractKey {
+        match self {
+            PolicyRefusal::Denied(key)
+            | PolicyRefusal::NotAllowed(key)
+            | PolicyRefusal::ExecutionDisabled(key)
+            | PolicyRefusal::UntrustedState(key) => *key,
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct ContractPolicy {
+    /// Instance ids or code hashes allowed to run.
+    allowed: HashSet<[u8; 32]>,
+    /// Instance ids or code hashes never allowed to run.
+    denied: HashSet<[u8; 32]>,
+    trusted_publishers: Vec<RsaPublicKey>,
+    /// Store and forward contracts flagged by the policy instead of refusing them.
+    metadata_only: bool,
+    decisions: Decisions,
+}
+
+type Decision = Result<PolicyDecision, PolicyRefusal>;
+
+/// Decisions cached per contract, the least recently checked contracts are dropped first.
+#[derive(Default)]
+struct Decisions {
+    /// Decisions along with when they were last checked.
+    decisions: HashMap<ContractKey, (Decision, u64)>,
+    /// The cached contracts by their last check, the least recent first.
+    recency: BTreeMap<u64, ContractKey>,
+    checks: u64,
+}
+
+impl Decisions {
+    fn contains_key(&self, key: &ContractKey) -> bool {
+        self.decisions.contains_key(key)
+    }
+
+    fn peek(&self, key: &ContractKey) -> Option<&Decision> {
+        self.decisions.get(key).map(|(decision, _)| decision)
+    }
+
+    /// Returns the decision about the contract, marking it as the most recently checked.
+    fn get(&mut self, key: &ContractKey) -> Option<Decision> {
+        self.checks += 1;
+        let checks = self.checks;
+        let (decision, last_check) = self.decisions.get_mut(key)?;
+        self.recency.remove(last_check);
+        *last_check = checks;
+        self.recency.insert(checks, *key);
+        Some(decision.clone())
+    }
+
+    fn insert(&mut self, key: ContractKey, decision: Decision) {
+        self.checks += 1;
+        let checks = self.checks;
+        match self.decisions.get(&key) {
+            Some((_, last_check)) => {
+                self.recency.remove(last_check);
+            }
+            None if self.decisions.len() >= MAX_CACHED_DECISIONS => {
+                if let Some((_, least_recent)) = self.recency.pop_first() {
+                    self.decisions.remove(&least_recent);
+                }
+            }
+            None => {}
+        }
+        self.recency.insert(checks, key);
+        self.decisions.insert(key, (decision, checks));
+    }
+}
+
+impl ContractPolicy {
+    pub fn from_config(config: &ContractPolicyConfig) -> anyhow::Result<Self> {
+        fn parse_ids(ids: &[String]) -> anyhow::Result<HashSet<[u8; 32]>> {
+            ids.iter()
+                .map(|id| {
+                    bs58::decode(id)
+                        .into_vec()
+                        .ok()
+                        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                        .ok_or_else(|| anyhow::anyhow!("invalid contract id or code hash `{id}`"))
+                })
+                .collect()
+        }
+
+        let trusted_publishers = config
+            .trusted_publishers
+            .iter()
+            .map(|path| {
+                let pem = std::fs::read_to_string(path)?;
+                RsaPublicKey::from_public_key_pem(&pem).map_err(|err| {
+                    anyhow::anyhow!("failed loading publisher key from {path:?}: {err}")
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            allowed: parse_ids(&config.allowed_contracts)?,
+            denied: parse_ids(&config.denied_contracts)?,
+            trusted_publishers,
+            metadata_only: config.metadata_only,
+            decisions: Decisions::default(),
+        })
+    }
+
+    fn is_restricted(&self) -> bool {
+        !self.allowed.is_empty() || !self.trusted_publishers.is_empty()
+    }
+
+    /// Whether the contract code is required to decide about this contract.
+    pub fn needs_code(&self, key: &ContractKey) -> bool {
+        !self.decisions.contains_key(key) && (self.is_restricted() || !self.denied.is_empty())
+    }
+
+    /// Decides how to handle the contract given its WASM code.
+    ///
+    /// Decisions are cached per contract, so the code is only needed the first time a contract
+    /// is seen by the node.
+    pub fn check(
+        &mut self,
+        key: &ContractKey,
+        code: Option<&[u8]>,
+    ) -> Result<PolicyDecision, PolicyRefusal> {
+        if !self.is_restricted() && self.denied.is_empty() {
+            return Ok(PolicyDecision::Execute);
+        }
+        if let Some(decision) = self.decisions.get(key) {
+            return decision;
+        }
+        let code_hash = key
+            .code_hash()
+            .map(|h| **h)
+            .or_else(|| code.map(|c| *ContractCode::gen_hash(c)));
+        let is_listed = |list: &HashSet<[u8; 32]>| {
+            list.contains(&**key.id()) || code_hash.is_some_and(|h| list.contains(&h))
+        };
+
+        let flagged = if is_listed(&self.denied) {
+            Some(PolicyRefusal::Denied(*key))
+        } else if !self.is_restricted() || is_listed(&self.allowed) {
+            None
+        } else {
+            match code {
+                Some(code) if self.signed_by_trusted_publisher(code) => None,
+                // without the code there is no way to verify the publisher, don't cache
+                None => return Err(PolicyRefusal::NotAllowed(*key)),
+                Some(_) => Some(PolicyRefusal::NotAllowed(*key)),
+            }
+        };
+        let decision = match flagged {
+            None => Ok(PolicyDecision::Execute),
+            Some(_) if self.metadata_only => {
+                tracing::info!(contract = %key, "contract flagged by policy, handling as metadata-only");
+                Ok(PolicyDecision::MetadataOnly)
+            }
+            Some(refusal) => {
+                tracing::info!(contract = %key, "{refusal}");
+                Err(refusal)
+            }
+        };
+        self.decisions.insert(*key, decision.clone());
+        decision
+    }
+
+    /// Returns whether the contract is known to be handled as metadata-only.
+    pub fn is_metadata_only(&self, key: &ContractKey) -> bool {
+        matches!(
+            self.decisions.peek(key),
+            Some(Ok(PolicyDecision::MetadataOnly))
+        )
+    }
+
+    fn signed_by_trusted_publisher(&self, code: &[u8]) -> bool {
+        let Some((unsigned, signature)) = split_signature(code) else {
+            return false;
+        };
+        let hash = blake3::hash(&unsigned);
+        self.trusted_publishers.iter().any(|publisher| {
+            publisher
+                .verify(Pkcs1v15Sign::new_unprefixed(), hash.as_bytes(), signature)
+                .is_ok()
+        })
+    }
+}
+
+/// Splits a WASM module into the module without the signature section and the signature.
+fn split_signature(code: &[u8]) -> Option<(Vec<u8>, &[u8])> {
+    let section = find_custom_section(code, SIGNATURE_SECTION)?;
+    let mut unsigned = Vec::with_capacity(code.len() - section.range.len());
+    unsigned.extend_from_slice(&code[..section.range.start]);
+    unsigned.extend_from_slice(&code[section.range.end..]);
+    Some((unsigned, section.content))
+}
+
+#[cfg(test)]
+mod tests {
+    use rsa::RsaPrivateKey;
+
+    use super::*;
+
+    fn leb128(mut n: usize) -> Vec<u8> {
+        let mut encoded = vec![];
+        loop {
+            let byte = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                encoded.push(byte);
+                return encoded;
+            }
+            encoded.push(byte | 0x80);
+        }
+    }
+
+    fn custom_section(name: &[u8], content: &[u8]) -> Vec<u8> {
+        let mut payload = leb128(name.len());
+        payload.extend_from_slice(name);
+        payload.extend_from_slice(content);
+        let mut section = vec![0];
+        section.extend(leb128(payload.len()));
+        section.extend(payload);
+        section
+    }
+
+    fn module() -> Vec<u8> {
+        let mut module = b"\0asm\x01\0\0\0".to_vec();
+        module.extend(custom_section(b"name", b"test"));
+        module
+    }
+
+    fn key_for(code: &[u8]) -> ContractKey {
+        let code = ContractCode::from(code.to_vec());
+        ContractKey::from_params_and_code(Parameters::from(vec![]), &code)
+    }
+
+    #[test]
+    fn allow_and_deny_lists() {
+        let code = module();
+        let key = key_for(&code);
+        let code_hash = key.code_hash().unwrap().encode();
+
+        let mut policy = ContractPolicy::default();
+        assert_eq!(policy.check(&key, Some(&code)), Ok(PolicyDecision::Execute));
+
+        let mut policy = ContractPolicy::from_config(&ContractPolicyConfig {
+            denied_contracts: vec![key.id().encode()],
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(matches!(
+            policy.check(&key, Some(&code)),
+            Err(PolicyRefusal::Denied(_))
+        ));
+        let error = crate::contract::ExecutorError::from(PolicyRefusal::Denied(key));
+        assert_eq!(error.policy_refusal(), Some(&PolicyRefusal::Denied(key)));
+
+        let mut policy = ContractPolicy::from_config(&ContractPolicyConfig {
+            allowed_contracts: vec![code_hash],
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(policy.check(&key, Some(&code)), Ok(PolicyDecision::Execute));
+        let other = key_for(b"\0asm\x01\0\0\0");
+        assert!(matches!(
+            policy.check(&other, None),
+            Err(PolicyRefusal::NotAllowed(_))
+        ));
+    }
+
+    #[test]
+    fn metadata_only() {
+        let code = module();
+        let key = key_for(&code);
+        let mut policy = ContractPolicy::from_config(&ContractPolicyConfig {
+            denied_contracts: vec![key.id().encode()],
+            metadata_only: true,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(
+            policy.check(&key, Some(&code)),
+            Ok(PolicyDecision::MetadataOnly)
+        );
+        assert!(policy.is_metadata_only(&key));
+    }
+
+    #[test]
+    fn bounded_decisions() {
+        let key = |i: usize| {
+            let mut id = [0; 32];
+            id[..8].copy_from_slice(&i.to_le_bytes());
+            ContractKey::from(ContractInstanceId::new(id))
+        };
+        let mut decisions = Decisions::default();
+        for i in 0..MAX_CACHED_DECISIONS {
+            decisions.insert(key(i), Ok(PolicyDecision::Execute));
+        }
+        // checked again, the first contract is no longer the least recent one
+        assert!(decisions.get(&key(0)).is_some());
+        decisions.insert(key(MAX_CACHED_DECISIONS), Ok(PolicyDecision::Execute));
+        assert_eq!(decisions.decisions.len(), MAX_CACHED_DECISIONS);
+        assert_eq!(decisions.recency.len(), MAX_CACHED_DECISIONS);
+        assert!(decisions.contains_key(&key(0)));
+        assert!(!decisions.contains_key(&key(1)));
+    }
+
+    #[test]
+    fn trusted_publisher_signature() {
+        let publisher = RsaPrivateKey::new(&mut rsa::rand_core::OsRng, 2048).unwrap();
+        let unsigned = module();
+        let signature = publisher
+            .sign(
+                Pkcs1v15Sign::new_unprefixed(),
+                blake3::hash(&unsigned).as_bytes(),
+            )
+            .unwrap();
+        let mut signed = unsigned.clone();
+        signed.extend(custom_section(SIGNATURE_SECTION, &signature));
+        assert_eq!(
+            split_signature(&signed),
+            Some((unsigned.clone(), signature.as_slice()))
+        );
+
+        let mut policy = ContractPolicy {
+            trusted_publishers: vec![publisher.to_public_key()],
+            ..Default::default()
+        };
+        let key = key_for(&signed);
+        assert_eq!(
+            policy.check(&key, Some(&signed)),
+            Ok(PolicyDecision::Execute)
+        );
+
+        let key = key_for(&unsigned);
+        assert!(matches!(
+            policy.check(&key, Some(&unsigned)),
+            Err(PolicyRefusal::NotAllowed(_))
+        ));
+    }
+}
@@ -1,3 +1,5 @@
+use super::policy::PolicyDecision;
+use super::upgrade::{ContractUpgrades, Recorded, UpgradeDeclaration, UpgradeError};
 use super::*;
 use super::{
     ContractExecutor, ContractRequest, ContractResponse, ExecutorError, ExecutorHalve,
@@ -20,9 +22,11 @@ impl ContractExecutor for Executor<Runtime> {
         &mut self,
         key: ContractKey,
         update: Either<WrappedState, StateDelta<'static>>,
-        related_contracts: RelatedContracts<'static>,
+        mut related_contracts: RelatedContracts<'static>,
         code: Option<ContractContainer>,
     ) -> Result<UpsertResult, ExecutorError> {
+        // every validation of this upsert, including the related contracts, sees the same time
+        self.runtime.set_time(chrono::Utc::now());
         let params = if let Some(code) = &code {
             code.params()
         } else {
@@ -38,6 +42,29 @@ impl ContractExecutor for Executor<Runtime> {
                 })?
         };
 
+        if self.check_policy(&key, &params, code.as_ref())? == PolicyDecision::MetadataOnly {
+            // only the puts of the clients of this node are trusted, other peers' aren't
+            let trusted =
+                matches!(&update, Either::Left(state) if self.validated_puts.take(&key, state));
+            return self
+                .store_without_executing(key, params, update, code, trusted)
+                .await;
+        }
+
+        // new versions of upgraded contracts are verified the first time they are seen
+        let upgrade = match &code {
+            Some(code)
+                if self
+                    .runtime
+                    .contract_store
+                    .fetch_contract(&key, &params)
+                    .is_none() =>
+            {
+                self.verify_upgrade(code, &mut related_contracts).await?
+            }
+            _ => None,
+        };
+
         let remove_if_fail = if self
             .runtime
             .contract_store
@@ -47,6 +74,11 @@ impl ContractExecutor for Executor<Runtime> {
             let code = code.ok_or_else(|| {
                 ExecutorError::request(StdContractError::MissingContract { key: key.into() })
             })?;
+            if let Either::Left(state) = &update {
+                self.runtime
+                    .check_contract(&code, state.as_ref())
+                    .map_err(put_rejection(key))?;
+            }
             self.runtime
                 .contract_store
                 .store_contract(code.clone())
@@ -58,21 +90,33 @@ impl ContractExecutor for Executor<Runtime> {
 
         let mut updates = match update {
             Either::Left(incoming_state) => {
-                let result = self
-                    .runtime
-                    .validate_state(&key, &params, &incoming_state, &related_contracts)
-                    .map_err(|err| {
-                        if remove_if_fail {
-                            let _ = self.runtime.contract_store.remove_contract(&key);
-                        }
-                        ExecutorError::execution(err, None)
-                    })?;
+                let result = run_blocking(|| {
+                    self.runtime
+                        .validate_state(&key, &params, &incoming_state, &related_contracts)
+                })
+                .map_err(|err| {
+                    if remove_if_fail {
+                        let _ = self.runtime.contract_store.remove_contract(&key);
+                    }
+                    ExecutorError::execution(err, None)
+                })?;
                 match result {
                     ValidateResult::Valid => {
                         self.state_store
                             .store(key, incoming_state.clone(), params.clone())
                             .await
                             .map_err(ExecutorError::other)?;
+                        if let Some(upgrade) = upgrade {
+                            self.complete_upgrade(upgrade, key).await?;
+                            if let Err(err) = self
+                                .send_update_notification(&key, &params, &incoming_state)
+                                .await
+                            {
+                                tracing::error!(
+                                    "Failed while sending notifications for contract {key}: {err}"
+                                );
+                            }
+                        }
                     }
                     ValidateResult::Invalid => {
                         return Err(ExecutorError::request(StdContractError::invalid_put(key)));
@@ -133,10 +177,11 @@ impl ContractExecutor for Executor<Runtime> {
                 }));
             }
         };
-        match self
-            .runtime
-            .validate_state(&key, &params, &updated_state, &related_contracts)
-            .map_err(|e| ExecutorError::execution(e, None))?
+        match run_blocking(|| {
+            self.runtime
+                .validate_state(&key, &params, &updated_state, &related_contracts)
+        })
+        .map_err(|e| ExecutorError::execution(e, None))?
         {
             ValidateResult::Valid => {
                 if updated_state.as_ref() == current_state.as_ref() {
@@ -189,6 +234,191 @@ impl ContractExecutor for Executor<Runtime> {
         }
         Ok(())
     }
+
+    fn update_journal(
+        &mut self,
+        key: ContractKey,
+        last: usize,
+    ) -> Result<Vec<JournalEntry>, ExecutorError> {
+        self.journal_updates(&key, last)
+    }
+
+    async fn summarize_contract_state(
+        &mut self,
+        key: ContractKey,
+    ) -> Result<Option<StateSummary<'static>>, ExecutorError> {
+        let Some((params, state)) = self.executable_state(&key).await? else {
+            return Ok(None);
+        };
+        let summary = run_blocking(|| self.runtime.summarize_state(&key, &params, &state))
+            .map_err(|e| ExecutorError::execution(e, None))?;
+        Ok(Some(summary))
+    }
+
+    async fn contract_state_delta(
+        &mut self,
+        key: ContractKey,
+        summary: StateSummary<'static>,
+    ) -> Result<Option<StateDelta<'static>>, ExecutorError> {
+        let Some((params, state)) = self.executable_state(&key).await? else {
+            return Ok(None);
+        };
+        let own_summary = run_blocking(|| self.runtime.summarize_state(&key, &params, &state))
+            .map_err(|e| ExecutorError::execution(e, None))?;
+        if own_summary == summary {
+            return Ok(None);
+        }
+        let delta = run_blocking(|| {
+            self.runtime
+                .get_state_delta(&key, &params, &state, &summary)
+        })
+        .map_err(|e| ExecutorError::execution(e, None))?;
+        Ok(Some(delta))
+    }
+
+    fn create_snapshot(
+        &mut self,
+        snapshot_dir: PathBuf,
+        keep: usize,
+    ) -> impl Future<Output = Result<PathBuf, ExecutorError>> + Send + 'static {
+        self.write_snapshot(snapshot_dir, keep)
+    }
+
+    async fn enforce_storage_budget(
+        &mut self,
+        subscribed: HashSet<ContractInstanceId>,
+        max_size: Option<u64>,
+    ) -> Result<Vec<(ContractKey, u64)>, ExecutorError> {
+        self.evict_contracts(&subscribed, max_size).await
+    }
+
+    async fn pin_contract(
+        &mut self,
+        id: ContractInstanceId,
+        pin: bool,
+    ) -> Result<bool, ExecutorError> {
+        Ok(self.update_pin(id, pin).await)
+    }
+
+    async fn pin_local_put(&mut self, id: ContractInstanceId) {
+        self.storage_budget.pin_local_put(id).await
+    }
+
+    fn pinned_contracts(&self) -> Vec<PinnedContract> {
+        self.storage_budget.pinned()
+    }
+
+    fn resize_caches(&mut self, state_cache_size: u64, code_cache_size: u64) {
+        self.state_store.set_cache_size(state_cache_size);
+        self.runtime.set_code_cache_size(code_cache_size);
+    }
+
+    fn contract_successor(&self, id: &ContractInstanceId) -> Option<ContractKey> {
+        self.upgrades.successor(id)
+    }
+
+    fn resolve_prefix(&self, prefix: &str) -> Resolution {
+        self.state_store.resolve_prefix(prefix)
+    }
+
+    fn secrets_consents(&self) -> Vec<SecretsConsent> {
+        self.runtime.pending_secrets_consents()
+    }
+
+    fn resolve_secrets_consent(
+        &mut self,
+        request_id: u32,
+        allowed: bool,
+    ) -> Result<bool, ExecutorError> {
+        self.runtime
+            .resolve_secrets_consent(request_id, allowed)
+            .map_err(ExecutorError::other)
+    }
+
+    async fn validate_put(
+        &mut self,
+        contract: ContractContainer,
+        state: WrappedState,
+        related_contracts: RelatedContracts<'static>,
+    ) -> Result<(), ExecutorError> {
+        let key = contract.key();
+        self.runtime
+            .check_contract(&contract, state.as_ref())
+            .map_err(put_rejection(key))?;
+        self.runtime.set_time(chrono::Utc::now());
+        if self.check_policy(&key, &contract.params(), Some(&contract))? != PolicyDecision::Execute
+        {
+            // left for the peers which execute the contract to validate, but trusted by this
+            // node once the put reaches it, as it comes from one of its clients
+            self.validated_puts.insert(key, &state);
+            return Ok(());
+        }
+        let result = run_blocking(|| {
+            self.runtime
+                .validate_initial_state(&contract, &state, &related_contracts)
+        })
+        .map_err(|err| ExecutorError::execution(err, None))?;
+        match result {
+            ValidateResult::Invalid => Err(put_rejection(key)(PutRejection::InvalidState)),
+            ValidateResult::Valid => {
+                self.validated_puts.insert(key, &state);
+                Ok(())
+            }
+            // related contracts are fetched by the peers storing the contract
+            ValidateResult::RequestRelated(_) => Ok(()),
+        }
+    }
+}
+
+type StoredState = Option<(Parameters<'static>, WrappedState)>;
+
+/// Reads the states contracts request from other contracts straight from the storage.
+///
+/// Host functions run synchronously in the middle of a contract execution, which may be
+/// within the async runtime, e.g. on a current thread runtime, so they can't block on the
+/// storage futures. The reads are done by a dedicated thread instead, with a runtime of its
+/// own, and the state is received from it over a channel.
+struct StorageStateSource {
+    reads: std::sync::mpsc::Sender<(ContractKey, std::sync::mpsc::SyncSender<StoredState>)>,
+}
+
+impl StorageStateSource {
+    fn spawn(storage: Storage) -> std::io::Result<Self> {
+        let (reads, requested) =
+            std::sync::mpsc::channel::<(ContractKey, std::sync::mpsc::SyncSender<StoredState>)>();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        std::thread::Builder::new()
+            .name("contract-state-reads".to_owned())
+            .spawn(move || {
+                // ends once the state source, and with it the sender, is dropped
+                for (key, reply) in requested {
+                    let read = async {
+                        let state = storage.get(&key).await?;
+                        let params = storage.get_params(&key).await?;
+                        Ok::<_, <Storage as StateStorage>::Error>(params.zip(state))
+                    };
+                    let state = rt
+                        .block_on(read)
+                        .map_err(|err| {
+                            tracing::warn!(contract = %key, "failed reading contract state: {err}");
+                        })
+                        .ok()
+                        .flatten();
+                    let _ = reply.send(state);
+                }
+            })?;
+        Ok(Self { reads })
+    }
+}
+
+impl ContractStateSource for StorageStateSource {
+    fn state(&self, key: &ContractKey) -> StoredState {
+        let (reply, state) = std::sync::mpsc::sync_channel(1);
+        self.reads.send((*key, reply)).ok()?;
+        state.recv().ok().flatten()
+    }
 }
 
 impl Executor<Runtime> {
@@ -196,9 +426,32 @@ impl Executor<Runtime> {
         config: Arc<Config>,
         event_loop_channel: Option<ExecutorToEventLoopChannel<ExecutorHalve>>,
     ) -> anyhow::Result<Self> {
-        let (contract_store, delegate_store, secret_store, state_store) =
+        let (contract_store, delegate_store, secret_store, mut state_store) =
             Self::get_stores(&config).await?;
-        let rt = Runtime::build(contract_store, delegate_store, secret_store, false).unwrap();
+        state_store.load_usage().await?;
+        let runtime_config = RuntimeConfig {
+            engine: config.wasm_engine,
+            time_epoch: Duration::from_secs(config.contract_time_epoch),
+            ..Default::default()
+        };
+        let mut rt = Runtime::build_with_config(
+            contract_store,
+            delegate_store,
+            secret_store,
+            false,
+            runtime_config,
+        )
+        .map_err(|err| anyhow::anyhow!("failed initializing the wasm runtime: {err}"))?;
+        rt.set_state_source(Arc::new(StorageStateSource::spawn(
+            state_store.storage().clone(),
+        )?));
+        let upgrades = ContractUpgrades::load(config.contracts_dir().join("upgrades.json"));
+        let journal_retention = config.update_journal_retention;
+        let policy = ContractPolicy::from_config(&config.contract_policy)?;
+        let storage_budget = StorageBudget::from_config(
+            &config.storage_budget,
+            Some(config.config_dir().join("pinned-contracts.json")),
+        );
         Executor::new(
             state_store,
             move || {
@@ -213,6 +466,130 @@ impl Executor<Runtime> {
             event_loop_channel,
         )
         .await
+        .map(|executor| {
+            executor
+                .with_update_journal(journal_retention)
+                .with_policy(policy)
+                .with_storage_budget(storage_budget)
+                .with_upgrades(upgrades)
+        })
+    }
+
+    fn check_policy(
+        &mut self,
+        key: &ContractKey,
+        params: &Parameters<'_>,
+        code: Option<&ContractContainer>,
+    ) -> Result<PolicyDecision, ExecutorError> {
+        let stored;
+        let code = match code {
+            Some(code) => Some(code),
+            None if self.policy.needs_code(key) => {
+                stored = self.runtime.contract_store.fetch_contract(key, params);
+                stored.as_ref()
+            }
+            None => None,
+        };
+        let wasm = code.and_then(|code| match code {
+            ContractContainer::Wasm(ContractWasmAPIVersion::V1(contract)) => {
+                Some(contract.code().data())
+            }
+            _ => None,
+        });
+        Ok(self.policy.check(key, wasm)?)
+    }
+
+    /// Parameters and current state of a contract, `None` if the contract is unknown or the node
+    /// policy does not allow to execute it.
+    async fn executable_state(
+        &mut self,
+        key: &ContractKey,
+    ) -> Result<Option<(Parameters<'static>, WrappedState)>, ExecutorError> {
+        let Some(params) = self
+            .state_store
+            .get_params(key)
+            .await
+            .map_err(ExecutorError::other)?
+        else {
+            return Ok(None);
+        };
+        if self.check_policy(key, &params, None)? != PolicyDecision::Execute {
+            return Ok(None);
+        }
+        let state = self
+            .state_store
+            .get(key)
+            .await
+            .map_err(ExecutorError::other)?;
+        Ok(Some((params, state)))
+    }
+
+    /// Stores and forwards the state of a contract the node policy does not allow to execute.
+    ///
+    /// The state can't be validated nor merged, so only full state replacements are accepted,
+    /// and only when `trusted`, i.e. they come from a client of this node. Any other state is
+    /// refused, keeping the stored one, so it is not forwarded either.
+    async fn store_without_executing(
+        &mut self,
+        key: ContractKey,
+        params: Parameters<'static>,
+        update: Either<WrappedState, StateDelta<'static>>,
+        code: Option<ContractContainer>,
+        trusted: bool,
+    ) -> Result<UpsertResult, ExecutorError> {
+        let Either::Left(incoming_state) = update else {
+            return Err(PolicyRefusal::ExecutionDisabled(key).into());
+        };
+        if let Ok(current_state) = self.state_store.get(&key).await {
+            if current_state.as_ref() == incoming_state.as_ref() {
+                return Ok(UpsertResult::NoChange);
+            }
+        }
+        if !trusted {
+            tracing::debug!(contract = %key, "refusing untrusted state of a metadata-only contract");
+            return Err(PolicyRefusal::UntrustedState(key).into());
+        }
+        if let Some(code) = code {
+            // keep the code around so it can be served to other peers
+            self.runtime
+                .contract_store
+                .store_contract(code)
+                .map_err(ExecutorError::other)?;
+        }
+        self.state_store
+            .store(key, incoming_state.clone(), params.clone())
+            .await
+            .map_err(ExecutorError::other)?;
+        if let Err(err) = self
+            .send_update_notification(&key, &params, &incoming_state)
+            .await
+        {
+            tracing::error!("Failed while sending notifications for contract {key}: {err}");
+        }
+        Ok(UpsertResult::Updated(incoming_state))
+    }
+
+    /// Applies a client update to a contract the node policy does not allow to execute.
+    async fn update_without_executing(
+        &mut self,
+        key: ContractKey,
+        params: Parameters<'static>,
+        update: UpdateData<'_>,
+    ) -> Response {
+        let UpdateData::State(state) = update else {
+            return Err(PolicyRefusal::ExecutionDisabled(key).into());
+        };
+        let state = WrappedState::new(state.into_bytes());
+        let result = self
+            .store_without_executing(key, params, Either::Left(state.clone()), None, true)
+            .await?;
+        if let (UpsertResult::Updated(new_state), OperationMode::Network) = (result, self.mode) {
+            let request = UpdateContract { key, new_state };
+            let _op: operations::update::UpdateResult = self.op_request(request).await?;
+        }
+        // without executing the contract there is nothing smaller than the state to summarize it
+        let summary = StateSummary::from(state.as_ref().to_vec());
+        Ok(ContractResponse::UpdateResponse { key, summary }.into())
     }
 
     pub fn register_contract_notifier(
@@ -302,6 +679,7 @@ impl Executor<Runtime> {
         cli_id: ClientId,
         updates: Option<mpsc::UnboundedSender<Result<HostResponse, WsClientError>>>,
     ) -> Response {
+        self.runtime.set_time(chrono::Utc::now());
         match req {
             ContractRequest::Put {
                 contract,
@@ -317,20 +695,30 @@ impl Executor<Runtime> {
             ContractRequest::Get {
                 key,
                 return_contract_code,
-            } => match self.perform_contract_get(return_contract_code, key).await {
-                Ok((state, contract)) => Ok(ContractResponse::GetResponse {
-                    key,
-                    state: state.ok_or_else(|| {
-                        ExecutorError::request(StdContractError::Get {
-                            key,
-                            cause: "contract state not found".into(),
-                        })
-                    })?,
-                    contract,
+            } => {
+                // upgraded contracts are redirected to their latest version
+                let key = match self.upgrades.successor(key.id()) {
+                    Some(successor) => {
+                        tracing::debug!(contract = %key, %successor, "redirecting get to successor");
+                        successor
+                    }
+                    None => key,
+                };
+                match self.perform_contract_get(return_contract_code, key).await {
+                    Ok((state, contract)) => Ok(ContractResponse::GetResponse {
+                        key,
+                        state: state.ok_or_else(|| {
+                            ExecutorError::request(StdContractError::Get {
+                                key,
+                                cause: "contract state not found".into(),
+                            })
+                        })?,
+                        contract,
+                    }
+                    .into()),
+                    Err(err) => Err(err),
                 }
-                .into()),
-                Err(err) => Err(err),
-            },
+            }
             ContractRequest::Subscribe { key, summary } => {
                 tracing::debug!("subscribing to contract {key}");
                 let updates = updates.ok_or_else(|| {
@@ -374,7 +762,7 @@ impl Executor<Runtime> {
                         .or_default()
                         .push(*contract);
                 }
-                match self.runtime.register_delegate(delegate, cipher, nonce) {
+                match run_blocking(|| self.runtime.register_delegate(delegate, cipher, nonce)) {
                     Ok(_) => Ok(DelegateResponse {
                         key,
                         values: Vec::new(),
@@ -405,12 +793,14 @@ impl Executor<Runtime> {
                         .get(&key)
                         .and_then(|contracts| contracts.iter().find(|c| *c == contract))
                 });
-                match self.runtime.inbound_app_message(
-                    &key,
-                    &params,
-                    attested.map(|c| c.as_bytes()),
-                    vec![InboundDelegateMsg::GetSecretRequest(get_request)],
-                ) {
+                match run_blocking(|| {
+                    self.runtime.inbound_app_message(
+                        &key,
+                        &params,
+                        attested,
+                        vec![InboundDelegateMsg::GetSecretRequest(get_request)],
+                    )
+                }) {
                     Ok(values) => Ok(HostResponse::DelegateResponse { key, values }),
                     Err(err) => Err(ExecutorError::execution(
                         err,
@@ -428,15 +818,17 @@ impl Executor<Runtime> {
                         .get(&key)
                         .and_then(|contracts| contracts.iter().find(|c| *c == contract))
                 });
-                match self.runtime.inbound_app_message(
-                    &key,
-                    &params,
-                    attested.map(|c| c.as_bytes()),
-                    inbound
-                        .into_iter()
-                        .map(InboundDelegateMsg::into_owned)
-                        .collect(),
-                ) {
+                match run_blocking(|| {
+                    self.runtime.inbound_app_message(
+                        &key,
+                        &params,
+                        attested,
+                        inbound
+                            .into_iter()
+                            .map(InboundDelegateMsg::into_owned)
+                            .collect(),
+                    )
+                }) {
                     Ok(values) => Ok(HostResponse::DelegateResponse { key, values }),
                     Err(err) => {
                         tracing::error!("failed executing delegate `{key}`: {err}");
@@ -455,11 +847,24 @@ impl Executor<Runtime> {
         &mut self,
         contract: ContractContainer,
         state: WrappedState,
-        related_contracts: RelatedContracts<'_>,
+        mut related_contracts: RelatedContracts<'_>,
     ) -> Response {
         let key = contract.key();
         let params = contract.params();
 
+        // the put of a client of this node was checked already before being propagated
+        let validated = self.validated_puts.take(&key, &state);
+        if !validated {
+            self.runtime
+                .check_contract(&contract, state.as_ref())
+                .map_err(put_rejection(key))?;
+        }
+        if self.check_policy(&key, &params, Some(&contract))? == PolicyDecision::MetadataOnly {
+            self.store_without_executing(key, params, Either::Left(state), Some(contract), true)
+                .await?;
+            return Ok(ContractResponse::PutResponse { key }.into());
+        }
+
         if self.get_local_contract(key.id()).await.is_ok() {
             // already existing contract, just try to merge states
             return self
@@ -467,8 +872,14 @@ impl Executor<Runtime> {
                 .await;
         }
 
-        self.verify_and_store_contract(state.clone(), contract, related_contracts)
+        let upgrade = self
+            .verify_upgrade(&contract, &mut related_contracts)
+            .await?;
+        self.verify_and_store_contract(state.clone(), contract, related_contracts, validated)
             .await?;
+        if let Some(upgrade) = upgrade {
+            self.complete_upgrade(upgrade, key).await?;
+        }
 
         self.send_update_notification(&key, &params, &state)
             .await
@@ -499,6 +910,10 @@ impl Executor<Runtime> {
                 })?
         };
 
+        if self.check_policy(&key, &parameters, None)? == PolicyDecision::MetadataOnly {
+            return self.update_without_executing(key, parameters, update).await;
+        }
+
         let current_state = self
             .state_store
             .get(&key)
@@ -512,9 +927,7 @@ impl Executor<Runtime> {
             .await?;
 
         // in the network impl this would be sent over the network
-        let summary = self
-            .runtime
-            .summarize_state(&key, &parameters, &new_state)
+        let summary = run_blocking(|| self.runtime.summarize_state(&key, &parameters, &new_state))
             .map_err(|e| ExecutorError::execution(e, None))?;
         self.send_update_notification(&key, &parameters, &new_state)
             .await?;
@@ -538,19 +951,18 @@ impl Executor<Runtime> {
         key: &ContractKey,
         updates: &[UpdateData<'_>],
     ) -> Result<Either<WrappedState, Vec<RelatedContract>>, ExecutorError> {
-        let update_modification =
-            match self
-                .runtime
+        let update_modification = match run_blocking(|| {
+            self.runtime
                 .update_state(key, parameters, current_state, updates)
-            {
-                Ok(result) => result,
-                Err(err) => {
-                    return Err(ExecutorError::execution(
-                        err,
-                        Some(InnerOpError::Upsert(*key)),
-                    ))
-                }
-            };
+        }) {
+            Ok(result) => result,
+            Err(err) => {
+                return Err(ExecutorError::execution(
+                    err,
+                    Some(InnerOpError::Upsert(*key)),
+                ))
+            }
+        };
         let UpdateModification {
             new_state, related, ..
         } = update_modification;
@@ -573,6 +985,9 @@ impl Executor<Runtime> {
             .update(key, new_state.clone())
             .await
             .map_err(ExecutorError::other)?;
+        if let Some(journal) = &mut self.update_journal {
+            journal.record(key, updates, current_state, &new_state);
+        }
 
         if let Err(err) = self
             .send_update_notification(key, parameters, &new_state)
@@ -650,6 +1065,7 @@ impl Executor<Runtime> {
                                         state.clone(),
                                         contract,
                                         RelatedContracts::default(),
+                                        false,
                                     )
                                     .await?;
                                     state
@@ -728,6 +1144,7 @@ impl Executor<Runtime> {
         state: WrappedState,
         trying_container: ContractContainer,
         mut related_contracts: RelatedContracts<'_>,
+        validated: bool,
     ) -> Result<(), ExecutorError> {
         let key = trying_container.key();
         let params = trying_container.params();
@@ -751,18 +1168,22 @@ impl Executor<Runtime> {
                     .map_err(ExecutorError::other)?;
             }
 
-            let result = self
-                .runtime
-                .validate_state(
-                    &trying_key,
-                    &trying_params,
-                    &trying_state,
-                    &related_contracts,
-                )
+            let result = if validated && trying_key == original_key {
+                ValidateResult::Valid
+            } else {
+                run_blocking(|| {
+                    self.runtime.validate_state(
+                        &trying_key,
+                        &trying_params,
+                        &trying_state,
+                        &related_contracts,
+                    )
+                })
                 .map_err(|err| {
                     let _ = self.runtime.contract_store.remove_contract(&trying_key);
                     ExecutorError::execution(err, None)
-                })?;
+                })?
+            };
 
             let is_valid = match result {
                 ValidateResult::Valid => true,
@@ -799,10 +1220,7 @@ impl Executor<Runtime> {
             };
 
             if !is_valid {
-                return Err(ExecutorError::request(StdContractError::Put {
-                    key: trying_key,
-                    cause: "not valid".into(),
-                }));
+                return Err(put_rejection(trying_key)(PutRejection::InvalidState));
             }
 
             self.state_store
@@ -833,6 +1251,8 @@ impl Executor<Runtime> {
     ) -> Result<(), ExecutorError> {
         tracing::debug!(contract = %key, "notify of contract update");
         let key = *key;
+        // contracts which are not executed can only notify full states
+        let metadata_only = self.policy.is_metadata_only(&key);
         if let Some(notifiers) = self.update_notifications.get_mut(&key) {
             let summaries = self.subscriber_summaries.get_mut(&key).unwrap();
             // in general there should be less than 32 failures
@@ -840,16 +1260,17 @@ impl Executor<Runtime> {
             for (peer_key, notifier) in notifiers.iter() {
                 let peer_summary = summaries.get_mut(peer_key).unwrap();
                 let update = match peer_summary {
-                    Some(summary) => self
-                        .runtime
-                        .get_state_delta(&key, params, new_state, &*summary)
-                        .map_err(|err| {
-                            tracing::error!("{err}");
-                            ExecutorError::execution(err, Some(InnerOpError::Upsert(key)))
-                        })?
-                        .to_owned()
-                        .into(),
-                    None => UpdateData::State(State::from(new_state.as_ref()).into_owned()),
+                    Some(summary) if !metadata_only => run_blocking(|| {
+                        self.runtime
+                            .get_state_delta(&key, params, new_state, &*summary)
+                    })
+                    .map_err(|err| {
+                        tracing::error!("{err}");
+                        ExecutorError::execution(err, Some(InnerOpError::Upsert(key)))
+                    })?
+                    .to_owned()
+                    .into(),
+                    _ => UpdateData::State(State::from(new_state.as_ref()).into_owned()),
                 };
                 if let Err(err) =
                     notifier.send(Ok(
@@ -869,6 +1290,107 @@ impl Executor<Runtime> {
         Ok(())
     }
 
+    /// Checks the contract is a valid new version of the contract it declares to replace, if
+    /// any, returning the replaced contract along with the version of the upgrade.
+    ///
+    /// The current state of the replaced contract is added to the related contracts, so the new
+    /// version can verify its state was migrated from it.
+    async fn verify_upgrade(
+        &mut self,
+        contract: &ContractContainer,
+        related_contracts: &mut RelatedContracts<'_>,
+    ) -> Result<Option<(ContractInstanceId, u64)>, ExecutorError> {
+        let key = contract.key();
+        let ContractContainer::Wasm(ContractWasmAPIVersion::V1(successor_code)) = contract else {
+            return Ok(None);
+        };
+        let Some(declaration) = UpgradeDeclaration::from_code(successor_code.code().data())
+            .map_err(upgrade_error(key))?
+        else {
+            return Ok(None);
+        };
+        let predecessor = declaration.predecessor;
+        self.upgrades
+            .check(&predecessor, &key, declaration.version)
+            .map_err(upgrade_error(key))?;
+
+        let (state, code) = match self.local_state_or_from_network(&predecessor, true).await? {
+            Either::Left(state) => {
+                let code = self.get_contract_locally(&predecessor.into()).await?;
+                (state, code)
+            }
+            Either::Right(GetResult {
+                state, contract, ..
+            }) => (state, contract),
+        };
+        let Some(ContractContainer::Wasm(ContractWasmAPIVersion::V1(predecessor_code))) = code
+        else {
+            return Err(upgrade_error(key)(UpgradeError::MissingPredecessor(
+                predecessor,
+            )));
+        };
+        declaration
+            .verify(predecessor_code.code().data(), &contract.params())
+            .map_err(upgrade_error(key))?;
+
+        related_contracts.missing(vec![predecessor]);
+        for (id, related) in related_contracts.update() {
+            if *id == predecessor {
+                *related = Some(state.clone().into());
+            }
+        }
+        Ok(Some((predecessor, declaration.version)))
+    }
+
+    /// Records the upgrade of a contract and moves the clients subscribed to it, or to the
+    /// successor it replaces, to the new version, letting them know about it with a subscribe
+    /// response for the new version.
+    async fn complete_upgrade(
+        &mut self,
+        (predecessor, version): (ContractInstanceId, u64),
+        key: ContractKey,
+    ) -> Result<(), ExecutorError> {
+        let replaced = match self
+            .upgrades
+            .record(predecessor, key, version)
+            .map_err(upgrade_error(key))?
+        {
+            Recorded::Unchanged => return Ok(()),
+            Recorded::New => None,
+            Recorded::Replaced(replaced) => Some(replaced),
+        };
+        tracing::info!(%predecessor, successor = %key, version, ?replaced, "Contract upgraded to a new version");
+        let mut subscribers = vec![];
+        for upgraded in std::iter::once(ContractKey::from(predecessor)).chain(replaced) {
+            self.subscriber_summaries.remove(&upgraded);
+            subscribers.extend(
+                self.update_notifications
+                    .remove(&upgraded)
+                    .unwrap_or_default(),
+            );
+        }
+        for (cli_id, notifier) in subscribers {
+            if notifier
+                .send(Ok(ContractResponse::SubscribeResponse {
+                    key,
+                    subscribed: true,
+                }
+                .into()))
+                .is_err()
+            {
+                continue;
+            }
+            // summaries of the old state are meaningless to the new version
+            if let Err(err) = self.register_contract_notifier(key, cli_id, notifier, None) {
+                tracing::warn!(%cli_id, contract = %key, "Failed moving subscriber to the new version: {err}");
+            }
+        }
+        if let Err(err) = self.subscribe(key).await {
+            tracing::warn!(contract = %key, "Failed subscribing to the new version: {err}");
+        }
+        Ok(())
+    }
+
     async fn get_contract_locally(
         &self,
         key: &ContractKey,
@@ -915,3 +1437,21 @@ impl Executor<Runtime> {
         Ok(Either::Right(get_result))
     }
 }
+
+fn put_rejection(key: ContractKey) -> impl Fn(PutRejection) -> ExecutorError {
+    move |rejection| {
+        ExecutorError::request(StdContractError::Put {
+            key,
+            cause: rejection.to_string().into(),
+        })
+    }
+}
+
+fn upgrade_error(key: ContractKey) -> impl Fn(UpgradeError) -> ExecutorError {
+    move |err| {
+        ExecutorError::request(StdContractError::Put {
+            key,
+            cause: format!("invalid upgrade: {err}").into(),
+        })
+    }
+}
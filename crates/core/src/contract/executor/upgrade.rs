@@ -0,0 +1,371 @@
+//! Upgrades of contracts to new versions.
+//!
+//! A new version of a contract declares the contract it replaces in the `freenet-predecessor`
+//! custom section of its WASM module: the 32 bytes of the predecessor instance id and the
+//! version of the upgrade as a little endian `u64`, followed by an ed25519 signature of the
+//! predecessor id, the version, the blake3 hash of the module without that section and the blake3
+//! hash of the parameters of the successor. The signature must verify with the key the
+//! predecessor embeds in its own `freenet-upgrade-key` section, so only whoever published a
+//! contract can publish its successors, and covers the whole key of the successor, so the signed
+//! code can't be published with other parameters.
+//!
+//! A contract is upgraded to the successor declaring the highest version: a successor with a
+//! higher version replaces one recorded before, so publishing a signed successor first doesn't
+//! hold the contract, while one with the same or a lower version is rejected.
+//!
+//! The initial state of the successor is validated along with the current state of the
+//! predecessor as a related contract, which is how the successor checks the state was correctly
+//! migrated. Once a successor is accepted, GETs for the predecessor are redirected to it and the
+//! clients subscribed to the predecessor are moved to the successor.
+
+use std::{collections::HashMap, fs, path::PathBuf};
+
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use freenet_stdlib::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::util::wasm_sections::find_custom_section;
+
+const PREDECESSOR_SECTION: &[u8] = b"freenet-predecessor";
+const UPGRADE_KEY_SECTION: &[u8] = b"freenet-upgrade-key";
+/// Longest chain of upgrades followed when redirecting a contract.
+const MAX_UPGRADE_CHAIN: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub(crate) enum UpgradeError {
+    #[error("malformed predecessor declaration")]
+    MalformedDeclaration,
+    #[error("code of contract {0} is not available")]
+    MissingPredecessor(ContractInstanceId),
+    #[error("contract {0} does not declare an upgrade key")]
+    NotUpgradable(ContractInstanceId),
+    #[error("upgrade of contract {0} is not signed with its upgrade key")]
+    InvalidSignature(ContractInstanceId),
+    #[error("contract {0} was already upgraded to {1} with the same or a later version")]
+    AlreadyUpgraded(ContractInstanceId, ContractKey),
+}
+
+/// Declaration of the contract replaced by a new contract version.
+#[derive(Debug)]
+pub(crate) struct UpgradeDeclaration {
+    pub predecessor: ContractInstanceId,
+    pub version: u64,
+    signature: Signature,
+    /// Hash of the successor module without the declaration.
+    code_hash: blake3::Hash,
+}
+
+/// Message signed by the upgrade key of the predecessor.
+fn signed_message(
+    predecessor: &ContractInstanceId,
+    version: u64,
+    code_hash: &blake3::Hash,
+    params: &Parameters,
+) -> Vec<u8> {
+    let mut message = Vec::with_capacity(32 + 8 + 32 + 32);
+    message.extend_from_slice(predecessor.as_bytes());
+    message.extend_from_slice(&version.to_le_bytes());
+    message.extend_from_slice(code_hash.as_bytes());
+    message.extend_from_slice(blake3::hash(params.as_ref()).as_bytes());
+    message
+}
+
+impl UpgradeDeclaration {
+    /// Reads the declaration of a new contract version, `None` if it doesn't replace any
+    /// contract.
+    pub fn from_code(code: &[u8]) -> Result<Option<Self>, UpgradeError> {
+        let Some(section) = find_custom_section(code, PREDECESSOR_SECTION) else {
+            return Ok(None);
+        };
+        if section.content.len() != 32 + 8 + Signature::BYTE_SIZE {
+            return Err(UpgradeError::MalformedDeclaration);
+        }
+        let (id, rest) = section.content.split_at(32);
+        let (version, signature) = rest.split_at(8);
+        let predecessor = ContractInstanceId::new(id.try_into().expect("32 bytes"));
+        let version = u64::from_le_bytes(version.try_into().expect("8 bytes"));
+        let signature =
+            Signature::from_slice(signature).map_err(|_| UpgradeError::MalformedDeclaration)?;
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&code[..section.range.start]);
+        hasher.update(&code[section.range.end..]);
+        Ok(Some(Self {
+            predecessor,
+            version,
+            signature,
+            code_hash: hasher.finalize(),
+        }))
+    }
+
+    /// Verifies the upgrade to the successor with these parameters was signed with the upgrade
+    /// key of the predecessor.
+    pub fn verify(&self, predecessor_code: &[u8], params: &Parameters) -> Result<(), UpgradeError> {
+        let upgrade_key = find_custom_section(predecessor_code, UPGRADE_KEY_SECTION)
+            .and_then(|section| <[u8; 32]>::try_from(section.content).ok())
+            .and_then(|key| VerifyingKey::from_bytes(&key).ok())
+            .ok_or(UpgradeError::NotUpgradable(self.predecessor))?;
+        let message = signed_message(&self.predecessor, self.version, &self.code_hash, params);
+        upgrade_key
+            .verify(&message, &self.signature)
+            .map_err(|_| UpgradeError::InvalidSignature(self.predecessor))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Upgrade {
+    successor: ContractKey,
+    version: u64,
+}
+
+/// Outcome of recording an upgrade.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Recorded {
+    /// The upgrade was already recorded.
+    Unchanged,
+    New,
+    /// The upgrade replaced the one to this successor, with a lower version.
+    Replaced(ContractKey),
+}
+
+/// Successors of the contracts upgraded to a new version.
+#[derive(Debug, Default)]
+pub(crate) struct ContractUpgrades {
+    successors: HashMap<ContractInstanceId, Upgrade>,
+    /// File the upgrades are persisted to.
+    file: Option<PathBuf>,
+}
+
+impl ContractUpgrades {
+    pub fn load(file: PathBuf) -> Self {
+        let mut successors = HashMap::new();
+        if let Ok(content) = fs::read_to_string(&file) {
+            match serde_json::from_str::<Vec<(ContractInstanceId, Upgrade)>>(&content) {
+                Ok(persisted) => successors.extend(persisted),
+                Err(error) => tracing::warn!(%error, "Failed loading persisted contract upgrades"),
+            }
+        }
+        Self {
+            successors,
+            file: Some(file),
+        }
+    }
+
+    /// Latest version of an upgraded contract, `None` if the contract was not upgraded.
+    pub fn successor(&self, id: &ContractInstanceId) -> Option<ContractKey> {
+        let mut successor = self.successors.get(id)?.successor;
+        for _ in 1..MAX_UPGRADE_CHAIN {
+            match self.successors.get(successor.id()) {
+                Some(next) => successor = next.successor,
+                None => break,
+            }
+        }
+        Some(successor)
+    }
+
+    /// Checks the contract can be upgraded to the successor declaring this version.
+    ///
+    /// The upgrade of a contract can only be replaced by one with a higher version.
+    pub fn check(
+        &self,
+        predecessor: &ContractInstanceId,
+        successor: &ContractKey,
+        version: u64,
+    ) -> Result<(), UpgradeError> {
+        match self.successors.get(predecessor) {
+            Some(existing)
+                if existing.successor.id() != successor.id() && existing.version >= version =>
+            {
+                Err(UpgradeError::AlreadyUpgraded(
+                    *predecessor,
+                    existing.successor,
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Records the upgrade of a contract to the successor declaring this version.
+    pub fn record(
+        &mut self,
+        predecessor: ContractInstanceId,
+        successor: ContractKey,
+        version: u64,
+    ) -> Result<Recorded, UpgradeError> {
+        self.check(&predecessor, &successor, version)?;
+        let upgrade = Upgrade { successor, version };
+        let recorded = match self.successors.insert(predecessor, upgrade) {
+            Some(previous) if previous.successor.id() == successor.id() => {
+                return Ok(Recorded::Unchanged)
+            }
+            Some(previous) => Recorded::Replaced(previous.successor),
+            None => Recorded::New,
+        };
+        self.persist();
+        Ok(recorded)
+    }
+
+    fn persist(&self) {
+        let Some(path) = &self.file else {
+            return;
+        };
+        let upgrades = self.successors.iter().collect::<Vec<_>>();
+        let result = serde_json::to_vec(&upgrades)
+            .map_err(std::io::Error::from)
+            .and_then(|content| fs::write(path, content));
+        if let Err(error) = result {
+            tracing::error!(%error, "Failed persisting contract upgrades");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signer, SigningKey};
+
+    use super::*;
+
+    fn leb128(mut n: usize) -> Vec<u8> {
+        let mut encoded = vec![];
+        loop {
+            let byte = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                encoded.push(byte);
+                return encoded;
+            }
+            encoded.push(byte | 0x80);
+        }
+    }
+
+    fn custom_section(name: &[u8], content: &[u8]) -> Vec<u8> {
+        let mut payload = leb128(name.len());
+        payload.extend_from_slice(name);
+        payload.extend_from_slice(content);
+        let mut section = vec![0];
+        section.extend(leb128(payload.len()));
+        section.extend(payload);
+        section
+    }
+
+    fn module(name: &str) -> Vec<u8> {
+        let mut module = b"\0asm\x01\0\0\0".to_vec();
+        module.extend(custom_section(b"name", name.as_bytes()));
+        module
+    }
+
+    fn key_for(code: &[u8]) -> ContractKey {
+        let code = ContractCode::from(code.to_vec());
+        ContractKey::from_params_and_code(Parameters::from(vec![]), &code)
+    }
+
+    fn successor_of(predecessor: &ContractKey, version: u64, signing_key: &SigningKey) -> Vec<u8> {
+        let code = module("successor");
+        let message = signed_message(
+            predecessor.id(),
+            version,
+            &blake3::hash(&code),
+            &Parameters::from(vec![]),
+        );
+        let signature = signing_key.sign(&message);
+        let mut declaration = predecessor.id().as_bytes().to_vec();
+        declaration.extend(version.to_le_bytes());
+        declaration.extend(signature.to_bytes());
+        let mut signed = code;
+        signed.extend(custom_section(PREDECESSOR_SECTION, &declaration));
+        signed
+    }
+
+    #[test]
+    fn verify_declaration() {
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        let mut predecessor = module("predecessor");
+        predecessor.extend(custom_section(
+            UPGRADE_KEY_SECTION,
+            signing_key.verifying_key().as_bytes(),
+        ));
+        let predecessor_key = key_for(&predecessor);
+
+        assert!(UpgradeDeclaration::from_code(&predecessor)
+            .unwrap()
+            .is_none());
+
+        let params = Parameters::from(vec![]);
+        let successor = successor_of(&predecessor_key, 1, &signing_key);
+        let declaration = UpgradeDeclaration::from_code(&successor).unwrap().unwrap();
+        assert_eq!(declaration.predecessor, *predecessor_key.id());
+        assert_eq!(declaration.version, 1);
+        assert_eq!(declaration.verify(&predecessor, &params), Ok(()));
+
+        // the signed code can't be published with other parameters
+        assert_eq!(
+            declaration.verify(&predecessor, &Parameters::from(vec![1])),
+            Err(UpgradeError::InvalidSignature(*predecessor_key.id()))
+        );
+
+        let forged = successor_of(&predecessor_key, 1, &SigningKey::from_bytes(&[8; 32]));
+        let declaration = UpgradeDeclaration::from_code(&forged).unwrap().unwrap();
+        assert_eq!(
+            declaration.verify(&predecessor, &params),
+            Err(UpgradeError::InvalidSignature(*predecessor_key.id()))
+        );
+
+        let not_upgradable = module("predecessor");
+        assert_eq!(
+            declaration.verify(&not_upgradable, &params),
+            Err(UpgradeError::NotUpgradable(*predecessor_key.id()))
+        );
+
+        let mut malformed = module("successor");
+        malformed.extend(custom_section(
+            PREDECESSOR_SECTION,
+            &[1; 32 + Signature::BYTE_SIZE],
+        ));
+        assert!(matches!(
+            UpgradeDeclaration::from_code(&malformed),
+            Err(UpgradeError::MalformedDeclaration)
+        ));
+    }
+
+    #[test]
+    fn follow_upgrades() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("upgrades.json");
+        let [v1, v2, v3, v4] = ["v1", "v2", "v3", "v4"].map(|name| key_for(&module(name)));
+
+        let mut upgrades = ContractUpgrades::load(file.clone());
+        assert_eq!(upgrades.successor(v1.id()), None);
+        assert_eq!(upgrades.record(*v1.id(), v2, 1), Ok(Recorded::New));
+        assert_eq!(upgrades.record(*v1.id(), v2, 1), Ok(Recorded::Unchanged));
+        assert_eq!(upgrades.record(*v2.id(), v3, 1), Ok(Recorded::New));
+        assert_eq!(
+            upgrades.record(*v1.id(), v3, 1),
+            Err(UpgradeError::AlreadyUpgraded(*v1.id(), v2))
+        );
+        assert_eq!(upgrades.successor(v1.id()), Some(v3));
+        assert_eq!(upgrades.successor(v2.id()), Some(v3));
+        assert_eq!(upgrades.successor(v3.id()), None);
+
+        let upgrades = ContractUpgrades::load(file.clone());
+        assert_eq!(upgrades.successor(v1.id()), Some(v3));
+    }
+
+    #[test]
+    fn later_versions_replace_upgrades() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("upgrades.json");
+        let [v1, early, late] = ["v1", "early", "late"].map(|name| key_for(&module(name)));
+
+        let mut upgrades = ContractUpgrades::load(file.clone());
+        assert_eq!(upgrades.record(*v1.id(), early, 1), Ok(Recorded::New));
+        assert_eq!(
+            upgrades.record(*v1.id(), late, 2),
+            Ok(Recorded::Replaced(early))
+        );
+        // an upgrade published earlier can't take the contract back
+        assert_eq!(
+            upgrades.record(*v1.id(), early, 1),
+            Err(UpgradeError::AlreadyUpgraded(*v1.id(), late))
+        );
+        assert_eq!(ContractUpgrades::load(file).successor(v1.id()), Some(late));
+    }
+}
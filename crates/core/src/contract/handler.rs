@@ -1,15 +1,20 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
 use std::future::Future;
 use std::hash::Hash;
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicU64, Ordering::SeqCst};
 use std::sync::Arc;
 use std::time::Duration;
 
+use dashmap::DashMap;
+use either::Either;
 use freenet_stdlib::prelude::*;
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::Semaphore;
 
-use super::executor::{ExecutorHalve, ExecutorToEventLoopChannel};
+use super::executor::{journal::JournalEntry, ExecutorHalve, ExecutorToEventLoopChannel};
+use super::storages::{index::Resolution, quota::PinnedContract};
 use super::ExecutorError;
 use super::{
     executor::{ContractExecutor, Executor},
@@ -18,7 +23,11 @@ use super::{
 use crate::client_events::HostResult;
 use crate::config::Config;
 use crate::message::Transaction;
-use crate::{client_events::ClientId, wasm_runtime::Runtime};
+use crate::node::load_shedding::QueuedEvents;
+use crate::{
+    client_events::ClientId,
+    wasm_runtime::{Runtime, SecretsConsent},
+};
 
 pub(crate) struct ClientResponsesReceiver(UnboundedReceiver<(ClientId, HostResult)>);
 
@@ -156,6 +165,49 @@ pub(crate) struct ContractHandlerHalve {
 pub(crate) struct SenderHalve {
     event_sender: mpsc::UnboundedSender<InternalCHEvent>,
     wait_for_res_tx: mpsc::Sender<(ClientId, WaitingTransaction)>,
+    /// Bounds the number of events queued or being processed by the handler.
+    queue_permits: Arc<Semaphore>,
+    pending_per_contract: PendingPerContract,
+    /// Events counted by the client API servers to shed load on.
+    queued_events: QueuedEvents,
+}
+
+/// Maximum number of events queued or being processed by the contract handler.
+const MAX_QUEUED_EVENTS: usize = 1024;
+/// Maximum number of events queued or being processed for a single contract, so a single
+/// busy contract cannot take over the whole handler queue. Only caps the queue, events are
+/// still processed one at a time whatever their contract.
+const MAX_PENDING_PER_CONTRACT: usize = 64;
+
+#[derive(Clone, Default)]
+struct PendingPerContract(Arc<DashMap<ContractKey, usize>>);
+
+impl PendingPerContract {
+    fn acquire(&self, key: ContractKey) -> Result<PendingGuard, ContractError> {
+        let mut pending = self.0.entry(key).or_default();
+        if *pending >= MAX_PENDING_PER_CONTRACT {
+            return Err(ContractError::TooManyPendingRequests(key));
+        }
+        *pending += 1;
+        Ok(PendingGuard {
+            pending: self.clone(),
+            key,
+        })
+    }
+}
+
+struct PendingGuard {
+    pending: PendingPerContract,
+    key: ContractKey,
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        self.pending.0.remove_if_mut(&self.key, |_, pending| {
+            *pending -= 1;
+            *pending == 0
+        });
+    }
 }
 
 #[derive(Debug)]
@@ -184,7 +236,9 @@ mod sealed {
     impl ChannelHalve for WaitingResolution {}
 }
 
-pub(crate) fn contract_handler_channel() -> (
+pub(crate) fn contract_handler_channel(
+    queued_events: QueuedEvents,
+) -> (
     ContractHandlerChannel<SenderHalve>,
     ContractHandlerChannel<ContractHandlerHalve>,
     ContractHandlerChannel<WaitingResolution>,
@@ -196,6 +250,9 @@ pub(crate) fn contract_handler_channel() -> (
             end: SenderHalve {
                 event_sender,
                 wait_for_res_tx,
+                queue_permits: Arc::new(Semaphore::new(MAX_QUEUED_EVENTS)),
+                pending_per_contract: PendingPerContract::default(),
+                queued_events,
             },
         },
         ContractHandlerChannel {
@@ -210,6 +267,20 @@ pub(crate) fn contract_handler_channel() -> (
     )
 }
 
+/// The response slot of an event answered outside of the handler loop.
+pub(crate) struct DetachedResponse {
+    id: EventId,
+    response: tokio::sync::oneshot::Sender<(EventId, ContractHandlerEvent)>,
+}
+
+impl DetachedResponse {
+    pub fn send(self, ev: ContractHandlerEvent) {
+        if let Err((_, ev)) = self.response.send((self.id, ev)) {
+            tracing::debug!(%ev, "requester dropped before receiving the response");
+        }
+    }
+}
+
 static EV_ID: AtomicU64 = AtomicU64::new(0);
 
 impl ContractHandlerChannel<WaitingResolution> {
@@ -237,6 +308,18 @@ impl ContractHandlerChannel<SenderHalve> {
         &self,
         ev: ContractHandlerEvent,
     ) -> Result<ContractHandlerEvent, ContractError> {
+        // both are held until the handler responds, or the request is dropped
+        let _permit = self
+            .end
+            .queue_permits
+            .acquire()
+            .await
+            .map_err(|_| ContractError::NoEvHandlerResponse)?;
+        let _pending = ev
+            .contract_key()
+            .map(|key| self.end.pending_per_contract.acquire(key))
+            .transpose()?;
+        let _queued = self.end.queued_events.enter();
         let id = EV_ID.fetch_add(1, SeqCst);
         let (result, result_receiver) = tokio::sync::oneshot::channel();
         self.end
@@ -269,18 +352,36 @@ impl ContractHandlerChannel<ContractHandlerHalve> {
         ev: ContractHandlerEvent,
     ) -> Result<(), ContractError> {
         if let Some(response) = self.end.waiting_response.remove(&id.id) {
-            response
-                .send((id, ev))
-                .map_err(|_| ContractError::NoEvHandlerResponse)
+            if let Err((_, ev)) = response.send((id, ev)) {
+                // the requester went away (timed out or cancelled), nothing to do
+                tracing::debug!(%ev, "requester dropped before receiving the response");
+            }
+            Ok(())
         } else {
             Err(ContractError::NoEvHandlerResponse)
         }
     }
 
+    /// Takes over answering the event, so the response can be sent by a task spawned off the
+    /// handler loop once it completes.
+    pub fn detach_response(&mut self, id: EventId) -> Result<DetachedResponse, ContractError> {
+        let response = self
+            .end
+            .waiting_response
+            .remove(&id.id)
+            .ok_or(ContractError::NoEvHandlerResponse)?;
+        Ok(DetachedResponse { id, response })
+    }
+
     pub async fn recv_from_sender(
         &mut self,
     ) -> Result<(EventId, ContractHandlerEvent), ContractError> {
-        if let Some(InternalCHEvent { ev, id, result }) = self.end.event_receiver.recv().await {
+        while let Some(InternalCHEvent { ev, id, result }) = self.end.event_receiver.recv().await {
+            if result.is_closed() {
+                // the requester is not waiting anymore, avoid executing the contract at all
+                tracing::debug!(%ev, "skipping cancelled contract handler event");
+                continue;
+            }
             self.end.waiting_response.insert(id, result);
             return Ok((EventId { id }, ev));
         }
@@ -327,7 +428,8 @@ pub(crate) enum ContractHandlerEvent {
     /// Updates a supposedly existing contract in this node
     UpdateQuery {
         key: ContractKey,
-        data: UpdateData<'static>,
+        /// Either a full state or a delta; the state is shared with the caller, not copied.
+        update: Either<WrappedState, StateDelta<'static>>,
         related_contracts: RelatedContracts<'static>,
     },
     /// The response to an update query
@@ -345,6 +447,139 @@ pub(crate) enum ContractHandlerEvent {
         subscriber_listener: UnboundedSender<HostResult>,
     },
     RegisterSubscriberListenerResponse,
+    /// Fetch the most recent entries of the update journal for a contract
+    UpdateJournalQuery {
+        key: ContractKey,
+        last: usize,
+    },
+    /// The response to an update journal query
+    UpdateJournalResponse {
+        key: ContractKey,
+        entries: Result<Vec<JournalEntry>, ExecutorError>,
+    },
+    /// Summarize the current state of a contract
+    SummaryQuery {
+        key: ContractKey,
+    },
+    /// The response to a summary query, with no summary if this node does not execute the contract
+    SummaryResponse {
+        key: ContractKey,
+        summary: Result<Option<StateSummary<'static>>, ExecutorError>,
+    },
+    /// Compute the changes missing from a state given its summary
+    DeltaQuery {
+        key: ContractKey,
+        summary: StateSummary<'static>,
+    },
+    /// The response to a delta query, with no delta if the summarized state is in sync
+    DeltaResponse {
+        key: ContractKey,
+        delta: Result<Option<StateDelta<'static>>, ExecutorError>,
+    },
+    /// Take a snapshot of the state store and write it to the given directory
+    SnapshotQuery {
+        snapshot_dir: PathBuf,
+        /// Number of snapshots to retain in the directory
+        keep: usize,
+    },
+    /// The response to a snapshot query, with the path of the written snapshot
+    SnapshotResponse {
+        result: Result<PathBuf, ExecutorError>,
+    },
+    /// Evict the contracts over the storage budget, except the ones with network subscribers
+    StorageBudgetQuery {
+        subscribed: HashSet<ContractInstanceId>,
+        /// Current budget, it may have changed since the node started
+        max_size: Option<u64>,
+    },
+    /// The response to a storage budget query, with the evicted contracts and the bytes freed
+    StorageBudgetResponse {
+        evicted: Result<Vec<(ContractKey, u64)>, ExecutorError>,
+    },
+    /// Pin or unpin a contract, so it is never evicted and always kept subscribed to
+    PinQuery {
+        id: ContractInstanceId,
+        pin: bool,
+    },
+    /// The response to a pin query, with whether the pinned status of the contract changed
+    PinResponse {
+        id: ContractInstanceId,
+        changed: Result<bool, ExecutorError>,
+    },
+    /// Fetch the contracts pinned in this node
+    PinnedContractsQuery,
+    /// The response to a pinned contracts query
+    PinnedContractsResponse {
+        pinned: Vec<PinnedContract>,
+    },
+    /// Resize the memory caches of the states and of the contract and delegate code
+    ResizeCachesQuery {
+        state_cache_size: u64,
+        code_cache_size: u64,
+    },
+    /// The response to a resize caches query
+    ResizeCachesResponse,
+    /// Fetch the prompts asking the user to let applications read delegate secrets
+    SecretsConsentsQuery,
+    /// The response to a secrets consents query
+    SecretsConsentsResponse {
+        pending: Vec<SecretsConsent>,
+    },
+    /// Answer a prompt asking the user to let an application read delegate secrets
+    ResolveSecretsConsentQuery {
+        request_id: u32,
+        allowed: bool,
+    },
+    /// The response to a resolve secrets consent query, with whether the prompt was pending
+    ResolveSecretsConsentResponse {
+        request_id: u32,
+        resolved: Result<bool, ExecutorError>,
+    },
+    /// Fetch the latest version of a contract, if it was upgraded
+    SuccessorQuery {
+        id: ContractInstanceId,
+    },
+    /// The response to a successor query
+    SuccessorResponse {
+        id: ContractInstanceId,
+        successor: Option<ContractKey>,
+    },
+    /// Resolve a prefix of the encoded id of a stored contract
+    ResolvePrefixQuery {
+        prefix: String,
+    },
+    /// The response to a resolve prefix query
+    ResolvePrefixResponse {
+        prefix: String,
+        resolution: Resolution,
+    },
+    /// Check a contract put by a client before propagating it
+    ValidatePutQuery {
+        contract: ContractContainer,
+        state: WrappedState,
+        related_contracts: RelatedContracts<'static>,
+    },
+    /// The response to a validate put query
+    ValidatePutResponse {
+        key: ContractKey,
+        result: Result<(), ExecutorError>,
+    },
+}
+
+impl ContractHandlerEvent {
+    /// The contract this event operates on, if any.
+    fn contract_key(&self) -> Option<ContractKey> {
+        match self {
+            ContractHandlerEvent::PutQuery { key, .. }
+            | ContractHandlerEvent::GetQuery { key, .. }
+            | ContractHandlerEvent::UpdateQuery { key, .. }
+            | ContractHandlerEvent::RegisterSubscriberListener { key, .. }
+            | ContractHandlerEvent::UpdateJournalQuery { key, .. }
+            | ContractHandlerEvent::SummaryQuery { key }
+            | ContractHandlerEvent::DeltaQuery { key, .. } => Some(*key),
+            _ => None,
+        }
+    }
 }
 
 impl std::fmt::Display for ContractHandlerEvent {
@@ -413,6 +648,132 @@ impl std::fmt::Display for ContractHandlerEvent {
             ContractHandlerEvent::RegisterSubscriberListenerResponse => {
                 write!(f, "register subscriber listener response")
             }
+            ContractHandlerEvent::UpdateJournalQuery { key, last } => {
+                write!(f, "update journal query {{ {key}, last: {last} }}")
+            }
+            ContractHandlerEvent::UpdateJournalResponse { key, entries } => match entries {
+                Ok(entries) => write!(
+                    f,
+                    "update journal response {{ {key}, entries: {} }}",
+                    entries.len()
+                ),
+                Err(e) => write!(f, "update journal query failed {{ {key}, {e} }}"),
+            },
+            ContractHandlerEvent::SummaryQuery { key } => {
+                write!(f, "summary query {{ {key} }}")
+            }
+            ContractHandlerEvent::SummaryResponse { key, summary } => match summary {
+                Ok(_) => write!(f, "summary response {{ {key} }}"),
+                Err(e) => write!(f, "summary query failed {{ {key}, {e} }}"),
+            },
+            ContractHandlerEvent::DeltaQuery { key, .. } => {
+                write!(f, "delta query {{ {key} }}")
+            }
+            ContractHandlerEvent::DeltaResponse { key, delta } => match delta {
+                Ok(Some(_)) => write!(f, "delta response {{ {key} }}"),
+                Ok(None) => write!(f, "delta response {{ {key}, in sync }}"),
+                Err(e) => write!(f, "delta query failed {{ {key}, {e} }}"),
+            },
+            ContractHandlerEvent::SnapshotQuery { snapshot_dir, .. } => {
+                write!(f, "snapshot query {{ {} }}", snapshot_dir.display())
+            }
+            ContractHandlerEvent::SnapshotResponse { result } => match result {
+                Ok(path) => write!(f, "snapshot response {{ {} }}", path.display()),
+                Err(e) => write!(f, "snapshot failed {{ {e} }}"),
+            },
+            ContractHandlerEvent::StorageBudgetQuery {
+                subscribed,
+                max_size,
+            } => {
+                write!(
+                    f,
+                    "storage budget query {{ subscribed: {}, max_size: {max_size:?} }}",
+                    subscribed.len()
+                )
+            }
+            ContractHandlerEvent::StorageBudgetResponse { evicted } => match evicted {
+                Ok(evicted) => write!(
+                    f,
+                    "storage budget response {{ evicted: {} }}",
+                    evicted.len()
+                ),
+                Err(e) => write!(f, "storage budget query failed {{ {e} }}"),
+            },
+            ContractHandlerEvent::PinQuery { id, pin } => {
+                write!(f, "pin query {{ {id}, pin: {pin} }}")
+            }
+            ContractHandlerEvent::PinResponse { id, changed } => match changed {
+                Ok(changed) => write!(f, "pin response {{ {id}, changed: {changed} }}"),
+                Err(e) => write!(f, "pin query failed {{ {id}, {e} }}"),
+            },
+            ContractHandlerEvent::PinnedContractsQuery => write!(f, "pinned contracts query"),
+            ContractHandlerEvent::PinnedContractsResponse { pinned } => {
+                write!(
+                    f,
+                    "pinned contracts response {{ pinned: {} }}",
+                    pinned.len()
+                )
+            }
+            ContractHandlerEvent::ResizeCachesQuery {
+                state_cache_size,
+                code_cache_size,
+            } => {
+                write!(
+                    f,
+                    "resize caches query {{ state: {state_cache_size}, code: {code_cache_size} }}"
+                )
+            }
+            ContractHandlerEvent::ResizeCachesResponse => write!(f, "resize caches response"),
+            ContractHandlerEvent::SecretsConsentsQuery => write!(f, "secrets consents query"),
+            ContractHandlerEvent::SecretsConsentsResponse { pending } => {
+                write!(
+                    f,
+                    "secrets consents response {{ pending: {} }}",
+                    pending.len()
+                )
+            }
+            ContractHandlerEvent::ResolveSecretsConsentQuery {
+                request_id,
+                allowed,
+            } => {
+                write!(
+                    f,
+                    "resolve secrets consent query {{ {request_id}, allowed: {allowed} }}"
+                )
+            }
+            ContractHandlerEvent::ResolveSecretsConsentResponse {
+                request_id,
+                resolved,
+            } => match resolved {
+                Ok(resolved) => write!(
+                    f,
+                    "resolve secrets consent response {{ {request_id}, resolved: {resolved} }}"
+                ),
+                Err(e) => write!(
+                    f,
+                    "resolve secrets consent query failed {{ {request_id}, {e} }}"
+                ),
+            },
+            ContractHandlerEvent::SuccessorQuery { id } => {
+                write!(f, "successor query {{ {id} }}")
+            }
+            ContractHandlerEvent::SuccessorResponse { id, successor } => match successor {
+                Some(successor) => write!(f, "successor response {{ {id}, {successor} }}"),
+                None => write!(f, "successor response {{ {id}, not upgraded }}"),
+            },
+            ContractHandlerEvent::ResolvePrefixQuery { prefix } => {
+                write!(f, "resolve prefix query {{ {prefix} }}")
+            }
+            ContractHandlerEvent::ResolvePrefixResponse { prefix, resolution } => {
+                write!(f, "resolve prefix response {{ {prefix}, {resolution:?} }}")
+            }
+            ContractHandlerEvent::ValidatePutQuery { contract, .. } => {
+                write!(f, "validate put query {{ {} }}", contract.key())
+            }
+            ContractHandlerEvent::ValidatePutResponse { key, result } => match result {
+                Ok(()) => write!(f, "validate put response {{ {key}, valid }}"),
+                Err(e) => write!(f, "validate put response {{ {key}, {e} }}"),
+            },
         }
     }
 }
@@ -426,7 +787,7 @@ pub mod test {
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     async fn channel_test() -> anyhow::Result<()> {
-        let (send_halve, mut rcv_halve, _) = contract_handler_channel();
+        let (send_halve, mut rcv_halve, _) = contract_handler_channel(QueuedEvents::default());
 
         let contract = ContractContainer::Wasm(ContractWasmAPIVersion::V1(WrappedContract::new(
             Arc::new(ContractCode::from(vec![0, 1, 2, 3])),
@@ -470,6 +831,257 @@ pub mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn pending_per_contract_limit() {
+        let pending = PendingPerContract::default();
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let guards = (0..MAX_PENDING_PER_CONTRACT)
+            .map(|_| pending.acquire(key))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert!(matches!(
+            pending.acquire(key),
+            Err(ContractError::TooManyPendingRequests(_))
+        ));
+        // other contracts are not affected
+        let other = ContractKey::from(ContractInstanceId::new([2; 32]));
+        assert!(pending.acquire(other).is_ok());
+
+        drop(guards);
+        assert!(pending.0.get(&key).is_none());
+        assert!(pending.acquire(key).is_ok());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn skip_cancelled_events() -> anyhow::Result<()> {
+        let (send_halve, mut rcv_halve, _) = contract_handler_channel(QueuedEvents::default());
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+
+        // the requester gives up before the handler gets to the event
+        let _ = tokio::time::timeout(
+            Duration::from_millis(10),
+            send_halve.send_to_handler(ContractHandlerEvent::GetQuery {
+                key,
+                return_contract_code: false,
+            }),
+        )
+        .await;
+        let h = GlobalExecutor::spawn(async move {
+            send_halve
+                .send_to_handler(ContractHandlerEvent::UpdateJournalQuery { key, last: 1 })
+                .await
+        });
+        let (_, ev) =
+            tokio::time::timeout(Duration::from_millis(100), rcv_halve.recv_from_sender())
+                .await??;
+        assert!(matches!(
+            ev,
+            ContractHandlerEvent::UpdateJournalQuery { .. }
+        ));
+        h.abort();
+        Ok(())
+    }
+}
+
+/// Contract handling of a node replaying a recorded session, see [`crate::node::replay`].
+///
+/// Contracts are not executed nor stored: states are kept in memory as the mock runtime would,
+/// so the replay doesn't depend on the disk or the contracts known to the node replaying it.
+pub(super) mod replay {
+    use std::collections::{HashMap, HashSet};
+    use std::path::PathBuf;
+
+    use either::Either;
+    use freenet_stdlib::{client_api::RequestError, prelude::*};
+    use tokio::sync::mpsc::UnboundedSender;
+
+    use super::{
+        super::{
+            executor::{ContractExecutor, ExecutorHalve, ExecutorToEventLoopChannel},
+            storages::{index::Resolution, quota::PinnedContract},
+            ExecutorError, JournalEntry, UpsertResult,
+        },
+        ContractHandler, ContractHandlerChannel, ContractHandlerHalve,
+    };
+    use crate::client_events::{ClientId, HostResult};
+    use crate::wasm_runtime::SecretsConsent;
+
+    pub(crate) struct ReplayContractHandler {
+        channel: ContractHandlerChannel<ContractHandlerHalve>,
+        executor: ReplayExecutor,
+    }
+
+    impl ReplayContractHandler {
+        pub fn new(channel: ContractHandlerChannel<ContractHandlerHalve>) -> Self {
+            Self {
+                channel,
+                executor: ReplayExecutor::default(),
+            }
+        }
+    }
+
+    impl ContractHandler for ReplayContractHandler {
+        type Builder = ();
+        type ContractExecutor = ReplayExecutor;
+
+        async fn build(
+            channel: ContractHandlerChannel<ContractHandlerHalve>,
+            _executor_request_sender: ExecutorToEventLoopChannel<ExecutorHalve>,
+            _builder: Self::Builder,
+        ) -> anyhow::Result<Self>
+        where
+            Self: Sized + 'static,
+        {
+            Ok(Self::new(channel))
+        }
+
+        fn channel(&mut self) -> &mut ContractHandlerChannel<ContractHandlerHalve> {
+            &mut self.channel
+        }
+
+        fn executor(&mut self) -> &mut Self::ContractExecutor {
+            &mut self.executor
+        }
+    }
+
+    /// Holds the states put or updated while replaying, without any contract code.
+    #[derive(Default)]
+    pub(crate) struct ReplayExecutor {
+        states: HashMap<ContractKey, WrappedState>,
+    }
+
+    impl ContractExecutor for ReplayExecutor {
+        async fn fetch_contract(
+            &mut self,
+            key: ContractKey,
+            _return_contract_code: bool,
+        ) -> Result<(Option<WrappedState>, Option<ContractContainer>), ExecutorError> {
+            Ok((self.states.get(&key).cloned(), None))
+        }
+
+        async fn upsert_contract_state(
+            &mut self,
+            key: ContractKey,
+            update: Either<WrappedState, StateDelta<'static>>,
+            _related_contracts: RelatedContracts<'static>,
+            _code: Option<ContractContainer>,
+        ) -> Result<UpsertResult, ExecutorError> {
+            // as in the mock runtime, deltas are full states
+            let state = match update {
+                Either::Left(state) => state,
+                Either::Right(delta) => WrappedState::new(delta.as_ref().to_vec()),
+            };
+            if self.states.get(&key) == Some(&state) {
+                return Ok(UpsertResult::NoChange);
+            }
+            self.states.insert(key, state.clone());
+            Ok(UpsertResult::Updated(state))
+        }
+
+        fn register_contract_notifier(
+            &mut self,
+            _key: ContractKey,
+            _cli_id: ClientId,
+            _notification_ch: UnboundedSender<HostResult>,
+            _summary: Option<StateSummary<'_>>,
+        ) -> Result<(), Box<RequestError>> {
+            Ok(())
+        }
+
+        fn update_journal(
+            &mut self,
+            _key: ContractKey,
+            _last: usize,
+        ) -> Result<Vec<JournalEntry>, ExecutorError> {
+            Ok(vec![])
+        }
+
+        async fn summarize_contract_state(
+            &mut self,
+            key: ContractKey,
+        ) -> Result<Option<StateSummary<'static>>, ExecutorError> {
+            Ok(self
+                .states
+                .get(&key)
+                .map(|state| StateSummary::from(blake3::hash(state.as_ref()).as_bytes().to_vec())))
+        }
+
+        async fn contract_state_delta(
+            &mut self,
+            key: ContractKey,
+            summary: StateSummary<'static>,
+        ) -> Result<Option<StateDelta<'static>>, ExecutorError> {
+            Ok(self
+                .states
+                .get(&key)
+                .filter(|state| blake3::hash(state.as_ref()).as_bytes() != summary.as_ref())
+                .map(|state| StateDelta::from(state.as_ref().to_vec())))
+        }
+
+        fn create_snapshot(
+            &mut self,
+            _snapshot_dir: PathBuf,
+            _keep: usize,
+        ) -> impl Future<Output = Result<PathBuf, ExecutorError>> + Send + 'static {
+            std::future::ready(Err(ExecutorError::other(anyhow::anyhow!(
+                "replayed sessions can't be snapshotted"
+            ))))
+        }
+
+        async fn enforce_storage_budget(
+            &mut self,
+            _subscribed: HashSet<ContractInstanceId>,
+            _max_size: Option<u64>,
+        ) -> Result<Vec<(ContractKey, u64)>, ExecutorError> {
+            Ok(vec![])
+        }
+
+        async fn pin_contract(
+            &mut self,
+            _id: ContractInstanceId,
+            _pin: bool,
+        ) -> Result<bool, ExecutorError> {
+            Ok(false)
+        }
+
+        async fn pin_local_put(&mut self, _id: ContractInstanceId) {}
+
+        fn pinned_contracts(&self) -> Vec<PinnedContract> {
+            vec![]
+        }
+
+        fn resize_caches(&mut self, _state_cache_size: u64, _code_cache_size: u64) {}
+
+        fn contract_successor(&self, _id: &ContractInstanceId) -> Option<ContractKey> {
+            None
+        }
+
+        fn resolve_prefix(&self, _prefix: &str) -> Resolution {
+            Resolution::NotFound
+        }
+
+        fn secrets_consents(&self) -> Vec<SecretsConsent> {
+            vec![]
+        }
+
+        fn resolve_secrets_consent(
+            &mut self,
+            _request_id: u32,
+            _allowed: bool,
+        ) -> Result<bool, ExecutorError> {
+            Ok(false)
+        }
+
+        async fn validate_put(
+            &mut self,
+            _contract: ContractContainer,
+            _state: WrappedState,
+            _related_contracts: RelatedContracts<'static>,
+        ) -> Result<(), ExecutorError> {
+            Ok(())
+        }
+    }
 }
 
 pub(super) mod in_memory {
@@ -0,0 +1,188 @@
+//! Index of the contracts stored by the node, to resolve the truncated contract ids users paste
+//! into the gateway or send through the client API.
+//!
+//! Contracts are indexed by the base58 encoding of their instance id, which is the form users
+//! see, so the ids starting with a given prefix are a range of the index. Only the contracts
+//! stored locally can be resolved: every state store keeps the index of its contracts, which the
+//! client API servers query through the node, see
+//! [`NodeQueryKind::ResolvePrefix`](crate::client_events::node_queries::NodeQueryKind).
+
+use std::collections::BTreeMap;
+
+use freenet_stdlib::prelude::*;
+use serde::Serialize;
+
+use super::snapshot::StoredSizes;
+
+/// Shortest prefix resolved, shorter ones would match a good part of the stored contracts.
+pub(crate) const MIN_PREFIX_LEN: usize = 4;
+/// Most candidates listed when a prefix is ambiguous.
+const MAX_CANDIDATES: usize = 20;
+
+#[derive(Debug, Clone, Copy)]
+struct IndexedContract {
+    key: ContractKey,
+    state_size: u64,
+}
+
+/// A contract matching a prefix, along with what users need to tell it apart from the others.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct ContractCandidate {
+    #[serde(skip)]
+    pub key: ContractKey,
+    pub id: String,
+    pub code_hash: Option<String>,
+    pub state_size: u64,
+}
+
+impl ContractCandidate {
+    fn new(id: &str, contract: &IndexedContract) -> Self {
+        Self {
+            key: contract.key,
+            id: id.to_owned(),
+            code_hash: contract.key.code_hash().map(|hash| hash.encode()),
+            state_size: contract.state_size,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Resolution {
+    Unique(ContractCandidate),
+    /// The first candidates matching the prefix, and the number of contracts matching it.
+    Ambiguous {
+        candidates: Vec<ContractCandidate>,
+        matches: usize,
+    },
+    NotFound,
+}
+
+/// Contracts of a state store, by encoded id.
+#[derive(Debug, Default)]
+pub(crate) struct ContractIndex {
+    contracts: BTreeMap<String, IndexedContract>,
+}
+
+impl ContractIndex {
+    /// Records a contract written to the state store.
+    pub fn record(&mut self, key: &ContractKey, state_size: usize) {
+        let contract = self
+            .contracts
+            .entry(key.encoded_contract_id())
+            .or_insert(IndexedContract {
+                key: *key,
+                state_size: 0,
+            });
+        // keys are not always known along with their code hash
+        if key.code_hash().is_some() {
+            contract.key = *key;
+        }
+        contract.state_size = state_size as u64;
+    }
+
+    pub fn remove(&mut self, key: &ContractKey) {
+        self.contracts.remove(&key.encoded_contract_id());
+    }
+
+    /// Replaces the indexed contracts with the ones in the state store.
+    pub fn reset(&mut self, sizes: &StoredSizes) {
+        self.contracts.clear();
+        for (key, state_size) in &sizes.states {
+            let Ok(id) = <[u8; 32]>::try_from(key.as_slice()) else {
+                continue;
+            };
+            let key = ContractKey::from(ContractInstanceId::new(id));
+            self.contracts.insert(
+                key.encoded_contract_id(),
+                IndexedContract {
+                    key,
+                    state_size: *state_size,
+                },
+            );
+        }
+    }
+
+    /// Resolves a prefix of the encoded id of a contract in the state store.
+    pub fn resolve(&self, prefix: &str) -> Resolution {
+        let mut matching = self
+            .contracts
+            .range(prefix.to_owned()..)
+            .take_while(|(id, _)| id.starts_with(prefix));
+        let Some((id, contract)) = matching.next() else {
+            return Resolution::NotFound;
+        };
+        let first = ContractCandidate::new(id, contract);
+        let Some((id, contract)) = matching.next() else {
+            return Resolution::Unique(first);
+        };
+        let mut candidates = vec![first, ContractCandidate::new(id, contract)];
+        let mut matches = candidates.len();
+        for (id, contract) in matching {
+            if candidates.len() < MAX_CANDIDATES {
+                candidates.push(ContractCandidate::new(id, contract));
+            }
+            matches += 1;
+        }
+        Resolution::Ambiguous {
+            candidates,
+            matches,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_prefixes() {
+        let mut close = [7; 32];
+        close[31] = 8;
+        let keys =
+            [[7; 32], close, [200; 32]].map(|id| ContractKey::from(ContractInstanceId::new(id)));
+        let mut index = ContractIndex::default();
+        for key in &keys {
+            index.record(key, 10);
+        }
+        let [first, second, third] = keys.map(|key| key.encoded_contract_id());
+        // ids of close values only differ in their last characters
+        let common = first
+            .chars()
+            .zip(second.chars())
+            .take_while(|(a, b)| a == b)
+            .count();
+        assert!(common > MIN_PREFIX_LEN);
+
+        match index.resolve(&first[..common]) {
+            Resolution::Ambiguous {
+                candidates,
+                matches,
+            } => {
+                assert_eq!(matches, 2);
+                let mut expected = vec![first.clone(), second];
+                expected.sort();
+                let ids = candidates.into_iter().map(|c| c.id).collect::<Vec<_>>();
+                assert_eq!(ids, expected);
+            }
+            other => panic!("unexpected resolution: {other:?}"),
+        }
+        assert!(matches!(
+            index.resolve(&first[..common + 1]),
+            Resolution::Unique(ContractCandidate { id, .. }) if id == first
+        ));
+        assert!(matches!(
+            index.resolve(&third[..MIN_PREFIX_LEN]),
+            Resolution::Unique(ContractCandidate { state_size: 10, .. })
+        ));
+
+        index.remove(&keys[0]);
+        assert_eq!(index.resolve(&first), Resolution::NotFound);
+
+        // every store has its own index
+        assert_eq!(
+            ContractIndex::default().resolve(&third),
+            Resolution::NotFound
+        );
+    }
+}
@@ -1,3 +1,12 @@
+/// Point-in-time snapshots of the state storage
+pub mod snapshot;
+
+/// Storage budget and eviction of the cached contracts
+pub(crate) mod quota;
+
+/// Resolution of truncated contract ids to the stored contracts
+pub(crate) mod index;
+
 /// State storage implementation based on the `sqlite`
 #[cfg(feature = "sqlite")]
 pub mod sqlite;
@@ -0,0 +1,607 @@
+//! Storage budget for the contracts cached by the node.
+//!
+//! The state store tracks how many bytes the state and parameters of every contract take, and
+//! when each contract was last read or written. If a budget is configured, the node periodically
+//! evicts the least recently used contracts until the store fits in it again.
+//!
+//! Pinned contracts, contracts local clients are subscribed to, contracts this node is subscribed
+//! to through another peer and contracts with network subscribers are never evicted. Contract
+//! code is kept, since it may be shared by several contract instances.
+//!
+//! Contracts are pinned in the node configuration, through the admin or client APIs, or when put
+//! by a local client, so the node never drops the data of its own users; the ones pinned at
+//! runtime are persisted, so they survive restarts. The node keeps itself subscribed to every pinned contract, fetching it
+//! from the network if missing, so their state is always up to date.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use freenet_stdlib::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::snapshot::StoredSizes;
+use crate::{
+    config::{CacheSizes, GlobalExecutor, StorageBudgetConfig},
+    contract::{ContractHandlerEvent, ExecutorError},
+    node::OpManager,
+};
+
+#[derive(Debug, Clone, Copy)]
+struct ContractUsage {
+    state_size: u64,
+    params_size: u64,
+    last_access: Instant,
+}
+
+impl ContractUsage {
+    fn size(&self) -> u64 {
+        self.state_size + self.params_size
+    }
+}
+
+/// Disk usage of the contracts in the state store.
+#[derive(Debug, Default)]
+pub(crate) struct StorageUsage {
+    contracts: HashMap<ContractInstanceId, ContractUsage>,
+    total: u64,
+}
+
+impl StorageUsage {
+    /// Usage of the contracts in the storage; all of them are considered just used.
+    pub fn from_sizes(sizes: &StoredSizes) -> Self {
+        fn instance_id(key: &[u8]) -> Option<ContractInstanceId> {
+            <[u8; 32]>::try_from(key).ok().map(ContractInstanceId::new)
+        }
+
+        let mut usage = Self::default();
+        let now = Instant::now();
+        let entries = sizes
+            .states
+            .iter()
+            .map(|(key, state_size)| (key, *state_size, 0))
+            .chain(
+                sizes
+                    .params
+                    .iter()
+                    .map(|(key, params_size)| (key, 0, *params_size)),
+            );
+        for (key, state_size, params_size) in entries {
+            let Some(id) = instance_id(key) else {
+                continue;
+            };
+            let contract = usage.contracts.entry(id).or_insert(ContractUsage {
+                state_size: 0,
+                params_size: 0,
+                last_access: now,
+            });
+            contract.state_size += state_size;
+            contract.params_size += params_size;
+            usage.total += state_size + params_size;
+        }
+        usage
+    }
+
+    /// Records a write of the contract state, and of its parameters if given.
+    pub fn record(&mut self, key: &ContractKey, state_size: usize, params_size: Option<usize>) {
+        let now = Instant::now();
+        let contract = self.contracts.entry(*key.id()).or_insert(ContractUsage {
+            state_size: 0,
+            params_size: 0,
+            last_access: now,
+        });
+        self.total -= contract.size();
+        contract.state_size = state_size as u64;
+        if let Some(params_size) = params_size {
+            contract.params_size = params_size as u64;
+        }
+        contract.last_access = now;
+        self.total += contract.size();
+    }
+
+    pub fn touch(&mut self, key: &ContractKey) {
+        if let Some(contract) = self.contracts.get_mut(key.id()) {
+            contract.last_access = Instant::now();
+        }
+    }
+
+    /// Stops tracking the contract, returning the number of bytes it was using.
+    pub fn remove(&mut self, key: &ContractKey) -> u64 {
+        let size = self
+            .contracts
+            .remove(key.id())
+            .map(|contract| contract.size())
+            .unwrap_or_default();
+        self.total -= size;
+        size
+    }
+
+    /// Total number of bytes used by the contracts.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum PinSource {
+    /// Pinned in the node configuration, can't be unpinned at runtime.
+    Config,
+    /// Pinned through the admin or client APIs.
+    Api,
+    /// Put by a local client.
+    LocalPut,
+}
+
+/// Pin as persisted; older versions of the node only persisted the id of the API pins.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum PersistedPin {
+    Api(String),
+    WithSource { id: String, source: PinSource },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PinnedContract {
+    pub id: ContractInstanceId,
+    pub source: PinSource,
+}
+
+/// Maximum disk usage of the cached contracts, and the contracts never evicted to honor it.
+#[derive(Debug, Default)]
+pub(crate) struct StorageBudget {
+    max_size: Option<u64>,
+    pinned: HashMap<ContractInstanceId, PinSource>,
+    /// File the contracts pinned at runtime are persisted to.
+    pins_file: Option<PathBuf>,
+}
+
+impl StorageBudget {
+    /// Budget of the configuration, along with the contracts pinned in previous runs.
+    ///
+    /// Invalid contract ids are skipped, so a bad entry doesn't keep the node from starting.
+    pub fn from_config(config: &StorageBudgetConfig, pins_file: Option<PathBuf>) -> Self {
+        fn parse_id(id: &str) -> Option<ContractInstanceId> {
+            ContractInstanceId::try_from(id.to_owned())
+                .inspect_err(
+                    |error| tracing::warn!(%id, %error, "Skipping invalid pinned contract"),
+                )
+                .ok()
+        }
+
+        let mut pinned = HashMap::new();
+        if let Some(content) = pins_file
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+        {
+            match serde_json::from_str::<Vec<PersistedPin>>(&content) {
+                Ok(persisted) => {
+                    for pin in persisted {
+                        let (id, source) = match pin {
+                            PersistedPin::Api(id) => (id, PinSource::Api),
+                            PersistedPin::WithSource { id, source } => (id, source),
+                        };
+                        if let Some(id) = parse_id(&id) {
+                            pinned.insert(id, source);
+                        }
+                    }
+                }
+                Err(error) => tracing::warn!(%error, "Failed loading persisted pinned contracts"),
+            }
+        }
+        for id in config.pinned_contracts.iter().filter_map(|id| parse_id(id)) {
+            pinned.insert(id, PinSource::Config);
+        }
+        Self {
+            max_size: config.max_storage_size,
+            pinned,
+            pins_file,
+        }
+    }
+
+    /// Changes the maximum size, e.g. when the settings of the node are reloaded.
+    pub fn set_max_size(&mut self, max_size: Option<u64>) {
+        self.max_size = max_size;
+    }
+
+    /// Pins the contract, returns whether it was not pinned already.
+    pub async fn pin(&mut self, id: ContractInstanceId) -> bool {
+        if self.pinned.contains_key(&id) {
+            return false;
+        }
+        self.pinned.insert(id, PinSource::Api);
+        self.persist().await;
+        true
+    }
+
+    /// Pins a contract put by a local client, unless it's pinned already.
+    pub async fn pin_local_put(&mut self, id: ContractInstanceId) {
+        if self.pinned.contains_key(&id) {
+            return;
+        }
+        self.pinned.insert(id, PinSource::LocalPut);
+        self.persist().await;
+    }
+
+    /// Unpins a contract pinned at runtime, returns whether it was pinned.
+    pub async fn unpin(&mut self, id: &ContractInstanceId) -> bool {
+        if matches!(self.pinned.get(id), None | Some(PinSource::Config)) {
+            return false;
+        }
+        self.pinned.remove(id);
+        self.persist().await;
+        true
+    }
+
+    pub fn pinned(&self) -> Vec<PinnedContract> {
+        self.pinned
+            .iter()
+            .map(|(id, source)| PinnedContract {
+                id: *id,
+                source: *source,
+            })
+            .collect()
+    }
+
+    async fn persist(&self) {
+        let Some(path) = &self.pins_file else {
+            return;
+        };
+        let pinned = self
+            .pinned
+            .iter()
+            .filter(|(_, source)| **source != PinSource::Config)
+            .map(|(id, source)| PersistedPin::WithSource {
+                id: id.to_string(),
+                source: *source,
+            })
+            .collect::<Vec<_>>();
+        let result = match serde_json::to_vec(&pinned) {
+            Ok(content) => tokio::fs::write(path, content).await,
+            Err(error) => Err(error.into()),
+        };
+        if let Err(error) = result {
+            tracing::error!(%error, "Failed persisting pinned contracts");
+        }
+    }
+
+    /// Picks the least recently used contracts which have to be evicted for the usage to fit in
+    /// the budget, along with the bytes each one is using.
+    ///
+    /// Pinned contracts and those for which `protected` returns true are never picked.
+    pub fn select_evictions(
+        &self,
+        usage: &StorageUsage,
+        protected: impl Fn(&ContractInstanceId) -> bool,
+    ) -> Vec<(ContractInstanceId, u64)> {
+        let Some(max_size) = self.max_size else {
+            return vec![];
+        };
+        let mut excess = usage.total.saturating_sub(max_size);
+        if excess == 0 {
+            return vec![];
+        }
+        let mut candidates = usage
+            .contracts
+            .iter()
+            .filter(|(id, _)| !self.pinned.contains_key(*id) && !protected(id))
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|(_, contract)| contract.last_access);
+
+        let mut evictions = vec![];
+        for (id, contract) in candidates {
+            if excess == 0 {
+                break;
+            }
+            excess = excess.saturating_sub(contract.size());
+            evictions.push((*id, contract.size()));
+        }
+        if excess > 0 {
+            tracing::warn!(
+                total = usage.total,
+                max_size,
+                "protected contracts alone exceed the storage budget"
+            );
+        }
+        evictions
+    }
+}
+
+/// Asks the contract handler to evict the contracts over the storage budget, returning the
+/// evicted contracts and the bytes freed by each.
+pub(crate) async fn request_evictions(
+    op_manager: &OpManager,
+    subscribed: HashSet<ContractInstanceId>,
+    max_size: Option<u64>,
+) -> Result<Vec<(ContractKey, u64)>, ExecutorError> {
+    match op_manager
+        .notify_contract_handler(ContractHandlerEvent::StorageBudgetQuery {
+            subscribed,
+            max_size,
+        })
+        .await
+    {
+        Ok(ContractHandlerEvent::StorageBudgetResponse { evicted }) => evicted,
+        Ok(_) => Err(ExecutorError::other(anyhow::anyhow!(
+            "unexpected contract handler response"
+        ))),
+        Err(err) => Err(ExecutorError::other(err)),
+    }
+}
+
+/// Periodically evicts the contracts over the storage budget.
+///
+/// The budget is read from the runtime settings on every run, so it can be set or changed while
+/// the node runs.
+pub(crate) async fn enforce_storage_budget(op_manager: Arc<OpManager>, interval: Duration) {
+    let mut tick = tokio::time::interval(interval);
+    tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    // skip the first, immediate, tick
+    tick.tick().await;
+    loop {
+        tick.tick().await;
+        let Some(max_size) = op_manager.runtime.settings().max_storage_size else {
+            continue;
+        };
+        let subscribed = op_manager
+            .ring
+            .subscribed_contracts()
+            .into_iter()
+            .chain(op_manager.ring.upstream_subscriptions())
+            .map(|key| *key.id())
+            .collect();
+        match request_evictions(&op_manager, subscribed, Some(max_size)).await {
+            Ok(evicted) => {
+                for (key, size) in evicted {
+                    tracing::info!(%key, size, "evicted contract over the storage budget");
+                    op_manager.ring.contract_evicted(key, size).await;
+                }
+            }
+            Err(error) => tracing::error!(%error, "failed enforcing the storage budget"),
+        }
+    }
+}
+
+/// Resizes the memory caches of the contract handler every time their sizes are changed in the
+/// runtime settings.
+pub(crate) async fn resize_caches(op_manager: Arc<OpManager>) {
+    let mut cache_sizes = op_manager.runtime.cache_sizes();
+    while cache_sizes.changed().await.is_ok() {
+        let CacheSizes { state, code } = *cache_sizes.borrow_and_update();
+        match op_manager
+            .notify_contract_handler(ContractHandlerEvent::ResizeCachesQuery {
+                state_cache_size: state,
+                code_cache_size: code,
+            })
+            .await
+        {
+            Ok(ContractHandlerEvent::ResizeCachesResponse) => {
+                tracing::info!(state, code, "resized the contract caches");
+            }
+            Ok(other) => {
+                tracing::error!(%other, "unexpected contract handler response");
+            }
+            Err(error) => tracing::error!(%error, "failed resizing the contract caches"),
+        }
+    }
+}
+
+/// Fetches the contracts pinned in this node.
+pub(crate) async fn pinned_contracts(
+    op_manager: &OpManager,
+) -> Result<Vec<PinnedContract>, ExecutorError> {
+    match op_manager
+        .notify_contract_handler(ContractHandlerEvent::PinnedContractsQuery)
+        .await
+    {
+        Ok(ContractHandlerEvent::PinnedContractsResponse { pinned }) => Ok(pinned),
+        Ok(_) => Err(ExecutorError::other(anyhow::anyhow!(
+            "unexpected contract handler response"
+        ))),
+        Err(err) => Err(ExecutorError::other(err)),
+    }
+}
+
+/// Pins or unpins a contract, returning whether its pinned status changed.
+///
+/// Newly pinned contracts are subscribed to right away.
+pub(crate) async fn request_pin(
+    op_manager: Arc<OpManager>,
+    id: ContractInstanceId,
+    pin: bool,
+) -> Result<bool, ExecutorError> {
+    let changed = match op_manager
+        .notify_contract_handler(ContractHandlerEvent::PinQuery { id, pin })
+        .await
+    {
+        Ok(ContractHandlerEvent::PinResponse { changed, .. }) => changed?,
+        Ok(_) => {
+            return Err(ExecutorError::other(anyhow::anyhow!(
+                "unexpected contract handler response"
+            )))
+        }
+        Err(err) => return Err(ExecutorError::other(err)),
+    };
+    if pin && changed {
+        subscribe_to_pinned(op_manager, ContractKey::from(id));
+    }
+    Ok(changed)
+}
+
+fn subscribe_to_pinned(op_manager: Arc<OpManager>, key: ContractKey) {
+    GlobalExecutor::spawn(async move {
+        if let Err(error) = crate::node::subscribe(op_manager, key, None).await {
+            tracing::debug!(%key, %error, "failed subscribing to pinned contract");
+        }
+    });
+}
+
+/// Periodically subscribes again to the pinned contracts this node lost its subscription to,
+/// e.g. after a restart or when the upstream peer disconnects.
+pub(crate) async fn keep_pinned_subscribed(op_manager: Arc<OpManager>, interval: Duration) {
+    let mut tick = tokio::time::interval(interval);
+    tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    // skip the first, immediate, tick; the node is not connected yet
+    tick.tick().await;
+    loop {
+        tick.tick().await;
+        let pinned = match pinned_contracts(&op_manager).await {
+            Ok(pinned) => pinned,
+            Err(error) => {
+                tracing::error!(%error, "failed fetching pinned contracts");
+                continue;
+            }
+        };
+        // only an upstream subscription keeps the contract in sync, having subscribers doesn't
+        let subscribed = op_manager
+            .ring
+            .upstream_subscriptions()
+            .iter()
+            .map(|key| *key.id())
+            .collect::<HashSet<_>>();
+        for contract in pinned {
+            if !subscribed.contains(&contract.id) {
+                tracing::debug!(contract = %contract.id, "subscribing to pinned contract");
+                subscribe_to_pinned(op_manager.clone(), ContractKey::from(contract.id));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(n: u8) -> ContractKey {
+        ContractKey::from(ContractInstanceId::new([n; 32]))
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut usage = StorageUsage::default();
+        for n in 0..4 {
+            usage.record(&key(n), 100, Some(10));
+        }
+        usage.touch(&key(0));
+        assert_eq!(usage.total(), 440);
+
+        let budget = StorageBudget {
+            max_size: Some(250),
+            pinned: HashMap::from([(*key(1).id(), PinSource::Config)]),
+            pins_file: None,
+        };
+        let protected = *key(2).id();
+        let evicted = budget.select_evictions(&usage, |id| id == &protected);
+        assert_eq!(evicted, vec![(*key(3).id(), 110), (*key(0).id(), 110)]);
+
+        assert_eq!(usage.remove(&key(3)), 110);
+        usage.record(&key(0), 20, None);
+        assert_eq!(usage.total(), 250);
+        assert!(budget.select_evictions(&usage, |_| false).is_empty());
+    }
+
+    #[tokio::test]
+    async fn pin_and_persist() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let pins_file = dir.path().join("pinned-contracts.json");
+        let config = StorageBudgetConfig {
+            pinned_contracts: vec![key(1).id().to_string()],
+            ..Default::default()
+        };
+        let mut budget = StorageBudget::from_config(&config, Some(pins_file.clone()));
+        assert!(budget.pin(*key(2).id()).await);
+        assert!(!budget.pin(*key(1).id()).await);
+        assert!(!budget.unpin(key(1).id()).await);
+
+        let mut reloaded = StorageBudget::from_config(&config, Some(pins_file));
+        let mut pinned = reloaded.pinned();
+        pinned.sort_by_key(|contract| contract.source == PinSource::Api);
+        assert_eq!(
+            pinned,
+            vec![
+                PinnedContract {
+                    id: *key(1).id(),
+                    source: PinSource::Config
+                },
+                PinnedContract {
+                    id: *key(2).id(),
+                    source: PinSource::Api
+                },
+            ]
+        );
+        assert!(reloaded.unpin(key(2).id()).await);
+        assert!(!reloaded.unpin(key(2).id()).await);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_pins_are_skipped() {
+        let config = StorageBudgetConfig {
+            pinned_contracts: vec!["not a contract id".to_owned(), key(1).id().to_string()],
+            ..Default::default()
+        };
+        let budget = StorageBudget::from_config(&config, None);
+        assert_eq!(
+            budget.pinned(),
+            vec![PinnedContract {
+                id: *key(1).id(),
+                source: PinSource::Config
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn local_puts_are_pinned() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let pins_file = dir.path().join("pinned-contracts.json");
+        // pins persisted by previous versions of the node
+        fs::write(&pins_file, serde_json::to_vec(&[key(1).id().to_string()])?)?;
+        let config = StorageBudgetConfig {
+            pinned_contracts: vec![],
+            ..Default::default()
+        };
+        let mut budget = StorageBudget::from_config(&config, Some(pins_file.clone()));
+        budget.pin_local_put(*key(2).id()).await;
+
+        let reloaded = StorageBudget::from_config(&config, Some(pins_file));
+        let mut pinned = reloaded.pinned();
+        pinned.sort_by_key(|contract| contract.source == PinSource::LocalPut);
+        assert_eq!(
+            pinned,
+            vec![
+                PinnedContract {
+                    id: *key(1).id(),
+                    source: PinSource::Api
+                },
+                PinnedContract {
+                    id: *key(2).id(),
+                    source: PinSource::LocalPut
+                },
+            ]
+        );
+
+        let mut usage = StorageUsage::default();
+        usage.record(&key(2), 100, None);
+        let budget = StorageBudget {
+            max_size: Some(10),
+            ..reloaded
+        };
+        assert!(budget.select_evictions(&usage, |_| false).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn usage_from_sizes() {
+        let sizes = StoredSizes {
+            states: vec![(vec![1; 32], 100), (vec![1, 2], 10)],
+            params: vec![(vec![1; 32], 10)],
+        };
+        let usage = StorageUsage::from_sizes(&sizes);
+        // entries not keyed by a contract id are skipped
+        assert_eq!(usage.total(), 110);
+    }
+}
@@ -1,21 +1,54 @@
-use std::path::Path;
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use freenet_stdlib::prelude::*;
-use redb::{Database, TableDefinition};
+use redb::{Database, ReadTransaction, ReadableTable, ReadableTableMetadata, TableDefinition};
 
+use super::snapshot::{SnapshotWriter, StateSnapshot, StoredSizes};
 use crate::wasm_runtime::StateStorage;
 
 const CONTRACT_PARAMS_TABLE: TableDefinition<&[u8], &[u8]> =
     TableDefinition::new("contract_params");
 const STATE_TABLE: TableDefinition<&[u8], &[u8]> = TableDefinition::new("state");
 
-pub struct ReDb(Database);
+#[derive(Clone)]
+pub struct ReDb(Arc<Database>);
+
+/// Streams the state and parameters tables, as seen by `txn`, into a new snapshot file.
+fn write_tables(txn: ReadTransaction, dir: &Path, keep: usize) -> anyhow::Result<PathBuf> {
+    let mut writer = SnapshotWriter::create(dir, chrono::Utc::now())?;
+    for table in [STATE_TABLE, CONTRACT_PARAMS_TABLE] {
+        let tbl = txn.open_table(table)?;
+        writer.begin_entries(tbl.len()?)?;
+        for entry in tbl.iter()? {
+            let (k, v) = entry?;
+            writer.entry(k.value(), v.value())?;
+        }
+    }
+    Ok(writer.finish(keep)?)
+}
+
+fn read_sizes(
+    txn: &ReadTransaction,
+    table: TableDefinition<&[u8], &[u8]>,
+) -> Result<Vec<(Vec<u8>, u64)>, redb::Error> {
+    let tbl = txn.open_table(table)?;
+    let mut sizes = vec![];
+    for entry in tbl.iter()? {
+        let (k, v) = entry?;
+        sizes.push((k.value().to_vec(), v.value().len() as u64));
+    }
+    Ok(sizes)
+}
 
 impl ReDb {
     pub async fn new(data_dir: &Path) -> Result<Self, redb::Error> {
         let db_path = data_dir.join("db");
         tracing::info!("loading contract store from {db_path:?}");
-        match Database::create(db_path).map(Self) {
+        match Database::create(db_path).map(|db| Self(Arc::new(db))) {
             Ok(db) => {
                 let txn = db.0.begin_write()?;
                 {
@@ -98,4 +131,54 @@ impl StateStorage for ReDb {
             None => Ok(None),
         }
     }
+
+    async fn remove(&mut self, key: &ContractKey) -> Result<(), Self::Error> {
+        let txn = self.0.begin_write()?;
+
+        {
+            let mut tbl = txn.open_table(STATE_TABLE)?;
+            tbl.remove(key.as_bytes())?;
+            let mut tbl = txn.open_table(CONTRACT_PARAMS_TABLE)?;
+            tbl.remove(key.as_bytes())?;
+        }
+        txn.commit().map_err(Into::into)
+    }
+
+    async fn sizes(&self) -> Result<StoredSizes, Self::Error> {
+        let txn = self.0.begin_read()?;
+        Ok(StoredSizes {
+            states: read_sizes(&txn, STATE_TABLE)?,
+            params: read_sizes(&txn, CONTRACT_PARAMS_TABLE)?,
+        })
+    }
+
+    fn write_snapshot(
+        &self,
+        dir: PathBuf,
+        keep: usize,
+    ) -> impl Future<Output = anyhow::Result<PathBuf>> + Send + 'static {
+        // the read transaction is opened right away, so later writes are not part of the snapshot
+        let txn = self.0.begin_read();
+        async move {
+            let txn = txn?;
+            tokio::task::spawn_blocking(move || write_tables(txn, &dir, keep)).await?
+        }
+    }
+
+    async fn restore(&mut self, snapshot: StateSnapshot) -> Result<(), Self::Error> {
+        let txn = self.0.begin_write()?;
+        {
+            txn.delete_table(STATE_TABLE)?;
+            txn.delete_table(CONTRACT_PARAMS_TABLE)?;
+            let mut tbl = txn.open_table(STATE_TABLE)?;
+            for (k, v) in &snapshot.states {
+                tbl.insert(k.as_slice(), v.as_slice())?;
+            }
+            let mut tbl = txn.open_table(CONTRACT_PARAMS_TABLE)?;
+            for (k, v) in &snapshot.params {
+                tbl.insert(k.as_slice(), v.as_slice())?;
+            }
+        }
+        txn.commit().map_err(Into::into)
+    }
 }
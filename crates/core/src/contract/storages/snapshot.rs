@@ -0,0 +1,355 @@
+//! Point-in-time snapshots of the contract state store.
+//!
+//! A snapshot contains the raw contents of the state and parameters tables as they were at
+//! a single read transaction, so it can be restored into an empty (or corrupted) store without
+//! having to resync every contract from the network.
+//!
+//! On disk a snapshot is laid out as `MAGIC | blake3(payload) | payload`, where the payload is the
+//! bincode encoded [`StateSnapshot`]. The checksum is verified before restoring.
+//!
+//! Snapshots are written entry by entry with a [`SnapshotWriter`], so the store is never loaded
+//! in memory as a whole while taking one.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::Storage;
+use crate::{
+    config::Config,
+    contract::{ContractHandlerEvent, ExecutorError},
+    node::OpManager,
+    wasm_runtime::StateStorage,
+};
+
+const MAGIC: &[u8; 8] = b"FNSNAP01";
+const SNAPSHOT_PREFIX: &str = "snapshot-";
+const SNAPSHOT_EXT: &str = "bin";
+
+/// Default number of snapshots retained in the snapshot directory.
+pub const DEFAULT_SNAPSHOTS_TO_KEEP: usize = 5;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StateSnapshot {
+    pub created_at: DateTime<Utc>,
+    /// Raw `(contract key, state)` entries.
+    pub states: Vec<(Vec<u8>, Vec<u8>)>,
+    /// Raw `(contract key, parameters)` entries.
+    pub params: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SnapshotError {
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+    #[error("not a state snapshot file")]
+    InvalidFormat,
+    #[error("snapshot checksum mismatch, the file is corrupted")]
+    ChecksumMismatch,
+    #[error(transparent)]
+    Serialization(#[from] bincode::Error),
+}
+
+/// Sizes of the entries of the state store, read without loading the entries themselves.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct StoredSizes {
+    /// Raw `(contract key, state size)` entries.
+    pub states: Vec<(Vec<u8>, u64)>,
+    /// Raw `(contract key, parameters size)` entries.
+    pub params: Vec<(Vec<u8>, u64)>,
+}
+
+impl From<&StateSnapshot> for StoredSizes {
+    fn from(snapshot: &StateSnapshot) -> Self {
+        let sizes = |entries: &[(Vec<u8>, Vec<u8>)]| {
+            entries
+                .iter()
+                .map(|(key, value)| (key.clone(), value.len() as u64))
+                .collect()
+        };
+        Self {
+            states: sizes(&snapshot.states),
+            params: sizes(&snapshot.params),
+        }
+    }
+}
+
+impl StateSnapshot {
+    pub fn new(states: Vec<(Vec<u8>, Vec<u8>)>, params: Vec<(Vec<u8>, Vec<u8>)>) -> Self {
+        Self {
+            created_at: Utc::now(),
+            states,
+            params,
+        }
+    }
+
+    /// Writes the snapshot into `dir`, returning the path of the new snapshot file.
+    ///
+    /// Older snapshots beyond `keep` are removed.
+    pub fn write_to(&self, dir: &Path, keep: usize) -> Result<PathBuf, SnapshotError> {
+        let mut writer = SnapshotWriter::create(dir, self.created_at)?;
+        writer.begin_entries(self.states.len() as u64)?;
+        for (key, state) in &self.states {
+            writer.entry(key, state)?;
+        }
+        writer.begin_entries(self.params.len() as u64)?;
+        for (key, params) in &self.params {
+            writer.entry(key, params)?;
+        }
+        writer.finish(keep)
+    }
+
+    pub fn read_from(path: &Path) -> Result<Self, SnapshotError> {
+        let contents = std::fs::read(path)?;
+        let header_len = MAGIC.len() + blake3::OUT_LEN;
+        if contents.len() < header_len || &contents[..MAGIC.len()] != MAGIC {
+            return Err(SnapshotError::InvalidFormat);
+        }
+        let (hash, payload) = contents[MAGIC.len()..].split_at(blake3::OUT_LEN);
+        if blake3::hash(payload).as_bytes() != hash {
+            return Err(SnapshotError::ChecksumMismatch);
+        }
+        Ok(bincode::deserialize(payload)?)
+    }
+}
+
+/// Writes a snapshot file one entry at a time, producing the same encoding as serializing a
+/// whole [`StateSnapshot`].
+///
+/// The states section must be written first, then the parameters one, each starting with
+/// [`SnapshotWriter::begin_entries`] and the exact number of entries that follow.
+///
+/// The file is written under a temporary name and renamed once complete, so a crash while
+/// writing never leaves a truncated snapshot behind.
+pub struct SnapshotWriter {
+    file: BufWriter<File>,
+    hasher: blake3::Hasher,
+    dir: PathBuf,
+    path: PathBuf,
+    tmp_path: PathBuf,
+}
+
+impl SnapshotWriter {
+    pub fn create(dir: &Path, created_at: DateTime<Utc>) -> Result<Self, SnapshotError> {
+        std::fs::create_dir_all(dir)?;
+        let file_name = format!(
+            "{SNAPSHOT_PREFIX}{}.{SNAPSHOT_EXT}",
+            created_at.format("%Y%m%dT%H%M%S%.3fZ")
+        );
+        let path = dir.join(file_name);
+        let tmp_path = path.with_extension("tmp");
+        let mut file = BufWriter::new(File::create(&tmp_path)?);
+        file.write_all(MAGIC)?;
+        // the checksum is only known once the whole payload is written
+        file.write_all(&[0; blake3::OUT_LEN])?;
+        let mut writer = Self {
+            file,
+            hasher: blake3::Hasher::new(),
+            dir: dir.to_owned(),
+            path,
+            tmp_path,
+        };
+        writer.write_payload(&created_at)?;
+        Ok(writer)
+    }
+
+    /// Starts a section of `len` entries.
+    pub fn begin_entries(&mut self, len: u64) -> Result<(), SnapshotError> {
+        self.write_payload(&len)
+    }
+
+    pub fn entry(&mut self, key: &[u8], value: &[u8]) -> Result<(), SnapshotError> {
+        self.write_payload(&(key, value))
+    }
+
+    /// Completes the snapshot, returning its path. Older snapshots beyond `keep` are removed.
+    pub fn finish(self, keep: usize) -> Result<PathBuf, SnapshotError> {
+        let Self {
+            file,
+            hasher,
+            dir,
+            path,
+            tmp_path,
+        } = self;
+        let mut file = file.into_inner().map_err(|err| err.into_error())?;
+        file.seek(SeekFrom::Start(MAGIC.len() as u64))?;
+        file.write_all(hasher.finalize().as_bytes())?;
+        file.sync_all()?;
+        drop(file);
+        std::fs::rename(&tmp_path, &path)?;
+        prune_snapshots(&dir, keep)?;
+        Ok(path)
+    }
+
+    fn write_payload<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SnapshotError> {
+        let bytes = bincode::serialize(value)?;
+        self.hasher.update(&bytes);
+        self.file.write_all(&bytes)?;
+        Ok(())
+    }
+}
+
+/// Returns the snapshots present in `dir`, oldest first.
+pub fn list_snapshots(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut snapshots = std::fs::read_dir(dir)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| {
+            p.extension().is_some_and(|ext| ext == SNAPSHOT_EXT)
+                && p.file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| n.starts_with(SNAPSHOT_PREFIX))
+        })
+        .collect::<Vec<_>>();
+    // file names embed the creation timestamp so lexicographic order is chronological
+    snapshots.sort();
+    Ok(snapshots)
+}
+
+fn prune_snapshots(dir: &Path, keep: usize) -> std::io::Result<()> {
+    let snapshots = list_snapshots(dir)?;
+    let excess = snapshots.len().saturating_sub(keep.max(1));
+    for old in &snapshots[..excess] {
+        tracing::debug!(snapshot = ?old, "removing old state snapshot");
+        std::fs::remove_file(old)?;
+    }
+    Ok(())
+}
+
+/// Replaces the contents of the node state store with the given snapshot, returning
+/// the number of contract states restored.
+///
+/// Must be run while the node is stopped.
+pub async fn restore_snapshot(config: &Config, snapshot_path: &Path) -> anyhow::Result<usize> {
+    let snapshot = StateSnapshot::read_from(snapshot_path)?;
+    let restored = snapshot.states.len();
+    let mut storage = Storage::new(&config.db_dir()).await?;
+    storage.restore(snapshot).await?;
+    Ok(restored)
+}
+
+/// Requests a snapshot from the contract handler, so it is consistent with any in-flight
+/// state mutations.
+pub(crate) async fn request_snapshot(
+    op_manager: &OpManager,
+    snapshot_dir: PathBuf,
+    keep: usize,
+) -> Result<PathBuf, ExecutorError> {
+    match op_manager
+        .notify_contract_handler(ContractHandlerEvent::SnapshotQuery { snapshot_dir, keep })
+        .await
+    {
+        Ok(ContractHandlerEvent::SnapshotResponse { result }) => result,
+        Ok(_) => Err(ExecutorError::other(anyhow::anyhow!(
+            "unexpected contract handler response"
+        ))),
+        Err(err) => Err(ExecutorError::other(err)),
+    }
+}
+
+/// Periodically snapshots the contract state store.
+pub(crate) async fn periodic_snapshots(
+    op_manager: Arc<OpManager>,
+    snapshot_dir: PathBuf,
+    interval: Duration,
+    keep: usize,
+) {
+    let mut tick = tokio::time::interval(interval);
+    tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    // skip the first, immediate, tick
+    tick.tick().await;
+    loop {
+        tick.tick().await;
+        match request_snapshot(&op_manager, snapshot_dir.clone(), keep).await {
+            Ok(path) => tracing::info!(snapshot = ?path, "created contract state snapshot"),
+            Err(error) => tracing::error!(%error, "failed creating contract state snapshot"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> StateSnapshot {
+        StateSnapshot::new(
+            vec![(vec![1, 2, 3], vec![4, 5, 6]), (vec![7], vec![8])],
+            vec![(vec![1, 2, 3], vec![9])],
+        )
+    }
+
+    #[test]
+    fn roundtrip() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let original = snapshot();
+        let path = original.write_to(dir.path(), DEFAULT_SNAPSHOTS_TO_KEEP)?;
+        assert_eq!(StateSnapshot::read_from(&path)?, original);
+        Ok(())
+    }
+
+    #[test]
+    fn detects_corruption() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = snapshot().write_to(dir.path(), DEFAULT_SNAPSHOTS_TO_KEEP)?;
+        let mut contents = std::fs::read(&path)?;
+        *contents.last_mut().unwrap() ^= 0xff;
+        std::fs::write(&path, contents)?;
+        assert!(matches!(
+            StateSnapshot::read_from(&path),
+            Err(SnapshotError::ChecksumMismatch)
+        ));
+
+        std::fs::write(&path, b"garbage")?;
+        assert!(matches!(
+            StateSnapshot::read_from(&path),
+            Err(SnapshotError::InvalidFormat)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn streamed_snapshot_matches_serialized() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let original = snapshot();
+        let mut writer = SnapshotWriter::create(dir.path(), original.created_at)?;
+        writer.begin_entries(original.states.len() as u64)?;
+        for (key, state) in &original.states {
+            writer.entry(key, state)?;
+        }
+        writer.begin_entries(original.params.len() as u64)?;
+        for (key, params) in &original.params {
+            writer.entry(key, params)?;
+        }
+        let path = writer.finish(DEFAULT_SNAPSHOTS_TO_KEEP)?;
+
+        let contents = std::fs::read(&path)?;
+        let payload = bincode::serialize(&original)?;
+        assert_eq!(
+            &contents[MAGIC.len() + blake3::OUT_LEN..],
+            payload.as_slice()
+        );
+        assert_eq!(StateSnapshot::read_from(&path)?, original);
+        Ok(())
+    }
+
+    #[test]
+    fn prunes_old_snapshots() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut written = vec![];
+        for _ in 0..4 {
+            written.push(snapshot().write_to(dir.path(), 2)?);
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(list_snapshots(dir.path())?, written[2..].to_vec());
+        Ok(())
+    }
+}
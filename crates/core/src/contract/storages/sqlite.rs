@@ -1,11 +1,17 @@
-use std::{path::Path, str::FromStr};
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 use freenet_stdlib::prelude::*;
+use futures::TryStreamExt;
 use sqlx::{
     sqlite::{SqliteConnectOptions, SqliteRow},
     ConnectOptions, Row, SqlitePool,
 };
 
+use super::snapshot::{SnapshotWriter, StateSnapshot, StoredSizes};
 use crate::wasm_runtime::{ContractError, StateStorage, StateStoreError};
 
 async fn create_contracts_table(pool: &SqlitePool) -> Result<(), SqlDbError> {
@@ -110,6 +116,89 @@ impl StateStorage for Pool {
             Err(_) => Err(SqlDbError::ContractNotFound),
         }
     }
+
+    async fn remove(&mut self, key: &ContractKey) -> Result<(), Self::Error> {
+        sqlx::query("DELETE FROM states WHERE contract = ?")
+            .bind(key.as_bytes())
+            .execute(&self.0)
+            .await?;
+        Ok(())
+    }
+
+    async fn sizes(&self) -> Result<StoredSizes, Self::Error> {
+        let rows = sqlx::query(
+            "SELECT contract, length(state) AS state_size, length(params) AS params_size FROM states",
+        )
+        .fetch_all(&self.0)
+        .await?;
+        let mut sizes = StoredSizes::default();
+        for row in rows {
+            let contract: Vec<u8> = row.get("contract");
+            if let Some(state_size) = row.get::<Option<i64>, _>("state_size") {
+                sizes.states.push((contract.clone(), state_size as u64));
+            }
+            if let Some(params_size) = row.get::<Option<i64>, _>("params_size") {
+                sizes.params.push((contract, params_size as u64));
+            }
+        }
+        Ok(sizes)
+    }
+
+    fn write_snapshot(
+        &self,
+        dir: PathBuf,
+        keep: usize,
+    ) -> impl Future<Output = anyhow::Result<PathBuf>> + Send + 'static {
+        let pool = self.0.clone();
+        async move {
+            // both sections are read within the same transaction, so they are consistent
+            let mut txn = pool.begin().await?;
+            let mut writer = SnapshotWriter::create(&dir, chrono::Utc::now())?;
+            for column in ["state", "params"] {
+                let len: i64 = sqlx::query_scalar(&format!(
+                    "SELECT COUNT(*) FROM states WHERE {column} IS NOT NULL"
+                ))
+                .fetch_one(&mut *txn)
+                .await?;
+                writer.begin_entries(len as u64)?;
+                let query =
+                    format!("SELECT contract, {column} FROM states WHERE {column} IS NOT NULL");
+                let mut rows = sqlx::query(&query).fetch(&mut *txn);
+                while let Some(row) = rows.try_next().await? {
+                    let contract: Vec<u8> = row.get("contract");
+                    let value: Vec<u8> = row.get(column);
+                    writer.entry(&contract, &value)?;
+                }
+            }
+            Ok(writer.finish(keep)?)
+        }
+    }
+
+    async fn restore(&mut self, snapshot: StateSnapshot) -> Result<(), Self::Error> {
+        let mut txn = self.0.begin().await?;
+        sqlx::query("DELETE FROM states").execute(&mut *txn).await?;
+        for (contract, state) in &snapshot.states {
+            sqlx::query("INSERT INTO states (contract, state) VALUES ($1, $2)")
+                .bind(contract.as_slice())
+                .bind(state.as_slice())
+                .execute(&mut *txn)
+                .await?;
+        }
+        for (contract, params) in &snapshot.params {
+            sqlx::query(
+                "INSERT INTO states (contract, params) 
+                     VALUES ($1, $2)
+                     ON CONFLICT(contract) DO UPDATE SET params = excluded.params
+                     ",
+            )
+            .bind(contract.as_slice())
+            .bind(params.as_slice())
+            .execute(&mut *txn)
+            .await?;
+        }
+        txn.commit().await?;
+        Ok(())
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
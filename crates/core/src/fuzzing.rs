@@ -0,0 +1,52 @@
+//! Entry points to the decoders of untrusted input, for fuzzing.
+//!
+//! Each function takes raw bytes as received from a remote peer, a client or a contract state
+//! and runs them through the same decoding the node does. Errors are expected and ignored; a
+//! panic, hang or unbounded allocation is a bug. The fuzz targets live in the `fuzz` directory of
+//! the crate.
+
+use std::path::Path;
+
+use crate::{
+    client_events::websocket::decode_client_request, message::NetMessage, server::WebApp,
+    transport, util::EncodingProtocol,
+};
+
+/// A decrypted packet received from a peer.
+pub fn peer_packet(data: &[u8]) {
+    if let Ok(packet) = transport::SymmetricMessage::deser(data) {
+        if let transport::SymmetricMessagePayload::ShortMessage { payload } = packet.payload {
+            peer_message(&payload);
+        }
+    }
+}
+
+/// A message received from a peer, once reassembled from its packets.
+pub fn peer_message(data: &[u8]) {
+    let max_size = crate::config::PayloadLimits::default().max_message_size;
+    if let Ok(decoded) = transport::decode_message(data.to_vec(), max_size) {
+        let _ = bincode::deserialize::<NetMessage>(&decoded);
+    }
+}
+
+/// Writes the messages of a session recorded by a node to `dir`, as seeds for [`peer_message`].
+/// Returns the number of new seeds.
+pub fn seeds_from_recording(recording: &Path, dir: &Path) -> anyhow::Result<usize> {
+    Ok(crate::node::replay::export_messages(recording, dir)?)
+}
+
+/// A request received through the websocket client API, in any of its encodings.
+pub fn client_request(data: &[u8]) {
+    for encoding in [EncodingProtocol::Native, EncodingProtocol::Flatbuffers] {
+        let _ = decode_client_request(data, encoding);
+    }
+}
+
+/// The state of a web app container contract, as served by the HTTP gateway.
+pub fn web_bundle(data: &[u8]) {
+    if let Ok(mut app) = WebApp::try_from(data) {
+        let _ = app.manifest();
+        let _ = app.mime_overrides();
+        let _ = app.get_file("index.html");
+    }
+}
@@ -7,6 +7,10 @@ pub mod config;
 /// Handling of contracts and delegates functionality.
 mod contract;
 
+/// Entry points for fuzzing the decoders of untrusted input.
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
+
 /// Generated messages from the flatbuffers schema for the network monitor.
 pub mod generated;
 
@@ -46,9 +50,12 @@ mod wasm_runtime;
 /// Exports to build a running local node.
 pub mod local_node {
     use super::*;
+    pub use contract::storages::snapshot::restore_snapshot;
     pub use contract::Executor;
     pub use contract::OperationMode;
+    pub use node::replay::{replay_session, ReplayReport, ReplayStep};
     pub use node::NodeConfig;
+    pub use wasm_runtime::WasmEngine;
 }
 
 /// Exports for the dev tool.
@@ -11,9 +11,15 @@ use serde::{Deserialize, Serialize};
 use ulid::Ulid;
 
 use crate::{
-    node::PeerId,
+    client_events::ClientId,
+    node::{gossip::GossipMsg, PeerId},
     operations::{
-        connect::ConnectMsg, get::GetMsg, put::PutMsg, subscribe::SubscribeMsg, update::UpdateMsg,
+        connect::{ConnectMsg, ConnectRequest},
+        get::GetMsg,
+        put::PutMsg,
+        state_transfer::StateTransferMsg,
+        subscribe::SubscribeMsg,
+        update::UpdateMsg,
     },
     ring::{Location, PeerKeyLocation},
 };
@@ -40,7 +46,7 @@ impl Transaction {
 
     pub(crate) fn new<T: TxType>() -> Self {
         let ty = <T as TxType>::tx_type_id();
-        let id = Ulid::new();
+        let id = crate::node::clock::new_ulid();
         Self::update(ty.0, id)
         // Self { id }
     }
@@ -57,8 +63,9 @@ impl Transaction {
         }
     }
 
-    pub fn timed_out(&self) -> bool {
-        self.elapsed() >= crate::config::OPERATION_TTL
+    /// Whether the transaction timed out by `now`, as given by the clock of the node.
+    pub(crate) fn timed_out_at(&self, now: SystemTime) -> bool {
+        self.elapsed_at(now) >= crate::config::OPERATION_TTL
     }
 
     #[cfg(feature = "trace-ot")]
@@ -71,8 +78,8 @@ impl Transaction {
         self.id.0.to_le_bytes()
     }
 
-    fn elapsed(&self) -> Duration {
-        let current_unix_epoch_ts = SystemTime::now()
+    fn elapsed_at(&self, now: SystemTime) -> Duration {
+        let current_unix_epoch_ts = now
             .duration_since(SystemTime::UNIX_EPOCH)
             .expect("now should be always be later than unix epoch")
             .as_millis() as u64;
@@ -89,9 +96,12 @@ impl Transaction {
     ///
     /// This will allow, for example, to compare against any older transactions,
     /// in order to remove them.
-    pub fn ttl_transaction() -> Self {
+    pub fn ttl_transaction_at(now: SystemTime) -> Self {
         let id = Ulid::new();
-        let ts = id.timestamp_ms();
+        let ts = now
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("now should be always be later than unix epoch")
+            .as_millis() as u64;
         const TTL_MS: u64 = crate::config::OPERATION_TTL.as_millis() as u64;
         let ttl_epoch: u64 = ts - TTL_MS;
 
@@ -128,6 +138,16 @@ impl Display for Transaction {
     }
 }
 
+impl std::str::FromStr for Transaction {
+    type Err = ulid::DecodeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self {
+            id: Ulid::from_string(s)?,
+        })
+    }
+}
+
 impl std::fmt::Debug for Transaction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.id)
@@ -200,6 +220,18 @@ mod sealed_msg_type {
         }
     }
 
+    impl From<TransactionType> for crate::config::OperationKind {
+        fn from(tx_type: TransactionType) -> Self {
+            match tx_type {
+                TransactionType::Connect => Self::Connect,
+                TransactionType::Put => Self::Put,
+                TransactionType::Get => Self::Get,
+                TransactionType::Subscribe => Self::Subscribe,
+                TransactionType::Update => Self::Update,
+            }
+        }
+    }
+
     macro_rules! transaction_type_enumeration {
         ($variant:ident, $enum_type:ident, decl struct { $( $var:ident -> $ty:ty ),+ }) => {
             $(
@@ -253,6 +285,14 @@ pub(crate) enum NetMessageV1 {
     },
     Update(UpdateMsg),
     Aborted(Transaction),
+    /// The requester of the transaction cancelled it, the peers working on it can drop it.
+    Cancelled(Transaction),
+    /// Ephemeral application message, not part of any transaction.
+    Gossip(GossipMsg),
+    /// The sender joined the gossip channel, and relays and accepts its messages.
+    GossipJoined,
+    /// Large state carried by a message of the transaction, sent in chunks.
+    StateTransfer(StateTransferMsg),
 }
 
 trait Versioned {
@@ -277,6 +317,10 @@ impl Versioned for NetMessageV1 {
             NetMessageV1::Unsubscribed { .. } => semver::Version::new(1, 0, 0),
             NetMessageV1::Update(_) => semver::Version::new(1, 0, 0),
             NetMessageV1::Aborted(_) => semver::Version::new(1, 0, 0),
+            NetMessageV1::Cancelled(_) => semver::Version::new(1, 1, 0),
+            NetMessageV1::Gossip(_) => semver::Version::new(1, 1, 0),
+            NetMessageV1::GossipJoined => semver::Version::new(1, 2, 0),
+            NetMessageV1::StateTransfer(_) => semver::Version::new(1, 2, 0),
         }
     }
 }
@@ -317,6 +361,10 @@ pub(crate) enum NodeEvent {
         callback: tokio::sync::mpsc::Sender<QueryResult>,
     },
     TransactionTimedOut(Transaction),
+    /// Cancel a transaction started by this node.
+    CancelTransaction(Transaction),
+    /// Cancel the transactions a client is waiting for, e.g. because it went away.
+    CancelClientTransactions(ClientId),
 }
 
 pub(crate) enum QueryResult {
@@ -349,6 +397,50 @@ impl Display for NodeEvent {
             NodeEvent::TransactionTimedOut(transaction) => {
                 write!(f, "Transaction timed out ({})", transaction)
             }
+            NodeEvent::CancelTransaction(transaction) => {
+                write!(f, "Cancel transaction ({transaction})")
+            }
+            NodeEvent::CancelClientTransactions(client) => {
+                write!(f, "Cancel transactions of client {client}")
+            }
+        }
+    }
+}
+
+impl NetMessage {
+    /// Whether the message is a request the peer it is sent to is expected to respond to, as
+    /// opposed to a response or a notification.
+    pub fn awaits_response(&self) -> bool {
+        matches!(
+            self,
+            NetMessage::V1(
+                NetMessageV1::Connect(ConnectMsg::Request { .. })
+                    | NetMessageV1::Put(PutMsg::SeekNode { .. })
+                    | NetMessageV1::Get(GetMsg::SeekNode { .. } | GetMsg::Probe { .. })
+                    | NetMessageV1::Subscribe(SubscribeMsg::SeekNode { .. })
+                    | NetMessageV1::Update(UpdateMsg::SeekNode { .. })
+            )
+        )
+    }
+
+    /// Hops a request may still travel before it's answered, `None` if the message doesn't tell.
+    pub fn hops_to_live(&self) -> Option<usize> {
+        match self {
+            NetMessage::V1(NetMessageV1::Put(PutMsg::SeekNode { htl, .. }))
+            | NetMessage::V1(NetMessageV1::Get(GetMsg::SeekNode { htl, .. }))
+            | NetMessage::V1(NetMessageV1::Subscribe(SubscribeMsg::SeekNode { htl, .. })) => {
+                Some(*htl)
+            }
+            NetMessage::V1(NetMessageV1::Get(GetMsg::Probe { .. })) => Some(1),
+            NetMessage::V1(NetMessageV1::Connect(ConnectMsg::Request { msg, .. })) => match msg {
+                ConnectRequest::StartJoinReq { hops_to_live, .. }
+                | ConnectRequest::CheckConnectivity { hops_to_live, .. } => Some(*hops_to_live),
+                ConnectRequest::FindOptimalPeer {
+                    max_hops_to_live, ..
+                } => Some(*max_hops_to_live),
+                _ => None,
+            },
+            _ => None,
         }
     }
 }
@@ -381,7 +473,9 @@ impl MessageStats for NetMessageV1 {
             NetMessageV1::Get(op) => op.id(),
             NetMessageV1::Subscribe(op) => op.id(),
             NetMessageV1::Update(op) => op.id(),
-            NetMessageV1::Aborted(tx) => tx,
+            NetMessageV1::Aborted(tx) | NetMessageV1::Cancelled(tx) => tx,
+            NetMessageV1::Gossip(_) | NetMessageV1::GossipJoined => Transaction::NULL,
+            NetMessageV1::StateTransfer(msg) => msg.id(),
             NetMessageV1::Unsubscribed { transaction, .. } => transaction,
         }
     }
@@ -393,7 +487,8 @@ impl MessageStats for NetMessageV1 {
             NetMessageV1::Get(op) => op.target().as_ref().map(|b| b.borrow().clone()),
             NetMessageV1::Subscribe(op) => op.target().as_ref().map(|b| b.borrow().clone()),
             NetMessageV1::Update(op) => op.target().as_ref().map(|b| b.borrow().clone()),
-            NetMessageV1::Aborted(_) => None,
+            NetMessageV1::Aborted(_) | NetMessageV1::Cancelled(_) | NetMessageV1::Gossip(_) => None,
+            NetMessageV1::GossipJoined | NetMessageV1::StateTransfer(_) => None,
             NetMessageV1::Unsubscribed { .. } => None,
         }
     }
@@ -405,7 +500,8 @@ impl MessageStats for NetMessageV1 {
             NetMessageV1::Get(op) => op.requested_location(),
             NetMessageV1::Subscribe(op) => op.requested_location(),
             NetMessageV1::Update(op) => op.requested_location(),
-            NetMessageV1::Aborted(_) => None,
+            NetMessageV1::Aborted(_) | NetMessageV1::Cancelled(_) | NetMessageV1::Gossip(_) => None,
+            NetMessageV1::GossipJoined | NetMessageV1::StateTransfer(_) => None,
             NetMessageV1::Unsubscribed { .. } => None,
         }
     }
@@ -423,6 +519,10 @@ impl Display for NetMessage {
                 Subscribe(msg) => msg.fmt(f)?,
                 Update(msg) => msg.fmt(f)?,
                 Aborted(msg) => msg.fmt(f)?,
+                Cancelled(tx) => write!(f, "Cancelled {{ {tx} }}")?,
+                Gossip(msg) => msg.fmt(f)?,
+                GossipJoined => write!(f, "GossipJoined")?,
+                StateTransfer(msg) => msg.fmt(f)?,
                 Unsubscribed { key, from, .. } => {
                     write!(f, "Unsubscribed {{  key: {}, from: {} }}", key, from)?;
                 }
@@ -471,11 +571,11 @@ mod tests {
 
     #[test]
     fn get_ttl_cutoff_transaction() {
-        let ttl_tx = Transaction::ttl_transaction();
+        let ttl_tx = Transaction::ttl_transaction_at(SystemTime::now());
         let original_tx = Transaction::new::<crate::operations::get::GetMsg>();
 
         assert!(original_tx > ttl_tx);
-        assert!(ttl_tx.timed_out());
+        assert!(ttl_tx.timed_out_at(SystemTime::now()));
         assert!(
             original_tx.id.timestamp_ms() - ttl_tx.id.timestamp_ms()
                 >= crate::config::OPERATION_TTL.as_millis() as u64
@@ -485,4 +585,15 @@ mod tests {
                 < crate::config::OPERATION_TTL.as_millis() as u64 + 5
         );
     }
+    #[test]
+    fn cancellation_message() {
+        let tx = Transaction::new::<GetMsg>();
+        let msg = NetMessage::V1(NetMessageV1::Cancelled(tx));
+        assert_eq!(msg.id(), &tx);
+        assert!(msg.target().is_none());
+        assert!(!msg.awaits_response());
+
+        let msg: NetMessage = bincode::deserialize(&bincode::serialize(&msg).unwrap()).unwrap();
+        assert!(matches!(msg, NetMessage::V1(NetMessageV1::Cancelled(id)) if id == tx));
+    }
 }
@@ -24,16 +24,19 @@ use std::{
     sync::Arc,
     time::Duration,
 };
-use std::{collections::HashSet, convert::Infallible};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+};
 
 use rsa::pkcs8::DecodePublicKey;
 use serde::{Deserialize, Serialize};
 use tracing::Instrument;
 
-use self::p2p_impl::NodeP2P;
+use self::{clock::NodeClock, p2p_impl::NodeP2P};
 use crate::{
     client_events::{BoxedClient, ClientEventsProxy, ClientId, OpenRequest},
-    config::{Address, GatewayConfig, WebsocketApiConfig},
+    config::{Address, GatewayConfig, NodeRole, WebsocketApiConfig},
     contract::{
         Callback, ClientResponsesSender, ContractError, ExecutorError, ExecutorToEventLoopChannel,
         NetworkContractHandler, WaitingTransaction,
@@ -60,9 +63,15 @@ use crate::topology::rate::Rate;
 use crate::transport::{TransportKeypair, TransportPublicKey};
 pub(crate) use op_state_manager::{OpManager, OpNotAvailable};
 
+mod admin_api;
+pub(crate) mod clock;
+pub(crate) mod diagnostics;
+pub(crate) mod gossip;
+pub(crate) mod load_shedding;
 mod network_bridge;
 mod op_state_manager;
 mod p2p_impl;
+pub(crate) mod replay;
 pub(crate) mod testing_impl;
 
 pub struct Node(NodeP2P);
@@ -98,6 +107,9 @@ pub struct NodeConfig {
     /// Only true for an initial gateway/node. If false, the gateway will be disconnected unless other peers connect through it.
     pub should_connect: bool,
     pub is_gateway: bool,
+    /// Part the node plays in the network, which the connection limits, the contracts it stores
+    /// and the subsystems it runs depend on.
+    pub role: NodeRole,
     /// If not specified, a key is generated and used when creating the node.
     pub key_pair: TransportKeypair,
     // optional local info, in case this is an initial bootstrap node
@@ -105,6 +117,9 @@ pub struct NodeConfig {
     pub network_listener_ip: IpAddr,
     /// socket port to bind to the network listener.
     pub network_listener_port: u16,
+    /// Additional IPs to bind the network listener to, on the same port (e.g. IPv6 or other
+    /// interfaces).
+    pub additional_listener_ips: Vec<IpAddr>,
     pub(crate) peer_id: Option<PeerId>,
     pub(crate) config: Arc<Config>,
     /// At least one gateway is required for joining the network.
@@ -118,17 +133,23 @@ pub struct NodeConfig {
     pub(crate) min_number_conn: Option<usize>,
     pub(crate) max_upstream_bandwidth: Option<Rate>,
     pub(crate) max_downstream_bandwidth: Option<Rate>,
+    /// Clock transactions are timed out against, see [`clock`].
+    pub(crate) clock: Arc<NodeClock>,
 }
 
 impl NodeConfig {
     pub async fn new(config: Config) -> anyhow::Result<NodeConfig> {
         tracing::info!("Loading node configuration for mode {}", config.mode);
         let mut gateways = Vec::with_capacity(config.gateways.len());
+        let listener_ips: Vec<_> = std::iter::once(config.network_api.address)
+            .chain(config.network_api.additional_addresses.iter().copied())
+            .collect();
         for gw in &config.gateways {
             let GatewayConfig {
                 address,
                 public_key_path,
                 location,
+                alternative_addresses,
             } = gw;
 
             let mut key_file = File::open(public_key_path).with_context(|| {
@@ -139,7 +160,16 @@ impl NodeConfig {
 
             let pub_key = rsa::RsaPublicKey::from_public_key_pem(&buf)?;
 
-            let address = Self::parse_socket_addr(address).await?;
+            let mut addresses = vec![Self::parse_socket_addr(address).await?];
+            for alternative in alternative_addresses {
+                match Self::parse_socket_addr(alternative).await {
+                    Ok(addr) => addresses.push(addr),
+                    Err(err) => {
+                        tracing::warn!("failed resolving gateway address {alternative:?}: {err}")
+                    }
+                }
+            }
+            let address = preferred_address(&addresses, &listener_ips);
             let peer_id = PeerId::new(address, TransportPublicKey::from(pub_key));
             let location = location
                 .map(Location::new)
@@ -147,9 +177,9 @@ impl NodeConfig {
             gateways.push(InitPeerNode::new(peer_id, location));
         }
         tracing::info!(
-            "Node will be listening at {}:{} internal address",
-            config.network_api.address,
-            config.network_api.port
+            "Node will be listening on port {} at internal addresses {:?}",
+            config.network_api.port,
+            listener_ips
         );
         if let Some(peer_id) = &config.peer_id {
             tracing::info!("Node external address: {}", peer_id.addr);
@@ -157,19 +187,22 @@ impl NodeConfig {
         Ok(NodeConfig {
             should_connect: true,
             is_gateway: config.is_gateway,
+            role: config.role(),
             key_pair: config.transport_keypair().clone(),
             gateways,
             peer_id: config.peer_id.clone(),
             network_listener_ip: config.network_api.address,
             network_listener_port: config.network_api.port,
+            additional_listener_ips: config.network_api.additional_addresses.clone(),
             location: config.location.map(Location::new),
+            max_number_conn: config.network_api.max_connections,
+            min_number_conn: config.network_api.min_connections,
             config: Arc::new(config),
             max_hops_to_live: None,
             rnd_if_htl_above: None,
-            max_number_conn: None,
-            min_number_conn: None,
             max_upstream_bandwidth: None,
             max_downstream_bandwidth: None,
+            clock: Arc::default(),
         })
     }
 
@@ -237,8 +270,21 @@ impl NodeConfig {
         &self.config
     }
 
+    /// All the local addresses the network listener binds to.
+    pub(crate) fn listener_addrs(&self) -> Vec<SocketAddr> {
+        std::iter::once(self.network_listener_ip)
+            .chain(self.additional_listener_ips.iter().copied())
+            .map(|ip| SocketAddr::new(ip, self.network_listener_port))
+            .collect()
+    }
+
     pub fn is_gateway(&mut self) -> &mut Self {
-        self.is_gateway = true;
+        self.with_role(NodeRole::Gateway)
+    }
+
+    pub fn with_role(&mut self, role: NodeRole) -> &mut Self {
+        self.role = role;
+        self.is_gateway = role.is_gateway();
         self
     }
 
@@ -343,6 +389,16 @@ impl NodeConfig {
     }
 }
 
+/// Picks the first gateway address reachable from one of the address families the node
+/// listens on, falling back to the gateway primary address.
+pub(crate) fn preferred_address(addresses: &[SocketAddr], listening: &[IpAddr]) -> SocketAddr {
+    addresses
+        .iter()
+        .find(|addr| listening.iter().any(|ip| ip.is_ipv4() == addr.is_ipv4()))
+        .copied()
+        .unwrap_or(addresses[0])
+}
+
 /// Gateway node to use for joining the network.
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct InitPeerNode {
@@ -493,6 +549,13 @@ async fn process_message<CB>(
     CB: NetworkBridge,
 {
     let tx = Some(*msg.id());
+    // every hop of a transaction logs under a span with its id, so it can be followed across peers
+    let span = tracing::info_span!(
+        "process_message",
+        transaction = %msg.id(),
+        tx_type = %msg.id().transaction_type(),
+        peer = ?op_manager.ring.connection_manager.get_peer_key(),
+    );
     match msg {
         NetMessage::V1(msg_v1) => {
             process_message_v1(
@@ -505,6 +568,7 @@ async fn process_message<CB>(
                 client_req_handler_callback,
                 client_ids,
             )
+            .instrument(span)
             .await
         }
     }
@@ -705,41 +769,68 @@ pub async fn subscribe(
     }
 }
 
+/// Error returned to the clients of cancelled transactions.
+fn cancelled_error() -> freenet_stdlib::client_api::ClientError {
+    ErrorKind::OperationError {
+        cause: "operation cancelled".into(),
+    }
+    .into()
+}
+
+/// Takes the transactions of a disconnected client, returning those to cancel.
+///
+/// PUTs are kept running, so the state the client published still reaches the network; the node
+/// only stops waiting on their result for the client.
+fn disconnected_client_transactions(
+    tx_to_client: &mut HashMap<Transaction, ClientId>,
+    client: ClientId,
+) -> Vec<Transaction> {
+    let mut cancelled = vec![];
+    tx_to_client.retain(|tx, waiting| {
+        if *waiting != client {
+            return true;
+        }
+        if !matches!(tx.transaction_type(), TransactionType::Put) {
+            cancelled.push(*tx);
+        }
+        false
+    });
+    cancelled
+}
+
 async fn handle_aborted_op(
     tx: Transaction,
-    op_manager: &OpManager,
+    op_manager: &Arc<OpManager>,
     gateways: &[PeerKeyLocation],
 ) -> Result<(), OpError> {
     use crate::util::IterExt;
     if let TransactionType::Connect = tx.transaction_type() {
         // attempt to establish a connection failed, this could be a fatal error since the node
-        // is useless without connecting to the network, we will retry with exponential backoff
-        // if necessary
-        match op_manager.pop(&tx) {
-            // only keep attempting to connect if the node hasn't got enough connections yet
-            Ok(Some(OpEnum::Connect(op)))
-                if op.has_backoff()
-                    && op_manager.ring.open_connections()
-                        < op_manager.ring.connection_manager.min_connections =>
-            {
-                let ConnectOp {
-                    gateway, backoff, ..
-                } = *op;
-                if let Some(gateway) = gateway {
-                    tracing::warn!("Retry connecting to gateway {}", gateway.peer);
-                    connect::join_ring_request(backoff, &gateway, op_manager).await?;
-                }
+        // is useless without connecting to the network, the gateway is dialed again once its
+        // backoff expires if necessary
+        if let Ok(Some(OpEnum::Connect(op))) = op_manager.pop(&tx) {
+            let ConnectOp { gateway, .. } = *op;
+            let ring = &op_manager.ring;
+            if let Some(gateway) = &gateway {
+                ring.dials.failed(gateway.peer.addr);
             }
-            Ok(Some(OpEnum::Connect(_))) => {
-                // if no connections were achieved just fail
-                if op_manager.ring.open_connections() == 0 && op_manager.ring.is_gateway() {
+            let retry = match gateway {
+                // only keep attempting to connect if the node hasn't got enough connections yet
+                Some(gateway)
+                    if ring.open_connections() < ring.connection_manager.min_connections() =>
+                {
+                    Some(*gateway)
+                }
+                None if ring.open_connections() == 0 && ring.is_gateway() => {
                     tracing::warn!("Retrying joining the ring with an other gateway");
-                    if let Some(gateway) = gateways.iter().shuffle().next() {
-                        connect::join_ring_request(None, gateway, op_manager).await?
-                    }
+                    gateways.iter().shuffle().next().cloned()
                 }
+                _ => None,
+            };
+            if let Some(gateway) = retry {
+                tracing::warn!("Retry connecting to gateway {}", gateway.peer);
+                connect::retry_gateway(op_manager.clone(), gateway);
             }
-            _ => {}
         }
     }
     Ok(())
@@ -873,7 +964,7 @@ pub async fn run_local_node(
         _ => {}
     }
 
-    let (mut gw, mut ws_proxy) = crate::server::serve_gateway_in(socket).await;
+    let (mut gw, mut ws_proxy) = crate::server::serve_gateway_in(socket).await?;
 
     // TODO: use combinator instead
     // let mut all_clients =
@@ -1013,6 +1104,32 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn gateway_address_family() {
+        let v4 = SocketAddr::from((Ipv4Addr::new(10, 0, 0, 1), 31337));
+        let v6 = SocketAddr::from((Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1), 31337));
+        let only_v6 = [IpAddr::V6(Ipv6Addr::UNSPECIFIED)];
+        assert_eq!(preferred_address(&[v4, v6], &only_v6), v6);
+        let dual = [IpAddr::V4(Ipv4Addr::UNSPECIFIED), only_v6[0]];
+        assert_eq!(preferred_address(&[v4, v6], &dual), v4);
+        assert_eq!(preferred_address(&[v4], &only_v6), v4);
+    }
+
+    #[test]
+    fn keep_puts_of_disconnected_clients() {
+        let client = ClientId::next();
+        let other = ClientId::next();
+        let get = Transaction::new::<get::GetMsg>();
+        let put = Transaction::new::<put::PutMsg>();
+        let foreign = Transaction::new::<get::GetMsg>();
+        let mut tx_to_client = HashMap::from([(get, client), (put, client), (foreign, other)]);
+        assert_eq!(
+            disconnected_client_transactions(&mut tx_to_client, client),
+            vec![get]
+        );
+        assert_eq!(tx_to_client, HashMap::from([(foreign, other)]));
+    }
+
     #[tokio::test]
     async fn test_hostname_resolution() {
         let addr = Address::Hostname("localhost".to_string());
@@ -0,0 +1,616 @@
+//! Administrative HTTP API for node operators.
+//!
+//! The admin API is only bound to localhost, and only if a port was configured.
+//!
+//! Being bound to localhost isn't enough to keep web pages out, a page can reach it through DNS
+//! rebinding or send it simple cross-origin requests. So every request must name localhost in its
+//! `Host` header and carry the admin token, as `Authorization: Bearer <token>`. The token is
+//! generated on every start and written to the `admin-token` file of the config directory, only
+//! readable by the user running the node.
+//!
+//! Operators can also answer there, with the admin token, the prompts asking to let an application
+//! read its namespace of the secrets of a delegate. They are otherwise answered through the client
+//! API by the owning UI of the application, which is sent the prompt.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
+    Extension, Json, Router,
+};
+use freenet_stdlib::prelude::ContractKey;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    replay::{self, RecordingError, RecordingInfo, DEFAULT_RECORDING_WINDOW},
+    OpManager,
+};
+use crate::{
+    client_events::AuthToken,
+    config::{Config, ReloadError, RuntimeSettings, RuntimeSettingsUpdate},
+    contract::{
+        storages::{
+            quota::{self, PinSource},
+            snapshot,
+        },
+        ContractHandlerEvent, JournalEntry, DEFAULT_JOURNAL_ENTRIES,
+    },
+    message::{NodeEvent, Transaction},
+    operations::get::{self, ContractHead, MAX_PROBED_CONTRACTS},
+    ring::Ban,
+    tracing::{transaction_events, TraceEvent},
+    wasm_runtime::{
+        contract_stats, engine_metrics, render_prometheus, ContractStatsReport,
+        EngineMetricsReport, SecretsConsent,
+    },
+};
+
+#[derive(Clone)]
+struct AdminState {
+    op_manager: Arc<OpManager>,
+    config: Arc<Config>,
+}
+
+#[derive(Serialize)]
+struct SnapshotInfo {
+    path: PathBuf,
+}
+
+#[derive(Serialize)]
+struct RecordingsInfo {
+    active: Option<RecordingInfo>,
+    recordings: Vec<PathBuf>,
+}
+
+#[derive(Serialize)]
+struct PinnedContractInfo {
+    contract: String,
+    source: PinSource,
+}
+
+struct AdminError(StatusCode, String);
+
+impl IntoResponse for AdminError {
+    fn into_response(self) -> Response {
+        (self.0, self.1).into_response()
+    }
+}
+
+impl<E: std::fmt::Display> From<E> for AdminError {
+    fn from(err: E) -> Self {
+        AdminError(StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+    }
+}
+
+/// Hosts the admin API is reachable through, a rebound DNS name never matches them.
+const LOCAL_HOSTS: &[&str] = &["localhost", "127.0.0.1", "[::1]"];
+
+fn is_local_host(host: &str) -> bool {
+    // strip the port, the colons of an IPv6 literal are within its brackets
+    let name = match host.rsplit_once(':') {
+        Some((name, port)) if !port.ends_with(']') => {
+            if port.parse::<u16>().is_err() {
+                return false;
+            }
+            name
+        }
+        _ => host,
+    };
+    LOCAL_HOSTS
+        .iter()
+        .any(|local| local.eq_ignore_ascii_case(name))
+}
+
+fn is_authorized(headers: &HeaderMap, token: &AuthToken) -> bool {
+    let Some(provided) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    else {
+        return false;
+    };
+    // comparing the hashes is constant time
+    blake3::hash(provided.trim().as_bytes()) == blake3::hash(token.as_bytes())
+}
+
+async fn authorize(
+    State(token): State<AuthToken>,
+    req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
+    let host = req
+        .headers()
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok());
+    if !host.is_some_and(is_local_host) {
+        return (StatusCode::MISDIRECTED_REQUEST, "unexpected host").into_response();
+    }
+    if !is_authorized(req.headers(), &token) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+            "missing or invalid admin token",
+        )
+            .into_response();
+    }
+    next.run(req).await
+}
+
+/// Writes the admin token to a file only readable by the owner.
+async fn write_token(path: &std::path::Path, token: &AuthToken) -> std::io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path).await?;
+    // the file may have been created before with wider permissions
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))
+            .await?;
+    }
+    file.write_all(token.as_bytes()).await?;
+    file.flush().await
+}
+
+pub(super) fn serve_admin_api(port: u16, op_manager: Arc<OpManager>, config: Arc<Config>) {
+    let socket = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let token = AuthToken::generate();
+    let token_path = config.admin_token_path();
+    let router = Router::new()
+        .route("/v1/admin/snapshots", get(list_snapshots))
+        .route("/v1/admin/snapshot", post(create_snapshot))
+        .route("/v1/admin/contract/:key/journal", get(update_journal))
+        .route("/v1/admin/contract/:key/head", get(contract_head))
+        .route("/v1/admin/contracts/head", post(contracts_head))
+        .route("/v1/admin/contracts/pinned", get(pinned_contracts))
+        .route(
+            "/v1/admin/contract/:key/pin",
+            put(pin_contract).delete(unpin_contract),
+        )
+        .route("/v1/admin/metrics", get(prometheus_metrics))
+        .route("/v1/admin/metrics/wasm", get(wasm_metrics))
+        .route("/v1/admin/metrics/contracts", get(contract_metrics))
+        .route("/v1/admin/peers/bans", get(peer_bans))
+        .route("/v1/admin/peers/bans/:peer", delete(unban_peer))
+        .route("/v1/admin/transactions/:tx", delete(cancel_transaction))
+        .route("/v1/admin/transactions/:tx/trace", get(transaction_trace))
+        .route(
+            "/v1/admin/recordings",
+            get(list_recordings).post(record_session),
+        )
+        .route(
+            "/v1/admin/config/runtime",
+            get(runtime_settings).put(update_runtime_settings),
+        )
+        .route("/v1/admin/config/reload", post(reload_config))
+        .route("/v1/admin/delegates/consents", get(secrets_consents))
+        .route(
+            "/v1/admin/delegates/consents/:request_id",
+            put(allow_secrets_consent).delete(deny_secrets_consent),
+        )
+        .layer(Extension(AdminState { op_manager, config }))
+        .layer(axum::middleware::from_fn_with_state(
+            token.clone(),
+            authorize,
+        ));
+    tokio::spawn(async move {
+        if let Err(error) = write_token(&token_path, &token).await {
+            tracing::error!(%error, "Failed writing the admin token to {token_path:?}, admin API disabled");
+            return;
+        }
+        tracing::info!("Admin API listening on {}, token at {token_path:?}", socket);
+        let listener = match tokio::net::TcpListener::bind(socket).await {
+            Ok(listener) => listener,
+            Err(error) => {
+                tracing::error!(%error, "Failed to bind admin API at {socket}");
+                return;
+            }
+        };
+        if let Err(error) = axum::serve(listener, router).await {
+            tracing::error!("Error while running admin API server: {error}");
+        }
+    });
+}
+
+async fn create_snapshot(
+    Extension(state): Extension<AdminState>,
+) -> Result<Json<SnapshotInfo>, AdminError> {
+    let path = snapshot::request_snapshot(
+        &state.op_manager,
+        state.config.snapshots_dir(),
+        state.config.snapshots.keep,
+    )
+    .await?;
+    Ok(Json(SnapshotInfo { path }))
+}
+
+async fn list_snapshots(
+    Extension(state): Extension<AdminState>,
+) -> Result<Json<Vec<SnapshotInfo>>, AdminError> {
+    let snapshots = snapshot::list_snapshots(&state.config.snapshots_dir())?
+        .into_iter()
+        .map(|path| SnapshotInfo { path })
+        .collect();
+    Ok(Json(snapshots))
+}
+
+#[derive(Deserialize)]
+struct JournalParams {
+    last: Option<usize>,
+}
+
+async fn update_journal(
+    Path(key): Path<String>,
+    Query(params): Query<JournalParams>,
+    Extension(state): Extension<AdminState>,
+) -> Result<Json<Vec<JournalEntry>>, AdminError> {
+    let key = parse_key(key)?;
+    let last = params.last.unwrap_or(DEFAULT_JOURNAL_ENTRIES);
+    match state
+        .op_manager
+        .notify_contract_handler(ContractHandlerEvent::UpdateJournalQuery { key, last })
+        .await?
+    {
+        ContractHandlerEvent::UpdateJournalResponse { entries, .. } => Ok(Json(entries?)),
+        other => Err(AdminError(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("unexpected contract handler response: {other}"),
+        )),
+    }
+}
+
+fn parse_key(key: String) -> Result<ContractKey, AdminError> {
+    ContractKey::from_id(key).map_err(|err| AdminError(StatusCode::BAD_REQUEST, err.to_string()))
+}
+
+async fn contract_head(
+    Path(key): Path<String>,
+    Extension(state): Extension<AdminState>,
+) -> Result<Json<ContractHead>, AdminError> {
+    let key = parse_key(key)?;
+    Ok(Json(get::probe(&state.op_manager, key).await?))
+}
+
+/// Probes a batch of contracts concurrently.
+async fn contracts_head(
+    Extension(state): Extension<AdminState>,
+    Json(keys): Json<Vec<String>>,
+) -> Result<Json<Vec<ContractHead>>, AdminError> {
+    if keys.len() > MAX_PROBED_CONTRACTS {
+        return Err(AdminError(
+            StatusCode::BAD_REQUEST,
+            format!("at most {MAX_PROBED_CONTRACTS} contracts can be probed at once"),
+        ));
+    }
+    let keys = keys
+        .into_iter()
+        .map(parse_key)
+        .collect::<Result<Vec<_>, _>>()?;
+    let heads = futures::future::try_join_all(
+        keys.into_iter()
+            .map(|key| get::probe(&state.op_manager, key)),
+    )
+    .await?;
+    Ok(Json(heads))
+}
+
+async fn pinned_contracts(
+    Extension(state): Extension<AdminState>,
+) -> Result<Json<Vec<PinnedContractInfo>>, AdminError> {
+    let pinned = quota::pinned_contracts(&state.op_manager)
+        .await?
+        .into_iter()
+        .map(|pinned| PinnedContractInfo {
+            contract: pinned.id.to_string(),
+            source: pinned.source,
+        })
+        .collect();
+    Ok(Json(pinned))
+}
+
+/// Pins a contract, so it is never evicted and the node keeps it in sync with the network.
+async fn pin_contract(
+    Path(key): Path<String>,
+    Extension(state): Extension<AdminState>,
+) -> Result<StatusCode, AdminError> {
+    let key = parse_key(key)?;
+    if quota::request_pin(state.op_manager, *key.id(), true).await? {
+        Ok(StatusCode::CREATED)
+    } else {
+        Ok(StatusCode::OK)
+    }
+}
+
+async fn unpin_contract(
+    Path(key): Path<String>,
+    Extension(state): Extension<AdminState>,
+) -> Result<StatusCode, AdminError> {
+    let key = parse_key(key)?;
+    if quota::request_pin(state.op_manager, *key.id(), false).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(AdminError(
+            StatusCode::NOT_FOUND,
+            format!("contract {key} is not pinned at runtime"),
+        ))
+    }
+}
+
+/// Events of a transaction recorded by this peer.
+async fn transaction_trace(
+    Path(tx): Path<String>,
+    Extension(state): Extension<AdminState>,
+) -> Result<Json<Vec<TraceEvent>>, AdminError> {
+    let tx: Transaction = tx.parse().map_err(|err| {
+        AdminError(
+            StatusCode::BAD_REQUEST,
+            format!("invalid transaction id: {err}"),
+        )
+    })?;
+    Ok(Json(
+        transaction_events(tx, &state.config.event_log()).await?,
+    ))
+}
+
+/// Cancels an operation started by this node, the client waiting for it gets a cancelled result.
+async fn cancel_transaction(
+    Path(tx): Path<String>,
+    Extension(state): Extension<AdminState>,
+) -> Result<StatusCode, AdminError> {
+    let tx: Transaction = tx.parse().map_err(|err| {
+        AdminError(
+            StatusCode::BAD_REQUEST,
+            format!("invalid transaction id: {err}"),
+        )
+    })?;
+    state
+        .op_manager
+        .notify_node_event(NodeEvent::CancelTransaction(tx))
+        .await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn list_recordings(
+    Extension(state): Extension<AdminState>,
+) -> Result<Json<RecordingsInfo>, AdminError> {
+    Ok(Json(RecordingsInfo {
+        active: state.op_manager.recorder.active(),
+        recordings: replay::list_recordings(&state.config.recordings_dir())?,
+    }))
+}
+
+#[derive(Deserialize)]
+struct RecordingParams {
+    /// Seconds the recording lasts.
+    seconds: Option<u64>,
+}
+
+/// Records the messages received from the network for a while, so the session can be replayed
+/// with `freenet replay`.
+async fn record_session(
+    Query(params): Query<RecordingParams>,
+    Extension(state): Extension<AdminState>,
+) -> Result<(StatusCode, Json<RecordingInfo>), AdminError> {
+    let window = params
+        .seconds
+        .map_or(DEFAULT_RECORDING_WINDOW, Duration::from_secs);
+    match replay::start_recording(&state.op_manager, &state.config.recordings_dir(), window) {
+        Ok(info) => Ok((StatusCode::CREATED, Json(info))),
+        Err(err @ RecordingError::AlreadyRecording(_)) => {
+            Err(AdminError(StatusCode::CONFLICT, err.to_string()))
+        }
+        Err(err @ RecordingError::NotJoined) => {
+            Err(AdminError(StatusCode::SERVICE_UNAVAILABLE, err.to_string()))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn reload_error(err: ReloadError) -> AdminError {
+    let status = match &err {
+        ReloadError::Invalid(_) => StatusCode::BAD_REQUEST,
+        ReloadError::MissingConfig(_) => StatusCode::NOT_FOUND,
+        ReloadError::IO(_) | ReloadError::LogLevel(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    AdminError(status, err.to_string())
+}
+
+async fn runtime_settings(Extension(state): Extension<AdminState>) -> Json<RuntimeSettings> {
+    Json(state.op_manager.runtime.settings())
+}
+
+/// Changes some of the runtime settings, which are either all applied or all rejected.
+async fn update_runtime_settings(
+    Extension(state): Extension<AdminState>,
+    Json(update): Json<RuntimeSettingsUpdate>,
+) -> Result<Json<RuntimeSettings>, AdminError> {
+    let settings = state
+        .op_manager
+        .runtime
+        .apply(update)
+        .map_err(reload_error)?;
+    Ok(Json(settings))
+}
+
+/// Applies the runtime settings of the configuration file, same as sending a SIGHUP.
+async fn reload_config(
+    Extension(state): Extension<AdminState>,
+) -> Result<Json<RuntimeSettings>, AdminError> {
+    let settings = state
+        .op_manager
+        .runtime
+        .reload_from_file()
+        .map_err(reload_error)?;
+    Ok(Json(settings))
+}
+
+/// Prompts asking the user to let applications read their namespace of delegate secrets.
+async fn secrets_consents(
+    Extension(state): Extension<AdminState>,
+) -> Result<Json<Vec<SecretsConsent>>, AdminError> {
+    match state
+        .op_manager
+        .notify_contract_handler(ContractHandlerEvent::SecretsConsentsQuery)
+        .await?
+    {
+        ContractHandlerEvent::SecretsConsentsResponse { pending } => Ok(Json(pending)),
+        other => Err(AdminError(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("unexpected contract handler response: {other}"),
+        )),
+    }
+}
+
+async fn allow_secrets_consent(
+    Path(request_id): Path<u32>,
+    Extension(state): Extension<AdminState>,
+) -> Result<StatusCode, AdminError> {
+    resolve_secrets_consent(&state, request_id, true).await
+}
+
+async fn deny_secrets_consent(
+    Path(request_id): Path<u32>,
+    Extension(state): Extension<AdminState>,
+) -> Result<StatusCode, AdminError> {
+    resolve_secrets_consent(&state, request_id, false).await
+}
+
+async fn resolve_secrets_consent(
+    state: &AdminState,
+    request_id: u32,
+    allowed: bool,
+) -> Result<StatusCode, AdminError> {
+    match state
+        .op_manager
+        .notify_contract_handler(ContractHandlerEvent::ResolveSecretsConsentQuery {
+            request_id,
+            allowed,
+        })
+        .await?
+    {
+        ContractHandlerEvent::ResolveSecretsConsentResponse { resolved, .. } => {
+            if resolved? {
+                Ok(StatusCode::NO_CONTENT)
+            } else {
+                Err(AdminError(
+                    StatusCode::NOT_FOUND,
+                    format!("no pending consent request {request_id}"),
+                ))
+            }
+        }
+        other => Err(AdminError(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("unexpected contract handler response: {other}"),
+        )),
+    }
+}
+
+async fn wasm_metrics() -> Json<Vec<EngineMetricsReport>> {
+    Json(engine_metrics())
+}
+
+async fn contract_metrics() -> Json<Vec<ContractStatsReport>> {
+    Json(contract_stats())
+}
+
+/// Metrics in the Prometheus text exposition format, for scrapers.
+async fn prometheus_metrics() -> impl IntoResponse {
+    let mut metrics = String::new();
+    render_prometheus(&mut metrics);
+    (
+        [(
+            axum::http::header::CONTENT_TYPE,
+            "text/plain; version=0.0.4",
+        )],
+        metrics,
+    )
+}
+
+async fn peer_bans(Extension(state): Extension<AdminState>) -> Json<Vec<Ban>> {
+    Json(state.op_manager.ring.reputation.bans())
+}
+
+async fn unban_peer(
+    Path(peer): Path<String>,
+    Extension(state): Extension<AdminState>,
+) -> Result<StatusCode, AdminError> {
+    // bans apply to the whole host, accept the address of any of its peers too
+    let peer: IpAddr = peer
+        .parse()
+        .or_else(|_| peer.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .map_err(|err| {
+            AdminError(
+                StatusCode::BAD_REQUEST,
+                format!("invalid peer address: {err}"),
+            )
+        })?;
+    if state.op_manager.ring.reputation.unban(&peer) {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(AdminError(
+            StatusCode::NOT_FOUND,
+            format!("peer {peer} is not banned"),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_local_hosts() {
+        for host in [
+            "localhost",
+            "localhost:7510",
+            "127.0.0.1:7510",
+            "[::1]",
+            "[::1]:7510",
+        ] {
+            assert!(is_local_host(host), "{host}");
+        }
+        for host in [
+            "attacker.example",
+            "attacker.example:7510",
+            "localhost.attacker.example",
+            "127.0.0.1:evil",
+            "[::2]:7510",
+        ] {
+            assert!(!is_local_host(host), "{host}");
+        }
+    }
+
+    #[test]
+    fn requires_bearer_token() {
+        let token = AuthToken::generate();
+        let mut headers = HeaderMap::new();
+        assert!(!is_authorized(&headers, &token));
+        headers.insert(
+            header::AUTHORIZATION,
+            format!("Bearer {}", AuthToken::generate().as_str())
+                .parse()
+                .unwrap(),
+        );
+        assert!(!is_authorized(&headers, &token));
+        headers.insert(
+            header::AUTHORIZATION,
+            format!("Bearer {}", token.as_str()).parse().unwrap(),
+        );
+        assert!(is_authorized(&headers, &token));
+    }
+}
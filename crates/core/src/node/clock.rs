@@ -0,0 +1,138 @@
+//! Time and randomness of a node.
+//!
+//! Nodes run on the system clock, and draw transaction ids and routing choices from the thread
+//! RNG. A node replaying a recorded session (see [`super::replay`]) runs instead on the time the
+//! messages were recorded at, and draws from an RNG seeded from the recording, so replaying it
+//! always takes the same decisions.
+//!
+//! Transactions are timed out against the clock of their node. Ids and routing choices follow the
+//! clock of the task creating them, set with [`NodeClock::scope`]; tasks spawned from a replay run
+//! on the system clock.
+
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+
+use parking_lot::Mutex;
+use rand::{rngs::StdRng, Rng, RngCore, SeedableRng};
+use ulid::Ulid;
+
+tokio::task_local! {
+    static CLOCK: Arc<NodeClock>;
+}
+
+#[derive(Default)]
+pub(crate) struct NodeClock {
+    replay: Option<ReplayClock>,
+}
+
+struct ReplayClock {
+    /// Milliseconds since the unix epoch.
+    now_ms: AtomicU64,
+    rng: Mutex<StdRng>,
+}
+
+impl NodeClock {
+    /// Clock of a replay starting at `start`, drawing from an RNG seeded with `seed`.
+    pub fn replay(start: SystemTime, seed: u64) -> Self {
+        Self {
+            replay: Some(ReplayClock {
+                now_ms: AtomicU64::new(unix_ms(start)),
+                rng: Mutex::new(StdRng::seed_from_u64(seed)),
+            }),
+        }
+    }
+
+    pub fn now(&self) -> SystemTime {
+        match &self.replay {
+            Some(replay) => {
+                SystemTime::UNIX_EPOCH
+                    + Duration::from_millis(replay.now_ms.load(Ordering::Relaxed))
+            }
+            None => SystemTime::now(),
+        }
+    }
+
+    /// Moves the clock of a replay to `now`. The system clock is left as is.
+    pub fn set(&self, now: SystemTime) {
+        if let Some(replay) = &self.replay {
+            replay.now_ms.store(unix_ms(now), Ordering::Relaxed);
+        }
+    }
+
+    /// Runs `fut` on this clock.
+    pub fn scope<F: Future>(self: &Arc<Self>, fut: F) -> impl Future<Output = F::Output> {
+        CLOCK.scope(self.clone(), fut)
+    }
+}
+
+/// A new id, at the time of the clock of the current task.
+pub(crate) fn new_ulid() -> Ulid {
+    CLOCK
+        .try_with(|clock| {
+            let replay = clock.replay.as_ref()?;
+            Some(Ulid::from_parts(
+                replay.now_ms.load(Ordering::Relaxed),
+                replay.rng.lock().gen(),
+            ))
+        })
+        .ok()
+        .flatten()
+        .unwrap_or_else(Ulid::new)
+}
+
+/// Runs `f` with the RNG of the clock of the current task.
+pub(crate) fn with_rng<T>(f: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+    let mut f = Some(f);
+    let replayed = CLOCK.try_with(|clock| {
+        let replay = clock.replay.as_ref()?;
+        let f = f.take()?;
+        Some(f(&mut *replay.rng.lock()))
+    });
+    if let Ok(Some(result)) = replayed {
+        return result;
+    }
+    let f = f.expect("only taken by replay clocks");
+    f(&mut rand::thread_rng())
+}
+
+fn unix_ms(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn replays_are_deterministic() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let replay = || async {
+            let clock = Arc::new(NodeClock::replay(start, 7));
+            clock
+                .scope(async {
+                    let first = new_ulid();
+                    clock.set(start + Duration::from_secs(1));
+                    (first, new_ulid(), with_rng(|rng| rng.gen::<u64>()))
+                })
+                .await
+        };
+        let (first, second, choice) = replay().await;
+        assert_eq!(replay().await, (first, second, choice));
+        assert_eq!(first.timestamp_ms(), unix_ms(start));
+        assert_eq!(second.timestamp_ms(), unix_ms(start) + 1000);
+
+        // out of a replay, the system clock is used
+        let clock = NodeClock::default();
+        clock.set(start);
+        assert!(clock.now() > start);
+        assert!(new_ulid().timestamp_ms() > unix_ms(start));
+    }
+}
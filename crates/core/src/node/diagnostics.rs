@@ -0,0 +1,190 @@
+//! Health of the node as seen by client applications.
+//!
+//! The node periodically publishes its connectivity, the number of peers it is connected to and
+//! how long ago the state of each contract it is subscribed to was last confirmed by the network,
+//! so web apps can tell users the node is reconnecting or degraded instead of silently showing
+//! stale state.
+//!
+//! A subscription is confirmed whenever it is established, an update of the contract is received
+//! or the state is repaired after diverging from the neighbors. A contract which doesn't change
+//! is therefore reported as increasingly stale, which is what clients should expect from a
+//! subscription they can't tell is still alive.
+//!
+//! Client API servers get the diagnostics through a node query, see
+//! [`NodeQueryKind::Diagnostics`](crate::client_events::node_queries::NodeQueryKind).
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use freenet_stdlib::prelude::ContractKey;
+use serde::Serialize;
+use tokio::sync::watch;
+
+use super::OpManager;
+
+/// Below this number of connections (or the configured minimum, if lower) the node is degraded.
+const DEGRADED_BELOW_PEERS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Connectivity {
+    /// Not connected to any peer, the node is (re)joining the network.
+    Disconnected,
+    /// Connected to too few peers for requests to be reliably routed.
+    Degraded,
+    Connected,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct SubscriptionHealth {
+    pub contract: String,
+    /// Seconds since the state of the contract was last confirmed by the network.
+    pub staleness_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct NodeDiagnostics {
+    pub connectivity: Connectivity,
+    pub connected_peers: usize,
+    pub subscriptions: Vec<SubscriptionHealth>,
+}
+
+/// Diagnostics of a node and the bookkeeping they are computed from.
+pub(crate) struct NodeHealth {
+    reports: watch::Sender<Option<NodeDiagnostics>>,
+    /// Last time the state of each subscribed contract was confirmed by the network.
+    last_sync: DashMap<ContractKey, Instant>,
+}
+
+impl Default for NodeHealth {
+    fn default() -> Self {
+        Self {
+            reports: watch::channel(None).0,
+            last_sync: DashMap::default(),
+        }
+    }
+}
+
+impl NodeHealth {
+    /// Receiver of the diagnostics published by the node, `None` until the first report.
+    pub fn subscribe(&self) -> watch::Receiver<Option<NodeDiagnostics>> {
+        self.reports.subscribe()
+    }
+
+    /// Records that the network confirmed the current state of the contract.
+    pub fn record_sync(&self, key: ContractKey) {
+        self.last_sync.insert(key, Instant::now());
+    }
+
+    /// Publishes the diagnostics if they changed since the last report.
+    ///
+    /// Only the contracts in `subscribed` are reported and tracked from then on; those not yet
+    /// confirmed are considered in sync when first reported.
+    fn report(
+        &self,
+        connected_peers: usize,
+        min_connections: usize,
+        subscribed: Vec<ContractKey>,
+        now: Instant,
+    ) {
+        self.last_sync.retain(|key, _| subscribed.contains(key));
+        let mut subscriptions: Vec<_> = subscribed
+            .into_iter()
+            .map(|key| {
+                let last_sync = *self.last_sync.entry(key).or_insert(now);
+                SubscriptionHealth {
+                    contract: key.to_string(),
+                    staleness_secs: now.duration_since(last_sync).as_secs(),
+                }
+            })
+            .collect();
+        subscriptions.sort_unstable_by(|a, b| a.contract.cmp(&b.contract));
+        let diagnostics = NodeDiagnostics {
+            connectivity: connectivity(connected_peers, min_connections),
+            connected_peers,
+            subscriptions,
+        };
+        self.reports.send_if_modified(|current| {
+            if current.as_ref() == Some(&diagnostics) {
+                return false;
+            }
+            *current = Some(diagnostics);
+            true
+        });
+    }
+}
+
+fn connectivity(connected_peers: usize, min_connections: usize) -> Connectivity {
+    if connected_peers == 0 {
+        Connectivity::Disconnected
+    } else if connected_peers < DEGRADED_BELOW_PEERS.min(min_connections) {
+        Connectivity::Degraded
+    } else {
+        Connectivity::Connected
+    }
+}
+
+/// Publishes the diagnostics of the node every `interval`.
+pub(crate) async fn report_diagnostics(op_manager: Arc<OpManager>, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    loop {
+        interval.tick().await;
+        op_manager.health.report(
+            op_manager.ring.open_connections(),
+            op_manager.ring.connection_manager.min_connections(),
+            op_manager.ring.subscribed_contracts(),
+            Instant::now(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use freenet_stdlib::prelude::ContractInstanceId;
+
+    use super::*;
+
+    #[test]
+    fn connectivity_levels() {
+        assert_eq!(connectivity(0, 25), Connectivity::Disconnected);
+        assert_eq!(connectivity(2, 25), Connectivity::Degraded);
+        assert_eq!(connectivity(3, 25), Connectivity::Connected);
+        assert_eq!(connectivity(1, 1), Connectivity::Connected);
+    }
+
+    #[test]
+    fn staleness_of_subscriptions() {
+        let health = NodeHealth::default();
+        let reports = health.subscribe();
+        let (synced, stale) = (
+            ContractKey::from(ContractInstanceId::new([1; 32])),
+            ContractKey::from(ContractInstanceId::new([2; 32])),
+        );
+        let start = Instant::now();
+        health.report(5, 25, vec![stale], start);
+        health.record_sync(synced);
+        health.report(5, 25, vec![synced, stale], start + Duration::from_secs(60));
+
+        let report = reports.borrow().clone().unwrap();
+        let staleness = |key: ContractKey| {
+            report
+                .subscriptions
+                .iter()
+                .find(|health| health.contract == key.to_string())
+                .map(|health| health.staleness_secs)
+        };
+        assert_eq!(staleness(stale), Some(60));
+        assert!(staleness(synced).unwrap() <= 60);
+
+        // contracts no longer subscribed to are forgotten
+        health.report(5, 25, vec![], start + Duration::from_secs(120));
+        assert!(health.last_sync.is_empty());
+        assert!(reports.borrow().as_ref().unwrap().subscriptions.is_empty());
+    }
+}
@@ -0,0 +1,420 @@
+//! Broadcast channel for ephemeral application messages.
+//!
+//! Some application messages, such as presence or typing indicators, are only relevant for a
+//! few seconds and don't belong in contract state. Clients publish them to a topic, addressed by
+//! the hash of its name, and they are flooded through the peers which joined the channel: each
+//! relays the messages it didn't see yet to a few of the joined peers it is connected to, until
+//! their hop budget is spent. Delivery is best effort, messages are neither stored nor
+//! acknowledged.
+//!
+//! Joining the channel is opt-in. Nodes which joined it announce it to each peer they connect to
+//! with [`NetMessageV1::GossipJoined`](crate::message::NetMessageV1::GossipJoined), and messages
+//! are only relayed to and accepted from peers which did, so nodes which didn't join, or don't
+//! know of the channel, never see them. Messages over the configured size are dropped, and so
+//! are the ones over the rate allowed for their topic, whether published by a local client or
+//! relayed by a peer, or over the rate allowed for the peer relaying them, so neither a topic nor
+//! a peer can be used to flood the network. The topics tracked at once are capped too.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::Display,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc};
+use ulid::Ulid;
+
+use crate::config::GossipConfig;
+
+/// Hops a message is relayed through at most.
+const MAX_HOPS: u8 = 6;
+/// Joined peers each message is relayed to.
+pub(crate) const FANOUT: usize = 6;
+/// Messages seen recently, whose duplicates are dropped.
+const MAX_SEEN: usize = 8192;
+/// Topics tracked at most, the inactive ones being forgotten first.
+const MAX_TOPICS: usize = 1024;
+/// Time after which topics without local subscribers are forgotten.
+const TOPIC_IDLE: Duration = Duration::from_secs(60);
+/// Messages buffered for each local subscriber.
+const SUBSCRIBER_CAPACITY: usize = 64;
+/// Joined peers tracked at most.
+const MAX_MEMBERS: usize = 1024;
+/// Messages per second accepted from each joined peer, over all topics.
+const PEER_RATE: u32 = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub(crate) struct TopicKey([u8; 32]);
+
+impl TopicKey {
+    pub fn from_name(name: &str) -> Self {
+        Self(*blake3::hash(name.as_bytes()).as_bytes())
+    }
+}
+
+impl Display for TopicKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", bs58::encode(self.0).into_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct GossipMsg {
+    pub id: Ulid,
+    pub topic: TopicKey,
+    pub payload: Vec<u8>,
+    /// Hops the message can still be relayed through.
+    pub hops_left: u8,
+}
+
+impl Display for GossipMsg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Gossip {{ id: {}, topic: {}, hops_left: {} }}",
+            self.id, self.topic, self.hops_left
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub(crate) enum GossipError {
+    #[error("the node didn't join the gossip channel")]
+    Disabled,
+    #[error("message of {size} bytes, over the maximum of {max}")]
+    TooLarge { size: usize, max: usize },
+    #[error("topic over its limit of {0} messages per second")]
+    RateLimited(u32),
+    #[error("too many active topics")]
+    TooManyTopics,
+}
+
+/// Messages which can be admitted right away, refilled at a given rate.
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    fn new(rate: u32) -> Self {
+        Self {
+            tokens: f64::from(rate),
+            refilled: Instant::now(),
+        }
+    }
+
+    fn take(&mut self, rate: u32) -> bool {
+        let now = Instant::now();
+        let refill = now.duration_since(self.refilled).as_secs_f64() * f64::from(rate);
+        self.tokens = (self.tokens + refill).min(f64::from(rate));
+        self.refilled = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+struct Topic {
+    subscribers: broadcast::Sender<Arc<GossipMsg>>,
+    rate: Bucket,
+}
+
+#[derive(Default)]
+struct Channel {
+    config: GossipConfig,
+    topics: HashMap<TopicKey, Topic>,
+    seen: HashSet<Ulid>,
+    seen_order: VecDeque<Ulid>,
+    /// Connected peers which joined the channel, and the rate of the messages accepted from them.
+    members: HashMap<SocketAddr, Bucket>,
+    /// Messages published by local clients, pending to be sent to peers.
+    outbound: Option<mpsc::UnboundedSender<GossipMsg>>,
+}
+
+impl Channel {
+    fn topic(&mut self, key: TopicKey) -> Result<&mut Topic, GossipError> {
+        if self.topics.len() >= MAX_TOPICS && !self.topics.contains_key(&key) {
+            self.topics.retain(|_, topic| {
+                topic.subscribers.receiver_count() > 0 || topic.rate.refilled.elapsed() < TOPIC_IDLE
+            });
+            if self.topics.len() >= MAX_TOPICS {
+                return Err(GossipError::TooManyTopics);
+            }
+        }
+        let rate = self.config.topic_rate;
+        Ok(self.topics.entry(key).or_insert_with(|| Topic {
+            subscribers: broadcast::channel(SUBSCRIBER_CAPACITY).0,
+            rate: Bucket::new(rate),
+        }))
+    }
+
+    fn admit(&mut self, topic: TopicKey, size: usize) -> Result<(), GossipError> {
+        if !self.config.enabled {
+            return Err(GossipError::Disabled);
+        }
+        if size > self.config.max_message_size {
+            return Err(GossipError::TooLarge {
+                size,
+                max: self.config.max_message_size,
+            });
+        }
+        let rate = self.config.topic_rate;
+        if !self.topic(topic)?.rate.take(rate) {
+            return Err(GossipError::RateLimited(rate));
+        }
+        Ok(())
+    }
+
+    /// Whether the message wasn't seen before.
+    fn first_seen(&mut self, id: Ulid) -> bool {
+        if !self.seen.insert(id) {
+            return false;
+        }
+        self.seen_order.push_back(id);
+        if self.seen_order.len() > MAX_SEEN {
+            if let Some(oldest) = self.seen_order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    fn deliver(&self, msg: &GossipMsg) {
+        if let Some(topic) = self.topics.get(&msg.topic) {
+            let _ = topic.subscribers.send(Arc::new(msg.clone()));
+        }
+    }
+
+    fn publish(&mut self, topic: TopicKey, payload: Vec<u8>) -> Result<Ulid, GossipError> {
+        self.admit(topic, payload.len())?;
+        let msg = GossipMsg {
+            id: Ulid::new(),
+            topic,
+            payload,
+            hops_left: MAX_HOPS,
+        };
+        self.first_seen(msg.id);
+        self.deliver(&msg);
+        let id = msg.id;
+        if let Some(outbound) = &self.outbound {
+            let _ = outbound.send(msg);
+        }
+        Ok(id)
+    }
+
+    fn receive(&mut self, mut msg: GossipMsg, from: SocketAddr) -> Option<GossipMsg> {
+        if !self.config.enabled {
+            return None;
+        }
+        let Some(peer) = self.members.get_mut(&from) else {
+            tracing::debug!(%from, "Dropped gossip message from a peer which didn't join");
+            return None;
+        };
+        if !peer.take(PEER_RATE) {
+            tracing::debug!(%from, "Dropped gossip message over the rate of the peer");
+            return None;
+        }
+        if !self.first_seen(msg.id) {
+            return None;
+        }
+        if let Err(error) = self.admit(msg.topic, msg.payload.len()) {
+            tracing::debug!(topic = %msg.topic, %error, "Dropped gossip message");
+            return None;
+        }
+        self.deliver(&msg);
+        msg.hops_left = msg.hops_left.min(MAX_HOPS).checked_sub(1)?;
+        Some(msg)
+    }
+
+    fn joined(&mut self, peer: SocketAddr) {
+        if !self.config.enabled
+            || (self.members.len() >= MAX_MEMBERS && !self.members.contains_key(&peer))
+        {
+            return;
+        }
+        self.members
+            .entry(peer)
+            .or_insert_with(|| Bucket::new(PEER_RATE));
+    }
+}
+
+/// Gossip channel of a node, shared by its event loop, relaying messages to and from peers, and
+/// its clients.
+pub(crate) struct GossipChannel {
+    channel: Mutex<Channel>,
+    /// Messages published by local clients, taken by the event loop of the node if it joined.
+    outbound: Mutex<Option<mpsc::UnboundedReceiver<GossipMsg>>>,
+}
+
+impl std::fmt::Debug for GossipChannel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GossipChannel")
+            .field("enabled", &self.enabled())
+            .finish_non_exhaustive()
+    }
+}
+
+impl GossipChannel {
+    pub fn new(config: GossipConfig) -> Self {
+        let mut channel = Channel {
+            config,
+            ..Default::default()
+        };
+        let outbound = config.enabled.then(|| {
+            let (outbound, outbound_rx) = mpsc::unbounded_channel();
+            channel.outbound = Some(outbound);
+            outbound_rx
+        });
+        Self {
+            channel: Mutex::new(channel),
+            outbound: Mutex::new(outbound),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.channel.lock().config.enabled
+    }
+
+    /// The messages published by local clients, to be sent to peers, if the node joined the
+    /// channel and they weren't taken yet.
+    pub fn take_outbound(&self) -> Option<mpsc::UnboundedReceiver<GossipMsg>> {
+        self.outbound.lock().take()
+    }
+
+    /// Receiver of the messages published to the topic.
+    pub fn subscribe(
+        &self,
+        topic: TopicKey,
+    ) -> Result<broadcast::Receiver<Arc<GossipMsg>>, GossipError> {
+        let mut channel = self.channel.lock();
+        if !channel.config.enabled {
+            return Err(GossipError::Disabled);
+        }
+        Ok(channel.topic(topic)?.subscribers.subscribe())
+    }
+
+    /// Publishes a message from a local client, delivered to the local subscribers of the topic
+    /// as well, returning its id.
+    pub fn publish(&self, topic: TopicKey, payload: Vec<u8>) -> Result<Ulid, GossipError> {
+        self.channel.lock().publish(topic, payload)
+    }
+
+    /// Delivers a message received from a peer to the local subscribers of its topic, returning
+    /// it if it has to be relayed further.
+    pub fn receive(&self, msg: GossipMsg, from: SocketAddr) -> Option<GossipMsg> {
+        self.channel.lock().receive(msg, from)
+    }
+
+    /// The peer announced it joined the channel.
+    pub fn joined(&self, peer: SocketAddr) {
+        self.channel.lock().joined(peer);
+    }
+
+    /// The connection to the peer was closed.
+    pub fn left(&self, peer: &SocketAddr) {
+        self.channel.lock().members.remove(peer);
+    }
+
+    pub fn is_member(&self, peer: &SocketAddr) -> bool {
+        self.channel.lock().members.contains_key(peer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn joined_channel() -> Channel {
+        Channel {
+            config: GossipConfig {
+                enabled: true,
+                max_message_size: 8,
+                topic_rate: 2,
+            },
+            ..Default::default()
+        }
+    }
+
+    fn message(topic: &str, hops_left: u8) -> GossipMsg {
+        GossipMsg {
+            id: Ulid::new(),
+            topic: TopicKey::from_name(topic),
+            payload: b"away".to_vec(),
+            hops_left,
+        }
+    }
+
+    #[test]
+    fn relay_within_limits() {
+        let topic = TopicKey::from_name("presence");
+        assert_eq!(
+            Channel::default().publish(topic, vec![]),
+            Err(GossipError::Disabled)
+        );
+
+        let mut channel = joined_channel();
+        let peer: SocketAddr = ([10, 0, 0, 1], 31337).into();
+        let mut subscriber = channel.topic(topic).unwrap().subscribers.subscribe();
+        assert_eq!(
+            channel.publish(topic, vec![0; 9]),
+            Err(GossipError::TooLarge { size: 9, max: 8 })
+        );
+        let id = channel.publish(topic, b"here".to_vec()).unwrap();
+        assert_eq!(subscriber.try_recv().unwrap().id, id);
+
+        let relayed = message("presence", u8::MAX);
+        // peers which didn't join are ignored
+        assert_eq!(channel.receive(relayed.clone(), peer), None);
+        channel.joined(peer);
+        // the hops of relayed messages are capped
+        assert_eq!(
+            channel.receive(relayed.clone(), peer).unwrap().hops_left,
+            MAX_HOPS - 1
+        );
+        assert_eq!(subscriber.try_recv().unwrap().payload, b"away");
+        // duplicates are dropped
+        assert_eq!(channel.receive(relayed, peer), None);
+
+        // the topic is over its rate
+        assert_eq!(
+            channel.publish(topic, vec![]),
+            Err(GossipError::RateLimited(2))
+        );
+        assert_eq!(channel.receive(message("typing", 0), peer), None);
+    }
+
+    #[test]
+    fn cap_topics_and_peers() {
+        let mut channel = joined_channel();
+        for i in 0..MAX_TOPICS {
+            channel
+                .publish(TopicKey::from_name(&i.to_string()), vec![])
+                .unwrap();
+        }
+        // all the topics are active
+        assert_eq!(
+            channel.publish(TopicKey::from_name("one more"), vec![]),
+            Err(GossipError::TooManyTopics)
+        );
+
+        let mut channel = joined_channel();
+        let peer: SocketAddr = ([10, 0, 0, 1], 31337).into();
+        channel.joined(peer);
+        let relayed = (0..=PEER_RATE)
+            .filter(|i| {
+                channel
+                    .receive(message(&i.to_string(), MAX_HOPS), peer)
+                    .is_some()
+            })
+            .count();
+        assert_eq!(relayed, PEER_RATE as usize);
+        channel.members.remove(&peer);
+        assert_eq!(channel.receive(message("presence", MAX_HOPS), peer), None);
+    }
+}
@@ -0,0 +1,236 @@
+//! Shedding of the expensive client requests while the node is overloaded.
+//!
+//! The pressure of the node is the number of events queued or being processed by the contract
+//! handler, which runs the contracts and does the state IO, and the memory still available on the
+//! system. Once either crosses its threshold, requests which would execute contracts are rejected
+//! right away, telling clients when to retry, instead of queuing up until everything times out.
+//!
+//! The client API servers are started before the node, so each of them owns the
+//! [`LoadShedder`] of its requests, and the node takes the [`QueuedEvents`] it counts from them,
+//! see [`ClientEventsProxy::queued_events`](crate::client_events::ClientEventsProxy).
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use freenet_stdlib::client_api::{ClientRequest, ContractRequest};
+use parking_lot::Mutex;
+
+use crate::config::LoadSheddingConfig;
+
+/// Time the available memory is cached for.
+const MEMORY_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Pressure {
+    QueuedEvents { queued: usize, max: usize },
+    Memory { available: u64, min: u64 },
+}
+
+impl std::fmt::Display for Pressure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Pressure::QueuedEvents { queued, max } => {
+                write!(
+                    f,
+                    "{queued} contract events queued, over the maximum of {max}"
+                )
+            }
+            Pressure::Memory { available, min } => write!(
+                f,
+                "{available} bytes of memory available, under the minimum of {min}"
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("node overloaded ({pressure}), retry in {} seconds", retry_after.as_secs())]
+pub(crate) struct Overloaded {
+    pub pressure: Pressure,
+    pub retry_after: Duration,
+}
+
+/// Events queued or being processed by the contract handler.
+#[derive(Debug, Clone, Default)]
+pub struct QueuedEvents(Arc<AtomicUsize>);
+
+impl QueuedEvents {
+    /// Tracks an event sent to the contract handler until it is processed.
+    pub fn enter(&self) -> QueuedEvent {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        QueuedEvent(self.0.clone())
+    }
+
+    fn count(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+pub(crate) struct QueuedEvent(Arc<AtomicUsize>);
+
+impl Drop for QueuedEvent {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Rejects the expensive requests of a client API server while the node is overloaded.
+#[derive(Clone, Default)]
+pub(crate) struct LoadShedder {
+    policy: LoadSheddingConfig,
+    queued: QueuedEvents,
+    /// Memory available when last read, and when it was.
+    available_memory: Arc<Mutex<Option<(Instant, Option<u64>)>>>,
+}
+
+impl LoadShedder {
+    pub fn new(policy: LoadSheddingConfig) -> Self {
+        Self {
+            policy,
+            ..Default::default()
+        }
+    }
+
+    /// The events the node counts for this server to shed load on.
+    pub fn queued_events(&self) -> QueuedEvents {
+        self.queued.clone()
+    }
+
+    /// Checks the node can take more expensive requests.
+    pub fn check(&self) -> Result<(), Overloaded> {
+        let available_memory = self.policy.min_free_memory.and_then(|_| {
+            let mut cached = self.available_memory.lock();
+            match *cached {
+                Some((read_at, available)) if read_at.elapsed() < MEMORY_REFRESH_INTERVAL => {
+                    available
+                }
+                _ => {
+                    let available = available_memory();
+                    *cached = Some((Instant::now(), available));
+                    available
+                }
+            }
+        });
+        match pressure(&self.policy, self.queued.count(), available_memory) {
+            Some(pressure) => Err(Overloaded {
+                pressure,
+                retry_after: Duration::from_secs(self.policy.retry_after),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+fn pressure(
+    policy: &LoadSheddingConfig,
+    queued: usize,
+    available_memory: Option<u64>,
+) -> Option<Pressure> {
+    if queued >= policy.max_queued_events {
+        return Some(Pressure::QueuedEvents {
+            queued,
+            max: policy.max_queued_events,
+        });
+    }
+    match (available_memory, policy.min_free_memory) {
+        (Some(available), Some(min)) if available < min => {
+            Some(Pressure::Memory { available, min })
+        }
+        _ => None,
+    }
+}
+
+/// Whether serving the request executes contracts or reads their state, as opposed to requests
+/// only changing the session of the client, such as subscriptions.
+pub(crate) fn is_expensive(request: &ClientRequest<'_>) -> bool {
+    match request {
+        ClientRequest::ContractOp(request) => !matches!(request, ContractRequest::Subscribe { .. }),
+        ClientRequest::DelegateOp(_) => true,
+        _ => false,
+    }
+}
+
+/// Memory available to new allocations without swapping, if it can be told on this platform.
+fn available_memory() -> Option<u64> {
+    #[cfg(target_os = "linux")]
+    {
+        let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+        meminfo.lines().find_map(|line| {
+            let kib = line
+                .strip_prefix("MemAvailable:")?
+                .trim()
+                .strip_suffix("kB")?;
+            kib.trim().parse::<u64>().ok().map(|kib| kib * 1024)
+        })
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use freenet_stdlib::prelude::{ContractInstanceId, ContractKey};
+
+    use super::*;
+
+    #[test]
+    fn shed_over_thresholds() {
+        let policy = LoadSheddingConfig {
+            max_queued_events: 2,
+            min_free_memory: Some(1024),
+            retry_after: 5,
+        };
+        assert_eq!(pressure(&policy, 1, Some(4096)), None);
+        assert_eq!(
+            pressure(&policy, 2, Some(4096)),
+            Some(Pressure::QueuedEvents { queued: 2, max: 2 })
+        );
+        assert_eq!(
+            pressure(&policy, 0, Some(512)),
+            Some(Pressure::Memory {
+                available: 512,
+                min: 1024
+            })
+        );
+        // the memory threshold is ignored where the available memory can't be told
+        assert_eq!(pressure(&policy, 0, None), None);
+
+        let client_op = |request| is_expensive(&ClientRequest::ContractOp(request));
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        assert!(client_op(ContractRequest::Get {
+            key,
+            return_contract_code: false,
+        }));
+        assert!(!client_op(ContractRequest::Subscribe {
+            key,
+            summary: None
+        }));
+        assert!(!is_expensive(&ClientRequest::Disconnect { cause: None }));
+    }
+
+    #[test]
+    fn shed_on_queued_events() {
+        let shedder = LoadShedder::new(LoadSheddingConfig {
+            max_queued_events: 1,
+            min_free_memory: None,
+            retry_after: 5,
+        });
+        let queued = shedder.queued_events();
+        assert!(shedder.check().is_ok());
+        let event = queued.enter();
+        let error = shedder.check().unwrap_err();
+        assert_eq!(error.retry_after, Duration::from_secs(5));
+        drop(event);
+        assert!(shedder.check().is_ok());
+        // other servers count their own events
+        let _other = LoadShedder::default().queued_events().enter();
+        assert!(shedder.check().is_ok());
+    }
+}
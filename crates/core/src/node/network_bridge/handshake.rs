@@ -12,6 +12,7 @@ use futures::{future::BoxFuture, stream::FuturesUnordered, FutureExt, StreamExt,
 use tokio::sync::mpsc::{self};
 
 use crate::{
+    config::NodeRole,
     dev_tool::{Location, PeerId, Transaction},
     message::{InnerMessage, NetMessage, NetMessageV1},
     node::NetworkBridge,
@@ -60,6 +61,7 @@ pub(super) enum Event {
         id: Transaction,
         conn: PeerConnection,
         joiner: PeerId,
+        joiner_role: NodeRole,
         location: Location,
         op: Option<Box<ConnectOp>>,
         forward_info: Option<Box<ForwardInfo>>,
@@ -378,6 +380,8 @@ impl HandshakeHandler {
                                     skip_connections,
                                     skip_forwards,
                                     joiner,
+                                    joiner_addresses,
+                                    joiner_role,
                                     ..
                                 } = req;
 
@@ -407,6 +411,8 @@ impl HandshakeHandler {
                                         skip_forwards,
                                         req_peer: my_peer_id.clone(),
                                         joiner: joiner_pk_loc.clone(),
+                                        joiner_addresses,
+                                        joiner_role,
                                     };
 
                                     let f = forward_conn(
@@ -442,8 +448,9 @@ impl HandshakeHandler {
                                     id,
                                     conn,
                                     joiner,
+                                    joiner_role,
                                     location,
-                                    op: ok.map(|ok_value| Box::new(ConnectOp::new(id, Some(ok_value), None, None))),
+                                    op: ok.map(|ok_value| Box::new(ConnectOp::new(id, Some(ok_value), None))),
                                     forward_info: forward_info.map(Box::new),
                                 })
 
@@ -456,6 +463,8 @@ impl HandshakeHandler {
                                     skip_connections,
                                     skip_forwards,
                                     joiner,
+                                    joiner_addresses,
+                                    joiner_role,
                                     ..
                                 } = req;
                                 let remote = conn.remote_addr();
@@ -463,6 +472,8 @@ impl HandshakeHandler {
                                 let mut tx = TransientConnection {
                                     tx: id,
                                     joiner: joiner.clone(),
+                                    joiner_addresses,
+                                    joiner_role,
                                     max_hops_to_live,
                                     hops_to_live,
                                     skip_connections,
@@ -568,6 +579,8 @@ impl HandshakeHandler {
             skip_forwards: transaction.skip_forwards.clone(),
             req_peer: my_peer_id.clone(),
             joiner: joiner_pk_loc.clone(),
+            joiner_addresses: transaction.joiner_addresses.clone(),
+            joiner_role: transaction.joiner_role,
         };
 
         match forward_conn(
@@ -733,7 +746,12 @@ impl HandshakeHandler {
         tracing::debug!(at=?conn.my_address(), %this_peer.addr, from=%conn.remote_addr(), remote_addr = %gw_peer_id, "Waiting for confirmation from gw");
         self.ongoing_outbound_connections.push(
             wait_for_gw_confirmation(
-                (this_peer, self.this_location),
+                (
+                    this_peer,
+                    self.this_location,
+                    self.connection_manager.advertised_addrs(),
+                    self.connection_manager.role(),
+                ),
                 AcceptedTracker {
                     gw_peer: gw_peer_id.into(),
                     gw_conn: conn,
@@ -787,6 +805,8 @@ struct InboundGwJoinRequest {
     pub id: Transaction,
     pub joiner: PeerId,
     pub location: Option<Location>,
+    pub joiner_addresses: Vec<SocketAddr>,
+    pub joiner_role: NodeRole,
     pub hops_to_live: usize,
     pub max_hops_to_live: usize,
     pub skip_connections: HashSet<PeerId>,
@@ -829,7 +849,12 @@ struct AcceptedTracker {
 
 /// Waits for confirmation from a gateway after initiating a connection.
 async fn wait_for_gw_confirmation(
-    (this_peer, this_location): (PeerId, Option<Location>),
+    (this_peer, this_location, this_addresses, this_role): (
+        PeerId,
+        Option<Location>,
+        Vec<SocketAddr>,
+        NodeRole,
+    ),
     mut tracker: AcceptedTracker,
 ) -> OutboundConnResult {
     let gw_peer_id = tracker.gw_peer.peer.clone();
@@ -840,6 +865,8 @@ async fn wait_for_gw_confirmation(
             joiner: Some(this_peer.clone()),
             joiner_key: this_peer.pub_key.clone(),
             joiner_location: this_location,
+            joiner_addresses: this_addresses,
+            joiner_role: this_role,
             hops_to_live: tracker.total_checks,
             max_hops_to_live: tracker.total_checks,
             skip_connections: HashSet::from([this_peer.clone()]),
@@ -994,7 +1021,9 @@ async fn gw_peer_connection_listener(
                             max_hops_to_live,
                             skip_connections,
                             skip_forwards,
-                            joiner_location
+                            joiner_location,
+                            joiner_addresses,
+                            joiner_role,
                         },
                         ..
                     })) => {
@@ -1008,6 +1037,8 @@ async fn gw_peer_connection_listener(
                                 id,
                                 joiner,
                                 location: joiner_location,
+                                joiner_addresses,
+                                joiner_role,
                                 hops_to_live,
                                 max_hops_to_live,
                                 skip_connections,
@@ -1122,6 +1153,8 @@ async fn gw_transient_peer_conn(
 struct TransientConnection {
     tx: Transaction,
     joiner: PeerId,
+    joiner_addresses: Vec<SocketAddr>,
+    joiner_role: NodeRole,
     max_hops_to_live: usize,
     hops_to_live: usize,
     skip_connections: HashSet<PeerId>,
@@ -1245,6 +1278,8 @@ mod tests {
                     joiner: None,
                     joiner_key: pub_key,
                     joiner_location: None,
+                    joiner_addresses: vec![],
+                    joiner_role: NodeRole::Peer,
                     hops_to_live,
                     max_hops_to_live: hops_to_live,
                     skip_connections: HashSet::new(),
@@ -1477,8 +1512,7 @@ mod tests {
         let (mut handler, mut test) = config_handler(addr, Some(vec![existing_conn]));
 
         // Configure the handler to reject connections by setting max_connections to 1
-        handler.connection_manager.max_connections = 1;
-        handler.connection_manager.min_connections = 1;
+        handler.connection_manager.set_connection_limits(1, 1);
 
         let remote_addr = ([127, 0, 0, 1], 10002).into();
 
@@ -1635,8 +1669,7 @@ mod tests {
         let (mut gw_handler, mut gw_test) = config_handler(gw_addr, None);
 
         // the gw only will accept one connection
-        gw_handler.connection_manager.max_connections = 1;
-        gw_handler.connection_manager.min_connections = 1;
+        gw_handler.connection_manager.set_connection_limits(1, 1);
 
         let peer_key = TransportKeypair::new();
         let joiner_key = TransportKeypair::new();
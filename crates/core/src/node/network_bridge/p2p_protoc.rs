@@ -2,6 +2,7 @@ use super::{ConnectionError, EventLoopNotificationsReceiver, NetworkBridge};
 use crate::contract::WaitingTransaction;
 use crate::message::{NetMessageV1, QueryResult};
 use crate::node::subscribe::SubscribeMsg;
+use crate::operations::state_transfer::{StateTransfers, TransferEvent};
 use crate::ring::Location;
 use dashmap::DashSet;
 use either::{Either, Left, Right};
@@ -9,9 +10,10 @@ use freenet_stdlib::client_api::ErrorKind;
 use futures::future::BoxFuture;
 use futures::stream::FuturesUnordered;
 use futures::{FutureExt, StreamExt};
+use rand::seq::IteratorRandom;
 use std::convert::Infallible;
 use std::future::Future;
-use std::net::{IpAddr, SocketAddr};
+use std::net::SocketAddr;
 use std::pin::Pin;
 use std::time::Duration;
 use std::{
@@ -31,18 +33,22 @@ use crate::node::network_bridge::handshake::{
 };
 use crate::node::PeerId;
 use crate::transport::{
-    create_connection_handler, PeerConnection, TransportError, TransportKeypair,
+    create_connection_handler, BandwidthLimit, PeerConnection, TransportError, TransportKeypair,
 };
 use crate::{
     client_events::ClientId,
-    config::GlobalExecutor,
+    config::{CompressionConfig, GlobalExecutor, OperationKind},
     contract::{
         ClientResponsesSender, ContractHandlerChannel, ExecutorToEventLoopChannel,
         NetworkEventListenerHalve, WaitingResolution,
     },
     message::{MessageStats, NetMessage, NodeEvent, Transaction},
-    node::{handle_aborted_op, process_message, NetEventRegister, NodeConfig, OpManager},
-    ring::PeerKeyLocation,
+    node::{
+        cancelled_error, disconnected_client_transactions,
+        gossip::{self, GossipMsg},
+        handle_aborted_op, process_message, NetEventRegister, NodeConfig, OpManager,
+    },
+    ring::{Misbehavior, PeerKeyLocation, PeerReputation},
     tracing::NetEventLog,
 };
 
@@ -113,8 +119,7 @@ pub(in crate::node) struct P2pConnManager {
     event_listener: Box<dyn NetEventRegister>,
     connections: HashMap<PeerId, PeerConnChannelSender>,
     key_pair: TransportKeypair,
-    listening_ip: IpAddr,
-    listening_port: u16,
+    listening_addrs: Vec<SocketAddr>,
     is_gateway: bool,
     /// If set, will sent the location over network messages.
     ///
@@ -124,7 +129,14 @@ pub(in crate::node) struct P2pConnManager {
     /// and locations should be derived from IP addresses.
     this_location: Option<Location>,
     check_version: bool,
-    bandwidth_limit: Option<usize>,
+    bandwidth_limit: BandwidthLimit,
+    /// Whether browser peers are accepted over WebRTC.
+    webrtc: bool,
+    compression: CompressionConfig,
+    /// Operations whose messages are sent uncompressed.
+    uncompressed_operations: Arc<[OperationKind]>,
+    /// Gossip messages published by local clients, if the node joined the gossip channel.
+    gossip_outbound: Option<mpsc::UnboundedReceiver<GossipMsg>>,
 }
 
 impl P2pConnManager {
@@ -133,10 +145,11 @@ impl P2pConnManager {
         op_manager: Arc<OpManager>,
         event_listener: impl NetEventRegister + Clone,
     ) -> anyhow::Result<Self> {
-        let listen_port = config.network_listener_port;
-        let listener_ip = config.network_listener_ip;
+        let listening_addrs = config.listener_addrs();
 
         let (tx_bridge_cmd, rx_bridge_cmd) = mpsc::channel(100);
+        let bandwidth_limit = op_manager.runtime.bandwidth_limit();
+        let gossip_outbound = op_manager.gossip.take_outbound();
         let bridge = P2pBridge::new(tx_bridge_cmd, op_manager, event_listener.clone());
 
         let gateways = config.get_gateways()?;
@@ -148,12 +161,21 @@ impl P2pConnManager {
             event_listener: Box::new(event_listener),
             connections: HashMap::new(),
             key_pair,
-            listening_ip: listener_ip,
-            listening_port: listen_port,
+            listening_addrs,
             is_gateway: config.is_gateway,
             this_location: config.location,
             check_version: !config.config.network_api.ignore_protocol_version,
-            bandwidth_limit: config.config.network_api.bandwidth_limit,
+            bandwidth_limit,
+            webrtc: config.is_gateway && config.config.network_api.webrtc,
+            compression: config.config.network_api.compression.clone(),
+            uncompressed_operations: config
+                .config
+                .network_api
+                .compression
+                .uncompressed_operations
+                .as_slice()
+                .into(),
+            gossip_outbound,
         })
     }
 
@@ -168,16 +190,18 @@ impl P2pConnManager {
         cli_response_sender: ClientResponsesSender,
         mut node_controller: Receiver<NodeEvent>,
     ) -> anyhow::Result<Infallible> {
-        tracing::info!(%self.listening_port, %self.listening_ip, %self.is_gateway, key = %self.key_pair.public(), "Opening network listener");
+        tracing::info!(listening_addrs = ?self.listening_addrs, %self.is_gateway, key = %self.key_pair.public(), "Opening network listener");
 
-        let mut state = EventListenerState::new();
+        let mut state = EventListenerState::new(op_manager.limits.max_state_size);
 
         let (outbound_conn_handler, inbound_conn_handler) = create_connection_handler::<UdpSocket>(
             self.key_pair.clone(),
-            self.listening_ip,
-            self.listening_port,
+            &self.listening_addrs,
             self.is_gateway,
-            self.bandwidth_limit,
+            self.bandwidth_limit.clone(),
+            self.webrtc.then_some(&*op_manager.webrtc),
+            &self.compression,
+            op_manager.limits.max_message_size,
         )
         .await?;
 
@@ -230,6 +254,8 @@ impl P2pConnManager {
                                 continue;
                             };
                             tracing::debug!(%target_peer, %msg, "Sending message to peer");
+                            // large states are sent apart, so the transfer can be resumed
+                            let msg = state.state_transfers.start(msg, target_peer.peer.addr);
                             match self.connections.get(&target_peer.peer) {
                                 Some(peer_connection) => {
                                     if let Err(e) = peer_connection.send(Left(msg)).await {
@@ -249,6 +275,9 @@ impl P2pConnManager {
                         ConnEvent::HandshakeAction(action) => {
                             self.handle_handshake_action(action, &mut state).await?;
                         }
+                        ConnEvent::Gossip(msg) => {
+                            self.relay_gossip(msg).await;
+                        }
                         ConnEvent::ClosedChannel => {
                             tracing::info!("Notification channel closed");
                             break;
@@ -256,6 +285,7 @@ impl P2pConnManager {
                         ConnEvent::NodeAction(action) => match action {
                             NodeEvent::DropConnection(peer) => {
                                 tracing::debug!(%peer, "Dropping connection");
+                                self.bridge.op_manager.gossip.left(&peer.addr);
                                 if let Some(conn) = self.connections.remove(&peer) {
                                     // TODO: review: this could potentially leave garbage tasks in the background with peer listener
                                     timeout(
@@ -312,6 +342,20 @@ impl P2pConnManager {
                                 cli_response_sender
                                     .send((client, Err(ErrorKind::FailedOperation.into())))?;
                             }
+                            NodeEvent::CancelTransaction(tx) => {
+                                self.cancel_transaction(tx, &mut state, &cli_response_sender)
+                                    .await;
+                            }
+                            NodeEvent::CancelClientTransactions(client) => {
+                                let transactions = disconnected_client_transactions(
+                                    &mut state.tx_to_client,
+                                    client,
+                                );
+                                for tx in transactions {
+                                    self.cancel_transaction(tx, &mut state, &cli_response_sender)
+                                        .await;
+                                }
+                            }
                             NodeEvent::Disconnect { cause } => {
                                 tracing::info!(
                                     "Disconnecting from network{}",
@@ -340,6 +384,7 @@ impl P2pConnManager {
         client_wait_for_transaction: &mut ContractHandlerChannel<WaitingResolution>,
         executor_listener: &mut ExecutorToEventLoopChannel<NetworkEventListenerHalve>,
     ) -> anyhow::Result<EventResult> {
+        let gossip_outbound = &mut self.gossip_outbound;
         select! {
             msg = state.peer_connections.next(), if !state.peer_connections.is_empty() => {
                 self.handle_peer_connection_msg(msg, state, handshake_handler_msg).await
@@ -362,6 +407,9 @@ impl P2pConnManager {
             id = executor_listener.transaction_from_executor() => {
                 Ok(self.handle_executor_transaction(id, state))
             }
+            Some(msg) = async { gossip_outbound.as_mut()?.recv().await }, if gossip_outbound.is_some() => {
+                Ok(EventResult::Event(ConnEvent::Gossip(msg)))
+            }
         }
     }
 
@@ -378,6 +426,41 @@ impl P2pConnManager {
             NetMessage::V1(NetMessageV1::Aborted(tx)) => {
                 handle_aborted_op(tx, op_manager, &self.gateways).await?;
             }
+            NetMessage::V1(NetMessageV1::Cancelled(tx)) => {
+                tracing::debug!(%tx, "Transaction cancelled by its requester");
+                self.cancel_transaction(tx, state, cli_response_sender)
+                    .await;
+            }
+            // handled as received, knowing the peer they came from
+            NetMessage::V1(
+                NetMessageV1::Gossip(_)
+                | NetMessageV1::GossipJoined
+                | NetMessageV1::StateTransfer(_),
+            ) => {}
+            // light nodes only take part in the transactions of their own clients, the requester
+            // is told right away instead of waiting for the transaction to time out
+            msg if !op_manager.ring.role().serves_network()
+                && msg.awaits_response()
+                && !op_manager.ring.live_tx_tracker.awaits_response(msg.id()) =>
+            {
+                let tx = *msg.id();
+                tracing::debug!(%tx, "Light node declined request from peer");
+                let Some(requester) = state.upstreams.take(&tx) else {
+                    return Ok(());
+                };
+                let conn = self
+                    .connections
+                    .iter()
+                    .find_map(|(peer, conn)| (peer.addr == requester).then_some(conn));
+                if let Some(conn) = conn {
+                    if let Err(error) = conn
+                        .send(Left(NetMessage::V1(NetMessageV1::Aborted(tx))))
+                        .await
+                    {
+                        tracing::debug!(%tx, %requester, %error, "Failed declining request");
+                    }
+                }
+            }
             msg => {
                 if let Some(addr) = state.transient_conn.get(msg.id()) {
                     // Forward message to transient joiner
@@ -454,12 +537,19 @@ impl P2pConnManager {
     async fn handle_connect_peer(
         &mut self,
         peer: PeerId,
-        callback: Box<dyn ConnectResultSender>,
+        mut callback: Box<dyn ConnectResultSender>,
         tx: Transaction,
         handshake_handler_msg: &HanshakeHandlerMsg,
         state: &mut EventListenerState,
         is_gw: bool,
     ) -> anyhow::Result<()> {
+        if self.bridge.op_manager.ring.reputation.is_banned(&peer.addr) {
+            tracing::info!(tx = %tx, remote = %peer, "Refusing to connect to banned peer");
+            callback
+                .send_result(Err(HandshakeError::ConnectionClosed(peer.addr)))
+                .await?;
+            return Ok(());
+        }
         tracing::info!(tx = %tx, remote = %peer, "Connecting to peer");
         state.awaiting_connection.insert(peer.addr, callback);
         let res = timeout(
@@ -492,16 +582,33 @@ impl P2pConnManager {
                 id,
                 conn,
                 joiner,
+                joiner_role,
                 location,
                 op,
                 forward_info,
             } => {
+                if self
+                    .bridge
+                    .op_manager
+                    .ring
+                    .reputation
+                    .is_banned(&joiner.addr)
+                {
+                    tracing::info!(%joiner, "Dropping inbound connection from banned peer");
+                    return Ok(());
+                }
                 let (tx, rx) = mpsc::channel(1);
+                self.announce_gossip(&joiner, &tx);
                 self.connections.insert(joiner.clone(), tx);
                 let was_reserved = {
                     // this is an unexpected inbound request at a gateway so it didn't have a reserved spot
                     false
                 };
+                self.bridge
+                    .op_manager
+                    .ring
+                    .connection_manager
+                    .set_peer_role(&joiner, joiner_role);
                 self.bridge
                     .op_manager
                     .ring
@@ -513,7 +620,13 @@ impl P2pConnManager {
                         .push(id, crate::operations::OpEnum::Connect(op))
                         .await?;
                 }
-                let task = peer_connection_listener(rx, conn).boxed();
+                let task = peer_connection_listener(
+                    rx,
+                    conn,
+                    self.reputation(),
+                    self.uncompressed_operations.clone(),
+                )
+                .boxed();
                 state.peer_connections.push(task);
 
                 if let Some(ForwardInfo {
@@ -653,8 +766,15 @@ impl P2pConnManager {
             tracing::warn!(%peer_id, "No callback for connection established");
         }
         let (tx, rx) = mpsc::channel(10);
+        self.announce_gossip(&peer_id, &tx);
         self.connections.insert(peer_id.clone(), tx);
-        let task = peer_connection_listener(rx, connection).boxed();
+        let task = peer_connection_listener(
+            rx,
+            connection,
+            self.reputation(),
+            self.uncompressed_operations.clone(),
+        )
+        .boxed();
         state.peer_connections.push(task);
         Ok(())
     }
@@ -662,13 +782,83 @@ impl P2pConnManager {
     async fn handle_peer_connection_msg(
         &mut self,
         msg: Option<Result<PeerConnectionInbound, TransportError>>,
-        state: &EventListenerState,
+        state: &mut EventListenerState,
         handshake_handler_msg: &HanshakeHandlerMsg,
     ) -> anyhow::Result<EventResult> {
         match msg {
             Some(Ok(peer_conn)) => {
-                let task = peer_connection_listener(peer_conn.rx, peer_conn.conn).boxed();
+                self.bridge
+                    .op_manager
+                    .recorder
+                    .record(peer_conn.conn.remote_addr(), &peer_conn.msg);
+                let ring = &self.bridge.op_manager.ring;
+                if let Some((peer, rtt)) = ring
+                    .live_tx_tracker
+                    .response_received(peer_conn.conn.remote_addr(), peer_conn.msg.id())
+                {
+                    ring.connection_manager.record_response_time(&peer, rtt);
+                }
+                let remote_addr = peer_conn.conn.remote_addr();
+                let accepted = match &peer_conn.msg {
+                    // only the requester of a transaction can cancel it
+                    NetMessage::V1(NetMessageV1::Cancelled(tx)) => {
+                        state.upstreams.remove(tx, remote_addr)
+                    }
+                    msg => {
+                        if msg.awaits_response() {
+                            state.upstreams.insert(*msg.id(), remote_addr);
+                        }
+                        true
+                    }
+                };
+                let task = peer_connection_listener(
+                    peer_conn.rx,
+                    peer_conn.conn,
+                    self.reputation(),
+                    self.uncompressed_operations.clone(),
+                )
+                .boxed();
                 state.peer_connections.push(task);
+                let channel = &self.bridge.op_manager.gossip;
+                match peer_conn.msg {
+                    NetMessage::V1(NetMessageV1::GossipJoined) => {
+                        channel.joined(remote_addr);
+                        return Ok(EventResult::Continue);
+                    }
+                    NetMessage::V1(NetMessageV1::Gossip(msg)) => {
+                        return Ok(channel
+                            .receive(msg, remote_addr)
+                            .map_or(EventResult::Continue, |msg| {
+                                EventResult::Event(ConnEvent::Gossip(msg))
+                            }));
+                    }
+                    // messages whose state was transferred apart are handled once all of it was
+                    // received
+                    NetMessage::V1(NetMessageV1::StateTransfer(msg)) => {
+                        match state.state_transfers.receive(msg, remote_addr) {
+                            TransferEvent::Deliver(msg) => {
+                                return Ok(EventResult::Event(ConnEvent::InboundMessage(*msg)));
+                            }
+                            TransferEvent::Reply(msg) => {
+                                self.send_state_transfer(remote_addr, std::iter::once(msg));
+                            }
+                            TransferEvent::Stream(stream) => {
+                                self.send_state_transfer(remote_addr, stream.into_msgs());
+                            }
+                            TransferEvent::Nothing => {}
+                        }
+                        return Ok(EventResult::Continue);
+                    }
+                    _ => {}
+                }
+                if !accepted {
+                    tracing::debug!(
+                        tx = %peer_conn.msg.id(),
+                        %remote_addr,
+                        "Ignoring cancellation from a peer other than the requester"
+                    );
+                    return Ok(EventResult::Continue);
+                }
                 Ok(EventResult::Event(ConnEvent::InboundMessage(peer_conn.msg)))
             }
             Some(Err(err)) => {
@@ -685,6 +875,7 @@ impl P2pConnManager {
                             .prune_connection(peer.clone())
                             .await;
                         self.connections.remove(&peer);
+                        self.bridge.op_manager.gossip.left(&socket_addr);
                         handshake_handler_msg.drop_connection(peer).await?;
                     }
                 }
@@ -697,6 +888,93 @@ impl P2pConnManager {
         }
     }
 
+    /// Cancels a transaction: the client waiting for it, if any, gets a cancelled result, and the
+    /// peers it awaits a response from are told to drop it too, on a best-effort basis.
+    async fn cancel_transaction(
+        &self,
+        tx: Transaction,
+        state: &mut EventListenerState,
+        cli_response_sender: &ClientResponsesSender,
+    ) {
+        if let Some(client) = state.tx_to_client.remove(&tx) {
+            if cli_response_sender
+                .send((client, Err(cancelled_error())))
+                .is_err()
+            {
+                tracing::debug!(%tx, %client, "Client response channel closed");
+            }
+        }
+        state.pending_from_executor.remove(&tx);
+        for peer in self.bridge.op_manager.cancel(&tx) {
+            let Some(conn) = self.connections.get(&peer) else {
+                continue;
+            };
+            if let Err(error) = conn
+                .send(Left(NetMessage::V1(NetMessageV1::Cancelled(tx))))
+                .await
+            {
+                tracing::debug!(%tx, %peer, %error, "Failed sending cancellation");
+            }
+        }
+    }
+
+    /// Relays a gossip message to a few random connections which joined the gossip channel, the
+    /// peers which saw it already dropping it.
+    async fn relay_gossip(&self, msg: GossipMsg) {
+        let channel = &self.bridge.op_manager.gossip;
+        let peers = self
+            .connections
+            .iter()
+            .filter(|(peer, _)| channel.is_member(&peer.addr))
+            .choose_multiple(&mut rand::thread_rng(), gossip::FANOUT);
+        for (peer, conn) in peers {
+            let msg = NetMessage::V1(NetMessageV1::Gossip(msg.clone()));
+            if let Err(error) = conn.send(Left(msg)).await {
+                tracing::debug!(%peer, %error, "Failed relaying gossip message");
+            }
+        }
+    }
+
+    /// Tells a new connection the node joined the gossip channel, if it did.
+    fn announce_gossip(&self, peer: &PeerId, conn: &PeerConnChannelSender) {
+        if !self.bridge.op_manager.gossip.enabled() {
+            return;
+        }
+        let msg = NetMessage::V1(NetMessageV1::GossipJoined);
+        if let Err(error) = conn.try_send(Left(msg)) {
+            tracing::debug!(%peer, %error, "Failed announcing the gossip channel");
+        }
+    }
+
+    /// Sends the messages of a state transfer to the peer in the background, so large states
+    /// don't hold up the event loop.
+    fn send_state_transfer(
+        &self,
+        remote_addr: SocketAddr,
+        msgs: impl Iterator<Item = NetMessage> + Send + 'static,
+    ) {
+        let Some(conn) = self
+            .connections
+            .iter()
+            .find_map(|(peer, conn)| (peer.addr == remote_addr).then(|| conn.clone()))
+        else {
+            tracing::debug!(%remote_addr, "No connection to continue state transfer");
+            return;
+        };
+        GlobalExecutor::spawn(async move {
+            for msg in msgs {
+                if let Err(error) = conn.send(Left(msg)).await {
+                    tracing::debug!(%remote_addr, %error, "Failed sending state transfer");
+                    break;
+                }
+            }
+        });
+    }
+
+    fn reputation(&self) -> Arc<PeerReputation> {
+        self.bridge.op_manager.ring.reputation.clone()
+    }
+
     fn handle_notification_msg(&self, msg: Option<Either<NetMessage, NodeEvent>>) -> EventResult {
         match msg {
             Some(Left(msg)) => EventResult::Event(ConnEvent::InboundMessage(msg)),
@@ -829,10 +1107,12 @@ struct EventListenerState {
     client_waiting_transaction: Vec<(WaitingTransaction, HashSet<ClientId>)>,
     transient_conn: HashMap<Transaction, SocketAddr>,
     awaiting_connection: HashMap<SocketAddr, Box<dyn ConnectResultSender>>,
+    state_transfers: StateTransfers,
+    upstreams: Upstreams,
 }
 
 impl EventListenerState {
-    fn new() -> Self {
+    fn new(max_state_size: usize) -> Self {
         Self {
             peer_connections: FuturesUnordered::new(),
             pending_from_executor: HashSet::new(),
@@ -840,10 +1120,48 @@ impl EventListenerState {
             client_waiting_transaction: Vec::new(),
             transient_conn: HashMap::new(),
             awaiting_connection: HashMap::new(),
+            state_transfers: StateTransfers::new(max_state_size),
+            upstreams: Upstreams::default(),
         }
     }
 }
 
+/// Peers which requested the transactions relayed by this node, the only ones allowed to cancel
+/// them.
+#[derive(Default)]
+struct Upstreams(HashMap<Transaction, SocketAddr>);
+
+impl Upstreams {
+    /// Requesters tracked at most, the transactions timed out are dropped past it.
+    const MAX_TRACKED: usize = 10_000;
+
+    fn insert(&mut self, tx: Transaction, requester: SocketAddr) {
+        if self.0.len() >= Self::MAX_TRACKED {
+            let now = std::time::SystemTime::now();
+            self.0.retain(|tx, _| !tx.timed_out_at(now));
+            if self.0.len() >= Self::MAX_TRACKED {
+                tracing::debug!(%tx, "Too many relayed transactions, not tracking requester");
+                return;
+            }
+        }
+        self.0.entry(tx).or_insert(requester);
+    }
+
+    /// Stops tracking the transaction, returning the peer which requested it.
+    fn take(&mut self, tx: &Transaction) -> Option<SocketAddr> {
+        self.0.remove(tx)
+    }
+
+    /// Stops tracking the transaction if `peer` requested it, returning whether it did.
+    fn remove(&mut self, tx: &Transaction, peer: SocketAddr) -> bool {
+        if self.0.get(tx) != Some(&peer) {
+            return false;
+        }
+        self.0.remove(tx);
+        true
+    }
+}
+
 enum EventResult {
     Continue,
     Event(ConnEvent),
@@ -855,6 +1173,8 @@ enum ConnEvent {
     OutboundMessage(NetMessage),
     HandshakeAction(HandshakeEvent),
     NodeAction(NodeEvent),
+    /// Gossip message published by a local client, to send to peers.
+    Gossip(GossipMsg),
     ClosedChannel,
 }
 
@@ -876,6 +1196,8 @@ struct PeerConnectionInbound {
 async fn peer_connection_listener(
     mut rx: PeerConnChannelRecv,
     mut conn: PeerConnection,
+    reputation: Arc<PeerReputation>,
+    uncompressed_operations: Arc<[OperationKind]>,
 ) -> Result<PeerConnectionInbound, TransportError> {
     loop {
         tokio::select! {
@@ -884,9 +1206,12 @@ async fn peer_connection_listener(
                 match msg {
                     Left(msg) => {
                         tracing::debug!(to=%conn.remote_addr() ,"Sending message to peer. Msg: {msg}");
-                        conn
-                            .send(msg)
-                            .await?;
+                        let operation = OperationKind::from(msg.id().transaction_type());
+                        if uncompressed_operations.contains(&operation) {
+                            conn.send_uncompressed(msg).await?;
+                        } else {
+                            conn.send(msg).await?;
+                        }
                     }
                     Right(action) => {
                         tracing::debug!(to=%conn.remote_addr(), "Received action from channel");
@@ -909,7 +1234,16 @@ async fn peer_connection_listener(
                 else {
                     break Err(TransportError::ConnectionClosed(conn.remote_addr()));
                 };
-                let net_message = decode_msg(&msg).unwrap();
+                let Ok(net_message) = decode_msg(&msg)
+                    .inspect_err(|error| {
+                        tracing::warn!(from=%conn.remote_addr(), "Received malformed message: {error}");
+                    })
+                else {
+                    if reputation.penalize(conn.remote_addr(), Misbehavior::MalformedMessage) {
+                        break Err(TransportError::ConnectionClosed(conn.remote_addr()));
+                    }
+                    continue;
+                };
                 tracing::debug!(from=%conn.remote_addr() ,"Received message from peer. Msg: {net_message}");
                 break Ok(PeerConnectionInbound { conn, rx, msg: net_message });
             }
@@ -923,3 +1257,83 @@ fn decode_msg(data: &[u8]) -> Result<NetMessage, ConnectionError> {
 }
 
 // TODO: add testing for the network loop, now it should be possible to do since we don't depend upon having real connections
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operations::get::GetMsg;
+
+    #[test]
+    fn only_requesters_cancel() {
+        let requester: SocketAddr = ([10, 0, 0, 1], 31337).into();
+        let other: SocketAddr = ([10, 0, 0, 2], 31337).into();
+        let tx = Transaction::new::<GetMsg>();
+        let mut upstreams = Upstreams::default();
+        assert!(!upstreams.remove(&tx, requester));
+        upstreams.insert(tx, requester);
+        // a request relayed back doesn't make its sender the requester
+        upstreams.insert(tx, other);
+        assert!(!upstreams.remove(&tx, other));
+        assert!(upstreams.remove(&tx, requester));
+        assert!(!upstreams.remove(&tx, requester));
+    }
+
+    #[test]
+    fn transfer_states_over_the_wire() {
+        use std::sync::Arc;
+
+        use freenet_stdlib::prelude::*;
+
+        use crate::{operations::put::PutMsg, ring::PeerKeyLocation};
+
+        let (sender_addr, receiver_addr): (SocketAddr, SocketAddr) =
+            (([10, 0, 0, 1], 31337).into(), ([10, 0, 0, 2], 31337).into());
+        let mut sender = EventListenerState::new(4 * 1024 * 1024);
+        let mut receiver = EventListenerState::new(4 * 1024 * 1024);
+        let wire = |msg: NetMessage| decode_msg(&bincode::serialize(&msg).unwrap()).unwrap();
+        let transfer_msg = |msg: NetMessage| match wire(msg) {
+            NetMessage::V1(NetMessageV1::StateTransfer(msg)) => msg,
+            other => panic!("unexpected message {other}"),
+        };
+
+        let state: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let contract = WrappedContract::new(
+            Arc::new(ContractCode::from(vec![0])),
+            Parameters::from(vec![]),
+        );
+        let msg = PutMsg::Replicate {
+            id: Transaction::new::<PutMsg>(),
+            sender: PeerKeyLocation::random(),
+            target: PeerKeyLocation::random(),
+            contract: ContractContainer::Wasm(ContractWasmAPIVersion::V1(contract)),
+            value: WrappedState::new(state.clone()),
+        };
+        let offer = sender.state_transfers.start(msg.into(), receiver_addr);
+        let TransferEvent::Reply(resume) = receiver
+            .state_transfers
+            .receive(transfer_msg(offer), sender_addr)
+        else {
+            panic!("offer not answered");
+        };
+        let TransferEvent::Stream(stream) = sender
+            .state_transfers
+            .receive(transfer_msg(resume), receiver_addr)
+        else {
+            panic!("state not sent");
+        };
+        let mut delivered = None;
+        for msg in stream.into_msgs() {
+            if let TransferEvent::Deliver(msg) = receiver
+                .state_transfers
+                .receive(transfer_msg(msg), sender_addr)
+            {
+                delivered = Some(*msg);
+            }
+        }
+        let Some(NetMessage::V1(NetMessageV1::Put(PutMsg::Replicate { value, .. }))) = delivered
+        else {
+            panic!("state not delivered");
+        };
+        assert_eq!(value.as_ref(), state.as_slice());
+    }
+}
@@ -1,11 +1,16 @@
-use std::{cmp::Reverse, collections::BTreeSet, sync::Arc, time::Duration};
+use std::{
+    cmp::Reverse,
+    collections::BTreeSet,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
 
 use dashmap::{DashMap, DashSet};
 use either::Either;
 use tracing::Instrument;
 
 use crate::{
-    config::GlobalExecutor,
+    config::{GlobalExecutor, PayloadLimits, RuntimeConfig},
     contract::{ContractError, ContractHandlerChannel, ContractHandlerEvent, SenderHalve},
     message::{MessageStats, NetMessage, NodeEvent, Transaction, TransactionType},
     operations::{
@@ -13,9 +18,14 @@ use crate::{
         OpEnum, OpError,
     },
     ring::{ConnectionManager, LiveTransactionTracker, Ring},
+    transport::webrtc::WebRtcSignaling,
 };
 
-use super::{network_bridge::EventLoopNotificationsSender, NetEventRegister, NodeConfig, PeerId};
+use super::{
+    clock::NodeClock, diagnostics::NodeHealth, gossip::GossipChannel,
+    network_bridge::EventLoopNotificationsSender, replay::SessionRecorder, NetEventRegister,
+    NodeConfig, PeerId,
+};
 
 #[cfg(debug_assertions)]
 macro_rules! check_id_op {
@@ -53,6 +63,20 @@ pub(crate) struct OpManager {
     to_event_listener: EventLoopNotificationsSender,
     pub ch_outbound: ContractHandlerChannel<SenderHalve>,
     new_transactions: tokio::sync::mpsc::Sender<Transaction>,
+    /// Recorder of the messages received from the network, see [`super::replay`].
+    pub recorder: SessionRecorder,
+    /// Settings which can be changed while the node runs.
+    pub runtime: Arc<RuntimeConfig>,
+    /// Limits of the payloads stored by the node, whether put by clients or received from peers.
+    pub limits: PayloadLimits,
+    /// Signalling of the WebRTC listener, once started if browser peers are accepted.
+    pub webrtc: Arc<OnceLock<WebRtcSignaling>>,
+    /// Diagnostics published to clients.
+    pub health: NodeHealth,
+    /// Clock transactions are timed out against.
+    pub clock: Arc<NodeClock>,
+    /// Channel of the ephemeral messages broadcast by applications.
+    pub gossip: Arc<GossipChannel>,
 }
 
 impl OpManager {
@@ -63,11 +87,15 @@ impl OpManager {
         event_register: ER,
         connection_manager: ConnectionManager,
     ) -> anyhow::Result<Self> {
+        let runtime = Arc::new(RuntimeConfig::new(
+            &config.config,
+            connection_manager.clone(),
+        ));
         let ring = Ring::new(
             config,
             notification_channel.clone(),
             event_register.clone(),
-            config.is_gateway,
+            config.role,
             connection_manager,
         )?;
         let ops = Arc::new(Ops::default());
@@ -84,8 +112,10 @@ impl OpManager {
                 rx,
                 ops.clone(),
                 ring.live_tx_tracker.clone(),
+                ring.connection_manager.clone(),
                 notification_channel.clone(),
                 event_register,
+                config.clock.clone(),
             )
             .instrument(garbage_span),
         );
@@ -96,6 +126,13 @@ impl OpManager {
             to_event_listener: notification_channel,
             ch_outbound,
             new_transactions,
+            recorder: SessionRecorder::default(),
+            runtime,
+            limits: config.config.ws_api.limits,
+            webrtc: Arc::default(),
+            health: NodeHealth::default(),
+            clock: config.clock.clone(),
+            gossip: Arc::new(GossipChannel::new(config.config.network_api.gossip)),
         })
     }
 
@@ -135,7 +172,7 @@ impl OpManager {
 
     pub async fn push(&self, id: Transaction, op: OpEnum) -> Result<(), OpError> {
         if let Some(tx) = self.ops.under_progress.remove(&id) {
-            if tx.timed_out() {
+            if tx.timed_out_at(self.clock.now()) {
                 self.ops.completed.insert(tx);
                 return Ok(());
             }
@@ -176,7 +213,7 @@ impl OpManager {
             return Err(OpNotAvailable::Completed);
         }
         if self.ops.under_progress.contains(id) {
-            if id.timed_out() {
+            if id.timed_out_at(self.clock.now()) {
                 self.ops.completed.insert(*id);
                 return Err(OpNotAvailable::Completed);
             }
@@ -213,6 +250,16 @@ impl OpManager {
         self.ops.completed.insert(id);
     }
 
+    /// Drops the state of a cancelled operation, returning the peers it awaited a response from,
+    /// which should be told to drop it as well.
+    pub fn cancel(&self, id: &Transaction) -> Vec<PeerId> {
+        let awaited = self.ring.live_tx_tracker.awaited_peers(id);
+        remove_op(&self.ops, id);
+        self.ops.under_progress.remove(id);
+        self.completed(*id);
+        awaited
+    }
+
     /// Notify the operation manager that a transaction is being transacted over the network.
     pub fn sending_transaction(&self, peer: &PeerId, msg: &NetMessage) {
         let transaction = msg.id();
@@ -220,9 +267,27 @@ impl OpManager {
             self.ring
                 .record_request(recipient.clone(), target, transaction.transaction_type());
         }
-        self.ring
-            .live_tx_tracker
-            .add_transaction(peer.clone(), *transaction);
+        if msg.awaits_response() {
+            let hops = msg.hops_to_live().unwrap_or(self.ring.max_hops_to_live);
+            self.ring
+                .live_tx_tracker
+                .add_request(peer.clone(), *transaction, hops);
+        } else {
+            self.ring
+                .live_tx_tracker
+                .add_transaction(peer.clone(), *transaction);
+        }
+    }
+}
+
+/// Drops the state of an operation, returns whether it was waiting to make progress.
+fn remove_op(ops: &Ops, tx: &Transaction) -> bool {
+    match tx.transaction_type() {
+        TransactionType::Connect => ops.connect.remove(tx).is_some(),
+        TransactionType::Put => ops.put.remove(tx).is_some(),
+        TransactionType::Get => ops.get.remove(tx).is_some(),
+        TransactionType::Subscribe => ops.subscribe.remove(tx).is_some(),
+        TransactionType::Update => ops.update.remove(tx).is_some(),
     }
 }
 
@@ -230,12 +295,17 @@ async fn garbage_cleanup_task<ER: NetEventRegister>(
     mut new_transactions: tokio::sync::mpsc::Receiver<Transaction>,
     ops: Arc<Ops>,
     live_tx_tracker: LiveTransactionTracker,
+    connection_manager: ConnectionManager,
     event_loop_notifier: EventLoopNotificationsSender,
     mut event_register: ER,
+    clock: Arc<NodeClock>,
 ) {
     const CLEANUP_INTERVAL: Duration = Duration::from_secs(5);
+    /// Granularity of the response timeouts, which are at least a second.
+    const RESPONSE_CHECK_INTERVAL: Duration = Duration::from_millis(250);
     let mut tick = tokio::time::interval(CLEANUP_INTERVAL);
     tick.tick().await;
+    let mut response_check = tokio::time::interval(RESPONSE_CHECK_INTERVAL);
 
     let mut ttl_set = BTreeSet::new();
 
@@ -247,6 +317,24 @@ async fn garbage_cleanup_task<ER: NetEventRegister>(
                     ttl_set.insert(Reverse(tx));
                 }
             }
+            _ = response_check.tick() => {
+                let overdue = live_tx_tracker
+                    .overdue_responses(|peer| connection_manager.response_timeout(peer));
+                for (peer, tx) in overdue {
+                    tracing::debug!(%peer, %tx, "Peer did not respond in time");
+                    connection_manager.record_response_timeout(&peer);
+                    // transactions time out once none of the peers they were sent to may still
+                    // respond, instead of waiting for the whole operation TTL
+                    if live_tx_tracker.awaits_response(&tx) || ops.under_progress.contains(&tx) {
+                        continue;
+                    }
+                    if remove_op(&ops, &tx) {
+                        tracing::debug!("Transaction timed out: {tx}");
+                        event_loop_notifier.send(Either::Right(NodeEvent::TransactionTimedOut(tx))).await.unwrap();
+                        live_tx_tracker.remove_finished_transaction(tx);
+                    }
+                }
+            }
             _ = tick.tick() => {
                 let mut old_missing = std::mem::replace(&mut delayed, Vec::with_capacity(200));
                 for tx in old_missing.drain(..) {
@@ -277,7 +365,7 @@ async fn garbage_cleanup_task<ER: NetEventRegister>(
                 }
 
                 // notice the use of reverse so the older transactions are removed instead of the newer ones
-                let older_than: Reverse<Transaction> = Reverse(Transaction::ttl_transaction());
+                let older_than: Reverse<Transaction> = Reverse(Transaction::ttl_transaction_at(clock.now()));
                 for Reverse(tx) in ttl_set.split_off(&older_than).into_iter() {
                     if ops.under_progress.contains(&tx) {
                         delayed.push(tx);
@@ -291,14 +379,7 @@ async fn garbage_cleanup_task<ER: NetEventRegister>(
                             _ = tx;
                         }
                     }
-                    let removed = match tx.transaction_type() {
-                        TransactionType::Connect => ops.connect.remove(&tx).is_some(),
-                        TransactionType::Put => ops.put.remove(&tx).is_some(),
-                        TransactionType::Get => ops.get.remove(&tx).is_some(),
-                        TransactionType::Subscribe => ops.subscribe.remove(&tx).is_some(),
-                        TransactionType::Update => ops.update.remove(&tx).is_some(),
-                    };
-                    if removed {
+                    if remove_op(&ops, &tx) {
                         tracing::debug!("Transaction timed out: {tx}");
                         event_loop_notifier.send(Either::Right(NodeEvent::TransactionTimedOut(tx))).await.unwrap();
                         live_tx_tracker.remove_finished_transaction(tx);
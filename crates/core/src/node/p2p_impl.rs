@@ -1,4 +1,4 @@
-use std::{convert::Infallible, sync::Arc};
+use std::{convert::Infallible, sync::Arc, time::Duration};
 
 use futures::{future::BoxFuture, FutureExt};
 use tracing::Instrument;
@@ -14,7 +14,7 @@ use crate::{
     ring::{ConnectionManager, Location},
 };
 use crate::{
-    client_events::{combinator::ClientEventsCombinator, BoxedClient},
+    client_events::{combinator::ClientEventsCombinator, node_queries, BoxedClient},
     config::GlobalExecutor,
     contract::{
         self, ClientResponsesSender, ContractHandler, ContractHandlerChannel,
@@ -22,9 +22,20 @@ use crate::{
     },
     message::NodeEvent,
     node::NodeConfig,
-    operations::connect,
+    operations::{self, connect},
 };
 
+/// How often replicas lost to disconnections are repaired.
+const REPLICATION_REPAIR_INTERVAL: Duration = Duration::from_secs(60);
+/// How often the state of cached contracts is compared with the neighbors.
+const ANTI_ENTROPY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// How often the diagnostics of the node are published to clients.
+const DIAGNOSTICS_INTERVAL: Duration = Duration::from_secs(5);
+/// How often contracts over the storage budget are evicted.
+const STORAGE_BUDGET_INTERVAL: Duration = Duration::from_secs(60);
+/// How often the subscriptions to pinned contracts are checked.
+const PINNED_CONTRACTS_INTERVAL: Duration = Duration::from_secs(30);
+
 use super::OpManager;
 
 pub(crate) struct NodeP2P {
@@ -76,7 +87,7 @@ impl NodeP2P {
 
     pub(crate) async fn build<CH, const CLIENTS: usize, ER>(
         config: NodeConfig,
-        clients: [BoxedClient; CLIENTS],
+        mut clients: [BoxedClient; CLIENTS],
         event_register: ER,
         ch_builder: CH::Builder,
     ) -> anyhow::Result<Self>
@@ -85,7 +96,13 @@ impl NodeP2P {
         ER: NetEventRegister + Clone,
     {
         let (notification_channel, notification_tx) = event_loop_notification_channel();
-        let (ch_outbound, ch_inbound, wait_for_event) = contract::contract_handler_channel();
+        // the client API servers shed load on the events queued in the contract handler
+        let queued_events = clients
+            .iter()
+            .find_map(|client| client.queued_events())
+            .unwrap_or_default();
+        let (ch_outbound, ch_inbound, wait_for_event) =
+            contract::contract_handler_channel(queued_events);
         let (client_responses, cli_response_sender) = contract::client_responses_channel();
 
         let connection_manager = ConnectionManager::new(&config);
@@ -115,6 +132,81 @@ impl NodeP2P {
             Err(e) => anyhow::anyhow!(e),
         })
         .boxed();
+        if let Some(interval) = config.config.snapshots.interval {
+            GlobalExecutor::spawn(
+                contract::storages::snapshot::periodic_snapshots(
+                    op_manager.clone(),
+                    config.config.snapshots_dir(),
+                    Duration::from_secs(interval),
+                    config.config.snapshots.keep,
+                )
+                .instrument(tracing::info_span!(parent: parent_span.clone(), "state_snapshots")),
+            );
+        }
+        // the storage budget can be set while running, so the task always runs
+        GlobalExecutor::spawn(
+            contract::storages::quota::enforce_storage_budget(
+                op_manager.clone(),
+                STORAGE_BUDGET_INTERVAL,
+            )
+            .instrument(tracing::info_span!(parent: parent_span.clone(), "storage_budget")),
+        );
+        GlobalExecutor::spawn(
+            contract::storages::quota::resize_caches(op_manager.clone())
+                .instrument(tracing::info_span!(parent: parent_span.clone(), "cache_sizes")),
+        );
+        #[cfg(unix)]
+        GlobalExecutor::spawn(
+            crate::config::reload_on_hangup(op_manager.runtime.clone())
+                .instrument(tracing::info_span!(parent: parent_span.clone(), "config_reload")),
+        );
+        GlobalExecutor::spawn(
+            contract::storages::quota::keep_pinned_subscribed(
+                op_manager.clone(),
+                PINNED_CONTRACTS_INTERVAL,
+            )
+            .instrument(tracing::info_span!(parent: parent_span.clone(), "pinned_contracts")),
+        );
+        // light nodes don't hold replicas of contracts for the network
+        if config.role.serves_network() {
+            GlobalExecutor::spawn(
+                operations::put::replication_repair(
+                    op_manager.clone(),
+                    conn_manager.bridge.clone(),
+                    REPLICATION_REPAIR_INTERVAL,
+                )
+                .instrument(tracing::info_span!(parent: parent_span.clone(), "replication_repair")),
+            );
+            GlobalExecutor::spawn(
+                operations::update::anti_entropy(
+                    op_manager.clone(),
+                    conn_manager.bridge.clone(),
+                    ANTI_ENTROPY_INTERVAL,
+                )
+                .instrument(tracing::info_span!(parent: parent_span.clone(), "anti_entropy")),
+            );
+        }
+        GlobalExecutor::spawn(
+            super::diagnostics::report_diagnostics(op_manager.clone(), DIAGNOSTICS_INTERVAL)
+                .instrument(tracing::info_span!(parent: parent_span.clone(), "diagnostics")),
+        );
+        if let Some(port) = config.config.admin_api.port {
+            super::admin_api::serve_admin_api(port, op_manager.clone(), config.config.clone());
+        }
+        for access_log in clients.iter_mut().filter_map(|client| client.access_log()) {
+            GlobalExecutor::spawn(
+                crate::config::follow_access_log(op_manager.runtime.clone(), access_log)
+                    .instrument(tracing::info_span!(parent: parent_span.clone(), "access_log")),
+            );
+        }
+        let queries = clients
+            .iter_mut()
+            .filter_map(|client| client.node_queries())
+            .collect();
+        GlobalExecutor::spawn(
+            node_queries::serve(op_manager.clone(), queries)
+                .instrument(tracing::info_span!(parent: parent_span.clone(), "node_queries")),
+        );
         let clients = ClientEventsCombinator::new(clients);
         let (node_controller_tx, node_controller_rx) = tokio::sync::mpsc::channel(1);
         let client_events_task = GlobalExecutor::spawn(
@@ -0,0 +1,561 @@
+//! Recording of the messages a node receives from the network, and their deterministic replay.
+//!
+//! While a recording is active, the connection manager appends every message received from a
+//! peer, with the time it arrived and the address it came from, to a session file in the
+//! `recordings` dir of the node. The file is written by a thread of its own, so recording doesn't
+//! block the event loop; if the thread falls behind, the recording stops. Recordings are started through the admin API for a window of
+//! time. The file starts with the location and connections the node had at the time, so routing
+//! decisions can be reproduced.
+//!
+//! [`replay_session`] feeds a recording back through the operation state machines of a node with
+//! that location and connections, one message at a time: each message, and every message the node
+//! sends to itself while handling it, is fully processed before moving on to the next one, and the
+//! clock of the node is set to the time the message arrived. Contracts are not executed, their
+//! states are held in memory by a mock executor, and transaction ids and routing choices are drawn
+//! from an RNG seeded from the recording (see [`super::clock`]), so replaying a session always
+//! gives the same result. The messages the node would send to other peers are collected instead
+//! of sent.
+//!
+//! On disk a recording is laid out as `MAGIC | header | message*`, each one bincode encoded.
+//!
+//! The messages of a recording can also be exported, as received from the network, to seed the
+//! fuzzing of the message decoders with real traffic.
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use either::Either;
+use futures::{future::BoxFuture, FutureExt};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use super::{
+    clock::NodeClock,
+    handle_aborted_op,
+    network_bridge::{event_loop_notification_channel, ConnResult, NetworkBridge},
+    process_message, NodeConfig, OpManager, PeerId,
+};
+use crate::{
+    config::{Config, GlobalExecutor},
+    contract::{self, ReplayContractHandler},
+    message::{NetMessage, NetMessageV1, Transaction},
+    ring::{ConnectionManager, Location, PeerKeyLocation},
+    router::RouteEvent,
+    tracing::{NetEventLog, NetEventRegister},
+};
+
+const MAGIC: &[u8; 8] = b"FNREC001";
+const RECORDING_PREFIX: &str = "session-";
+const RECORDING_EXT: &str = "rec";
+
+/// Window of time recordings last when not specified.
+pub(crate) const DEFAULT_RECORDING_WINDOW: Duration = Duration::from_secs(60);
+/// Longest window of time a recording can be started for.
+pub(crate) const MAX_RECORDING_WINDOW: Duration = Duration::from_secs(60 * 60);
+/// Messages waiting to be written to a recording before it is stopped for falling behind.
+const MAX_PENDING_WRITES: usize = 1024;
+
+/// State of the node when a recording started.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct SessionHeader {
+    /// Version of the node which recorded the session.
+    pub version: String,
+    pub started_at: DateTime<Utc>,
+    pub peer: PeerId,
+    pub location: Option<Location>,
+    pub connections: Vec<PeerKeyLocation>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RecordedMessage<M> {
+    received_at: DateTime<Utc>,
+    from: SocketAddr,
+    msg: M,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum RecordingError {
+    #[error("a recording is already active until {0}")]
+    AlreadyRecording(DateTime<Utc>),
+    #[error("the node has not joined the network yet")]
+    NotJoined,
+    #[error("not a session recording file")]
+    InvalidFormat,
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+    #[error(transparent)]
+    Serialization(#[from] bincode::Error),
+}
+
+/// A recording in progress.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct RecordingInfo {
+    pub path: PathBuf,
+    pub until: DateTime<Utc>,
+}
+
+struct Recording {
+    info: RecordingInfo,
+    /// Encoded messages for the writer thread to append to the recording.
+    writes: mpsc::Sender<Vec<u8>>,
+    writer: JoinHandle<()>,
+}
+
+/// Appends the messages received from the network to the active recording, if any.
+#[derive(Default)]
+pub(crate) struct SessionRecorder {
+    active: Mutex<Option<Recording>>,
+}
+
+impl SessionRecorder {
+    fn start(
+        &self,
+        dir: &Path,
+        window: Duration,
+        header: &SessionHeader,
+    ) -> Result<RecordingInfo, RecordingError> {
+        let mut active = self.active.lock();
+        if let Some(recording) = active.as_ref() {
+            return Err(RecordingError::AlreadyRecording(recording.info.until));
+        }
+        std::fs::create_dir_all(dir)?;
+        let file_name = format!(
+            "{RECORDING_PREFIX}{}.{RECORDING_EXT}",
+            header.started_at.format("%Y%m%dT%H%M%S%.3fZ")
+        );
+        let path = dir.join(file_name);
+        let mut out = BufWriter::new(File::create(&path)?);
+        out.write_all(MAGIC)?;
+        bincode::serialize_into(&mut out, header)?;
+        let (writes, pending) = mpsc::channel(MAX_PENDING_WRITES);
+        let writer = {
+            let path = path.clone();
+            std::thread::Builder::new()
+                .name("session-recorder".to_owned())
+                .spawn(move || write_recording(&path, out, pending))?
+        };
+        let info = RecordingInfo {
+            path,
+            until: header.started_at
+                + chrono::Duration::from_std(window).unwrap_or(chrono::Duration::zero()),
+        };
+        *active = Some(Recording {
+            info: info.clone(),
+            writes,
+            writer,
+        });
+        Ok(info)
+    }
+
+    /// Appends a message received from a peer to the active recording.
+    pub fn record(&self, from: SocketAddr, msg: &NetMessage) {
+        let mut active = self.active.lock();
+        let Some(recording) = active.as_ref() else {
+            return;
+        };
+        let received_at = Utc::now();
+        if received_at >= recording.info.until {
+            finish(active.take());
+            return;
+        }
+        let entry = RecordedMessage {
+            received_at,
+            from,
+            msg,
+        };
+        let encoded = match bincode::serialize(&entry) {
+            Ok(encoded) => encoded,
+            Err(err) => {
+                tracing::error!(path = ?recording.info.path, "Failed recording message, stopping: {err}");
+                finish(active.take());
+                return;
+            }
+        };
+        if recording.writes.try_send(encoded).is_err() {
+            tracing::error!(path = ?recording.info.path, "Recording fell behind the messages received, stopping");
+            finish(active.take());
+        }
+    }
+
+    /// The recording in progress, if any.
+    pub fn active(&self) -> Option<RecordingInfo> {
+        self.active
+            .lock()
+            .as_ref()
+            .map(|recording| recording.info.clone())
+    }
+
+    /// Stops the recording at `path`, if still active, returning its writer thread, which exits
+    /// once the recording is fully written.
+    fn stop(&self, path: &Path) -> Option<JoinHandle<()>> {
+        let mut active = self.active.lock();
+        if active
+            .as_ref()
+            .is_some_and(|recording| recording.info.path == path)
+        {
+            return finish(active.take());
+        }
+        None
+    }
+}
+
+fn finish(recording: Option<Recording>) -> Option<JoinHandle<()>> {
+    let Recording { writes, writer, .. } = recording?;
+    // the writer exits once the pending messages are written
+    drop(writes);
+    Some(writer)
+}
+
+fn write_recording(path: &Path, mut out: BufWriter<File>, mut pending: mpsc::Receiver<Vec<u8>>) {
+    let mut messages = 0;
+    while let Some(entry) = pending.blocking_recv() {
+        if let Err(err) = out.write_all(&entry) {
+            tracing::error!(?path, "Failed writing session recording: {err}");
+            return;
+        }
+        messages += 1;
+    }
+    match out.flush() {
+        Ok(()) => tracing::info!(?path, messages, "Finished recording session"),
+        Err(err) => tracing::error!(?path, "Failed writing session recording: {err}"),
+    }
+}
+
+/// Starts recording the messages received by the node for the given window of time.
+pub(crate) fn start_recording(
+    op_manager: &Arc<OpManager>,
+    dir: &Path,
+    window: Duration,
+) -> Result<RecordingInfo, RecordingError> {
+    let window = window.min(MAX_RECORDING_WINDOW);
+    let connection_manager = &op_manager.ring.connection_manager;
+    let peer = connection_manager
+        .get_peer_key()
+        .ok_or(RecordingError::NotJoined)?;
+    let header = SessionHeader {
+        version: env!("CARGO_PKG_VERSION").to_owned(),
+        started_at: Utc::now(),
+        peer,
+        location: connection_manager.own_location().location,
+        connections: op_manager.ring.connections(),
+    };
+    let info = op_manager.recorder.start(dir, window, &header)?;
+    tracing::info!(path = ?info.path, until = %info.until, "Recording session");
+
+    let op_manager = op_manager.clone();
+    let path = info.path.clone();
+    GlobalExecutor::spawn(async move {
+        tokio::time::sleep(window).await;
+        op_manager.recorder.stop(&path);
+    });
+    Ok(info)
+}
+
+/// Returns the recordings present in `dir`, oldest first.
+pub(crate) fn list_recordings(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut recordings = std::fs::read_dir(dir)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| {
+            p.extension().is_some_and(|ext| ext == RECORDING_EXT)
+                && p.file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| n.starts_with(RECORDING_PREFIX))
+        })
+        .collect::<Vec<_>>();
+    // file names embed the start timestamp so lexicographic order is chronological
+    recordings.sort();
+    Ok(recordings)
+}
+
+fn read_recording(
+    path: &Path,
+) -> Result<(SessionHeader, Vec<RecordedMessage<NetMessage>>), RecordingError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut magic = [0; MAGIC.len()];
+    reader
+        .read_exact(&mut magic)
+        .map_err(|_| RecordingError::InvalidFormat)?;
+    if &magic != MAGIC {
+        return Err(RecordingError::InvalidFormat);
+    }
+    let header: SessionHeader = bincode::deserialize_from(&mut reader)?;
+    let mut messages = vec![];
+    while !reader.fill_buf()?.is_empty() {
+        match bincode::deserialize_from(&mut reader) {
+            Ok(msg) => messages.push(msg),
+            Err(err) => {
+                // the node may have stopped while writing the last message
+                tracing::warn!(?path, "Ignoring the rest of the recording: {err}");
+                break;
+            }
+        }
+    }
+    Ok((header, messages))
+}
+
+/// Writes each message of a recording to a file in `dir`, encoded as it is received from the
+/// network, and returns the number of files written. Files are named after their content, so
+/// exporting several recordings to the same dir doesn't duplicate messages.
+#[cfg(any(test, feature = "fuzzing"))]
+pub(crate) fn export_messages(recording: &Path, dir: &Path) -> Result<usize, RecordingError> {
+    let (_, messages) = read_recording(recording)?;
+    std::fs::create_dir_all(dir)?;
+    let mut written = 0;
+    for recorded in messages {
+        let encoded =
+            crate::transport::Compression::NONE.encode(bincode::serialize(&recorded.msg)?, false);
+        let path = dir.join(format!(
+            "recorded-{}",
+            &blake3::hash(&encoded).to_hex()[..16]
+        ));
+        if !path.exists() {
+            std::fs::write(path, encoded)?;
+            written += 1;
+        }
+    }
+    Ok(written)
+}
+
+/// A recorded message, along with the messages the node sent while handling it.
+#[derive(Debug)]
+pub struct ReplayStep {
+    pub received_at: DateTime<Utc>,
+    pub from: SocketAddr,
+    pub message: String,
+    /// Messages sent to other peers, with the peer they were sent to.
+    pub sent: Vec<(PeerId, String)>,
+}
+
+#[derive(Debug)]
+pub struct ReplayReport {
+    pub peer: PeerId,
+    pub location: Option<Location>,
+    pub steps: Vec<ReplayStep>,
+}
+
+/// Replays a session recorded by a node, see the [module docs](self).
+pub async fn replay_session(config: Config, recording: &Path) -> anyhow::Result<ReplayReport> {
+    let (header, messages) = read_recording(recording)?;
+    if header.version != env!("CARGO_PKG_VERSION") {
+        tracing::warn!(
+            recorded_by = %header.version,
+            "Replaying a session recorded by a different version, messages may be handled differently"
+        );
+    }
+    let mut node_config = NodeConfig::new(config).await?;
+    node_config.peer_id = Some(header.peer.clone());
+    node_config.location = header.location;
+    node_config.clock = Arc::new(NodeClock::replay(
+        header.started_at.into(),
+        header.started_at.timestamp_millis() as u64,
+    ));
+
+    let (mut notifications, notification_tx) = event_loop_notification_channel();
+    let (ch_outbound, ch_inbound, _wait_for_event) =
+        contract::contract_handler_channel(Default::default());
+    let op_manager = Arc::new(OpManager::new(
+        notification_tx,
+        ch_outbound,
+        &node_config,
+        ReplayRegister,
+        ConnectionManager::new(&node_config),
+    )?);
+    let contract_handling = GlobalExecutor::spawn(contract::contract_handling(
+        ReplayContractHandler::new(ch_inbound),
+    ));
+    for peer in header.connections {
+        if let Some(location) = peer.location {
+            op_manager
+                .ring
+                .add_connection(location, peer.peer, false)
+                .await;
+        }
+    }
+
+    let bridge = ReplayBridge::default();
+    let clock = op_manager.clock.clone();
+    let steps = clock
+        .scope(async {
+            let mut steps = Vec::with_capacity(messages.len());
+            for recorded in messages {
+                op_manager.clock.set(recorded.received_at.into());
+                let message = recorded.msg.to_string();
+                handle_message(recorded.msg, &op_manager, &bridge).await;
+                // messages the node sends to itself are handled before moving on to the next one
+                while let Ok(notification) = notifications.try_recv() {
+                    match notification {
+                        Either::Left(msg) => handle_message(msg, &op_manager, &bridge).await,
+                        Either::Right(event) => tracing::debug!(?event, "Ignoring node event"),
+                    }
+                }
+                steps.push(ReplayStep {
+                    received_at: recorded.received_at,
+                    from: recorded.from,
+                    message,
+                    sent: std::mem::take(&mut *bridge.sent.lock()),
+                });
+            }
+            steps
+        })
+        .await;
+
+    contract_handling.abort();
+    Ok(ReplayReport {
+        peer: header.peer,
+        location: header.location,
+        steps,
+    })
+}
+
+async fn handle_message(msg: NetMessage, op_manager: &Arc<OpManager>, bridge: &ReplayBridge) {
+    tracing::debug!(%msg, "Replaying message");
+    if let NetMessage::V1(NetMessageV1::Aborted(tx)) = msg {
+        if let Err(err) = handle_aborted_op(tx, op_manager, &[]).await {
+            tracing::warn!(%tx, "Failed handling aborted transaction: {err}");
+        }
+        return;
+    }
+    process_message(
+        msg,
+        op_manager.clone(),
+        bridge.clone(),
+        Box::new(ReplayRegister),
+        None,
+        None,
+        None,
+    )
+    .await;
+}
+
+/// Collects the messages sent to other peers instead of sending them.
+#[derive(Clone, Default)]
+struct ReplayBridge {
+    sent: Arc<Mutex<Vec<(PeerId, String)>>>,
+}
+
+impl NetworkBridge for ReplayBridge {
+    async fn drop_connection(&mut self, peer: &PeerId) -> ConnResult<()> {
+        tracing::debug!(%peer, "Dropping connection");
+        Ok(())
+    }
+
+    async fn send(&self, target: &PeerId, msg: NetMessage) -> ConnResult<()> {
+        self.sent.lock().push((target.clone(), msg.to_string()));
+        Ok(())
+    }
+}
+
+/// Event register discarding every event, the replayed node doesn't report to the network
+/// metrics server nor learns from the routing outcomes.
+#[derive(Clone)]
+struct ReplayRegister;
+
+impl NetEventRegister for ReplayRegister {
+    fn register_events<'a>(
+        &'a self,
+        _events: Either<NetEventLog<'a>, Vec<NetEventLog<'a>>>,
+    ) -> BoxFuture<'a, ()> {
+        async {}.boxed()
+    }
+
+    fn notify_of_time_out(&mut self, tx: Transaction) -> BoxFuture<()> {
+        async move { tracing::debug!(%tx, "Transaction timed out") }.boxed()
+    }
+
+    fn trait_clone(&self) -> Box<dyn NetEventRegister> {
+        Box::new(self.clone())
+    }
+
+    fn get_router_events(&self, _number: usize) -> BoxFuture<anyhow::Result<Vec<RouteEvent>>> {
+        async { Ok(vec![]) }.boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_and_read() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let peer = PeerId::random();
+        let header = SessionHeader {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            started_at: Utc::now(),
+            peer: peer.clone(),
+            location: Some(Location::new(0.25)),
+            connections: vec![],
+        };
+        let recorder = SessionRecorder::default();
+        let info = recorder.start(dir.path(), Duration::from_secs(60), &header)?;
+        assert!(matches!(
+            recorder.start(dir.path(), Duration::from_secs(60), &header),
+            Err(RecordingError::AlreadyRecording(_))
+        ));
+
+        let tx = Transaction::new::<crate::operations::put::PutMsg>();
+        recorder.record(peer.addr, &NetMessage::V1(NetMessageV1::Aborted(tx)));
+        recorder.record(peer.addr, &NetMessage::V1(NetMessageV1::Aborted(tx)));
+        recorder
+            .stop(&info.path)
+            .expect("recording active")
+            .join()
+            .expect("recording written");
+        assert!(recorder.active().is_none());
+        // once stopped messages are not recorded anymore
+        recorder.record(peer.addr, &NetMessage::V1(NetMessageV1::Aborted(tx)));
+
+        assert_eq!(list_recordings(dir.path())?, vec![info.path.clone()]);
+        let (read_header, messages) = read_recording(&info.path)?;
+        assert_eq!(read_header.peer, peer);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].from, peer.addr);
+        assert!(matches!(
+            messages[1].msg,
+            NetMessage::V1(NetMessageV1::Aborted(id)) if id == tx
+        ));
+
+        // both messages are the same, so only one is exported
+        let seeds = dir.path().join("seeds");
+        assert_eq!(export_messages(&info.path, &seeds)?, 1);
+        for seed in std::fs::read_dir(&seeds)? {
+            let decoded = crate::transport::decode_message(
+                std::fs::read(seed?.path())?,
+                crate::config::PayloadLimits::default().max_message_size,
+            )?;
+            assert!(matches!(
+                bincode::deserialize(&decoded)?,
+                NetMessage::V1(NetMessageV1::Aborted(id)) if id == tx
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn fuzz_seeds_decode() -> anyhow::Result<()> {
+        // seeds stop being useful once the wire format changes, then they must be exported again
+        let seeds = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/seeds/peer_message");
+        for seed in std::fs::read_dir(seeds)? {
+            let seed = seed?.path();
+            let decoded = crate::transport::decode_message(
+                std::fs::read(&seed)?,
+                crate::config::PayloadLimits::default().max_message_size,
+            )?;
+            bincode::deserialize::<NetMessage>(&decoded)
+                .map_err(|err| anyhow::anyhow!("{}: {err}", seed.display()))?;
+        }
+        Ok(())
+    }
+}
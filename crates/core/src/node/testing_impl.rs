@@ -816,6 +816,22 @@ where
         .await
 }
 
+/// Tells the peers a cancelled transaction awaits a response from to drop it too.
+async fn cancel_at_peers<NB: NetworkBridge>(
+    conn_manager: &NB,
+    op_manager: &Arc<OpManager>,
+    tx: Transaction,
+) {
+    for peer in op_manager.cancel(&tx) {
+        if let Err(error) = conn_manager
+            .send(&peer, NetMessage::V1(NetMessageV1::Cancelled(tx)))
+            .await
+        {
+            tracing::debug!(%tx, %peer, %error, "Failed sending cancellation");
+        }
+    }
+}
+
 /// Starts listening to incoming events. Will attempt to join the ring if any gateways have been provided.
 async fn run_event_listener<NB, UsrEv>(
     cli_response_sender: contract::ClientResponsesSender,
@@ -909,8 +925,29 @@ where
                 NodeEvent::QueryConnections { .. } => {
                     unimplemented!()
                 }
-                NodeEvent::TransactionTimedOut(_) => {
-                    unimplemented!()
+                NodeEvent::TransactionTimedOut(tx) => {
+                    if let Some(client) = tx_to_client.remove(&tx) {
+                        let _ = cli_response_sender.send((
+                            client,
+                            Err(freenet_stdlib::client_api::ErrorKind::FailedOperation.into()),
+                        ));
+                    }
+                    continue;
+                }
+                NodeEvent::CancelTransaction(tx) => {
+                    if let Some(client) = tx_to_client.remove(&tx) {
+                        let _ = cli_response_sender.send((client, Err(super::cancelled_error())));
+                    }
+                    pending_from_executor.remove(&tx);
+                    cancel_at_peers(&conn_manager, &op_manager, tx).await;
+                    continue;
+                }
+                NodeEvent::CancelClientTransactions(client) => {
+                    for tx in super::disconnected_client_transactions(&mut tx_to_client, client) {
+                        pending_from_executor.remove(&tx);
+                        cancel_at_peers(&conn_manager, &op_manager, tx).await;
+                    }
+                    continue;
                 }
             },
             Err(err) => {
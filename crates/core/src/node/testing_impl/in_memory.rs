@@ -30,7 +30,8 @@ impl<ER> Builder<ER> {
         let gateways = self.config.get_gateways()?;
 
         let (notification_channel, notification_tx) = event_loop_notification_channel();
-        let (ops_ch_channel, ch_channel, wait_for_event) = contract::contract_handler_channel();
+        let (ops_ch_channel, ch_channel, wait_for_event) =
+            contract::contract_handler_channel(Default::default());
 
         let _guard = parent_span.enter();
         let connection_manager = ConnectionManager::new(&self.config);
@@ -7,16 +7,17 @@ use futures::Future;
 use tokio::sync::mpsc::error::SendError;
 
 use crate::{
-    client_events::HostResult,
+    client_events::{limits::PayloadTooLarge, HostResult},
     contract::{ContractError, ExecutorError},
     message::{InnerMessage, MessageStats, NetMessage, NetMessageV1, Transaction, TransactionType},
     node::{ConnectionError, NetworkBridge, OpManager, OpNotAvailable, PeerId},
-    ring::{Location, PeerKeyLocation, RingError},
+    ring::{Location, Misbehavior, PeerKeyLocation, RingError},
 };
 
 pub(crate) mod connect;
 pub(crate) mod get;
 pub(crate) mod put;
+pub(crate) mod state_transfer;
 pub(crate) mod subscribe;
 pub(crate) mod update;
 
@@ -96,6 +97,15 @@ where
         }
         Err(err) => {
             if let Some(sender) = sender {
+                if matches!(err, OpError::InvalidStateTransition { .. })
+                    && op_manager
+                        .ring
+                        .reputation
+                        .penalize(sender.addr, Misbehavior::InvalidStateTransition)
+                {
+                    network_bridge.drop_connection(&sender).await?;
+                    return Err(err);
+                }
                 network_bridge
                     .send(&sender, NetMessage::V1(NetMessageV1::Aborted(tx_id)))
                     .await?;
@@ -262,6 +272,8 @@ pub(crate) enum OpError {
     MaxRetriesExceeded(Transaction, TransactionType),
     #[error("op not available")]
     OpNotAvailable(#[from] OpNotAvailable),
+    #[error(transparent)]
+    PayloadTooLarge(#[from] PayloadTooLarge),
 
     // used for control flow
     /// This is used as an early interrumpt of an op update when an op
@@ -330,6 +342,19 @@ async fn start_subscription_request(
     }
 }
 
+/// Light nodes only hold the contracts of their own clients, and refuse to store any other one
+/// for the network.
+async fn check_stores_contract(op_manager: &OpManager, key: ContractKey) -> Result<(), OpError> {
+    let ring = &op_manager.ring;
+    if ring.role().serves_network()
+        || ring.is_seeding_contract(&key)
+        || has_contract(op_manager, key).await?
+    {
+        return Ok(());
+    }
+    Err(OpError::RingError(RingError::NotServingNetwork(key)))
+}
+
 async fn has_contract(op_manager: &OpManager, key: ContractKey) -> Result<bool, OpError> {
     match op_manager
         .notify_contract_handler(crate::contract::ContractHandlerEvent::GetQuery {
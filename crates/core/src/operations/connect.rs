@@ -1,6 +1,7 @@
 //! Operation which seeks new connections in the ring.
 use std::borrow::Borrow;
 use std::collections::HashSet;
+use std::net::SocketAddr;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::time::Duration;
@@ -16,11 +17,11 @@ use crate::ring::ConnectionManager;
 use crate::router::Router;
 use crate::transport::TransportPublicKey;
 use crate::{
+    config::NodeRole,
     message::{InnerMessage, NetMessage, Transaction},
     node::{NetworkBridge, OpManager, PeerId},
     operations::OpEnum,
     ring::PeerKeyLocation,
-    util::Backoff,
 };
 
 pub(crate) use self::messages::{ConnectMsg, ConnectRequest, ConnectResponse};
@@ -30,8 +31,6 @@ pub(crate) struct ConnectOp {
     id: Transaction,
     state: Option<ConnectState>,
     pub gateway: Option<Box<PeerKeyLocation>>,
-    /// keeps track of the number of retries and applies an exponential backoff cooldown period
-    pub backoff: Option<Backoff>,
 }
 
 impl ConnectOp {
@@ -39,18 +38,8 @@ impl ConnectOp {
         id: Transaction,
         state: Option<ConnectState>,
         gateway: Option<Box<PeerKeyLocation>>,
-        backoff: Option<Backoff>,
     ) -> Self {
-        Self {
-            id,
-            state,
-            gateway,
-            backoff,
-        }
-    }
-
-    pub fn has_backoff(&self) -> bool {
-        self.backoff.is_some()
+        Self { id, state, gateway }
     }
 
     pub(super) fn outcome(&self) -> OpOutcome {
@@ -118,7 +107,6 @@ impl Operation for ConnectOp {
                     op: Self {
                         id: tx,
                         state: Some(ConnectState::Initializing),
-                        backoff: None,
                         gateway,
                     },
                     sender: None,
@@ -157,6 +145,8 @@ impl Operation for ConnectOp {
                             query_target,
                             ideal_location,
                             joiner,
+                            joiner_addresses,
+                            joiner_role,
                             max_hops_to_live,
                             skip_connections,
                             skip_forwards,
@@ -200,7 +190,7 @@ impl Operation for ConnectOp {
                             let msg = create_forward_message(
                                 *id,
                                 &own_loc,
-                                joiner,
+                                (joiner, joiner_addresses.as_slice(), *joiner_role),
                                 &desirable_peer,
                                 *max_hops_to_live,
                                 *max_hops_to_live,
@@ -239,6 +229,8 @@ impl Operation for ConnectOp {
                                 query_target: query_target.clone(),
                                 ideal_location: *ideal_location,
                                 joiner: joiner.clone(),
+                                joiner_addresses: joiner_addresses.clone(),
+                                joiner_role: *joiner_role,
                                 max_hops_to_live: *max_hops_to_live,
                                 skip_connections,
                                 skip_forwards,
@@ -254,6 +246,8 @@ impl Operation for ConnectOp {
                         ConnectRequest::CheckConnectivity {
                             sender,
                             joiner,
+                            joiner_addresses,
+                            joiner_role,
                             hops_to_live,
                             max_hops_to_live,
                             skip_connections,
@@ -285,17 +279,22 @@ impl Operation for ConnectOp {
                         "Checking connectivity request received"
                     );
 
+                    // the joiner may only be reachable from here through another address family
+                    let joiner_peer = op_manager
+                        .ring
+                        .connection_manager
+                        .dialable_peer(&joiner.peer, joiner_addresses);
                     let should_accept = if op_manager
                         .ring
                         .connection_manager
-                        .should_accept(joiner_loc, &joiner.peer)
+                        .should_accept(joiner_loc, &joiner_peer)
                     {
-                        tracing::debug!(tx = %id, %joiner, "Accepting connection from");
+                        tracing::debug!(tx = %id, %joiner, addr = %joiner_peer.addr, "Accepting connection from");
                         let (callback, mut result) = tokio::sync::mpsc::channel(1);
                         // Attempt to connect to the joiner
                         op_manager
                             .notify_node_event(NodeEvent::ConnectPeer {
-                                peer: joiner.peer.clone(),
+                                peer: joiner_peer.clone(),
                                 tx: *id,
                                 callback,
                                 is_gw: false,
@@ -314,7 +313,11 @@ impl Operation for ConnectOp {
                             // Add the connection to the ring
                             op_manager
                                 .ring
-                                .add_connection(joiner_loc, joiner.peer.clone(), was_reserved)
+                                .connection_manager
+                                .set_peer_role(&joiner_peer, *joiner_role);
+                            op_manager
+                                .ring
+                                .add_connection(joiner_loc, joiner_peer, was_reserved)
                                 .await;
                             true
                         } else {
@@ -322,7 +325,7 @@ impl Operation for ConnectOp {
                             op_manager
                                 .ring
                                 .connection_manager
-                                .prune_in_transit_connection(&joiner.peer);
+                                .prune_in_transit_connection(&joiner_peer);
                             false
                         }
                     } else {
@@ -346,6 +349,8 @@ impl Operation for ConnectOp {
                                 skip_forwards: skip_forwards.clone(),
                                 req_peer: sender.clone(),
                                 joiner: joiner.clone(),
+                                joiner_addresses: joiner_addresses.clone(),
+                                joiner_role: *joiner_role,
                             },
                         )
                         .await?
@@ -541,7 +546,6 @@ impl Operation for ConnectOp {
                                 id: *id,
                                 state: None,
                                 gateway: self.gateway,
-                                backoff: self.backoff,
                             };
                             op_manager
                                 .notify_op_change(
@@ -556,7 +560,7 @@ impl Operation for ConnectOp {
                 _ => return Err(OpError::UnexpectedOpState),
             }
 
-            build_op_result(self.id, new_state, return_msg, self.gateway, self.backoff)
+            build_op_result(self.id, new_state, return_msg, self.gateway)
         })
     }
 }
@@ -566,7 +570,6 @@ fn build_op_result(
     state: Option<ConnectState>,
     msg: Option<ConnectMsg>,
     gateway: Option<Box<PeerKeyLocation>>,
-    backoff: Option<Backoff>,
 ) -> Result<OperationResult, OpError> {
     tracing::debug!(tx = %id, ?msg, "Connect operation result");
     Ok(OperationResult {
@@ -576,7 +579,6 @@ fn build_op_result(
                 id,
                 state: Some(state),
                 gateway,
-                backoff,
             }))
         }),
     })
@@ -678,7 +680,7 @@ pub(crate) async fn initial_join_procedure(
         let max_potential_conns_per_gw = op_manager.ring.max_hops_to_live;
         // e.g. 10 gateways and htl 5 -> only need 2 connections in parallel
         let needed_to_cover_max =
-            op_manager.ring.connection_manager.max_connections / max_potential_conns_per_gw;
+            op_manager.ring.connection_manager.max_connections() / max_potential_conns_per_gw;
         gateways.iter().take(needed_to_cover_max).count().max(1)
     };
     let gateways = gateways.to_vec();
@@ -699,14 +701,8 @@ pub(crate) async fn initial_join_procedure(
                     .shuffle()
                     .take(number_of_parallel_connections)
                 {
-                    tracing::info!(%gateway, "Attempting connection to gateway");
-                    if let Err(error) = join_ring_request(None, gateway, &op_manager).await {
-                        if !matches!(
-                            error,
-                            OpError::ConnError(crate::node::ConnectionError::UnwantedConnection)
-                        ) {
-                            tracing::error!(%error, "Failed while attempting connection to gateway");
-                        }
+                    if let Err(error) = dial_gateway(gateway, &op_manager).await {
+                        tracing::error!(%error, "Failed while attempting connection to gateway");
                     }
                 }
             }
@@ -720,9 +716,68 @@ pub(crate) async fn initial_join_procedure(
     Ok(())
 }
 
+/// Joins the ring through the gateway, unless dialing it is backing off or too many dials are in
+/// flight. Returns whether the gateway was dialed.
+async fn dial_gateway(gateway: &PeerKeyLocation, op_manager: &OpManager) -> Result<bool, OpError> {
+    let dials = &op_manager.ring.dials;
+    if !dials.try_start(gateway.peer.addr) {
+        tracing::debug!(%gateway, "Postponing connection to gateway");
+        return Ok(false);
+    }
+    tracing::info!(%gateway, "Attempting connection to gateway");
+    match join_ring_request(gateway, op_manager).await {
+        Ok(()) => Ok(true),
+        Err(OpError::ConnError(crate::node::ConnectionError::UnwantedConnection)) => {
+            // the node doesn't want the connection for now, which is no fault of the gateway
+            dials.postpone(gateway.peer.addr);
+            Ok(false)
+        }
+        Err(error) => {
+            dials.failed(gateway.peer.addr);
+            Err(error)
+        }
+    }
+}
+
+/// Dials the gateway again once its backoff expires, as long as the node is short of
+/// connections. Only one task retries each gateway.
+pub(crate) fn retry_gateway(op_manager: Arc<OpManager>, gateway: PeerKeyLocation) {
+    if !op_manager.ring.dials.start_retrying(gateway.peer.addr) {
+        tracing::debug!(%gateway, "Gateway already being retried");
+        return;
+    }
+    tokio::task::spawn(async move {
+        let ring = &op_manager.ring;
+        retry_until_connected(&op_manager, &gateway).await;
+        ring.dials.stop_retrying(&gateway.peer.addr);
+    });
+}
+
+async fn retry_until_connected(op_manager: &OpManager, gateway: &PeerKeyLocation) {
+    loop {
+        let delay = ring.dials.delay(&gateway.peer.addr);
+        tokio::time::sleep(delay.max(Duration::from_secs(1))).await;
+        let connected = ring
+            .is_not_connected(std::iter::once(gateway))
+            .next()
+            .is_none();
+        if connected || ring.open_connections() >= ring.connection_manager.min_connections() {
+            return;
+        }
+        match dial_gateway(gateway, op_manager).await {
+            Ok(true) => return,
+            // still backing off, or too many dials in flight
+            Ok(false) => continue,
+            Err(error) => {
+                tracing::error!(%error, "Failed while attempting connection to gateway");
+                return;
+            }
+        }
+    }
+}
+
 #[tracing::instrument(fields(peer = %op_manager.ring.connection_manager.pub_key), skip_all)]
 pub(crate) async fn join_ring_request(
-    backoff: Option<Backoff>,
     gateway: &PeerKeyLocation,
     op_manager: &OpManager,
 ) -> Result<(), OpError> {
@@ -742,25 +797,8 @@ pub(crate) async fn join_ring_request(
 
     let tx_id = Transaction::new::<ConnectMsg>();
     tracing::info!(%gateway.peer, "Attempting network join");
-    let mut op = initial_request(gateway.clone(), op_manager.ring.max_hops_to_live, tx_id);
-    if let Some(mut backoff) = backoff {
-        // backoff to retry later in case it failed
-        tracing::warn!("Performing a new join, attempt {}", backoff.retries() + 1);
-        if backoff.sleep().await.is_none() {
-            tracing::error!("Max number of retries reached");
-            if op_manager.ring.open_connections() == 0 {
-                // only consider this a complete failure if no connections were established at all
-                // if connections where established the peer should incrementally acquire more over time
-                return Err(OpError::MaxRetriesExceeded(tx_id, tx_id.transaction_type()));
-            } else {
-                return Ok(());
-            }
-        }
-        // on first run the backoff will be initialized at the `initial_request` function
-        // if the op was to fail and retried this function will be called with the previous backoff
-        // passed as an argument and advanced
-        op.backoff = Some(backoff);
-    }
+    // failed joins are retried by dialing the gateway again, see `retry_gateway`
+    let op = initial_request(gateway.clone(), op_manager.ring.max_hops_to_live, tx_id);
     connect_request(tx_id, op_manager, op).await?;
     Ok(())
 }
@@ -770,26 +808,15 @@ fn initial_request(
     max_hops_to_live: usize,
     id: Transaction,
 ) -> ConnectOp {
-    const MAX_JOIN_RETRIES: usize = usize::MAX;
     let state = ConnectState::ConnectingToNode(ConnectionInfo {
         gateway: gateway.clone(),
         accepted_by: HashSet::new(),
         remaining_connections: max_hops_to_live,
     });
-    let ceiling = if cfg!(test) {
-        Duration::from_secs(1)
-    } else {
-        Duration::from_secs(120)
-    };
     ConnectOp {
         id,
         state: Some(state),
         gateway: Some(Box::new(gateway)),
-        backoff: Some(Backoff::new(
-            Duration::from_secs(1),
-            ceiling,
-            MAX_JOIN_RETRIES,
-        )),
     }
 }
 
@@ -799,9 +826,7 @@ async fn connect_request(
     op_manager: &OpManager,
     join_op: ConnectOp,
 ) -> Result<(), OpError> {
-    let ConnectOp {
-        id, state, backoff, ..
-    } = join_op;
+    let ConnectOp { id, state, .. } = join_op;
     let ConnectionInfo { gateway, .. } = state.expect("infallible").try_unwrap_connecting()?;
 
     tracing::info!(
@@ -852,7 +877,6 @@ async fn connect_request(
                             remaining_connections,
                         })),
                         gateway: Some(Box::new(gateway)),
-                        backoff,
                     })),
                 )
                 .await?;
@@ -874,6 +898,9 @@ pub(crate) struct ForwardParams {
     pub skip_forwards: HashSet<PeerId>,
     pub req_peer: PeerKeyLocation,
     pub joiner: PeerKeyLocation,
+    /// Other addresses the joiner advertised.
+    pub joiner_addresses: Vec<SocketAddr>,
+    pub joiner_role: NodeRole,
 }
 
 pub(crate) async fn forward_conn<NB>(
@@ -894,6 +921,8 @@ where
         mut skip_forwards,
         req_peer,
         joiner,
+        joiner_addresses,
+        joiner_role,
     } = params;
     if left_htl == 0 {
         tracing::debug!(
@@ -932,7 +961,7 @@ where
             let forward_msg = create_forward_message(
                 id,
                 &req_peer,
-                &joiner,
+                (&joiner, joiner_addresses.as_slice(), joiner_role),
                 &target_peer,
                 left_htl,
                 max_htl,
@@ -984,7 +1013,7 @@ fn select_forward_target(
 fn create_forward_message(
     id: Transaction,
     request_peer: &PeerKeyLocation,
-    joiner: &PeerKeyLocation,
+    (joiner, joiner_addresses, joiner_role): (&PeerKeyLocation, &[SocketAddr], NodeRole),
     target: &PeerKeyLocation,
     hops_to_live: usize,
     max_hops_to_live: usize,
@@ -997,6 +1026,8 @@ fn create_forward_message(
         msg: ConnectRequest::CheckConnectivity {
             sender: request_peer.clone(),
             joiner: joiner.clone(),
+            joiner_addresses: joiner_addresses.to_vec(),
+            joiner_role,
             hops_to_live: hops_to_live.saturating_sub(1), // decrement the hops to live for the next hop
             max_hops_to_live,
             skip_connections,
@@ -1138,6 +1169,10 @@ mod messages {
             /// Used for deterministic testing purposes. In production, this should be none and will be ignored
             /// by the gateway.
             joiner_location: Option<Location>,
+            /// Other addresses the joiner listens at, e.g. of another address family.
+            joiner_addresses: Vec<SocketAddr>,
+            /// Role of the joiner, requests are never routed to light nodes.
+            joiner_role: NodeRole,
             hops_to_live: usize,
             max_hops_to_live: usize,
             // Peers we don't want to connect to directly
@@ -1152,6 +1187,8 @@ mod messages {
             /// The ideal location of the peer to which you would connect.
             ideal_location: Location,
             joiner: PeerKeyLocation,
+            joiner_addresses: Vec<SocketAddr>,
+            joiner_role: NodeRole,
             max_hops_to_live: usize,
             skip_connections: HashSet<PeerId>,
             skip_forwards: HashSet<PeerId>,
@@ -1159,6 +1196,8 @@ mod messages {
         CheckConnectivity {
             sender: PeerKeyLocation,
             joiner: PeerKeyLocation,
+            joiner_addresses: Vec<SocketAddr>,
+            joiner_role: NodeRole,
             hops_to_live: usize,
             max_hops_to_live: usize,
             skip_connections: HashSet<PeerId>,
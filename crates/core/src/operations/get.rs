@@ -3,11 +3,17 @@ use freenet_stdlib::prelude::*;
 use std::collections::HashSet;
 use std::fmt::Display;
 use std::pin::Pin;
-use std::{future::Future, time::Instant};
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use tokio::sync::mpsc;
 
 use crate::client_events::HostResult;
 use crate::{
-    contract::{ContractHandlerEvent, StoreResponse},
+    contract::{ContractError, ContractHandlerEvent, StoreResponse},
     message::{InnerMessage, NetMessage, Transaction},
     node::{NetworkBridge, OpManager, PeerId},
     operations::{OpInitialization, Operation},
@@ -16,11 +22,16 @@ use crate::{
 
 use super::{OpEnum, OpError, OpOutcome, OperationResult};
 
-pub(crate) use self::messages::GetMsg;
+pub(crate) use self::messages::{GetMsg, StateInfo};
 
 /// Maximum number of retries to get values.
 const MAX_RETRIES: usize = 10;
 
+/// Number of peers closest to the contract location asked by a probe.
+const PROBE_PEERS: usize = 3;
+/// Time a probe waits for the replies of the probed peers.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub(crate) fn start_op(key: ContractKey, fetch_contract: bool) -> GetOp {
     let contract_location = Location::from(&key);
     let id = Transaction::new::<GetMsg>();
@@ -132,6 +143,11 @@ enum GetState {
     },
     /// Operation completed successfully
     Finished { key: ContractKey },
+    /// Awaiting the reply of a peer to a probe.
+    Probing {
+        key: ContractKey,
+        replies: mpsc::Sender<(PeerKeyLocation, Option<StateInfo>)>,
+    },
 }
 
 impl Display for GetState {
@@ -158,6 +174,7 @@ impl Display for GetState {
                 write!(f, "AwaitingResponse(requester: {:?}, fetch_contract: {}, retries: {}, current_hop: {})", requester, fetch_contract, retries, current_hop)
             }
             GetState::Finished { key, .. } => write!(f, "Finished(key: {})", key),
+            GetState::Probing { key, .. } => write!(f, "Probing(key: {})", key),
         }
     }
 }
@@ -298,7 +315,7 @@ impl Operation for GetOp {
 
     fn process_message<'a, NB: NetworkBridge>(
         self,
-        _conn_manager: &'a mut NB,
+        conn_manager: &'a mut NB,
         op_manager: &'a OpManager,
         input: &'a Self::Message,
     ) -> Pin<Box<dyn Future<Output = Result<OperationResult, OpError>> + Send + 'a>> {
@@ -364,12 +381,28 @@ impl Operation for GetOp {
                     let mut new_skip_list = skip_list.clone();
                     new_skip_list.insert(this_peer.clone().peer);
 
-                    let get_result = op_manager
+                    // upgraded contracts are redirected to their latest version when it's cached
+                    // here and its code requested, so the requester can verify the upgrade when
+                    // storing it
+                    let latest = if fetch_contract {
+                        latest_version(op_manager, key).await
+                    } else {
+                        key
+                    };
+                    let mut get_result = op_manager
                         .notify_contract_handler(ContractHandlerEvent::GetQuery {
-                            key,
+                            key: latest,
                             return_contract_code: fetch_contract,
                         })
                         .await;
+                    if latest != key && !has_state(&get_result) {
+                        get_result = op_manager
+                            .notify_contract_handler(ContractHandlerEvent::GetQuery {
+                                key,
+                                return_contract_code: fetch_contract,
+                            })
+                            .await;
+                    }
 
                     let (returned_key, contract, state) = match get_result {
                         Ok(ContractHandlerEvent::GetResponse {
@@ -407,10 +440,10 @@ impl Operation for GetOp {
                         Some(GetState::AwaitingResponse { requester, .. }) => {
                             if let Some(requester) = requester {
                                 new_state = None;
-                                tracing::debug!(tx = %id, "Returning contract {} to {}", key, sender.peer);
+                                tracing::debug!(tx = %id, "Returning contract {} to {}", returned_key, sender.peer);
                                 return_msg = Some(GetMsg::ReturnGet {
                                     id,
-                                    key,
+                                    key: returned_key,
                                     value: StoreResponse {
                                         state: Some(state),
                                         contract,
@@ -431,10 +464,10 @@ impl Operation for GetOp {
                         }
                         Some(GetState::ReceivedRequest) => {
                             new_state = None;
-                            tracing::debug!(tx = %id, "Returning contract {} to {}", key, sender.peer);
+                            tracing::debug!(tx = %id, "Returning contract {} to {}", returned_key, sender.peer);
                             return_msg = Some(GetMsg::ReturnGet {
                                 id,
-                                key,
+                                key: returned_key,
                                 value: StoreResponse {
                                     state: Some(state),
                                     contract,
@@ -712,6 +745,18 @@ impl Operation for GetOp {
                                     )
                                     .await;
                                 }
+                                if let Some(contract) =
+                                    contract.as_ref().filter(|_| should_subscribe)
+                                {
+                                    super::put::replicate(
+                                        op_manager,
+                                        conn_manager,
+                                        contract,
+                                        value.clone(),
+                                        HashSet::from([sender.peer.clone()]),
+                                    )
+                                    .await;
+                                }
                             }
                             ContractHandlerEvent::PutResponse {
                                 new_value: Err(err),
@@ -822,6 +867,41 @@ impl Operation for GetOp {
                         None => return Err(OpError::invalid_transition(self.id)),
                     };
                 }
+                GetMsg::RequestProbe { id, key, target } => {
+                    // fast tracked from the probe func
+                    debug_assert!(matches!(self.state, Some(GetState::Probing { .. })));
+                    new_state = self.state;
+                    return_msg = Some(GetMsg::Probe {
+                        id: *id,
+                        key: *key,
+                        sender: op_manager.ring.connection_manager.own_location(),
+                        target: target.clone(),
+                    });
+                }
+                GetMsg::Probe {
+                    id, key, sender, ..
+                } => {
+                    new_state = None;
+                    return_msg = Some(GetMsg::ProbeResult {
+                        id: *id,
+                        key: *key,
+                        state: local_state_info(op_manager, *key).await,
+                        sender: op_manager.ring.connection_manager.own_location(),
+                        target: sender.clone(),
+                    });
+                }
+                GetMsg::ProbeResult {
+                    key, state, sender, ..
+                } => {
+                    let Some(GetState::Probing { replies, .. }) = self.state else {
+                        return Err(OpError::invalid_transition(self.id));
+                    };
+                    tracing::debug!(%key, peer = %sender.peer, caching = %state.is_some(), "Received probe result");
+                    // the prober may have given up already
+                    let _ = replies.send((sender.clone(), state.clone())).await;
+                    new_state = None;
+                    return_msg = None;
+                }
             }
 
             build_op_result(self.id, new_state, return_msg, result, stats)
@@ -829,6 +909,153 @@ impl Operation for GetOp {
     }
 }
 
+/// Metadata about a contract, found without transferring its code nor its state.
+#[derive(Debug, Serialize)]
+pub(crate) struct ContractHead {
+    #[serde(serialize_with = "serialize_key")]
+    pub key: ContractKey,
+    /// Whether this node or any of the probed peers caches the contract.
+    pub known: bool,
+    pub state_size: Option<u64>,
+    /// Base58 encoded blake3 hash of the state.
+    pub state_hash: Option<String>,
+    /// Distance to the contract location of the closest peer found caching it.
+    pub caching_distance: Option<f64>,
+}
+
+impl ContractHead {
+    /// Records a peer caching the contract, keeping the state seen by the closest one.
+    fn record(&mut self, peer: &PeerKeyLocation, state: StateInfo) {
+        self.known = true;
+        let Some(distance) = peer
+            .location
+            .map(|loc| loc.distance(Location::from(&self.key)).as_f64())
+        else {
+            return;
+        };
+        if self
+            .caching_distance
+            .map_or(true, |closest| distance < closest)
+        {
+            self.state_size = Some(state.size);
+            self.state_hash = Some(bs58::encode(state.hash).into_string());
+            self.caching_distance = Some(distance);
+        }
+    }
+}
+
+fn serialize_key<S: serde::Serializer>(
+    key: &ContractKey,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(key)
+}
+
+/// Maximum number of contracts probed in a single request.
+pub(crate) const MAX_PROBED_CONTRACTS: usize = 100;
+
+/// Probes whether the contract is cached by this node or by the peers closest to its location,
+/// without fetching its code nor its state.
+pub(crate) async fn probe(
+    op_manager: &OpManager,
+    key: ContractKey,
+) -> Result<ContractHead, OpError> {
+    let mut head = ContractHead {
+        key,
+        known: false,
+        state_size: None,
+        state_hash: None,
+        caching_distance: None,
+    };
+    if let Some(state) = local_state_info(op_manager, key).await {
+        head.record(&op_manager.ring.connection_manager.own_location(), state);
+    }
+
+    let (replies_tx, mut replies) = mpsc::channel(PROBE_PEERS);
+    for target in op_manager
+        .ring
+        .k_closest_peers(Location::from(&key), PROBE_PEERS)
+    {
+        // every peer is probed in its own transaction, so replies are processed independently
+        let id = Transaction::new::<GetMsg>();
+        let op = GetOp {
+            id,
+            state: Some(GetState::Probing {
+                key,
+                replies: replies_tx.clone(),
+            }),
+            result: None,
+            stats: None,
+        };
+        op_manager
+            .notify_op_change(
+                NetMessage::from(GetMsg::RequestProbe { id, key, target }),
+                OpEnum::Get(op),
+            )
+            .await?;
+    }
+    drop(replies_tx);
+
+    // the channel closes once every probe got a reply or timed out
+    let _ = tokio::time::timeout(PROBE_TIMEOUT, async {
+        while let Some((peer, state)) = replies.recv().await {
+            if let Some(state) = state {
+                head.record(&peer, state);
+            }
+        }
+    })
+    .await;
+    Ok(head)
+}
+
+/// Latest version of a contract, if it was upgraded.
+pub(crate) async fn latest_version(op_manager: &OpManager, key: ContractKey) -> ContractKey {
+    match op_manager
+        .notify_contract_handler(ContractHandlerEvent::SuccessorQuery { id: *key.id() })
+        .await
+    {
+        Ok(ContractHandlerEvent::SuccessorResponse {
+            successor: Some(successor),
+            ..
+        }) => {
+            tracing::debug!(contract = %key, %successor, "Redirecting get to successor");
+            successor
+        }
+        _ => key,
+    }
+}
+
+fn has_state(result: &Result<ContractHandlerEvent, ContractError>) -> bool {
+    matches!(
+        result,
+        Ok(ContractHandlerEvent::GetResponse {
+            response: Ok(StoreResponse { state: Some(_), .. }),
+            ..
+        })
+    )
+}
+
+async fn local_state_info(op_manager: &OpManager, key: ContractKey) -> Option<StateInfo> {
+    match op_manager
+        .notify_contract_handler(ContractHandlerEvent::GetQuery {
+            key,
+            return_contract_code: false,
+        })
+        .await
+    {
+        Ok(ContractHandlerEvent::GetResponse {
+            response: Ok(StoreResponse {
+                state: Some(state), ..
+            }),
+            ..
+        }) => Some(StateInfo {
+            size: state.size() as u64,
+            hash: *blake3::hash(state.as_ref()).as_bytes(),
+        }),
+        _ => None,
+    }
+}
+
 fn build_op_result(
     id: Transaction,
     state: Option<GetState>,
@@ -980,6 +1207,34 @@ mod messages {
             target: PeerKeyLocation,
             skip_list: HashSet<PeerId>,
         },
+        /// Internal node instruction to probe a peer for a contract.
+        RequestProbe {
+            id: Transaction,
+            key: ContractKey,
+            target: PeerKeyLocation,
+        },
+        /// Asks whether the target caches a contract, without fetching it.
+        Probe {
+            id: Transaction,
+            key: ContractKey,
+            sender: PeerKeyLocation,
+            target: PeerKeyLocation,
+        },
+        ProbeResult {
+            id: Transaction,
+            key: ContractKey,
+            /// The state cached by the sender, if any.
+            state: Option<StateInfo>,
+            sender: PeerKeyLocation,
+            target: PeerKeyLocation,
+        },
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub(crate) struct StateInfo {
+        pub size: u64,
+        /// Blake3 hash of the state.
+        pub hash: [u8; 32],
     }
 
     impl InnerMessage for GetMsg {
@@ -988,6 +1243,9 @@ mod messages {
                 Self::RequestGet { id, .. } => id,
                 Self::SeekNode { id, .. } => id,
                 Self::ReturnGet { id, .. } => id,
+                Self::RequestProbe { id, .. } => id,
+                Self::Probe { id, .. } => id,
+                Self::ProbeResult { id, .. } => id,
             }
         }
 
@@ -996,6 +1254,9 @@ mod messages {
                 Self::SeekNode { target, .. } => Some(target),
                 Self::RequestGet { target, .. } => Some(target),
                 Self::ReturnGet { target, .. } => Some(target),
+                Self::RequestProbe { target, .. } => Some(target),
+                Self::Probe { target, .. } => Some(target),
+                Self::ProbeResult { target, .. } => Some(target),
             }
         }
 
@@ -1004,6 +1265,9 @@ mod messages {
                 GetMsg::RequestGet { key, .. } => Some(Location::from(key.id())),
                 GetMsg::SeekNode { key, .. } => Some(Location::from(key.id())),
                 GetMsg::ReturnGet { key, .. } => Some(Location::from(key.id())),
+                GetMsg::RequestProbe { key, .. }
+                | GetMsg::Probe { key, .. }
+                | GetMsg::ProbeResult { key, .. } => Some(Location::from(key.id())),
             }
         }
     }
@@ -1012,6 +1276,8 @@ mod messages {
         pub fn sender(&self) -> Option<&PeerKeyLocation> {
             match self {
                 Self::SeekNode { target, .. } => Some(target),
+                Self::Probe { sender, .. } => Some(sender),
+                Self::ProbeResult { sender, .. } => Some(sender),
                 _ => None,
             }
         }
@@ -1024,7 +1290,47 @@ mod messages {
                 Self::RequestGet { .. } => write!(f, "RequestGet(id: {id})"),
                 Self::SeekNode { .. } => write!(f, "SeekNode(id: {id})"),
                 Self::ReturnGet { .. } => write!(f, "ReturnGet(id: {id})"),
+                Self::RequestProbe { .. } => write!(f, "RequestProbe(id: {id})"),
+                Self::Probe { .. } => write!(f, "Probe(id: {id})"),
+                Self::ProbeResult { .. } => write!(f, "ProbeResult(id: {id})"),
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contract_head_keeps_closest_state() {
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let contract_loc = Location::from(&key).as_f64();
+        let peer_at = |offset: f64| PeerKeyLocation {
+            peer: PeerId::random(),
+            location: Some(Location::new((contract_loc + offset).rem_euclid(1.0))),
+        };
+        let state = |size| StateInfo {
+            size,
+            hash: [size as u8; 32],
+        };
+        let mut head = ContractHead {
+            key,
+            known: false,
+            state_size: None,
+            state_hash: None,
+            caching_distance: None,
+        };
+
+        head.record(&PeerKeyLocation::from(PeerId::random()), state(1));
+        assert!(head.known);
+        assert_eq!(head.state_size, None);
+
+        head.record(&peer_at(0.3), state(2));
+        head.record(&peer_at(0.01), state(3));
+        head.record(&peer_at(0.2), state(4));
+        assert_eq!(head.state_size, Some(3));
+        assert_eq!(head.state_hash, Some(bs58::encode([3; 32]).into_string()));
+        assert!(head.caching_distance.unwrap() < 0.02);
+    }
+}
@@ -14,8 +14,8 @@ use freenet_stdlib::{
 
 use super::{OpEnum, OpError, OpInitialization, OpOutcome, Operation, OperationResult};
 use crate::{
-    client_events::HostResult,
-    contract::ContractHandlerEvent,
+    client_events::{limits::Payload, HostResult},
+    contract::{ContractHandlerEvent, StoreResponse},
     message::{InnerMessage, NetMessage, NetMessageV1, Transaction},
     node::{NetworkBridge, OpManager, PeerId},
     ring::{Location, PeerKeyLocation, RingError},
@@ -212,7 +212,7 @@ impl Operation for PutOp {
                         already_put = true;
                     }
 
-                    if !is_subscribed_contract {
+                    if !is_subscribed_contract && op_manager.ring.role().serves_network() {
                         let mut skip_list = HashSet::new();
                         skip_list.insert(sender.peer.clone());
                         skip_list.insert(target.peer.clone());
@@ -268,6 +268,17 @@ impl Operation for PutOp {
                         }
                     }
 
+                    if last_hop {
+                        replicate(
+                            op_manager,
+                            conn_manager,
+                            contract,
+                            value.clone(),
+                            HashSet::from([sender.peer.clone()]),
+                        )
+                        .await;
+                    }
+
                     let broadcast_to = op_manager.get_broadcast_targets(&key, &sender.peer);
 
                     match try_to_broadcast(
@@ -571,6 +582,19 @@ impl Operation for PutOp {
                         true
                     };
 
+                    if last_hop {
+                        let mut skip_list = skip_list.clone();
+                        skip_list.insert(sender.peer.clone());
+                        replicate(
+                            op_manager,
+                            conn_manager,
+                            contract,
+                            new_value.clone(),
+                            skip_list,
+                        )
+                        .await;
+                    }
+
                     let broadcast_to = op_manager.get_broadcast_targets(&key, &sender.peer);
                     match try_to_broadcast(
                         *id,
@@ -590,6 +614,39 @@ impl Operation for PutOp {
                         Err(err) => return Err(err),
                     }
                 }
+                PutMsg::Replicate {
+                    id,
+                    contract,
+                    value,
+                    sender,
+                    ..
+                } => {
+                    let key = contract.key();
+                    // replicas are unsolicited, only hold those this peer would seed anyway
+                    if !op_manager.ring.is_seeding_contract(&key)
+                        && !op_manager.ring.should_seed(&key)
+                    {
+                        tracing::debug!(tx = %id, %key, sender = %sender.peer, "Refusing contract replica, not close enough to the contract");
+                        return build_op_result(self.id, None, None, stats);
+                    }
+                    tracing::debug!(tx = %id, %key, sender = %sender.peer, "Storing contract replica");
+                    put_contract(
+                        op_manager,
+                        key,
+                        value.clone(),
+                        RelatedContracts::default(),
+                        contract,
+                    )
+                    .await?;
+                    if !op_manager.ring.is_seeding_contract(&key) {
+                        let skip_list = HashSet::from([sender.peer.clone()]);
+                        super::start_subscription_request(op_manager, key, false, skip_list).await;
+                        op_manager.ring.seed_contract(key);
+                    }
+                    // replicas are fire and forget, nothing to respond
+                    new_state = None;
+                    return_msg = None;
+                }
                 _ => return Err(OpError::UnexpectedOpState),
             }
 
@@ -820,6 +877,8 @@ async fn put_contract(
     related_contracts: RelatedContracts<'static>,
     contract: &ContractContainer,
 ) -> Result<WrappedState, OpError> {
+    op_manager.limits.check(Payload::State, state.size())?;
+    super::check_stores_contract(op_manager, key).await?;
     // after the contract has been cached, push the update query
     match op_manager
         .notify_contract_handler(ContractHandlerEvent::PutQuery {
@@ -836,7 +895,10 @@ async fn put_contract(
         Ok(ContractHandlerEvent::PutResponse {
             new_value: Err(err),
         }) => {
-            tracing::error!(%key, "Failed to update contract value: {}", err);
+            match err.policy_refusal() {
+                Some(refusal) => tracing::debug!(%key, "{refusal}"),
+                None => tracing::error!(%key, "Failed to update contract value: {}", err),
+            }
             Err(OpError::from(err))
             // TODO: not a valid value update, notify back to requester
         }
@@ -897,6 +959,83 @@ where
     true
 }
 
+/// Pushes replicas of the contract to the closest peers to its location which are not caching it
+/// yet, until the contract replication factor is met.
+///
+/// Replicas are "fire and forget", the peers which were sent one are assumed to hold it until they
+/// disconnect.
+pub(crate) async fn replicate<CB>(
+    op_manager: &OpManager,
+    conn_manager: &CB,
+    contract: &ContractContainer,
+    value: WrappedState,
+    skip_list: HashSet<PeerId>,
+) where
+    CB: NetworkBridge,
+{
+    let key = contract.key();
+    let factor = op_manager.ring.replication.factor_for(contract);
+    let targets = op_manager.ring.replica_targets(&key, factor, &skip_list);
+    if targets.is_empty() {
+        return;
+    }
+    let sender = op_manager.ring.connection_manager.own_location();
+    let mut holders = Vec::with_capacity(targets.len());
+    for target in targets {
+        let msg = PutMsg::Replicate {
+            id: Transaction::new::<PutMsg>(),
+            sender: sender.clone(),
+            target: target.clone(),
+            contract: contract.clone(),
+            value: value.clone(),
+        };
+        match conn_manager.send(&target.peer, msg.into()).await {
+            Ok(()) => holders.push(target.peer),
+            Err(error) => {
+                tracing::debug!(%key, peer = %target.peer, %error, "Failed sending contract replica")
+            }
+        }
+    }
+    tracing::debug!(%key, factor, replicas = holders.len(), "Replicated contract");
+    op_manager.ring.replication.record(key, factor, holders);
+}
+
+/// Periodically pushes new replicas for the contracts whose replica holders disconnected.
+pub(crate) async fn replication_repair<CB>(
+    op_manager: std::sync::Arc<OpManager>,
+    conn_manager: CB,
+    interval: std::time::Duration,
+) where
+    CB: NetworkBridge,
+{
+    let mut interval = tokio::time::interval(interval);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        for key in op_manager.ring.replication.under_replicated() {
+            let response = op_manager
+                .notify_contract_handler(ContractHandlerEvent::GetQuery {
+                    key,
+                    return_contract_code: true,
+                })
+                .await;
+            match response {
+                Ok(ContractHandlerEvent::GetResponse {
+                    response:
+                        Ok(StoreResponse {
+                            state: Some(state),
+                            contract: Some(contract),
+                        }),
+                    ..
+                }) => {
+                    replicate(&op_manager, &conn_manager, &contract, state, HashSet::new()).await;
+                }
+                _ => tracing::debug!(%key, "Contract no longer available, skipping replica repair"),
+            }
+        }
+    }
+}
+
 mod messages {
     use std::{borrow::Borrow, fmt::Display};
 
@@ -969,6 +1108,14 @@ mod messages {
             contract: ContractContainer,
             target: PeerKeyLocation,
         },
+        /// Push a replica of a contract to one of the closest peers to its location.
+        Replicate {
+            id: Transaction,
+            sender: PeerKeyLocation,
+            target: PeerKeyLocation,
+            contract: ContractContainer,
+            value: WrappedState,
+        },
     }
 
     impl InnerMessage for PutMsg {
@@ -981,6 +1128,7 @@ mod messages {
                 Self::PutForward { id, .. } => id,
                 Self::AwaitPut { id } => id,
                 Self::BroadcastTo { id, .. } => id,
+                Self::Replicate { id, .. } => id,
             }
         }
 
@@ -991,6 +1139,7 @@ mod messages {
                 Self::SuccessfulPut { target, .. } => Some(target),
                 Self::PutForward { target, .. } => Some(target),
                 Self::BroadcastTo { target, .. } => Some(target),
+                Self::Replicate { target, .. } => Some(target),
                 _ => None,
             }
         }
@@ -1002,6 +1151,7 @@ mod messages {
                 Self::Broadcasting { key, .. } => Some(Location::from(key.id())),
                 Self::PutForward { contract, .. } => Some(Location::from(contract.id())),
                 Self::BroadcastTo { key, .. } => Some(Location::from(key.id())),
+                Self::Replicate { contract, .. } => Some(Location::from(contract.id())),
                 _ => None,
             }
         }
@@ -1012,6 +1162,7 @@ mod messages {
             match self {
                 Self::SeekNode { sender, .. } => Some(sender),
                 Self::BroadcastTo { sender, .. } => Some(sender),
+                Self::Replicate { sender, .. } => Some(sender),
                 _ => None,
             }
         }
@@ -1028,6 +1179,7 @@ mod messages {
                 Self::PutForward { .. } => write!(f, "PutForward(id: {id})"),
                 Self::AwaitPut { .. } => write!(f, "AwaitPut(id: {id})"),
                 Self::BroadcastTo { .. } => write!(f, "BroadcastTo(id: {id})"),
+                Self::Replicate { .. } => write!(f, "Replicate(id: {id})"),
             }
         }
     }
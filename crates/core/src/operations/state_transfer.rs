@@ -0,0 +1,709 @@
+//! Transfer of large contract states in chunks, which resumes after a connection drop.
+//!
+//! States are otherwise sent whole within the messages of the operations, so a connection dropping
+//! while a large state is in flight loses all of it, and the operation sends it again from the
+//! start once retried. The messages returning the state found by a get and pushing replicas carry
+//! the states over [`CHUNK_SIZE`] through a transfer instead:
+//!
+//! 1. The sender offers the state, by its hash and size.
+//! 2. The receiver answers with the bytes of the state it already has, as their length and hash.
+//! 3. The sender sends the rest of the state in chunks if its bytes match those, or all of it
+//!    otherwise, followed by the message of the operation without its state.
+//! 4. The receiver checks the hash of the whole state and restores it into the message, which is
+//!    then processed like any other.
+//!
+//! Receivers keep the states partially received from each peer for a while, so the next transfer
+//! of the same state from that peer, either because the operation was retried or from another
+//! one, picks up where the previous one was interrupted. States over the maximum state size are
+//! refused, and the bytes reserved by the partial states of a peer, and of all of them, are
+//! bounded by a few times that size.
+
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use freenet_stdlib::prelude::WrappedState;
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+
+use super::{get::GetMsg, put::PutMsg};
+use crate::{
+    contract::StoreResponse,
+    message::{MessageStats, NetMessage, NetMessageV1, Transaction},
+};
+
+/// Size of the chunks states are sent in, states up to this size are sent whole.
+const CHUNK_SIZE: usize = 256 * 1024;
+/// Time a transfer waits for the receiver to answer the offer.
+const OFFER_TIMEOUT: Duration = Duration::from_secs(60);
+/// Time partially received states are kept without progress, to be resumed.
+const PARTIAL_TTL: Duration = Duration::from_secs(10 * 60);
+/// Partially received states kept at most, the least recently updated are dropped first.
+const MAX_PARTIALS: usize = 32;
+/// Maximum state sizes the partial states of a single peer reserve at most.
+const PEER_PARTIALS_BUDGET: u64 = 2;
+/// Maximum state sizes all the partial states reserve at most.
+const PARTIALS_BUDGET: u64 = 8;
+
+type StateHash = [u8; 32];
+
+fn hash(bytes: &[u8]) -> StateHash {
+    *blake3::hash(bytes).as_bytes()
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) enum StateTransferMsg {
+    /// Offers the state carried by a message of the transaction.
+    Offer {
+        id: Transaction,
+        transfer: Ulid,
+        hash: StateHash,
+        size: u64,
+    },
+    /// Answers an offer with the bytes of the state already received.
+    Resume {
+        id: Transaction,
+        transfer: Ulid,
+        received: u64,
+        prefix: StateHash,
+    },
+    Chunk {
+        id: Transaction,
+        transfer: Ulid,
+        offset: u64,
+        data: Vec<u8>,
+    },
+    /// Ends the transfer with the message carrying the state, sent without it.
+    Complete {
+        id: Transaction,
+        transfer: Ulid,
+        msg: CarrierMsg,
+    },
+}
+
+impl StateTransferMsg {
+    pub fn id(&self) -> &Transaction {
+        match self {
+            Self::Offer { id, .. }
+            | Self::Resume { id, .. }
+            | Self::Chunk { id, .. }
+            | Self::Complete { id, .. } => id,
+        }
+    }
+}
+
+impl From<StateTransferMsg> for NetMessage {
+    fn from(msg: StateTransferMsg) -> Self {
+        NetMessage::V1(NetMessageV1::StateTransfer(msg))
+    }
+}
+
+impl Display for StateTransferMsg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Offer { transfer, size, .. } => {
+                write!(f, "StateOffer(transfer: {transfer}, size: {size})")
+            }
+            Self::Resume {
+                transfer, received, ..
+            } => write!(f, "StateResume(transfer: {transfer}, received: {received})"),
+            Self::Chunk {
+                transfer,
+                offset,
+                data,
+                ..
+            } => write!(
+                f,
+                "StateChunk(transfer: {transfer}, offset: {offset}, size: {})",
+                data.len()
+            ),
+            Self::Complete { transfer, msg, .. } => {
+                write!(f, "StateComplete(transfer: {transfer}, {msg})")
+            }
+        }
+    }
+}
+
+/// Messages whose states can be transferred in chunks. Only operation messages are carried, so
+/// transfer messages never nest one another.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) enum CarrierMsg {
+    Get(GetMsg),
+    Put(PutMsg),
+}
+
+impl CarrierMsg {
+    /// The state carried by the message, if transferred in chunks.
+    fn state(&mut self) -> Option<&mut WrappedState> {
+        match self {
+            Self::Get(GetMsg::ReturnGet {
+                value: StoreResponse {
+                    state: Some(state), ..
+                },
+                ..
+            })
+            | Self::Put(PutMsg::Replicate { value: state, .. }) => Some(state),
+            _ => None,
+        }
+    }
+}
+
+impl From<CarrierMsg> for NetMessage {
+    fn from(msg: CarrierMsg) -> Self {
+        match msg {
+            CarrierMsg::Get(msg) => NetMessage::V1(NetMessageV1::Get(msg)),
+            CarrierMsg::Put(msg) => NetMessage::V1(NetMessageV1::Put(msg)),
+        }
+    }
+}
+
+impl Display for CarrierMsg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Get(msg) => msg.fmt(f),
+            Self::Put(msg) => msg.fmt(f),
+        }
+    }
+}
+
+/// Outcome of a transfer message.
+pub(crate) enum TransferEvent {
+    /// Answer to send back to the peer.
+    Reply(NetMessage),
+    /// State to send to the peer which answered the offer.
+    Stream(StateStream),
+    /// Message whose state was received, to be processed.
+    Deliver(Box<NetMessage>),
+    Nothing,
+}
+
+/// The rest of a state to send, followed by the message carrying it.
+pub(crate) struct StateStream {
+    id: Transaction,
+    transfer: Ulid,
+    msg: CarrierMsg,
+    state: WrappedState,
+    start: usize,
+}
+
+impl StateStream {
+    /// The messages of the transfer, the bytes of each chunk copied as it is sent.
+    pub fn into_msgs(self) -> impl Iterator<Item = NetMessage> + Send {
+        let Self {
+            id,
+            transfer,
+            msg,
+            state,
+            start,
+        } = self;
+        let size = state.size();
+        let chunks = (start..size).step_by(CHUNK_SIZE).map(move |offset| {
+            let bytes: &[u8] = state.as_ref();
+            StateTransferMsg::Chunk {
+                id,
+                transfer,
+                offset: offset as u64,
+                data: bytes[offset..size.min(offset + CHUNK_SIZE)].to_vec(),
+            }
+            .into()
+        });
+        chunks.chain(std::iter::once(
+            StateTransferMsg::Complete { id, transfer, msg }.into(),
+        ))
+    }
+}
+
+struct Outgoing {
+    id: Transaction,
+    target: SocketAddr,
+    /// Message carrying the state, without it.
+    msg: CarrierMsg,
+    state: WrappedState,
+    offered: Instant,
+}
+
+struct Partial {
+    size: u64,
+    data: Vec<u8>,
+    updated: Instant,
+}
+
+/// Transfers of the node, both the ones it sends and the ones it receives.
+pub(crate) struct StateTransfers {
+    max_state_size: u64,
+    outgoing: HashMap<Ulid, Outgoing>,
+    /// Hash of the state received through each transfer of a peer.
+    incoming: HashMap<(SocketAddr, Ulid), StateHash>,
+    partials: HashMap<(SocketAddr, StateHash), Partial>,
+}
+
+impl StateTransfers {
+    pub fn new(max_state_size: usize) -> Self {
+        Self {
+            max_state_size: max_state_size as u64,
+            outgoing: HashMap::new(),
+            incoming: HashMap::new(),
+            partials: HashMap::new(),
+        }
+    }
+
+    /// Starts a transfer if the message carries a state over the chunk size, returning the offer
+    /// to send to the target instead; otherwise returns the message as is.
+    pub fn start(&mut self, msg: NetMessage, target: SocketAddr) -> NetMessage {
+        let id = *msg.id();
+        let mut msg = match msg {
+            NetMessage::V1(NetMessageV1::Get(msg)) => CarrierMsg::Get(msg),
+            NetMessage::V1(NetMessageV1::Put(msg)) => CarrierMsg::Put(msg),
+            msg => return msg,
+        };
+        let Some(state) = msg.state().filter(|state| state.size() > CHUNK_SIZE) else {
+            return msg.into();
+        };
+        let state = std::mem::replace(state, WrappedState::new(vec![]));
+        self.outgoing
+            .retain(|_, transfer| transfer.offered.elapsed() < OFFER_TIMEOUT);
+        let transfer = Ulid::new();
+        let offer = StateTransferMsg::Offer {
+            id,
+            transfer,
+            hash: hash(state.as_ref()),
+            size: state.size() as u64,
+        };
+        self.outgoing.insert(
+            transfer,
+            Outgoing {
+                id,
+                target,
+                msg,
+                state,
+                offered: Instant::now(),
+            },
+        );
+        offer.into()
+    }
+
+    /// Handles a transfer message received from the peer at `from`.
+    pub fn receive(&mut self, msg: StateTransferMsg, from: SocketAddr) -> TransferEvent {
+        match msg {
+            StateTransferMsg::Offer {
+                id,
+                transfer,
+                hash: state_hash,
+                size,
+            } => {
+                if size > self.max_state_size {
+                    tracing::debug!(tx = %id, %transfer, %from, size, "Refusing state over the maximum state size");
+                    return TransferEvent::Nothing;
+                }
+                // a peer resumes a state through a single transfer at a time
+                self.incoming
+                    .retain(|(peer, _), other| *peer != from || *other != state_hash);
+                let partial = self.partial((from, state_hash), size);
+                let resume = StateTransferMsg::Resume {
+                    id,
+                    transfer,
+                    received: partial.data.len() as u64,
+                    prefix: hash(&partial.data),
+                };
+                self.incoming.insert((from, transfer), state_hash);
+                TransferEvent::Reply(resume.into())
+            }
+            StateTransferMsg::Resume {
+                transfer,
+                received,
+                prefix,
+                ..
+            } => {
+                // only the target of the transfer resumes it
+                if self.outgoing.get(&transfer).map(|outgoing| outgoing.target) != Some(from) {
+                    return TransferEvent::Nothing;
+                }
+                let Some(Outgoing { id, msg, state, .. }) = self.outgoing.remove(&transfer) else {
+                    return TransferEvent::Nothing;
+                };
+                let bytes: &[u8] = state.as_ref();
+                // a prefix which doesn't match is overwritten from the start
+                let start = usize::try_from(received)
+                    .ok()
+                    .filter(|&received| {
+                        received <= bytes.len() && hash(&bytes[..received]) == prefix
+                    })
+                    .unwrap_or(0);
+                tracing::debug!(tx = %id, %transfer, target = %from, start, size = bytes.len(), "Sending state");
+                TransferEvent::Stream(StateStream {
+                    id,
+                    transfer,
+                    msg,
+                    state,
+                    start,
+                })
+            }
+            StateTransferMsg::Chunk {
+                transfer,
+                offset,
+                data,
+                ..
+            } => {
+                let Some(partial) = self
+                    .incoming
+                    .get(&(from, transfer))
+                    .and_then(|state_hash| self.partials.get_mut(&(from, *state_hash)))
+                else {
+                    return TransferEvent::Nothing;
+                };
+                if offset == 0 {
+                    partial.data.clear();
+                }
+                // chunks arrive in order, so one which doesn't follow the received bytes means
+                // others were lost
+                if offset != partial.data.len() as u64 || offset + data.len() as u64 > partial.size
+                {
+                    tracing::debug!(%transfer, offset, received = partial.data.len(), "Unexpected state chunk");
+                    return TransferEvent::Nothing;
+                }
+                partial.data.extend_from_slice(&data);
+                partial.updated = Instant::now();
+                TransferEvent::Nothing
+            }
+            StateTransferMsg::Complete {
+                id,
+                transfer,
+                mut msg,
+            } => {
+                let Some(state_hash) = self.incoming.remove(&(from, transfer)) else {
+                    return TransferEvent::Nothing;
+                };
+                let Some(partial) = self.partials.remove(&(from, state_hash)) else {
+                    return TransferEvent::Nothing;
+                };
+                if (partial.data.len() as u64) < partial.size {
+                    tracing::debug!(tx = %id, %transfer, received = partial.data.len(), size = partial.size, "State transfer interrupted");
+                    self.partials.insert((from, state_hash), partial);
+                    return TransferEvent::Nothing;
+                }
+                if hash(&partial.data) != state_hash {
+                    tracing::warn!(tx = %id, %transfer, "Received state doesn't match its hash");
+                    return TransferEvent::Nothing;
+                }
+                let Some(state) = msg.state() else {
+                    tracing::warn!(tx = %id, %transfer, "State transferred for a message not carrying one");
+                    return TransferEvent::Nothing;
+                };
+                *state = WrappedState::new(partial.data);
+                TransferEvent::Deliver(Box::new(msg.into()))
+            }
+        }
+    }
+
+    /// The state partially received from a peer with the given hash, forgetting the stale ones
+    /// and making room for it within the budgets.
+    fn partial(&mut self, key: (SocketAddr, StateHash), size: u64) -> &mut Partial {
+        self.partials
+            .retain(|_, partial| partial.updated.elapsed() < PARTIAL_TTL);
+        if self
+            .partials
+            .get(&key)
+            .is_some_and(|partial| partial.size != size)
+        {
+            self.partials.remove(&key);
+        }
+        if !self.partials.contains_key(&key) {
+            let (peer, _) = key;
+            let peer_budget = self.max_state_size.saturating_mul(PEER_PARTIALS_BUDGET);
+            let budget = self.max_state_size.saturating_mul(PARTIALS_BUDGET);
+            loop {
+                let reserved: u64 = self.partials.values().map(|partial| partial.size).sum();
+                let peer_reserved: u64 = self
+                    .partials
+                    .iter()
+                    .filter(|((other, _), _)| *other == peer)
+                    .map(|(_, partial)| partial.size)
+                    .sum();
+                // a peer over its budget makes room with its own states
+                let over_peer_budget = peer_reserved + size > peer_budget;
+                if !over_peer_budget
+                    && reserved + size <= budget
+                    && self.partials.len() < MAX_PARTIALS
+                {
+                    break;
+                }
+                let oldest = self
+                    .partials
+                    .iter()
+                    .filter(|((other, _), _)| !over_peer_budget || *other == peer)
+                    .min_by_key(|(_, partial)| partial.updated)
+                    .map(|(key, _)| *key);
+                let Some(oldest) = oldest else {
+                    break;
+                };
+                self.partials.remove(&oldest);
+            }
+        }
+        let partials = &self.partials;
+        self.incoming
+            .retain(|(peer, _), state_hash| partials.contains_key(&(*peer, *state_hash)));
+        let partial = self.partials.entry(key).or_insert_with(|| Partial {
+            size,
+            data: vec![],
+            updated: Instant::now(),
+        });
+        partial.updated = Instant::now();
+        partial
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use freenet_stdlib::prelude::*;
+
+    use super::*;
+    use crate::ring::PeerKeyLocation;
+
+    const MAX_STATE_SIZE: usize = CHUNK_SIZE * 4;
+
+    fn addr(port: u16) -> SocketAddr {
+        ([10, 0, 0, 1], port).into()
+    }
+
+    fn transfer_msg(msg: NetMessage) -> StateTransferMsg {
+        match msg {
+            NetMessage::V1(NetMessageV1::StateTransfer(msg)) => msg,
+            other => panic!("unexpected message {other}"),
+        }
+    }
+
+    /// Starts the transfer of a replica of the state.
+    fn replicate(transfers: &mut StateTransfers, state: &[u8], target: SocketAddr) -> NetMessage {
+        let (sender, target_loc) = (PeerKeyLocation::random(), PeerKeyLocation::random());
+        let contract = WrappedContract::new(
+            Arc::new(ContractCode::from(vec![0])),
+            Parameters::from(vec![]),
+        );
+        let msg = PutMsg::Replicate {
+            id: Transaction::new::<PutMsg>(),
+            sender,
+            target: target_loc,
+            contract: ContractContainer::Wasm(ContractWasmAPIVersion::V1(contract)),
+            value: WrappedState::new(state.to_vec()),
+        };
+        transfers.start(msg.into(), target)
+    }
+
+    /// Has the receiver answer the offer and returns the messages the sender sends then.
+    fn answer(
+        offer: NetMessage,
+        (sender, sender_addr): (&mut StateTransfers, SocketAddr),
+        (receiver, receiver_addr): (&mut StateTransfers, SocketAddr),
+    ) -> Vec<StateTransferMsg> {
+        let TransferEvent::Reply(resume) = receiver.receive(transfer_msg(offer), sender_addr)
+        else {
+            panic!("offer not answered");
+        };
+        let TransferEvent::Stream(stream) = sender.receive(transfer_msg(resume), receiver_addr)
+        else {
+            panic!("state not sent");
+        };
+        stream.into_msgs().map(transfer_msg).collect()
+    }
+
+    /// Offers a replica of the state and returns the messages sent after the receiver answered.
+    fn offer(
+        (sender, sender_addr): (&mut StateTransfers, SocketAddr),
+        (receiver, receiver_addr): (&mut StateTransfers, SocketAddr),
+        state: &[u8],
+    ) -> Vec<StateTransferMsg> {
+        let offer = replicate(sender, state, receiver_addr);
+        answer(offer, (sender, sender_addr), (receiver, receiver_addr))
+    }
+
+    #[test]
+    fn resume_interrupted_transfer() {
+        let state: Vec<u8> = (0..CHUNK_SIZE * 5 / 2).map(|i| i as u8).collect();
+        let mut sender = StateTransfers::new(MAX_STATE_SIZE);
+        let mut receiver = StateTransfers::new(MAX_STATE_SIZE);
+        let (from, to) = (addr(1), addr(2));
+
+        let msgs = offer((&mut sender, from), (&mut receiver, to), &state);
+        assert_eq!(msgs.len(), 4);
+        // the connection drops after the first chunk
+        let first = msgs.into_iter().next().unwrap();
+        assert!(matches!(
+            receiver.receive(first, from),
+            TransferEvent::Nothing
+        ));
+
+        let mut msgs = offer((&mut sender, from), (&mut receiver, to), &state);
+        assert_eq!(msgs.len(), 3);
+        assert!(matches!(
+            msgs[0],
+            StateTransferMsg::Chunk { offset, .. } if offset == CHUNK_SIZE as u64
+        ));
+        let complete = msgs.pop().unwrap();
+        for chunk in msgs {
+            assert!(matches!(
+                receiver.receive(chunk, from),
+                TransferEvent::Nothing
+            ));
+        }
+        let TransferEvent::Deliver(msg) = receiver.receive(complete, from) else {
+            panic!("state not delivered");
+        };
+        let NetMessage::V1(NetMessageV1::Put(PutMsg::Replicate { value, .. })) = *msg else {
+            panic!("unexpected message {msg}");
+        };
+        assert_eq!(value.as_ref(), state.as_slice());
+
+        // small states are sent whole
+        assert!(matches!(
+            replicate(&mut sender, &[1; 16], to),
+            NetMessage::V1(NetMessageV1::Put(PutMsg::Replicate { .. }))
+        ));
+    }
+
+    #[test]
+    fn transfer_returned_gets() {
+        let state: Vec<u8> = (0..CHUNK_SIZE * 3 / 2).map(|i| (i / 7) as u8).collect();
+        let mut sender = StateTransfers::new(MAX_STATE_SIZE);
+        let mut receiver = StateTransfers::new(MAX_STATE_SIZE);
+        let (from, to) = (addr(1), addr(2));
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let msg = GetMsg::ReturnGet {
+            id: Transaction::new::<GetMsg>(),
+            key,
+            value: StoreResponse {
+                state: Some(WrappedState::new(state.clone())),
+                contract: None,
+            },
+            sender: PeerKeyLocation::random(),
+            target: PeerKeyLocation::random(),
+            skip_list: Default::default(),
+        };
+
+        let offer = sender.start(msg.into(), to);
+        let mut msgs = answer(offer, (&mut sender, from), (&mut receiver, to));
+        assert_eq!(msgs.len(), 3);
+        let complete = msgs.pop().unwrap();
+        for chunk in msgs {
+            receiver.receive(chunk, from);
+        }
+        let TransferEvent::Deliver(msg) = receiver.receive(complete, from) else {
+            panic!("state not delivered");
+        };
+        let NetMessage::V1(NetMessageV1::Get(GetMsg::ReturnGet {
+            key: returned,
+            value: StoreResponse {
+                state: Some(value), ..
+            },
+            ..
+        })) = *msg
+        else {
+            panic!("unexpected message {msg}");
+        };
+        assert_eq!(returned, key);
+        assert_eq!(value.as_ref(), state.as_slice());
+
+        // messages not carrying a state, or not of an operation, are sent as they are
+        let probe = GetMsg::Probe {
+            id: Transaction::new::<GetMsg>(),
+            key,
+            sender: PeerKeyLocation::random(),
+            target: PeerKeyLocation::random(),
+        };
+        assert!(matches!(
+            sender.start(probe.into(), to),
+            NetMessage::V1(NetMessageV1::Get(GetMsg::Probe { .. }))
+        ));
+    }
+
+    #[test]
+    fn isolate_and_bound_transfers_of_peers() {
+        let state: Vec<u8> = (0..CHUNK_SIZE * 2).map(|i| i as u8).collect();
+        let mut sender = StateTransfers::new(MAX_STATE_SIZE);
+        let mut receiver = StateTransfers::new(MAX_STATE_SIZE);
+        let (from, to, other) = (addr(1), addr(2), addr(3));
+
+        // states over the maximum size are refused
+        let oversized = StateTransferMsg::Offer {
+            id: Transaction::new::<PutMsg>(),
+            transfer: Ulid::new(),
+            hash: [0; 32],
+            size: u64::MAX,
+        };
+        assert!(matches!(
+            receiver.receive(oversized, other),
+            TransferEvent::Nothing
+        ));
+        assert!(receiver.partials.is_empty());
+
+        // only the target resumes a transfer
+        let offer = transfer_msg(replicate(&mut sender, &state, to));
+        let TransferEvent::Reply(resume) = receiver.receive(offer, from) else {
+            panic!("offer not answered");
+        };
+        let resume = transfer_msg(resume);
+        let StateTransferMsg::Resume { transfer, .. } = &resume else {
+            panic!("unexpected message {resume}");
+        };
+        let transfer = *transfer;
+        assert!(matches!(
+            sender.receive(
+                StateTransferMsg::Resume {
+                    id: *resume.id(),
+                    transfer,
+                    received: 0,
+                    prefix: hash(&[]),
+                },
+                other
+            ),
+            TransferEvent::Nothing
+        ));
+        let TransferEvent::Stream(stream) = sender.receive(resume, to) else {
+            panic!("state not sent");
+        };
+        let mut msgs = stream.into_msgs().map(transfer_msg);
+        receiver.receive(msgs.next().unwrap(), from);
+
+        // other peers can't reset nor fill the state received from a peer
+        let restart = StateTransferMsg::Chunk {
+            id: Transaction::new::<PutMsg>(),
+            transfer,
+            offset: 0,
+            data: vec![0; 16],
+        };
+        receiver.receive(restart, other);
+        let received = |receiver: &StateTransfers| {
+            receiver
+                .partials
+                .get(&(from, hash(&state)))
+                .map(|partial| partial.data.len())
+        };
+        assert_eq!(received(&receiver), Some(CHUNK_SIZE));
+        for msg in msgs {
+            receiver.receive(msg, from);
+        }
+        assert_eq!(received(&receiver), None);
+
+        // a peer making room for its states drops its own oldest ones
+        let other_state = vec![7; CHUNK_SIZE * 3];
+        offer((&mut sender, other), (&mut receiver, to), &other_state);
+        for i in 0..3u8 {
+            let state = vec![i; MAX_STATE_SIZE];
+            offer((&mut sender, from), (&mut receiver, to), &state);
+        }
+        let reserved = |peer| {
+            receiver
+                .partials
+                .iter()
+                .filter(|((owner, _), _)| *owner == peer)
+                .map(|(_, partial)| partial.size)
+                .sum::<u64>()
+        };
+        assert_eq!(reserved(from), (MAX_STATE_SIZE * 2) as u64);
+        assert_eq!(reserved(other), (CHUNK_SIZE * 3) as u64);
+    }
+}
@@ -397,6 +397,8 @@ impl Operation for SubscribeOp {
                             return Err(OpError::UnexpectedOpState);
                         }
 
+                        op_manager.ring.set_upstream(*key, sender.clone());
+                        op_manager.health.record_sync(*key);
                         new_state = Some(SubscribeState::Completed { key: *key });
                         if let Some(upstream_subscriber) = upstream_subscriber {
                             return_msg = Some(SubscribeMsg::ReturnSub {
@@ -1,4 +1,5 @@
 // TODO: complete update logic in the network
+use either::Either;
 use freenet_stdlib::client_api::{ErrorKind, HostResponse};
 use freenet_stdlib::prelude::*;
 
@@ -7,12 +8,17 @@ use crate::contract::ContractHandlerEvent;
 use crate::message::{InnerMessage, NetMessage, Transaction};
 use crate::ring::{Location, PeerKeyLocation, RingError};
 use crate::{
-    client_events::HostResult,
+    client_events::{limits::Payload, HostResult},
     node::{NetworkBridge, OpManager, PeerId},
 };
 
 pub(crate) use self::messages::UpdateMsg;
 
+/// Contracts compared with the neighbors at each anti-entropy round.
+const ANTI_ENTROPY_SAMPLE: usize = 8;
+/// Neighbors closest to a contract location its state is compared with.
+const ANTI_ENTROPY_PEERS: usize = 2;
+
 pub(crate) struct UpdateOp {
     pub id: Transaction,
     pub(crate) state: Option<UpdateState>,
@@ -309,9 +315,7 @@ impl Operation for UpdateOp {
                         "Successfully broadcasted update contract {key} to {broadcasted_to} peers - Broadcasting"
                     );
 
-                    let raw_state = State::from(new_value.clone());
-
-                    let summary = StateSummary::from(raw_state.into_bytes());
+                    let summary = state_summary(op_manager, *key, new_value).await;
 
                     // Subscriber nodes have been notified of the change, the operation is complete
                     return_msg = Some(UpdateMsg::SuccessfulUpdate {
@@ -357,6 +361,65 @@ impl Operation for UpdateOp {
                         }
                     };
                 }
+                UpdateMsg::SyncSummary {
+                    id,
+                    key,
+                    summary,
+                    sender,
+                    ..
+                } => {
+                    let delta = match op_manager
+                        .notify_contract_handler(ContractHandlerEvent::DeltaQuery {
+                            key: *key,
+                            summary: summary.clone(),
+                        })
+                        .await?
+                    {
+                        ContractHandlerEvent::DeltaResponse { delta, .. } => delta?,
+                        _ => return Err(OpError::UnexpectedOpState),
+                    };
+                    return_msg = delta.map(|delta| {
+                        tracing::debug!(tx = %id, %key, peer = %sender.peer, "Sending missing changes to peer");
+                        UpdateMsg::SyncDelta {
+                            id: *id,
+                            key: *key,
+                            delta,
+                            sender: op_manager.ring.connection_manager.own_location(),
+                            target: sender.clone(),
+                        }
+                    });
+                    new_state = None;
+                }
+                UpdateMsg::SyncDelta {
+                    id,
+                    key,
+                    delta,
+                    sender,
+                    ..
+                } => {
+                    tracing::debug!(tx = %id, %key, peer = %sender.peer, "Applying missing changes from peer");
+                    match op_manager
+                        .notify_contract_handler(ContractHandlerEvent::UpdateQuery {
+                            key: *key,
+                            update: Either::Right(delta.clone()),
+                            related_contracts: RelatedContracts::default(),
+                        })
+                        .await?
+                    {
+                        ContractHandlerEvent::UpdateResponse { new_value: Ok(_) } => {
+                            tracing::info!(tx = %id, %key, "Repaired diverged contract state");
+                            op_manager.health.record_sync(*key);
+                        }
+                        ContractHandlerEvent::UpdateResponse {
+                            new_value: Err(err),
+                        } => {
+                            tracing::warn!(tx = %id, %key, %err, "Failed applying missing changes");
+                        }
+                        _ => {}
+                    }
+                    new_state = None;
+                    return_msg = None;
+                }
                 _ => return Err(OpError::UnexpectedOpState),
             }
 
@@ -365,6 +428,27 @@ impl Operation for UpdateOp {
     }
 }
 
+/// Summary of the new state of a contract, sent back upstream once the update is done.
+///
+/// Falls back to the whole state if the contract can't summarize it, at the cost of a copy of
+/// the state carried by every hop back to the requester.
+async fn state_summary(
+    op_manager: &OpManager,
+    key: ContractKey,
+    state: &WrappedState,
+) -> StateSummary<'static> {
+    match op_manager
+        .notify_contract_handler(ContractHandlerEvent::SummaryQuery { key })
+        .await
+    {
+        Ok(ContractHandlerEvent::SummaryResponse {
+            summary: Ok(Some(summary)),
+            ..
+        }) => summary,
+        _ => StateSummary::from(state.as_ref().to_vec()),
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 async fn try_to_broadcast(
     id: Transaction,
@@ -426,9 +510,7 @@ async fn try_to_broadcast(
                     .await?;
                 return Err(OpError::StatePushed);
             } else {
-                let raw_state = State::from(new_value);
-
-                let summary = StateSummary::from(raw_state.into_bytes());
+                let summary = state_summary(op_manager, key, &new_value).await;
 
                 new_state = None;
                 return_msg = Some(UpdateMsg::SuccessfulUpdate {
@@ -484,29 +566,90 @@ fn build_op_result(
     })
 }
 
+/// Periodically compares the state of a sample of the locally cached contracts with the
+/// neighbors closest to their location, pulling any changes missing in this node.
+///
+/// Protects against states silently diverging when update broadcasts are lost. Neighbors run
+/// the same task, so changes only this node has are eventually pulled by them.
+pub(crate) async fn anti_entropy<CB>(
+    op_manager: std::sync::Arc<OpManager>,
+    conn_manager: CB,
+    interval: std::time::Duration,
+) where
+    CB: NetworkBridge,
+{
+    use rand::seq::IteratorRandom;
+
+    let mut interval = tokio::time::interval(interval);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let sample = op_manager
+            .ring
+            .seeded_contracts()
+            .into_iter()
+            .choose_multiple(&mut rand::thread_rng(), ANTI_ENTROPY_SAMPLE);
+        for key in sample {
+            let summary = match op_manager
+                .notify_contract_handler(ContractHandlerEvent::SummaryQuery { key })
+                .await
+            {
+                Ok(ContractHandlerEvent::SummaryResponse {
+                    summary: Ok(Some(summary)),
+                    ..
+                }) => summary,
+                _ => continue,
+            };
+            let sender = op_manager.ring.connection_manager.own_location();
+            for peer in op_manager
+                .ring
+                .k_closest_peers(Location::from(&key), ANTI_ENTROPY_PEERS)
+            {
+                let msg = UpdateMsg::SyncSummary {
+                    id: Transaction::new::<UpdateMsg>(),
+                    key,
+                    summary: summary.clone(),
+                    sender: sender.clone(),
+                    target: peer.clone(),
+                };
+                if let Err(error) = conn_manager.send(&peer.peer, msg.into()).await {
+                    tracing::debug!(%key, peer = %peer.peer, %error, "Failed sending state summary");
+                }
+            }
+        }
+    }
+}
+
 async fn update_contract(
     op_manager: &OpManager,
     key: ContractKey,
     state: WrappedState,
     related_contracts: RelatedContracts<'static>,
 ) -> Result<WrappedState, OpError> {
-    let update_data = UpdateData::State(State::from(state));
+    op_manager.limits.check(Payload::State, state.size())?;
+    super::check_stores_contract(op_manager, key).await?;
     match op_manager
         .notify_contract_handler(ContractHandlerEvent::UpdateQuery {
             key,
-            data: update_data,
+            update: Either::Left(state),
             related_contracts,
         })
         .await
     {
         Ok(ContractHandlerEvent::UpdateResponse {
             new_value: Ok(new_val),
-        }) => Ok(new_val),
+        }) => {
+            op_manager.health.record_sync(key);
+            Ok(new_val)
+        }
         Ok(ContractHandlerEvent::UpdateResponse {
-            new_value: Err(_rr),
+            new_value: Err(err),
         }) => {
-            tracing::error!("Failed to update contract value");
-            Err(OpError::UnexpectedOpState)
+            match err.policy_refusal() {
+                Some(refusal) => tracing::debug!(%key, "{refusal}"),
+                None => tracing::error!(%key, "Failed to update contract value: {err}"),
+            }
+            Err(OpError::from(err))
         }
         Err(err) => Err(err.into()),
         Ok(_) => Err(OpError::UnexpectedOpState),
@@ -617,7 +760,9 @@ pub(crate) async fn request_update(
 mod messages {
     use std::{borrow::Borrow, fmt::Display};
 
-    use freenet_stdlib::prelude::{ContractKey, RelatedContracts, StateSummary, WrappedState};
+    use freenet_stdlib::prelude::{
+        ContractKey, RelatedContracts, StateDelta, StateSummary, WrappedState,
+    };
     use serde::{Deserialize, Serialize};
 
     use crate::{
@@ -672,6 +817,24 @@ mod messages {
             new_value: WrappedState,
             target: PeerKeyLocation,
         },
+        /// Summary of the local state of a contract, the target answers with the missing changes.
+        SyncSummary {
+            id: Transaction,
+            sender: PeerKeyLocation,
+            target: PeerKeyLocation,
+            key: ContractKey,
+            #[serde(deserialize_with = "StateSummary::deser_state_summary")]
+            summary: StateSummary<'static>,
+        },
+        /// Changes missing from a summarized state.
+        SyncDelta {
+            id: Transaction,
+            sender: PeerKeyLocation,
+            target: PeerKeyLocation,
+            key: ContractKey,
+            #[serde(deserialize_with = "StateDelta::deser_state_delta")]
+            delta: StateDelta<'static>,
+        },
     }
 
     impl InnerMessage for UpdateMsg {
@@ -683,6 +846,8 @@ mod messages {
                 UpdateMsg::SeekNode { id, .. } => id,
                 UpdateMsg::Broadcasting { id, .. } => id,
                 UpdateMsg::BroadcastTo { id, .. } => id,
+                UpdateMsg::SyncSummary { id, .. } => id,
+                UpdateMsg::SyncDelta { id, .. } => id,
             }
         }
 
@@ -692,6 +857,8 @@ mod messages {
                 UpdateMsg::SuccessfulUpdate { target, .. } => Some(target),
                 UpdateMsg::SeekNode { target, .. } => Some(target),
                 UpdateMsg::BroadcastTo { target, .. } => Some(target),
+                UpdateMsg::SyncSummary { target, .. } => Some(target),
+                UpdateMsg::SyncDelta { target, .. } => Some(target),
                 _ => None,
             }
         }
@@ -702,6 +869,8 @@ mod messages {
                 UpdateMsg::SeekNode { key, .. } => Some(Location::from(key.id())),
                 UpdateMsg::Broadcasting { key, .. } => Some(Location::from(key.id())),
                 UpdateMsg::BroadcastTo { key, .. } => Some(Location::from(key.id())),
+                UpdateMsg::SyncSummary { key, .. } => Some(Location::from(key.id())),
+                UpdateMsg::SyncDelta { key, .. } => Some(Location::from(key.id())),
                 _ => None,
             }
         }
@@ -712,6 +881,8 @@ mod messages {
             match self {
                 Self::SeekNode { sender, .. } => Some(sender),
                 Self::BroadcastTo { sender, .. } => Some(sender),
+                Self::SyncSummary { sender, .. } => Some(sender),
+                Self::SyncDelta { sender, .. } => Some(sender),
                 _ => None,
             }
         }
@@ -726,6 +897,8 @@ mod messages {
                 UpdateMsg::SeekNode { id, .. } => write!(f, "SeekNode(id: {id})"),
                 UpdateMsg::Broadcasting { id, .. } => write!(f, "Broadcasting(id: {id})"),
                 UpdateMsg::BroadcastTo { id, .. } => write!(f, "BroadcastTo(id: {id})"),
+                UpdateMsg::SyncSummary { id, .. } => write!(f, "SyncSummary(id: {id})"),
+                UpdateMsg::SyncDelta { id, .. } => write!(f, "SyncDelta(id: {id})"),
             }
         }
     }
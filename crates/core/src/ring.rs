@@ -31,7 +31,7 @@ use crate::tracing::{NetEventLog, NetEventRegister};
 use crate::transport::TransportPublicKey;
 use crate::util::Contains;
 use crate::{
-    config::GlobalExecutor,
+    config::{GlobalExecutor, NodeRole},
     message::Transaction,
     node::{self, EventLoopNotificationsSender, NodeConfig, PeerId},
     operations::connect,
@@ -41,9 +41,13 @@ use crate::{
 mod connection_manager;
 pub(crate) use connection_manager::ConnectionManager;
 mod connection;
+mod dials;
 mod live_tx;
 mod location;
 mod peer_key_location;
+mod replication;
+mod reputation;
+mod rtt;
 mod score;
 mod seeding;
 
@@ -51,8 +55,12 @@ use self::score::Score;
 
 pub use self::live_tx::LiveTransactionTracker;
 pub use connection::Connection;
+pub(crate) use dials::DialScheduler;
 pub use location::{Distance, Location};
 pub use peer_key_location::PeerKeyLocation;
+pub(crate) use replication::ReplicationManager;
+pub use replication::{DEFAULT_REPLICATION_FACTOR, MAX_REPLICATION_FACTOR};
+pub(crate) use reputation::{Ban, Misbehavior, PeerReputation};
 
 /// Thread safe and friendly data structure to keep track of the local knowledge
 /// of the state of the ring.
@@ -65,26 +73,25 @@ pub(crate) struct Ring {
     pub connection_manager: ConnectionManager,
     pub router: Arc<RwLock<Router>>,
     pub live_tx_tracker: LiveTransactionTracker,
+    pub reputation: Arc<PeerReputation>,
+    /// Backoff of the outbound dials to gateways.
+    pub dials: DialScheduler,
+    pub replication: ReplicationManager,
     seeding_manager: seeding::SeedingManager,
     event_register: Box<dyn NetEventRegister>,
-    /// Whether this peer is a gateway or not. This will affect behavior of the node when acquiring
-    /// and dropping connections.
-    #[allow(unused)]
-    is_gateway: bool,
+    /// Part this peer plays in the network. This will affect behavior of the node when acquiring
+    /// and dropping connections, and which contracts it stores.
+    role: NodeRole,
 }
 
-// /// A data type that represents the fact that a peer has been blacklisted
-// /// for some action. Has to be coupled with that action
-// #[derive(Debug)]
-// struct Blacklisted {
-//     since: Instant,
-//     peer: PeerKey,
-// }
-
 impl Ring {
-    const DEFAULT_MIN_CONNECTIONS: usize = 25;
+    pub(crate) const DEFAULT_MIN_CONNECTIONS: usize = 25;
+
+    pub(crate) const DEFAULT_MAX_CONNECTIONS: usize = 200;
+
+    pub(crate) const LIGHT_MIN_CONNECTIONS: usize = 1;
 
-    const DEFAULT_MAX_CONNECTIONS: usize = 200;
+    pub(crate) const LIGHT_MAX_CONNECTIONS: usize = 3;
 
     const DEFAULT_MAX_UPSTREAM_BANDWIDTH: Rate = Rate::new_per_second(1_000_000.0);
 
@@ -100,7 +107,7 @@ impl Ring {
         config: &NodeConfig,
         event_loop_notifier: EventLoopNotificationsSender,
         event_register: ER,
-        is_gateway: bool,
+        role: NodeRole,
         connection_manager: ConnectionManager,
     ) -> anyhow::Result<Arc<Self>> {
         let (live_tx_tracker, missing_candidate_rx) = LiveTransactionTracker::new();
@@ -121,12 +128,17 @@ impl Ring {
             connection_manager,
             seeding_manager: seeding::SeedingManager::new(),
             live_tx_tracker: live_tx_tracker.clone(),
+            reputation: Arc::new(PeerReputation::new(Some(
+                config.config.config_dir().join("peer-bans.json"),
+            ))),
+            dials: DialScheduler::new(Some(config.config.config_dir().join("bad-addresses.json"))),
+            replication: ReplicationManager::new(config.config.replication_factor),
             event_register: Box::new(event_register),
-            is_gateway,
+            role,
         };
 
         if let Some(loc) = config.location {
-            if config.peer_id.is_none() && is_gateway {
+            if config.peer_id.is_none() && role.is_gateway() {
                 return Err(anyhow::anyhow!("PeerId is required for gateways"));
             }
             ring.connection_manager.update_location(Some(loc));
@@ -150,7 +162,11 @@ impl Ring {
     }
 
     pub fn is_gateway(&self) -> bool {
-        self.is_gateway
+        self.role.is_gateway()
+    }
+
+    pub fn role(&self) -> NodeRole {
+        self.role
     }
 
     pub fn open_connections(&self) -> usize {
@@ -179,6 +195,9 @@ impl Ring {
 
     /// Return if a contract is within appropiate seeding distance.
     pub fn should_seed(&self, key: &ContractKey) -> bool {
+        if !self.role.serves_network() {
+            return false;
+        }
         let own_loc = self
             .connection_manager
             .own_location()
@@ -217,6 +236,7 @@ impl Ring {
 
     pub async fn add_connection(&self, loc: Location, peer: PeerId, was_reserved: bool) {
         tracing::info!(%peer, this = ?self.connection_manager.get_peer_key(), %was_reserved, "Adding connection to peer");
+        self.dials.succeeded(&peer.addr);
         self.connection_manager
             .add_connection(loc, peer.clone(), was_reserved);
         self.event_register
@@ -282,6 +302,32 @@ impl Ring {
         self.seeding_manager.subscribers_of(contract)
     }
 
+    /// Contracts with subscribers, including the upstream subscription of this node.
+    pub fn subscribed_contracts(&self) -> Vec<ContractKey> {
+        self.seeding_manager.subscribed_contracts()
+    }
+
+    /// Records the peer this node subscribed to the contract through.
+    pub fn set_upstream(&self, contract: ContractKey, provider: PeerKeyLocation) {
+        self.seeding_manager.set_upstream(contract, provider)
+    }
+
+    /// Contracts this node is subscribed to through another peer, as opposed to those it only
+    /// has subscribers for.
+    pub fn upstream_subscriptions(&self) -> Vec<ContractKey> {
+        self.seeding_manager.upstream_subscriptions()
+    }
+
+    /// Stops seeding a contract evicted from the store and records the eviction.
+    pub async fn contract_evicted(&self, key: ContractKey, size: u64) {
+        self.seeding_manager.stop_seeding(&key);
+        if let Some(event) = NetEventLog::evicted(self, key, size) {
+            self.event_register
+                .register_events(Either::Left(event))
+                .await;
+        }
+    }
+
     pub async fn prune_connection(&self, peer: PeerId) {
         tracing::debug!(%peer, "Removing connection");
         self.live_tx_tracker.prune_transactions_from_peer(&peer);
@@ -291,6 +337,7 @@ impl Ring {
         };
         {
             self.seeding_manager.prune_subscriber(loc);
+            self.replication.prune_holder(&peer);
         }
         self.event_register
             .register_events(Either::Left(NetEventLog::disconnected(self, &peer)))
@@ -311,9 +358,10 @@ impl Ring {
             })
             .find_map(|(_, conns)| {
                 for _ in 0..conns.len() {
-                    let conn = conns.choose(&mut rand::thread_rng()).unwrap();
-                    let selected =
-                        (!skip_list.contains(&conn.location.peer)).then_some(conn.location.clone());
+                    let conn = node::clock::with_rng(|rng| conns.choose(rng)).unwrap();
+                    let selected = (!skip_list.contains(&conn.location.peer)
+                        && self.connection_manager.serves_network(&conn.location.peer))
+                    .then_some(conn.location.clone());
                     if selected.is_some() {
                         return selected;
                     }
@@ -322,6 +370,76 @@ impl Ring {
             })
     }
 
+    /// Peers the contract should be replicated to, so that it is cached by `factor` peers
+    /// including this one.
+    ///
+    /// Only the peers among the `factor` closest to the contract location, as far as this peer
+    /// knows, push replicas; any other peer returns no targets.
+    pub fn replica_targets(
+        &self,
+        key: &ContractKey,
+        factor: usize,
+        skip_list: &HashSet<PeerId>,
+    ) -> Vec<PeerKeyLocation> {
+        let Some(own_loc) = self.connection_manager.own_location().location else {
+            return vec![];
+        };
+        let location = Location::from(key);
+        let own_distance = own_loc.distance(location);
+        let by_distance = self.connections_by_distance(location);
+        let closer = by_distance
+            .iter()
+            .filter(|(loc, _)| loc.distance(location) < own_distance)
+            .count();
+        if closer >= factor {
+            return vec![];
+        }
+        let holders = self.replication.holders(key);
+        let missing = (factor - 1).saturating_sub(holders.len());
+        by_distance
+            .into_iter()
+            .map(|(_, peer)| peer)
+            .filter(|peer| !holders.contains(&peer.peer) && !skip_list.contains(&peer.peer))
+            .take(missing)
+            .collect()
+    }
+
+    /// The `k` connected peers closest to the given location.
+    pub fn k_closest_peers(&self, location: Location, k: usize) -> Vec<PeerKeyLocation> {
+        self.connections_by_distance(location)
+            .into_iter()
+            .map(|(_, peer)| peer)
+            .take(k)
+            .collect()
+    }
+
+    /// Peers this node is connected to.
+    pub fn connections(&self) -> Vec<PeerKeyLocation> {
+        self.connection_manager
+            .get_connections_by_location()
+            .into_values()
+            .flatten()
+            .map(|conn| conn.location)
+            .collect()
+    }
+
+    /// Contracts this node is seeding.
+    pub fn seeded_contracts(&self) -> Vec<ContractKey> {
+        self.seeding_manager.seeded_contracts()
+    }
+
+    fn connections_by_distance(&self, location: Location) -> Vec<(Location, PeerKeyLocation)> {
+        self.connection_manager
+            .get_connections_by_location()
+            .into_iter()
+            .sorted_by(|(loc_a, _), (loc_b, _)| {
+                loc_a.distance(location).cmp(&loc_b.distance(location))
+            })
+            .flat_map(|(loc, conns)| conns.into_iter().map(move |conn| (loc, conn.location)))
+            .filter(|(_, peer)| self.connection_manager.serves_network(&peer.peer))
+            .collect()
+    }
+
     async fn connection_maintenance(
         self: Arc<Self>,
         notifier: EventLoopNotificationsSender,
@@ -409,6 +527,20 @@ impl Ring {
                 }
             }
 
+            if !self.role.serves_network() {
+                // light nodes don't shape their neighbourhood, they only replace the connections
+                // they lost, up to their few allowed ones
+                if pending_conn_adds.is_empty()
+                    && self.open_connections() < self.connection_manager.max_connections()
+                {
+                    if let Some(own_loc) = self.connection_manager.own_location().location {
+                        pending_conn_adds.insert(own_loc);
+                    }
+                }
+                check_interval.tick().await;
+                continue;
+            }
+
             let neighbor_locations = {
                 let peers = self.connection_manager.get_connections_by_location();
                 peers
@@ -508,7 +640,10 @@ impl Ring {
             skip_list = ?new_skip_list,
             "Adding new connections"
         );
-        let missing_connections = self.connection_manager.max_connections - self.open_connections();
+        let missing_connections = self
+            .connection_manager
+            .max_connections()
+            .saturating_sub(self.open_connections());
         let id = Transaction::new::<connect::ConnectMsg>();
         live_tx_tracker.add_transaction(query_target.peer.clone(), id);
         let msg = connect::ConnectMsg::Request {
@@ -518,6 +653,8 @@ impl Ring {
                 query_target,
                 ideal_location,
                 joiner,
+                joiner_addresses: self.connection_manager.advertised_addrs(),
+                joiner_role: self.connection_manager.role(),
                 max_hops_to_live: missing_connections,
                 skip_connections: new_skip_list,
                 skip_forwards: HashSet::new(),
@@ -538,4 +675,6 @@ pub(crate) enum RingError {
     NoCachingPeers(ContractKey),
     #[error("No location assigned to this peer")]
     NoLocation,
+    #[error("Light nodes don't store contract {0} for the network")]
+    NotServingNetwork(ContractKey),
 }
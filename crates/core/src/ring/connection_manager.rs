@@ -2,7 +2,7 @@ use parking_lot::Mutex;
 
 use crate::topology::{Limits, TopologyManager};
 
-use super::*;
+use super::{rtt::RttEstimate, *};
 
 #[derive(Clone)]
 pub(crate) struct ConnectionManager {
@@ -15,12 +15,23 @@ pub(crate) struct ConnectionManager {
     /// Is important to keep track of this so no more connections are accepted prematurely.
     own_location: Arc<AtomicU64>,
     peer_key: Arc<Mutex<Option<PeerId>>>,
-    pub min_connections: usize,
-    pub max_connections: usize,
+    min_connections: Arc<AtomicUsize>,
+    max_connections: Arc<AtomicUsize>,
+    /// Response times of the connected peers.
+    response_times: Arc<RwLock<BTreeMap<PeerId, RttEstimate>>>,
+    /// Connected peers which joined as light nodes, never routed to.
+    light_peers: Arc<RwLock<HashSet<PeerId>>>,
+    /// Role of this peer, advertised when joining the network.
+    role: NodeRole,
     pub rnd_if_htl_above: usize,
     pub pub_key: Arc<TransportPublicKey>,
+    /// Local addresses the network listener is bound to.
+    listener_addrs: Arc<[SocketAddr]>,
 }
 
+/// Addresses taken into account from the ones advertised by a remote peer.
+const MAX_ADVERTISED_ADDRS: usize = 8;
+
 #[cfg(test)]
 impl ConnectionManager {
     pub fn default_with_key(pub_key: TransportPublicKey) -> Self {
@@ -43,6 +54,7 @@ impl ConnectionManager {
                     Location::random().as_f64().to_le_bytes(),
                 )),
             ),
+            vec![],
         )
     }
 }
@@ -90,7 +102,7 @@ impl ConnectionManager {
             AtomicU64::new(u64::from_le_bytes((-1f64).to_le_bytes()))
         };
 
-        Self::init(
+        let mut manager = Self::init(
             max_upstream_bandwidth,
             max_downstream_bandwidth,
             min_connections,
@@ -101,7 +113,10 @@ impl ConnectionManager {
                 config.peer_id.clone(),
                 own_location,
             ),
-        )
+            config.listener_addrs(),
+        );
+        manager.role = config.role;
+        manager
     }
 
     fn init(
@@ -111,6 +126,7 @@ impl ConnectionManager {
         max_connections: usize,
         rnd_if_htl_above: usize,
         (pub_key, peer_id, own_location): (TransportPublicKey, Option<PeerId>, AtomicU64),
+        listener_addrs: Vec<SocketAddr>,
     ) -> Self {
         let topology_manager = Arc::new(RwLock::new(TopologyManager::new(Limits {
             max_upstream_bandwidth,
@@ -127,13 +143,135 @@ impl ConnectionManager {
             topology_manager,
             own_location: own_location.into(),
             peer_key: Arc::new(Mutex::new(peer_id)),
-            min_connections,
-            max_connections,
+            min_connections: Arc::new(AtomicUsize::new(min_connections)),
+            max_connections: Arc::new(AtomicUsize::new(max_connections)),
+            response_times: Arc::new(RwLock::new(BTreeMap::new())),
+            light_peers: Arc::new(RwLock::new(HashSet::new())),
+            role: NodeRole::default(),
             rnd_if_htl_above,
             pub_key: Arc::new(pub_key),
+            listener_addrs: listener_addrs.into(),
+        }
+    }
+
+    /// Addresses this peer can be reached at besides the one remote peers see it connecting
+    /// from, advertised when joining the network.
+    ///
+    /// Only the addresses the listener is explicitly bound to are known to be reachable,
+    /// wildcard and loopback addresses are never advertised.
+    pub fn advertised_addrs(&self) -> Vec<SocketAddr> {
+        let own_addr = self.get_peer_key().map(|peer| peer.addr);
+        self.listener_addrs
+            .iter()
+            .filter(|addr| {
+                !addr.ip().is_unspecified() && !addr.ip().is_loopback() && Some(**addr) != own_addr
+            })
+            .copied()
+            .collect()
+    }
+
+    /// Returns the peer id through which a remote peer is best dialed from this peer: through
+    /// one of the addresses it advertised if the address it is known by is of an address
+    /// family this peer doesn't listen on.
+    pub fn dialable_peer(&self, peer: &PeerId, advertised: &[SocketAddr]) -> PeerId {
+        let listening: Vec<_> = self.listener_addrs.iter().map(SocketAddr::ip).collect();
+        let addresses: Vec<_> = std::iter::once(peer.addr)
+            .chain(advertised.iter().take(MAX_ADVERTISED_ADDRS).copied())
+            .collect();
+        let addr = crate::node::preferred_address(&addresses, &listening);
+        if addr == peer.addr {
+            peer.clone()
+        } else {
+            PeerId::new(addr, peer.pub_key.clone())
+        }
+    }
+
+    pub fn role(&self) -> NodeRole {
+        self.role
+    }
+
+    /// Records the role a connected peer joined with.
+    pub fn set_peer_role(&self, peer: &PeerId, role: NodeRole) {
+        if role.serves_network() {
+            self.light_peers.write().remove(peer);
+        } else {
+            self.light_peers.write().insert(peer.clone());
         }
     }
 
+    /// Whether the peer routes requests and stores contracts for others, so requests can be
+    /// routed to it.
+    pub fn serves_network(&self, peer: &PeerId) -> bool {
+        !self.light_peers.read().contains(peer)
+    }
+
+    pub fn min_connections(&self) -> usize {
+        self.min_connections
+            .load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    pub fn max_connections(&self) -> usize {
+        self.max_connections
+            .load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    /// Changes the connection limits of a running node. Open connections over the new maximum are
+    /// not dropped, but no new ones are accepted until under it.
+    pub fn set_connection_limits(&self, min_connections: usize, max_connections: usize) {
+        self.min_connections
+            .store(min_connections, std::sync::atomic::Ordering::SeqCst);
+        self.max_connections
+            .store(max_connections, std::sync::atomic::Ordering::SeqCst);
+        self.topology_manager
+            .write()
+            .set_connection_limits(min_connections, max_connections);
+    }
+
+    /// Records the time a peer took to respond to a request, per hop the request could travel.
+    pub fn record_response_time(&self, peer: &PeerId, rtt: Duration) {
+        self.response_times
+            .write()
+            .entry(peer.clone())
+            .or_default()
+            .sample(rtt);
+    }
+
+    /// Records a peer didn't respond in time to a request, backing off its timeout.
+    pub fn record_response_timeout(&self, peer: &PeerId) {
+        self.response_times
+            .write()
+            .entry(peer.clone())
+            .or_default()
+            .timed_out();
+    }
+
+    /// Time the requests sent to a peer wait for its response, per hop they may travel.
+    pub fn response_timeout(&self, peer: &PeerId) -> Duration {
+        self.response_times
+            .read()
+            .get(peer)
+            .map(RttEstimate::timeout)
+            .unwrap_or(rtt::INITIAL_TIMEOUT)
+    }
+
+    /// Peers responding much slower than the other connected peers.
+    fn slow_peers(&self) -> HashSet<PeerId> {
+        let response_times = self.response_times.read();
+        let Some(threshold) = rtt::slow_threshold(
+            response_times
+                .values()
+                .filter_map(RttEstimate::smoothed)
+                .collect(),
+        ) else {
+            return HashSet::new();
+        };
+        response_times
+            .iter()
+            .filter(|(_, estimate)| estimate.smoothed().is_some_and(|rtt| rtt > threshold))
+            .map(|(peer, _)| peer.clone())
+            .collect()
+    }
+
     /// Whether a node should accept a new node connection or not based
     /// on the relative location and other conditions.
     ///
@@ -162,10 +300,10 @@ impl ConnectionManager {
             return false;
         }
 
-        let accepted = if total_conn < self.min_connections {
+        let accepted = if total_conn < self.min_connections() {
             tracing::debug!(%peer_id, "Accepted connection, below min connections");
             true
-        } else if total_conn >= self.max_connections {
+        } else if total_conn >= self.max_connections() {
             tracing::debug!(%peer_id, "Rejected connection, max connections reached");
             false
         } else {
@@ -287,6 +425,8 @@ impl ConnectionManager {
         tracing::debug!(%peer, "Pruning {} connection", connection_type);
 
         let mut locations_for_peer = self.location_for_peer.write();
+        self.response_times.write().remove(peer);
+        self.light_peers.write().remove(peer);
 
         let Some(loc) = locations_for_peer.remove(peer) else {
             if is_alive {
@@ -326,25 +466,26 @@ impl ConnectionManager {
         self.connections_by_location.read().clone()
     }
 
-    /// Get a random peer from the known ring connections.
+    /// Get a random peer, among the ones requests can be routed to, from the known ring
+    /// connections.
     pub fn random_peer<F>(&self, filter_fn: F) -> Option<PeerKeyLocation>
     where
         F: Fn(&PeerId) -> bool,
     {
+        let light_peers = &*self.light_peers.read();
         let peers = &*self.location_for_peer.read();
         let amount = peers.len();
         if amount == 0 {
             return None;
         }
-        let mut rng = rand::thread_rng();
         let mut attempts = 0;
         loop {
             if attempts >= amount * 2 {
                 return None;
             }
-            let selected = rng.gen_range(0..amount);
+            let selected = node::clock::with_rng(|rng| rng.gen_range(0..amount));
             let (peer, loc) = peers.iter().nth(selected).expect("infallible");
-            if !filter_fn(peer) {
+            if !filter_fn(peer) || light_peers.contains(peer) {
                 attempts += 1;
                 continue;
             } else {
@@ -364,10 +505,17 @@ impl ConnectionManager {
         skip_list: impl Contains<PeerId>,
         router: &Router,
     ) -> Option<PeerKeyLocation> {
-        use rand::seq::SliceRandom;
+        use rand::seq::IteratorRandom;
+        let light_peers = self.light_peers.read();
         let connections = self.connections_by_location.read();
         let peers = connections.values().filter_map(|conns| {
-            let conn = conns.choose(&mut rand::thread_rng())?;
+            // light nodes only take part in the transactions of their own clients
+            let conn = node::clock::with_rng(|rng| {
+                conns
+                    .iter()
+                    .filter(|conn| !light_peers.contains(&conn.location.peer))
+                    .choose(rng)
+            })?;
             if let Some(requester) = requesting {
                 if requester == &conn.location.peer {
                     return None;
@@ -375,7 +523,17 @@ impl ConnectionManager {
             }
             (!skip_list.has_element(conn.location.peer.clone())).then_some(&conn.location)
         });
-        router.select_peer(peers, target).cloned()
+        // slow peers are only routed to when there are no other candidates, so they don't get
+        // more requests than they can handle
+        let slow_peers = self.slow_peers();
+        let (slow, responsive): (Vec<_>, Vec<_>) =
+            peers.partition(|peer| slow_peers.contains(&peer.peer));
+        let candidates = if responsive.is_empty() {
+            slow
+        } else {
+            responsive
+        };
+        router.select_peer(candidates, target).cloned()
     }
 
     pub fn num_connections(&self) -> usize {
@@ -0,0 +1,342 @@
+//! Scheduling of the outbound dials to gateways.
+//!
+//! Addresses which fail to connect are not dialed again until their backoff expires. The backoff
+//! doubles with every failure in a row, up to a ceiling, and is jittered so nodes which lost
+//! their gateways at once don't dial them again at once. Only a few dials are in flight at a
+//! time, and a single task retries each address.
+//!
+//! Dials the node itself declines, because it doesn't want the connection at the moment, back
+//! off the same way without counting as failures of the address.
+//!
+//! Failing addresses are persisted, so a restarted node doesn't hammer dead bootstrap nodes
+//! either. Their failures decay over time, so addresses which were down for a while are
+//! eventually dialed as often as any other.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    net::SocketAddr,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+const MAX_CONCURRENT_DIALS: usize = 8;
+const BASE_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(10 * 60);
+/// Time after which half of the failures of an address are forgiven.
+const FAILURES_HALF_LIFE: Duration = Duration::from_secs(60 * 60);
+/// Dials without outcome after this long are considered failed.
+const DIAL_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct BadAddress {
+    addr: SocketAddr,
+    /// Failed dials in a row.
+    failures: u32,
+    last_failure: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Backoff {
+    failures: u32,
+    /// Dials declined by the node in a row.
+    postponed: u32,
+    last_failure: DateTime<Utc>,
+    retry_at: Instant,
+}
+
+#[derive(Default)]
+struct Dials {
+    backoffs: HashMap<SocketAddr, Backoff>,
+    /// Addresses being dialed, and since when.
+    in_flight: HashMap<SocketAddr, Instant>,
+    /// Addresses a task is dialing until connected.
+    retrying: HashSet<SocketAddr>,
+}
+
+pub(crate) struct DialScheduler {
+    dials: Mutex<Dials>,
+    /// File the failing addresses are persisted to.
+    file: Option<PathBuf>,
+}
+
+impl DialScheduler {
+    pub fn new(file: Option<PathBuf>) -> Self {
+        let mut dials = Dials::default();
+        if let Some(content) = file.as_ref().and_then(|path| fs::read_to_string(path).ok()) {
+            match serde_json::from_str::<Vec<BadAddress>>(&content) {
+                Ok(persisted) => {
+                    let now = Utc::now();
+                    for bad in persisted {
+                        let elapsed = (now - bad.last_failure).to_std().unwrap_or_default();
+                        let half_lives = elapsed.as_secs() / FAILURES_HALF_LIFE.as_secs();
+                        let failures = bad.failures.checked_shr(half_lives as u32).unwrap_or(0);
+                        if failures == 0 {
+                            continue;
+                        }
+                        let remaining = backoff(failures).saturating_sub(elapsed);
+                        dials.backoffs.insert(
+                            bad.addr,
+                            Backoff {
+                                failures,
+                                postponed: 0,
+                                last_failure: bad.last_failure,
+                                retry_at: Instant::now() + jittered(remaining),
+                            },
+                        );
+                    }
+                }
+                Err(error) => tracing::warn!(%error, "Failed loading persisted bad addresses"),
+            }
+        }
+        Self {
+            dials: Mutex::new(dials),
+            file,
+        }
+    }
+
+    /// Time until the address can be dialed again.
+    pub fn delay(&self, addr: &SocketAddr) -> Duration {
+        self.dials
+            .lock()
+            .backoffs
+            .get(addr)
+            .map(|backoff| backoff.retry_at.saturating_duration_since(Instant::now()))
+            .unwrap_or_default()
+    }
+
+    /// Starts dialing the address, unless it is backing off, already being dialed, or too many
+    /// dials are in flight.
+    pub fn try_start(&self, addr: SocketAddr) -> bool {
+        let mut expired = vec![];
+        let started = {
+            let mut dials = self.dials.lock();
+            let now = Instant::now();
+            dials.in_flight.retain(|addr, started| {
+                let in_flight = now.duration_since(*started) < DIAL_TIMEOUT;
+                if !in_flight {
+                    expired.push(*addr);
+                }
+                in_flight
+            });
+            let backing_off = dials
+                .backoffs
+                .get(&addr)
+                .is_some_and(|backoff| backoff.retry_at > now);
+            let can_start = !backing_off
+                && !dials.in_flight.contains_key(&addr)
+                && dials.in_flight.len() < MAX_CONCURRENT_DIALS;
+            if can_start {
+                dials.in_flight.insert(addr, now);
+            }
+            can_start
+        };
+        for addr in expired {
+            tracing::debug!(%addr, "Dial timed out");
+            self.failed(addr);
+        }
+        started
+    }
+
+    /// The node declined the dial, the address is dialed again after a backoff growing with the
+    /// dials declined in a row.
+    pub fn postpone(&self, addr: SocketAddr) {
+        let mut dials = self.dials.lock();
+        dials.in_flight.remove(&addr);
+        let entry = dials.backoffs.entry(addr).or_insert(Backoff {
+            failures: 0,
+            postponed: 0,
+            last_failure: Utc::now(),
+            retry_at: Instant::now(),
+        });
+        entry.postponed = entry.postponed.saturating_add(1);
+        let delay = jittered(backoff(entry.failures.max(entry.postponed)));
+        tracing::debug!(%addr, postponed = entry.postponed, ?delay, "Postponing dialing address");
+        entry.retry_at = Instant::now() + delay;
+    }
+
+    /// Registers the task retrying the address, unless one already is.
+    pub fn start_retrying(&self, addr: SocketAddr) -> bool {
+        self.dials.lock().retrying.insert(addr)
+    }
+
+    pub fn stop_retrying(&self, addr: &SocketAddr) {
+        self.dials.lock().retrying.remove(addr);
+    }
+
+    pub fn succeeded(&self, addr: &SocketAddr) {
+        let was_bad = {
+            let mut dials = self.dials.lock();
+            dials.in_flight.remove(addr);
+            dials.backoffs.remove(addr).is_some()
+        };
+        if was_bad {
+            self.persist();
+        }
+    }
+
+    pub fn failed(&self, addr: SocketAddr) {
+        {
+            let mut dials = self.dials.lock();
+            dials.in_flight.remove(&addr);
+            let failures = dials
+                .backoffs
+                .get(&addr)
+                .map_or(1, |backoff| backoff.failures.saturating_add(1));
+            let delay = jittered(backoff(failures));
+            tracing::debug!(%addr, failures, ?delay, "Backing off dialing address");
+            dials.backoffs.insert(
+                addr,
+                Backoff {
+                    failures,
+                    postponed: 0,
+                    last_failure: Utc::now(),
+                    retry_at: Instant::now() + delay,
+                },
+            );
+        }
+        self.persist();
+    }
+
+    fn persist(&self) {
+        let Some(path) = &self.file else {
+            return;
+        };
+        let bad_addresses = self
+            .dials
+            .lock()
+            .backoffs
+            .iter()
+            // declined dials say nothing of the address
+            .filter(|(_, backoff)| backoff.failures > 0)
+            .map(|(addr, backoff)| BadAddress {
+                addr: *addr,
+                failures: backoff.failures,
+                last_failure: backoff.last_failure,
+            })
+            .collect::<Vec<_>>();
+        let result = serde_json::to_vec(&bad_addresses)
+            .map_err(std::io::Error::from)
+            .and_then(|content| fs::write(path, content));
+        if let Err(error) = result {
+            tracing::error!(%error, "Failed persisting bad addresses");
+        }
+    }
+}
+
+fn backoff(failures: u32) -> Duration {
+    let exponent = failures.saturating_sub(1).min(16);
+    BASE_BACKOFF.saturating_mul(1 << exponent).min(MAX_BACKOFF)
+}
+
+/// A random delay between half and the whole of the given one.
+fn jittered(delay: Duration) -> Duration {
+    delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn back_off_failing_addresses() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("bad-addresses.json");
+        let scheduler = DialScheduler::new(Some(file.clone()));
+        let gateway: SocketAddr = ([10, 0, 0, 1], 31337).into();
+
+        assert!(scheduler.try_start(gateway));
+        // already being dialed
+        assert!(!scheduler.try_start(gateway));
+        scheduler.failed(gateway);
+        assert!(!scheduler.try_start(gateway));
+        let delay = scheduler.delay(&gateway);
+        assert!(
+            delay > BASE_BACKOFF / 4 && delay <= BASE_BACKOFF,
+            "{delay:?}"
+        );
+
+        for _ in 0..20 {
+            scheduler.failed(gateway);
+        }
+        assert!(scheduler.delay(&gateway) <= MAX_BACKOFF);
+        assert!(scheduler.delay(&gateway) > MAX_BACKOFF / 3);
+
+        // restarts keep backing off
+        let reloaded = DialScheduler::new(Some(file.clone()));
+        assert!(reloaded.delay(&gateway) > MAX_BACKOFF / 3);
+        assert!(!reloaded.try_start(gateway));
+
+        scheduler.succeeded(&gateway);
+        assert!(scheduler.try_start(gateway));
+        assert!(DialScheduler::new(Some(file)).try_start(gateway));
+    }
+
+    #[test]
+    fn limit_concurrent_dials() {
+        let scheduler = DialScheduler::new(None);
+        let addrs = (0..=MAX_CONCURRENT_DIALS as u16)
+            .map(|port| SocketAddr::from(([10, 0, 0, 1], port)))
+            .collect::<Vec<_>>();
+        for addr in &addrs[..MAX_CONCURRENT_DIALS] {
+            assert!(scheduler.try_start(*addr));
+        }
+        assert!(!scheduler.try_start(addrs[MAX_CONCURRENT_DIALS]));
+        scheduler.postpone(addrs[0]);
+        assert!(scheduler.try_start(addrs[MAX_CONCURRENT_DIALS]));
+    }
+
+    #[test]
+    fn back_off_declined_dials() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("bad-addresses.json");
+        let scheduler = DialScheduler::new(Some(file.clone()));
+        let gateway: SocketAddr = ([10, 0, 0, 1], 31337).into();
+
+        assert!(scheduler.try_start(gateway));
+        scheduler.postpone(gateway);
+        assert!(!scheduler.try_start(gateway));
+        for _ in 0..3 {
+            scheduler.postpone(gateway);
+        }
+        assert!(scheduler.delay(&gateway) > BASE_BACKOFF * 2);
+        // the address didn't fail
+        scheduler.failed(([10, 0, 0, 2], 31337).into());
+        assert_eq!(
+            DialScheduler::new(Some(file)).delay(&gateway),
+            Duration::ZERO
+        );
+
+        assert!(scheduler.start_retrying(gateway));
+        assert!(!scheduler.start_retrying(gateway));
+        scheduler.stop_retrying(&gateway);
+        assert!(scheduler.start_retrying(gateway));
+    }
+
+    #[test]
+    fn decay_failures() {
+        assert_eq!(backoff(1), BASE_BACKOFF);
+        assert_eq!(backoff(3), BASE_BACKOFF * 4);
+        assert_eq!(backoff(u32::MAX), MAX_BACKOFF);
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("bad-addresses.json");
+        let gateway: SocketAddr = ([10, 0, 0, 1], 31337).into();
+        let persisted = vec![BadAddress {
+            addr: gateway,
+            failures: 3,
+            last_failure: Utc::now() - chrono::Duration::hours(2),
+        }];
+        fs::write(&file, serde_json::to_vec(&persisted).unwrap()).unwrap();
+        // two half lives later, the failures dropped to zero
+        assert_eq!(
+            DialScheduler::new(Some(file)).delay(&gateway),
+            Duration::ZERO
+        );
+    }
+}
@@ -1,14 +1,30 @@
-use crate::{message::Transaction, node::PeerId};
+use crate::{message::Transaction, node::PeerId, ring::rtt};
 use dashmap::DashMap;
-use std::sync::Arc;
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::sync;
 
 #[derive(Clone)]
 pub struct LiveTransactionTracker {
     tx_per_peer: Arc<DashMap<PeerId, Vec<Transaction>>>,
+    /// Requests sent to peers which didn't respond yet.
+    awaiting_response: Arc<DashMap<(PeerId, Transaction), AwaitingResponse>>,
     missing_candidate_sender: sync::mpsc::Sender<PeerId>,
 }
 
+#[derive(Debug, Clone, Copy)]
+struct AwaitingResponse {
+    sent_at: Instant,
+    /// Whether the transaction was sent more than once to the peer, in which case the time it
+    /// takes to respond can't be told.
+    resent: bool,
+    /// Hops the request may travel before it's answered.
+    hops: usize,
+}
+
 impl LiveTransactionTracker {
     /// The given peer does not have (good) candidates for acquiring new connections.
     pub async fn missing_candidate_peers(&self, peer: PeerId) {
@@ -26,6 +42,76 @@ impl LiveTransactionTracker {
         self.tx_per_peer.entry(peer).or_default().push(tx);
     }
 
+    /// Tracks a request sent to a peer, which may travel `hops` hops, until it responds, to time
+    /// how long it takes.
+    pub fn add_request(&self, peer: PeerId, tx: Transaction, hops: usize) {
+        self.awaiting_response
+            .entry((peer.clone(), tx))
+            .and_modify(|awaiting| awaiting.resent = true)
+            .or_insert(AwaitingResponse {
+                sent_at: Instant::now(),
+                resent: false,
+                hops: hops.max(1),
+            });
+        self.add_transaction(peer, tx);
+    }
+
+    /// Records a message for the transaction was received from a peer, returning the time it took
+    /// the peer to respond per hop the request could travel, if it can be told.
+    pub(crate) fn response_received(
+        &self,
+        addr: SocketAddr,
+        tx: &Transaction,
+    ) -> Option<(PeerId, Duration)> {
+        let peer = self
+            .tx_per_peer
+            .iter()
+            .find(|entry| entry.key().addr == addr)
+            .map(|entry| entry.key().clone())?;
+        let (_, awaiting) = self.awaiting_response.remove(&(peer.clone(), *tx))?;
+        (!awaiting.resent).then(|| (peer, awaiting.sent_at.elapsed() / awaiting.hops as u32))
+    }
+
+    /// Requests which waited longer for a response than the timeout of their peer, given per hop,
+    /// for the hops they may travel. They are only returned once, as they no longer await a
+    /// response.
+    pub(crate) fn overdue_responses(
+        &self,
+        hop_timeout: impl Fn(&PeerId) -> Duration,
+    ) -> Vec<(PeerId, Transaction)> {
+        let now = Instant::now();
+        let overdue = self
+            .awaiting_response
+            .iter()
+            .filter(|entry| {
+                let awaiting = entry.value();
+                now.duration_since(awaiting.sent_at)
+                    >= rtt::request_timeout(hop_timeout(&entry.key().0), awaiting.hops)
+            })
+            .map(|entry| entry.key().clone())
+            .collect::<Vec<_>>();
+        for request in &overdue {
+            self.awaiting_response.remove(request);
+        }
+        overdue
+    }
+
+    /// Peers the transaction awaits a response from.
+    pub(crate) fn awaited_peers(&self, tx: &Transaction) -> Vec<PeerId> {
+        self.awaiting_response
+            .iter()
+            .filter(|entry| &entry.key().1 == tx)
+            .map(|entry| entry.key().0.clone())
+            .collect()
+    }
+
+    /// Whether the transaction awaits the response of any peer.
+    pub(crate) fn awaits_response(&self, tx: &Transaction) -> bool {
+        self.awaiting_response
+            .iter()
+            .any(|entry| &entry.key().1 == tx)
+    }
+
     pub fn remove_finished_transaction(&self, tx: Transaction) {
         let keys_to_remove: Vec<PeerId> = self
             .tx_per_peer
@@ -34,6 +120,7 @@ impl LiveTransactionTracker {
             .map(|entry| entry.key().clone())
             .collect();
 
+        self.awaiting_response.retain(|(_, otx), _| otx != &tx);
         for k in keys_to_remove {
             self.tx_per_peer.remove_if_mut(&k, |_, v| {
                 v.retain(|otx| otx != &tx);
@@ -47,6 +134,7 @@ impl LiveTransactionTracker {
         (
             Self {
                 tx_per_peer: Arc::new(DashMap::default()),
+                awaiting_response: Arc::new(DashMap::default()),
                 missing_candidate_sender: missing_peer,
             },
             rx,
@@ -55,6 +143,7 @@ impl LiveTransactionTracker {
 
     pub(crate) fn prune_transactions_from_peer(&self, peer: &PeerId) {
         self.tx_per_peer.remove(peer);
+        self.awaiting_response.retain(|(other, _), _| other != peer);
     }
 
     pub(crate) fn has_live_connection(&self, peer: &PeerId) -> bool {
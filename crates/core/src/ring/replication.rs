@@ -0,0 +1,154 @@
+//! Proactive replication of contracts to the peers closest to their location.
+//!
+//! After storing a contract as the final hop of a PUT, or caching it after a GET, a peer which is
+//! among the closest ones to the contract location pushes replicas to the next closest peers it is
+//! connected to, until the contract is cached by as many peers as its replication factor.
+//!
+//! The replication factor is set per node, and contracts can suggest their own in the
+//! `freenet-replication` custom section of their WASM module. The peers holding the replicas
+//! pushed by this node are tracked, so replicas are repaired when their holders disconnect.
+
+use dashmap::DashMap;
+use freenet_stdlib::prelude::*;
+
+use crate::{node::PeerId, util::wasm_sections::find_custom_section};
+
+/// Default number of peers caching a contract, including the one which stored it.
+pub const DEFAULT_REPLICATION_FACTOR: usize = 3;
+/// Upper bound for the replication factor suggested by contracts.
+pub const MAX_REPLICATION_FACTOR: usize = 16;
+
+const REPLICATION_SECTION: &[u8] = b"freenet-replication";
+
+struct Replicas {
+    factor: usize,
+    holders: Vec<PeerId>,
+}
+
+pub(crate) struct ReplicationManager {
+    replication_factor: usize,
+    /// Contracts this peer pushed replicas of.
+    replicas: DashMap<ContractKey, Replicas>,
+}
+
+impl ReplicationManager {
+    pub fn new(replication_factor: usize) -> Self {
+        Self {
+            replication_factor: replication_factor.clamp(1, MAX_REPLICATION_FACTOR),
+            replicas: DashMap::new(),
+        }
+    }
+
+    /// Number of peers which should cache the contract, including this one.
+    pub fn factor_for(&self, contract: &ContractContainer) -> usize {
+        suggested_factor(contract)
+            .map(|factor| factor.clamp(1, MAX_REPLICATION_FACTOR))
+            .unwrap_or(self.replication_factor)
+    }
+
+    pub fn holders(&self, key: &ContractKey) -> Vec<PeerId> {
+        self.replicas
+            .get(key)
+            .map(|replicas| replicas.holders.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn record(
+        &self,
+        key: ContractKey,
+        factor: usize,
+        holders: impl IntoIterator<Item = PeerId>,
+    ) {
+        let mut replicas = self.replicas.entry(key).or_insert(Replicas {
+            factor,
+            holders: vec![],
+        });
+        replicas.factor = factor;
+        for holder in holders {
+            if !replicas.holders.contains(&holder) {
+                replicas.holders.push(holder);
+            }
+        }
+    }
+
+    /// Forgets the replicas held by a peer which is no longer connected.
+    pub fn prune_holder(&self, peer: &PeerId) {
+        self.replicas.alter_all(|_, mut replicas| {
+            replicas.holders.retain(|holder| holder != peer);
+            replicas
+        });
+    }
+
+    /// Contracts held by fewer peers than their replication factor.
+    pub fn under_replicated(&self) -> Vec<ContractKey> {
+        self.replicas
+            .iter()
+            .filter(|replicas| replicas.holders.len() + 1 < replicas.factor)
+            .map(|replicas| *replicas.key())
+            .collect()
+    }
+}
+
+/// Replication factor suggested by the contract.
+fn suggested_factor(contract: &ContractContainer) -> Option<usize> {
+    let code = match contract {
+        ContractContainer::Wasm(ContractWasmAPIVersion::V1(contract)) => contract.code().data(),
+        _ => return None,
+    };
+    let section = find_custom_section(code, REPLICATION_SECTION)?;
+    std::str::from_utf8(section.content)
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::TransportKeypair;
+
+    fn contract(sections: &[u8]) -> ContractContainer {
+        let mut code = b"\0asm\x01\0\0\0".to_vec();
+        code.extend_from_slice(sections);
+        ContractContainer::Wasm(ContractWasmAPIVersion::V1(WrappedContract::new(
+            std::sync::Arc::new(ContractCode::from(code)),
+            Parameters::from(vec![]),
+        )))
+    }
+
+    #[test]
+    fn contract_suggested_factor() {
+        let manager = ReplicationManager::new(DEFAULT_REPLICATION_FACTOR);
+        assert_eq!(
+            manager.factor_for(&contract(&[])),
+            DEFAULT_REPLICATION_FACTOR
+        );
+        let section = [&[0, 21, 19][..], REPLICATION_SECTION, b"5"].concat();
+        assert_eq!(manager.factor_for(&contract(&section)), 5);
+        let section = [&[0, 23, 19][..], REPLICATION_SECTION, b"500"].concat();
+        assert_eq!(
+            manager.factor_for(&contract(&section)),
+            MAX_REPLICATION_FACTOR
+        );
+    }
+
+    #[test]
+    fn tracks_under_replicated() {
+        let manager = ReplicationManager::new(3);
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let peer = |port| {
+            PeerId::new(
+                ([127, 0, 0, 1], port).into(),
+                TransportKeypair::new().public().clone(),
+            )
+        };
+        let (a, b) = (peer(1000), peer(1001));
+        manager.record(key, 3, [a.clone(), b.clone()]);
+        assert!(manager.under_replicated().is_empty());
+
+        manager.prune_holder(&a);
+        assert_eq!(manager.holders(&key), vec![b]);
+        assert_eq!(manager.under_replicated(), vec![key]);
+    }
+}
@@ -0,0 +1,276 @@
+//! Reputation of the peers this node interacts with.
+//!
+//! Peers accumulate penalty points when they send malformed messages or trigger invalid operation
+//! state transitions. Points decay over time, and peers crossing the ban threshold are banned for
+//! a while. Bans are persisted, so they survive node restarts.
+//!
+//! Timeouts don't count: a request times out the same whether the peer it was sent to or any
+//! peer further down the route failed to respond, so they would get honest relays banned. They
+//! only slow down the peers in routing, through their response time estimates.
+//!
+//! Peers are tracked by IP address, so a misbehaving peer can't shed its reputation by
+//! reconnecting from another port; this also means peers sharing a public address behind a NAT
+//! share their reputation.
+
+use std::{
+    fs,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+/// Penalty points at which a peer is banned.
+const BAN_THRESHOLD: f64 = 100.0;
+const BAN_DURATION: Duration = Duration::from_secs(60 * 60);
+/// Time after which half of the penalty points of a peer are forgiven.
+const PENALTY_HALF_LIFE: Duration = Duration::from_secs(10 * 60);
+/// Penalty points under which a peer is forgotten.
+const FORGOTTEN_PENALTY: f64 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Misbehavior {
+    MalformedMessage,
+    InvalidStateTransition,
+}
+
+impl Misbehavior {
+    fn penalty(self) -> f64 {
+        match self {
+            Misbehavior::MalformedMessage => 25.0,
+            Misbehavior::InvalidStateTransition => 10.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Ban {
+    pub peer: IpAddr,
+    pub until: DateTime<Utc>,
+    /// The misbehavior which made the peer cross the ban threshold.
+    pub reason: Misbehavior,
+}
+
+struct Penalty {
+    points: f64,
+    updated: Instant,
+}
+
+impl Penalty {
+    fn decayed(&self, now: Instant) -> f64 {
+        let half_lives =
+            now.duration_since(self.updated).as_secs_f64() / PENALTY_HALF_LIFE.as_secs_f64();
+        self.points * 0.5f64.powf(half_lives)
+    }
+}
+
+pub(crate) struct PeerReputation {
+    penalties: DashMap<IpAddr, Penalty>,
+    /// Last time the penalties which decayed were dropped.
+    pruned: Mutex<Instant>,
+    bans: Arc<DashMap<IpAddr, Ban>>,
+    /// File the bans are persisted to.
+    bans_file: Option<PathBuf>,
+    /// Serializes the writes to the bans file.
+    persisting: Arc<Mutex<()>>,
+}
+
+impl PeerReputation {
+    pub fn new(bans_file: Option<PathBuf>) -> Self {
+        let bans = DashMap::new();
+        if let Some(content) = bans_file
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+        {
+            match serde_json::from_str::<Vec<Ban>>(&content) {
+                Ok(persisted) => {
+                    let now = Utc::now();
+                    for ban in persisted.into_iter().filter(|ban| ban.until > now) {
+                        bans.insert(ban.peer, ban);
+                    }
+                }
+                Err(error) => tracing::warn!(%error, "Failed loading persisted peer bans"),
+            }
+        }
+        Self {
+            penalties: DashMap::new(),
+            pruned: Mutex::new(Instant::now()),
+            bans: Arc::new(bans),
+            bans_file,
+            persisting: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Penalizes the peer for the given misbehavior.
+    ///
+    /// Returns whether the peer got banned, in which case any connection to it should be dropped.
+    pub fn penalize(&self, peer: SocketAddr, misbehavior: Misbehavior) -> bool {
+        if self.is_banned(&peer) {
+            return false;
+        }
+        let peer = peer.ip();
+        let now = Instant::now();
+        self.prune(now);
+        let points = {
+            let mut penalty = self.penalties.entry(peer).or_insert(Penalty {
+                points: 0.0,
+                updated: now,
+            });
+            penalty.points = penalty.decayed(now) + misbehavior.penalty();
+            penalty.updated = now;
+            penalty.points
+        };
+        tracing::debug!(%peer, ?misbehavior, points, "Penalized peer");
+        if points < BAN_THRESHOLD {
+            return false;
+        }
+        self.penalties.remove(&peer);
+        let ban = Ban {
+            peer,
+            until: Utc::now() + chrono::Duration::seconds(BAN_DURATION.as_secs() as i64),
+            reason: misbehavior,
+        };
+        tracing::warn!(%peer, ?misbehavior, until = %ban.until, "Banning peer");
+        self.bans.insert(peer, ban);
+        self.persist();
+        true
+    }
+
+    /// Forgets the peers whose penalty decayed, at most once per half life.
+    fn prune(&self, now: Instant) {
+        {
+            let mut pruned = self.pruned.lock();
+            if now.duration_since(*pruned) < PENALTY_HALF_LIFE {
+                return;
+            }
+            *pruned = now;
+        }
+        self.penalties
+            .retain(|_, penalty| penalty.decayed(now) >= FORGOTTEN_PENALTY);
+    }
+
+    pub fn is_banned(&self, peer: &SocketAddr) -> bool {
+        let peer = peer.ip();
+        let expired = match self.bans.get(&peer) {
+            None => return false,
+            Some(ban) => ban.until <= Utc::now(),
+        };
+        if expired {
+            // expired bans are dropped from the file the next time it is written
+            self.bans.remove(&peer);
+        }
+        !expired
+    }
+
+    /// Currently active bans.
+    pub fn bans(&self) -> Vec<Ban> {
+        active_bans(&self.bans)
+    }
+
+    /// Lifts the ban on a peer, returns whether the peer was banned.
+    pub fn unban(&self, peer: &IpAddr) -> bool {
+        self.penalties.remove(peer);
+        let unbanned = self.bans.remove(peer).is_some();
+        if unbanned {
+            tracing::info!(%peer, "Peer unbanned");
+            self.persist();
+        }
+        unbanned
+    }
+
+    /// Writes the bans to the bans file, off the async runtime when running in one so callers
+    /// on the message handling paths don't block on disk.
+    fn persist(&self) {
+        let Some(path) = self.bans_file.clone() else {
+            return;
+        };
+        let bans = self.bans.clone();
+        let persisting = self.persisting.clone();
+        let write = move || {
+            // the bans are read once the lock is held, so the last write has the latest bans
+            let _guard = persisting.lock();
+            let result = serde_json::to_vec(&active_bans(&bans))
+                .map_err(std::io::Error::from)
+                .and_then(|content| fs::write(&path, content));
+            if let Err(error) = result {
+                tracing::error!(%error, "Failed persisting peer bans");
+            }
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn_blocking(write);
+            }
+            Err(_) => write(),
+        }
+    }
+}
+
+fn active_bans(bans: &DashMap<IpAddr, Ban>) -> Vec<Ban> {
+    let now = Utc::now();
+    bans.iter()
+        .filter(|ban| ban.until > now)
+        .map(|ban| ban.value().clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ban_and_persist() {
+        let dir = tempfile::tempdir().unwrap();
+        let bans_file = dir.path().join("bans.json");
+        let reputation = PeerReputation::new(Some(bans_file.clone()));
+        let peer: SocketAddr = ([10, 0, 0, 1], 31337).into();
+        let other: SocketAddr = ([10, 0, 0, 2], 31337).into();
+        let same_host: SocketAddr = ([10, 0, 0, 1], 31338).into();
+
+        assert!(!reputation.penalize(other, Misbehavior::InvalidStateTransition));
+        let bans = (0..5)
+            .filter(|_| reputation.penalize(peer, Misbehavior::MalformedMessage))
+            .count();
+        assert_eq!(bans, 1);
+        assert!(reputation.is_banned(&peer));
+        assert!(!reputation.is_banned(&other));
+        // reconnecting from another port doesn't evade the ban
+        assert!(reputation.is_banned(&same_host));
+
+        let reloaded = PeerReputation::new(Some(bans_file));
+        assert_eq!(reloaded.bans(), reputation.bans());
+        assert_eq!(reloaded.bans()[0].reason, Misbehavior::MalformedMessage);
+
+        assert!(reloaded.unban(&peer.ip()));
+        assert!(!reloaded.is_banned(&peer));
+        assert!(!reloaded.unban(&peer.ip()));
+    }
+
+    #[test]
+    fn penalties_decay() {
+        let now = Instant::now();
+        let penalty = Penalty {
+            points: 80.0,
+            updated: now,
+        };
+        let later = now + PENALTY_HALF_LIFE * 2;
+        assert!((penalty.decayed(later) - 20.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn forget_decayed_penalties() {
+        let reputation = PeerReputation::new(None);
+        let peer: SocketAddr = ([10, 0, 0, 1], 31337).into();
+        assert!(!reputation.penalize(peer, Misbehavior::InvalidStateTransition));
+        let now = Instant::now();
+        reputation.prune(now + PENALTY_HALF_LIFE);
+        assert_eq!(reputation.penalties.len(), 1);
+        reputation.prune(now + PENALTY_HALF_LIFE * 5);
+        assert!(reputation.penalties.is_empty());
+    }
+}
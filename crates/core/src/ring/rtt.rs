@@ -0,0 +1,153 @@
+//! Estimation of the time peers take to respond, to time out the requests sent to them.
+//!
+//! Estimates follow TCP's retransmission timer (RFC 6298): a smoothed response time and its
+//! variation are updated with every response, and requests time out once they wait longer than
+//! the smoothed time plus four times its variation. Timeouts back off exponentially until the peer
+//! responds again, so slow but alive peers get more time instead of more requests.
+//!
+//! Requests are answered once they went through all the hops they travel, so estimates are kept
+//! per hop: responses are sampled as the time they took divided by the hops the request could
+//! travel, and requests time out after the timeout of their peer times those hops.
+//!
+//! Responses to requests sent more than once to the same peer are ambiguous and not sampled
+//! (Karn's algorithm).
+
+use std::time::Duration;
+
+use crate::config::OPERATION_TTL;
+
+/// Timeout of the requests to peers which didn't respond yet.
+pub(crate) const INITIAL_TIMEOUT: Duration = Duration::from_secs(10);
+pub(crate) const MIN_TIMEOUT: Duration = Duration::from_secs(1);
+/// Requests don't wait longer than the whole operation they are part of.
+pub(crate) const MAX_TIMEOUT: Duration = OPERATION_TTL;
+/// Peers with a smoothed response time this many times over the median one are slow.
+const SLOW_PEER_FACTOR: f64 = 4.0;
+
+const ALPHA: f64 = 1.0 / 8.0;
+const BETA: f64 = 1.0 / 4.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct RttEstimate {
+    /// Smoothed response time, in seconds, `None` until the peer first responds.
+    smoothed: Option<f64>,
+    variation: f64,
+    /// Timeouts in a row since the peer last responded.
+    backoff: u32,
+}
+
+impl Default for RttEstimate {
+    fn default() -> Self {
+        Self {
+            smoothed: None,
+            variation: 0.0,
+            backoff: 0,
+        }
+    }
+}
+
+impl RttEstimate {
+    pub fn sample(&mut self, rtt: Duration) {
+        let rtt = rtt.as_secs_f64();
+        match self.smoothed {
+            None => {
+                self.smoothed = Some(rtt);
+                self.variation = rtt / 2.0;
+            }
+            Some(smoothed) => {
+                self.variation = (1.0 - BETA) * self.variation + BETA * (smoothed - rtt).abs();
+                self.smoothed = Some((1.0 - ALPHA) * smoothed + ALPHA * rtt);
+            }
+        }
+        self.backoff = 0;
+    }
+
+    /// Doubles the timeout, until the peer responds again.
+    pub fn timed_out(&mut self) {
+        self.backoff = self.backoff.saturating_add(1);
+    }
+
+    pub fn smoothed(&self) -> Option<Duration> {
+        self.smoothed.map(Duration::from_secs_f64)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        let base = match self.smoothed {
+            Some(smoothed) => Duration::from_secs_f64(smoothed + 4.0 * self.variation),
+            None => INITIAL_TIMEOUT,
+        };
+        let backoff = 2u32.saturating_pow(self.backoff.min(16));
+        base.saturating_mul(backoff).clamp(MIN_TIMEOUT, MAX_TIMEOUT)
+    }
+}
+
+/// Time a request which may travel `hops` hops waits for the response of a peer with the given
+/// timeout per hop.
+pub(crate) fn request_timeout(hop_timeout: Duration, hops: usize) -> Duration {
+    hop_timeout
+        .saturating_mul(hops.clamp(1, u32::MAX as usize) as u32)
+        .min(MAX_TIMEOUT)
+}
+
+/// Smoothed response time over which peers are slow compared to the others, given the smoothed
+/// response times of all the peers which responded; `None` if too few did to tell.
+pub(crate) fn slow_threshold(mut all: Vec<Duration>) -> Option<Duration> {
+    if all.len() < 3 {
+        return None;
+    }
+    all.sort();
+    Some(all[all.len() / 2].mul_f64(SLOW_PEER_FACTOR))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adapt_timeout() {
+        let mut estimate = RttEstimate::default();
+        assert_eq!(estimate.timeout(), INITIAL_TIMEOUT);
+
+        for _ in 0..20 {
+            estimate.sample(Duration::from_millis(200));
+        }
+        // steady responses converge to the minimum timeout
+        assert_eq!(estimate.timeout(), MIN_TIMEOUT);
+
+        for _ in 0..20 {
+            estimate.sample(Duration::from_secs(5));
+        }
+        let slow = estimate.timeout();
+        assert!(slow > Duration::from_secs(5), "{slow:?}");
+
+        estimate.timed_out();
+        assert_eq!(estimate.timeout(), (slow * 2).min(MAX_TIMEOUT));
+        for _ in 0..10 {
+            estimate.timed_out();
+        }
+        assert_eq!(estimate.timeout(), MAX_TIMEOUT);
+        estimate.sample(Duration::from_secs(5));
+        assert!(estimate.timeout() < MAX_TIMEOUT);
+    }
+
+    #[test]
+    fn scale_timeouts_by_hops() {
+        let hop = Duration::from_secs(2);
+        assert_eq!(request_timeout(hop, 0), hop);
+        assert_eq!(request_timeout(hop, 1), hop);
+        assert_eq!(request_timeout(hop, 5), Duration::from_secs(10));
+        assert_eq!(request_timeout(hop, usize::MAX), MAX_TIMEOUT);
+    }
+
+    #[test]
+    fn detect_slow_peers() {
+        let all = [100, 120, 150, 2000].map(Duration::from_millis).to_vec();
+        let threshold = slow_threshold(all).unwrap();
+        assert!(Duration::from_millis(2000) > threshold);
+        assert!(Duration::from_millis(150) <= threshold);
+        assert_eq!(
+            slow_threshold(vec![Duration::from_millis(100), Duration::from_secs(2)]),
+            None
+        );
+    }
+}
@@ -11,6 +11,8 @@ pub(crate) struct SeedingManager {
     subscribers: DashMap<ContractKey, Vec<PeerKeyLocation>>,
     /// Contracts this peer is seeding.
     seeding_contract: DashMap<ContractKey, Score>,
+    /// Peer providing the updates of each contract this node is subscribed to.
+    upstream: DashMap<ContractKey, PeerKeyLocation>,
 }
 
 impl SeedingManager {
@@ -30,6 +32,7 @@ impl SeedingManager {
         Self {
             subscribers: DashMap::new(),
             seeding_contract: DashMap::new(),
+            upstream: DashMap::new(),
         }
     }
 
@@ -99,6 +102,37 @@ impl SeedingManager {
         self.seeding_contract.contains_key(key)
     }
 
+    /// Contracts this node is seeding.
+    pub fn seeded_contracts(&self) -> Vec<ContractKey> {
+        self.seeding_contract.iter().map(|c| *c.key()).collect()
+    }
+
+    /// Stops seeding the contract, dropping its subscribers.
+    pub fn stop_seeding(&self, key: &ContractKey) {
+        self.seeding_contract.remove(key);
+        self.subscribers.remove(key);
+        self.upstream.remove(key);
+    }
+
+    /// Contracts with at least one subscriber.
+    pub fn subscribed_contracts(&self) -> Vec<ContractKey> {
+        self.subscribers
+            .iter()
+            .filter(|subs| !subs.value().is_empty())
+            .map(|subs| *subs.key())
+            .collect()
+    }
+
+    /// Records the peer this node subscribed to the contract through.
+    pub fn set_upstream(&self, contract: ContractKey, provider: PeerKeyLocation) {
+        self.upstream.insert(contract, provider);
+    }
+
+    /// Contracts this node is subscribed to through another peer.
+    pub fn upstream_subscriptions(&self) -> Vec<ContractKey> {
+        self.upstream.iter().map(|entry| *entry.key()).collect()
+    }
+
     /// Will return an error in case the max number of subscribers has been added.
     pub fn add_subscriber(
         &self,
@@ -131,6 +165,8 @@ impl SeedingManager {
     }
 
     pub fn prune_subscriber(&self, loc: Location) {
+        self.upstream
+            .retain(|_, provider| provider.location != Some(loc));
         self.subscribers.alter_all(|_, mut subs| {
             if let Some(pos) = subs.iter().position(|l| l.location == Some(loc)) {
                 subs.swap_remove(pos);
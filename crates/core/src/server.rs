@@ -1,24 +1,41 @@
+pub(crate) mod access_log;
 pub(crate) mod app_packaging;
+mod cors;
 pub(crate) mod errors;
 mod http_gateway;
 pub(crate) mod path_handlers;
+mod publish;
+mod tls;
+mod virtual_hosts;
 
 use std::net::SocketAddr;
 
+use anyhow::Context;
+use axum::{
+    extract::{DefaultBodyLimit, State},
+    http::header::CONTENT_LENGTH,
+    response::IntoResponse,
+    Extension,
+};
 use freenet_stdlib::{
     client_api::{ClientError, ClientRequest, HostResponse},
     prelude::*,
 };
 
+use errors::WebSocketApiError;
 use http_gateway::HttpGateway;
 use tower_http::trace::TraceLayer;
+use virtual_hosts::VirtualHosts;
 
 use crate::{
-    client_events::{websocket::WebSocketProxy, AuthToken, BoxedClient, ClientId, HostResult},
-    config::WebsocketApiConfig,
+    client_events::{
+        limits::Payload, websocket::WebSocketProxy, AuthToken, BoxedClient, ClientId, HostResult,
+    },
+    config::{PayloadLimits, WebsocketApiConfig},
+    node::load_shedding::LoadShedder,
 };
 
-pub use app_packaging::WebApp;
+pub use app_packaging::{AppManifest, CorsPolicy, WebApp};
 
 #[derive(Debug)]
 #[allow(clippy::large_enum_variant)]
@@ -50,11 +67,85 @@ pub(crate) enum HostCallbackResult {
     },
 }
 
+/// Applies the payload limits to every route of the client API.
+fn with_limits(router: axum::Router, limits: PayloadLimits) -> axum::Router {
+    router
+        .layer(DefaultBodyLimit::max(limits.max_body_size))
+        .layer(axum::middleware::from_fn_with_state(
+            limits,
+            reject_oversized_body,
+        ))
+        .layer(Extension(limits))
+}
+
+/// Rejects requests declaring a body over the limit before reading it, with a typed error.
+///
+/// Bodies without a declared length are still bounded by the [`DefaultBodyLimit`].
+async fn reject_oversized_body(
+    State(limits): State<PayloadLimits>,
+    req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    let declared_size = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|size| size.to_str().ok())
+        .and_then(|size| size.parse::<usize>().ok());
+    if let Some(size) = declared_size {
+        if let Err(error) = limits.check(Payload::Body, size) {
+            return WebSocketApiError::PayloadTooLarge(error).into_response();
+        }
+    }
+    next.run(req).await
+}
+
+/// Rejects the requests serving contract web apps while the node is overloaded, as they fetch
+/// the contract state, and the ones publishing contracts.
+fn with_load_shedding(router: axum::Router, shedder: LoadShedder) -> axum::Router {
+    router.layer(axum::middleware::from_fn_with_state(shedder, shed_load))
+}
+
+async fn shed_load(
+    State(shedder): State<LoadShedder>,
+    req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    let path = req.uri().path();
+    if path.starts_with("/v1/contract/web/") || path == "/v1/contract/publish" {
+        if let Err(error) = shedder.check() {
+            tracing::debug!(%error, path = req.uri().path(), "shedding gateway request");
+            return WebSocketApiError::Overloaded(error).into_response();
+        }
+    }
+    next.run(req).await
+}
+
+/// Serves the web app of the contract of each virtual host at the root of its domain.
+///
+/// Requests are rewritten before being routed, hence the gateway router is nested as the
+/// fallback of the one doing the rewriting.
+fn with_virtual_hosts(router: axum::Router, virtual_hosts: VirtualHosts) -> axum::Router {
+    if virtual_hosts.is_empty() {
+        return router;
+    }
+    axum::Router::new()
+        .fallback_service(router)
+        .layer(axum::middleware::from_fn_with_state(
+            virtual_hosts,
+            virtual_hosts::route,
+        ))
+}
+
 fn serve(socket: SocketAddr, router: axum::Router) {
     tokio::spawn(async move {
         tracing::info!("HTTP gateway listening on {}", socket);
         let listener = tokio::net::TcpListener::bind(socket).await.unwrap();
-        axum::serve(listener, router).await.map_err(|e| {
+        axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+        .map_err(|e| {
             tracing::error!("Error while running HTTP gateway server: {e}");
         })
     });
@@ -67,10 +158,12 @@ pub mod local_node {
 
     use crate::{
         client_events::{websocket::WebSocketProxy, ClientEventsProxy, OpenRequest},
+        config::{AccessLogConfig, PayloadLimits},
         contract::{Executor, ExecutorError},
+        node::load_shedding::LoadShedder,
     };
 
-    use super::{http_gateway::HttpGateway, serve};
+    use super::{access_log::with_access_log, http_gateway::HttpGateway, serve, with_limits};
 
     pub async fn run_local_node(mut executor: Executor, socket: SocketAddr) -> anyhow::Result<()> {
         match socket.ip() {
@@ -82,10 +175,15 @@ pub mod local_node {
             }
             _ => {}
         }
-        let (mut gw, gw_router) = HttpGateway::as_router(&socket);
-        let (mut ws_proxy, ws_router) = WebSocketProxy::as_router(gw_router);
+        let webs_dir = crate::config::default_webs_dir();
+        tokio::fs::create_dir_all(&webs_dir).await?;
+        let (mut gw, gw_router) = HttpGateway::as_router(&socket, None, false, webs_dir);
+        let (mut ws_proxy, ws_router) =
+            WebSocketProxy::as_router(gw_router, false, LoadShedder::default());
 
-        serve(socket, ws_router.layer(TraceLayer::new_for_http()));
+        let (_, access_log) = tokio::sync::watch::channel(AccessLogConfig::default());
+        let router = with_access_log(with_limits(ws_router, PayloadLimits::default()), access_log);
+        serve(socket, router.layer(TraceLayer::new_for_http()));
 
         // TODO: use combinator instead
         // let mut all_clients =
@@ -181,15 +279,51 @@ pub mod local_node {
     }
 }
 
-pub async fn serve_gateway(config: WebsocketApiConfig) -> [BoxedClient; 2] {
-    let (gw, ws_proxy) = serve_gateway_in(config).await;
-    [Box::new(gw), Box::new(ws_proxy)]
+pub async fn serve_gateway(config: WebsocketApiConfig) -> anyhow::Result<[BoxedClient; 2]> {
+    let (gw, ws_proxy) = serve_gateway_in(config).await?;
+    Ok([Box::new(gw), Box::new(ws_proxy)])
 }
 
-pub(crate) async fn serve_gateway_in(config: WebsocketApiConfig) -> (HttpGateway, WebSocketProxy) {
+pub(crate) async fn serve_gateway_in(
+    config: WebsocketApiConfig,
+) -> anyhow::Result<(HttpGateway, WebSocketProxy)> {
+    tokio::fs::create_dir_all(&config.webs_dir)
+        .await
+        .with_context(|| {
+            format!(
+                "failed creating the web apps directory {}",
+                config.webs_dir.display()
+            )
+        })?;
     let ws_socket = (config.address, config.port).into();
-    let (gw, gw_router) = HttpGateway::as_router(&ws_socket);
-    let (ws_proxy, ws_router) = WebSocketProxy::as_router(gw_router);
-    serve(ws_socket, ws_router.layer(TraceLayer::new_for_http()));
-    (gw, ws_proxy)
+    let domain = config.acme.as_ref().map(|acme| acme.domain.as_str());
+    let (mut gw, gw_router) = HttpGateway::as_router(
+        &ws_socket,
+        domain,
+        config.serves_tls(),
+        config.webs_dir.clone(),
+    );
+    let shedder = LoadShedder::new(config.load_shedding);
+    let (ws_proxy, ws_router) =
+        WebSocketProxy::as_router(gw_router, config.webrtc, shedder.clone());
+    let router = with_load_shedding(with_limits(ws_router, config.limits), shedder.clone());
+    let router = cors::with_cors(
+        router,
+        config.cors.clone(),
+        config.serves_tls(),
+        config.webs_dir.clone(),
+    );
+    let (access_log, access_log_policy) = tokio::sync::watch::channel(config.access_log);
+    let router = access_log::with_access_log(router, access_log_policy);
+    let router = with_virtual_hosts(router, VirtualHosts::new(&config.virtual_hosts))
+        .layer(TraceLayer::new_for_http());
+    if config.serves_tls() {
+        tls::serve(ws_socket, router, config);
+    } else {
+        serve(ws_socket, router);
+    }
+    // the node counts the events the server sheds load on and applies the access log settings
+    gw.queued_events = Some(shedder.queued_events());
+    gw.access_log = Some(access_log);
+    Ok((gw, ws_proxy))
 }
@@ -0,0 +1,119 @@
+//! Access logs of the HTTP gateway, so operators can analyze the traffic of their gateway without
+//! putting a proxy in front of it.
+//!
+//! Each sampled request is emitted as a tracing event with the `freenet::access` target, which
+//! subscribers can route apart from the other node logs. Client addresses are never logged, they
+//! are replaced by a hash keyed with a secret generated on start: the requests of a client can be
+//! told apart during a run, but not traced back to its address nor across restarts. Query strings
+//! are dropped too, as they may carry auth tokens.
+//!
+//! Logging can be toggled and its sample rate changed while the node runs, through the runtime
+//! settings, which the node applies to the policy of the gateway, see
+//! [`ClientEventsProxy::access_log`](crate::client_events::ClientEventsProxy::access_log).
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Instant,
+};
+
+use axum::{
+    body::HttpBody,
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use once_cell::sync::Lazy;
+use tokio::sync::watch;
+
+use crate::config::AccessLogConfig;
+
+/// Tracing target of the access log events.
+pub const ACCESS_LOG_TARGET: &str = "freenet::access";
+
+/// Key of the client address hashes, never persisted.
+static CLIENT_HASH_KEY: Lazy<[u8; 32]> = Lazy::new(rand::random);
+
+/// Logs the requests to every route of the gateway, following the changes to the policy.
+pub(super) fn with_access_log(
+    router: axum::Router,
+    policy: watch::Receiver<AccessLogConfig>,
+) -> axum::Router {
+    router.layer(axum::middleware::from_fn_with_state(policy, log_request))
+}
+
+async fn log_request(
+    State(policy): State<watch::Receiver<AccessLogConfig>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let policy = *policy.borrow();
+    if !sampled(&policy) {
+        return next.run(req).await;
+    }
+    let method = req.method().clone();
+    let path = req.uri().path().to_owned();
+    let client = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| client_hash(addr.ip()));
+    let started = Instant::now();
+    let response = next.run(req).await;
+    tracing::info!(
+        target: ACCESS_LOG_TARGET,
+        %method,
+        path,
+        contract = contract_in(&path),
+        status = response.status().as_u16(),
+        latency_ms = started.elapsed().as_millis() as u64,
+        // streamed bodies are not counted
+        bytes = response.body().size_hint().exact(),
+        client = client.as_deref(),
+    );
+    response
+}
+
+fn sampled(policy: &AccessLogConfig) -> bool {
+    policy.enabled && (policy.sample_rate >= 1.0 || rand::random::<f64>() < policy.sample_rate)
+}
+
+fn client_hash(ip: IpAddr) -> String {
+    let hash = blake3::keyed_hash(&CLIENT_HASH_KEY, ip.to_string().as_bytes());
+    hash.to_hex()[..16].to_owned()
+}
+
+/// Contract requested, for the routes of web apps.
+fn contract_in(path: &str) -> Option<&str> {
+    path.strip_prefix("/v1/contract/web/")?
+        .split('/')
+        .next()
+        .filter(|id| !id.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_and_redact() {
+        let mut policy = AccessLogConfig {
+            enabled: false,
+            sample_rate: 1.0,
+        };
+        assert!(!sampled(&policy));
+        policy.enabled = true;
+        assert!(sampled(&policy));
+        policy.sample_rate = 0.0;
+        assert!(!(0..100).any(|_| sampled(&policy)));
+
+        let client: IpAddr = "203.0.113.7".parse().unwrap();
+        assert_eq!(client_hash(client), client_hash(client));
+        assert_ne!(
+            client_hash(client),
+            client_hash("203.0.113.8".parse().unwrap())
+        );
+
+        assert_eq!(contract_in("/v1/contract/web/abc/index.html"), Some("abc"));
+        assert_eq!(contract_in("/v1/contract/web/"), None);
+        assert_eq!(contract_in("/v1/contract/command"), None);
+    }
+}
@@ -1,10 +1,12 @@
 //! Helper functions and types for dealing with HTTP gateway compatible contracts.
 use std::{
+    collections::HashMap,
     io::{Cursor, Read},
     path::Path,
 };
 
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use serde::Deserialize;
 use tar::{Archive, Builder};
 use xz2::read::{XzDecoder, XzEncoder};
 
@@ -18,6 +20,55 @@ pub enum WebContractError {
     FileNotFound(String),
 }
 
+/// Table in the bundle metadata mapping file extensions to the content type they are served with.
+const MIME_TYPES_KEY: &str = "mime-types";
+/// Table in the bundle metadata holding the app manifest.
+const MANIFEST_KEY: &str = "manifest";
+
+/// Permissions and policies a web app declares in the `[manifest]` table of its bundle metadata,
+/// which the gateway enforces when serving it, e.g.:
+/// ```toml
+/// [manifest]
+/// auth-token = true
+/// service-worker = true
+/// spa-fallback = true
+/// required-delegates = ["<delegate key>"]
+/// content-security-policy = "default-src 'self'"
+///
+/// [manifest.cors]
+/// allowed-origins = ["https://app.example.org"]
+/// ```
+/// Apps without a manifest are served as before manifests existed, with an auth token and no
+/// other permissions. A malformed manifest grants no permissions at all.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct AppManifest {
+    /// Whether the gateway hands a client auth token to the app.
+    pub auth_token: bool,
+    /// Whether the app may register service workers.
+    pub service_worker: bool,
+    /// Whether unknown paths are served the app entry point, for client-side routing.
+    pub spa_fallback: bool,
+    /// Delegates the app needs to be registered in the node to work.
+    pub required_delegates: Vec<String>,
+    /// Content security policy the app is served with.
+    pub content_security_policy: Option<String>,
+    /// Cross-origin policy of the app routes, restricting the one of the gateway.
+    pub cors: Option<CorsPolicy>,
+}
+
+/// Cross-origin settings declared by an app, the ones not declared are taken from the gateway.
+///
+/// An app can only narrow the policy of the gateway: origins and methods the gateway doesn't
+/// allow are ignored, and credentials are only allowed if the gateway allows them too.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct CorsPolicy {
+    pub allowed_origins: Option<Vec<String>>,
+    pub allowed_methods: Option<Vec<String>>,
+    pub allow_credentials: Option<bool>,
+}
+
 #[non_exhaustive]
 pub struct WebApp {
     pub metadata: Vec<u8>,
@@ -30,9 +81,16 @@ impl WebApp {
         web: Builder<Cursor<Vec<u8>>>,
     ) -> Result<Self, WebContractError> {
         let buf = web.into_inner().unwrap().into_inner();
-        let mut encoder = XzEncoder::new(Cursor::new(buf), 6);
+        Self::from_tar(metadata, buf)
+    }
+
+    /// Packs an already built tar archive of the web interface.
+    pub fn from_tar(metadata: Vec<u8>, tar: Vec<u8>) -> Result<Self, WebContractError> {
+        let mut encoder = XzEncoder::new(Cursor::new(tar), 6);
         let mut compressed = vec![];
-        encoder.read_to_end(&mut compressed).unwrap();
+        encoder
+            .read_to_end(&mut compressed)
+            .map_err(WebContractError::StoringError)?;
         Ok(Self {
             metadata,
             web: compressed,
@@ -79,12 +137,104 @@ impl WebApp {
         Err(WebContractError::FileNotFound(path.to_owned()))
     }
 
+    /// Content type overrides declared in the bundle metadata, keyed by lowercase file extension.
+    ///
+    /// The metadata is expected to be a TOML document with a `[mime-types]` table, e.g.:
+    /// ```toml
+    /// [mime-types]
+    /// wasm = "application/wasm"
+    /// webmanifest = "application/manifest+json"
+    /// ```
+    /// Metadata which is not TOML or which lacks the table yields no overrides.
+    pub fn mime_overrides(&self) -> HashMap<String, String> {
+        parse_mime_overrides(&self.metadata)
+    }
+
+    /// Manifest declared in the bundle metadata.
+    pub fn manifest(&self) -> AppManifest {
+        parse_manifest(&self.metadata)
+    }
+
     fn decode_web(&self) -> Archive<XzDecoder<&[u8]>> {
         let decoder = XzDecoder::new(self.web.as_slice());
         Archive::new(decoder)
     }
 }
 
+pub(crate) fn parse_mime_overrides(metadata: &[u8]) -> HashMap<String, String> {
+    let Some(table) = std::str::from_utf8(metadata)
+        .ok()
+        .and_then(|md| md.parse::<toml::Table>().ok())
+    else {
+        return HashMap::new();
+    };
+    table
+        .get(MIME_TYPES_KEY)
+        .and_then(|v| v.as_table())
+        .map(|types| {
+            types
+                .iter()
+                .filter_map(|(ext, mime)| {
+                    let ext = ext.trim_start_matches('.').to_ascii_lowercase();
+                    Some((ext, mime.as_str()?.to_owned()))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub(crate) fn parse_manifest(metadata: &[u8]) -> AppManifest {
+    let manifest = std::str::from_utf8(metadata)
+        .ok()
+        .and_then(|md| md.parse::<toml::Table>().ok())
+        .and_then(|mut table| table.remove(MANIFEST_KEY));
+    let Some(manifest) = manifest else {
+        return AppManifest {
+            auth_token: true,
+            ..Default::default()
+        };
+    };
+    manifest.try_into().unwrap_or_else(|err| {
+        tracing::warn!("malformed app manifest: {err}");
+        AppManifest::default()
+    })
+}
+
+/// Checks the metadata of a state is well formed, if the state is a web app bundle.
+///
+/// Malformed metadata is otherwise only noticed once the app is served, and then ignored.
+/// States which are not bundles are not checked.
+pub(crate) fn check_bundle(state: &[u8]) -> Result<(), WebContractError> {
+    const XZ_MAGIC: &[u8] = b"\xFD7zXZ\0";
+    let Ok(app) = WebApp::try_from(state) else {
+        return Ok(());
+    };
+    if app.metadata.is_empty() || !app.web.starts_with(XZ_MAGIC) {
+        return Ok(());
+    }
+    let malformed = |cause: String| WebContractError::UnpackingError(anyhow::anyhow!(cause));
+    let mut table = std::str::from_utf8(&app.metadata)
+        .map_err(|err| malformed(format!("metadata is not UTF-8: {err}")))?
+        .parse::<toml::Table>()
+        .map_err(|err| malformed(format!("metadata is not TOML: {err}")))?;
+    if let Some(types) = table.get(MIME_TYPES_KEY) {
+        let well_formed = types
+            .as_table()
+            .is_some_and(|types| types.values().all(|mime| mime.is_str()));
+        if !well_formed {
+            return Err(malformed(format!(
+                "`{MIME_TYPES_KEY}` is not a table of content types"
+            )));
+        }
+    }
+    if let Some(manifest) = table.remove(MANIFEST_KEY) {
+        let _: AppManifest = manifest
+            .try_into()
+            .map_err(|err| malformed(format!("malformed manifest: {err}")))?;
+    }
+    Ok(())
+}
+
 impl<'a> TryFrom<&'a [u8]> for WebApp {
     type Error = WebContractError;
 
@@ -103,6 +253,12 @@ impl<'a> TryFrom<&'a [u8]> for WebApp {
                 metadata_size
             )));
         }
+        if metadata_size > remaining(&state) {
+            return Err(WebContractError::UnpackingError(anyhow::anyhow!(
+                "Truncated metadata: {} bytes declared",
+                metadata_size
+            )));
+        }
         let mut metadata = vec![0; metadata_size as usize];
         state
             .read_exact(&mut metadata)
@@ -117,6 +273,13 @@ impl<'a> TryFrom<&'a [u8]> for WebApp {
                 web_size
             )));
         }
+        // don't trust the declared size before allocating for it
+        if web_size > remaining(&state) {
+            return Err(WebContractError::UnpackingError(anyhow::anyhow!(
+                "Truncated packed web: {} bytes declared",
+                web_size
+            )));
+        }
         let mut web = vec![0; web_size as usize];
         state
             .read_exact(&mut web)
@@ -125,3 +288,87 @@ impl<'a> TryFrom<&'a [u8]> for WebApp {
         Ok(Self { metadata, web })
     }
 }
+
+fn remaining(state: &Cursor<&[u8]>) -> u64 {
+    (state.get_ref().len() as u64).saturating_sub(state.position())
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn bundle() -> Vec<u8> {
+        bundle_with_metadata(b"[manifest]\nspa-fallback = true")
+    }
+
+    fn bundle_with_metadata(metadata: &[u8]) -> Vec<u8> {
+        let mut archive = Builder::new(Cursor::new(Vec::new()));
+        let index = b"<html></html>";
+        let mut header = tar::Header::new_gnu();
+        header.set_size(index.len() as u64);
+        header.set_cksum();
+        archive
+            .append_data(&mut header, "index.html", index.as_slice())
+            .unwrap();
+        WebApp::from_data(metadata.to_vec(), archive)
+            .unwrap()
+            .pack()
+            .unwrap()
+    }
+
+    #[test]
+    fn parse_malformed_bundles() {
+        let bundle = bundle();
+        let mut app = WebApp::try_from(bundle.as_slice()).unwrap();
+        assert!(app.manifest().spa_fallback);
+        assert_eq!(app.get_file("index.html").unwrap(), b"<html></html>");
+
+        // errors are fine, panics are not
+        for len in 0..bundle.len() {
+            if let Ok(mut app) = WebApp::try_from(&bundle[..len]) {
+                let _ = app.get_file("index.html");
+            }
+        }
+        assert!(check_bundle(b"not a bundle").is_ok());
+
+        // sizes over the actual content are rejected before allocating
+        let mut oversized = 0u64.to_be_bytes().to_vec();
+        oversized.extend((50 * 1024 * 1024u64).to_be_bytes());
+        assert!(WebApp::try_from(oversized.as_slice()).is_err());
+
+        // bundles are checked before being put, and malformed metadata rejected
+        assert!(check_bundle(&bundle).is_ok());
+        assert!(
+            check_bundle(&bundle_with_metadata(b"[manifest]\nspa-fallback = \"yes\"")).is_err()
+        );
+        assert!(check_bundle(&bundle_with_metadata(b"[mime-types]\nwasm = 1")).is_err());
+        assert!(check_bundle(&bundle_with_metadata(b"not = toml = at all")).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn parse_corrupted_bundles(
+            flips in prop::collection::vec((any::<prop::sample::Index>(), any::<u8>()), 1..16),
+        ) {
+            let mut corrupted = bundle();
+            for (index, byte) in flips {
+                let index = index.index(corrupted.len());
+                corrupted[index] ^= byte;
+            }
+            // errors are fine, panics are not
+            if let Ok(mut app) = WebApp::try_from(corrupted.as_slice()) {
+                let _ = app.manifest();
+                let _ = app.get_file("index.html");
+            }
+            let _ = check_bundle(&corrupted);
+        }
+
+        #[test]
+        fn parse_arbitrary_bundles(data in prop::collection::vec(any::<u8>(), 0..2048)) {
+            let _ = WebApp::try_from(data.as_slice());
+            let _ = check_bundle(&data);
+        }
+    }
+}
@@ -0,0 +1,334 @@
+//! Cross-origin resource sharing (CORS) for the gateway endpoints.
+//!
+//! Same-origin requests are left as is. Requests from the origins allowed by the operator get
+//! the CORS headers, and their preflight requests are answered directly. The routes of a web app
+//! (`/v1/contract/web/<contract>/...`) can further restrict the policy in the app manifest, which
+//! is cached for a short time rather than read on every request.
+//!
+//! Browsers don't apply CORS to websockets, so upgrades from any origin not explicitly allowed are
+//! rejected.
+
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{Request, State},
+    http::{
+        header::{
+            ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+            ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
+            ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS,
+            ACCESS_CONTROL_REQUEST_METHOD, HOST, ORIGIN, UPGRADE, VARY,
+        },
+        HeaderMap, HeaderValue, Method, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use dashmap::DashMap;
+use freenet_stdlib::prelude::ContractKey;
+
+use crate::config::CorsConfig;
+
+use super::{app_packaging::CorsPolicy, errors::WebSocketApiError, path_handlers};
+
+/// Time browsers may cache the answer to a preflight request.
+const PREFLIGHT_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+/// Response headers readable by cross-origin apps, besides the CORS safelisted ones.
+const EXPOSED_HEADERS: &str = "etag, x-freenet-required-delegates";
+/// Time the CORS policy of an app manifest is cached.
+const MANIFEST_TTL: Duration = Duration::from_secs(30);
+/// Apps whose CORS policy is cached at once.
+const MAX_CACHED_MANIFESTS: usize = 256;
+
+/// Applies the CORS policy to every route of the gateway, served over TLS if `tls`. The manifests
+/// of the web apps are read from `webs_dir`.
+pub(super) fn with_cors(
+    router: axum::Router,
+    config: CorsConfig,
+    tls: bool,
+    webs_dir: PathBuf,
+) -> axum::Router {
+    let cors = Cors {
+        config: Arc::new(config),
+        scheme: if tls { "https" } else { "http" },
+        webs_dir: Arc::new(webs_dir),
+        manifests: Arc::default(),
+    };
+    router.layer(axum::middleware::from_fn_with_state(cors, apply))
+}
+
+#[derive(Clone)]
+struct Cors {
+    config: Arc<CorsConfig>,
+    /// Scheme the gateway is served with.
+    scheme: &'static str,
+    /// Directory the web apps are unpacked to.
+    webs_dir: Arc<PathBuf>,
+    manifests: Arc<DashMap<ContractKey, (Instant, Option<CorsPolicy>)>>,
+}
+
+impl Cors {
+    /// CORS policy declared by the manifest of the app.
+    async fn app_policy(&self, key: ContractKey) -> Option<CorsPolicy> {
+        let now = Instant::now();
+        if let Some(cached) = self.manifests.get(&key) {
+            let (cached_at, policy) = &*cached;
+            if now.duration_since(*cached_at) < MANIFEST_TTL {
+                return policy.clone();
+            }
+        }
+        let policy = path_handlers::read_manifest(&self.webs_dir, &key)
+            .await
+            .cors;
+        if self.manifests.len() >= MAX_CACHED_MANIFESTS {
+            self.manifests
+                .retain(|_, (cached_at, _)| now.duration_since(*cached_at) < MANIFEST_TTL);
+            if self.manifests.len() >= MAX_CACHED_MANIFESTS {
+                self.manifests.clear();
+            }
+        }
+        self.manifests.insert(key, (now, policy.clone()));
+        policy
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Policy {
+    allowed_origins: Vec<String>,
+    allowed_methods: Vec<String>,
+    allow_credentials: bool,
+}
+
+impl Policy {
+    /// Policy of the gateway, restricted by the one declared by an app if any.
+    fn new(config: &CorsConfig, overrides: Option<CorsPolicy>) -> Self {
+        let node = Self {
+            allowed_origins: config.allowed_origins.clone(),
+            allowed_methods: config.allowed_methods.clone(),
+            allow_credentials: config.allow_credentials,
+        };
+        let Some(overrides) = overrides else {
+            return node;
+        };
+        let allowed_origins = match overrides.allowed_origins {
+            Some(origins) if !origins.iter().any(|origin| origin == "*") => origins
+                .into_iter()
+                .filter(|origin| node.allows_origin(origin.trim_end_matches('/')))
+                .collect(),
+            _ => node.allowed_origins.clone(),
+        };
+        let allowed_methods = match overrides.allowed_methods {
+            Some(methods) => methods
+                .into_iter()
+                .filter(|method| node.allows_method(method))
+                .collect(),
+            None => node.allowed_methods.clone(),
+        };
+        Self {
+            allowed_origins,
+            allowed_methods,
+            allow_credentials: node.allow_credentials
+                && overrides.allow_credentials.unwrap_or(true),
+        }
+    }
+
+    fn any_origin(&self) -> bool {
+        self.allowed_origins.iter().any(|allowed| allowed == "*")
+    }
+
+    fn allows_origin(&self, origin: &str) -> bool {
+        self.any_origin()
+            || self
+                .allowed_origins
+                .iter()
+                .any(|allowed| allowed.trim_end_matches('/').eq_ignore_ascii_case(origin))
+    }
+
+    fn allows_method(&self, method: &str) -> bool {
+        self.allowed_methods
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(method))
+    }
+
+    /// Headers granting access to a response to the origin.
+    fn grant(&self, origin: &HeaderValue, headers: &mut HeaderMap) {
+        // the wildcard can't be used along with credentials, so the origin is echoed instead
+        let allowed_origin = if self.any_origin() && !self.allow_credentials {
+            HeaderValue::from_static("*")
+        } else {
+            origin.clone()
+        };
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, allowed_origin);
+        if self.allow_credentials {
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+        headers.append(VARY, HeaderValue::from_static("origin"));
+    }
+}
+
+async fn apply(State(cors): State<Cors>, req: Request, next: Next) -> Response {
+    let Some(origin) = req.headers().get(ORIGIN).cloned() else {
+        return next.run(req).await;
+    };
+    let origin_str = origin.to_str().unwrap_or_default();
+    if same_origin(origin_str, cors.scheme, req.headers()) {
+        return next.run(req).await;
+    }
+    let overrides = match web_app_contract(req.uri().path()) {
+        Some(key) => cors.app_policy(key).await,
+        None => None,
+    };
+    let policy = Policy::new(&cors.config, overrides);
+    let allowed = policy.allows_origin(origin_str);
+
+    if is_websocket_upgrade(req.headers()) {
+        if !allowed {
+            tracing::debug!(origin = origin_str, "rejected cross-origin websocket");
+            return forbidden(origin_str);
+        }
+        return next.run(req).await;
+    }
+
+    if req.method() == Method::OPTIONS {
+        if let Some(method) = req.headers().get(ACCESS_CONTROL_REQUEST_METHOD) {
+            let method_allowed = method
+                .to_str()
+                .is_ok_and(|method| policy.allows_method(method));
+            if !allowed || !method_allowed {
+                return forbidden(origin_str);
+            }
+            return preflight(&policy, &origin, req.headers());
+        }
+    }
+
+    let mut response = next.run(req).await;
+    if allowed {
+        let headers = response.headers_mut();
+        policy.grant(&origin, headers);
+        headers.insert(
+            ACCESS_CONTROL_EXPOSE_HEADERS,
+            HeaderValue::from_static(EXPOSED_HEADERS),
+        );
+    }
+    response
+}
+
+fn preflight(policy: &Policy, origin: &HeaderValue, request_headers: &HeaderMap) -> Response {
+    let mut response = StatusCode::NO_CONTENT.into_response();
+    let headers = response.headers_mut();
+    policy.grant(origin, headers);
+    if let Ok(methods) = HeaderValue::from_str(&policy.allowed_methods.join(", ")) {
+        headers.insert(ACCESS_CONTROL_ALLOW_METHODS, methods);
+    }
+    if let Some(requested) = request_headers.get(ACCESS_CONTROL_REQUEST_HEADERS) {
+        headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, requested.clone());
+    }
+    headers.insert(
+        ACCESS_CONTROL_MAX_AGE,
+        HeaderValue::from(PREFLIGHT_MAX_AGE.as_secs()),
+    );
+    response
+}
+
+fn forbidden(origin: &str) -> Response {
+    WebSocketApiError::Forbidden {
+        error_cause: format!("origin {origin} is not allowed"),
+    }
+    .into_response()
+}
+
+/// Whether the origin is the gateway itself, e.g. an app served by it, served with `scheme`.
+fn same_origin(origin: &str, scheme: &str, headers: &HeaderMap) -> bool {
+    let Some(host) = headers.get(HOST).and_then(|host| host.to_str().ok()) else {
+        return false;
+    };
+    origin
+        .split_once("://")
+        .is_some_and(|(origin_scheme, authority)| {
+            origin_scheme.eq_ignore_ascii_case(scheme) && authority.eq_ignore_ascii_case(host)
+        })
+}
+
+fn is_websocket_upgrade(headers: &HeaderMap) -> bool {
+    headers
+        .get(UPGRADE)
+        .and_then(|upgrade| upgrade.to_str().ok())
+        .is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"))
+}
+
+/// Contract of the web app a route belongs to, if any.
+fn web_app_contract(path: &str) -> Option<ContractKey> {
+    let key = path.strip_prefix("/v1/contract/web/")?.split('/').next()?;
+    ContractKey::from_id(key.to_owned()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policy_overrides() {
+        let config = CorsConfig {
+            allowed_origins: vec!["https://app.example.org/".to_owned()],
+            ..Default::default()
+        };
+        let policy = Policy::new(&config, None);
+        assert!(policy.allows_origin("https://APP.example.org"));
+        assert!(!policy.allows_origin("https://evil.example.org"));
+        assert!(policy.allows_method("get"));
+        assert!(!policy.allows_method("DELETE"));
+
+        // apps can't widen the policy of the gateway
+        let policy = Policy::new(
+            &config,
+            Some(CorsPolicy {
+                allowed_origins: Some(vec!["*".to_owned(), "https://evil.example.org".to_owned()]),
+                allowed_methods: Some(vec!["GET".to_owned(), "DELETE".to_owned()]),
+                allow_credentials: Some(true),
+            }),
+        );
+        assert!(!policy.allows_origin("https://evil.example.org"));
+        assert!(policy.allows_origin("https://app.example.org"));
+        assert_eq!(policy.allowed_methods, vec!["GET".to_owned()]);
+        assert!(!policy.allow_credentials);
+
+        // but can narrow it
+        let config = CorsConfig {
+            allowed_origins: vec!["*".to_owned()],
+            allow_credentials: true,
+            ..Default::default()
+        };
+        let policy = Policy::new(
+            &config,
+            Some(CorsPolicy {
+                allowed_origins: Some(vec!["https://app.example.org".to_owned()]),
+                ..Default::default()
+            }),
+        );
+        assert!(policy.allows_origin("https://app.example.org"));
+        assert!(!policy.allows_origin("https://evil.example.org"));
+        let origin = HeaderValue::from_static("https://app.example.org");
+        let mut headers = HeaderMap::new();
+        Policy::new(&config, None).grant(&origin, &mut headers);
+        // credentials can't be granted to the wildcard, so the origin is echoed
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], origin);
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+    }
+
+    #[test]
+    fn request_origin() {
+        let mut headers = HeaderMap::new();
+        headers.insert(HOST, HeaderValue::from_static("localhost:50509"));
+        assert!(same_origin("http://localhost:50509", "http", &headers));
+        assert!(!same_origin("http://localhost:3000", "http", &headers));
+        assert!(!same_origin("http://localhost:50509", "https", &headers));
+        assert!(web_app_contract("/v1/contract/command").is_none());
+    }
+}
@@ -1,11 +1,18 @@
-use axum::http::StatusCode;
-use axum::response::{Html, IntoResponse, Response};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
 use freenet_stdlib::client_api::ErrorKind;
 use freenet_stdlib::prelude::ContractKey;
+use serde::Serialize;
 use std::fmt::{Display, Formatter};
 
+use crate::client_events::limits::PayloadTooLarge;
+use crate::client_events::node_queries::NodeQueryError;
+use crate::contract::storages::index::ContractCandidate;
+use crate::node::load_shedding::Overloaded;
+
 #[derive(Debug)]
-pub(super) enum WebSocketApiError {
+pub(crate) enum WebSocketApiError {
     /// Something went wrong when calling the user repo.
     InvalidParam {
         error_cause: String,
@@ -19,18 +26,172 @@ pub(super) enum WebSocketApiError {
     MissingContract {
         key: ContractKey,
     },
+    /// The request is not allowed by the app manifest.
+    Forbidden {
+        error_cause: String,
+    },
+    PayloadTooLarge(PayloadTooLarge),
+    /// A truncated contract id matches several stored contracts.
+    AmbiguousContract {
+        prefix: String,
+        candidates: Vec<ContractCandidate>,
+        matches: usize,
+    },
+    /// The node is shedding load, the request should be retried later.
+    Overloaded(Overloaded),
+    /// The client holds as many of the resource requested as it is allowed to.
+    TooManyRequests {
+        error_cause: String,
+    },
+}
+
+/// Stable, machine readable code of the errors returned by the HTTP gateway.
+///
+/// Codes are part of the API: new ones may be added, but existing ones are never renamed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorCode {
+    InvalidParam,
+    Forbidden,
+    MissingContract,
+    /// The contract id prefix matches several contracts, the candidates are listed.
+    AmbiguousContract,
+    /// The request, or one of the payloads it carries, is over the limits of the node.
+    PayloadTooLarge,
+    /// The node could not be reached, or dropped the request.
+    NodeUnavailable,
+    /// The operation could not be completed by the network.
+    NetworkError,
+    /// The operation was rejected by the node, e.g. because the contract failed executing.
+    OperationFailed,
+    /// Internal failure of the gateway.
+    NodeError,
+    /// The node is overloaded, the request should be retried after the time told.
+    Overloaded,
+    /// The client, or all of them, hold too many of the resource requested, like update streams.
+    TooManyRequests,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorCategory {
+    /// The request is invalid, it should not be retried as is.
+    Client,
+    Permission,
+    Node,
+    Network,
+}
+
+impl ErrorCode {
+    pub fn category(self) -> ErrorCategory {
+        match self {
+            ErrorCode::InvalidParam
+            | ErrorCode::MissingContract
+            | ErrorCode::AmbiguousContract
+            | ErrorCode::PayloadTooLarge
+            | ErrorCode::TooManyRequests => ErrorCategory::Client,
+            ErrorCode::Forbidden => ErrorCategory::Permission,
+            ErrorCode::NodeUnavailable
+            | ErrorCode::OperationFailed
+            | ErrorCode::NodeError
+            | ErrorCode::Overloaded => ErrorCategory::Node,
+            ErrorCode::NetworkError => ErrorCategory::Network,
+        }
+    }
+
+    /// Whether the same request may succeed if retried later.
+    pub fn retryable(self) -> bool {
+        matches!(
+            self,
+            ErrorCode::NodeUnavailable
+                | ErrorCode::NetworkError
+                | ErrorCode::Overloaded
+                | ErrorCode::TooManyRequests
+        )
+    }
+}
+
+/// Body of the error responses.
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    code: ErrorCode,
+    category: ErrorCategory,
+    retryable: bool,
+    message: String,
+    /// Seconds after which the request can be retried, for overloaded nodes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_after: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    candidates: Option<Vec<ContractCandidate>>,
 }
 
 impl WebSocketApiError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            WebSocketApiError::InvalidParam { .. } => ErrorCode::InvalidParam,
+            WebSocketApiError::NodeError { .. } => ErrorCode::NodeError,
+            WebSocketApiError::AxumError { error } => match error {
+                ErrorKind::NodeUnavailable
+                | ErrorKind::ChannelClosed
+                | ErrorKind::Disconnect
+                | ErrorKind::TransportProtocolDisconnect
+                | ErrorKind::Shutdown => ErrorCode::NodeUnavailable,
+                ErrorKind::FailedOperation => ErrorCode::NetworkError,
+                _ => ErrorCode::OperationFailed,
+            },
+            WebSocketApiError::MissingContract { .. } => ErrorCode::MissingContract,
+            WebSocketApiError::Forbidden { .. } => ErrorCode::Forbidden,
+            WebSocketApiError::PayloadTooLarge(_) => ErrorCode::PayloadTooLarge,
+            WebSocketApiError::AmbiguousContract { .. } => ErrorCode::AmbiguousContract,
+            WebSocketApiError::Overloaded(_) => ErrorCode::Overloaded,
+            WebSocketApiError::TooManyRequests { .. } => ErrorCode::TooManyRequests,
+        }
+    }
+
     pub fn status_code(&self) -> StatusCode {
+        match self.code() {
+            ErrorCode::InvalidParam => StatusCode::BAD_REQUEST,
+            ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+            ErrorCode::MissingContract => StatusCode::NOT_FOUND,
+            ErrorCode::AmbiguousContract => StatusCode::MULTIPLE_CHOICES,
+            ErrorCode::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::NodeUnavailable | ErrorCode::Overloaded => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::NetworkError => StatusCode::BAD_GATEWAY,
+            ErrorCode::OperationFailed | ErrorCode::NodeError => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Body of the error, as returned to HTTP clients and, serialized as JSON, in the cause of
+    /// the errors sent to websocket clients.
+    fn body(&self) -> ErrorBody {
+        let code = self.code();
+        ErrorBody {
+            code,
+            category: code.category(),
+            retryable: code.retryable(),
+            message: self.error_message(),
+            retry_after: self.retry_after(),
+            candidates: match self {
+                WebSocketApiError::AmbiguousContract { candidates, .. } => Some(candidates.clone()),
+                _ => None,
+            },
+        }
+    }
+
+    /// Seconds after which the request can be retried, if told.
+    fn retry_after(&self) -> Option<u64> {
         match self {
-            WebSocketApiError::InvalidParam { .. } => StatusCode::BAD_REQUEST,
-            WebSocketApiError::NodeError { .. } => StatusCode::INTERNAL_SERVER_ERROR,
-            WebSocketApiError::AxumError { .. } => StatusCode::INTERNAL_SERVER_ERROR,
-            WebSocketApiError::MissingContract { .. } => StatusCode::NOT_FOUND,
+            WebSocketApiError::Overloaded(error) => Some(error.retry_after.as_secs()),
+            _ => None,
         }
     }
 
+    /// The error as the JSON body websocket clients can tell the kind of rejection from.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&self.body()).unwrap_or_else(|_| self.error_message())
+    }
+
     pub fn error_message(&self) -> String {
         match self {
             WebSocketApiError::InvalidParam { error_cause } => {
@@ -39,6 +200,15 @@ impl WebSocketApiError {
             WebSocketApiError::NodeError { error_cause } => format!("Node error: {}", error_cause),
             WebSocketApiError::AxumError { error } => format!("Server error: {}", error),
             WebSocketApiError::MissingContract { key } => format!("Missing contract {key}"),
+            WebSocketApiError::Forbidden { error_cause } => format!("Forbidden: {error_cause}"),
+            WebSocketApiError::PayloadTooLarge(error) => format!("{error}"),
+            WebSocketApiError::AmbiguousContract {
+                prefix, matches, ..
+            } => format!("Contract id prefix `{prefix}` matches {matches} contracts"),
+            WebSocketApiError::Overloaded(error) => format!("{error}"),
+            WebSocketApiError::TooManyRequests { error_cause } => {
+                format!("Too many requests: {error_cause}")
+            }
         }
     }
 }
@@ -49,37 +219,73 @@ impl Display for WebSocketApiError {
     }
 }
 
+impl From<NodeQueryError> for WebSocketApiError {
+    fn from(error: NodeQueryError) -> Self {
+        match error {
+            NodeQueryError::NodeUnavailable | NodeQueryError::Timeout => {
+                WebSocketApiError::AxumError {
+                    error: ErrorKind::NodeUnavailable,
+                }
+            }
+            NodeQueryError::Failed(error_cause) => WebSocketApiError::NodeError { error_cause },
+        }
+    }
+}
+
 impl From<WebSocketApiError> for Response {
     fn from(error: WebSocketApiError) -> Self {
-        let body = Html(error.error_message());
-        (error.status_code(), body).into_response()
+        error.into_response()
     }
 }
 
 impl IntoResponse for WebSocketApiError {
     fn into_response(self) -> Response {
-        let (status, error_message) = match self {
-            WebSocketApiError::InvalidParam { error_cause } => {
-                (StatusCode::BAD_REQUEST, error_cause)
-            }
-            WebSocketApiError::NodeError { error_cause }
-                if error_cause.starts_with("Contract not found") =>
-            {
-                (StatusCode::NOT_FOUND, error_cause)
-            }
-            WebSocketApiError::NodeError { error_cause } => {
-                (StatusCode::INTERNAL_SERVER_ERROR, error_cause)
-            }
-            err @ WebSocketApiError::MissingContract { .. } => {
-                (StatusCode::NOT_FOUND, err.error_message())
-            }
-            WebSocketApiError::AxumError { error } => {
-                (StatusCode::INTERNAL_SERVER_ERROR, format!("{error}"))
-            }
+        let retry_after = self.retry_after();
+        let mut response = (self.status_code(), Json(self.body())).into_response();
+        if let Some(seconds) = retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, seconds.into());
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::node::load_shedding::Pressure;
+
+    #[test]
+    fn error_taxonomy() {
+        let error = WebSocketApiError::AxumError {
+            error: ErrorKind::NodeUnavailable,
         };
+        assert_eq!(error.code(), ErrorCode::NodeUnavailable);
+        assert_eq!(error.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(error.code().retryable());
 
-        let body = Html(error_message);
+        let error = WebSocketApiError::InvalidParam {
+            error_cause: "bad key".into(),
+        };
+        assert_eq!(error.code().category(), ErrorCategory::Client);
+        assert!(!error.code().retryable());
+        let body: serde_json::Value = serde_json::from_str(&error.to_json()).unwrap();
+        assert_eq!(body["code"], "invalid-param");
+        assert_eq!(body["category"], "client");
+        assert_eq!(body["retryable"], false);
+        assert!(body.get("retry_after").is_none());
 
-        (status, body).into_response()
+        let error = WebSocketApiError::Overloaded(Overloaded {
+            pressure: Pressure::QueuedEvents { queued: 2, max: 1 },
+            retry_after: Duration::from_secs(5),
+        });
+        let body: serde_json::Value = serde_json::from_str(&error.to_json()).unwrap();
+        assert_eq!(body["code"], "overloaded");
+        assert_eq!(body["retry_after"], 5);
+        let response = error.into_response();
+        assert_eq!(response.headers()[header::RETRY_AFTER], "5");
     }
 }
@@ -1,20 +1,31 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
 use axum::extract::Path;
 use axum::response::IntoResponse;
-use axum::routing::get;
-use axum::{Extension, Router};
+use axum::routing::{get, post};
+use axum::{Extension, Json, Router};
 use freenet_stdlib::client_api::{ClientError, ErrorKind, HostResponse};
-use freenet_stdlib::prelude::ContractInstanceId;
+use freenet_stdlib::prelude::{ContractInstanceId, ContractKey};
 use futures::future::BoxFuture;
 use futures::FutureExt;
-use tokio::sync::mpsc;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, watch};
 
+use crate::client_events::node_queries::NodeQuerySender;
+use crate::client_events::scoped_tokens::{TokenOperation, TokenScopes};
 use crate::client_events::{ClientEventsProxy, ClientId, OpenRequest};
+use crate::config::AccessLogConfig;
+use crate::node::load_shedding::QueuedEvents;
 use crate::server::HostCallbackResult;
 
-use super::{errors::WebSocketApiError, path_handlers, AuthToken, ClientConnection};
+use super::{
+    errors::WebSocketApiError, path_handlers, publish, virtual_hosts::VirtualHostRequest,
+    AuthToken, ClientConnection,
+};
 
 mod v1;
 
@@ -39,18 +50,35 @@ pub(crate) struct HttpGateway {
     pub attested_contracts: HashMap<AuthToken, (ContractInstanceId, ClientId)>,
     proxy_server_request: mpsc::Receiver<ClientConnection>,
     response_channels: HashMap<ClientId, mpsc::UnboundedSender<HostCallbackResult>>,
+    /// Events the server sheds load on, counted by the node.
+    pub(super) queued_events: Option<QueuedEvents>,
+    /// Policy of the access log of the server, set by the node.
+    pub(super) access_log: Option<watch::Sender<AccessLogConfig>>,
 }
 
 impl HttpGateway {
     /// Returns the uninitialized axum router to compose with other routing handling or websockets.
-    pub fn as_router(socket: &SocketAddr) -> (Self, Router) {
-        Self::as_router_v1(socket)
+    ///
+    /// Gateways not bound to localhost serve their web apps for the public `domain` they are
+    /// reached at. Auth cookies are only restricted to secure connections if served over `tls`.
+    /// Web apps are unpacked to `webs_dir`.
+    pub fn as_router(
+        socket: &SocketAddr,
+        domain: Option<&str>,
+        tls: bool,
+        webs_dir: PathBuf,
+    ) -> (Self, Router) {
+        Self::as_router_v1(socket, domain, tls, webs_dir)
     }
 }
 
 #[derive(Clone)]
 struct Config {
     localhost: bool,
+    domain: Option<String>,
+    tls: bool,
+    webs_dir: Arc<PathBuf>,
+    assets: Arc<path_handlers::WebAssets>,
 }
 
 async fn home() -> axum::response::Response {
@@ -115,4 +143,12 @@ impl ClientEventsProxy for HttpGateway {
         }
         .boxed()
     }
+
+    fn queued_events(&self) -> Option<QueuedEvents> {
+        self.queued_events.clone()
+    }
+
+    fn access_log(&mut self) -> Option<watch::Sender<AccessLogConfig>> {
+        self.access_log.take()
+    }
 }
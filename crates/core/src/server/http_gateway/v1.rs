@@ -2,24 +2,35 @@ use super::*;
 
 impl HttpGateway {
     /// Returns the uninitialized axum router to compose with other routing handling or websockets.
-    pub fn as_router_v1(socket: &SocketAddr) -> (Self, Router) {
+    pub fn as_router_v1(
+        socket: &SocketAddr,
+        domain: Option<&str>,
+        tls: bool,
+        webs_dir: PathBuf,
+    ) -> (Self, Router) {
         let localhost = match socket.ip() {
             IpAddr::V4(ip) if ip.is_loopback() => true,
             IpAddr::V6(ip) if ip.is_loopback() => true,
             _ => false,
         };
-        let contract_web_path = std::env::temp_dir().join("freenet").join("webs");
-        std::fs::create_dir_all(contract_web_path).unwrap();
-
         let (proxy_request_sender, request_to_server) = mpsc::channel(1);
 
-        let config = Config { localhost };
+        let config = Config {
+            localhost,
+            domain: domain.map(str::to_owned),
+            tls,
+            webs_dir: Arc::new(webs_dir),
+            assets: Arc::default(),
+        };
 
         let router = Router::new()
             .route("/v1", get(home))
             .route("/v1/contract/web/:key/", get(web_home))
-            .with_state(config)
             .route("/v1/contract/web/:key/*path", get(web_subpages))
+            .with_state(config)
+            .route("/v1/contract/stats/:key", get(contract_stats))
+            .route("/v1/contract/publish", post(publish::publish))
+            .route("/v1/token/delegate", post(delegate_token))
             .layer(Extension(HttpGatewayRequest(proxy_request_sender)));
 
         (
@@ -27,6 +38,8 @@ impl HttpGateway {
                 proxy_server_request: request_to_server,
                 attested_contracts: HashMap::new(),
                 response_channels: HashMap::new(),
+                queued_events: None,
+                access_log: None,
             },
             router,
         )
@@ -36,44 +49,217 @@ impl HttpGateway {
 async fn web_home(
     Path(key): Path<String>,
     Extension(rs): Extension<HttpGatewayRequest>,
+    Extension(scopes): Extension<TokenScopes>,
+    Extension(queries): Extension<NodeQuerySender>,
+    vhost: Option<Extension<VirtualHostRequest>>,
     axum::extract::State(config): axum::extract::State<Config>,
+    headers: axum::http::HeaderMap,
+) -> Result<axum::response::Response, WebSocketApiError> {
+    app_home(key, None, rs, scopes, queries, vhost, config, headers).await
+}
+
+/// Serves the home of an app, redirecting to the path of its current `version` unless that is
+/// the one requested.
+#[allow(clippy::too_many_arguments)]
+async fn app_home(
+    key: String,
+    version: Option<&str>,
+    rs: HttpGatewayRequest,
+    scopes: TokenScopes,
+    queries: NodeQuerySender,
+    vhost: Option<Extension<VirtualHostRequest>>,
+    config: Config,
+    headers: axum::http::HeaderMap,
 ) -> Result<axum::response::Response, WebSocketApiError> {
     use headers::{Header, HeaderMapExt};
 
-    let domain = config
-        .localhost
-        .then_some("localhost")
-        .expect("non-local connections not supported yet");
+    // apps served by a virtual host live at the root of its domain, which serves no other app
+    let (domain, path) = match &vhost {
+        Some(Extension(vhost)) => (vhost.host.as_str(), "/".to_owned()),
+        None => (
+            match &config.domain {
+                _ if config.localhost => "localhost",
+                Some(domain) => domain.as_str(),
+                None => {
+                    return Err(WebSocketApiError::NodeError {
+                        error_cause: "web apps are only served to remote clients for the \
+                            domain of the gateway"
+                            .to_owned(),
+                    })
+                }
+            },
+            format!("/v1/contract/web/{key}"),
+        ),
+    };
     let token = AuthToken::generate();
 
     let auth_header = headers::Authorization::<headers::authorization::Bearer>::name().to_string();
     let cookie = cookie::Cookie::build((auth_header, format!("Bearer {}", token.as_str())))
         .domain(domain)
-        .path(format!("/v1/contract/web/{key}"))
+        .path(path)
         .same_site(cookie::SameSite::Strict)
         .max_age(cookie::time::Duration::days(1))
-        .secure(!config.localhost)
+        .secure(config.tls)
         .http_only(false)
         .build();
 
-    let token_header = headers::Authorization::bearer(token.as_str()).unwrap();
-    let contract_idx = path_handlers::contract_home(key, rs, token).await?;
-    let mut response = contract_idx.into_response();
-    response.headers_mut().typed_insert(token_header);
-    response.headers_mut().insert(
-        headers::SetCookie::name(),
-        headers::HeaderValue::from_str(&cookie.to_string()).unwrap(),
-    );
+    let token_header = headers::Authorization::bearer(token.as_str()).map_err(|err| {
+        WebSocketApiError::NodeError {
+            error_cause: format!("invalid auth token: {err}"),
+        }
+    })?;
+    let if_none_match = headers.get(axum::http::header::IF_NONE_MATCH).cloned();
+    // truncated ids are redirected to the app of the full one, which is the one given a token
+    let contract = ContractInstanceId::try_from(key.clone());
+    let app_root = match &vhost {
+        Some(_) => String::new(),
+        None => format!("/v1/contract/web/{key}"),
+    };
+    let (mut response, manifest) = path_handlers::contract_home(
+        &config.webs_dir,
+        &config.assets,
+        key,
+        version,
+        &app_root,
+        rs,
+        &queries,
+        token.clone(),
+        if_none_match,
+    )
+    .await?;
+    // only apps which requested it in their manifest get the token
+    if let (true, Ok(contract)) = (manifest.auth_token, contract) {
+        scopes.assign(token, contract);
+        response.headers_mut().typed_insert(token_header);
+        let cookie = headers::HeaderValue::from_str(&cookie.to_string()).map_err(|err| {
+            WebSocketApiError::NodeError {
+                error_cause: format!("invalid auth cookie: {err}"),
+            }
+        })?;
+        response
+            .headers_mut()
+            .insert(headers::SetCookie::name(), cookie);
+    }
 
     Ok(response)
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn web_subpages(
     Path((key, last_path)): Path<(String, String)>,
+    Extension(rs): Extension<HttpGatewayRequest>,
+    Extension(scopes): Extension<TokenScopes>,
+    Extension(queries): Extension<NodeQuerySender>,
+    vhost: Option<Extension<VirtualHostRequest>>,
+    axum::extract::State(config): axum::extract::State<Config>,
+    headers: axum::http::HeaderMap,
 ) -> Result<axum::response::Response, WebSocketApiError> {
-    let full_path: String = format!("/v1/contract/web/{}/{}", key, last_path);
-    path_handlers::variable_content(key, full_path)
-        .await
-        .map_err(|e| *e)
-        .map(|r| r.into_response())
+    let (version, path) = path_handlers::split_version(&last_path);
+    if let (Some(version), "") = (version, path) {
+        return app_home(
+            key,
+            Some(version),
+            rs,
+            scopes,
+            queries,
+            vhost,
+            config,
+            headers,
+        )
+        .await;
+    }
+    let full_path: String = format!("/v1/contract/web/{}/{}", key, path);
+    let if_none_match = headers.get(axum::http::header::IF_NONE_MATCH).cloned();
+    let service_worker_scope = headers
+        .get("service-worker")
+        .is_some_and(|value| value == "script")
+        .then(|| match vhost {
+            Some(_) => "/".to_owned(),
+            None => format!("/v1/contract/web/{key}/"),
+        });
+    path_handlers::variable_content(
+        &config.webs_dir,
+        &config.assets,
+        key,
+        version,
+        full_path,
+        if_none_match,
+        service_worker_scope,
+    )
+    .await
+    .map_err(|e| *e)
+    .map(|r| r.into_response())
+}
+
+/// Execution statistics of a contract, so their authors can follow how it performs on the node.
+///
+/// Only the web app of the contract, or local clients, can get them.
+async fn contract_stats(
+    Path(key): Path<String>,
+    Extension(auth_token): Extension<Option<AuthToken>>,
+    Extension(scopes): Extension<TokenScopes>,
+) -> Result<Json<crate::wasm_runtime::ContractStatsReport>, WebSocketApiError> {
+    let id = ContractInstanceId::try_from(key).map_err(|err| WebSocketApiError::InvalidParam {
+        error_cause: format!("{err}"),
+    })?;
+    scopes
+        .authorize_owner(auth_token.as_ref(), &id)
+        .map_err(|err| WebSocketApiError::Forbidden {
+            error_cause: err.to_string(),
+        })?;
+    crate::wasm_runtime::contract_stats_of(&id)
+        .map(Json)
+        .ok_or_else(|| WebSocketApiError::MissingContract {
+            key: ContractKey::from(id),
+        })
+}
+
+#[derive(Deserialize)]
+struct DelegateTokenRequest {
+    contracts: Vec<String>,
+    operations: HashSet<TokenOperation>,
+    /// Lifetime of the token in seconds.
+    ttl: u64,
+}
+
+#[derive(Serialize)]
+struct DelegateTokenResponse {
+    token: String,
+    /// Seconds until the token expires, which may be less than requested.
+    expires_in: u64,
+}
+
+/// Mints a token restricted to some contracts and operations out of the bearer token, so web apps
+/// can hand it to the contract UIs they embed.
+async fn delegate_token(
+    Extension(scopes): Extension<TokenScopes>,
+    Extension(auth_token): Extension<Option<AuthToken>>,
+    Json(request): Json<DelegateTokenRequest>,
+) -> Result<Json<DelegateTokenResponse>, WebSocketApiError> {
+    let contracts = request
+        .contracts
+        .into_iter()
+        .map(|id| {
+            ContractInstanceId::try_from(id).map_err(|err| WebSocketApiError::InvalidParam {
+                error_cause: format!("{err}"),
+            })
+        })
+        .collect::<Result<HashSet<_>, _>>()?;
+    let parent = auth_token.ok_or_else(|| WebSocketApiError::Forbidden {
+        error_cause: "missing auth token".to_owned(),
+    })?;
+    let (token, expires_in) = scopes
+        .delegate(
+            &parent,
+            contracts,
+            request.operations,
+            Duration::from_secs(request.ttl),
+        )
+        .map_err(|err| WebSocketApiError::Forbidden {
+            error_cause: format!("{err}"),
+        })?;
+    Ok(Json(DelegateTokenResponse {
+        token: token.as_str().to_owned(),
+        expires_in: expires_in.as_secs(),
+    }))
 }
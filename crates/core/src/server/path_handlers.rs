@@ -1,18 +1,42 @@
 //! Handle the `web` part of the bundles.
+//!
+//! Web apps are served under a path naming the version of their web state, e.g.
+//! `/v1/contract/web/{key}/~{version}/`, where the home of the app redirects to. The assets of
+//! the app, requested relative to its home, so under the same versioned path, never change and
+//! are cached as immutable. Once the web state of the contract is updated the home redirects to
+//! the new version. Assets requested under an outdated version or without version are still
+//! served, but must be revalidated by clients against their ETag.
 
-use std::path::{Path, PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
 
-use axum::response::{Html, IntoResponse};
+use axum::{
+    body::Body,
+    http::{
+        header::{CACHE_CONTROL, CONTENT_LENGTH, CONTENT_SECURITY_POLICY, ETAG},
+        HeaderValue, StatusCode,
+    },
+    response::{Html, IntoResponse, Response},
+};
 use freenet_stdlib::{
-    client_api::{ClientRequest, ContractRequest, ContractResponse, HostResponse},
+    client_api::{ClientRequest, ContractRequest, ContractResponse, ErrorKind, HostResponse},
     prelude::*,
 };
 use tokio::{fs::File, io::AsyncReadExt, sync::mpsc};
 
-use crate::client_events::AuthToken;
+use crate::{
+    client_events::{
+        node_queries::{NodeQueryKind, NodeQueryResult, NodeQuerySender},
+        AuthToken,
+    },
+    contract::storages::index::{self, ContractCandidate, Resolution},
+};
 
 use super::{
-    app_packaging::{WebApp, WebContractError},
+    app_packaging::{parse_manifest, parse_mime_overrides, AppManifest, WebApp, WebContractError},
     errors::WebSocketApiError,
     http_gateway::HttpGatewayRequest,
     ClientConnection, HostCallbackResult,
@@ -22,16 +46,162 @@ mod v1;
 
 const ALPHABET: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
 
+/// Assets served under the current version of the app never change.
+const VERSIONED_ASSET_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+/// Assets served without version, or under an outdated one, change along with the web state of
+/// the contract, so clients must revalidate their copy against the ETag before every use.
+const ASSET_CACHE_CONTROL: &str = "public, no-cache";
+/// The app entry point is revalidated often so app updates are picked up quickly, and is
+/// private since it comes along with the client auth token.
+const INDEX_CACHE_CONTROL: &str = "private, max-age=60, must-revalidate";
+
+/// Delegates required by an app, as declared in its manifest, so they can be installed by the
+/// user before the app is used.
+const REQUIRED_DELEGATES_HEADER: &str = "x-freenet-required-delegates";
+const SERVICE_WORKER_ALLOWED_HEADER: &str = "service-worker-allowed";
+
+/// Prefix of the path segment naming the version of an app.
+const VERSION_PREFIX: char = '~';
+/// Maximum number of apps whose version and asset ETags are cached.
+const MAX_CACHED_APPS: usize = 256;
+/// Maximum number of asset ETags cached per app.
+const MAX_CACHED_ASSETS: usize = 1024;
+
+/// Versions and asset ETags of the web apps served by a gateway, only for the apps served most
+/// recently.
+#[derive(Default)]
+pub(super) struct WebAssets(parking_lot::Mutex<CachedApps>);
+
+#[derive(Default)]
+struct CachedApps {
+    apps: HashMap<ContractInstanceId, CachedApp>,
+    /// Requests served so far, which orders them.
+    served: u64,
+}
+
+#[derive(Default)]
+struct CachedApp {
+    last_served: u64,
+    version: Option<String>,
+    /// Strong ETags of the assets, recomputed whenever the file is modified.
+    etags: HashMap<PathBuf, (SystemTime, HeaderValue)>,
+}
+
+impl CachedApps {
+    fn app(&mut self, id: &ContractInstanceId) -> &mut CachedApp {
+        self.served += 1;
+        if !self.apps.contains_key(id) && self.apps.len() >= MAX_CACHED_APPS {
+            let least_recent = self
+                .apps
+                .iter()
+                .min_by_key(|(_, app)| app.last_served)
+                .map(|(id, _)| *id);
+            if let Some(least_recent) = least_recent {
+                self.apps.remove(&least_recent);
+            }
+        }
+        let app = self.apps.entry(*id).or_default();
+        app.last_served = self.served;
+        app
+    }
+}
+
+impl WebAssets {
+    /// The version of the web state unpacked for the contract, if any.
+    async fn version(&self, webs_dir: &Path, key: &ContractKey) -> Option<String> {
+        if let Some(version) = self
+            .0
+            .lock()
+            .apps
+            .get(key.id())
+            .and_then(|app| app.version.clone())
+        {
+            return Some(version);
+        }
+        let version = tokio::fs::read_to_string(contract_version_path(webs_dir, key))
+            .await
+            .ok()?;
+        self.0.lock().app(key.id()).version = Some(version.clone());
+        Some(version)
+    }
+
+    /// Records the version of a newly unpacked web state, forgetting the ETags of the previous.
+    fn unpacked(&self, key: &ContractKey, version: String) {
+        let mut apps = self.0.lock();
+        let app = apps.app(key.id());
+        app.version = Some(version);
+        app.etags.clear();
+    }
+
+    async fn etag(&self, key: &ContractKey, path: &Path) -> Option<HeaderValue> {
+        let modified = tokio::fs::metadata(path).await.ok()?.modified().ok()?;
+        if let Some((at, etag)) = self
+            .0
+            .lock()
+            .apps
+            .get(key.id())
+            .and_then(|app| app.etags.get(path))
+        {
+            if *at == modified {
+                return Some(etag.clone());
+            }
+        }
+        let content = tokio::fs::read(path).await.ok()?;
+        let etag = etag(&content);
+        let mut apps = self.0.lock();
+        let etags = &mut apps.app(key.id()).etags;
+        if etags.len() >= MAX_CACHED_ASSETS && !etags.contains_key(path) {
+            etags.clear();
+        }
+        etags.insert(path.to_owned(), (modified, etag.clone()));
+        Some(etag)
+    }
+}
+
+/// Splits the path of a request under an app into the version it names, if any, and the path
+/// within the app.
+pub(super) fn split_version(path: &str) -> (Option<&str>, &str) {
+    match path.strip_prefix(VERSION_PREFIX) {
+        Some(versioned) => match versioned.split_once('/') {
+            Some((version, rest)) => (Some(version), rest),
+            None => (Some(versioned), ""),
+        },
+        None => (None, path),
+    }
+}
+
+/// Version of a web state, naming the path its app is served under.
+fn web_version(state: &[u8]) -> String {
+    let hash = blake3::hash(state);
+    bs58::encode(&hash.as_bytes()[..16]).into_string()
+}
+
+/// Serves the home of the app of a contract, at `app_root`, redirecting to the path of the
+/// current version of the app unless `version` is the current one.
+#[allow(clippy::too_many_arguments)]
 pub(super) async fn contract_home(
+    webs_dir: &Path,
+    assets: &WebAssets,
     key: String,
+    version: Option<&str>,
+    app_root: &str,
     request_sender: HttpGatewayRequest,
+    queries: &NodeQuerySender,
     assigned_token: AuthToken,
-) -> Result<impl IntoResponse, WebSocketApiError> {
-    let key = ContractKey::from_id(key)
-        .map_err(|err| WebSocketApiError::InvalidParam {
-            error_cause: format!("{err}"),
-        })
-        .unwrap();
+    if_none_match: Option<HeaderValue>,
+) -> Result<(Response, AppManifest), WebSocketApiError> {
+    let key = match ContractKey::from_id(key.clone()) {
+        Ok(key) => key,
+        Err(_) => {
+            // send the browser to the app of the contract the truncated id identifies
+            let contract = resolve_prefix(queries, &key).await?;
+            let location = format!("/v1/contract/web/{}/", contract.id);
+            return Ok((
+                axum::response::Redirect::temporary(&location).into_response(),
+                AppManifest::default(),
+            ));
+        }
+    };
     let (response_sender, mut response_recv) = mpsc::unbounded_channel();
     request_sender
         .send(ClientConnection::NewConnection {
@@ -39,10 +209,7 @@ pub(super) async fn contract_home(
             assigned_token: Some((assigned_token, key.into())),
         })
         .await
-        .map_err(|err| WebSocketApiError::NodeError {
-            error_cause: format!("{err}"),
-        })
-        .unwrap();
+        .map_err(node_unavailable)?;
     let client_id = if let Some(HostCallbackResult::NewId { id }) = response_recv.recv().await {
         id
     } else {
@@ -63,10 +230,7 @@ pub(super) async fn contract_home(
             auth_token: None,
         })
         .await
-        .map_err(|err| WebSocketApiError::NodeError {
-            error_cause: format!("{err}"),
-        })
-        .unwrap();
+        .map_err(node_unavailable)?;
     let response = match response_recv.recv().await {
         Some(HostCallbackResult::Result {
             result:
@@ -77,50 +241,93 @@ pub(super) async fn contract_home(
                 })),
             ..
         }) => match contract {
+            // the contract was upgraded, send the browser to its latest version; not permanently,
+            // as a later version can still replace it
+            Some(contract) if contract.key().id() != key.id() => {
+                let location =
+                    format!("/v1/contract/web/{}/", contract.key().encoded_contract_id());
+                (
+                    axum::response::Redirect::temporary(&location).into_response(),
+                    AppManifest::default(),
+                )
+            }
             Some(contract) => {
                 let key = contract.key();
-                let path = contract_web_path(&key);
-                let web_body = match get_web_body(&path).await {
-                    Ok(b) => b.into_response(),
-                    Err(err) => match err {
-                        WebSocketApiError::NodeError {
-                            error_cause: _cause,
-                        } => {
-                            let state = State::from(state.as_ref());
-
-                            fn err(
-                                err: WebContractError,
-                                contract: &ContractContainer,
-                            ) -> WebSocketApiError {
-                                let key = contract.key();
-                                tracing::error!("{err}");
-                                WebSocketApiError::InvalidParam {
-                                    error_cause: format!("failed unpacking contract: {key}"),
-                                }
-                            }
+                let path = contract_web_path(webs_dir, &key);
+                let current = web_version(state.as_ref());
+                if version != Some(current.as_str()) {
+                    let location = format!(
+                        "{}/{VERSION_PREFIX}{current}/",
+                        app_root.trim_end_matches('/')
+                    );
+                    return disconnect(&request_sender, client_id).await.map(|_| {
+                        (
+                            axum::response::Redirect::temporary(&location).into_response(),
+                            AppManifest::default(),
+                        )
+                    });
+                }
+                let unpacked = assets.version(webs_dir, &key).await;
+                let index_body = match get_web_body(&path).await {
+                    Ok(b) if unpacked.as_deref() == Some(current.as_str()) => b,
+                    Ok(_)
+                    | Err(WebSocketApiError::NodeError {
+                        error_cause: _cause,
+                    }) => {
+                        let state = State::from(state.as_ref());
 
-                            let mut web = WebApp::try_from(state.as_ref())
-                                .map_err(|e| err(e, &contract))
-                                .unwrap();
-                            web.unpack(path).map_err(|e| err(e, &contract)).unwrap();
-                            let index = web
-                                .get_file("index.html")
-                                .map_err(|e| err(e, &contract))
-                                .unwrap();
-                            let index_body = String::from_utf8(index).map_err(|err| {
-                                WebSocketApiError::NodeError {
-                                    error_cause: format!("{err}"),
-                                }
-                            })?;
-                            Html(index_body).into_response()
+                        fn err(
+                            err: WebContractError,
+                            contract: &ContractContainer,
+                        ) -> WebSocketApiError {
+                            let key = contract.key();
+                            tracing::error!("{err}");
+                            WebSocketApiError::InvalidParam {
+                                error_cause: format!("failed unpacking contract: {key}"),
+                            }
                         }
-                        other => {
-                            tracing::error!("{other}");
-                            return Err(other);
+                        let io_err = |err: std::io::Error| WebSocketApiError::NodeError {
+                            error_cause: format!("{err}"),
+                        };
+
+                        let mut web =
+                            WebApp::try_from(state.as_ref()).map_err(|e| err(e, &contract))?;
+                        // the previous version of the app is replaced as a whole
+                        match tokio::fs::remove_dir_all(&path).await {
+                            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                                return Err(io_err(e))
+                            }
+                            _ => {}
                         }
-                    },
+                        tokio::fs::create_dir_all(&path).await.map_err(io_err)?;
+                        web.unpack(path).map_err(|e| err(e, &contract))?;
+                        tokio::fs::write(contract_metadata_path(webs_dir, &key), &web.metadata)
+                            .await
+                            .map_err(io_err)?;
+                        tokio::fs::write(contract_version_path(webs_dir, &key), &current)
+                            .await
+                            .map_err(io_err)?;
+                        assets.unpacked(&key, current);
+                        let index = web.get_file("index.html").map_err(|e| err(e, &contract))?;
+                        String::from_utf8(index).map_err(|err| WebSocketApiError::NodeError {
+                            error_cause: format!("{err}"),
+                        })?
+                    }
+                    Err(other) => {
+                        tracing::error!("{other}");
+                        return Err(other);
+                    }
                 };
-                web_body
+                let manifest = read_manifest(webs_dir, &key).await;
+                let etag = etag(index_body.as_bytes());
+                let mut response = cached_response(
+                    Html(index_body).into_response(),
+                    Some(etag),
+                    INDEX_CACHE_CONTROL,
+                    if_none_match.as_ref(),
+                );
+                apply_manifest(&mut response, &manifest);
+                (response, manifest)
             }
             None => {
                 return Err(WebSocketApiError::MissingContract { key });
@@ -135,12 +342,25 @@ pub(super) async fn contract_home(
             });
         }
         None => {
+            return Err(WebSocketApiError::AxumError {
+                error: ErrorKind::NodeUnavailable,
+            });
+        }
+        Some(other) => {
+            tracing::error!("received unexpected node response: {other:?}");
             return Err(WebSocketApiError::NodeError {
-                error_cause: format!("Contract not found: {key}"),
+                error_cause: format!("unexpected response getting contract `{key}`"),
             });
         }
-        other => unreachable!("received unexpected node response: {other:?}"),
     };
+    disconnect(&request_sender, client_id).await?;
+    Ok(response)
+}
+
+async fn disconnect(
+    request_sender: &HttpGatewayRequest,
+    client_id: crate::client_events::ClientId,
+) -> Result<(), WebSocketApiError> {
     request_sender
         .send(ClientConnection::Request {
             client_id,
@@ -148,45 +368,205 @@ pub(super) async fn contract_home(
             auth_token: None,
         })
         .await
-        .map_err(|err| WebSocketApiError::NodeError {
-            error_cause: format!("{err}"),
+        .map_err(node_unavailable)
+}
+
+/// Resolves a truncated contract id to the contract stored by the node it identifies, listing
+/// the candidates if it is ambiguous.
+pub(crate) async fn resolve_prefix(
+    queries: &NodeQuerySender,
+    prefix: &str,
+) -> Result<ContractCandidate, WebSocketApiError> {
+    let prefix = prefix.trim();
+    if prefix.len() < index::MIN_PREFIX_LEN || !prefix.chars().all(|c| ALPHABET.contains(c)) {
+        return Err(WebSocketApiError::InvalidParam {
+            error_cause: format!("`{prefix}` is not a contract id nor a prefix of one"),
+        });
+    }
+    let resolution = match queries
+        .query(NodeQueryKind::ResolvePrefix {
+            prefix: prefix.to_owned(),
         })
-        .unwrap();
-    Ok(response)
+        .await?
+    {
+        NodeQueryResult::Resolution(resolution) => resolution,
+        other => {
+            return Err(WebSocketApiError::NodeError {
+                error_cause: format!("unexpected node query result: {other:?}"),
+            })
+        }
+    };
+    match resolution {
+        Resolution::Unique(contract) => Ok(contract),
+        Resolution::Ambiguous {
+            candidates,
+            matches,
+        } => Err(WebSocketApiError::AmbiguousContract {
+            prefix: prefix.to_owned(),
+            candidates,
+            matches,
+        }),
+        Resolution::NotFound => Err(WebSocketApiError::InvalidParam {
+            error_cause: format!(
+                "no contract stored by the node has an id starting with `{prefix}`"
+            ),
+        }),
+    }
+}
+
+pub(super) fn node_unavailable<T>(err: mpsc::error::SendError<T>) -> WebSocketApiError {
+    tracing::error!("failed sending request to the node: {err}");
+    WebSocketApiError::AxumError {
+        error: ErrorKind::NodeUnavailable,
+    }
 }
 
+/// Serves an asset of the app of a contract, as immutable if requested under the `version` of
+/// the app currently unpacked.
 pub(super) async fn variable_content(
+    webs_dir: &Path,
+    assets: &WebAssets,
     key: String,
+    version: Option<&str>,
     req_path: String,
+    if_none_match: Option<HeaderValue>,
+    service_worker_scope: Option<String>,
 ) -> Result<impl IntoResponse, Box<WebSocketApiError>> {
     // compose the correct absolute path
     let key = ContractKey::from_id(key).map_err(|err| WebSocketApiError::InvalidParam {
         error_cause: format!("{err}"),
     })?;
-    let base_path = contract_web_path(&key);
+    let base_path = contract_web_path(webs_dir, &key);
     let req_uri = req_path
         .parse()
         .map_err(|err| WebSocketApiError::NodeError {
             error_cause: format!("{err}"),
         })?;
-    let file_path = base_path.join(get_file_path(req_uri)?);
+    let mut file_path = base_path.join(get_file_path(req_uri)?);
+
+    let metadata = tokio::fs::read(contract_metadata_path(webs_dir, &key))
+        .await
+        .unwrap_or_default();
+    let manifest = parse_manifest(&metadata);
+    if service_worker_scope.is_some() && !manifest.service_worker {
+        return Err(Box::new(WebSocketApiError::Forbidden {
+            error_cause: format!("contract {key} is not allowed to register service workers"),
+        }));
+    }
+    if manifest.spa_fallback
+        && file_path.extension().is_none()
+        && !tokio::fs::try_exists(&file_path).await.unwrap_or(false)
+    {
+        file_path = base_path.join("web").join("index.html");
+    }
 
-    // serve the file
-    let mut serve_file = tower_http::services::fs::ServeFile::new(&file_path);
+    // serve the file, with the content type declared in the bundle metadata if any
+    let overrides = parse_mime_overrides(&metadata);
+    let mut serve_file = match mime_type(&file_path, &overrides)
+        .and_then(|mime| axum::http::HeaderValue::from_str(mime).ok())
+    {
+        Some(mime) => tower_http::services::fs::ServeFile::new_with_mime(&file_path, &mime),
+        None => tower_http::services::fs::ServeFile::new(&file_path),
+    };
     let fake_req = axum::http::Request::new(axum::body::Body::empty());
-    serve_file
+    let response = serve_file
         .try_call(fake_req)
         .await
-        .map_err(|err| {
-            WebSocketApiError::NodeError {
-                error_cause: format!("{err}"),
-            }
-            .into()
-        })
-        .map(|r| r.into_response())
+        .map_err(|err| WebSocketApiError::NodeError {
+            error_cause: format!("{err}"),
+        })?
+        .into_response();
+    let cache_control = if file_path
+        .file_name()
+        .is_some_and(|name| name == "index.html")
+    {
+        INDEX_CACHE_CONTROL
+    } else if version.is_some() && assets.version(webs_dir, &key).await.as_deref() == version {
+        VERSIONED_ASSET_CACHE_CONTROL
+    } else {
+        ASSET_CACHE_CONTROL
+    };
+    let etag = assets.etag(&key, &file_path).await;
+    let mut response = cached_response(response, etag, cache_control, if_none_match.as_ref());
+    apply_manifest(&mut response, &manifest);
+    if let Some(scope) = service_worker_scope {
+        // scope service workers to the app, whatever the path of their script
+        if let Ok(scope) = HeaderValue::from_str(&scope) {
+            response
+                .headers_mut()
+                .insert(SERVICE_WORKER_ALLOWED_HEADER, scope);
+        }
+    }
+    Ok(response)
+}
+
+pub(super) async fn read_manifest(webs_dir: &Path, key: &ContractKey) -> AppManifest {
+    let metadata = tokio::fs::read(contract_metadata_path(webs_dir, key))
+        .await
+        .unwrap_or_default();
+    parse_manifest(&metadata)
+}
+
+/// Emits the headers declared in the app manifest.
+fn apply_manifest(response: &mut Response, manifest: &AppManifest) {
+    let headers = response.headers_mut();
+    if let Some(csp) = manifest
+        .content_security_policy
+        .as_deref()
+        .and_then(|csp| HeaderValue::from_str(csp).ok())
+    {
+        headers.insert(CONTENT_SECURITY_POLICY, csp);
+    }
+    if !manifest.required_delegates.is_empty() {
+        if let Ok(delegates) = HeaderValue::from_str(&manifest.required_delegates.join(",")) {
+            headers.insert(REQUIRED_DELEGATES_HEADER, delegates);
+        }
+    }
+}
+
+fn etag(content: &[u8]) -> HeaderValue {
+    let hash = bs58::encode(blake3::hash(content).as_bytes()).into_string();
+    HeaderValue::from_str(&format!("\"{hash}\"")).expect("base58 is a valid header value")
 }
 
-async fn get_web_body(path: &Path) -> Result<impl IntoResponse, WebSocketApiError> {
+/// Sets the caching headers of a successful response, turning it into a `304 Not Modified`
+/// when the client already holds the same version.
+fn cached_response(
+    mut response: Response,
+    etag: Option<HeaderValue>,
+    cache_control: &'static str,
+    if_none_match: Option<&HeaderValue>,
+) -> Response {
+    if !response.status().is_success() {
+        return response;
+    }
+    let headers = response.headers_mut();
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static(cache_control));
+    let Some(etag) = etag else {
+        return response;
+    };
+    let not_modified = if_none_match
+        .and_then(|tags| tags.to_str().ok())
+        .is_some_and(|tags| etag_matches(tags, &etag));
+    headers.insert(ETAG, etag);
+    if not_modified {
+        headers.remove(CONTENT_LENGTH);
+        *response.status_mut() = StatusCode::NOT_MODIFIED;
+        *response.body_mut() = Body::empty();
+    }
+    response
+}
+
+/// Whether any of the tags in an `If-None-Match` header matches the etag, using the weak
+/// comparison as mandated for `If-None-Match`.
+fn etag_matches(if_none_match: &str, etag: &HeaderValue) -> bool {
+    if_none_match
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag.as_bytes() == etag.as_bytes())
+}
+
+async fn get_web_body(path: &Path) -> Result<String, WebSocketApiError> {
     let web_path = path.join("web").join("index.html");
     let mut key_file = File::open(&web_path)
         .await
@@ -203,18 +583,189 @@ async fn get_web_body(path: &Path) -> Result<impl IntoResponse, WebSocketApiErro
     let body = String::from_utf8(buf).map_err(|err| WebSocketApiError::NodeError {
         error_cause: format!("{err}"),
     })?;
-    Ok(Html(body))
+    Ok(body)
+}
+
+fn contract_web_path(webs_dir: &Path, key: &ContractKey) -> PathBuf {
+    webs_dir.join(key.encoded_contract_id()).join("web")
 }
 
-fn contract_web_path(key: &ContractKey) -> PathBuf {
-    std::env::temp_dir()
-        .join("freenet")
-        .join("webs")
-        .join(key.encoded_contract_id())
-        .join("web")
+fn contract_metadata_path(webs_dir: &Path, key: &ContractKey) -> PathBuf {
+    webs_dir.join(key.encoded_contract_id()).join("metadata")
+}
+
+fn contract_version_path(webs_dir: &Path, key: &ContractKey) -> PathBuf {
+    webs_dir.join(key.encoded_contract_id()).join("version")
+}
+
+/// Resolves the content type for a bundle asset, giving precedence to the overrides declared
+/// in the bundle metadata. Returns `None` when the extension is unknown, in which case the
+/// content type is guessed by the file server.
+fn mime_type<'a>(path: &Path, overrides: &'a HashMap<String, String>) -> Option<&'a str> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    if let Some(mime) = overrides.get(&ext) {
+        return Some(mime.as_str());
+    }
+    let mime = match ext.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "js" | "mjs" => "text/javascript",
+        "css" => "text/css",
+        "wasm" => "application/wasm",
+        "json" | "map" => "application/json",
+        "webmanifest" => "application/manifest+json",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "txt" => "text/plain; charset=utf-8",
+        _ => return None,
+    };
+    Some(mime)
 }
 
 #[inline]
 fn get_file_path(uri: axum::http::Uri) -> Result<String, Box<WebSocketApiError>> {
     v1::get_file_path(uri)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mime_type_defaults() {
+        let overrides = HashMap::new();
+        assert_eq!(
+            mime_type(Path::new("web/app_bg.wasm"), &overrides),
+            Some("application/wasm")
+        );
+        assert_eq!(
+            mime_type(Path::new("web/index.JS"), &overrides),
+            Some("text/javascript")
+        );
+        assert_eq!(
+            mime_type(Path::new("web/site.webmanifest"), &overrides),
+            Some("application/manifest+json")
+        );
+        assert_eq!(mime_type(Path::new("web/data.bin"), &overrides), None);
+        assert_eq!(mime_type(Path::new("web/LICENSE"), &overrides), None);
+    }
+
+    #[test]
+    fn mime_type_overrides() {
+        let metadata = br#"
+            [mime-types]
+            js = "application/javascript"
+            ".bin" = "application/x-custom"
+        "#;
+        let overrides = parse_mime_overrides(metadata);
+        assert_eq!(
+            mime_type(Path::new("web/index.js"), &overrides),
+            Some("application/javascript")
+        );
+        assert_eq!(
+            mime_type(Path::new("web/data.bin"), &overrides),
+            Some("application/x-custom")
+        );
+        assert!(parse_mime_overrides(b"not toml at all [").is_empty());
+    }
+
+    #[test]
+    fn versioned_paths() {
+        assert_eq!(
+            split_version("~3xQ/assets/app.js"),
+            (Some("3xQ"), "assets/app.js")
+        );
+        assert_eq!(split_version("~3xQ/"), (Some("3xQ"), ""));
+        assert_eq!(split_version("~3xQ"), (Some("3xQ"), ""));
+        assert_eq!(split_version("assets/app.js"), (None, "assets/app.js"));
+        assert_ne!(web_version(b"web state"), web_version(b"updated web state"));
+    }
+
+    #[test]
+    fn bounded_cached_apps() {
+        let mut cached = CachedApps::default();
+        let ids: Vec<_> = (0..=MAX_CACHED_APPS as u8)
+            .map(|i| ContractInstanceId::new([i; 32]))
+            .collect();
+        for id in &ids[..MAX_CACHED_APPS] {
+            cached.app(id).version = Some(id.to_string());
+        }
+        // the first app is served again, so the second is the least recently served
+        cached.app(&ids[0]);
+        cached.app(&ids[MAX_CACHED_APPS]);
+        assert_eq!(cached.apps.len(), MAX_CACHED_APPS);
+        assert!(cached.apps.contains_key(&ids[0]));
+        assert!(!cached.apps.contains_key(&ids[1]));
+    }
+
+    #[test]
+    fn cache_headers() {
+        let tag = etag(b"bundle asset");
+        let response = cached_response(
+            Response::new(Body::from("bundle asset")),
+            Some(tag.clone()),
+            ASSET_CACHE_CONTROL,
+            None,
+        );
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[ETAG], tag);
+        assert_eq!(response.headers()[CACHE_CONTROL], ASSET_CACHE_CONTROL);
+
+        let if_none_match =
+            HeaderValue::from_str(&format!("\"other\", W/{}", tag.to_str().unwrap())).unwrap();
+        let response = cached_response(
+            Response::new(Body::from("bundle asset")),
+            Some(tag.clone()),
+            ASSET_CACHE_CONTROL,
+            Some(&if_none_match),
+        );
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[ETAG], tag);
+
+        let mut not_found = Response::new(Body::empty());
+        *not_found.status_mut() = StatusCode::NOT_FOUND;
+        let response = cached_response(not_found, Some(tag), ASSET_CACHE_CONTROL, None);
+        assert!(response.headers().get(CACHE_CONTROL).is_none());
+    }
+
+    #[test]
+    fn app_manifest() {
+        let legacy = parse_manifest(b"[mime-types]\njs = \"text/javascript\"");
+        assert!(legacy.auth_token);
+        assert!(!legacy.service_worker);
+
+        let metadata = br#"
+            [manifest]
+            spa-fallback = true
+            required-delegates = ["D1", "D2"]
+            content-security-policy = "default-src 'self'"
+        "#;
+        let manifest = parse_manifest(metadata);
+        assert!(!manifest.auth_token);
+        assert!(manifest.spa_fallback);
+
+        let mut response = Response::new(Body::empty());
+        apply_manifest(&mut response, &manifest);
+        assert_eq!(
+            response.headers()[CONTENT_SECURITY_POLICY],
+            "default-src 'self'"
+        );
+        assert_eq!(response.headers()[REQUIRED_DELEGATES_HEADER], "D1,D2");
+        assert!(manifest.cors.is_none());
+
+        let manifest = parse_manifest(b"[manifest.cors]\nallowed-origins = [\"https://a.org\"]");
+        let cors = manifest.cors.unwrap();
+        assert_eq!(cors.allowed_origins, Some(vec!["https://a.org".to_owned()]));
+        assert_eq!(cors.allow_credentials, None);
+
+        // a malformed manifest grants nothing
+        let manifest = parse_manifest(b"[manifest]\nauth-token = \"yes\"");
+        assert_eq!(manifest, AppManifest::default());
+    }
+}
@@ -0,0 +1,344 @@
+//! Publishing of contracts through the HTTP gateway, so they can be put without a custom client.
+//!
+//! Contracts are uploaded to `POST /v1/contract/publish` as a `multipart/form-data` body with
+//! the parts:
+//! - `code`: the contract WASM module, required.
+//! - `parameters`: the contract parameters, empty if missing.
+//! - `state`: the initial state, empty if missing. If a web app is sent, the metadata of the
+//!   bundle instead.
+//! - `web`: a tar archive of a web app, packed along with the metadata into the initial state.
+//!
+//! The contract is put through the node like any client put. Clients accepting
+//! `text/event-stream` are sent the progress of the put as server-sent events, ending with either
+//! a `published` event carrying the contract key or a `failed` one; other clients are answered
+//! with the contract key once published.
+//!
+//! Uploads are bounded by the maximum body size of the gateway, which has to be raised to publish
+//! large contracts.
+//!
+//! Browsers send multipart forms to any origin without asking, so requests carrying an `Origin`
+//! header must come with an auth token allowed to put the contract, like the one the gateway
+//! hands to the web apps it serves. Clients which aren't browsers don't need one on this machine.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{multipart::MultipartRejection, Multipart},
+    http::{
+        header::{ACCEPT, ORIGIN},
+        HeaderMap,
+    },
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    Extension, Json,
+};
+use freenet_stdlib::{
+    client_api::{ClientRequest, ContractRequest, ContractResponse, ErrorKind, HostResponse},
+    prelude::*,
+};
+use futures::StreamExt;
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use crate::{
+    client_events::scoped_tokens::{TokenOperation, TokenScopes},
+    config::PayloadLimits,
+};
+
+use super::{
+    app_packaging::{WebApp, WebContractError},
+    errors::{ErrorCode, WebSocketApiError},
+    http_gateway::HttpGatewayRequest,
+    path_handlers::node_unavailable,
+    AuthToken, ClientConnection, HostCallbackResult,
+};
+
+const CODE_PART: &str = "code";
+const PARAMETERS_PART: &str = "parameters";
+const STATE_PART: &str = "state";
+const WEB_PART: &str = "web";
+
+/// Progress of a publication, sent as server-sent events named after the variant.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+enum Progress {
+    /// The upload was read and packed into a contract.
+    Received {
+        key: String,
+        code_size: usize,
+        state_size: usize,
+    },
+    /// The put was handed to the node.
+    Submitted,
+    Published {
+        key: String,
+    },
+    Failed {
+        code: ErrorCode,
+        message: String,
+    },
+}
+
+impl Progress {
+    fn name(&self) -> &'static str {
+        match self {
+            Progress::Received { .. } => "received",
+            Progress::Submitted => "submitted",
+            Progress::Published { .. } => "published",
+            Progress::Failed { .. } => "failed",
+        }
+    }
+
+    fn event(&self) -> Result<Event, axum::Error> {
+        Event::default().event(self.name()).json_data(self)
+    }
+}
+
+#[derive(Serialize)]
+struct PublishResponse {
+    key: String,
+}
+
+pub(super) async fn publish(
+    Extension(request_sender): Extension<HttpGatewayRequest>,
+    Extension(limits): Extension<PayloadLimits>,
+    Extension(auth_token): Extension<Option<AuthToken>>,
+    Extension(scopes): Extension<TokenScopes>,
+    headers: HeaderMap,
+    multipart: Result<Multipart, MultipartRejection>,
+) -> Result<Response, WebSocketApiError> {
+    if headers.contains_key(ORIGIN) && auth_token.is_none() {
+        return Err(WebSocketApiError::Forbidden {
+            error_cause: "publishing from a browser requires an auth token".into(),
+        });
+    }
+    let multipart = multipart.map_err(|err| WebSocketApiError::InvalidParam {
+        error_cause: err.body_text(),
+    })?;
+    let (contract, state) = read_upload(multipart).await?;
+    scopes
+        .authorize_operation(
+            auth_token.as_ref(),
+            Some((TokenOperation::Put, Some(*contract.key().id()))),
+        )
+        .map_err(|err| WebSocketApiError::Forbidden {
+            error_cause: err.to_string(),
+        })?;
+    let received = Progress::Received {
+        key: contract.key().encoded_contract_id(),
+        code_size: contract.code().data().len(),
+        state_size: state.size(),
+    };
+    let request = ClientRequest::from(ContractRequest::Put {
+        contract: ContractContainer::Wasm(ContractWasmAPIVersion::V1(contract)),
+        state,
+        related_contracts: RelatedContracts::default(),
+    });
+    limits
+        .check_request(&request)
+        .map_err(WebSocketApiError::PayloadTooLarge)?;
+
+    let streams_progress = headers
+        .get(ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/event-stream"));
+    if !streams_progress {
+        let key = put(request_sender, request, auth_token, |_| {}).await?;
+        return Ok(Json(PublishResponse {
+            key: key.encoded_contract_id(),
+        })
+        .into_response());
+    }
+
+    let (progress, events) = futures::channel::mpsc::unbounded();
+    let _ = progress.unbounded_send(received);
+    tokio::spawn(async move {
+        let outcome = match put(request_sender, request, auth_token, |p| {
+            let _ = progress.unbounded_send(p);
+        })
+        .await
+        {
+            Ok(key) => Progress::Published {
+                key: key.encoded_contract_id(),
+            },
+            Err(err) => Progress::Failed {
+                code: err.code(),
+                message: err.error_message(),
+            },
+        };
+        let _ = progress.unbounded_send(outcome);
+    });
+    Ok(Sse::new(events.map(|progress| progress.event()))
+        .keep_alive(KeepAlive::default())
+        .into_response())
+}
+
+/// Reads the uploaded parts into the contract and its initial state.
+async fn read_upload(
+    mut multipart: Multipart,
+) -> Result<(WrappedContract, WrappedState), WebSocketApiError> {
+    let invalid = |error_cause: String| WebSocketApiError::InvalidParam { error_cause };
+    let (mut code, mut parameters, mut state, mut web) = (None, vec![], vec![], None);
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|err| invalid(format!("malformed multipart body: {err}")))?
+    {
+        let name = field.name().unwrap_or_default().to_owned();
+        let data = field
+            .bytes()
+            .await
+            .map_err(|err| invalid(format!("failed reading part `{name}`: {err}")))?
+            .to_vec();
+        match name.as_str() {
+            CODE_PART => code = Some(data),
+            PARAMETERS_PART => parameters = data,
+            STATE_PART => state = data,
+            WEB_PART => web = Some(data),
+            _ => return Err(invalid(format!("unexpected part `{name}`"))),
+        }
+    }
+    let code = code.ok_or_else(|| invalid(format!("missing `{CODE_PART}` part")))?;
+    if let Some(web) = web {
+        state = WebApp::from_tar(state, web)
+            .and_then(|app| app.pack().map_err(WebContractError::StoringError))
+            .map_err(|err| invalid(format!("failed packing web app: {err}")))?;
+    }
+    let contract = WrappedContract::new(
+        Arc::new(ContractCode::from(code)),
+        Parameters::from(parameters),
+    );
+    Ok((contract, WrappedState::new(state)))
+}
+
+/// Puts the contract through the node, returning its key once published.
+async fn put(
+    request_sender: HttpGatewayRequest,
+    request: ClientRequest<'static>,
+    auth_token: Option<AuthToken>,
+    progress: impl Fn(Progress),
+) -> Result<ContractKey, WebSocketApiError> {
+    let (response_sender, mut response_recv) = mpsc::unbounded_channel();
+    request_sender
+        .send(ClientConnection::NewConnection {
+            callbacks: response_sender,
+            assigned_token: None,
+        })
+        .await
+        .map_err(node_unavailable)?;
+    let Some(HostCallbackResult::NewId { id: client_id }) = response_recv.recv().await else {
+        return Err(WebSocketApiError::NodeError {
+            error_cause: "Couldn't register new client in the node".into(),
+        });
+    };
+    request_sender
+        .send(ClientConnection::Request {
+            client_id,
+            req: Box::new(request),
+            auth_token: auth_token.clone(),
+        })
+        .await
+        .map_err(node_unavailable)?;
+    progress(Progress::Submitted);
+    let outcome = match response_recv.recv().await {
+        Some(HostCallbackResult::Result {
+            result: Ok(HostResponse::ContractResponse(ContractResponse::PutResponse { key })),
+            ..
+        }) => Ok(key),
+        Some(HostCallbackResult::Result {
+            result: Err(err), ..
+        }) => Err(WebSocketApiError::AxumError {
+            error: err.kind().clone(),
+        }),
+        Some(other) => Err(WebSocketApiError::NodeError {
+            error_cause: format!("unexpected response to put: {other:?}"),
+        }),
+        None => Err(WebSocketApiError::AxumError {
+            error: ErrorKind::NodeUnavailable,
+        }),
+    };
+    let disconnect = request_sender
+        .send(ClientConnection::Request {
+            client_id,
+            req: Box::new(ClientRequest::Disconnect { cause: None }),
+            auth_token,
+        })
+        .await;
+    if disconnect.is_err() {
+        tracing::debug!(%client_id, "Node gone before disconnecting publishing client");
+    }
+    outcome
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, extract::FromRequest, http::Request};
+
+    use super::*;
+
+    async fn upload(parts: &[(&str, &[u8])]) -> Result<(WrappedContract, WrappedState), String> {
+        const BOUNDARY: &str = "publish-boundary";
+        let mut body = vec![];
+        for (name, data) in parts {
+            body.extend(
+                format!("--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n")
+                    .as_bytes(),
+            );
+            body.extend(*data);
+            body.extend(b"\r\n");
+        }
+        body.extend(format!("--{BOUNDARY}--\r\n").as_bytes());
+        let request = Request::post("/v1/contract/publish")
+            .header(
+                "content-type",
+                format!("multipart/form-data; boundary={BOUNDARY}"),
+            )
+            .body(Body::from(body))
+            .unwrap();
+        let multipart = Multipart::from_request(request, &()).await.unwrap();
+        read_upload(multipart)
+            .await
+            .map_err(|err| err.error_message())
+    }
+
+    #[tokio::test]
+    async fn read_uploaded_contract() {
+        let (contract, state) = upload(&[
+            ("code", b"\0asm"),
+            ("parameters", b"params"),
+            ("state", b"s"),
+        ])
+        .await
+        .unwrap();
+        assert_eq!(contract.code().data(), b"\0asm");
+        assert_eq!(contract.params().as_ref(), b"params");
+        assert_eq!(state.as_ref(), b"s");
+
+        // web apps are packed into the state, along with their metadata
+        let mut archive = tar::Builder::new(vec![]);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_cksum();
+        archive
+            .append_data(&mut header, "index.html", b"<p/>".as_slice())
+            .unwrap();
+        let tar = archive.into_inner().unwrap();
+        let (_, state) = upload(&[("code", b"\0asm"), ("state", b"[manifest]"), ("web", &tar)])
+            .await
+            .unwrap();
+        let mut app = WebApp::try_from(state.as_ref()).unwrap();
+        assert_eq!(app.metadata, b"[manifest]");
+        assert_eq!(app.get_file("index.html").unwrap(), b"<p/>");
+
+        assert!(upload(&[("state", b"s")])
+            .await
+            .unwrap_err()
+            .contains("code"));
+        assert!(upload(&[("code", b"\0asm"), ("other", b"")])
+            .await
+            .unwrap_err()
+            .contains("unexpected part"));
+    }
+}
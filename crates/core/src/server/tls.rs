@@ -0,0 +1,486 @@
+//! TLS termination for the HTTP gateway.
+//!
+//! The certificate is either provided by the operator or, when a domain is configured, obtained
+//! from an ACME provider (Let's Encrypt by default) through the HTTP-01 challenge and renewed
+//! before it expires. Challenges are answered by a plain HTTP listener, which redirects any
+//! other request to the HTTPS gateway.
+//!
+//! Virtual hosts can have their own certificate, selected by the server name requested by the
+//! client (SNI); the gateway certificate is served otherwise. When ACME is enabled, the
+//! certificates of the virtual hosts are obtained and renewed as well.
+
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use arc_swap::ArcSwapOption;
+use axum::{
+    extract::Path as UrlPath,
+    http::{header::HOST, HeaderMap, StatusCode, Uri},
+    response::{IntoResponse, Redirect, Response},
+    routing::get,
+    Extension, Router,
+};
+use axum_server::tls_rustls::RustlsConfig;
+use dashmap::DashMap;
+use instant_acme::{
+    Account, AccountCredentials, AuthorizationStatus, ChallengeType, Identifier, NewAccount,
+    NewOrder, OrderStatus,
+};
+use rustls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+};
+
+use crate::config::{AcmeConfig, WebsocketApiConfig};
+
+use super::virtual_hosts::host_name;
+
+/// Interval at which the certificate is checked for renewal.
+const RENEWAL_CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+/// Age at which a certificate is renewed, issued certificates are valid for 90 days.
+const RENEW_AFTER: Duration = Duration::from_secs(60 * 24 * 60 * 60);
+/// Maximum time waiting for the provider to validate an order, and then to issue its certificate.
+const ORDER_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Key authorizations of the pending HTTP-01 challenges, by token.
+#[derive(Clone, Default)]
+struct Challenges(Arc<DashMap<String, String>>);
+
+/// A certificate served by the gateway.
+#[derive(Clone)]
+struct Site {
+    /// Virtual host the certificate is served for, `None` for the gateway certificate.
+    host: Option<String>,
+    certificate: PathBuf,
+    private_key: PathBuf,
+}
+
+impl Site {
+    /// Domain the certificate is issued for when obtained through ACME.
+    fn domain<'a>(&'a self, acme: &'a AcmeConfig) -> &'a str {
+        self.host.as_deref().unwrap_or(&acme.domain)
+    }
+}
+
+fn sites(config: &WebsocketApiConfig) -> Vec<Site> {
+    let gateway = config.tls().map(|(certificate, private_key)| Site {
+        host: None,
+        certificate: certificate.to_owned(),
+        private_key: private_key.to_owned(),
+    });
+    let vhosts = config.virtual_hosts.iter().filter_map(|vhost| {
+        let (certificate, private_key) = vhost.tls()?;
+        Some(Site {
+            host: Some(vhost.host.clone()),
+            certificate: certificate.to_owned(),
+            private_key: private_key.to_owned(),
+        })
+    });
+    gateway.into_iter().chain(vhosts).collect()
+}
+
+/// Certificates served, selected by the server name requested by the client.
+#[derive(Debug, Default)]
+struct Certificates {
+    gateway: ArcSwapOption<CertifiedKey>,
+    by_host: DashMap<String, Arc<CertifiedKey>>,
+}
+
+impl Certificates {
+    async fn load(&self, site: &Site) -> anyhow::Result<()> {
+        let certified_key = load_certified_key(&site.certificate, &site.private_key).await?;
+        match &site.host {
+            Some(host) => {
+                self.by_host.insert(host.clone(), certified_key);
+            }
+            None => self.gateway.store(Some(certified_key)),
+        }
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for Certificates {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        client_hello
+            .server_name()
+            .and_then(|name| self.by_host.get(&name.to_ascii_lowercase()))
+            .map(|certified_key| certified_key.value().clone())
+            .or_else(|| self.gateway.load_full())
+    }
+}
+
+async fn load_certified_key(
+    certificate: &Path,
+    private_key: &Path,
+) -> anyhow::Result<Arc<CertifiedKey>> {
+    let chain = tokio::fs::read(certificate).await?;
+    let chain = rustls_pemfile::certs(&mut chain.as_slice()).collect::<Result<Vec<_>, _>>()?;
+    let key = tokio::fs::read(private_key).await?;
+    let key = rustls_pemfile::private_key(&mut key.as_slice())?
+        .ok_or_else(|| anyhow::anyhow!("no private key in {}", private_key.display()))?;
+    let key = rustls::crypto::ring::sign::any_supported_type(&key)?;
+    Ok(Arc::new(CertifiedKey::new(chain, key)))
+}
+
+pub(super) fn serve(socket: SocketAddr, router: Router, config: WebsocketApiConfig) {
+    tokio::spawn(async move {
+        tracing::info!("HTTPS gateway listening on {}", socket);
+        if let Err(e) = serve_tls(socket, router, config).await {
+            tracing::error!("Error while running HTTPS gateway server: {e}");
+        }
+    });
+}
+
+async fn serve_tls(
+    socket: SocketAddr,
+    router: Router,
+    config: WebsocketApiConfig,
+) -> anyhow::Result<()> {
+    let sites = sites(&config);
+    if sites.is_empty() {
+        anyhow::bail!("missing TLS certificate");
+    }
+    let challenges = Challenges::default();
+    let certificates = Arc::new(Certificates::default());
+    if let Some(acme) = &config.acme {
+        let domains = std::iter::once(acme.domain.clone())
+            .chain(sites.iter().filter_map(|site| site.host.clone()))
+            .collect();
+        serve_challenges(acme, domains, socket.port(), challenges.clone()).await?;
+    }
+    for site in &sites {
+        let loaded = async {
+            if let Some(acme) = &config.acme {
+                if needs_renewal(&site.certificate) {
+                    obtain_certificate(acme, site, &challenges).await?;
+                }
+            }
+            certificates.load(site).await
+        };
+        match (loaded.await, &site.host) {
+            (Ok(()), _) => {}
+            // a virtual host without certificate should not take down the whole gateway
+            (Err(error), Some(host)) => {
+                tracing::error!(%host, %error, "Failed loading virtual host TLS certificate")
+            }
+            (Err(error), None) => return Err(error),
+        }
+    }
+    let mut tls = rustls::ServerConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()?
+    .with_no_client_auth()
+    .with_cert_resolver(certificates.clone());
+    tls.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    if let Some(acme) = config.acme.clone() {
+        tokio::spawn(renew_certificates(acme, sites, challenges, certificates));
+    }
+    axum_server::bind_rustls(socket, RustlsConfig::from_config(Arc::new(tls)))
+        .serve(router.into_make_service_with_connect_info::<SocketAddr>())
+        .await?;
+    Ok(())
+}
+
+/// Serves the HTTP-01 challenges and redirects everything else to the HTTPS gateway.
+///
+/// Requests are redirected to the domain they were addressed to if it's served by the gateway,
+/// to the gateway domain otherwise.
+async fn serve_challenges(
+    acme: &AcmeConfig,
+    domains: Vec<String>,
+    https_port: u16,
+    challenges: Challenges,
+) -> anyhow::Result<()> {
+    let domains = Arc::new(domains);
+    let router = Router::new()
+        .route(
+            "/.well-known/acme-challenge/:token",
+            get(challenge_response),
+        )
+        .fallback(move |headers: HeaderMap, uri: Uri| {
+            let domains = domains.clone();
+            async move {
+                let requested = headers
+                    .get(HOST)
+                    .and_then(|host| host.to_str().ok())
+                    .map(|host| host_name(host).to_ascii_lowercase());
+                let domain = requested
+                    .filter(|host| domains.contains(host))
+                    .unwrap_or_else(|| domains[0].clone());
+                let authority = if https_port == 443 {
+                    domain
+                } else {
+                    format!("{domain}:{https_port}")
+                };
+                redirect_to_https(&authority, uri)
+            }
+        })
+        .layer(Extension(challenges));
+    let socket = SocketAddr::from(([0, 0, 0, 0], acme.http_port));
+    let listener = tokio::net::TcpListener::bind(socket).await?;
+    tracing::info!("ACME challenge listener on {}", socket);
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, router).await {
+            tracing::error!("Error while serving ACME challenges: {e}");
+        }
+    });
+    Ok(())
+}
+
+async fn challenge_response(
+    UrlPath(token): UrlPath<String>,
+    Extension(challenges): Extension<Challenges>,
+) -> Response {
+    match challenges.0.get(&token) {
+        Some(key_authorization) => key_authorization.clone().into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+fn redirect_to_https(authority: &str, uri: Uri) -> Redirect {
+    let path = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    Redirect::permanent(&format!("https://{authority}{path}"))
+}
+
+fn needs_renewal(certificate: &Path) -> bool {
+    let issued = std::fs::metadata(certificate).and_then(|metadata| metadata.modified());
+    match issued {
+        Ok(issued) => SystemTime::now()
+            .duration_since(issued)
+            .map(|age| age >= RENEW_AFTER)
+            .unwrap_or(false),
+        Err(_) => true,
+    }
+}
+
+async fn renew_certificates(
+    acme: AcmeConfig,
+    sites: Vec<Site>,
+    challenges: Challenges,
+    certificates: Arc<Certificates>,
+) {
+    let mut interval = tokio::time::interval(RENEWAL_CHECK_INTERVAL);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        for site in &sites {
+            if !needs_renewal(&site.certificate) {
+                continue;
+            }
+            let domain = site.domain(&acme);
+            let renewed = async {
+                obtain_certificate(&acme, site, &challenges).await?;
+                certificates.load(site).await
+            };
+            match renewed.await {
+                Ok(()) => tracing::info!(%domain, "TLS certificate renewed"),
+                Err(error) => {
+                    tracing::error!(%domain, %error, "Failed renewing TLS certificate")
+                }
+            }
+        }
+    }
+}
+
+/// Obtains a certificate for the domain of the site, storing it with its private key.
+async fn obtain_certificate(
+    acme: &AcmeConfig,
+    site: &Site,
+    challenges: &Challenges,
+) -> anyhow::Result<()> {
+    let domain = site.domain(acme);
+    let (certificate, private_key) = (&site.certificate, &site.private_key);
+    tracing::info!(%domain, directory = %acme.directory, "Requesting TLS certificate");
+    let account = load_account(acme, certificate).await?;
+    let identifier = Identifier::Dns(domain.to_owned());
+    let mut order = account
+        .new_order(&NewOrder {
+            identifiers: &[identifier],
+        })
+        .await?;
+
+    let mut tokens = vec![];
+    for authorization in order.authorizations().await? {
+        if matches!(authorization.status, AuthorizationStatus::Valid) {
+            continue;
+        }
+        let challenge = authorization
+            .challenges
+            .iter()
+            .find(|challenge| challenge.r#type == ChallengeType::Http01)
+            .ok_or_else(|| anyhow::anyhow!("HTTP-01 challenge not offered by the provider"))?;
+        let key_authorization = order.key_authorization(challenge);
+        challenges.0.insert(
+            challenge.token.clone(),
+            key_authorization.as_str().to_owned(),
+        );
+        tokens.push(challenge.token.clone());
+        order.set_challenge_ready(&challenge.url).await?;
+    }
+
+    let validated = async {
+        let mut delay = Duration::from_millis(250);
+        let deadline = tokio::time::Instant::now() + ORDER_TIMEOUT;
+        loop {
+            tokio::time::sleep(delay).await;
+            let state = order.refresh().await?;
+            match state.status {
+                OrderStatus::Ready => return Ok(()),
+                OrderStatus::Invalid => anyhow::bail!("order for {domain} is invalid"),
+                _ if tokio::time::Instant::now() >= deadline => {
+                    anyhow::bail!("timed out waiting for the order of {domain}")
+                }
+                _ => delay = (delay * 2).min(Duration::from_secs(10)),
+            }
+        }
+    }
+    .await;
+    for token in tokens {
+        challenges.0.remove(&token);
+    }
+    validated?;
+
+    let key_pair = rcgen::KeyPair::generate()?;
+    let mut params = rcgen::CertificateParams::new(vec![domain.to_owned()])?;
+    params.distinguished_name = rcgen::DistinguishedName::new();
+    let csr = params.serialize_request(&key_pair)?;
+    order.finalize(csr.der()).await?;
+    let deadline = tokio::time::Instant::now() + ORDER_TIMEOUT;
+    let chain = loop {
+        match order.certificate().await? {
+            Some(chain) => break chain,
+            None if tokio::time::Instant::now() >= deadline => {
+                anyhow::bail!("timed out waiting for the certificate of {domain}")
+            }
+            None => tokio::time::sleep(Duration::from_secs(1)).await,
+        }
+    };
+
+    if let Some(dir) = certificate.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    write_private(private_key, key_pair.serialize_pem().as_bytes()).await?;
+    tokio::fs::write(certificate, chain).await?;
+    tracing::info!(%domain, "TLS certificate obtained");
+    Ok(())
+}
+
+/// Loads the ACME account stored next to the certificate, registering a new one if missing.
+async fn load_account(acme: &AcmeConfig, certificate: &Path) -> anyhow::Result<Account> {
+    let credentials_file = certificate.with_file_name("acme-account.json");
+    if let Ok(credentials) = tokio::fs::read(&credentials_file).await {
+        let credentials: AccountCredentials = serde_json::from_slice(&credentials)?;
+        return Ok(Account::from_credentials(credentials).await?);
+    }
+    let contact = acme.contact.as_ref().map(|email| format!("mailto:{email}"));
+    let contact: Vec<&str> = contact.iter().map(String::as_str).collect();
+    let (account, credentials) = Account::create(
+        &NewAccount {
+            contact: &contact,
+            terms_of_service_agreed: true,
+            only_return_existing: false,
+        },
+        &acme.directory,
+        None,
+    )
+    .await?;
+    if let Some(dir) = credentials_file.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    write_private(&credentials_file, &serde_json::to_vec(&credentials)?).await?;
+    Ok(account)
+}
+
+/// Writes a file only readable by the owner, for private keys and account credentials.
+async fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path).await?;
+    // the file may have been created before with wider permissions
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))
+            .await?;
+    }
+    file.write_all(contents).await?;
+    file.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redirects_to_https() {
+        let uri: Uri = "/v1/contract/web/key/?q=1".parse().unwrap();
+        let redirect = redirect_to_https("example.org:8443", uri).into_response();
+        assert_eq!(redirect.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(
+            redirect.headers()[axum::http::header::LOCATION],
+            "https://example.org:8443/v1/contract/web/key/?q=1"
+        );
+    }
+
+    #[test]
+    fn missing_certificate_needs_renewal() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let certificate = dir.path().join("gateway.crt");
+        assert!(needs_renewal(&certificate));
+        std::fs::write(&certificate, b"")?;
+        assert!(!needs_renewal(&certificate));
+        Ok(())
+    }
+
+    #[test]
+    fn sites_of_the_gateway_and_virtual_hosts() {
+        let mut config = WebsocketApiConfig {
+            tls_certificate: Some("gateway.crt".into()),
+            tls_private_key: Some("gateway.key".into()),
+            ..Default::default()
+        };
+        let mut vhost: crate::config::VirtualHost = "app.example.org=contract".parse().unwrap();
+        config.virtual_hosts.push(vhost.clone());
+        vhost.host = "other.example.org".to_owned();
+        vhost.tls_certificate = Some("other.crt".into());
+        vhost.tls_private_key = Some("other.key".into());
+        config.virtual_hosts.push(vhost);
+
+        let sites = sites(&config);
+        assert_eq!(sites.len(), 2);
+        assert_eq!(sites[0].host, None);
+        assert_eq!(sites[1].host.as_deref(), Some("other.example.org"));
+        let acme = AcmeConfig {
+            domain: "example.org".to_owned(),
+            contact: None,
+            directory: String::new(),
+            http_port: 80,
+        };
+        assert_eq!(sites[0].domain(&acme), "example.org");
+        assert_eq!(sites[1].domain(&acme), "other.example.org");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn private_files_are_owner_only() -> anyhow::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir()?;
+        let key = dir.path().join("gateway.key");
+        std::fs::write(&key, b"old")?;
+        std::fs::set_permissions(&key, std::fs::Permissions::from_mode(0o644))?;
+        write_private(&key, b"key").await?;
+        assert_eq!(std::fs::read(&key)?, b"key");
+        assert_eq!(std::fs::metadata(&key)?.permissions().mode() & 0o777, 0o600);
+        Ok(())
+    }
+}
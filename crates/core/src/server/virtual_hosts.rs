@@ -0,0 +1,175 @@
+//! Domains serving the web app of a contract at their root.
+//!
+//! Requests addressed to a virtual host are rewritten to the web app routes of its contract, so
+//! `https://app.example.org/assets/app.js` is served as
+//! `/v1/contract/web/<contract>/assets/app.js` and the relative asset paths of the app resolve
+//! the same way as when served from the gateway. The API routes (`/v1/...`) are left as is, so
+//! apps can still open the websocket API on their own domain; except for the web app routes,
+//! which are not served on virtual hosts: the auth cookie of the app is scoped to the whole
+//! domain, so no other app may be served from it.
+
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{
+    extract::{Request, State},
+    http::{header::HOST, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use freenet_stdlib::prelude::ContractInstanceId;
+
+use crate::config::VirtualHost;
+
+#[derive(Clone, Default)]
+pub(super) struct VirtualHosts(Arc<HashMap<String, ContractInstanceId>>);
+
+impl VirtualHosts {
+    pub fn new(virtual_hosts: &[VirtualHost]) -> Self {
+        let hosts = virtual_hosts
+            .iter()
+            .filter_map(
+                |vhost| match ContractInstanceId::try_from(vhost.contract.clone()) {
+                    Ok(contract) => Some((vhost.host.to_ascii_lowercase(), contract)),
+                    Err(err) => {
+                        tracing::error!(host = %vhost.host, "Invalid virtual host contract: {err}");
+                        None
+                    }
+                },
+            )
+            .collect();
+        Self(Arc::new(hosts))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn resolve(&self, authority: &str) -> Option<(&str, ContractInstanceId)> {
+        let host = host_name(authority)
+            .trim_end_matches('.')
+            .to_ascii_lowercase();
+        self.0
+            .get_key_value(&host)
+            .map(|(host, contract)| (host.as_str(), *contract))
+    }
+}
+
+/// The virtual host a request was addressed to.
+#[derive(Clone, Debug)]
+pub(super) struct VirtualHostRequest {
+    pub host: String,
+}
+
+/// Strips the port, if any, from the authority of a request.
+pub(super) fn host_name(authority: &str) -> &str {
+    if authority.starts_with('[') {
+        return authority
+            .find(']')
+            .map_or(authority, |end| &authority[..=end]);
+    }
+    authority
+        .split_once(':')
+        .map_or(authority, |(host, _port)| host)
+}
+
+/// Routes the requests addressed to a virtual host to the web app of its contract.
+pub(super) async fn route(
+    State(hosts): State<VirtualHosts>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let authority = req
+        .headers()
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .or_else(|| req.uri().host());
+    let Some((host, contract)) = authority.and_then(|authority| hosts.resolve(authority)) else {
+        return next.run(req).await;
+    };
+    match rewrite(req.uri(), &contract) {
+        Rewrite::To(uri) => {
+            tracing::trace!(%host, %uri, "routing virtual host request");
+            let host = host.to_owned();
+            *req.uri_mut() = uri;
+            req.extensions_mut().insert(VirtualHostRequest { host });
+            next.run(req).await
+        }
+        Rewrite::Api => next.run(req).await,
+        Rewrite::Denied => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Rewrite {
+    /// Path of the web app of the contract.
+    To(Uri),
+    /// API route, served as is.
+    Api,
+    /// Web app route, not served on virtual hosts.
+    Denied,
+}
+
+fn rewrite(uri: &Uri, contract: &ContractInstanceId) -> Rewrite {
+    let path = uri.path();
+    if path == "/v1/contract/web" || path.starts_with("/v1/contract/web/") {
+        return Rewrite::Denied;
+    }
+    if path == "/v1" || path.starts_with("/v1/") {
+        return Rewrite::Api;
+    }
+    let mut rewritten = format!("/v1/contract/web/{contract}{path}");
+    if let Some(query) = uri.query() {
+        rewritten.push('?');
+        rewritten.push_str(query);
+    }
+    rewritten.parse().map_or(Rewrite::Denied, Rewrite::To)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_and_rewrite() {
+        let contract = ContractInstanceId::new([1; 32]);
+        let hosts = VirtualHosts::new(
+            &[
+                "App.Example.org=".to_owned() + &contract.to_string(),
+                "bad.example.org=not-a-contract".to_owned(),
+            ]
+            .map(|vhost| vhost.parse::<VirtualHost>().unwrap()),
+        );
+        assert_eq!(hosts.0.len(), 1);
+        assert_eq!(
+            hosts.resolve("app.example.org:443"),
+            Some(("app.example.org", contract))
+        );
+        assert_eq!(
+            hosts.resolve("APP.example.org."),
+            Some(("app.example.org", contract))
+        );
+        assert_eq!(hosts.resolve("other.example.org"), None);
+        assert_eq!(host_name("[::1]:50509"), "[::1]");
+
+        let rewritten = |path: &str| rewrite(&path.parse().unwrap(), &contract);
+        assert_eq!(
+            rewritten("/assets/app.js?v=2"),
+            Rewrite::To(
+                format!("/v1/contract/web/{contract}/assets/app.js?v=2")
+                    .parse()
+                    .unwrap()
+            )
+        );
+        assert_eq!(
+            rewritten("/"),
+            Rewrite::To(format!("/v1/contract/web/{contract}/").parse().unwrap())
+        );
+        assert_eq!(rewritten("/v1/contract/command"), Rewrite::Api);
+        // other apps can't be served from the domain of the app, which gets its auth cookie
+        let other = ContractInstanceId::new([2; 32]);
+        assert_eq!(
+            rewritten(&format!("/v1/contract/web/{other}/")),
+            Rewrite::Denied
+        );
+    }
+}
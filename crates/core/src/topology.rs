@@ -165,6 +165,11 @@ impl TopologyManager {
         Ok(best_location)
     }
 
+    pub(crate) fn set_connection_limits(&mut self, min_connections: usize, max_connections: usize) {
+        self.limits.min_connections = min_connections;
+        self.limits.max_connections = max_connections;
+    }
+
     #[cfg(test)]
     pub(self) fn update_limits(&mut self, limits: Limits) {
         self.limits = limits;
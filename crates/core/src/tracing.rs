@@ -148,6 +148,15 @@ impl<'a> NetEventLog<'a> {
         }
     }
 
+    pub fn evicted(ring: &'a Ring, key: ContractKey, size: u64) -> Option<Self> {
+        let peer_id = ring.connection_manager.get_peer_key()?;
+        Some(NetEventLog {
+            tx: Transaction::NULL,
+            peer_id,
+            kind: EventKind::Evicted { key, size },
+        })
+    }
+
     pub fn from_outbound_msg(msg: &'a NetMessage, ring: &'a Ring) -> Either<Self, Vec<Self>> {
         let Some(peer_id) = ring.connection_manager.get_peer_key() else {
             return Either::Right(vec![]);
@@ -287,6 +296,35 @@ impl<'a> NetEventLog<'a> {
                 requester: target.clone(),
                 target: sender.clone(),
             },
+            NetMessageV1::Get(GetMsg::SeekNode {
+                id,
+                key,
+                sender,
+                target,
+                htl,
+                ..
+            }) => EventKind::GetHop(GetHopEvent::Seek {
+                id: *id,
+                key: *key,
+                sender: sender.clone(),
+                target: target.clone(),
+                htl: *htl,
+                timestamp: chrono::Utc::now().timestamp() as u64,
+            }),
+            NetMessageV1::Get(GetMsg::ReturnGet {
+                id,
+                key,
+                value: StoreResponse { state: None, .. },
+                sender,
+                target,
+                ..
+            }) => EventKind::GetHop(GetHopEvent::NotFound {
+                id: *id,
+                key: *key,
+                sender: sender.clone(),
+                target: target.clone(),
+                timestamp: chrono::Utc::now().timestamp() as u64,
+            }),
             NetMessageV1::Subscribe(SubscribeMsg::ReturnSub {
                 id,
                 subscribed: true,
@@ -352,6 +390,162 @@ impl NetLogMessage {
     }
 }
 
+/// An event of a transaction as recorded by a peer.
+///
+/// Merging the events reported by the peers a transaction went through, ordered by time,
+/// reconstructs the path it followed across the network.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct TraceEvent {
+    pub datetime: DateTime<Utc>,
+    /// Peer which recorded the event.
+    pub peer: String,
+    pub event: &'static str,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub contract: Option<String>,
+}
+
+impl TraceEvent {
+    fn new(
+        log: &NetLogMessage,
+        event: &'static str,
+        from: Option<&PeerId>,
+        to: Option<&PeerId>,
+        contract: Option<&ContractKey>,
+    ) -> Self {
+        Self {
+            datetime: log.datetime,
+            peer: log.peer_id.to_string(),
+            event,
+            from: from.map(|peer| peer.to_string()),
+            to: to.map(|peer| peer.to_string()),
+            contract: contract.map(|key| key.to_string()),
+        }
+    }
+
+    fn from_log(log: &NetLogMessage) -> Option<Self> {
+        let event = match &log.kind {
+            EventKind::Connect(ConnectEvent::StartConnection { from }) => {
+                Self::new(log, "connect-start", Some(from), None, None)
+            }
+            EventKind::Connect(ConnectEvent::Connected { this, connected }) => Self::new(
+                log,
+                "connected",
+                Some(&this.peer),
+                Some(&connected.peer),
+                None,
+            ),
+            EventKind::Connect(ConnectEvent::Finished { initiator, .. }) => {
+                Self::new(log, "connect-finished", Some(initiator), None, None)
+            }
+            EventKind::Put(PutEvent::Request {
+                requester,
+                target,
+                key,
+                ..
+            }) => Self::new(
+                log,
+                "put-request",
+                Some(&requester.peer),
+                Some(&target.peer),
+                Some(key),
+            ),
+            EventKind::Put(PutEvent::PutSuccess {
+                requester,
+                target,
+                key,
+                ..
+            }) => Self::new(
+                log,
+                "put-success",
+                Some(&target.peer),
+                Some(&requester.peer),
+                Some(key),
+            ),
+            EventKind::Put(PutEvent::BroadcastEmitted { sender, key, .. }) => Self::new(
+                log,
+                "put-broadcast-emitted",
+                Some(&sender.peer),
+                None,
+                Some(key),
+            ),
+            EventKind::Put(PutEvent::BroadcastReceived {
+                requester,
+                target,
+                key,
+                ..
+            }) => Self::new(
+                log,
+                "put-broadcast-received",
+                Some(&requester.peer),
+                Some(&target.peer),
+                Some(key),
+            ),
+            EventKind::GetHop(GetHopEvent::Seek {
+                sender,
+                target,
+                key,
+                ..
+            }) => Self::new(
+                log,
+                "get-seek",
+                Some(&sender.peer),
+                Some(&target.peer),
+                Some(key),
+            ),
+            EventKind::GetHop(GetHopEvent::NotFound {
+                sender,
+                target,
+                key,
+                ..
+            }) => Self::new(
+                log,
+                "get-not-found",
+                Some(&sender.peer),
+                Some(&target.peer),
+                Some(key),
+            ),
+            EventKind::Get {
+                key,
+                requester,
+                target,
+                ..
+            } => Self::new(
+                log,
+                "get-found",
+                Some(&target.peer),
+                Some(&requester.peer),
+                Some(key),
+            ),
+            EventKind::Subscribed {
+                key, at, requester, ..
+            } => Self::new(
+                log,
+                "subscribed",
+                Some(&at.peer),
+                Some(&requester.peer),
+                Some(key),
+            ),
+            EventKind::Disconnected { from } => {
+                Self::new(log, "disconnected", Some(from), None, None)
+            }
+            EventKind::Evicted { key, .. } => Self::new(log, "evicted", None, None, Some(key)),
+            EventKind::Route(_) | EventKind::Ignored => return None,
+        };
+        Some(event)
+    }
+}
+
+/// Returns the events of a transaction recorded in the event log of this peer.
+pub(crate) async fn transaction_events(
+    tx: Transaction,
+    event_log: &std::path::Path,
+) -> anyhow::Result<Vec<TraceEvent>> {
+    let events = aof::LogFile::get_transaction_events(tx, event_log).await?;
+    Ok(events.iter().filter_map(TraceEvent::from_log).collect())
+}
+
 impl<'a> From<NetEventLog<'a>> for NetLogMessage {
     fn from(log: NetEventLog<'a>) -> NetLogMessage {
         NetLogMessage {
@@ -993,6 +1187,13 @@ enum EventKind {
     Disconnected {
         from: PeerId,
     },
+    GetHop(GetHopEvent),
+    /// A contract was evicted from the store to keep it within the storage budget.
+    Evicted {
+        key: ContractKey,
+        /// Bytes freed by the eviction.
+        size: u64,
+    },
 }
 
 impl EventKind {
@@ -1003,6 +1204,8 @@ impl EventKind {
     const SUBSCRIBED: u8 = 4;
     const IGNORED: u8 = 5;
     const DISCONNECTED: u8 = 6;
+    const GET_HOP: u8 = 7;
+    const EVICTED: u8 = 8;
 
     const fn varint_id(&self) -> u8 {
         match self {
@@ -1013,6 +1216,8 @@ impl EventKind {
             EventKind::Subscribed { .. } => Self::SUBSCRIBED,
             EventKind::Ignored => Self::IGNORED,
             EventKind::Disconnected { .. } => Self::DISCONNECTED,
+            EventKind::GetHop(_) => Self::GET_HOP,
+            EventKind::Evicted { .. } => Self::EVICTED,
         }
     }
 }
@@ -1033,6 +1238,30 @@ enum ConnectEvent {
     },
 }
 
+/// Intermediate hops of a GET, which together with the final [`EventKind::Get`] allow
+/// reconstructing the path followed by the request across peers.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[cfg_attr(test, derive(arbitrary::Arbitrary))]
+enum GetHopEvent {
+    /// The request was forwarded by `sender` to `target`.
+    Seek {
+        id: Transaction,
+        key: ContractKey,
+        sender: PeerKeyLocation,
+        target: PeerKeyLocation,
+        htl: usize,
+        timestamp: u64,
+    },
+    /// `sender` didn't find the contract and returned the request to `target`.
+    NotFound {
+        id: Transaction,
+        key: ContractKey,
+        sender: PeerKeyLocation,
+        target: PeerKeyLocation,
+        timestamp: u64,
+    },
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 #[cfg_attr(test, derive(arbitrary::Arbitrary))]
 enum PutEvent {
@@ -1079,8 +1308,30 @@ enum PutEvent {
 
 #[cfg(feature = "trace")]
 pub(crate) mod tracer {
+    use std::sync::OnceLock;
+
     use tracing::level_filters::LevelFilter;
-    use tracing_subscriber::{Layer, Registry};
+    use tracing_subscriber::{reload, EnvFilter, Layer, Registry};
+
+    /// Handle to change the log filter of the global subscriber, once initialized.
+    static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+    fn env_filter(level: LevelFilter) -> EnvFilter {
+        EnvFilter::builder()
+            .with_default_directive(level.into())
+            .from_env_lossy()
+            .add_directive("stretto=off".parse().expect("infallible"))
+            .add_directive("sqlx=error".parse().expect("infallible"))
+    }
+
+    /// Changes the default level of the logs. Does nothing if the tracer was not initialized.
+    pub fn set_level(level: LevelFilter) -> anyhow::Result<()> {
+        let Some(filter) = FILTER.get() else {
+            return Ok(());
+        };
+        filter.reload(env_filter(level))?;
+        Ok(())
+    }
 
     pub fn init_tracer(level: Option<LevelFilter>, endpoint: Option<String>) -> anyhow::Result<()> {
         let default_filter = if cfg!(any(test, debug_assertions)) {
@@ -1089,11 +1340,7 @@ pub(crate) mod tracer {
             LevelFilter::INFO
         };
         let default_filter = level.unwrap_or(default_filter);
-        let filter_layer = tracing_subscriber::EnvFilter::builder()
-            .with_default_directive(default_filter.into())
-            .from_env_lossy()
-            .add_directive("stretto=off".parse().expect("infallible"))
-            .add_directive("sqlx=error".parse().expect("infallible"));
+        let (filter_layer, filter_handle) = reload::Layer::new(env_filter(default_filter));
 
         // use opentelemetry_sdk::propagation::TraceContextPropagator;
         use tracing_subscriber::layer::SubscriberExt;
@@ -1168,6 +1415,7 @@ pub(crate) mod tracer {
 
         // Set the global subscriber
         tracing::subscriber::set_global_default(subscriber).expect("Error setting subscriber");
+        let _ = FILTER.set(filter_handle);
         Ok(())
     }
 }
@@ -9,6 +9,7 @@ use std::path::{Path, PathBuf};
 use tokio::sync::Mutex;
 
 use super::{EventKind, NetLogMessage, RouteEvent, NEW_RECORDS_TS};
+use crate::message::Transaction;
 
 static FILE_LOCK: Mutex<()> = Mutex::const_new(());
 
@@ -357,6 +358,56 @@ impl LogFile {
         Ok(deserialized_records)
     }
 
+    /// Returns the events of the given transaction recorded in the log, in order.
+    pub async fn get_transaction_events(
+        tx: Transaction,
+        event_log_path: &Path,
+    ) -> anyhow::Result<Vec<NetLogMessage>> {
+        let _guard: tokio::sync::MutexGuard<'_, ()> = FILE_LOCK.lock().await;
+        let mut file = BufReader::new(OpenOptions::new().read(true).open(event_log_path).await?);
+        Self::get_transaction_events_in(tx, &mut file).await
+    }
+
+    async fn get_transaction_events_in(
+        tx: Transaction,
+        file: &mut (impl AsyncRead + AsyncSeek + Unpin),
+    ) -> anyhow::Result<Vec<NetLogMessage>> {
+        let mut records = vec![];
+        loop {
+            let mut header = [0; EVENT_LOG_HEADER_SIZE];
+            if let Err(error) = file.read_exact(&mut header).await {
+                if !matches!(error.kind(), io::ErrorKind::UnexpectedEof) {
+                    let pos = file.stream_position().await;
+                    tracing::error!(%error, ?pos, "error while trying to read file");
+                    return Err(error.into());
+                } else {
+                    break;
+                }
+            }
+            let length = DefaultEndian::read_u32(&header[..4]);
+            if matches!(header[4], EventKind::IGNORED | EventKind::ROUTE) {
+                file.seek(io::SeekFrom::Current(length as i64)).await?;
+                continue;
+            }
+            let mut buf = vec![0; length as usize];
+            file.read_exact(&mut buf).await?;
+            records.push(buf);
+        }
+
+        let events = tokio::task::spawn_blocking(move || {
+            let mut events = vec![];
+            for buf in records {
+                let record: NetLogMessage = bincode::deserialize(&buf)?;
+                if record.tx == tx {
+                    events.push(record);
+                }
+            }
+            Ok::<_, anyhow::Error>(events)
+        })
+        .await??;
+        Ok(events)
+    }
+
     pub async fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
         let _guard = FILE_LOCK.lock().await;
         let file = self.file.as_mut().unwrap();
@@ -448,6 +499,42 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn read_transaction_events() -> anyhow::Result<()> {
+        NEW_RECORDS_TS.get_or_init(SystemTime::now);
+        let temp_dir = tempfile::tempdir()?;
+        let log_path = temp_dir.path().join("event_log");
+
+        const TEST_LOGS: usize = BATCH_SIZE;
+
+        let mut log = LogFile::open(&log_path).await?;
+        let bytes = crate::util::test::random_bytes_2mb();
+        let mut gen = arbitrary::Unstructured::new(&bytes);
+        let transactions: [Transaction; 2] = [gen.arbitrary()?, gen.arbitrary()?];
+        let mut events = vec![];
+        let mut traced_events = 0;
+        for i in 0..TEST_LOGS {
+            let kind: EventKind = gen.arbitrary()?;
+            let tx = &transactions[i % 2];
+            if tx == &transactions[0] && !matches!(kind, EventKind::Route(_) | EventKind::Ignored) {
+                traced_events += 1;
+            }
+            events.push(NetEventLog {
+                tx,
+                peer_id: PeerId::random(),
+                kind,
+            });
+        }
+        for msg in NetLogMessage::to_log_message(either::Either::Right(events)) {
+            log.persist_log(msg).await;
+        }
+
+        let ev = LogFile::get_transaction_events(transactions[0], &log_path).await?;
+        assert_eq!(ev.len(), traced_events);
+        assert!(ev.iter().all(|record| record.tx == transactions[0]));
+        Ok(())
+    }
+
     #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
     async fn read_write_small() -> anyhow::Result<()> {
         NEW_RECORDS_TS.get_or_init(SystemTime::now);
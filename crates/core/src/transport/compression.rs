@@ -0,0 +1,257 @@
+//! Compression of the messages exchanged between peers.
+//!
+//! Each peer advertises the codecs it supports in its intro packet (or connection ack), as a
+//! bit mask appended to its inbound key; the codec used to send messages to a peer is the first
+//! one in our preferences the remote supports too.
+//!
+//! Every message carries a trailing byte identifying how it was encoded, so the receiver can
+//! always decode it regardless of what was negotiated. Messages under the threshold, or which
+//! would not get smaller, are sent as is.
+//!
+//! Decompressed messages are bounded by the maximum message size of the node, the largest any
+//! peer is allowed to send, so a small frame can't make a connection allocate more.
+
+use std::io::Read;
+
+use crate::config::{Codec, CompressionConfig};
+
+use super::TransportError;
+
+/// Upper bound to the compression ratio of lz4, so messages can't claim sizes they can't reach.
+const MAX_LZ4_RATIO: usize = 255;
+/// Upper bound to the window of zstd messages, bounding the memory needed to decode them. Frames
+/// compressed by us at [`ZSTD_LEVEL`] never need more.
+const MAX_ZSTD_WINDOW_LOG: u32 = 23;
+const ZSTD_LEVEL: i32 = 3;
+
+const RAW_TAG: u8 = 0;
+const ZSTD_TAG: u8 = 1;
+const LZ4_TAG: u8 = 2;
+
+impl Codec {
+    fn flag(self) -> u8 {
+        match self {
+            Codec::Zstd => 1 << 0,
+            Codec::Lz4 => 1 << 1,
+        }
+    }
+
+    fn tag(self) -> u8 {
+        match self {
+            Codec::Zstd => ZSTD_TAG,
+            Codec::Lz4 => LZ4_TAG,
+        }
+    }
+}
+
+/// Bit mask of the codecs supported, as advertised to remote peers.
+pub(super) fn supported_codecs(config: &CompressionConfig) -> u8 {
+    config
+        .codecs
+        .iter()
+        .fold(0, |mask, codec| mask | codec.flag())
+}
+
+/// Compression of the messages sent through a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Compression {
+    codec: Option<Codec>,
+    threshold: usize,
+    /// Maximum size of the messages received, once decompressed.
+    max_size: usize,
+}
+
+impl Compression {
+    /// Sends messages as is, and accepts no compressed message.
+    pub(crate) const NONE: Self = Self {
+        codec: None,
+        threshold: usize::MAX,
+        max_size: 0,
+    };
+
+    /// Picks the preferred codec among the ones supported by the remote peer, accepting
+    /// messages up to `max_size` once decompressed.
+    pub(super) fn negotiate(
+        config: &CompressionConfig,
+        remote_codecs: u8,
+        max_size: usize,
+    ) -> Self {
+        let codec = config
+            .codecs
+            .iter()
+            .copied()
+            .find(|codec| remote_codecs & codec.flag() != 0);
+        Self {
+            codec,
+            threshold: config.threshold,
+            max_size,
+        }
+    }
+
+    pub(crate) fn decode(&self, data: Vec<u8>) -> Result<Vec<u8>, TransportError> {
+        decode(data, self.max_size)
+    }
+
+    pub(crate) fn encode(&self, mut data: Vec<u8>, allow_compression: bool) -> Vec<u8> {
+        if let Some(codec) = self.codec.filter(|_| allow_compression) {
+            if data.len() >= self.threshold {
+                let compressed = match codec {
+                    Codec::Zstd => zstd::bulk::compress(&data, ZSTD_LEVEL).ok(),
+                    Codec::Lz4 => Some(lz4_flex::compress_prepend_size(&data)),
+                };
+                if let Some(mut compressed) = compressed.filter(|c| c.len() < data.len()) {
+                    tracing::trace!(
+                        ?codec,
+                        size = data.len(),
+                        compressed_size = compressed.len(),
+                        "compressed message"
+                    );
+                    compressed.push(codec.tag());
+                    return compressed;
+                }
+            }
+        }
+        data.push(RAW_TAG);
+        data
+    }
+}
+
+/// Decodes a message, which must be at most `max_size` once decompressed.
+pub(crate) fn decode(mut data: Vec<u8>, max_size: usize) -> Result<Vec<u8>, TransportError> {
+    let malformed = |cause: String| TransportError::Other(anyhow::anyhow!(cause));
+    match data.pop() {
+        Some(RAW_TAG) => Ok(data),
+        Some(ZSTD_TAG) => {
+            // streamed, so memory grows with the actual output instead of the upper bound
+            let mut decompressed = Vec::new();
+            zstd::stream::read::Decoder::new(data.as_slice())
+                .and_then(|mut decoder| {
+                    decoder.window_log_max(MAX_ZSTD_WINDOW_LOG)?;
+                    decoder
+                        .take(max_size as u64 + 1)
+                        .read_to_end(&mut decompressed)
+                })
+                .map_err(|err| malformed(format!("invalid zstd message: {err}")))?;
+            if decompressed.len() > max_size {
+                return Err(malformed("zstd message too large".into()));
+            }
+            Ok(decompressed)
+        }
+        Some(LZ4_TAG) => {
+            let size = data
+                .get(..4)
+                .map(|size| u32::from_le_bytes(size.try_into().expect("4 bytes")) as usize);
+            let max_size = max_size.min(data.len().saturating_mul(MAX_LZ4_RATIO));
+            if size.map_or(true, |size| size > max_size) {
+                return Err(malformed("invalid lz4 message size".into()));
+            }
+            lz4_flex::decompress_size_prepended(&data)
+                .map_err(|err| malformed(format!("invalid lz4 message: {err}")))
+        }
+        Some(tag) => Err(malformed(format!("unknown message encoding: {tag}"))),
+        None => Err(malformed("empty message".into())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    const MAX_SIZE: usize = 16 * 1024 * 1024;
+
+    #[test]
+    fn negotiate_and_roundtrip() {
+        let config = CompressionConfig {
+            codecs: vec![Codec::Zstd, Codec::Lz4],
+            threshold: 16,
+            uncompressed_operations: vec![],
+        };
+        let remote_only_lz4 = Codec::Lz4.flag();
+        let compression = Compression::negotiate(&config, remote_only_lz4, MAX_SIZE);
+        assert_eq!(compression.codec, Some(Codec::Lz4));
+        assert_eq!(
+            Compression::negotiate(&config, supported_codecs(&config), MAX_SIZE).codec,
+            Some(Codec::Zstd)
+        );
+        assert_eq!(Compression::negotiate(&config, 0, MAX_SIZE).codec, None);
+
+        let msg = vec![7u8; 4096];
+        let encoded = compression.encode(msg.clone(), true);
+        assert!(encoded.len() < msg.len());
+        assert_eq!(compression.decode(encoded.clone()).unwrap(), msg);
+        assert!(Compression::NONE.decode(encoded).is_err());
+
+        let uncompressed = compression.encode(msg.clone(), false);
+        assert_eq!(uncompressed.len(), msg.len() + 1);
+        assert_eq!(compression.decode(uncompressed).unwrap(), msg);
+
+        let small = compression.encode(vec![1, 2, 3], true);
+        assert_eq!(compression.decode(small).unwrap(), vec![1, 2, 3]);
+    }
+
+    proptest! {
+        #[test]
+        fn roundtrip_arbitrary_messages(
+            codec in prop_oneof![Just(Codec::Zstd), Just(Codec::Lz4)],
+            threshold in 0..=64usize,
+            chunk in prop::collection::vec(any::<u8>(), 0..256),
+            repeat in 1..=8usize,
+            allow_compression in any::<bool>(),
+        ) {
+            let config = CompressionConfig {
+                codecs: vec![codec],
+                threshold,
+                uncompressed_operations: vec![],
+            };
+            let compression = Compression::negotiate(&config, supported_codecs(&config), MAX_SIZE);
+            // repeat some bytes so the message is likely to be compressed
+            let msg = chunk.repeat(repeat);
+            let encoded = compression.encode(msg.clone(), allow_compression);
+            prop_assert_eq!(compression.decode(encoded).unwrap(), msg);
+        }
+
+        #[test]
+        fn decode_malformed_messages(
+            mut msg in prop::collection::vec(any::<u8>(), 0..64),
+            tag in prop::sample::select(vec![RAW_TAG, ZSTD_TAG, LZ4_TAG, u8::MAX]),
+        ) {
+            msg.push(tag);
+            // errors are fine, panics are not
+            let _ = decode(msg, MAX_SIZE);
+        }
+    }
+
+    #[test]
+    fn decompression_bombs() -> std::io::Result<()> {
+        // zstd message decompressing over the limit
+        let mut encoder = zstd::stream::write::Encoder::new(Vec::new(), ZSTD_LEVEL)?;
+        for _ in 0..8 {
+            encoder.write_all(&[0; 1024 * 1024])?;
+        }
+        let mut bomb = encoder.finish()?;
+        assert!(bomb.len() < 4096);
+        bomb.push(ZSTD_TAG);
+        assert!(decode(bomb.clone(), 1024 * 1024).is_err());
+        assert_eq!(decode(bomb, MAX_SIZE).unwrap().len(), 8 * 1024 * 1024);
+
+        // zstd message requiring a huge window to be decoded
+        let mut encoder = zstd::stream::write::Encoder::new(Vec::new(), ZSTD_LEVEL)?;
+        encoder.window_log(30)?;
+        encoder.include_contentsize(false)?;
+        encoder.write_all(&[1; 1024])?;
+        let mut wide = encoder.finish()?;
+        wide.push(ZSTD_TAG);
+        assert!(decode(wide, MAX_SIZE).is_err());
+
+        // lz4 message declaring a huge decompressed size
+        let mut bomb = u32::MAX.to_le_bytes().to_vec();
+        bomb.extend([0; 8]);
+        bomb.push(LZ4_TAG);
+        assert!(decode(bomb, MAX_SIZE).is_err());
+        Ok(())
+    }
+}
@@ -1,12 +1,12 @@
 use std::borrow::Cow;
 use std::collections::{BTreeMap, HashMap};
-use std::net::{IpAddr, SocketAddr};
+use std::net::SocketAddr;
 use std::pin::Pin;
 use std::sync::atomic::AtomicU32;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use std::time::{Duration, Instant};
 
-use crate::config::PCK_VERSION;
+use crate::config::{CompressionConfig, PCK_VERSION};
 use crate::transport::crypto::TransportSecretKey;
 use crate::transport::packet_data::{AssymetricRSA, UnknownEncryption};
 use crate::transport::symmetric_message::OutboundConnection;
@@ -27,11 +27,14 @@ use tracing::{span, Instrument};
 use version_cmp::PROTOC_VERSION;
 
 use super::{
+    compression::{self, Compression},
     crypto::{TransportKeypair, TransportPublicKey},
     packet_data::{PacketData, SymmetricAES, MAX_PACKET_SIZE},
     peer_connection::{PeerConnection, RemoteConnection},
+    rate_limiter::BandwidthLimit,
     sent_packet_tracker::SentPacketTracker,
     symmetric_message::{SymmetricMessage, SymmetricMessagePayload},
+    webrtc::{is_virtual_addr, WebRtcSignaling, WebRtcSocket, WEBRTC_LISTEN_ADDR},
     Socket, TransportError,
 };
 
@@ -59,24 +62,72 @@ type GatewayConnectionFuture = BoxFuture<
 type TraverseNatFuture =
     BoxFuture<'static, Result<(RemoteConnection, InboundRemoteConnection), TransportError>>;
 
+/// Binds a socket on each of the listening addresses.
+///
+/// Inbound connections from every socket are notified through the same handler, and are
+/// always answered from the socket the remote peer dialed. Outbound connections are
+/// established from a socket of the same address family as the remote peer.
+///
+/// If `webrtc` is given, browser peers are accepted as well, through the signalling handle the
+/// slot is filled with.
 pub(crate) async fn create_connection_handler<S: Socket>(
     keypair: TransportKeypair,
-    listen_host: IpAddr,
-    listen_port: u16,
+    listen_addrs: &[SocketAddr],
     is_gateway: bool,
-    bandwith_limit: Option<usize>,
+    bandwith_limit: BandwidthLimit,
+    webrtc: Option<&OnceLock<WebRtcSignaling>>,
+    compression: &CompressionConfig,
+    max_message_size: usize,
 ) -> Result<(OutboundConnectionHandler, InboundConnectionHandler), TransportError> {
-    // Bind the UDP socket to the specified port
-    let socket = S::bind((listen_host, listen_port).into()).await?;
-    let (och, new_connection_notifier) = OutboundConnectionHandler::config_listener(
-        Arc::new(socket),
-        keypair,
-        is_gateway,
-        (listen_host, listen_port).into(),
-        bandwith_limit,
-    )?;
+    let (new_connection_sender, new_connection_notifier) = mpsc::channel(100);
+    let mut send_queues = Vec::with_capacity(listen_addrs.len());
+    for listen_addr in listen_addrs {
+        // Bind the UDP socket to the specified address
+        let socket = if listen_addr.is_ipv6() && listen_addrs.iter().any(SocketAddr::is_ipv4) {
+            S::bind_v6_only(*listen_addr).await?
+        } else {
+            S::bind(*listen_addr).await?
+        };
+        let send_queue = OutboundConnectionHandler::spawn_listener(
+            Arc::new(socket),
+            keypair.clone(),
+            is_gateway,
+            *listen_addr,
+            bandwith_limit.clone(),
+            compression.clone(),
+            max_message_size,
+            new_connection_sender.clone(),
+        );
+        send_queues.push((*listen_addr, send_queue));
+    }
+    if send_queues.is_empty() {
+        return Err(TransportError::Other(anyhow::anyhow!(
+            "no address to listen on"
+        )));
+    }
+    if let Some(signaling) = webrtc {
+        let socket = WebRtcSocket::bind(WEBRTC_LISTEN_ADDR).await?;
+        if signaling.set(socket.signaling()).is_err() {
+            return Err(TransportError::Other(anyhow::anyhow!(
+                "WebRTC listener already started"
+            )));
+        }
+        let send_queue = OutboundConnectionHandler::spawn_listener(
+            Arc::new(socket),
+            keypair.clone(),
+            is_gateway,
+            WEBRTC_LISTEN_ADDR,
+            bandwith_limit.clone(),
+            compression.clone(),
+            max_message_size,
+            new_connection_sender.clone(),
+        );
+        send_queues.push((WEBRTC_LISTEN_ADDR, send_queue));
+    }
     Ok((
-        och,
+        OutboundConnectionHandler {
+            send_queues: send_queues.into(),
+        },
         InboundConnectionHandler {
             new_connection_notifier,
         },
@@ -103,30 +154,38 @@ impl InboundConnectionHandler {
     }
 }
 
+type SendQueue = mpsc::Sender<(SocketAddr, ConnectionEvent)>;
+
 /// Requests a new outbound connection to a remote peer.
 #[derive(Clone)]
 pub(crate) struct OutboundConnectionHandler {
-    send_queue: mpsc::Sender<(SocketAddr, ConnectionEvent)>,
+    /// Connection requests queue of the listener bound to each local address.
+    send_queues: Arc<[(SocketAddr, SendQueue)]>,
 }
 
 #[cfg(test)]
 impl OutboundConnectionHandler {
-    pub fn new(send_queue: mpsc::Sender<(SocketAddr, ConnectionEvent)>) -> Self {
-        OutboundConnectionHandler { send_queue }
+    pub fn new(send_queue: SendQueue) -> Self {
+        OutboundConnectionHandler {
+            send_queues: Arc::new([((std::net::Ipv4Addr::UNSPECIFIED, 0).into(), send_queue)]),
+        }
     }
 }
 
 impl OutboundConnectionHandler {
-    fn config_listener(
+    /// Spawns the packets listener for the socket, returning its connection requests queue.
+    fn spawn_listener(
         socket: Arc<impl Socket>,
         keypair: TransportKeypair,
         is_gateway: bool,
         socket_addr: SocketAddr,
-        bandwith_limit: Option<usize>,
-    ) -> Result<(Self, mpsc::Receiver<PeerConnection>), TransportError> {
+        bandwith_limit: BandwidthLimit,
+        compression: CompressionConfig,
+        max_message_size: usize,
+        new_connection_sender: mpsc::Sender<PeerConnection>,
+    ) -> SendQueue {
         // Channel buffer is one so senders will await until the receiver is ready, important for bandwidth limiting
         let (conn_handler_sender, conn_handler_receiver) = mpsc::channel(100);
-        let (new_connection_sender, new_connection_notifier) = mpsc::channel(100);
 
         // Channel buffer is one so senders will await until the receiver is ready, important for bandwidth limiting
         let (outbound_sender, outbound_recv) = mpsc::channel(10000);
@@ -139,19 +198,33 @@ impl OutboundConnectionHandler {
             new_connection_notifier: new_connection_sender,
             outbound_packets: outbound_sender,
             this_addr: socket_addr,
+            compression,
+            max_message_size,
         };
         let bw_tracker = super::rate_limiter::PacketRateLimiter::new(
             DEFAULT_BW_TRACKER_WINDOW_SIZE,
             outbound_recv,
         );
-        let connection_handler = OutboundConnectionHandler {
-            send_queue: conn_handler_sender,
-        };
 
         task::spawn(bw_tracker.rate_limiter(bandwith_limit, socket));
         task::spawn(RANDOM_U64.scope(StdRng::from_entropy().gen(), transport.listen()));
 
-        Ok((connection_handler, new_connection_notifier))
+        conn_handler_sender
+    }
+
+    /// Picks the listener to connect from, preferring one of the same address family as the
+    /// remote peer. Browser peers are only reachable through the WebRTC listener.
+    fn send_queue_for(&self, remote_addr: SocketAddr) -> &SendQueue {
+        let remote_is_virtual = is_virtual_addr(&remote_addr);
+        let (_, send_queue) = self
+            .send_queues
+            .iter()
+            .find(|(local_addr, _)| {
+                is_virtual_addr(local_addr) == remote_is_virtual
+                    && local_addr.is_ipv4() == remote_addr.is_ipv4()
+            })
+            .unwrap_or(&self.send_queues[0]);
+        send_queue
     }
 
     #[cfg(test)]
@@ -161,7 +234,21 @@ impl OutboundConnectionHandler {
         keypair: TransportKeypair,
         is_gateway: bool,
     ) -> Result<(Self, mpsc::Receiver<PeerConnection>), TransportError> {
-        Self::config_listener(socket, keypair, is_gateway, socket_addr, None)
+        let (new_connection_sender, new_connection_notifier) = mpsc::channel(100);
+        let send_queue = Self::spawn_listener(
+            socket,
+            keypair,
+            is_gateway,
+            socket_addr,
+            BandwidthLimit::default(),
+            CompressionConfig::default(),
+            crate::config::PayloadLimits::default().max_message_size,
+            new_connection_sender,
+        );
+        let connection_handler = OutboundConnectionHandler {
+            send_queues: Arc::new([(socket_addr, send_queue)]),
+        };
+        Ok((connection_handler, new_connection_notifier))
     }
 
     pub async fn connect(
@@ -171,7 +258,7 @@ impl OutboundConnectionHandler {
     ) -> Pin<Box<dyn Future<Output = Result<PeerConnection, TransportError>> + Send>> {
         let (open_connection, recv_connection) = oneshot::channel();
         if self
-            .send_queue
+            .send_queue_for(remote_addr)
             .send((
                 remote_addr,
                 ConnectionEvent::ConnectionStart {
@@ -206,6 +293,9 @@ struct UdpPacketsListener<S = UdpSocket> {
     new_connection_notifier: mpsc::Sender<PeerConnection>,
     outbound_packets: mpsc::Sender<(SocketAddr, Arc<[u8]>)>,
     this_addr: SocketAddr,
+    compression: CompressionConfig,
+    /// Maximum size of the messages received from peers.
+    max_message_size: usize,
 }
 
 type OngoingConnection = (
@@ -484,6 +574,8 @@ impl<S: Socket> UdpPacketsListener<S> {
     ) {
         let secret = self.this_peer_keypair.secret.clone();
         let outbound_packets = self.outbound_packets.clone();
+        let compression_config = self.compression.clone();
+        let max_message_size = self.max_message_size;
 
         let (inbound_from_remote, mut next_inbound) =
             mpsc::channel::<PacketData<UnknownEncryption>>(1);
@@ -511,6 +603,10 @@ impl<S: Socket> UdpPacketsListener<S> {
                     cause: "invalid symmetric key".into(),
                 }
             })?;
+            let remote_codecs = decrypted_intro_packet
+                .get(PROTOC_VERSION.len() + 16)
+                .copied()
+                .unwrap_or_default();
             if protoc != PROTOC_VERSION {
                 let packet = SymmetricMessage::ack_error(&outbound_key)?;
                 outbound_packets
@@ -527,8 +623,13 @@ impl<S: Socket> UdpPacketsListener<S> {
             }
 
             let inbound_key = Aes128Gcm::new(&inbound_key_bytes.into());
-            let outbound_ack_packet =
-                SymmetricMessage::ack_ok(&outbound_key, inbound_key_bytes, remote_addr)?;
+            let supported_codecs = compression::supported_codecs(&compression_config);
+            let outbound_ack_packet = SymmetricMessage::ack_ok(
+                &outbound_key,
+                inbound_key_bytes,
+                remote_addr,
+                supported_codecs,
+            )?;
 
             tracing::debug!(%remote_addr, "Sending outbound ack packet: {:?}", outbound_ack_packet.data());
 
@@ -573,6 +674,12 @@ impl<S: Socket> UdpPacketsListener<S> {
                 inbound_symmetric_key: inbound_key,
                 inbound_symmetric_key_bytes: inbound_key_bytes,
                 my_address: None,
+                compression: Compression::negotiate(
+                    &compression_config,
+                    remote_codecs,
+                    max_message_size,
+                ),
+                supported_codecs,
             };
 
             let inbound_conn = InboundRemoteConnection {
@@ -622,6 +729,7 @@ impl<S: Socket> UdpPacketsListener<S> {
             packet: &PacketData<UnknownEncryption>,
             transport_secret_key: &TransportSecretKey,
             outbound_sym_key: &mut Option<Aes128Gcm>,
+            remote_codecs: &mut u8,
             state: &mut ConnectionState,
         ) -> Result<(), ()> {
             // probably the first packet to punch through the NAT
@@ -637,6 +745,11 @@ impl<S: Socket> UdpPacketsListener<S> {
                 let outbound_key =
                     Aes128Gcm::new_from_slice(outbound_key_bytes).expect("correct length");
                 *outbound_sym_key = Some(outbound_key.clone());
+                *remote_codecs = decrypted_intro_packet
+                    .data()
+                    .get(PROTOC_VERSION.len() + 16)
+                    .copied()
+                    .unwrap_or_default();
                 *state = ConnectionState::RemoteInbound {
                     intro_packet: packet.assert_assymetric(),
                 };
@@ -651,6 +764,8 @@ impl<S: Socket> UdpPacketsListener<S> {
         let (inbound_from_remote, mut next_inbound) =
             mpsc::channel::<PacketData<UnknownEncryption>>(1);
         let this_addr = self.this_addr;
+        let compression_config = self.compression.clone();
+        let max_message_size = self.max_message_size;
         let f = async move {
             let mut state = ConnectionState::StartOutbound {};
             // Initialize timeout and interval
@@ -664,10 +779,14 @@ impl<S: Socket> UdpPacketsListener<S> {
             let inbound_sym_key = Aes128Gcm::new(&inbound_sym_key_bytes.into());
 
             let mut outbound_sym_key: Option<Aes128Gcm> = None;
+            let supported_codecs = compression::supported_codecs(&compression_config);
+            let mut remote_codecs = 0;
             let outbound_intro_packet = {
-                let mut data = [0u8; { 16 + PROTOC_VERSION.len() }];
+                let mut data = [0u8; { 16 + PROTOC_VERSION.len() + 1 }];
                 data[..PROTOC_VERSION.len()].copy_from_slice(&PROTOC_VERSION);
-                data[PROTOC_VERSION.len()..].copy_from_slice(&inbound_sym_key_bytes);
+                data[PROTOC_VERSION.len()..PROTOC_VERSION.len() + 16]
+                    .copy_from_slice(&inbound_sym_key_bytes);
+                data[PROTOC_VERSION.len() + 16] = supported_codecs;
                 PacketData::<_, MAX_PACKET_SIZE>::encrypt_with_pubkey(&data, &remote_public_key)
             };
 
@@ -688,6 +807,7 @@ impl<S: Socket> UdpPacketsListener<S> {
                             outbound_sym_key.as_ref().expect("should be set"),
                             inbound_sym_key_bytes,
                             remote_addr,
+                            supported_codecs,
                         )?;
                         outbound_packets
                             .send((remote_addr, our_inbound.data().into()))
@@ -730,6 +850,7 @@ impl<S: Socket> UdpPacketsListener<S> {
                                                 Ok(OutboundConnection {
                                                     key,
                                                     remote_addr: my_address,
+                                                    codecs,
                                                 }),
                                         } => {
                                             let outbound_sym_key = Aes128Gcm::new_from_slice(&key)
@@ -746,6 +867,7 @@ impl<S: Socket> UdpPacketsListener<S> {
                                                         &outbound_sym_key,
                                                         inbound_sym_key_bytes,
                                                         remote_addr,
+                                                        supported_codecs,
                                                     )?
                                                     .data()
                                                     .into(),
@@ -768,6 +890,12 @@ impl<S: Socket> UdpPacketsListener<S> {
                                                     inbound_symmetric_key_bytes:
                                                         inbound_sym_key_bytes,
                                                     my_address: Some(my_address),
+                                                    compression: Compression::negotiate(
+                                                        &compression_config,
+                                                        codecs,
+                                                        max_message_size,
+                                                    ),
+                                                    supported_codecs,
                                                 },
                                                 InboundRemoteConnection {
                                                     inbound_packet_sender: inbound_sender,
@@ -793,6 +921,7 @@ impl<S: Socket> UdpPacketsListener<S> {
                                     &packet,
                                     &transport_secret_key,
                                     &mut outbound_sym_key,
+                                    &mut remote_codecs,
                                     &mut state,
                                 )
                                 .is_ok()
@@ -833,6 +962,12 @@ impl<S: Socket> UdpPacketsListener<S> {
                                         inbound_symmetric_key: inbound_sym_key,
                                         inbound_symmetric_key_bytes: inbound_sym_key_bytes,
                                         my_address: None,
+                                        compression: Compression::negotiate(
+                                            &compression_config,
+                                            remote_codecs,
+                                            max_message_size,
+                                        ),
+                                        supported_codecs,
                                     },
                                     InboundRemoteConnection {
                                         inbound_packet_sender: inbound_sender,
@@ -7,6 +7,7 @@ use std::{borrow::Cow, io, net::SocketAddr};
 use futures::Future;
 use tokio::net::UdpSocket;
 
+mod compression;
 mod connection_handler;
 mod crypto;
 mod packet_data;
@@ -16,24 +17,29 @@ mod rate_limiter;
 mod received_packet_tracker;
 mod sent_packet_tracker;
 mod symmetric_message;
+pub(crate) mod webrtc;
 
 type MessagePayload = Vec<u8>;
 
 type PacketId = u32;
 
+#[cfg(any(test, feature = "fuzzing"))]
+pub(crate) use self::compression::{decode as decode_message, Compression};
 pub use self::crypto::{TransportKeypair, TransportPublicKey};
+#[cfg(any(test, feature = "fuzzing"))]
+pub(crate) use self::symmetric_message::{SymmetricMessage, SymmetricMessagePayload};
 #[cfg(test)]
 pub(crate) use self::{
     connection_handler::ConnectionEvent,
     packet_data::{PacketData, UnknownEncryption},
     peer_connection::RemoteConnection,
-    symmetric_message::{SymmetricMessage, SymmetricMessagePayload},
 };
 pub(crate) use self::{
     connection_handler::{
         create_connection_handler, InboundConnectionHandler, OutboundConnectionHandler,
     },
     peer_connection::PeerConnection,
+    rate_limiter::BandwidthLimit,
 };
 
 #[derive(Debug, thiserror::Error)]
@@ -62,6 +68,11 @@ pub(crate) enum TransportError {
 /// Make connection handler more testable
 pub(crate) trait Socket: Sized + Send + Sync + 'static {
     fn bind(addr: SocketAddr) -> impl Future<Output = io::Result<Self>> + Send;
+    /// Binds an IPv6 socket which doesn't accept IPv4 traffic, so an IPv4 socket can be
+    /// bound to the same port.
+    fn bind_v6_only(addr: SocketAddr) -> impl Future<Output = io::Result<Self>> + Send {
+        Self::bind(addr)
+    }
     fn recv_from(
         &self,
         buf: &mut [u8],
@@ -78,6 +89,15 @@ impl Socket for UdpSocket {
         Self::bind(addr).await
     }
 
+    async fn bind_v6_only(addr: SocketAddr) -> io::Result<Self> {
+        use socket2::{Domain, Protocol, Type};
+        let socket = socket2::Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_only_v6(true)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&addr.into())?;
+        Self::from_std(socket.into())
+    }
+
     async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
         self.recv_from(buf).await
     }
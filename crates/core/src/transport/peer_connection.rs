@@ -19,6 +19,7 @@ mod inbound_stream;
 mod outbound_stream;
 
 use super::{
+    compression::Compression,
     connection_handler::SerializedMessage,
     packet_data::{self, PacketData},
     received_packet_tracker::ReceivedPacketTracker,
@@ -47,6 +48,10 @@ pub(crate) struct RemoteConnection {
     pub(super) inbound_symmetric_key: Aes128Gcm,
     pub(super) inbound_symmetric_key_bytes: [u8; 16],
     pub(super) my_address: Option<SocketAddr>,
+    /// Compression of the messages sent to the remote, as negotiated when connecting.
+    pub(super) compression: Compression,
+    /// Compression codecs advertised to the remote.
+    pub(super) supported_codecs: u8,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -157,6 +162,8 @@ impl PeerConnection {
             inbound_symmetric_key,
             inbound_symmetric_key_bytes: [1; 16],
             my_address: Some(my_address),
+            compression: Compression::NONE,
+            supported_codecs: 0,
         };
         (
             Self::new(remote),
@@ -186,20 +193,40 @@ impl PeerConnection {
                 inbound_symmetric_key,
                 inbound_symmetric_key_bytes: [1; 16],
                 my_address: Some(my_address),
+                compression: Compression::NONE,
+                supported_codecs: 0,
             },
             inbound_packet_sender,
             outbound_packets_recv,
         )
     }
 
-    #[instrument(name = "peer_connection", skip_all)]
     pub async fn send<T>(&mut self, data: T) -> Result
     where
         T: Serialize + Send + std::fmt::Debug + 'static,
     {
-        let data = tokio::task::spawn_blocking(move || bincode::serialize(&data).unwrap())
-            .await
-            .unwrap();
+        self.send_with(data, true).await
+    }
+
+    /// Sends the message without compressing it, e.g. because its payload is already compressed.
+    pub async fn send_uncompressed<T>(&mut self, data: T) -> Result
+    where
+        T: Serialize + Send + std::fmt::Debug + 'static,
+    {
+        self.send_with(data, false).await
+    }
+
+    #[instrument(name = "peer_connection", skip_all)]
+    async fn send_with<T>(&mut self, data: T, allow_compression: bool) -> Result
+    where
+        T: Serialize + Send + std::fmt::Debug + 'static,
+    {
+        let compression = self.remote_conn.compression;
+        let data = tokio::task::spawn_blocking(move || {
+            compression.encode(bincode::serialize(&data).unwrap(), allow_compression)
+        })
+        .await
+        .unwrap();
         if data.len() + SymmetricMessage::short_message_overhead() > MAX_DATA_SIZE {
             tracing::trace!(total_size = data.len(), "sending as stream");
             self.outbound_stream(data).await;
@@ -317,7 +344,7 @@ impl PeerConnection {
                         error
                     })? {
                         tracing::trace!(%packet_id, "returning full stream message");
-                        return Ok(msg);
+                        return self.remote_conn.compression.decode(msg);
                     }
                 }
                 inbound_stream = self.inbound_stream_futures.next(), if !self.inbound_stream_futures.is_empty() => {
@@ -332,7 +359,7 @@ impl PeerConnection {
                     };
                     self.inbound_streams.remove(&stream_id);
                     tracing::trace!(%stream_id, "stream finished");
-                    return Ok(msg);
+                    return self.remote_conn.compression.decode(msg);
                 }
                 outbound_stream = self.outbound_stream_futures.next(), if !self.outbound_stream_futures.is_empty() => {
                     let Some(res) = outbound_stream else {
@@ -400,6 +427,7 @@ impl PeerConnection {
                     &self.remote_conn.outbound_symmetric_key,
                     self.remote_conn.inbound_symmetric_key_bytes,
                     self.remote_conn.remote_addr,
+                    self.remote_conn.supported_codecs,
                 )?;
                 self.remote_conn
                     .outbound_packets
@@ -2,12 +2,36 @@ use tokio::sync::mpsc;
 
 use std::collections::VecDeque;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use super::Socket;
 use crate::util::time_source::{InstantTimeSrc, TimeSource};
 
+/// Upstream bandwidth limit shared by the rate limiters, which can be changed while they run.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct BandwidthLimit(Arc<AtomicUsize>);
+
+impl BandwidthLimit {
+    const UNLIMITED: usize = 0;
+
+    pub fn new(limit: Option<usize>) -> Self {
+        let this = Self::default();
+        this.set(limit);
+        this
+    }
+
+    pub fn get(&self) -> Option<usize> {
+        Some(self.0.load(Ordering::Relaxed)).filter(|limit| *limit != Self::UNLIMITED)
+    }
+
+    pub fn set(&self, limit: Option<usize>) {
+        self.0
+            .store(limit.unwrap_or(Self::UNLIMITED), Ordering::Relaxed);
+    }
+}
+
 /// Keeps track of the bandwidth used in the last window_size. Recommend a `window_size` of
 /// 10 seconds.
 pub(super) struct PacketRateLimiter<T: TimeSource> {
@@ -36,13 +60,16 @@ impl PacketRateLimiter<InstantTimeSrc> {
 impl<T: TimeSource> PacketRateLimiter<T> {
     pub(super) async fn rate_limiter<S: Socket>(
         mut self,
-        bandwidth_limit: Option<usize>,
+        bandwidth_limit: BandwidthLimit,
         socket: Arc<S>,
     ) {
-        tracing::info!(bandwidth_limit, "Rate limiter task started");
+        tracing::info!(
+            bandwidth_limit = bandwidth_limit.get(),
+            "Rate limiter task started"
+        );
         while let Some((socket_addr, packet)) = self.outbound_packets.recv().await {
             // tracing::trace!(%socket_addr, packet_len = %packet.len(), "Sending outbound packet");
-            if let Some(bandwidth_limit) = bandwidth_limit {
+            if let Some(bandwidth_limit) = bandwidth_limit.get() {
                 self.rate_limiting(bandwidth_limit, &*socket, packet, socket_addr)
                     .await;
             } else if let Err(error) = socket.send_to(&packet, socket_addr).await {
@@ -96,6 +96,7 @@ impl SymmetricMessage {
         outbound_sym_key: &Aes128Gcm,
         our_inbound_key: [u8; 16],
         remote_addr: SocketAddr,
+        codecs: u8,
     ) -> Result<PacketData<SymmetricAES>, bincode::Error> {
         let message = Self {
             packet_id: Self::FIRST_PACKET_ID,
@@ -104,6 +105,7 @@ impl SymmetricMessage {
                 result: Ok(OutboundConnection {
                     key: our_inbound_key,
                     remote_addr,
+                    codecs,
                 }),
             },
         };
@@ -220,6 +222,8 @@ impl From<StreamFragment> for SymmetricMessagePayload {
 pub(crate) struct OutboundConnection {
     pub(super) key: [u8; 16],
     pub(super) remote_addr: SocketAddr,
+    /// Compression codecs supported by the remote, see [`super::compression`].
+    pub(super) codecs: u8,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -302,6 +306,7 @@ mod test {
                 result: Ok(OutboundConnection {
                     key: [0; 16],
                     remote_addr: (Ipv4Addr::LOCALHOST, 1234).into(),
+                    codecs: 0,
                 }),
             },
             SymmetricMessagePayload::AckConnection {
@@ -354,13 +359,15 @@ mod test {
                 result: Ok(OutboundConnection {
                     key: [0; 16],
                     remote_addr: (Ipv4Addr::LOCALHOST, 1234).into(),
+                    codecs: 0,
                 }),
             },
         })?;
         let _dec: SymmetricMessage = bincode::deserialize(&enc)?;
 
         let key = gen_key();
-        let packet = SymmetricMessage::ack_ok(&key, [0; 16], (Ipv4Addr::LOCALHOST, 1234).into())?;
+        let packet =
+            SymmetricMessage::ack_ok(&key, [0; 16], (Ipv4Addr::LOCALHOST, 1234).into(), 0)?;
         let data = packet.decrypt(&key).unwrap();
         let deser = SymmetricMessage::deser(data.data())?;
         assert!(matches!(
@@ -397,4 +404,30 @@ mod test {
         let size = bincode::serialized_size(&msg).unwrap();
         assert_eq!(size, MAX_DATA_SIZE as u64);
     }
+
+    #[test]
+    fn deser_truncated_packets() {
+        // truncating a valid packet anywhere fails cleanly
+        let packet = bincode::serialize(&SymmetricMessage {
+            packet_id: 7,
+            confirm_receipt: vec![5, 6],
+            payload: SymmetricMessagePayload::ShortMessage {
+                payload: vec![1; 32],
+            },
+        })
+        .unwrap();
+        for len in 0..packet.len() {
+            assert!(SymmetricMessage::deser(&packet[..len]).is_err());
+        }
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn deser_malformed_packets(
+            packet in proptest::collection::vec(proptest::prelude::any::<u8>(), 0..MAX_DATA_SIZE),
+        ) {
+            // errors are fine, panics are not
+            let _ = SymmetricMessage::deser(&packet);
+        }
+    }
 }
@@ -0,0 +1,332 @@
+//! WebRTC data channel transport, so browsers can join the network as lightweight peers.
+//!
+//! A browser peer sends its SDP offer through the client API of a gateway node, authenticated
+//! with its auth token. The node answers it once ICE gathering completes (no trickle ICE, so
+//! signalling is a single request). The data channel the browser opens then carries the regular
+//! transport packets, so the connection is established and encrypted exactly as with UDP peers.
+//!
+//! Browser peers are not reachable from the internet, so each one is given a virtual address in
+//! a unique local IPv6 range which is only routable through the gateway which accepted it.
+//!
+//! Every answered offer holds one of the [`MAX_PEERS`] slots of the gateway until its connection
+//! is over, so each auth token can only have [`MAX_PENDING_PER_TOKEN`] offers waiting for their
+//! data channel, and offers whose data channel isn't opened within [`DATA_CHANNEL_TIMEOUT`] are
+//! dropped.
+
+use std::{
+    io,
+    net::{Ipv6Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use bytes::Bytes;
+use dashmap::DashMap;
+use parking_lot::Mutex as SyncMutex;
+use tokio::sync::{mpsc, Mutex};
+use webrtc::{
+    api::{APIBuilder, API},
+    data_channel::{data_channel_message::DataChannelMessage, RTCDataChannel},
+    peer_connection::{
+        configuration::RTCConfiguration, peer_connection_state::RTCPeerConnectionState,
+        sdp::session_description::RTCSessionDescription, RTCPeerConnection,
+    },
+};
+
+use super::{Socket, TransportError};
+use crate::client_events::AuthToken;
+
+/// Unique local prefix of the virtual addresses assigned to browser peers.
+const VIRTUAL_PREFIX: u128 = 0xfd46_7265_656e_6574 << 64;
+/// Address the WebRTC listener is bound to.
+pub(crate) const WEBRTC_LISTEN_ADDR: SocketAddr = SocketAddr::new(
+    std::net::IpAddr::V6(Ipv6Addr::new(0xfd46, 0x7265, 0x656e, 0x6574, 0, 0, 0, 0)),
+    0,
+);
+/// Maximum number of browser peers connected, or connecting, at the same time.
+const MAX_PEERS: usize = 256;
+/// Maximum number of offers of an auth token whose data channel isn't opened yet.
+const MAX_PENDING_PER_TOKEN: usize = 4;
+/// Maximum time, after answering an offer, for the browser to open its data channel.
+const DATA_CHANNEL_TIMEOUT: Duration = Duration::from_secs(30);
+/// Maximum time waiting for ICE candidates to be gathered before answering an offer.
+const ICE_GATHERING_TIMEOUT: Duration = Duration::from_secs(10);
+const INBOUND_QUEUE_SIZE: usize = 1024;
+
+pub(crate) fn is_virtual_addr(addr: &SocketAddr) -> bool {
+    match addr {
+        SocketAddr::V6(addr) => u128::from(*addr.ip()) >> 64 == VIRTUAL_PREFIX >> 64,
+        SocketAddr::V4(_) => false,
+    }
+}
+
+struct WebRtcPeer {
+    connection: Arc<RTCPeerConnection>,
+    channel: Option<Arc<RTCDataChannel>>,
+    /// Token the offer was made with.
+    token: AuthToken,
+}
+
+type Peers = Arc<DashMap<SocketAddr, WebRtcPeer>>;
+
+/// Datagram socket over the data channels of the connected browser peers.
+pub(crate) struct WebRtcSocket {
+    peers: Peers,
+    inbound: Mutex<mpsc::Receiver<(SocketAddr, Bytes)>>,
+    signaling: WebRtcSignaling,
+}
+
+impl WebRtcSocket {
+    /// Signalling handle through which browser peers connect to this socket.
+    pub fn signaling(&self) -> WebRtcSignaling {
+        self.signaling.clone()
+    }
+}
+
+impl Socket for WebRtcSocket {
+    async fn bind(addr: SocketAddr) -> io::Result<Self> {
+        if !is_virtual_addr(&addr) {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrNotAvailable,
+                "WebRTC listeners can only be bound to virtual addresses",
+            ));
+        }
+        let peers = Peers::default();
+        let (inbound_sender, inbound) = mpsc::channel(INBOUND_QUEUE_SIZE);
+        let signaling = WebRtcSignaling {
+            api: Arc::new(APIBuilder::new().build()),
+            peers: peers.clone(),
+            admission: Arc::default(),
+            inbound_sender,
+            next_peer: Arc::new(AtomicU64::new(1)),
+            port: addr.port(),
+        };
+        Ok(Self {
+            peers,
+            inbound: Mutex::new(inbound),
+            signaling,
+        })
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let mut inbound = self.inbound.lock().await;
+        loop {
+            let (remote_addr, data) = inbound
+                .recv()
+                .await
+                .ok_or_else(|| io::Error::from(io::ErrorKind::BrokenPipe))?;
+            // a truncated packet would fail decryption anyway, so don't hand it over
+            if data.len() > buf.len() {
+                tracing::debug!(%remote_addr, size = data.len(), "dropping oversized WebRTC message");
+                continue;
+            }
+            buf[..data.len()].copy_from_slice(&data);
+            return Ok((data.len(), remote_addr));
+        }
+    }
+
+    async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        let channel = self
+            .peers
+            .get(&target)
+            .and_then(|peer| peer.channel.clone())
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+        channel
+            .send(&Bytes::copy_from_slice(buf))
+            .await
+            .map_err(io::Error::other)
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct WebRtcSignaling {
+    api: Arc<API>,
+    peers: Peers,
+    /// Held while checking there's room for a peer and inserting it.
+    admission: Arc<SyncMutex<()>>,
+    inbound_sender: mpsc::Sender<(SocketAddr, Bytes)>,
+    next_peer: Arc<AtomicU64>,
+    port: u16,
+}
+
+impl WebRtcSignaling {
+    /// Accepts the SDP offer of a browser peer, made with the given auth token, returning the
+    /// SDP answer.
+    pub async fn accept_offer(
+        &self,
+        offer: String,
+        token: AuthToken,
+    ) -> Result<String, TransportError> {
+        self.check_capacity(&token)?;
+        let peer = self.next_peer.fetch_add(1, Ordering::Relaxed) as u128;
+        let remote_addr = SocketAddr::new(Ipv6Addr::from(VIRTUAL_PREFIX | peer).into(), self.port);
+        let connection = Arc::new(
+            self.api
+                .new_peer_connection(RTCConfiguration::default())
+                .await
+                .map_err(webrtc_error)?,
+        );
+        let admitted = {
+            let _admission = self.admission.lock();
+            self.check_capacity(&token).map(|()| {
+                self.peers.insert(
+                    remote_addr,
+                    WebRtcPeer {
+                        connection: connection.clone(),
+                        channel: None,
+                        token,
+                    },
+                );
+            })
+        };
+        if let Err(err) = admitted {
+            let _ = connection.close().await;
+            return Err(err);
+        }
+
+        let peers = self.peers.clone();
+        let inbound_sender = self.inbound_sender.clone();
+        connection.on_data_channel(Box::new(move |channel: Arc<RTCDataChannel>| {
+            let peers = peers.clone();
+            let inbound_sender = inbound_sender.clone();
+            Box::pin(async move {
+                tracing::debug!(%remote_addr, label = %channel.label(), "WebRTC data channel opened");
+                channel.on_message(Box::new(move |msg: DataChannelMessage| {
+                    let inbound_sender = inbound_sender.clone();
+                    Box::pin(async move {
+                        let _ = inbound_sender.send((remote_addr, msg.data)).await;
+                    })
+                }));
+                if let Some(mut peer) = peers.get_mut(&remote_addr) {
+                    peer.channel = Some(channel);
+                }
+            })
+        }));
+        let peers = self.peers.clone();
+        connection.on_peer_connection_state_change(Box::new(
+            move |state: RTCPeerConnectionState| {
+                let peers = peers.clone();
+                Box::pin(async move {
+                    tracing::debug!(%remote_addr, %state, "WebRTC peer connection state changed");
+                    if matches!(
+                        state,
+                        RTCPeerConnectionState::Failed | RTCPeerConnectionState::Closed
+                    ) {
+                        if let Some((_, peer)) = peers.remove(&remote_addr) {
+                            let _ = peer.connection.close().await;
+                        }
+                    }
+                })
+            },
+        ));
+
+        let answer = async {
+            let offer = RTCSessionDescription::offer(offer).map_err(webrtc_error)?;
+            connection
+                .set_remote_description(offer)
+                .await
+                .map_err(webrtc_error)?;
+            let answer = connection.create_answer(None).await.map_err(webrtc_error)?;
+            let mut gathering_complete = connection.gathering_complete_promise().await;
+            connection
+                .set_local_description(answer)
+                .await
+                .map_err(webrtc_error)?;
+            let _ = tokio::time::timeout(ICE_GATHERING_TIMEOUT, gathering_complete.recv()).await;
+            connection
+                .local_description()
+                .await
+                .map(|answer| answer.sdp)
+                .ok_or_else(|| TransportError::ConnectionEstablishmentFailure {
+                    cause: "missing local description".into(),
+                })
+        }
+        .await;
+        if answer.is_err() {
+            self.peers.remove(&remote_addr);
+            let _ = connection.close().await;
+            return answer;
+        }
+
+        let peers = self.peers.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(DATA_CHANNEL_TIMEOUT).await;
+            if let Some((_, peer)) = peers.remove_if(&remote_addr, |_, peer| peer.channel.is_none())
+            {
+                tracing::debug!(%remote_addr, "WebRTC data channel not opened in time");
+                let _ = peer.connection.close().await;
+            }
+        });
+        answer
+    }
+
+    /// Checks there's room for one more peer, and one more pending offer of the token.
+    fn check_capacity(&self, token: &AuthToken) -> Result<(), TransportError> {
+        let too_many = |cause: &'static str| {
+            Err(TransportError::ConnectionEstablishmentFailure {
+                cause: cause.into(),
+            })
+        };
+        if self.peers.len() >= MAX_PEERS {
+            return too_many("too many WebRTC peers");
+        }
+        let pending = self
+            .peers
+            .iter()
+            .filter(|peer| peer.channel.is_none() && peer.token == *token)
+            .count();
+        if pending >= MAX_PENDING_PER_TOKEN {
+            return too_many("too many pending WebRTC offers for the auth token");
+        }
+        Ok(())
+    }
+}
+
+fn webrtc_error(error: webrtc::Error) -> TransportError {
+    TransportError::ConnectionEstablishmentFailure {
+        cause: error.to_string().into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn virtual_addresses() {
+        assert!(is_virtual_addr(&WEBRTC_LISTEN_ADDR));
+        let peer = SocketAddr::new(Ipv6Addr::from(VIRTUAL_PREFIX | 42).into(), 0);
+        assert!(is_virtual_addr(&peer));
+        assert!(!is_virtual_addr(&([127, 0, 0, 1], 31337).into()));
+        assert!(!is_virtual_addr(&(Ipv6Addr::LOCALHOST, 31337).into()));
+    }
+
+    #[tokio::test]
+    async fn cap_pending_offers_per_token() -> anyhow::Result<()> {
+        let socket = WebRtcSocket::bind(WEBRTC_LISTEN_ADDR).await?;
+        let signaling = socket.signaling();
+        let token = AuthToken::generate();
+        for peer in 0..MAX_PENDING_PER_TOKEN as u128 {
+            signaling.check_capacity(&token)?;
+            let connection = signaling
+                .api
+                .new_peer_connection(RTCConfiguration::default())
+                .await?;
+            signaling.peers.insert(
+                SocketAddr::new(Ipv6Addr::from(VIRTUAL_PREFIX | peer).into(), 0),
+                WebRtcPeer {
+                    connection: Arc::new(connection),
+                    channel: None,
+                    token: token.clone(),
+                },
+            );
+        }
+        assert!(signaling.check_capacity(&token).is_err());
+        // other tokens can still make offers
+        signaling.check_capacity(&AuthToken::generate())?;
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 pub(crate) mod time_source;
+pub(crate) mod wasm_sections;
 
 use std::{
     borrow::Borrow,
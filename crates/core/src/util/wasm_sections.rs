@@ -0,0 +1,52 @@
+//! Lookup of custom sections in WASM modules without compiling them.
+
+use std::ops::Range;
+
+const WASM_HEADER_LEN: usize = 8;
+const CUSTOM_SECTION_ID: u8 = 0;
+
+pub(crate) struct CustomSection<'a> {
+    /// Byte range of the whole section in the module.
+    pub range: Range<usize>,
+    pub content: &'a [u8],
+}
+
+/// Finds the first custom section with the given name in a WASM module.
+pub(crate) fn find_custom_section<'a>(code: &'a [u8], name: &[u8]) -> Option<CustomSection<'a>> {
+    if code.len() < WASM_HEADER_LEN || &code[..4] != b"\0asm" {
+        return None;
+    }
+    let mut pos = WASM_HEADER_LEN;
+    while pos < code.len() {
+        let section_start = pos;
+        let id = code[pos];
+        pos += 1;
+        let (size, read) = read_leb128_u32(&code[pos..])?;
+        pos += read;
+        let end = pos.checked_add(size as usize)?;
+        let payload = code.get(pos..end)?;
+        if id == CUSTOM_SECTION_ID {
+            let (name_len, read) = read_leb128_u32(payload)?;
+            let section_name = payload.get(read..read + name_len as usize)?;
+            if section_name == name {
+                return Some(CustomSection {
+                    range: section_start..end,
+                    content: &payload[read + name_len as usize..],
+                });
+            }
+        }
+        pos = end;
+    }
+    None
+}
+
+fn read_leb128_u32(bytes: &[u8]) -> Option<(u32, usize)> {
+    let mut result = 0u32;
+    for (i, byte) in bytes.iter().take(5).enumerate() {
+        result |= ((byte & 0x7f) as u32) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((result, i + 1));
+        }
+    }
+    None
+}
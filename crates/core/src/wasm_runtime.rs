@@ -1,23 +1,34 @@
+mod abi;
 mod contract;
 mod contract_store;
+mod cross_contract;
 mod delegate;
 mod delegate_store;
+mod engine;
 mod error;
+mod key_management;
 mod native_api;
 mod runtime;
 mod secrets_store;
 mod state_store;
+mod stats;
 mod store;
 #[cfg(test)]
 mod tests;
+mod validation;
 
 pub(crate) use contract::ContractRuntimeInterface;
 pub use contract_store::ContractStore;
+pub(crate) use cross_contract::ContractStateSource;
 pub(crate) use delegate::DelegateRuntimeInterface;
 pub use delegate_store::DelegateStore;
+pub use engine::{engine_metrics, EngineMetricsReport, WasmEngine};
 pub(crate) use error::{ContractError, RuntimeInnerError, RuntimeResult};
-pub use runtime::{ContractExecError, Runtime};
-pub(crate) use secrets_store::SecretStoreError;
+pub(crate) use native_api::time::DEFAULT_TIME_EPOCH;
+pub use runtime::{ContractExecError, Runtime, RuntimeConfig};
 pub use secrets_store::SecretsStore;
+pub(crate) use secrets_store::{SecretStoreError, SecretsConsent};
 pub use state_store::StateStore;
 pub(crate) use state_store::{StateStorage, StateStoreError};
+pub use stats::{contract_stats, contract_stats_of, render_prometheus, ContractStatsReport};
+pub use validation::PutRejection;
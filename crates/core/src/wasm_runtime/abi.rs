@@ -0,0 +1,94 @@
+//! Versioning of the host ABI exposed to contracts and delegates.
+//!
+//! Each ABI version has its own set of host functions, registered under versioned import
+//! namespaces (e.g. `freenet_time` for v1 and `freenet_time_v2` for v2), so modules compiled
+//! against an older stdlib keep running when the ABI evolves.
+//!
+//! The version a module targets is read from its `freenet-abi` custom section if present,
+//! otherwise it is inferred from the namespaces it imports from, defaulting to v1.
+
+use wasmer::Module;
+
+use super::{RuntimeInnerError, RuntimeResult};
+use crate::util::wasm_sections::find_custom_section;
+
+const ABI_SECTION: &str = "freenet-abi";
+const V2_NAMESPACE_SUFFIX: &str = "_v2";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum AbiVersion {
+    V1,
+    V2,
+}
+
+impl AbiVersion {
+    pub const ALL: [AbiVersion; 2] = [AbiVersion::V1, AbiVersion::V2];
+
+    pub fn detect(module: &Module) -> RuntimeResult<Self> {
+        if let Some(section) = module.custom_sections(ABI_SECTION).next() {
+            return Self::from_section(&section);
+        }
+        let targets_v2 = module
+            .imports()
+            .any(|import| import.module().ends_with(V2_NAMESPACE_SUFFIX));
+        Ok(if targets_v2 {
+            AbiVersion::V2
+        } else {
+            AbiVersion::V1
+        })
+    }
+
+    /// Version declared in the `freenet-abi` section of a module, if any, without compiling it.
+    pub fn declared(code: &[u8]) -> RuntimeResult<Option<Self>> {
+        find_custom_section(code, ABI_SECTION.as_bytes())
+            .map(|section| Self::from_section(section.content))
+            .transpose()
+    }
+
+    fn from_section(section: &[u8]) -> RuntimeResult<Self> {
+        match std::str::from_utf8(section).map(str::trim) {
+            Ok("1") => Ok(AbiVersion::V1),
+            Ok("2") => Ok(AbiVersion::V2),
+            _ => Err(RuntimeInnerError::UnsupportedAbi(
+                String::from_utf8_lossy(section).into_owned(),
+            )),
+        }
+    }
+
+    /// Name of the import namespace for the given host module in this ABI version.
+    pub fn namespace(self, module: &str) -> String {
+        match self {
+            AbiVersion::V1 => module.to_owned(),
+            AbiVersion::V2 => format!("{module}{V2_NAMESPACE_SUFFIX}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn abi_from_section() {
+        assert_eq!(AbiVersion::from_section(b"1").unwrap(), AbiVersion::V1);
+        assert_eq!(AbiVersion::from_section(b" 2\n").unwrap(), AbiVersion::V2);
+        assert!(AbiVersion::from_section(b"3").is_err());
+    }
+
+    #[test]
+    fn abi_from_imports() {
+        let store = wasmer::Store::new(super::super::WasmEngine::Singlepass.build(None).unwrap());
+        let v1 = Module::new(
+            &store,
+            r#"(module (import "freenet_time" "__frnt__time__utc_now" (func (param i64 i64))))"#,
+        )
+        .unwrap();
+        assert_eq!(AbiVersion::detect(&v1).unwrap(), AbiVersion::V1);
+        let v2 = Module::new(
+            &store,
+            r#"(module (import "freenet_time_v2" "__frnt__time__utc_now" (func (param i64) (result i64))))"#,
+        )
+        .unwrap();
+        assert_eq!(AbiVersion::detect(&v2).unwrap(), AbiVersion::V2);
+    }
+}
@@ -1,6 +1,6 @@
 use std::{
     thread::{self, JoinHandle},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use super::{ContractExecError, RuntimeResult};
@@ -83,13 +83,13 @@ impl ContractRuntimeInterface for super::Runtime {
             state_buf.ptr()
         };
         let related_buf_ptr = {
-            let serialized = bincode::serialize(related)?;
-            let mut related_buf = self.init_buf(&running.instance, &serialized)?;
-            related_buf.write(serialized)?;
+            let related_buf = self.init_buf_serialized(&running.instance, related)?;
             related_buf.ptr()
         };
 
         let mut wasm_store = self.wasm_store.take().unwrap();
+        // the validity of a state can't depend on when it is validated
+        self.clock.as_mut(&mut wasm_store).expose(false);
         let validate_func: TypedFunction<(i64, i64, i64), FfiReturnTy> = match running
             .instance
             .exports
@@ -105,24 +105,27 @@ impl ContractRuntimeInterface for super::Runtime {
         let param_buf_ptr = param_buf_ptr as i64;
         let state_buf_ptr = state_buf_ptr as i64;
         let related_buf_ptr = related_buf_ptr as i64;
+        let metrics = self.engine.metrics();
         let t = std::thread::spawn(move || {
-            let r = validate_func.call(
-                &mut wasm_store,
-                param_buf_ptr,
-                state_buf_ptr,
-                related_buf_ptr,
-            );
-            (r, wasm_store)
+            let (r, elapsed) = metrics.timed_execution(|| {
+                validate_func.call(
+                    &mut wasm_store,
+                    param_buf_ptr,
+                    state_buf_ptr,
+                    related_buf_ptr,
+                )
+            });
+            (r, wasm_store, elapsed)
         });
-        let r = handle_execution_call(t, self);
+        let (r, elapsed) = handle_execution_call(t, self);
 
-        let result = match_err(self, &running.instance, r)?;
-        let is_valid = unsafe {
+        let result = match_err(self, &running.instance, r).and_then(|result| unsafe {
             ContractInterfaceResult::from_raw(result, &linear_mem)
                 .unwrap_validate_state_res(linear_mem)
-                .map_err(Into::<ContractExecError>::into)?
-        };
-        Ok(is_valid)
+                .map_err(|err| ContractExecError::from(err).into())
+        });
+        self.record_contract_call(key, &running.instance, elapsed, result.is_err());
+        result
     }
 
     fn update_state(
@@ -151,13 +154,12 @@ impl ContractRuntimeInterface for super::Runtime {
             state_buf.ptr()
         };
         let update_data_buf_ptr = {
-            let serialized = bincode::serialize(update_data)?;
-            let mut update_data_buf = self.init_buf(&running.instance, &serialized)?;
-            update_data_buf.write(serialized)?;
+            let update_data_buf = self.init_buf_serialized(&running.instance, update_data)?;
             update_data_buf.ptr()
         };
 
         let mut wasm_store = self.wasm_store.take().unwrap();
+        self.clock.as_mut(&mut wasm_store).expose(true);
         let update_state_func: TypedFunction<(i64, i64, i64), FfiReturnTy> = match running
             .instance
             .exports
@@ -173,25 +175,27 @@ impl ContractRuntimeInterface for super::Runtime {
         let param_buf_ptr = param_buf_ptr as i64;
         let state_buf_ptr = state_buf_ptr as i64;
         let update_data_buf_ptr = update_data_buf_ptr as i64;
+        let metrics = self.engine.metrics();
         let t = std::thread::spawn(move || {
-            let r = update_state_func.call(
-                &mut wasm_store,
-                param_buf_ptr,
-                state_buf_ptr,
-                update_data_buf_ptr,
-            );
-            (r, wasm_store)
+            let (r, elapsed) = metrics.timed_execution(|| {
+                update_state_func.call(
+                    &mut wasm_store,
+                    param_buf_ptr,
+                    state_buf_ptr,
+                    update_data_buf_ptr,
+                )
+            });
+            (r, wasm_store, elapsed)
         });
-        let r = handle_execution_call(t, self);
+        let (r, elapsed) = handle_execution_call(t, self);
 
-        let result = match_err(self, &running.instance, r)?;
-        let update_res = unsafe {
+        let result = match_err(self, &running.instance, r).and_then(|result| unsafe {
             ContractInterfaceResult::from_raw(result, &linear_mem)
                 .unwrap_update_state(linear_mem)
-                .map_err(Into::<ContractExecError>::into)?
-        };
-
-        Ok(update_res)
+                .map_err(|err| ContractExecError::from(err).into())
+        });
+        self.record_contract_call(key, &running.instance, elapsed, result.is_err());
+        result
     }
 
     fn summarize_state(
@@ -216,6 +220,7 @@ impl ContractRuntimeInterface for super::Runtime {
         };
 
         let mut wasm_store = self.wasm_store.take().unwrap();
+        self.clock.as_mut(&mut wasm_store).expose(true);
         let summary_func: TypedFunction<(i64, i64), FfiReturnTy> = match running
             .instance
             .exports
@@ -230,20 +235,22 @@ impl ContractRuntimeInterface for super::Runtime {
 
         let param_buf_ptr = param_buf_ptr as i64;
         let state_buf_ptr = state_buf_ptr as i64;
+        let metrics = self.engine.metrics();
         let t = std::thread::spawn(move || {
-            let r = summary_func.call(&mut wasm_store, param_buf_ptr, state_buf_ptr);
-            (r, wasm_store)
+            let (r, elapsed) = metrics.timed_execution(|| {
+                summary_func.call(&mut wasm_store, param_buf_ptr, state_buf_ptr)
+            });
+            (r, wasm_store, elapsed)
         });
-        let r = handle_execution_call(t, self);
+        let (r, elapsed) = handle_execution_call(t, self);
 
-        let result = match_err(self, &running.instance, r)?;
-        let result = unsafe {
+        let result = match_err(self, &running.instance, r).and_then(|result| unsafe {
             ContractInterfaceResult::from_raw(result, &linear_mem)
                 .unwrap_summarize_state(linear_mem)
-                .map_err(Into::<ContractExecError>::into)?
-        };
-
-        Ok(result)
+                .map_err(|err| ContractExecError::from(err).into())
+        });
+        self.record_contract_call(key, &running.instance, elapsed, result.is_err());
+        result
     }
 
     fn get_state_delta<'a>(
@@ -274,6 +281,7 @@ impl ContractRuntimeInterface for super::Runtime {
         };
 
         let mut wasm_store = self.wasm_store.take().unwrap();
+        self.clock.as_mut(&mut wasm_store).expose(true);
         let get_state_delta_func: TypedFunction<(i64, i64, i64), FfiReturnTy> = running
             .instance
             .exports
@@ -282,32 +290,37 @@ impl ContractRuntimeInterface for super::Runtime {
         let param_buf_ptr = param_buf_ptr as i64;
         let state_buf_ptr = state_buf_ptr as i64;
         let summary_buf_ptr = summary_buf_ptr as i64;
+        let metrics = self.engine.metrics();
         let t = std::thread::spawn(move || {
-            let r = get_state_delta_func.call(
-                &mut wasm_store,
-                param_buf_ptr,
-                state_buf_ptr,
-                summary_buf_ptr,
-            );
-            (r, wasm_store)
+            let (r, elapsed) = metrics.timed_execution(|| {
+                get_state_delta_func.call(
+                    &mut wasm_store,
+                    param_buf_ptr,
+                    state_buf_ptr,
+                    summary_buf_ptr,
+                )
+            });
+            (r, wasm_store, elapsed)
         });
-        let r = handle_execution_call(t, self);
+        let (r, elapsed) = handle_execution_call(t, self);
 
-        let result = match_err(self, &running.instance, r)?;
-        let result = unsafe {
+        let result = match_err(self, &running.instance, r).and_then(|result| unsafe {
             ContractInterfaceResult::from_raw(result, &linear_mem)
                 .unwrap_get_state_delta(linear_mem)
-                .map_err(Into::<ContractExecError>::into)?
-        };
-
-        Ok(result)
+                .map_err(|err| ContractExecError::from(err).into())
+        });
+        self.record_contract_call(key, &running.instance, elapsed, result.is_err());
+        result
     }
 }
 
+/// Waits for the execution of a contract function, returning its result along with the time it
+/// took.
 fn handle_execution_call(
-    r: JoinHandle<(Result<i64, wasmer::RuntimeError>, Store)>,
+    r: JoinHandle<(Result<i64, wasmer::RuntimeError>, Store, Duration)>,
     rt: &mut super::Runtime,
-) -> Result<i64, Errors> {
+) -> (Result<i64, Errors>, Duration) {
+    let started = Instant::now();
     for _ in 0..5 {
         if r.is_finished() {
             break;
@@ -315,13 +328,16 @@ fn handle_execution_call(
         thread::sleep(Duration::from_secs(1));
     }
     if !r.is_finished() {
-        return Err(Errors::MaxComputeTimeExceeded);
+        return (Err(Errors::MaxComputeTimeExceeded), started.elapsed());
     }
-    let (r, s) = r
-        .join()
-        .map_err(|_| Errors::Other(anyhow::anyhow!("Failed to join thread")))?;
+    let Ok((r, s, elapsed)) = r.join() else {
+        return (
+            Err(Errors::Other(anyhow::anyhow!("Failed to join thread"))),
+            started.elapsed(),
+        );
+    };
     rt.wasm_store = Some(s);
-    r.map_err(Errors::Wasmer)
+    (r.map_err(Errors::Wasmer), elapsed)
 }
 
 fn match_err(
@@ -63,6 +63,11 @@ impl ContractStore {
         })
     }
 
+    /// Changes the max size in bytes of the contracts being cached.
+    pub fn set_cache_size(&self, max_size: i64) {
+        self.contract_cache.update_max_cost(max_size);
+    }
+
     /// Returns a copy of the contract bytes if available, none otherwise.
     // todo: instead return Result<Option<_>, _> to handle IO errors upstream
     pub fn fetch_contract(
@@ -202,6 +207,41 @@ impl ContractStore {
     pub fn code_hash_from_key(&self, key: &ContractKey) -> Option<CodeHash> {
         self.key_to_code_part.get(key.id()).map(|r| r.value().1)
     }
+
+    pub(super) fn code_reader(&self) -> ContractCodeReader {
+        ContractCodeReader {
+            contracts_dir: self.contracts_dir.clone(),
+            key_to_code_part: self.key_to_code_part.clone(),
+        }
+    }
+}
+
+/// Read-only view of the stored contract code, usable outside of the runtime (e.g. from
+/// host functions).
+#[derive(Clone)]
+pub(super) struct ContractCodeReader {
+    contracts_dir: PathBuf,
+    key_to_code_part: Arc<DashMap<ContractInstanceId, (u64, CodeHash)>>,
+}
+
+impl ContractCodeReader {
+    pub fn code_hash(&self, id: &ContractInstanceId) -> Option<CodeHash> {
+        self.key_to_code_part.get(id).map(|r| r.value().1)
+    }
+
+    pub fn fetch_code(&self, code_hash: &CodeHash) -> Option<ContractCode<'static>> {
+        let key_path = self
+            .contracts_dir
+            .join(code_hash.encode())
+            .with_extension("wasm");
+        ContractCode::load_versioned_from_path(&key_path)
+            .map(|(code, _ver)| code)
+            .map_err(|err| {
+                tracing::debug!("contract not found: {err}");
+                err
+            })
+            .ok()
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,457 @@
+//! Host interface through which contracts read the state of other contracts.
+//!
+//! Composite applications often need the state of a contract to validate or update another
+//! one. Instead of having the client fetch it and pass it along, a contract can request it
+//! from the node through the `freenet_contract.__frnt__contract__read_state` import:
+//!
+//! - with an empty query the current state of the target contract is returned as stored,
+//! - otherwise the query is handed as the summary to the target contract `get_state_delta`,
+//!   so the target decides which part of its state to return.
+//!
+//! Queries execute the target contract on the same store as the caller. The call tree is
+//! limited to [`MAX_CALL_DEPTH`] nested contracts, a contract can't be re-entered while it is
+//! executing higher up in the tree, and when metering is enabled every nested execution draws
+//! from the fuel left to its caller, so the whole tree is bounded by the budget of the top
+//! level invocation.
+//!
+//! The import returns the size of the result, writing it to the output buffer only when it
+//! fits, so callers can retry with a buffer large enough; or a negative [`ReadError`] code,
+//! also when the key, query or output buffers aren't within the memory of the caller.
+
+use std::{collections::HashMap, sync::Arc};
+
+use freenet_stdlib::{
+    memory::{
+        buf::{BufferBuilder, BufferMut},
+        WasmLinearMem,
+    },
+    prelude::*,
+};
+use wasmer::{
+    AsStoreMut, Bytes, Function, FunctionEnv, FunctionEnvMut, Imports, Instance, Memory,
+    MemoryView, Module, TypedFunction,
+};
+use wasmer_middlewares::metering::{get_remaining_points, set_remaining_points, MeteringPoints};
+
+use super::{
+    abi::AbiVersion,
+    contract_store::ContractCodeReader,
+    native_api::MEM_ADDR,
+    runtime::{Key, RunningInstance},
+};
+
+/// Maximum number of contracts executed on behalf of a single top level contract call.
+pub(super) const MAX_CALL_DEPTH: usize = 4;
+/// Fuel charged to the caller for every read, on top of any execution of the target.
+const READ_COST: u64 = 10_000;
+
+/// Source of the contract states the node hosts.
+pub(crate) trait ContractStateSource: Send + Sync {
+    /// Returns the parameters and current state of the contract, if hosted by this node.
+    fn state(&self, key: &ContractKey) -> Option<(Parameters<'static>, WrappedState)>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i64)]
+pub(super) enum ReadError {
+    NotFound = -1,
+    DepthExceeded = -2,
+    Reentrant = -3,
+    OutOfFuel = -4,
+    Failed = -5,
+    Unavailable = -6,
+    OutOfBounds = -7,
+}
+
+/// Result of a read, written to the memory of the caller.
+enum ReadOutput {
+    /// The whole state, as shared with the state source.
+    State(WrappedState),
+    Delta(Vec<u8>),
+}
+
+impl AsRef<[u8]> for ReadOutput {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            ReadOutput::State(state) => state.as_ref(),
+            ReadOutput::Delta(delta) => delta,
+        }
+    }
+}
+
+pub(super) struct CrossContractEnv {
+    pub source: Option<Arc<dyn ContractStateSource>>,
+    pub imports: HashMap<AbiVersion, Imports>,
+    code: ContractCodeReader,
+    modules: HashMap<CodeHash, Module>,
+    /// Contracts executing in the current call tree, outermost first.
+    call_stack: Vec<ContractInstanceId>,
+    metering: bool,
+    /// Memory shared by all instances, if any.
+    ///
+    /// With a shared host memory nested instances would overwrite the caller buffers.
+    host_mem: Option<Memory>,
+    /// Memory limit of the engine, nested instances are never grown beyond it.
+    max_pages: u32,
+}
+
+impl CrossContractEnv {
+    pub fn new(
+        code: ContractCodeReader,
+        metering: bool,
+        host_mem: Option<Memory>,
+        max_pages: u32,
+    ) -> Self {
+        Self {
+            source: None,
+            imports: HashMap::new(),
+            code,
+            modules: HashMap::new(),
+            call_stack: Vec::new(),
+            metering,
+            host_mem,
+            max_pages,
+        }
+    }
+}
+
+pub(super) fn prepare_export(
+    abi: AbiVersion,
+    store: &mut wasmer::Store,
+    imports: &mut Imports,
+    env: &FunctionEnv<CrossContractEnv>,
+) {
+    let read_state = Function::new_typed_with_env(store, env, read_state);
+    imports.register_namespace(
+        &abi.namespace("freenet_contract"),
+        [("__frnt__contract__read_state".to_owned(), read_state.into())],
+    );
+}
+
+fn read_state(
+    mut env: FunctionEnvMut<CrossContractEnv>,
+    id: i64,
+    key_ptr: i64,
+    query_ptr: i64,
+    query_len: i32,
+    out_ptr: i64,
+    out_cap: i32,
+) -> i64 {
+    if id == -1 {
+        panic!("unset module id");
+    }
+    // don't hold the entry while executing other contracts, which register their own
+    let (caller, caller_instance) = {
+        let info = MEM_ADDR.get(&id).expect("instance mem space not recorded");
+        (info.contract_id(), info.instance.clone())
+    };
+    // only contracts can read the state of other contracts, delegates can't
+    let Some(caller) = caller else {
+        return ReadError::Unavailable as i64;
+    };
+    let Some(memory) = env
+        .data()
+        .host_mem
+        .clone()
+        .or_else(|| caller_instance.exports.get_memory("memory").ok().cloned())
+    else {
+        return ReadError::Unavailable as i64;
+    };
+    let (target, query) = {
+        let view = memory.view(&env);
+        let mut key = [0u8; 32];
+        let query = guest_range(&view, key_ptr, key.len() as i64)
+            .and_then(|key_ptr| view.read(key_ptr, &mut key).map_err(out_of_bounds))
+            .and_then(|_| guest_range(&view, query_ptr, query_len as i64))
+            .and_then(|query_ptr| {
+                let mut query = vec![0u8; query_len as usize];
+                view.read(query_ptr, &mut query).map_err(out_of_bounds)?;
+                Ok(query)
+            });
+        match query {
+            Ok(query) => (ContractInstanceId::new(key), query),
+            Err(err) => return err as i64,
+        }
+    };
+
+    match read(&mut env, caller, &caller_instance, target, query) {
+        Ok(result) => {
+            let result = result.as_ref();
+            if result.len() <= out_cap.max(0) as usize {
+                let view = memory.view(&env);
+                if let Err(err) = guest_range(&view, out_ptr, result.len() as i64)
+                    .and_then(|out_ptr| view.write(out_ptr, result).map_err(out_of_bounds))
+                {
+                    return err as i64;
+                }
+            }
+            result.len() as i64
+        }
+        Err(err) => {
+            tracing::debug!(contract = %caller, %target, "cross-contract read failed: {err:?}");
+            err as i64
+        }
+    }
+}
+
+/// Returns the offset of a buffer of the guest if the whole buffer is within its memory.
+fn guest_range(view: &MemoryView, ptr: i64, len: i64) -> Result<u64, ReadError> {
+    let (Ok(ptr), Ok(len)) = (u64::try_from(ptr), u64::try_from(len)) else {
+        return Err(ReadError::OutOfBounds);
+    };
+    match ptr.checked_add(len) {
+        Some(end) if end <= view.data_size() => Ok(ptr),
+        _ => Err(ReadError::OutOfBounds),
+    }
+}
+
+fn out_of_bounds(err: wasmer::MemoryAccessError) -> ReadError {
+    tracing::debug!("cross-contract read out of the caller memory: {err}");
+    ReadError::OutOfBounds
+}
+
+fn read(
+    env: &mut FunctionEnvMut<CrossContractEnv>,
+    caller: ContractInstanceId,
+    caller_instance: &Instance,
+    target: ContractInstanceId,
+    query: Vec<u8>,
+) -> Result<ReadOutput, ReadError> {
+    let source = env.data().source.clone().ok_or(ReadError::Unavailable)?;
+    if env.data().metering {
+        charge(&mut env.as_store_mut(), caller_instance, READ_COST)?;
+    }
+    let (params, state) = source
+        .state(&ContractKey::from(target))
+        .ok_or(ReadError::NotFound)?;
+    if query.is_empty() {
+        return Ok(ReadOutput::State(state));
+    }
+
+    let is_root = {
+        let data = env.data_mut();
+        if data.host_mem.is_some() {
+            return Err(ReadError::Unavailable);
+        }
+        let is_root = data.call_stack.is_empty();
+        if is_root {
+            data.call_stack.push(caller);
+        }
+        is_root
+    };
+    let result = guarded_call(env, caller_instance, target, params, state, query);
+    if is_root {
+        env.data_mut().call_stack.clear();
+    }
+    result.map(ReadOutput::Delta)
+}
+
+fn guarded_call(
+    env: &mut FunctionEnvMut<CrossContractEnv>,
+    caller_instance: &Instance,
+    target: ContractInstanceId,
+    params: Parameters<'static>,
+    state: WrappedState,
+    query: Vec<u8>,
+) -> Result<Vec<u8>, ReadError> {
+    {
+        let data = env.data_mut();
+        check_call(&data.call_stack, &target)?;
+        data.call_stack.push(target);
+    }
+    let result = get_state_delta(env, caller_instance, target, params, state, query);
+    env.data_mut().call_stack.pop();
+    result
+}
+
+fn check_call(
+    call_stack: &[ContractInstanceId],
+    target: &ContractInstanceId,
+) -> Result<(), ReadError> {
+    if call_stack.contains(target) {
+        return Err(ReadError::Reentrant);
+    }
+    if call_stack.len() >= MAX_CALL_DEPTH {
+        return Err(ReadError::DepthExceeded);
+    }
+    Ok(())
+}
+
+fn get_state_delta(
+    env: &mut FunctionEnvMut<CrossContractEnv>,
+    caller_instance: &Instance,
+    target: ContractInstanceId,
+    params: Parameters<'static>,
+    state: WrappedState,
+    query: Vec<u8>,
+) -> Result<Vec<u8>, ReadError> {
+    let module = load_module(env, &target)?;
+    let abi = AbiVersion::detect(&module).map_err(failed)?;
+    let imports = env
+        .data()
+        .imports
+        .get(&abi)
+        .cloned()
+        .ok_or(ReadError::Unavailable)?;
+    let metering = env.data().metering;
+    let max_pages = env.data().max_pages;
+
+    let mut store = env.as_store_mut();
+    let instance = Instance::new(&mut store, &module, &imports).map_err(failed)?;
+    let memory = instance
+        .exports
+        .get_memory("memory")
+        .map_err(failed)?
+        .clone();
+    let req_pages: wasmer::Pages = Bytes::from(params.size() + state.size() + query.len())
+        .try_into()
+        .map_err(failed)?;
+    let current_pages = memory.view(&store).size();
+    if current_pages < req_pages {
+        if req_pages.0 > max_pages {
+            return Err(ReadError::Failed);
+        }
+        // grows by the number of pages missing, not to a number of pages
+        memory
+            .grow(&mut store, req_pages - current_pages)
+            .map_err(failed)?;
+    }
+    let running = RunningInstance::register(&mut store, instance, &memory, Key::Contract(target))
+        .map_err(failed)?;
+
+    let param_buf_ptr = write_buf(&mut store, &running.instance, &memory, params.as_ref())?;
+    let state_buf_ptr = write_buf(&mut store, &running.instance, &memory, state.as_ref())?;
+    let query_buf_ptr = write_buf(&mut store, &running.instance, &memory, &query)?;
+    let get_state_delta_func: TypedFunction<(i64, i64, i64), i64> = running
+        .instance
+        .exports
+        .get_typed_function(&store, "get_state_delta")
+        .map_err(failed)?;
+
+    if metering {
+        let remaining = remaining_points(&mut store, caller_instance)?;
+        set_remaining_points(&mut store, &running.instance, remaining);
+    }
+    let result = get_state_delta_func.call(&mut store, param_buf_ptr, state_buf_ptr, query_buf_ptr);
+    if metering {
+        let remaining = remaining_points(&mut store, &running.instance).unwrap_or(0);
+        set_remaining_points(&mut store, caller_instance, remaining);
+        if remaining == 0 {
+            return Err(ReadError::OutOfFuel);
+        }
+    }
+    let result = result.map_err(failed)?;
+
+    let linear_mem = linear_mem(&mut store, &memory);
+    let delta = unsafe {
+        ContractInterfaceResult::from_raw(result, &linear_mem)
+            .unwrap_get_state_delta(linear_mem)
+            .map_err(failed)?
+    };
+    Ok(delta.as_ref().to_vec())
+}
+
+fn load_module(
+    env: &mut FunctionEnvMut<CrossContractEnv>,
+    target: &ContractInstanceId,
+) -> Result<Module, ReadError> {
+    let (data, store) = env.data_and_store_mut();
+    let code_hash = data.code.code_hash(target).ok_or(ReadError::NotFound)?;
+    if let Some(module) = data.modules.get(&code_hash) {
+        return Ok(module.clone());
+    }
+    let code = data
+        .code
+        .fetch_code(&code_hash)
+        .ok_or(ReadError::NotFound)?;
+    let module = Module::new(&store, code.data()).map_err(failed)?;
+    data.modules.insert(code_hash, module.clone());
+    Ok(module)
+}
+
+fn write_buf(
+    store: &mut impl AsStoreMut,
+    instance: &Instance,
+    memory: &Memory,
+    data: &[u8],
+) -> Result<i64, ReadError> {
+    let initiate_buffer: TypedFunction<u32, i64> = instance
+        .exports
+        .get_typed_function(&*store, "__frnt__initiate_buffer")
+        .map_err(failed)?;
+    let builder_ptr = initiate_buffer
+        .call(store, data.len() as u32)
+        .map_err(failed)?;
+    let mut buf = unsafe {
+        BufferMut::from_ptr(builder_ptr as *mut BufferBuilder, linear_mem(store, memory))
+    };
+    buf.write(data).map_err(failed)?;
+    Ok(buf.ptr() as i64)
+}
+
+fn linear_mem(store: &mut impl AsStoreMut, memory: &Memory) -> WasmLinearMem {
+    let view = memory.view(&*store);
+    unsafe { WasmLinearMem::new(view.data_ptr() as *const _, view.data_size()) }
+}
+
+fn remaining_points(store: &mut impl AsStoreMut, instance: &Instance) -> Result<u64, ReadError> {
+    match get_remaining_points(store, instance) {
+        MeteringPoints::Remaining(points) => Ok(points),
+        MeteringPoints::Exhausted => Err(ReadError::OutOfFuel),
+    }
+}
+
+fn charge(store: &mut impl AsStoreMut, instance: &Instance, cost: u64) -> Result<(), ReadError> {
+    let remaining = remaining_points(store, instance)?;
+    // once exhausted the caller traps on its next metered instruction
+    set_remaining_points(store, instance, remaining.saturating_sub(cost));
+    if remaining < cost {
+        return Err(ReadError::OutOfFuel);
+    }
+    Ok(())
+}
+
+fn failed(err: impl std::fmt::Display) -> ReadError {
+    tracing::debug!("failed executing contract on cross-contract read: {err}");
+    ReadError::Failed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn call_guards() {
+        let ids: Vec<_> = (0..=MAX_CALL_DEPTH as u8)
+            .map(|i| ContractInstanceId::new([i; 32]))
+            .collect();
+        let other = ContractInstanceId::new([u8::MAX; 32]);
+        assert_eq!(check_call(&ids[..1], &other), Ok(()));
+        assert_eq!(check_call(&ids[..MAX_CALL_DEPTH - 1], &other), Ok(()));
+        assert_eq!(
+            check_call(&ids[..MAX_CALL_DEPTH], &other),
+            Err(ReadError::DepthExceeded)
+        );
+        assert_eq!(check_call(&ids[..2], &ids[0]), Err(ReadError::Reentrant));
+    }
+
+    #[test]
+    fn guest_bounds() {
+        let mut store = wasmer::Store::default();
+        let memory = Memory::new(&mut store, wasmer::MemoryType::new(1, None, false)).unwrap();
+        let view = memory.view(&store);
+        let size = view.data_size() as i64;
+        assert_eq!(guest_range(&view, 0, size), Ok(0));
+        assert_eq!(guest_range(&view, size - 32, 32), Ok(size as u64 - 32));
+        assert_eq!(
+            guest_range(&view, size - 31, 32),
+            Err(ReadError::OutOfBounds)
+        );
+        assert_eq!(guest_range(&view, -1, 32), Err(ReadError::OutOfBounds));
+        assert_eq!(guest_range(&view, 0, -1), Err(ReadError::OutOfBounds));
+        assert_eq!(
+            guest_range(&view, i64::MAX, i64::MAX),
+            Err(ReadError::OutOfBounds)
+        );
+    }
+}
@@ -2,16 +2,24 @@ use std::collections::{HashMap, HashSet, VecDeque};
 
 use chacha20poly1305::{XChaCha20Poly1305, XNonce};
 use freenet_stdlib::prelude::{
-    ApplicationMessage, ClientResponse, DelegateContainer, DelegateContext, DelegateError,
-    DelegateInterfaceResult, DelegateKey, GetSecretRequest, GetSecretResponse, InboundDelegateMsg,
-    OutboundDelegateMsg, Parameters, SecretsId, SetSecretRequest,
+    ApplicationMessage, ClientResponse, ContractInstanceId, DelegateContainer, DelegateContext,
+    DelegateError, DelegateInterfaceResult, DelegateKey, GetSecretRequest, GetSecretResponse,
+    InboundDelegateMsg, NotificationMessage, OutboundDelegateMsg, Parameters, SecretsId,
+    SetSecretRequest, UserInputRequest,
 };
 use serde::{Deserialize, Serialize};
 use wasmer::{Instance, TypedFunction};
 
 use super::error::RuntimeInnerError;
+use super::secrets_store::{SecretStoreError, SecretsConsent};
 use super::{ContractError, Runtime, RuntimeResult};
 
+/// Responses offered by the prompts asking the user to let an application read its namespace of
+/// the secrets of a delegate. They are answered through the client API by the owning UI, never
+/// through the delegate.
+const CONSENT_ALLOW: &str = "allow";
+const CONSENT_DENY: &str = "deny";
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Response {
     Allowed,
@@ -45,7 +53,7 @@ pub(crate) trait DelegateRuntimeInterface {
         &mut self,
         key: &DelegateKey,
         params: &Parameters,
-        attested: Option<&[u8]>,
+        attested: Option<&ContractInstanceId>,
         inbound: Vec<InboundDelegateMsg>,
     ) -> RuntimeResult<Vec<OutboundDelegateMsg>>;
 
@@ -63,7 +71,7 @@ impl Runtime {
     fn exec_inbound(
         &mut self,
         params: &Parameters<'_>,
-        attested: Option<&[u8]>,
+        attested: Option<&ContractInstanceId>,
         msg: &InboundDelegateMsg,
         process_func: &TypedFunction<(i64, i64, i64), i64>,
         instance: &Instance,
@@ -74,7 +82,7 @@ impl Runtime {
             param_buf.ptr()
         };
         let attested_buf_ptr = {
-            let bytes = attested.unwrap_or(&[]);
+            let bytes = attested.map(|app| app.as_bytes()).unwrap_or(&[]);
             let mut attested_buf = self.init_buf(instance, bytes)?;
             attested_buf.write(bytes)?;
             attested_buf.ptr()
@@ -85,12 +93,15 @@ impl Runtime {
             msg_buf.write(msg)?;
             msg_buf.ptr()
         };
-        let res = process_func.call(
-            self.wasm_store.as_mut().unwrap(),
-            param_buf_ptr as i64,
-            attested_buf_ptr as i64,
-            msg_ptr as i64,
-        )?;
+        let wasm_store = self.wasm_store.as_mut().unwrap();
+        let res = self.engine.metrics().time_execution(|| {
+            process_func.call(
+                wasm_store,
+                param_buf_ptr as i64,
+                attested_buf_ptr as i64,
+                msg_ptr as i64,
+            )
+        })?;
         let linear_mem = self.linear_mem(instance)?;
         let outbound = unsafe {
             DelegateInterfaceResult::from_raw(res, &linear_mem)
@@ -108,7 +119,7 @@ impl Runtime {
         instance: &Instance,
         process_func: &TypedFunction<(i64, i64, i64), i64>,
         params: &Parameters<'_>,
-        attested: Option<&[u8]>,
+        attested: Option<&ContractInstanceId>,
         outbound_msgs: &mut VecDeque<OutboundDelegateMsg>,
         results: &mut Vec<OutboundDelegateMsg>,
     ) -> RuntimeResult<DelegateContext> {
@@ -123,7 +134,10 @@ impl Runtime {
                 OutboundDelegateMsg::GetSecretRequest(GetSecretRequest {
                     key, processed, ..
                 }) if !processed => {
-                    let secret = self.secret_store.get_secret(delegate_key, &key)?;
+                    if !self.secrets_access(delegate_key, attested, &key, results)? {
+                        continue;
+                    }
+                    let secret = self.secret_store.get_secret(delegate_key, attested, &key)?;
                     let inbound = InboundDelegateMsg::GetSecretResponse(GetSecretResponse {
                         key,
                         value: Some(secret),
@@ -169,9 +183,10 @@ impl Runtime {
                 OutboundDelegateMsg::SetSecretRequest(SetSecretRequest { key, value }) => {
                     if let Some(plaintext) = value {
                         self.secret_store
-                            .store_secret(delegate_key, &key, plaintext)?;
+                            .store_secret(delegate_key, attested, &key, plaintext)?;
                     } else {
-                        self.secret_store.remove_secret(delegate_key, &key)?;
+                        self.secret_store
+                            .remove_secret(delegate_key, attested, &key)?;
                     }
                 }
                 OutboundDelegateMsg::ApplicationMessage(msg) if !msg.processed => {
@@ -222,6 +237,80 @@ impl Runtime {
         }
         Ok(last_context)
     }
+
+    /// Whether the secrets of the namespace of the application can be released. The first time an
+    /// application reads them a prompt is sent back to its client connection, and nothing is
+    /// released until the owning UI allows it through the client API; the application then sends
+    /// its request again.
+    fn secrets_access(
+        &mut self,
+        delegate_key: &DelegateKey,
+        app: Option<&ContractInstanceId>,
+        secret: &SecretsId,
+        results: &mut Vec<OutboundDelegateMsg>,
+    ) -> RuntimeResult<bool> {
+        let Some(app) = app else {
+            return Ok(true);
+        };
+        if self.secret_store.has_consent(delegate_key, app) {
+            return Ok(true);
+        }
+        self.prompt_consent(delegate_key, app, Some(secret), results)?;
+        Ok(false)
+    }
+
+    /// Sends a prompt asking the user to let the application read the secrets of the delegate,
+    /// unless one was already sent in this batch of results.
+    fn prompt_consent(
+        &mut self,
+        delegate_key: &DelegateKey,
+        app: &ContractInstanceId,
+        secret: Option<&SecretsId>,
+        results: &mut Vec<OutboundDelegateMsg>,
+    ) -> RuntimeResult<()> {
+        let request_id = self.secret_store.request_consent(delegate_key, app);
+        let prompted = results.iter().any(|msg| {
+            matches!(msg, OutboundDelegateMsg::RequestUserInput(req) if req.request_id == request_id)
+        });
+        if prompted {
+            return Ok(());
+        }
+        tracing::info!(%delegate_key, %app, request_id, "prompting for consent to read the delegate secrets");
+        let message = NotificationMessage::try_from(&serde_json::json!({
+            "secrets_access": {
+                "request_id": request_id,
+                "delegate": delegate_key.encode(),
+                "application": app.encode(),
+                "secret": secret.map(|secret| secret.encode()),
+            }
+        }))
+        .map_err(|_| {
+            DelegateExecError::from(DelegateError::Other("invalid consent prompt".into()))
+        })?;
+        results.push(OutboundDelegateMsg::RequestUserInput(UserInputRequest {
+            request_id,
+            message,
+            responses: [CONSENT_ALLOW, CONSENT_DENY]
+                .iter()
+                .map(|response| ClientResponse::new(response.as_bytes().to_vec()))
+                .collect(),
+        }));
+        Ok(())
+    }
+
+    /// Prompts asking the user to let applications read the secrets of delegates.
+    pub(crate) fn pending_secrets_consents(&self) -> Vec<SecretsConsent> {
+        self.secret_store.pending_consents()
+    }
+
+    /// Answers a consent prompt, returning whether it was pending.
+    pub(crate) fn resolve_secrets_consent(
+        &mut self,
+        request_id: u32,
+        allowed: bool,
+    ) -> Result<bool, SecretStoreError> {
+        self.secret_store.resolve_consent(request_id, allowed)
+    }
 }
 
 impl DelegateRuntimeInterface for Runtime {
@@ -229,7 +318,7 @@ impl DelegateRuntimeInterface for Runtime {
         &mut self,
         delegate_key: &DelegateKey,
         params: &Parameters,
-        attested: Option<&[u8]>,
+        attested: Option<&ContractInstanceId>,
         inbound: Vec<InboundDelegateMsg>,
     ) -> RuntimeResult<Vec<OutboundDelegateMsg>> {
         let mut results = Vec::with_capacity(inbound.len());
@@ -237,6 +326,9 @@ impl DelegateRuntimeInterface for Runtime {
             return Ok(results);
         }
         let running = self.prepare_delegate_call(params, delegate_key, 4096)?;
+        self.keys
+            .as_mut(self.wasm_store.as_mut().unwrap())
+            .start_call(delegate_key.clone(), attested.copied());
         let process_func: TypedFunction<(i64, i64, i64), i64> = running
             .instance
             .exports
@@ -330,13 +422,19 @@ impl DelegateRuntimeInterface for Runtime {
                     key: secret_key, ..
                 }) => {
                     if attested.is_some() {
-                        let secret = self.secret_store.get_secret(delegate_key, &secret_key)?;
-                        let msg = OutboundDelegateMsg::GetSecretResponse(GetSecretResponse {
-                            key: secret_key,
-                            value: Some(secret),
-                            context: Default::default(),
-                        });
-                        results.push(msg);
+                        if self.secrets_access(delegate_key, attested, &secret_key, &mut results)? {
+                            let secret = self.secret_store.get_secret(
+                                delegate_key,
+                                attested,
+                                &secret_key,
+                            )?;
+                            let msg = OutboundDelegateMsg::GetSecretResponse(GetSecretResponse {
+                                key: secret_key,
+                                value: Some(secret),
+                                context: Default::default(),
+                            });
+                            results.push(msg);
+                        }
                     } else {
                         return Err(DelegateExecError::UnauthorizedSecretAccess {
                             secret: secret_key.clone(),
@@ -353,14 +451,27 @@ impl DelegateRuntimeInterface for Runtime {
                 OutboundDelegateMsg::SetSecretRequest(SetSecretRequest { key, value }) => {
                     if let Some(plaintext) = value {
                         self.secret_store
-                            .store_secret(delegate_key, &key, plaintext)?;
+                            .store_secret(delegate_key, attested, &key, plaintext)?;
                     } else {
-                        self.secret_store.remove_secret(delegate_key, &key)?;
+                        self.secret_store
+                            .remove_secret(delegate_key, attested, &key)?;
                     }
                 }
                 _ => unreachable!(),
             }
         }
+        let keys = self
+            .keys
+            .as_mut(self.wasm_store.as_mut().unwrap())
+            .end_call();
+        for (key, value) in keys.stored {
+            self.secret_store
+                .store_secret(delegate_key, attested, &key, value)?;
+        }
+        if let (true, Some(app)) = (keys.consent_required, attested) {
+            // the delegate was told through the key imports, the user is asked here
+            self.prompt_consent(delegate_key, app, None, &mut results)?;
+        }
         Ok(results)
     }
 
@@ -61,6 +61,11 @@ impl DelegateStore {
         })
     }
 
+    /// Changes the max size in bytes of the delegates being cached.
+    pub fn set_cache_size(&self, max_size: i64) {
+        self.delegate_cache.update_max_cost(max_size);
+    }
+
     // Returns a copy of the delegate bytes if available, none otherwise.
     pub fn fetch_delegate(
         &self,
@@ -0,0 +1,212 @@
+//! Selection of the wasmer compiler backend and per-engine timing metrics.
+//!
+//! Singlepass compiles fast but produces slower code, which suits gateways handling many
+//! short-lived contracts; Cranelift and LLVM take longer to compile but execute faster on
+//! long running nodes. Cranelift and LLVM are only available when the node is built with the
+//! `wasm-cranelift` and `wasm-llvm` features respectively.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use wasmer::{CompilerConfig, Engine, ModuleMiddleware};
+
+use super::{RuntimeInnerError, RuntimeResult};
+
+#[derive(
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Serialize,
+    Deserialize,
+    clap::ValueEnum,
+    schemars::JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum WasmEngine {
+    #[default]
+    Singlepass,
+    Cranelift,
+    Llvm,
+}
+
+impl WasmEngine {
+    const ALL: [WasmEngine; 3] = [
+        WasmEngine::Singlepass,
+        WasmEngine::Cranelift,
+        WasmEngine::Llvm,
+    ];
+
+    /// Whether support for this engine was compiled in.
+    pub fn is_available(self) -> bool {
+        match self {
+            WasmEngine::Singlepass => true,
+            WasmEngine::Cranelift => cfg!(feature = "wasm-cranelift"),
+            WasmEngine::Llvm => cfg!(feature = "wasm-llvm"),
+        }
+    }
+
+    pub(super) fn build(
+        self,
+        middleware: Option<Arc<dyn ModuleMiddleware>>,
+    ) -> RuntimeResult<Engine> {
+        fn with_middleware(
+            mut config: impl CompilerConfig + 'static,
+            middleware: Option<Arc<dyn ModuleMiddleware>>,
+        ) -> Engine {
+            if let Some(middleware) = middleware {
+                config.push_middleware(middleware);
+            }
+            wasmer::EngineBuilder::new(config).engine()
+        }
+
+        match self {
+            WasmEngine::Singlepass => Ok(with_middleware(
+                wasmer_compiler_singlepass::Singlepass::default(),
+                middleware,
+            )),
+            #[cfg(feature = "wasm-cranelift")]
+            WasmEngine::Cranelift => Ok(with_middleware(
+                wasmer_compiler_cranelift::Cranelift::default(),
+                middleware,
+            )),
+            #[cfg(feature = "wasm-llvm")]
+            WasmEngine::Llvm => Ok(with_middleware(
+                wasmer_compiler_llvm::LLVM::default(),
+                middleware,
+            )),
+            #[allow(unreachable_patterns)]
+            engine => Err(RuntimeInnerError::EngineNotAvailable(engine)),
+        }
+    }
+
+    pub(super) fn metrics(self) -> &'static EngineMetrics {
+        &METRICS[self as usize]
+    }
+}
+
+impl std::fmt::Display for WasmEngine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WasmEngine::Singlepass => write!(f, "singlepass"),
+            WasmEngine::Cranelift => write!(f, "cranelift"),
+            WasmEngine::Llvm => write!(f, "llvm"),
+        }
+    }
+}
+
+static METRICS: [EngineMetrics; 3] = [
+    EngineMetrics::new(),
+    EngineMetrics::new(),
+    EngineMetrics::new(),
+];
+
+/// Process wide compile and execution timings for a given engine.
+pub(super) struct EngineMetrics {
+    compilations: AtomicU64,
+    compile_time_us: AtomicU64,
+    executions: AtomicU64,
+    execution_time_us: AtomicU64,
+}
+
+impl EngineMetrics {
+    const fn new() -> Self {
+        Self {
+            compilations: AtomicU64::new(0),
+            compile_time_us: AtomicU64::new(0),
+            executions: AtomicU64::new(0),
+            execution_time_us: AtomicU64::new(0),
+        }
+    }
+
+    pub fn time_compilation<T>(&self, f: impl FnOnce() -> T) -> T {
+        let (result, elapsed) = timed(f);
+        self.compilations.fetch_add(1, Ordering::Relaxed);
+        self.compile_time_us
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        result
+    }
+
+    pub fn time_execution<T>(&self, f: impl FnOnce() -> T) -> T {
+        self.timed_execution(f).0
+    }
+
+    /// Like [`Self::time_execution`], also returning the time the execution took.
+    pub fn timed_execution<T>(&self, f: impl FnOnce() -> T) -> (T, Duration) {
+        let (result, elapsed) = timed(f);
+        self.executions.fetch_add(1, Ordering::Relaxed);
+        self.execution_time_us
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        (result, elapsed)
+    }
+}
+
+fn timed<T>(f: impl FnOnce() -> T) -> (T, Duration) {
+    let start = Instant::now();
+    let result = f();
+    (result, start.elapsed())
+}
+
+#[derive(Debug, Serialize)]
+pub struct EngineMetricsReport {
+    pub engine: WasmEngine,
+    pub available: bool,
+    pub compilations: u64,
+    pub avg_compile_time_us: u64,
+    pub executions: u64,
+    pub avg_execution_time_us: u64,
+}
+
+/// Reports the compile and execution timings of every engine used in this process.
+pub fn engine_metrics() -> Vec<EngineMetricsReport> {
+    WasmEngine::ALL
+        .into_iter()
+        .map(|engine| {
+            let metrics = engine.metrics();
+            let compilations = metrics.compilations.load(Ordering::Relaxed);
+            let executions = metrics.executions.load(Ordering::Relaxed);
+            EngineMetricsReport {
+                engine,
+                available: engine.is_available(),
+                compilations,
+                avg_compile_time_us: metrics.compile_time_us.load(Ordering::Relaxed)
+                    / compilations.max(1),
+                executions,
+                avg_execution_time_us: metrics.execution_time_us.load(Ordering::Relaxed)
+                    / executions.max(1),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn engine_availability() {
+        assert!(WasmEngine::Singlepass.build(None).is_ok());
+        for engine in [WasmEngine::Cranelift, WasmEngine::Llvm] {
+            assert_eq!(engine.build(None).is_ok(), engine.is_available());
+        }
+    }
+
+    #[test]
+    fn records_timings() {
+        let metrics = EngineMetrics::new();
+        assert_eq!(metrics.time_execution(|| 1 + 1), 2);
+        metrics.time_compilation(|| std::thread::sleep(Duration::from_millis(1)));
+        assert_eq!(metrics.executions.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.compilations.load(Ordering::Relaxed), 1);
+        assert!(metrics.compile_time_us.load(Ordering::Relaxed) >= 1000);
+    }
+}
@@ -99,4 +99,10 @@ pub(crate) enum RuntimeInnerError {
 
     #[error(transparent)]
     WasmRtError(#[from] wasmer::RuntimeError),
+
+    #[error("unsupported contract ABI version `{0}`")]
+    UnsupportedAbi(String),
+
+    #[error("wasm engine {0} is not available in this build")]
+    EngineNotAvailable(super::WasmEngine),
 }
@@ -0,0 +1,515 @@
+//! Host interface through which delegates use the keys held in their secrets without reading
+//! them.
+//!
+//! Delegates signing with a private key would otherwise load it into their memory, where any bug
+//! in the delegate or the application driving it can leak it. Through the `freenet_keys`
+//! imports the runtime does the work instead, with keys referenced by the id of the secret
+//! holding them:
+//!
+//! - `__frnt__keys__generate` stores a new random private key for an algorithm,
+//! - `__frnt__keys__derive` stores a private key for an algorithm derived from a secret with
+//!   HKDF-SHA256,
+//! - `__frnt__keys__public_key` and `__frnt__keys__sign` return the public key of a private key
+//!   or the signature of a message.
+//!
+//! Keys are stored along with the algorithm they were generated or derived for, and can't be
+//! used with any other one. Secrets set by the delegate itself can only be derived from.
+//!
+//! Secrets are looked up in the namespace of the application the delegate is executing for, as
+//! when the delegate reads them, which requires the consent of the user the same way; keys stored
+//! during a call are written to it once the call completes. The imports returning data write it
+//! to the output buffer only when it fits and return its size, so callers can retry with a buffer
+//! large enough; or a negative [`KeyError`] code, also when the buffers aren't within the memory
+//! of the delegate.
+
+use ed25519_dalek::Signer as _;
+use freenet_stdlib::prelude::*;
+use hkdf::Hkdf;
+use k256::ecdsa::signature::Signer as _;
+use rand::RngCore;
+use sha2::Sha256;
+use wasmer::{Function, FunctionEnv, FunctionEnvMut, Imports, Memory, MemoryView};
+
+use super::{abi::AbiVersion, native_api::MEM_ADDR, secrets_store::SecretsReader};
+
+/// Size of the generated and derived keys.
+const KEY_SIZE: usize = 32;
+/// Prefix of the secrets holding a key, followed by the code of its algorithm and the key.
+const KEY_HEADER: &[u8] = b"frnt-key";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub(super) enum KeyAlgorithm {
+    Ed25519 = 1,
+    /// ECDSA over secp256k1 with SHA-256 digests, signatures in compact (r, s) form and public
+    /// keys SEC1 encoded with point compression.
+    Secp256k1 = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i64)]
+pub(super) enum KeyError {
+    NotFound = -1,
+    /// The secret doesn't hold a key, or not a valid one.
+    InvalidKey = -2,
+    UnknownAlgorithm = -3,
+    /// Only delegates can use keys.
+    Unavailable = -4,
+    /// A buffer is not within the memory of the delegate.
+    OutOfBounds = -5,
+    /// The key was generated or derived for another algorithm.
+    AlgorithmMismatch = -6,
+    /// The user has not allowed the application to read its secrets yet.
+    ConsentRequired = -7,
+}
+
+impl KeyAlgorithm {
+    fn from_code(code: i32) -> Option<Self> {
+        match code {
+            1 => Some(Self::Ed25519),
+            2 => Some(Self::Secp256k1),
+            _ => None,
+        }
+    }
+
+    fn generate(self) -> Vec<u8> {
+        let mut rng = rand::thread_rng();
+        loop {
+            let mut key = vec![0; KEY_SIZE];
+            rng.fill_bytes(&mut key);
+            // not every scalar is a valid secp256k1 key
+            if self.public_key(&key).is_ok() {
+                return key;
+            }
+        }
+    }
+
+    fn public_key(self, private_key: &[u8]) -> Result<Vec<u8>, KeyError> {
+        match self {
+            Self::Ed25519 => Ok(ed25519_key(private_key)?
+                .verifying_key()
+                .to_bytes()
+                .to_vec()),
+            Self::Secp256k1 => Ok(secp256k1_key(private_key)?
+                .verifying_key()
+                .to_sec1_bytes()
+                .into_vec()),
+        }
+    }
+
+    fn sign(self, private_key: &[u8], message: &[u8]) -> Result<Vec<u8>, KeyError> {
+        match self {
+            Self::Ed25519 => Ok(ed25519_key(private_key)?.sign(message).to_bytes().to_vec()),
+            Self::Secp256k1 => {
+                let signature: k256::ecdsa::Signature = secp256k1_key(private_key)?.sign(message);
+                Ok(signature.to_bytes().to_vec())
+            }
+        }
+    }
+
+    /// Secret holding the key along with this algorithm.
+    fn encode_key(self, private_key: &[u8]) -> Vec<u8> {
+        let mut secret = Vec::with_capacity(KEY_HEADER.len() + 1 + private_key.len());
+        secret.extend_from_slice(KEY_HEADER);
+        secret.push(self as u8);
+        secret.extend_from_slice(private_key);
+        secret
+    }
+
+    /// The private key held by a secret, if it was stored for this algorithm.
+    fn decode_key(self, secret: &[u8]) -> Result<&[u8], KeyError> {
+        let (code, private_key) = secret
+            .strip_prefix(KEY_HEADER)
+            .and_then(|secret| secret.split_first())
+            .ok_or(KeyError::InvalidKey)?;
+        match Self::from_code(*code as i32) {
+            Some(algorithm) if algorithm == self => Ok(private_key),
+            Some(_) => Err(KeyError::AlgorithmMismatch),
+            None => Err(KeyError::InvalidKey),
+        }
+    }
+}
+
+fn ed25519_key(private_key: &[u8]) -> Result<ed25519_dalek::SigningKey, KeyError> {
+    let seed = private_key.try_into().map_err(|_| KeyError::InvalidKey)?;
+    Ok(ed25519_dalek::SigningKey::from_bytes(seed))
+}
+
+fn secp256k1_key(private_key: &[u8]) -> Result<k256::ecdsa::SigningKey, KeyError> {
+    k256::ecdsa::SigningKey::from_slice(private_key).map_err(|_| KeyError::InvalidKey)
+}
+
+/// Derives a subkey of a secret, distinct for every `info`.
+fn derive_key(secret: &[u8], info: &[u8]) -> Vec<u8> {
+    let mut key = vec![0; KEY_SIZE];
+    Hkdf::<Sha256>::new(None, secret)
+        .expand(info, &mut key)
+        .expect("valid output length");
+    key
+}
+
+/// Delegate executing, along with the application it executes for.
+struct Caller {
+    delegate: DelegateKey,
+    app: Option<ContractInstanceId>,
+    /// Whether the secrets of the application were read without the consent of the user.
+    consent_required: bool,
+}
+
+/// What a call of a delegate left to the runtime once it completes.
+#[derive(Default)]
+pub(super) struct KeysCall {
+    /// Keys to store in the secrets.
+    pub stored: Vec<(SecretsId, Vec<u8>)>,
+    /// Whether the user has to be asked to let the application read its secrets.
+    pub consent_required: bool,
+}
+
+pub(super) struct KeysEnv {
+    secrets: SecretsReader,
+    /// Memory shared by every instance, if the runtime uses one.
+    host_mem: Option<Memory>,
+    caller: Option<Caller>,
+    /// Keys stored during the current call, written to the secrets once it completes.
+    stored: Vec<(SecretsId, Vec<u8>)>,
+}
+
+impl KeysEnv {
+    pub fn new(secrets: SecretsReader, host_mem: Option<Memory>) -> Self {
+        Self {
+            secrets,
+            host_mem,
+            caller: None,
+            stored: Vec::new(),
+        }
+    }
+
+    /// Starts a call of a delegate, discarding anything left by a previous one.
+    pub fn start_call(&mut self, delegate: DelegateKey, app: Option<ContractInstanceId>) {
+        self.caller = Some(Caller {
+            delegate,
+            app,
+            consent_required: false,
+        });
+        self.stored.clear();
+    }
+
+    /// Ends the current call.
+    pub fn end_call(&mut self) -> KeysCall {
+        KeysCall {
+            consent_required: self
+                .caller
+                .take()
+                .is_some_and(|caller| caller.consent_required),
+            stored: std::mem::take(&mut self.stored),
+        }
+    }
+
+    fn secret(&mut self, id: &SecretsId) -> Result<Vec<u8>, KeyError> {
+        let caller = self.caller.as_mut().ok_or(KeyError::Unavailable)?;
+        if let Some((_, key)) = self.stored.iter().rev().find(|(stored, _)| stored == id) {
+            return Ok(key.clone());
+        }
+        if let Some(app) = &caller.app {
+            if !self.secrets.has_consent(&caller.delegate, app) {
+                caller.consent_required = true;
+                return Err(KeyError::ConsentRequired);
+            }
+        }
+        self.secrets
+            .get_secret(&caller.delegate, caller.app.as_ref(), id)
+            .map_err(|_| KeyError::NotFound)
+    }
+
+    fn store(&mut self, id: SecretsId, key: Vec<u8>) -> Result<(), KeyError> {
+        if self.caller.is_none() {
+            return Err(KeyError::Unavailable);
+        }
+        self.stored.push((id, key));
+        Ok(())
+    }
+}
+
+pub(super) fn prepare_export(
+    abi: AbiVersion,
+    store: &mut wasmer::Store,
+    imports: &mut Imports,
+    env: &FunctionEnv<KeysEnv>,
+) {
+    let generate = Function::new_typed_with_env(store, env, generate);
+    let derive = Function::new_typed_with_env(store, env, derive);
+    let public_key = Function::new_typed_with_env(store, env, public_key);
+    let sign = Function::new_typed_with_env(store, env, sign);
+    imports.register_namespace(
+        &abi.namespace("freenet_keys"),
+        [
+            ("__frnt__keys__generate".to_owned(), generate.into()),
+            ("__frnt__keys__derive".to_owned(), derive.into()),
+            ("__frnt__keys__public_key".to_owned(), public_key.into()),
+            ("__frnt__keys__sign".to_owned(), sign.into()),
+        ],
+    );
+}
+
+/// Memory of the instance calling.
+fn guest_memory(env: &FunctionEnvMut<KeysEnv>, id: i64) -> Result<Memory, KeyError> {
+    if let Some(memory) = &env.data().host_mem {
+        return Ok(memory.clone());
+    }
+    let info = MEM_ADDR.get(&id).ok_or(KeyError::Unavailable)?;
+    info.instance
+        .exports
+        .get_memory("memory")
+        .cloned()
+        .map_err(|_| KeyError::Unavailable)
+}
+
+/// Returns the offset of a buffer of the guest if the whole buffer is within its memory of
+/// `size` bytes.
+fn guest_range(size: u64, ptr: i64, len: i64) -> Result<u64, KeyError> {
+    let (Ok(ptr), Ok(len)) = (u64::try_from(ptr), u64::try_from(len)) else {
+        return Err(KeyError::OutOfBounds);
+    };
+    match ptr.checked_add(len) {
+        Some(end) if end <= size => Ok(ptr),
+        _ => Err(KeyError::OutOfBounds),
+    }
+}
+
+fn read_bytes(view: &MemoryView, ptr: i64, len: i32) -> Result<Vec<u8>, KeyError> {
+    let offset = guest_range(view.data_size(), ptr, len as i64)?;
+    let mut bytes = vec![0; len as usize];
+    view.read(offset, &mut bytes)
+        .map_err(|_| KeyError::OutOfBounds)?;
+    Ok(bytes)
+}
+
+fn write_output(
+    env: &FunctionEnvMut<KeysEnv>,
+    memory: &Memory,
+    out_ptr: i64,
+    out_cap: i32,
+    data: &[u8],
+) -> i64 {
+    if data.len() <= out_cap.max(0) as usize {
+        let view = memory.view(env);
+        if let Err(err) = guest_range(view.data_size(), out_ptr, data.len() as i64)
+            .and_then(|offset| view.write(offset, data).map_err(|_| KeyError::OutOfBounds))
+        {
+            return err as i64;
+        }
+    }
+    data.len() as i64
+}
+
+fn generate(
+    mut env: FunctionEnvMut<KeysEnv>,
+    id: i64,
+    algorithm: i32,
+    key_ptr: i64,
+    key_len: i32,
+) -> i64 {
+    let Some(algorithm) = KeyAlgorithm::from_code(algorithm) else {
+        return KeyError::UnknownAlgorithm as i64;
+    };
+    let key_id = match guest_memory(&env, id)
+        .and_then(|memory| read_bytes(&memory.view(&env), key_ptr, key_len))
+    {
+        Ok(key_id) => SecretsId::new(key_id),
+        Err(err) => return err as i64,
+    };
+    let key = algorithm.encode_key(&algorithm.generate());
+    match env.data_mut().store(key_id, key) {
+        Ok(()) => 0,
+        Err(err) => err as i64,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn derive(
+    mut env: FunctionEnvMut<KeysEnv>,
+    id: i64,
+    algorithm: i32,
+    secret_ptr: i64,
+    secret_len: i32,
+    info_ptr: i64,
+    info_len: i32,
+    key_ptr: i64,
+    key_len: i32,
+) -> i64 {
+    let Some(algorithm) = KeyAlgorithm::from_code(algorithm) else {
+        return KeyError::UnknownAlgorithm as i64;
+    };
+    let buffers = guest_memory(&env, id).and_then(|memory| {
+        let view = memory.view(&env);
+        Ok((
+            read_bytes(&view, secret_ptr, secret_len)?,
+            read_bytes(&view, info_ptr, info_len)?,
+            read_bytes(&view, key_ptr, key_len)?,
+        ))
+    });
+    let (secret_id, info, key_id) = match buffers {
+        Ok((secret_id, info, key_id)) => (SecretsId::new(secret_id), info, SecretsId::new(key_id)),
+        Err(err) => return err as i64,
+    };
+    let key = match env.data_mut().secret(&secret_id) {
+        Ok(secret) => derive_key(&secret, &info),
+        Err(err) => return err as i64,
+    };
+    // the derived bytes are not always a valid secp256k1 key
+    if let Err(err) = algorithm.public_key(&key) {
+        return err as i64;
+    }
+    match env.data_mut().store(key_id, algorithm.encode_key(&key)) {
+        Ok(()) => 0,
+        Err(err) => err as i64,
+    }
+}
+
+fn public_key(
+    mut env: FunctionEnvMut<KeysEnv>,
+    id: i64,
+    algorithm: i32,
+    key_ptr: i64,
+    key_len: i32,
+    out_ptr: i64,
+    out_cap: i32,
+) -> i64 {
+    let Some(algorithm) = KeyAlgorithm::from_code(algorithm) else {
+        return KeyError::UnknownAlgorithm as i64;
+    };
+    let memory = match guest_memory(&env, id) {
+        Ok(memory) => memory,
+        Err(err) => return err as i64,
+    };
+    let key_id = match read_bytes(&memory.view(&env), key_ptr, key_len) {
+        Ok(key_id) => SecretsId::new(key_id),
+        Err(err) => return err as i64,
+    };
+    match env
+        .data_mut()
+        .secret(&key_id)
+        .and_then(|secret| algorithm.public_key(algorithm.decode_key(&secret)?))
+    {
+        Ok(public_key) => write_output(&env, &memory, out_ptr, out_cap, &public_key),
+        Err(err) => err as i64,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn sign(
+    mut env: FunctionEnvMut<KeysEnv>,
+    id: i64,
+    algorithm: i32,
+    key_ptr: i64,
+    key_len: i32,
+    msg_ptr: i64,
+    msg_len: i32,
+    out_ptr: i64,
+    out_cap: i32,
+) -> i64 {
+    let Some(algorithm) = KeyAlgorithm::from_code(algorithm) else {
+        return KeyError::UnknownAlgorithm as i64;
+    };
+    let memory = match guest_memory(&env, id) {
+        Ok(memory) => memory,
+        Err(err) => return err as i64,
+    };
+    let buffers = {
+        let view = memory.view(&env);
+        read_bytes(&view, key_ptr, key_len)
+            .and_then(|key_id| Ok((key_id, read_bytes(&view, msg_ptr, msg_len)?)))
+    };
+    let (key_id, message) = match buffers {
+        Ok((key_id, message)) => (SecretsId::new(key_id), message),
+        Err(err) => return err as i64,
+    };
+    match env
+        .data_mut()
+        .secret(&key_id)
+        .and_then(|secret| algorithm.sign(algorithm.decode_key(&secret)?, &message))
+    {
+        Ok(signature) => write_output(&env, &memory, out_ptr, out_cap, &signature),
+        Err(err) => {
+            tracing::debug!(?algorithm, "signing failed: {err:?}");
+            err as i64
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_and_verify() {
+        use ed25519_dalek::Verifier as _;
+        use k256::ecdsa::signature::Verifier as _;
+
+        let message = b"message";
+        let key = KeyAlgorithm::Ed25519.generate();
+        let public_key = KeyAlgorithm::Ed25519.public_key(&key).unwrap();
+        let signature = KeyAlgorithm::Ed25519.sign(&key, message).unwrap();
+        let verifying_key =
+            ed25519_dalek::VerifyingKey::from_bytes(&public_key.try_into().unwrap()).unwrap();
+        let signature = ed25519_dalek::Signature::from_slice(&signature).unwrap();
+        assert!(verifying_key.verify(message, &signature).is_ok());
+
+        let key = KeyAlgorithm::Secp256k1.generate();
+        let public_key = KeyAlgorithm::Secp256k1.public_key(&key).unwrap();
+        assert_eq!(public_key.len(), 33);
+        let signature = KeyAlgorithm::Secp256k1.sign(&key, message).unwrap();
+        let verifying_key = k256::ecdsa::VerifyingKey::from_sec1_bytes(&public_key).unwrap();
+        let signature = k256::ecdsa::Signature::from_slice(&signature).unwrap();
+        assert!(verifying_key.verify(message, &signature).is_ok());
+
+        // zero is not a valid secp256k1 scalar
+        assert_eq!(
+            KeyAlgorithm::Secp256k1.sign(&[0; KEY_SIZE], message),
+            Err(KeyError::InvalidKey)
+        );
+        assert_eq!(
+            KeyAlgorithm::Ed25519.sign(&[1; 16], message),
+            Err(KeyError::InvalidKey)
+        );
+    }
+
+    #[test]
+    fn keys_record_their_algorithm() {
+        let key = KeyAlgorithm::Ed25519.generate();
+        let secret = KeyAlgorithm::Ed25519.encode_key(&key);
+        assert_eq!(
+            KeyAlgorithm::Ed25519.decode_key(&secret),
+            Ok(key.as_slice())
+        );
+        assert_eq!(
+            KeyAlgorithm::Secp256k1.decode_key(&secret),
+            Err(KeyError::AlgorithmMismatch)
+        );
+        // secrets set by delegates are not keys
+        assert_eq!(
+            KeyAlgorithm::Ed25519.decode_key(&key),
+            Err(KeyError::InvalidKey)
+        );
+    }
+
+    #[test]
+    fn guest_buffers_bounds() {
+        assert_eq!(guest_range(64, 0, 64), Ok(0));
+        assert_eq!(guest_range(64, 60, 4), Ok(60));
+        assert_eq!(guest_range(64, 60, 5), Err(KeyError::OutOfBounds));
+        assert_eq!(guest_range(64, -1, 1), Err(KeyError::OutOfBounds));
+        assert_eq!(guest_range(64, 0, -1), Err(KeyError::OutOfBounds));
+        assert_eq!(guest_range(64, i64::MAX, 1), Err(KeyError::OutOfBounds));
+    }
+
+    #[test]
+    fn derive_subkeys() {
+        let secret = b"secret";
+        let key = derive_key(secret, b"signing");
+        assert_eq!(key, derive_key(secret, b"signing"));
+        assert_ne!(key, derive_key(secret, b"encryption"));
+        assert_ne!(key, derive_key(b"other secret", b"signing"));
+        assert!(KeyAlgorithm::Ed25519.public_key(&key).is_ok());
+    }
+}
@@ -4,7 +4,7 @@ use dashmap::DashMap;
 use once_cell::sync::Lazy;
 use wasmer::{Function, Imports};
 
-use super::runtime::InstanceInfo;
+use super::{abi::AbiVersion, runtime::InstanceInfo};
 
 /// This is a map of starting addresses of the instance memory space.
 ///
@@ -15,10 +15,32 @@ pub(super) static MEM_ADDR: Lazy<DashMap<InstanceId, InstanceInfo>> = Lazy::new(
 type InstanceId = i64;
 
 #[inline(always)]
-fn compute_ptr<T>(ptr: i64, start_ptr: i64) -> *mut T {
+pub(super) fn compute_ptr<T>(ptr: i64, start_ptr: i64) -> *mut T {
     (start_ptr + ptr) as _
 }
 
+/// Registers the host functions of the given ABI version.
+pub(super) fn prepare_exports(
+    abi: AbiVersion,
+    store: &mut wasmer::Store,
+    imports: &mut Imports,
+    clock: &wasmer::FunctionEnv<time::EpochEnv>,
+) {
+    time::prepare_epoch_export(abi, store, imports, clock);
+    match abi {
+        AbiVersion::V1 => {
+            log::prepare_export(store, imports);
+            rand::prepare_export(store, imports);
+            time::prepare_export(store, imports);
+        }
+        AbiVersion::V2 => {
+            log::prepare_export_v2(store, imports);
+            rand::prepare_export_v2(store, imports);
+            time::prepare_export_v2(store, imports);
+        }
+    }
+}
+
 pub(crate) mod log {
     use super::*;
 
@@ -30,10 +52,28 @@ pub(crate) mod log {
         );
     }
 
+    /// v2 logging takes the level of the message along with it.
+    pub(crate) fn prepare_export_v2(store: &mut wasmer::Store, imports: &mut Imports) {
+        let log = Function::new_typed(store, log);
+        imports.register_namespace(
+            &AbiVersion::V2.namespace("freenet_log"),
+            [("__frnt__logger__log".to_owned(), log.into())],
+        );
+    }
+
     // TODO: this API right now is just a patch, ideally we want to impl a tracing subscriber
     // that can be used in wasm and that under the hood will just pass data to the host via
     // functions like this in a structured way
     fn info(id: i64, ptr: i64, len: i32) {
+        log(id, LEVEL_INFO, ptr, len)
+    }
+
+    const LEVEL_ERROR: i32 = 1;
+    const LEVEL_WARN: i32 = 2;
+    const LEVEL_INFO: i32 = 3;
+    const LEVEL_DEBUG: i32 = 4;
+
+    fn log(id: i64, level: i32, ptr: i64, len: i32) {
         if id == -1 {
             panic!("unset module id");
         }
@@ -41,7 +81,14 @@ pub(crate) mod log {
         let ptr = compute_ptr::<u8>(ptr, info.start_ptr);
         let msg =
             unsafe { std::str::from_utf8_unchecked(std::slice::from_raw_parts(ptr, len as _)) };
-        tracing::info!(target: "contract", contract = %info.value().key(), "{msg}");
+        let contract = info.value().key();
+        match level {
+            LEVEL_ERROR => tracing::error!(target: "contract", %contract, "{msg}"),
+            LEVEL_WARN => tracing::warn!(target: "contract", %contract, "{msg}"),
+            LEVEL_INFO => tracing::info!(target: "contract", %contract, "{msg}"),
+            LEVEL_DEBUG => tracing::debug!(target: "contract", %contract, "{msg}"),
+            _ => tracing::trace!(target: "contract", %contract, "{msg}"),
+        }
     }
 }
 
@@ -58,6 +105,14 @@ pub(crate) mod rand {
         );
     }
 
+    pub(crate) fn prepare_export_v2(store: &mut wasmer::Store, imports: &mut Imports) {
+        let rand_bytes = Function::new_typed(store, rand_bytes);
+        imports.register_namespace(
+            &AbiVersion::V2.namespace("freenet_rand"),
+            [("__frnt__rand__rand_bytes".to_owned(), rand_bytes.into())],
+        );
+    }
+
     fn rand_bytes(id: i64, ptr: i64, len: u32) {
         if id == -1 {
             panic!("unset module id");
@@ -71,8 +126,128 @@ pub(crate) mod rand {
 }
 
 pub(crate) mod time {
+    use std::time::Duration;
+
     use super::*;
     use chrono::{DateTime, Utc as UtcOriginal};
+    use wasmer::{FunctionEnv, FunctionEnvMut};
+
+    /// Default length of the time quantum exposed to contracts.
+    ///
+    /// Every peer in a network must use the same epoch length, otherwise they may disagree
+    /// on the validity of state transitions which depend on time.
+    pub(crate) const DEFAULT_TIME_EPOCH: Duration = Duration::from_secs(60);
+
+    /// Number of past epochs, besides the current one, a claimed epoch can be from and still
+    /// be considered recent; accounts for clock skew and propagation delay between peers.
+    const EPOCH_TOLERANCE: i64 = 1;
+
+    /// Epoch returned while the runtime caller hasn't set the time.
+    const UNSET_EPOCH: i64 = i64::MIN;
+
+    /// Time as seen by contracts, set by the runtime caller before running them.
+    pub(crate) struct EpochEnv {
+        epoch_ms: i64,
+        now_ms: Option<i64>,
+        /// Whether the contract call in progress may see the time at all.
+        exposed: bool,
+    }
+
+    impl EpochEnv {
+        pub(crate) fn new(epoch: Duration) -> Self {
+            Self {
+                epoch_ms: (epoch.as_millis() as i64).max(1),
+                now_ms: None,
+                exposed: true,
+            }
+        }
+
+        pub(crate) fn set_now(&mut self, now: DateTime<UtcOriginal>) {
+            self.now_ms = Some(now.timestamp_millis());
+        }
+
+        /// Hides the time from the next contract calls when `false`, they see no epoch as if
+        /// the time was never set.
+        pub(crate) fn expose(&mut self, exposed: bool) {
+            self.exposed = exposed;
+        }
+    }
+
+    /// Exposes a coarse, network agreed, time quantum instead of the raw wall clock.
+    ///
+    /// Contracts should embed the epoch in the deltas they produce and check it with
+    /// `__frnt__time__is_recent_epoch` when updating their state, so every peer applying the
+    /// same delta within the tolerance window reaches the same result.
+    ///
+    /// The time is never exposed to `validate_state`: the same state is validated by other peers
+    /// at any later time, e.g. on GETs or when replicating it, so its validity can't depend on
+    /// when it is validated. While validating no epoch is recent and the current epoch is unset.
+    ///
+    /// The wall clock is never read here: the epoch is derived from the time the caller of the
+    /// runtime passed in, and until then no epoch is recent.
+    pub(crate) fn prepare_epoch_export(
+        abi: AbiVersion,
+        store: &mut wasmer::Store,
+        imports: &mut Imports,
+        env: &FunctionEnv<EpochEnv>,
+    ) {
+        let current = Function::new_typed_with_env(store, env, current_epoch);
+        let is_recent = Function::new_typed_with_env(store, env, is_recent_epoch);
+        let length = Function::new_typed_with_env(store, env, epoch_millis);
+        imports.register_namespace(
+            &abi.namespace("freenet_time"),
+            [
+                ("__frnt__time__epoch".to_owned(), current.into()),
+                ("__frnt__time__is_recent_epoch".to_owned(), is_recent.into()),
+                ("__frnt__time__epoch_millis".to_owned(), length.into()),
+            ],
+        );
+    }
+
+    fn current_epoch(env: FunctionEnvMut<EpochEnv>, id: i64) -> i64 {
+        if id == -1 {
+            panic!("unset module id");
+        }
+        let EpochEnv {
+            epoch_ms,
+            now_ms,
+            exposed,
+        } = env.data();
+        match now_ms {
+            Some(now_ms) if *exposed => epoch_at(*now_ms, *epoch_ms),
+            _ => UNSET_EPOCH,
+        }
+    }
+
+    fn is_recent_epoch(env: FunctionEnvMut<EpochEnv>, id: i64, claimed: i64) -> i32 {
+        let current = current_epoch(env, id);
+        is_recent(claimed, current) as i32
+    }
+
+    fn epoch_millis(env: FunctionEnvMut<EpochEnv>, _id: i64) -> i64 {
+        env.data().epoch_ms
+    }
+
+    fn epoch_at(now_ms: i64, epoch_ms: i64) -> i64 {
+        now_ms.div_euclid(epoch_ms)
+    }
+
+    fn is_recent(claimed: i64, current: i64) -> bool {
+        current != UNSET_EPOCH && (current - EPOCH_TOLERANCE..=current).contains(&claimed)
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn epoch_quantization() {
+        assert_eq!(epoch_at(59_999, 60_000), 0);
+        assert_eq!(epoch_at(60_000, 60_000), 1);
+        assert!(is_recent(10, 10));
+        assert!(is_recent(9, 10));
+        assert!(!is_recent(8, 10));
+        // epochs in the future are never valid
+        assert!(!is_recent(11, 10));
+        assert!(!is_recent(UNSET_EPOCH, UNSET_EPOCH));
+    }
 
     pub(crate) fn prepare_export(store: &mut wasmer::Store, imports: &mut Imports) {
         let utc_now = Function::new_typed(store, utc_now);
@@ -82,6 +257,23 @@ pub(crate) mod time {
         );
     }
 
+    /// v2 returns the unix timestamp in milliseconds instead of writing the host
+    /// representation of the date into the instance memory.
+    pub(crate) fn prepare_export_v2(store: &mut wasmer::Store, imports: &mut Imports) {
+        let utc_now = Function::new_typed(store, utc_now_millis);
+        imports.register_namespace(
+            &AbiVersion::V2.namespace("freenet_time"),
+            [("__frnt__time__utc_now".to_owned(), utc_now.into())],
+        );
+    }
+
+    fn utc_now_millis(id: i64) -> i64 {
+        if id == -1 {
+            panic!("unset module id");
+        }
+        UtcOriginal::now().timestamp_millis()
+    }
+
     fn utc_now(id: i64, ptr: i64) {
         if id == -1 {
             panic!("unset module id");
@@ -1,6 +1,14 @@
 use super::{
-    contract_store::ContractStore, delegate_store::DelegateStore, error::RuntimeInnerError,
-    native_api, secrets_store::SecretsStore, RuntimeResult,
+    abi::AbiVersion,
+    contract_store::ContractStore,
+    cross_contract::{self, ContractStateSource, CrossContractEnv},
+    delegate_store::DelegateStore,
+    engine::WasmEngine,
+    error::RuntimeInnerError,
+    key_management::{self, KeysEnv},
+    native_api,
+    secrets_store::SecretsStore,
+    RuntimeResult,
 };
 use freenet_stdlib::{
     memory::{
@@ -9,15 +17,32 @@ use freenet_stdlib::{
     },
     prelude::*,
 };
-use std::{collections::HashMap, sync::atomic::AtomicI64};
+use serde::Serialize;
+use std::{collections::HashMap, sync::atomic::AtomicI64, time::Duration};
 use wasmer::{
-    imports, Bytes, CompilerConfig, Imports, Instance, Memory, MemoryType, Module, Store,
+    imports, AsStoreMut, Bytes, FunctionEnv, Imports, Instance, Memory, MemoryType, Module, Store,
     TypedFunction,
 };
 use wasmer_middlewares::metering::{get_remaining_points, MeteringPoints};
 
 static INSTANCE_ID: AtomicI64 = AtomicI64::new(0);
 
+/// Sequentially writes into a buffer living in the instance memory.
+struct BufferWriter<'a, 'b>(&'a mut BufferMut<'b>);
+
+impl std::io::Write for BufferWriter<'_, '_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .write(buf)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::OutOfMemory, err.to_string()))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
 pub(super) struct RunningInstance {
     pub id: i64,
     pub instance: Instance,
@@ -31,6 +56,7 @@ impl Drop for RunningInstance {
 
 pub(super) struct InstanceInfo {
     pub start_ptr: i64,
+    pub instance: Instance,
     key: Key,
 }
 
@@ -41,9 +67,16 @@ impl InstanceInfo {
             Key::Delegate(k) => k.encode(),
         }
     }
+
+    pub fn contract_id(&self) -> Option<ContractInstanceId> {
+        match &self.key {
+            Key::Contract(k) => Some(*k),
+            Key::Delegate(_) => None,
+        }
+    }
 }
 
-enum Key {
+pub(super) enum Key {
     Contract(ContractInstanceId),
     Delegate(DelegateKey),
 }
@@ -54,19 +87,29 @@ impl RunningInstance {
             .host_memory
             .as_ref()
             .map(Ok)
-            .unwrap_or_else(|| instance.exports.get_memory("memory"))?;
-        let wasm_store = rt.wasm_store.as_mut().unwrap();
+            .unwrap_or_else(|| instance.exports.get_memory("memory"))?
+            .clone();
+        Self::register(rt.wasm_store.as_mut().unwrap(), instance, &memory, key)
+    }
+
+    /// Assigns an id to the instance and records its memory space for the host functions.
+    pub(super) fn register(
+        store: &mut impl AsStoreMut,
+        instance: Instance,
+        memory: &Memory,
+        key: Key,
+    ) -> RuntimeResult<Self> {
         let set_id: TypedFunction<i64, ()> = instance
             .exports
-            .get_typed_function(&*wasm_store, "__frnt_set_id")
-            .unwrap();
+            .get_typed_function(&*store, "__frnt_set_id")?;
         let id = INSTANCE_ID.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-        set_id.call(wasm_store, id).unwrap();
-        let ptr = memory.view(&*wasm_store).data_ptr() as i64;
+        set_id.call(store, id)?;
+        let ptr = memory.view(&*store).data_ptr() as i64;
         native_api::MEM_ADDR.insert(
             id,
             InstanceInfo {
                 start_ptr: ptr,
+                instance: instance.clone(),
                 key,
             },
         );
@@ -106,8 +149,17 @@ pub struct RuntimeConfig {
     /// Safety margin for CPU speed variations (0.0 to 1.0)
     pub safety_margin: f64,
     pub enable_metering: bool,
+    /// Compiler backend used to compile contracts and delegates
+    pub engine: WasmEngine,
+    /// Length of the time quantum exposed to contracts
+    pub time_epoch: Duration,
+    /// Maximum linear memory of an instance, in WASM pages of 64 KiB
+    pub max_memory_pages: u32,
 }
 
+/// Maximum linear memory of an instance by default, 1 GiB.
+const DEFAULT_MAX_MEMORY_PAGES: u32 = 16 * 1024;
+
 impl Default for RuntimeConfig {
     fn default() -> Self {
         Self {
@@ -115,6 +167,9 @@ impl Default for RuntimeConfig {
             cpu_cycles_per_second: None,
             safety_margin: 0.2,
             enable_metering: false,
+            engine: WasmEngine::default(),
+            time_epoch: native_api::time::DEFAULT_TIME_EPOCH,
+            max_memory_pages: DEFAULT_MAX_MEMORY_PAGES,
         }
     }
 }
@@ -122,8 +177,9 @@ impl Default for RuntimeConfig {
 pub struct Runtime {
     /// Working memory store used by the inner engine
     pub(super) wasm_store: Option<Store>,
-    /// includes all the necessary imports to interact with the native runtime environment
-    pub(super) top_level_imports: Imports,
+    /// includes all the necessary imports to interact with the native runtime environment,
+    /// for each supported ABI version
+    pub(super) top_level_imports: HashMap<AbiVersion, Imports>,
     /// assigned growable host memory
     pub(super) host_memory: Option<Memory>,
 
@@ -137,6 +193,17 @@ pub struct Runtime {
     /// loaded contract modules
     pub(super) contract_modules: HashMap<ContractKey, Module>,
     pub(crate) enabled_metering: bool,
+    /// Fuel contract instances start with, when metering is enabled.
+    pub(super) max_fuel: Option<u64>,
+    /// Maximum linear memory of an instance, in WASM pages.
+    pub(super) max_memory_pages: u32,
+    pub(super) engine: WasmEngine,
+    /// shared by the host functions through which contracts read other contracts
+    pub(super) cross_contract: FunctionEnv<CrossContractEnv>,
+    /// shared by the host functions through which delegates use their keys
+    pub(super) keys: FunctionEnv<KeysEnv>,
+    /// time exposed to contracts through the epoch host functions
+    pub(super) clock: FunctionEnv<native_api::time::EpochEnv>,
 }
 
 impl Runtime {
@@ -147,8 +214,8 @@ impl Runtime {
         host_mem: bool,
         config: RuntimeConfig,
     ) -> RuntimeResult<Self> {
-        let mut store = Self::instance_store_with_config(&config);
-        let (host_memory, mut top_level_imports) = if host_mem {
+        let mut store = Self::instance_store_with_config(&config)?;
+        let (host_memory, base_imports) = if host_mem {
             let mem = Self::instance_host_mem(&mut store)?;
             let imports = imports! {
                 "env" => {
@@ -159,9 +226,34 @@ impl Runtime {
         } else {
             (None, imports! {})
         };
-        native_api::log::prepare_export(&mut store, &mut top_level_imports);
-        native_api::rand::prepare_export(&mut store, &mut top_level_imports);
-        native_api::time::prepare_export(&mut store, &mut top_level_imports);
+        let cross_contract = FunctionEnv::new(
+            &mut store,
+            CrossContractEnv::new(
+                contract_store.code_reader(),
+                config.enable_metering,
+                host_memory.clone(),
+                config.max_memory_pages,
+            ),
+        );
+        let keys = FunctionEnv::new(
+            &mut store,
+            KeysEnv::new(secret_store.reader(), host_memory.clone()),
+        );
+        let clock = FunctionEnv::new(
+            &mut store,
+            native_api::time::EpochEnv::new(config.time_epoch),
+        );
+        let top_level_imports: HashMap<_, _> = AbiVersion::ALL
+            .into_iter()
+            .map(|abi| {
+                let mut imports = base_imports.clone();
+                native_api::prepare_exports(abi, &mut store, &mut imports, &clock);
+                cross_contract::prepare_export(abi, &mut store, &mut imports, &cross_contract);
+                key_management::prepare_export(abi, &mut store, &mut imports, &keys);
+                (abi, imports)
+            })
+            .collect();
+        cross_contract.as_mut(&mut store).imports = top_level_imports.clone();
 
         Ok(Self {
             wasm_store: Some(store),
@@ -175,9 +267,42 @@ impl Runtime {
             contract_store,
             delegate_modules: HashMap::new(),
             enabled_metering: config.enable_metering,
+            max_fuel: config.enable_metering.then(|| Self::max_cycles(&config)),
+            max_memory_pages: config.max_memory_pages,
+            engine: config.engine,
+            cross_contract,
+            keys,
+            clock,
         })
     }
 
+    /// Sets the time from which the epoch seen by contracts is derived.
+    ///
+    /// Contracts never read the clock of the node while validating or updating states, so
+    /// the same call with the same time gives the same result on every peer. The epoch is
+    /// hidden from `validate_state` altogether, states must be valid whenever validated.
+    pub fn set_time(&mut self, now: chrono::DateTime<chrono::Utc>) {
+        self.clock
+            .as_mut(self.wasm_store.as_mut().unwrap())
+            .set_now(now);
+    }
+
+    /// Sets where the states read by contracts from other contracts come from.
+    ///
+    /// Until set, contracts can't read the state of other contracts.
+    pub(crate) fn set_state_source(&mut self, source: std::sync::Arc<dyn ContractStateSource>) {
+        self.cross_contract
+            .as_mut(self.wasm_store.as_mut().unwrap())
+            .source = Some(source);
+    }
+
+    /// Changes the max size in bytes of the contract and delegate code being cached, each.
+    pub(crate) fn set_code_cache_size(&self, max_size: u64) {
+        let max_size = max_size.min(i64::MAX as u64) as i64;
+        self.contract_store.set_cache_size(max_size);
+        self.delegate_store.set_cache_size(max_size);
+    }
+
     pub fn build(
         contract_store: ContractStore,
         delegate_store: DelegateStore,
@@ -197,12 +322,31 @@ impl Runtime {
     where
         T: AsRef<[u8]>,
     {
-        let data = data.as_ref();
+        self.alloc_buf(instance, data.as_ref().len())
+    }
+
+    /// Serializes `value` directly into a new buffer in the instance memory, avoiding an
+    /// intermediate host allocation for large values (e.g. full state updates).
+    pub(super) fn init_buf_serialized<T>(
+        &mut self,
+        instance: &Instance,
+        value: &T,
+    ) -> RuntimeResult<BufferMut>
+    where
+        T: Serialize + ?Sized,
+    {
+        let size = bincode::serialized_size(value)? as usize;
+        let mut buf = self.alloc_buf(instance, size)?;
+        bincode::serialize_into(BufferWriter(&mut buf), value)?;
+        Ok(buf)
+    }
+
+    fn alloc_buf(&mut self, instance: &Instance, size: usize) -> RuntimeResult<BufferMut> {
         let wasm_store = self.wasm_store.as_mut().unwrap();
         let initiate_buffer: TypedFunction<u32, i64> = instance
             .exports
             .get_typed_function(&*wasm_store, "__frnt__initiate_buffer")?;
-        let builder_ptr = initiate_buffer.call(wasm_store, data.len() as u32)?;
+        let builder_ptr = initiate_buffer.call(wasm_store, size as u32)?;
         let linear_mem = self.linear_mem(instance)?;
         unsafe {
             Ok(BufferMut::from_ptr(
@@ -237,7 +381,9 @@ impl Runtime {
                 .ok_or_else(|| RuntimeInnerError::ContractNotFound(*key))?;
             let module = match contract {
                 ContractContainer::Wasm(ContractWasmAPIVersion::V1(contract_v1)) => {
-                    Module::new(self.wasm_store.as_ref().unwrap(), contract_v1.code().data())?
+                    self.engine.metrics().time_compilation(|| {
+                        Module::new(self.wasm_store.as_ref().unwrap(), contract_v1.code().data())
+                    })?
                 }
                 _ => unimplemented!(),
             };
@@ -263,7 +409,9 @@ impl Runtime {
                 .delegate_store
                 .fetch_delegate(key, params)
                 .ok_or_else(|| RuntimeInnerError::DelegateNotFound(key.clone()))?;
-            let module = Module::new(self.wasm_store.as_ref().unwrap(), delegate.code().as_ref())?;
+            let module = self.engine.metrics().time_compilation(|| {
+                Module::new(self.wasm_store.as_ref().unwrap(), delegate.code().as_ref())
+            })?;
             self.delegate_modules.insert(key.clone(), module);
             self.delegate_modules.get(key).unwrap()
         }
@@ -281,8 +429,17 @@ impl Runtime {
             .map(Ok)
             .unwrap_or_else(|| instance.exports.get_memory("memory"))?;
         let req_pages: wasmer::Pages = Bytes::from(req_bytes).try_into().unwrap();
-        if memory.view(&*wasm_store).size() < req_pages {
-            if let Err(err) = memory.grow(wasm_store, req_pages) {
+        let current_pages = memory.view(&*wasm_store).size();
+        if current_pages < req_pages {
+            if req_pages.0 > self.max_memory_pages {
+                return Err(ContractExecError::InsufficientMemory {
+                    req: (req_pages.0 as usize * wasmer::WASM_PAGE_SIZE),
+                    free: (self.max_memory_pages as usize * wasmer::WASM_PAGE_SIZE),
+                }
+                .into());
+            }
+            // grows by the number of pages missing, not to a number of pages
+            if let Err(err) = memory.grow(wasm_store, req_pages - current_pages) {
                 tracing::error!("wasm runtime failed with memory error: {err}");
                 return Err(ContractExecError::InsufficientMemory {
                     req: (req_pages.0 as usize * wasmer::WASM_PAGE_SIZE),
@@ -299,20 +456,34 @@ impl Runtime {
         Ok(Memory::new(store, MemoryType::new(20u32, None, false))?)
     }
 
-    fn prepare_instance(&mut self, module: &Module) -> RuntimeResult<Instance> {
+    pub(super) fn prepare_instance(&mut self, module: &Module) -> RuntimeResult<Instance> {
+        let abi = AbiVersion::detect(module)?;
         Ok(Instance::new(
             self.wasm_store.as_mut().unwrap(),
             module,
-            &self.top_level_imports,
+            &self.top_level_imports[&abi],
         )?)
     }
 
-    fn instance_store_with_config(config: &RuntimeConfig) -> Store {
+    fn instance_store_with_config(config: &RuntimeConfig) -> RuntimeResult<Store> {
         use std::sync::Arc;
         use wasmer::wasmparser::Operator;
-        use wasmer_compiler_singlepass::Singlepass;
         use wasmer_middlewares::Metering;
 
+        let max_cycles = Self::max_cycles(config);
+        let operation_cost = |_operator: &Operator| -> u64 { 1 };
+
+        let metering = config.enable_metering.then(|| {
+            Arc::new(Metering::new(max_cycles, operation_cost)) as Arc<dyn wasmer::ModuleMiddleware>
+        });
+        let engine = config.engine.build(metering)?;
+        tracing::debug!(engine = %config.engine, "initialized wasm engine");
+
+        Ok(Store::new(&engine))
+    }
+
+    /// Cycles a call can execute for within the maximum execution time.
+    fn max_cycles(config: &RuntimeConfig) -> u64 {
         fn get_cpu_cycles_per_second() -> (u64, f64) {
             // Assumed CPU speed for cost calculations (3.0 GHz)
             const DEFAULT_CPU_CYCLES_PER_SECOND: u64 = 3_000_000_000;
@@ -334,21 +505,26 @@ impl Runtime {
         };
 
         // Calculate total allowed cycles including safety margin
-        let max_cycles: u64 = (config.max_execution_seconds
-            * cpu_cycles_per_sec as f64
-            * (1.0 + safety_margin)) as u64;
-
-        let operation_cost = |_operator: &Operator| -> u64 { 1 };
-
-        let metering = Arc::new(Metering::new(max_cycles, operation_cost));
-        let mut compiler_config = Singlepass::default();
-        if config.enable_metering {
-            compiler_config.push_middleware(metering.clone());
-        }
-
-        let engine = wasmer::EngineBuilder::new(compiler_config).engine();
+        (config.max_execution_seconds * cpu_cycles_per_sec as f64 * (1.0 + safety_margin)) as u64
+    }
 
-        Store::new(&engine)
+    /// Records a call into a contract in its execution statistics.
+    pub(super) fn record_contract_call(
+        &mut self,
+        key: &ContractKey,
+        instance: &Instance,
+        elapsed: Duration,
+        failed: bool,
+    ) {
+        let fuel = self.max_fuel.and_then(|max_fuel| {
+            // the store is lost when the call timed out
+            let store = self.wasm_store.as_mut()?;
+            Some(match get_remaining_points(store, instance) {
+                MeteringPoints::Remaining(remaining) => max_fuel.saturating_sub(remaining),
+                MeteringPoints::Exhausted => max_fuel,
+            })
+        });
+        super::stats::record(key.id(), elapsed, fuel, failed);
     }
 
     pub(crate) fn handle_contract_error(
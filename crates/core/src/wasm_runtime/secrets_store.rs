@@ -9,6 +9,7 @@ use std::{
 use chacha20poly1305::{aead::Aead, Error as EncryptionError, XChaCha20Poly1305, XNonce};
 use dashmap::DashMap;
 use freenet_stdlib::prelude::*;
+use serde::Serialize;
 
 use crate::config::Secrets;
 
@@ -19,6 +20,12 @@ use super::{
 
 type SecretKey = [u8; 32];
 
+/// Directory of the markers of the namespaces the user allowed applications to read, one file per
+/// delegate and application, outside of the namespaces so delegates can't create them.
+///
+/// Delegate keys are base58 encoded, which excludes `O`, so this never clashes with a delegate.
+const CONSENTS_DIR: &str = "CONSENTS";
+
 #[derive(Debug, thiserror::Error)]
 pub enum SecretStoreError {
     #[error("encryption error: {0}")]
@@ -38,14 +45,22 @@ struct Encryption {
 }
 
 pub struct SecretsStore {
-    base_path: PathBuf,
+    reader: SecretsReader,
     #[allow(unused)]
     secrets: Secrets,
-    ciphers: HashMap<DelegateKey, Encryption>,
     key_to_secret_part: Arc<DashMap<DelegateKey, (u64, HashSet<SecretKey>)>>,
     index_file: SafeWriter<Self>,
     key_file: PathBuf,
-    default_encryption: Encryption,
+    /// Consent prompts awaiting the answer of the user.
+    pending_consents: HashMap<u32, (DelegateKey, ContractInstanceId)>,
+}
+
+/// A prompt asking the user to let an application read its namespace of the secrets of a delegate.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct SecretsConsent {
+    pub request_id: u32,
+    pub delegate: String,
+    pub application: String,
 }
 
 pub(super) struct ConcatenatedSecretKeys(Vec<u8>);
@@ -114,15 +129,18 @@ impl SecretsStore {
 
         let index_file = SafeWriter::new(&key_file, false)?;
         Ok(Self {
-            base_path: secrets_dir,
-            ciphers: HashMap::new(),
+            reader: SecretsReader {
+                base_path: secrets_dir,
+                ciphers: Arc::new(DashMap::new()),
+                default_encryption: Encryption {
+                    cipher: secrets.cipher(),
+                    nonce: secrets.nonce(),
+                },
+            },
             key_to_secret_part,
             index_file,
             key_file,
-            default_encryption: Encryption {
-                cipher: secrets.cipher(),
-                nonce: secrets.nonce(),
-            },
+            pending_consents: HashMap::new(),
             secrets,
         })
     }
@@ -133,32 +151,103 @@ impl SecretsStore {
         cipher: XChaCha20Poly1305,
         nonce: XNonce,
     ) -> Result<(), SecretStoreError> {
-        if nonce != self.default_encryption.nonce {
+        if nonce != self.reader.default_encryption.nonce {
             let encryption = Encryption { cipher, nonce };
-            self.ciphers.insert(delegate, encryption);
+            self.reader.ciphers.insert(delegate, encryption);
         }
         Ok(())
     }
 
+    /// Read-only access to the secrets, e.g. for host functions.
+    pub(super) fn reader(&self) -> SecretsReader {
+        self.reader.clone()
+    }
+
+    /// Whether the user allowed the application to read its namespace of the delegate secrets.
+    pub fn has_consent(&self, delegate: &DelegateKey, app: &ContractInstanceId) -> bool {
+        self.reader.has_consent(delegate, app)
+    }
+
+    /// Registers a consent prompt for the application, returning its id, the same one as long as
+    /// the user has not answered.
+    pub fn request_consent(&mut self, delegate: &DelegateKey, app: &ContractInstanceId) -> u32 {
+        if let Some((request_id, _)) =
+            self.pending_consents
+                .iter()
+                .find(|(_, (pending_delegate, pending_app))| {
+                    pending_delegate == delegate && pending_app == app
+                })
+        {
+            return *request_id;
+        }
+        let request_id = loop {
+            let id = rand::random();
+            if !self.pending_consents.contains_key(&id) {
+                break id;
+            }
+        };
+        self.pending_consents
+            .insert(request_id, (delegate.clone(), *app));
+        request_id
+    }
+
+    /// Consent prompts awaiting the answer of the user.
+    pub(crate) fn pending_consents(&self) -> Vec<SecretsConsent> {
+        let mut pending: Vec<_> = self
+            .pending_consents
+            .iter()
+            .map(|(request_id, (delegate, app))| SecretsConsent {
+                request_id: *request_id,
+                delegate: delegate.encode(),
+                application: app.encode(),
+            })
+            .collect();
+        pending.sort_unstable_by_key(|consent| consent.request_id);
+        pending
+    }
+
+    /// Resolves a consent prompt with the answer of the user, returning whether it was pending.
+    ///
+    /// Only the user can answer, through the client API from the owning UI of the application or
+    /// through the authenticated admin API of the node, never the delegates themselves.
+    pub fn resolve_consent(
+        &mut self,
+        request_id: u32,
+        allowed: bool,
+    ) -> Result<bool, SecretStoreError> {
+        let Some((delegate, app)) = self.pending_consents.remove(&request_id) else {
+            return Ok(false);
+        };
+        if allowed {
+            let consent = self.reader.consent_path(&delegate, &app);
+            if let Some(dir) = consent.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            File::create(consent)?;
+            tracing::info!(%delegate, %app, "application allowed to read the delegate secrets");
+        } else {
+            tracing::info!(%delegate, %app, "application denied reading the delegate secrets");
+        }
+        Ok(true)
+    }
+
     pub fn store_secret(
         &mut self,
         delegate: &DelegateKey,
+        app: Option<&ContractInstanceId>,
         key: &SecretsId,
         plaintext: Vec<u8>,
     ) -> RuntimeResult<()> {
-        let delegate_path = self.base_path.join(delegate.encode());
+        let delegate_path = self.reader.namespace_path(delegate, app);
         let secret_file_path = delegate_path.join(key.encode());
         let secret_key = *key.hash();
-        let encryption = self
-            .ciphers
-            .get(delegate)
-            .unwrap_or(&self.default_encryption);
+        let encryption = self.reader.encryption(delegate);
 
         let ciphertext = encryption
             .cipher
             .encrypt(&encryption.nonce, plaintext.as_ref())
             .map_err(|err| {
-                if encryption.nonce == self.default_encryption.nonce {
+                if encryption.nonce == self.reader.default_encryption.nonce {
                     SecretStoreError::MissingCipher
                 } else {
                     SecretStoreError::Encryption(err)
@@ -205,9 +294,10 @@ impl SecretsStore {
     pub fn remove_secret(
         &mut self,
         delegate: &DelegateKey,
+        app: Option<&ContractInstanceId>,
         key: &SecretsId,
     ) -> Result<(), SecretStoreError> {
-        let secret_path = self.base_path.join(delegate.encode()).join(key.encode());
+        let secret_path = self.reader.namespace_path(delegate, app).join(key.encode());
         match fs::remove_file(secret_path) {
             Ok(_) => Ok(()),
             Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
@@ -218,13 +308,86 @@ impl SecretsStore {
     pub fn get_secret(
         &self,
         delegate: &DelegateKey,
+        app: Option<&ContractInstanceId>,
         key: &SecretsId,
     ) -> Result<Vec<u8>, SecretStoreError> {
-        let secret_path = self.base_path.join(delegate.encode()).join(key.encode());
-        let encryption = self
-            .ciphers
+        if let Some(app) = app {
+            self.migrate_secret(delegate, app, key)?;
+        }
+        self.reader.get_secret(delegate, app, key)
+    }
+
+    /// Copies a secret stored before secrets were namespaced per application, when they were
+    /// shared by every application, to the namespace of `app` unless it has one of its own.
+    fn migrate_secret(
+        &self,
+        delegate: &DelegateKey,
+        app: &ContractInstanceId,
+        key: &SecretsId,
+    ) -> Result<(), SecretStoreError> {
+        let namespace = self.reader.namespace_path(delegate, Some(app));
+        let secret_path = namespace.join(key.encode());
+        let legacy_path = self
+            .reader
+            .namespace_path(delegate, None)
+            .join(key.encode());
+        if secret_path.exists() || !legacy_path.is_file() {
+            return Ok(());
+        }
+        fs::create_dir_all(&namespace)?;
+        fs::copy(&legacy_path, &secret_path)?;
+        tracing::info!(%delegate, %app, "migrated secret `{key}` to the namespace of the application");
+        Ok(())
+    }
+}
+
+/// Read-only view of the stored secrets, usable outside of the runtime (e.g. from host
+/// functions).
+#[derive(Clone)]
+pub(super) struct SecretsReader {
+    base_path: PathBuf,
+    ciphers: Arc<DashMap<DelegateKey, Encryption>>,
+    default_encryption: Encryption,
+}
+
+impl SecretsReader {
+    /// Directory of the secrets of a delegate, in the namespace of an application if set, which
+    /// only that application can read, or shared by every application otherwise.
+    fn namespace_path(&self, delegate: &DelegateKey, app: Option<&ContractInstanceId>) -> PathBuf {
+        let delegate_path = self.base_path.join(delegate.encode());
+        match app {
+            Some(app) => delegate_path.join(app.encode()),
+            None => delegate_path,
+        }
+    }
+
+    fn consent_path(&self, delegate: &DelegateKey, app: &ContractInstanceId) -> PathBuf {
+        self.base_path
+            .join(CONSENTS_DIR)
+            .join(delegate.encode())
+            .join(app.encode())
+    }
+
+    /// Whether the user allowed the application to read its namespace of the delegate secrets.
+    pub fn has_consent(&self, delegate: &DelegateKey, app: &ContractInstanceId) -> bool {
+        self.consent_path(delegate, app).exists()
+    }
+
+    fn encryption(&self, delegate: &DelegateKey) -> Encryption {
+        self.ciphers
             .get(delegate)
-            .unwrap_or(&self.default_encryption);
+            .map(|encryption| encryption.clone())
+            .unwrap_or_else(|| self.default_encryption.clone())
+    }
+
+    pub fn get_secret(
+        &self,
+        delegate: &DelegateKey,
+        app: Option<&ContractInstanceId>,
+        key: &SecretsId,
+    ) -> Result<Vec<u8>, SecretStoreError> {
+        let secret_path = self.namespace_path(delegate, app).join(key.encode());
+        let encryption = self.encryption(delegate);
 
         let ciphertext =
             fs::read(secret_path).map_err(|_| SecretStoreError::MissingSecret(key.clone()))?;
@@ -265,10 +428,81 @@ mod test {
         let text = vec![0, 1, 2];
 
         store.register_delegate(delegate.key().clone(), cipher, nonce)?;
-        store.store_secret(delegate.key(), &secret_id, text)?;
-        let f = store.get_secret(delegate.key(), &secret_id);
+        store.store_secret(delegate.key(), None, &secret_id, text)?;
+        let f = store.get_secret(delegate.key(), None, &secret_id);
 
         assert!(f.is_ok());
         Ok(())
     }
+
+    #[test]
+    fn application_namespaces() -> Result<(), Box<dyn std::error::Error>> {
+        let secrets_dir = tempfile::tempdir()?;
+        let mut store = SecretsStore::new(secrets_dir.path().to_path_buf(), Default::default())?;
+        let delegate = Delegate::from((&vec![3, 4, 5].into(), &vec![].into()));
+        let cipher = XChaCha20Poly1305::new(&XChaCha20Poly1305::generate_key(&mut OsRng));
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        store.register_delegate(delegate.key().clone(), cipher, nonce)?;
+
+        let app = ContractInstanceId::new([1; 32]);
+        let other_app = ContractInstanceId::new([2; 32]);
+        let secret_id = SecretsId::new(vec![0, 1, 2]);
+        store.store_secret(delegate.key(), Some(&app), &secret_id, vec![7])?;
+        assert_eq!(
+            store.get_secret(delegate.key(), Some(&app), &secret_id)?,
+            vec![7]
+        );
+        assert!(store
+            .get_secret(delegate.key(), Some(&other_app), &secret_id)
+            .is_err());
+        assert!(store.get_secret(delegate.key(), None, &secret_id).is_err());
+
+        assert!(!store.has_consent(delegate.key(), &app));
+        let request_id = store.request_consent(delegate.key(), &app);
+        assert_eq!(store.request_consent(delegate.key(), &app), request_id);
+        let other_request = store.request_consent(delegate.key(), &other_app);
+        assert_eq!(store.pending_consents().len(), 2);
+        assert!(store.resolve_consent(request_id, true)?);
+        assert!(!store.resolve_consent(request_id, true)?);
+        assert!(store.resolve_consent(other_request, false)?);
+        assert!(store.pending_consents().is_empty());
+        assert!(store.has_consent(delegate.key(), &app));
+        assert!(!store.has_consent(delegate.key(), &other_app));
+        // delegates can't grant consent by storing a secret named after the marker
+        let marker = SecretsId::new(b"CONSENT".to_vec());
+        store.store_secret(delegate.key(), Some(&other_app), &marker, vec![1])?;
+        assert!(!store.has_consent(delegate.key(), &other_app));
+        Ok(())
+    }
+
+    #[test]
+    fn migrate_shared_secrets() -> Result<(), Box<dyn std::error::Error>> {
+        let secrets_dir = tempfile::tempdir()?;
+        let mut store = SecretsStore::new(secrets_dir.path().to_path_buf(), Default::default())?;
+        let delegate = Delegate::from((&vec![6, 7, 8].into(), &vec![].into()));
+        let cipher = XChaCha20Poly1305::new(&XChaCha20Poly1305::generate_key(&mut OsRng));
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        store.register_delegate(delegate.key().clone(), cipher, nonce)?;
+
+        // stored before secrets were namespaced
+        let secret_id = SecretsId::new(vec![9]);
+        store.store_secret(delegate.key(), None, &secret_id, vec![1, 2])?;
+        let app = ContractInstanceId::new([3; 32]);
+        assert_eq!(
+            store.get_secret(delegate.key(), Some(&app), &secret_id)?,
+            vec![1, 2]
+        );
+
+        // the secret of the application takes precedence once it has its own
+        store.store_secret(delegate.key(), Some(&app), &secret_id, vec![3])?;
+        assert_eq!(
+            store.get_secret(delegate.key(), Some(&app), &secret_id)?,
+            vec![3]
+        );
+        assert_eq!(
+            store.get_secret(delegate.key(), None, &secret_id)?,
+            vec![1, 2]
+        );
+        Ok(())
+    }
 }
@@ -1,7 +1,15 @@
 use core::future::Future;
+use std::path::PathBuf;
+
 use freenet_stdlib::prelude::*;
 use stretto::AsyncCache;
 
+use crate::contract::storages::{
+    index::{ContractIndex, Resolution},
+    quota::StorageUsage,
+    snapshot::{StateSnapshot, StoredSizes},
+};
+
 #[derive(thiserror::Error, Debug)]
 pub enum StateStoreError {
     #[error(transparent)]
@@ -43,12 +51,34 @@ pub trait StateStorage {
         &'a self,
         key: &'a ContractKey,
     ) -> impl Future<Output = Result<Option<Parameters<'static>>, Self::Error>> + Send + 'a;
+    /// Removes the state and parameters of the contract.
+    fn remove(&mut self, key: &ContractKey)
+        -> impl Future<Output = Result<(), Self::Error>> + Send;
+    /// Reads the size of every state and parameters in the storage, one entry at a time.
+    fn sizes(&self) -> impl Future<Output = Result<StoredSizes, Self::Error>> + Send;
+    /// Writes the whole contents of the storage into a new snapshot file in `dir`, one entry at a
+    /// time, returning the path of the file.
+    ///
+    /// The contents are those at the time of the call, read in a single consistent read; the
+    /// returned future doesn't borrow the storage so it can be awaited outside of the caller.
+    fn write_snapshot(
+        &self,
+        dir: PathBuf,
+        keep: usize,
+    ) -> impl Future<Output = anyhow::Result<PathBuf>> + Send + 'static;
+    /// Replaces the whole contents of the storage with the snapshot contents.
+    fn restore(
+        &mut self,
+        snapshot: StateSnapshot,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
 }
 
 pub struct StateStore<S: StateStorage> {
     state_mem_cache: AsyncCache<ContractKey, WrappedState>,
     // params_mem_cache: AsyncCache<ContractKey, Parameters<'static>>,
     store: S,
+    usage: parking_lot::Mutex<StorageUsage>,
+    index: ContractIndex,
 }
 
 impl<S> StateStore<S>
@@ -68,9 +98,23 @@ where
             // params_mem_cache: AsyncCache::new(counters, max_size as i64)
             //     .map_err(|err| StateStoreError::Any(Box::new(err)))?,
             store,
+            usage: Default::default(),
+            index: Default::default(),
         })
     }
 
+    /// Changes the max number of bytes for the mem cache. The number of counters of the cache is
+    /// sized on creation, so growing it well past the initial size makes it less effective.
+    pub fn set_cache_size(&self, max_size: u64) {
+        self.state_mem_cache
+            .update_max_cost(max_size.min(i64::MAX as u64) as i64);
+    }
+
+    /// The underlying storage, bypassing the memory cache.
+    pub fn storage(&self) -> &S {
+        &self.store
+    }
+
     pub async fn update(
         &mut self,
         key: &ContractKey,
@@ -88,6 +132,8 @@ where
             .store(*key, state.clone())
             .await
             .map_err(Into::into)?;
+        self.usage.get_mut().record(key, state.size(), None);
+        self.index.record(key, state.size());
         let cost = state.size() as i64;
         self.state_mem_cache.insert(*key, state, cost).await;
         Ok(())
@@ -103,18 +149,24 @@ where
             .store(key, state.clone())
             .await
             .map_err(Into::into)?;
-        let cost = state.size() as i64;
+        let state_size = state.size();
+        let cost = state_size as i64;
         self.state_mem_cache.insert(key, state, cost).await;
         self.store
             .store_params(key, params.clone())
             .await
             .map_err(Into::into)?;
+        self.usage
+            .get_mut()
+            .record(&key, state_size, Some(params.as_ref().len()));
+        self.index.record(&key, state_size);
         // let cost = params.size();
         // self.params_mem_cache.insert(key, params, cost as i64).await;
         Ok(())
     }
 
     pub async fn get(&self, key: &ContractKey) -> Result<WrappedState, StateStoreError> {
+        self.usage.lock().touch(key);
         if let Some(v) = self.state_mem_cache.get(key).await {
             return Ok(v.value().clone());
         }
@@ -132,4 +184,55 @@ where
         let r = self.store.get_params(key).await.map_err(Into::into)?;
         Ok(r)
     }
+
+    /// Removes the state and parameters of the contract from the store.
+    pub async fn remove(&mut self, key: &ContractKey) -> Result<(), StateStoreError> {
+        self.store.remove(key).await.map_err(Into::into)?;
+        self.state_mem_cache.remove(key).await;
+        self.usage.get_mut().remove(key);
+        self.index.remove(key);
+        Ok(())
+    }
+
+    /// Resolves a prefix of the encoded id of a contract in the store.
+    pub(crate) fn resolve_prefix(&self, prefix: &str) -> Resolution {
+        self.index.resolve(prefix)
+    }
+
+    /// Disk usage of the contracts in the store.
+    pub(crate) fn usage(&self) -> parking_lot::MutexGuard<'_, StorageUsage> {
+        self.usage.lock()
+    }
+
+    /// Accounts for the contracts already present in the storage, e.g. from a previous run.
+    ///
+    /// Only the sizes of the entries are read, the store is never loaded in memory as a whole.
+    pub async fn load_usage(&mut self) -> Result<(), StateStoreError> {
+        let sizes = self.store.sizes().await.map_err(Into::into)?;
+        *self.usage.get_mut() = StorageUsage::from_sizes(&sizes);
+        self.index.reset(&sizes);
+        Ok(())
+    }
+
+    pub fn write_snapshot(
+        &self,
+        dir: PathBuf,
+        keep: usize,
+    ) -> impl Future<Output = Result<PathBuf, StateStoreError>> + Send + 'static {
+        let written = self.store.write_snapshot(dir, keep);
+        async move { Ok(written.await?) }
+    }
+
+    pub async fn restore(&mut self, snapshot: StateSnapshot) -> Result<(), StateStoreError> {
+        let sizes = StoredSizes::from(&snapshot);
+        let usage = StorageUsage::from_sizes(&sizes);
+        self.store.restore(snapshot).await.map_err(Into::into)?;
+        *self.usage.get_mut() = usage;
+        self.index.reset(&sizes);
+        self.state_mem_cache
+            .clear()
+            .await
+            .map_err(|err| anyhow::anyhow!(err))?;
+        Ok(())
+    }
 }
@@ -0,0 +1,289 @@
+//! Per-contract execution statistics.
+//!
+//! Every call into a contract is recorded along with the time it took, the fuel it consumed when
+//! metering is enabled, and whether it failed. The aggregates are kept process wide, as the
+//! contract runtimes, the admin API and the gateway all need to reach them.
+//!
+//! Only the contracts called most recently are tracked, so the registry stays bounded on nodes
+//! executing many contracts. The metrics are only labelled with the most called ones, the others
+//! are added up under the `other` label.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    time::Duration,
+};
+
+use freenet_stdlib::prelude::ContractInstanceId;
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use serde::Serialize;
+
+/// Most contracts tracked at once.
+const MAX_TRACKED_CONTRACTS: usize = 10_000;
+/// Most contracts the metrics are labelled with.
+const MAX_LABELLED_CONTRACTS: usize = 20;
+
+static STATS: Lazy<RwLock<Registry>> = Lazy::new(Default::default);
+
+#[derive(Default)]
+struct Registry {
+    stats: HashMap<ContractInstanceId, ContractStats>,
+    /// The tracked contracts by their last call, the least recent first.
+    recency: BTreeMap<u64, ContractInstanceId>,
+    /// Calls recorded so far, which orders them.
+    recorded: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct ContractStats {
+    calls: u64,
+    failures: u64,
+    execution_time_us: u64,
+    /// Calls whose consumed fuel is known, only the metered ones.
+    metered_calls: u64,
+    fuel: u64,
+    /// When the contract was last called, in the order of the recorded calls.
+    last_call: u64,
+}
+
+impl ContractStats {
+    fn add(&mut self, other: &ContractStats) {
+        self.calls += other.calls;
+        self.failures += other.failures;
+        self.execution_time_us = self
+            .execution_time_us
+            .saturating_add(other.execution_time_us);
+        self.metered_calls += other.metered_calls;
+        self.fuel = self.fuel.saturating_add(other.fuel);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ContractStatsReport {
+    pub contract: String,
+    pub calls: u64,
+    pub failures: u64,
+    pub failure_rate: f64,
+    pub avg_execution_time_us: u64,
+    /// Not known unless the contract runs metered.
+    pub avg_fuel: Option<u64>,
+}
+
+impl ContractStatsReport {
+    fn new(id: &ContractInstanceId, stats: &ContractStats) -> Self {
+        Self {
+            contract: id.encode(),
+            calls: stats.calls,
+            failures: stats.failures,
+            failure_rate: stats.failures as f64 / stats.calls.max(1) as f64,
+            avg_execution_time_us: stats.execution_time_us / stats.calls.max(1),
+            avg_fuel: (stats.metered_calls > 0).then(|| stats.fuel / stats.metered_calls),
+        }
+    }
+}
+
+impl Registry {
+    fn record(
+        &mut self,
+        id: &ContractInstanceId,
+        elapsed: Duration,
+        fuel: Option<u64>,
+        failed: bool,
+    ) {
+        self.recorded += 1;
+        let call = self.recorded;
+        match self.stats.get(id) {
+            Some(stats) => {
+                self.recency.remove(&stats.last_call);
+            }
+            None if self.stats.len() >= MAX_TRACKED_CONTRACTS => {
+                if let Some((_, least_recent)) = self.recency.pop_first() {
+                    self.stats.remove(&least_recent);
+                }
+            }
+            None => {}
+        }
+        self.recency.insert(call, *id);
+        let stats = self.stats.entry(*id).or_default();
+        stats.calls += 1;
+        stats.failures += u64::from(failed);
+        stats.execution_time_us = stats
+            .execution_time_us
+            .saturating_add(elapsed.as_micros() as u64);
+        if let Some(fuel) = fuel {
+            stats.metered_calls += 1;
+            stats.fuel = stats.fuel.saturating_add(fuel);
+        }
+        stats.last_call = call;
+    }
+}
+
+/// Records a call into a contract.
+pub(super) fn record(id: &ContractInstanceId, elapsed: Duration, fuel: Option<u64>, failed: bool) {
+    STATS.write().record(id, elapsed, fuel, failed);
+}
+
+/// Reports the statistics of every tracked contract, the most called first.
+pub fn contract_stats() -> Vec<ContractStatsReport> {
+    let mut reports = STATS
+        .read()
+        .stats
+        .iter()
+        .map(|(id, stats)| ContractStatsReport::new(id, stats))
+        .collect::<Vec<_>>();
+    reports.sort_by(|a, b| {
+        b.calls
+            .cmp(&a.calls)
+            .then_with(|| a.contract.cmp(&b.contract))
+    });
+    reports
+}
+
+/// Reports the statistics of a contract, if it was called since the node started.
+pub fn contract_stats_of(id: &ContractInstanceId) -> Option<ContractStatsReport> {
+    STATS
+        .read()
+        .stats
+        .get(id)
+        .map(|stats| ContractStatsReport::new(id, stats))
+}
+
+/// Renders the statistics of the tracked contracts in the Prometheus text format, labelled by
+/// contract for the most called ones.
+///
+/// Totals are exported rather than averages, so they can be aggregated over any time range.
+pub fn render_prometheus(out: &mut String) {
+    // copied out, so calls are not held up while rendering
+    let tracked = STATS
+        .read()
+        .stats
+        .iter()
+        .map(|(id, stats)| (*id, *stats))
+        .collect::<Vec<_>>();
+    let labelled = labelled(tracked);
+    let metrics: [(&str, &str, &str, fn(&ContractStats) -> f64); 4] = [
+        (
+            "freenet_contract_calls_total",
+            "counter",
+            "Calls into the contract.",
+            |stats| stats.calls as f64,
+        ),
+        (
+            "freenet_contract_failures_total",
+            "counter",
+            "Calls into the contract which failed.",
+            |stats| stats.failures as f64,
+        ),
+        (
+            "freenet_contract_execution_seconds_total",
+            "counter",
+            "Time spent executing the contract.",
+            |stats| stats.execution_time_us as f64 / 1_000_000.0,
+        ),
+        (
+            "freenet_contract_fuel_total",
+            "counter",
+            "Fuel consumed by the metered calls into the contract.",
+            |stats| stats.fuel as f64,
+        ),
+    ];
+    for (name, kind, help, value) in metrics {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} {kind}");
+        for (contract, stats) in &labelled {
+            let _ = writeln!(out, "{name}{{contract=\"{contract}\"}} {}", value(stats));
+        }
+    }
+}
+
+/// The statistics of the most called contracts, by contract, and those of the others added up.
+fn labelled(mut tracked: Vec<(ContractInstanceId, ContractStats)>) -> Vec<(String, ContractStats)> {
+    tracked.sort_unstable_by_key(|(_, stats)| std::cmp::Reverse(stats.calls));
+    let mut labelled = tracked
+        .iter()
+        .take(MAX_LABELLED_CONTRACTS)
+        .map(|(id, stats)| (id.encode(), *stats))
+        .collect::<Vec<_>>();
+    if tracked.len() > MAX_LABELLED_CONTRACTS {
+        let mut other = ContractStats::default();
+        for (_, stats) in &tracked[MAX_LABELLED_CONTRACTS..] {
+            other.add(stats);
+        }
+        labelled.push(("other".to_owned(), other));
+    }
+    labelled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aggregate_calls() {
+        let id = ContractInstanceId::new([42; 32]);
+        assert_eq!(contract_stats_of(&id), None);
+        record(&id, Duration::from_micros(300), Some(1000), false);
+        record(&id, Duration::from_micros(100), None, true);
+
+        let report = contract_stats_of(&id).unwrap();
+        assert_eq!(report.contract, id.encode());
+        assert_eq!(report.calls, 2);
+        assert_eq!(report.failure_rate, 0.5);
+        assert_eq!(report.avg_execution_time_us, 200);
+        // only the metered call counts towards the fuel
+        assert_eq!(report.avg_fuel, Some(1000));
+
+        let mut rendered = String::new();
+        render_prometheus(&mut rendered);
+        assert!(rendered.contains(&format!(
+            "freenet_contract_calls_total{{contract=\"{}\"}} 2\n",
+            id.encode()
+        )));
+    }
+
+    #[test]
+    fn bound_tracked_contracts() {
+        let id = |i: usize| {
+            let mut id = [0; 32];
+            id[..8].copy_from_slice(&i.to_le_bytes());
+            ContractInstanceId::new(id)
+        };
+        let mut registry = Registry::default();
+        for i in 0..MAX_TRACKED_CONTRACTS {
+            registry.record(&id(i), Duration::from_micros(1), None, false);
+        }
+        // called again, the first contract is no longer the least recent one
+        registry.record(&id(0), Duration::from_micros(1), None, false);
+        registry.record(
+            &id(MAX_TRACKED_CONTRACTS),
+            Duration::from_micros(1),
+            None,
+            false,
+        );
+        assert_eq!(registry.stats.len(), MAX_TRACKED_CONTRACTS);
+        assert_eq!(registry.recency.len(), MAX_TRACKED_CONTRACTS);
+        assert!(registry.stats.contains_key(&id(0)));
+        assert!(!registry.stats.contains_key(&id(1)));
+
+        let tracked = registry
+            .stats
+            .iter()
+            .map(|(id, stats)| (*id, *stats))
+            .collect();
+        let labelled = labelled(tracked);
+        assert_eq!(labelled.len(), MAX_LABELLED_CONTRACTS + 1);
+        // the contract called twice comes first
+        assert_eq!(labelled[0], (id(0).encode(), registry.stats[&id(0)]));
+        let (label, other) = &labelled[MAX_LABELLED_CONTRACTS];
+        assert_eq!(label, "other");
+        assert_eq!(
+            other.calls as usize,
+            MAX_TRACKED_CONTRACTS + 1
+                - labelled[..MAX_LABELLED_CONTRACTS]
+                    .iter()
+                    .map(|(_, stats)| stats.calls as usize)
+                    .sum::<usize>()
+        );
+    }
+}
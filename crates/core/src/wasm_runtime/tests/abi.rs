@@ -0,0 +1,59 @@
+//! Runs the same logical contract compiled against each of the supported host ABIs.
+
+use wasmer::TypedFunction;
+
+use super::{super::Runtime, TestSetup};
+
+/// Logs a message and returns whether the host clock could be read.
+const CONTRACT_V1: &str = r#"
+(module
+  (import "freenet_log" "__frnt__logger__info" (func $info (param i64 i64 i32)))
+  (import "freenet_time" "__frnt__time__utc_now" (func $utc_now (param i64 i64)))
+  (memory (export "memory") 1)
+  (global $id (mut i64) (i64.const -1))
+  (data (i32.const 0) "checking time")
+  (func (export "__frnt_set_id") (param i64) (global.set $id (local.get 0)))
+  (func (export "has_time") (result i32)
+    (call $info (global.get $id) (i64.const 0) (i32.const 13))
+    (call $utc_now (global.get $id) (i64.const 64))
+    (i64.ne (i64.load (i32.const 64)) (i64.const 0))))
+"#;
+
+const CONTRACT_V2: &str = r#"
+(module
+  (import "freenet_log_v2" "__frnt__logger__log" (func $log (param i64 i32 i64 i32)))
+  (import "freenet_time_v2" "__frnt__time__utc_now" (func $utc_now (param i64) (result i64)))
+  (memory (export "memory") 1)
+  (global $id (mut i64) (i64.const -1))
+  (data (i32.const 0) "checking time")
+  (func (export "__frnt_set_id") (param i64) (global.set $id (local.get 0)))
+  (func (export "has_time") (result i32)
+    (call $log (global.get $id) (i32.const 3) (i64.const 0) (i32.const 13))
+    (i64.gt_s (call $utc_now (global.get $id)) (i64.const 0))))
+"#;
+
+fn run_contract(code: &str) -> Result<i32, Box<dyn std::error::Error>> {
+    let TestSetup {
+        contract_store,
+        delegate_store,
+        secrets_store,
+        contract_key,
+        temp_dir: _temp_dir,
+    } = super::setup_wat_contract(code)?;
+    let mut runtime = Runtime::build(contract_store, delegate_store, secrets_store, false)?;
+    let running = runtime.prepare_contract_call(&contract_key, &vec![].into(), 1_000)?;
+    let wasm_store = runtime.wasm_store.as_mut().unwrap();
+    let f: TypedFunction<(), i32> = running
+        .instance
+        .exports
+        .get_function("has_time")?
+        .typed(&*wasm_store)?;
+    Ok(f.call(wasm_store)?)
+}
+
+#[test]
+fn same_contract_on_both_abis() -> Result<(), Box<dyn std::error::Error>> {
+    assert_eq!(run_contract(CONTRACT_V1)?, 1);
+    assert_eq!(run_contract(CONTRACT_V2)?, 1);
+    Ok(())
+}
@@ -0,0 +1,70 @@
+//! Contracts reading the state of other contracts through the host.
+
+use std::sync::Arc;
+
+use freenet_stdlib::prelude::*;
+use wasmer::TypedFunction;
+
+use super::{
+    super::{ContractStateSource, Runtime},
+    TestSetup,
+};
+
+/// Reads the state of the contract whose id is at the start of the memory into offset 64.
+const READER_CONTRACT: &str = r#"
+(module
+  (import "freenet_contract" "__frnt__contract__read_state"
+    (func $read_state (param i64 i64 i64 i32 i64 i32) (result i64)))
+  (memory (export "memory") 1)
+  (global $id (mut i64) (i64.const -1))
+  (func (export "__frnt_set_id") (param i64) (global.set $id (local.get 0)))
+  (func (export "read") (param $cap i32) (result i64)
+    (call $read_state (global.get $id) (i64.const 0) (i64.const 0) (i32.const 0)
+      (i64.const 64) (local.get $cap))))
+"#;
+
+const TARGET: [u8; 32] = [7; 32];
+
+struct Source;
+
+impl ContractStateSource for Source {
+    fn state(&self, key: &ContractKey) -> Option<(Parameters<'static>, WrappedState)> {
+        (**key.id() == TARGET).then(|| (vec![].into(), WrappedState::new(vec![1, 2, 3, 4])))
+    }
+}
+
+#[test]
+fn read_other_contract_state() -> Result<(), Box<dyn std::error::Error>> {
+    let TestSetup {
+        contract_store,
+        delegate_store,
+        secrets_store,
+        contract_key,
+        temp_dir: _temp_dir,
+    } = super::setup_wat_contract(READER_CONTRACT)?;
+    let mut runtime = Runtime::build(contract_store, delegate_store, secrets_store, false)?;
+    runtime.set_state_source(Arc::new(Source));
+
+    let running = runtime.prepare_contract_call(&contract_key, &vec![].into(), 1_000)?;
+    let wasm_store = runtime.wasm_store.as_mut().unwrap();
+    let memory = running.instance.exports.get_memory("memory")?.clone();
+    let read: TypedFunction<i32, i64> = running
+        .instance
+        .exports
+        .get_function("read")?
+        .typed(&*wasm_store)?;
+
+    memory.view(&*wasm_store).write(0, &TARGET)?;
+    // the buffer is too small, only the required size is returned
+    assert_eq!(read.call(wasm_store, 2)?, 4);
+    assert_eq!(memory.view(&*wasm_store).copy_range_to_vec(64..68)?, [0; 4]);
+    assert_eq!(read.call(wasm_store, 64)?, 4);
+    assert_eq!(
+        memory.view(&*wasm_store).copy_range_to_vec(64..68)?,
+        [1, 2, 3, 4]
+    );
+
+    memory.view(&*wasm_store).write(0, &[8; 32])?;
+    assert_eq!(read.call(wasm_store, 64)?, -1);
+    Ok(())
+}
@@ -12,9 +12,12 @@ use crate::util::tests::get_temp_dir;
 
 use super::{ContractStore, DelegateStore, SecretsStore};
 
+mod abi;
 mod contract;
 mod contract_metering;
+mod cross_contract;
 mod time;
+mod validation;
 
 pub(crate) fn get_test_module(name: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
     let module_path = {
@@ -59,16 +62,22 @@ pub(crate) struct TestSetup {
 }
 
 pub(crate) fn setup_test_contract(name: &str) -> Result<TestSetup, Box<dyn std::error::Error>> {
+    setup_contract(get_test_module(name)?)
+}
+
+/// Sets up a contract written in the WASM text format.
+pub(crate) fn setup_wat_contract(code: &str) -> Result<TestSetup, Box<dyn std::error::Error>> {
+    setup_contract(code.as_bytes().to_vec())
+}
+
+fn setup_contract(code: Vec<u8>) -> Result<TestSetup, Box<dyn std::error::Error>> {
     // let _ = tracing_subscriber::fmt().with_env_filter("info").try_init();
     let temp_dir = get_temp_dir();
 
     let mut contract_store = ContractStore::new(temp_dir.path().join("contract"), 10_000)?;
     let delegate_store = DelegateStore::new(temp_dir.path().join("delegate"), 10_000)?;
     let secrets_store = SecretsStore::new(temp_dir.path().join("secrets"), Default::default())?;
-    let contract_bytes = WrappedContract::new(
-        Arc::new(ContractCode::from(get_test_module(name)?)),
-        vec![].into(),
-    );
+    let contract_bytes = WrappedContract::new(Arc::new(ContractCode::from(code)), vec![].into());
     let contract = ContractContainer::Wasm(ContractWasmAPIVersion::V1(contract_bytes));
     let contract_key = contract.key();
     contract_store.store_contract(contract)?;
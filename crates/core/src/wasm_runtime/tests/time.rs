@@ -26,3 +26,51 @@ fn now() -> Result<(), Box<dyn std::error::Error>> {
     std::mem::drop(temp_dir);
     Ok(())
 }
+
+const EPOCH_CONTRACT: &str = r#"
+(module
+  (import "freenet_time" "__frnt__time__epoch" (func $epoch (param i64) (result i64)))
+  (import "freenet_time" "__frnt__time__is_recent_epoch" (func $is_recent (param i64 i64) (result i32)))
+  (memory (export "memory") 1)
+  (global $id (mut i64) (i64.const -1))
+  (func (export "__frnt_set_id") (param i64) (global.set $id (local.get 0)))
+  (func (export "epoch") (result i64) (call $epoch (global.get $id)))
+  (func (export "is_recent") (param i64) (result i32)
+    (call $is_recent (global.get $id) (local.get 0))))
+"#;
+
+#[test]
+fn time_epoch() -> Result<(), Box<dyn std::error::Error>> {
+    let TestSetup {
+        contract_store,
+        delegate_store,
+        secrets_store,
+        contract_key,
+        temp_dir: _temp_dir,
+    } = super::setup_wat_contract(EPOCH_CONTRACT)?;
+    let mut runtime = Runtime::build(contract_store, delegate_store, secrets_store, false)?;
+
+    let module = runtime.prepare_contract_call(&contract_key, &vec![].into(), 1_000)?;
+    let wasm_store = runtime.wasm_store.as_mut().unwrap();
+    let is_recent: TypedFunction<i64, i32> = module
+        .instance
+        .exports
+        .get_function("is_recent")?
+        .typed(&*wasm_store)?;
+    // until the caller passes the time in no epoch is recent
+    assert_eq!(is_recent.call(wasm_store, 0)?, 0);
+
+    let now = chrono::DateTime::from_timestamp(3_600, 0).unwrap();
+    runtime.set_time(now);
+    let wasm_store = runtime.wasm_store.as_mut().unwrap();
+    let epoch: TypedFunction<(), i64> = module
+        .instance
+        .exports
+        .get_function("epoch")?
+        .typed(&*wasm_store)?;
+    let current = epoch.call(wasm_store)?;
+    assert_eq!(current, 60);
+    assert_eq!(is_recent.call(wasm_store, current)?, 1);
+    assert_eq!(is_recent.call(wasm_store, current - 10)?, 0);
+    Ok(())
+}
@@ -0,0 +1,84 @@
+//! Checks of the contracts put by clients.
+
+use std::sync::Arc;
+
+use freenet_stdlib::prelude::*;
+
+use super::{
+    super::{PutRejection, Runtime, RuntimeConfig},
+    TestSetup,
+};
+
+fn contract(code: Vec<u8>) -> ContractContainer {
+    let contract = WrappedContract::new(Arc::new(ContractCode::from(code)), vec![].into());
+    ContractContainer::Wasm(ContractWasmAPIVersion::V1(contract))
+}
+
+#[test]
+fn reject_invalid_contracts() -> Result<(), Box<dyn std::error::Error>> {
+    let TestSetup {
+        contract_store,
+        delegate_store,
+        secrets_store,
+        temp_dir: _temp_dir,
+        ..
+    } = super::setup_wat_contract("(module)")?;
+    let runtime = Runtime::build(contract_store, delegate_store, secrets_store, false)?;
+
+    let empty = b"\0asm\x01\0\0\0".to_vec();
+    assert_eq!(
+        runtime.check_contract(&contract(empty.clone()), &[]),
+        Ok(())
+    );
+    assert_eq!(
+        runtime.check_contract(&contract(b"(module)".to_vec()), &[]),
+        Ok(())
+    );
+    assert!(matches!(
+        runtime.check_contract(&contract(b"\0asm\x01\0\0\0\x01".to_vec()), &[]),
+        Err(PutRejection::InvalidWasm(_))
+    ));
+
+    let mut future_abi = empty;
+    future_abi.extend([0, 13, 11]);
+    future_abi.extend(b"freenet-abi3");
+    assert_eq!(
+        runtime.check_contract(&contract(future_abi), &[]),
+        Err(PutRejection::UnsupportedAbi("3".into()))
+    );
+    Ok(())
+}
+
+#[test]
+fn reject_contracts_over_memory_limit() -> Result<(), Box<dyn std::error::Error>> {
+    let TestSetup {
+        contract_store,
+        delegate_store,
+        secrets_store,
+        temp_dir: _temp_dir,
+        ..
+    } = super::setup_wat_contract("(module)")?;
+    let config = RuntimeConfig {
+        max_memory_pages: 16,
+        ..Default::default()
+    };
+    let runtime =
+        Runtime::build_with_config(contract_store, delegate_store, secrets_store, false, config)?;
+
+    assert_eq!(
+        runtime.check_contract(&contract(b"(module (memory 16))".to_vec()), &[]),
+        Ok(())
+    );
+    assert_eq!(
+        runtime.check_contract(&contract(b"(module (memory 17))".to_vec()), &[]),
+        Err(PutRejection::MemoryLimit { pages: 17, max: 16 })
+    );
+    assert_eq!(
+        runtime.check_contract(
+            &contract(b"(module (import \"env\" \"memory\" (memory 17)))".to_vec()),
+            &[]
+        ),
+        Err(PutRejection::MemoryLimit { pages: 17, max: 16 })
+    );
+    Ok(())
+}
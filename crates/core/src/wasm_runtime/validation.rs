@@ -0,0 +1,122 @@
+//! Checks of the contracts put by clients, before they are propagated.
+//!
+//! A contract which can't run, or whose initial state it rejects itself, would otherwise be
+//! forwarded through the network and fail at whichever peers end up storing it, with the client
+//! only ever seeing the put time out. The checks run on the node the put originates from, so the
+//! client is told right away why it was rejected, and again on the peers storing the contract.
+
+use freenet_stdlib::prelude::*;
+use wasmer::Module;
+
+use super::{
+    abi::AbiVersion, runtime::Runtime, ContractRuntimeInterface, RuntimeInnerError, RuntimeResult,
+};
+
+/// Why a contract put was rejected.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum PutRejection {
+    #[error("unsupported contract API version")]
+    UnsupportedApiVersion,
+    #[error("invalid WASM module: {0}")]
+    InvalidWasm(String),
+    #[error("contract requires {pages} memory pages, at most {max} are allowed")]
+    MemoryLimit { pages: u64, max: u32 },
+    #[error("unsupported host ABI version `{0}`")]
+    UnsupportedAbi(String),
+    #[error("malformed web app bundle: {0}")]
+    MalformedBundle(String),
+    #[error("initial state rejected by the contract")]
+    InvalidState,
+}
+
+impl Runtime {
+    /// Checks the contract code compiles under the configured engine, fits its memory limit and
+    /// targets a supported host ABI, and that the state is well formed if it is a web app bundle,
+    /// without running the contract.
+    pub(crate) fn check_contract(
+        &self,
+        contract: &ContractContainer,
+        state: &[u8],
+    ) -> Result<(), PutRejection> {
+        let ContractContainer::Wasm(ContractWasmAPIVersion::V1(contract)) = contract else {
+            return Err(PutRejection::UnsupportedApiVersion);
+        };
+        // modules in the text format are accepted wherever they are compiled
+        let code = wasmer::wat2wasm(contract.code().data())
+            .map_err(|err| PutRejection::InvalidWasm(err.to_string()))?;
+        Module::validate(self.wasm_store.as_ref().unwrap(), &code)
+            .map_err(|err| PutRejection::InvalidWasm(err.to_string()))?;
+        check_memories(&code, self.max_memory_pages)?;
+        match AbiVersion::declared(&code) {
+            Err(RuntimeInnerError::UnsupportedAbi(version)) => {
+                return Err(PutRejection::UnsupportedAbi(version))
+            }
+            Err(err) => return Err(PutRejection::InvalidWasm(err.to_string())),
+            Ok(_) => {}
+        }
+        #[cfg(feature = "websocket")]
+        crate::server::app_packaging::check_bundle(state)
+            .map_err(|err| PutRejection::MalformedBundle(err.to_string()))?;
+        #[cfg(not(feature = "websocket"))]
+        let _ = state;
+        Ok(())
+    }
+
+    /// Validates the initial state of a contract which may not be stored in this node, without
+    /// storing it.
+    pub(crate) fn validate_initial_state(
+        &mut self,
+        contract: &ContractContainer,
+        state: &WrappedState,
+        related: &RelatedContracts<'_>,
+    ) -> RuntimeResult<ValidateResult> {
+        let key = contract.key();
+        let params = contract.params();
+        let stored = self.contract_store.fetch_contract(&key, &params).is_some();
+        if !stored {
+            self.contract_store.store_contract(contract.clone())?;
+        }
+        let result = self.validate_state(&key, &params, state, related);
+        if !stored {
+            let _ = self.contract_store.remove_contract(&key);
+            self.contract_modules.remove(&key);
+        }
+        result
+    }
+}
+
+/// Checks the memories a module defines or imports start within the memory limit of the engine.
+///
+/// Memories are only grown up to the limit at runtime, so their declared maximum doesn't matter.
+fn check_memories(code: &[u8], max_pages: u32) -> Result<(), PutRejection> {
+    use wasmer::wasmparser::{BinaryReaderError, MemoryType, Parser, Payload, TypeRef};
+
+    let invalid = |err: BinaryReaderError| PutRejection::InvalidWasm(err.to_string());
+    let check = |memory: MemoryType| {
+        if memory.initial > u64::from(max_pages) {
+            return Err(PutRejection::MemoryLimit {
+                pages: memory.initial,
+                max: max_pages,
+            });
+        }
+        Ok(())
+    };
+    for payload in Parser::new(0).parse_all(code) {
+        match payload.map_err(invalid)? {
+            Payload::ImportSection(imports) => {
+                for import in imports {
+                    if let TypeRef::Memory(memory) = import.map_err(invalid)?.ty {
+                        check(memory)?;
+                    }
+                }
+            }
+            Payload::MemorySection(memories) => {
+                for memory in memories {
+                    check(memory.map_err(invalid)?)?;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
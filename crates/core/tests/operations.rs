@@ -57,18 +57,26 @@ async fn base_node_test_config(
         ws_api: WebsocketApiArgs {
             address: Some(Ipv4Addr::LOCALHOST.into()),
             ws_api_port: Some(ws_api_port),
+            ..Default::default()
         },
         network_api: NetworkArgs {
             public_address: Some(Ipv4Addr::LOCALHOST.into()),
             public_port,
             is_gateway,
+            role: None,
             skip_load_from_network: true,
             gateways: Some(gateways),
             location: Some(RNG.lock().unwrap().gen()),
             ignore_protocol_checking: true,
             address: Some(Ipv4Addr::LOCALHOST.into()),
             network_port: public_port,
+            additional_addresses: None,
             bandwidth_limit: None,
+            min_connections: None,
+            max_connections: None,
+            webrtc: false,
+            compression: Default::default(),
+            gossip: Default::default(),
         },
         config_paths: {
             freenet::config::ConfigPathsArgs {
@@ -168,7 +176,7 @@ async fn test_put_contract() -> TestResult {
         let config = config_a.build().await?;
         let node = NodeConfig::new(config.clone())
             .await?
-            .build(serve_gateway(config.ws_api).await)
+            .build(serve_gateway(config.ws_api).await?)
             .await?;
         node.run().await
     }
@@ -180,7 +188,7 @@ async fn test_put_contract() -> TestResult {
         let config = config_b.build().await?;
         let node = NodeConfig::new(config.clone())
             .await?
-            .build(serve_gateway(config.ws_api).await)
+            .build(serve_gateway(config.ws_api).await?)
             .await?;
         node.run().await
     }
@@ -326,7 +334,7 @@ async fn test_update_contract() -> TestResult {
         let config = config_a.build().await?;
         let node = NodeConfig::new(config.clone())
             .await?
-            .build(serve_gateway(config.ws_api).await)
+            .build(serve_gateway(config.ws_api).await?)
             .await?;
         node.run().await
     }
@@ -337,7 +345,7 @@ async fn test_update_contract() -> TestResult {
         let config = config_b.build().await?;
         let node = NodeConfig::new(config.clone())
             .await?
-            .build(serve_gateway(config.ws_api).await)
+            .build(serve_gateway(config.ws_api).await?)
             .await?;
         node.run().await
     }
@@ -0,0 +1,316 @@
+//! End-to-end test harness.
+//!
+//! [`TestNode`] boots a full node, with its HTTP gateway and websocket API, on ephemeral ports so
+//! tests can publish contracts and web apps and then drive them the same way clients do: through
+//! the websocket client API and plain HTTP requests to the gateway.
+//!
+//! Contracts are compiled from the `tests` directory of the repository, which requires the
+//! `wasm32-unknown-unknown` target to be installed and `CARGO_TARGET_DIR` to be set.
+
+use std::{
+    io::Cursor,
+    net::{Ipv4Addr, TcpListener},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Arc,
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context};
+use freenet::{
+    config::{ConfigArgs, ConfigPathsArgs, NetworkArgs, SecretArgs, WebsocketApiArgs},
+    dev_tool::TransportKeypair,
+    local_node::NodeConfig,
+    server::{serve_gateway, WebApp},
+};
+use freenet_stdlib::{
+    client_api::{ClientRequest, ContractRequest, ContractResponse, HostResponse, WebApi},
+    prelude::*,
+};
+use tempfile::TempDir;
+use tokio::sync::oneshot;
+
+/// Time waited for the node to accept connections.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+/// Time waited for the response to a client request.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+const TARGET_DIR_VAR: &str = "CARGO_TARGET_DIR";
+const WASM_TARGET: &str = "wasm32-unknown-unknown";
+
+/// A node running in its own runtime, stopped when dropped.
+pub struct TestNode {
+    ws_api_port: u16,
+    http: reqwest::Client,
+    shutdown: Option<oneshot::Sender<()>>,
+    node: Option<JoinHandle<()>>,
+    data_dir: TempDir,
+}
+
+impl TestNode {
+    /// Starts a gateway node without peers, waiting until its websocket API is up.
+    pub async fn start() -> anyhow::Result<Self> {
+        let data_dir = tempfile::tempdir()?;
+        let keypair_file = data_dir.path().join("private.pem");
+        let keypair = TransportKeypair::new();
+        keypair.save(&keypair_file)?;
+        keypair.public().save(data_dir.path().join("public.pem"))?;
+
+        let network_port = free_port()?;
+        let ws_api_port = free_port()?;
+        let config = ConfigArgs {
+            ws_api: WebsocketApiArgs {
+                address: Some(Ipv4Addr::LOCALHOST.into()),
+                ws_api_port: Some(ws_api_port),
+                ..Default::default()
+            },
+            network_api: NetworkArgs {
+                address: Some(Ipv4Addr::LOCALHOST.into()),
+                network_port: Some(network_port),
+                public_address: Some(Ipv4Addr::LOCALHOST.into()),
+                public_port: Some(network_port),
+                is_gateway: true,
+                skip_load_from_network: true,
+                gateways: Some(vec![]),
+                ignore_protocol_checking: true,
+                ..Default::default()
+            },
+            config_paths: ConfigPathsArgs {
+                config_dir: Some(data_dir.path().to_path_buf()),
+                data_dir: Some(data_dir.path().to_path_buf()),
+            },
+            secrets: SecretArgs {
+                transport_keypair: Some(keypair_file),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let (shutdown, shutdown_rx) = oneshot::channel();
+        let node = std::thread::spawn(move || run_node(config, shutdown_rx));
+        let node = Self {
+            ws_api_port,
+            http: reqwest::Client::new(),
+            shutdown: Some(shutdown),
+            node: Some(node),
+            data_dir,
+        };
+        node.wait_until_ready().await?;
+        Ok(node)
+    }
+
+    async fn wait_until_ready(&self) -> anyhow::Result<()> {
+        let deadline = tokio::time::Instant::now() + STARTUP_TIMEOUT;
+        loop {
+            if tokio::net::TcpStream::connect((Ipv4Addr::LOCALHOST, self.ws_api_port))
+                .await
+                .is_ok()
+            {
+                return Ok(());
+            }
+            if self.node.as_ref().is_some_and(|node| node.is_finished()) {
+                bail!("node stopped while starting");
+            }
+            if tokio::time::Instant::now() >= deadline {
+                bail!("node did not start in {STARTUP_TIMEOUT:?}");
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        self.data_dir.path()
+    }
+
+    /// URL of a path of the HTTP gateway.
+    pub fn gateway_url(&self, path: &str) -> String {
+        format!("http://127.0.0.1:{}{path}", self.ws_api_port)
+    }
+
+    /// URL of a file of the web app of a contract, the app entry point if the path is empty.
+    pub fn web_app_url(&self, key: &ContractKey, path: &str) -> String {
+        self.gateway_url(&format!(
+            "/v1/contract/web/{}/{path}",
+            key.encoded_contract_id()
+        ))
+    }
+
+    pub fn http(&self) -> &reqwest::Client {
+        &self.http
+    }
+
+    /// Opens a connection to the websocket client API.
+    pub async fn client(&self) -> anyhow::Result<WebApi> {
+        let uri = format!(
+            "ws://127.0.0.1:{}/v1/contract/command?encodingProtocol=native",
+            self.ws_api_port
+        );
+        let (stream, _) = tokio_tungstenite::connect_async(&uri).await?;
+        Ok(WebApi::start(stream))
+    }
+
+    /// Puts a contract through the client API, waiting until the put completes.
+    pub async fn publish(
+        &self,
+        contract: ContractContainer,
+        state: WrappedState,
+    ) -> anyhow::Result<ContractKey> {
+        let mut client = self.client().await?;
+        let expected = contract.key();
+        client
+            .send(ClientRequest::ContractOp(ContractRequest::Put {
+                contract,
+                state,
+                related_contracts: RelatedContracts::default(),
+            }))
+            .await?;
+        match recv(&mut client).await? {
+            HostResponse::ContractResponse(ContractResponse::PutResponse { key })
+                if key == expected =>
+            {
+                Ok(key)
+            }
+            other => Err(anyhow!("unexpected response to put: {other:?}")),
+        }
+    }
+}
+
+impl Drop for TestNode {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(node) = self.node.take() {
+            let _ = node.join();
+        }
+    }
+}
+
+fn run_node(config: ConfigArgs, shutdown: oneshot::Receiver<()>) {
+    let runtime = match tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(err) => {
+            tracing::error!("failed starting the node runtime: {err}");
+            return;
+        }
+    };
+    runtime.block_on(async move {
+        let node = async {
+            let config = config.build().await?;
+            let node = NodeConfig::new(config.clone())
+                .await?
+                .build(serve_gateway(config.ws_api).await?)
+                .await?;
+            node.run().await
+        };
+        tokio::select! {
+            result = node => {
+                let Err(err) = result;
+                tracing::error!("test node failed: {err}");
+            }
+            _ = shutdown => {}
+        }
+    });
+    runtime.shutdown_timeout(Duration::from_secs(1));
+}
+
+fn free_port() -> anyhow::Result<u16> {
+    Ok(TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?
+        .local_addr()?
+        .port())
+}
+
+/// Waits for the next response received by a client.
+pub async fn recv(client: &mut WebApi) -> anyhow::Result<HostResponse> {
+    tokio::time::timeout(RESPONSE_TIMEOUT, client.recv())
+        .await
+        .map_err(|_| anyhow!("timed out waiting for a response"))?
+        .map_err(|err| anyhow!("error response: {err}"))
+}
+
+/// Files of a web app, packed as the state of its container contract.
+#[derive(Default)]
+pub struct WebBundle {
+    metadata: Vec<u8>,
+    files: Vec<(String, Vec<u8>)>,
+}
+
+impl WebBundle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bundle metadata, e.g. the app manifest.
+    pub fn metadata(mut self, metadata: impl Into<Vec<u8>>) -> Self {
+        self.metadata = metadata.into();
+        self
+    }
+
+    /// Adds a file, at a path relative to the root of the app.
+    pub fn file(mut self, path: &str, content: impl Into<Vec<u8>>) -> Self {
+        self.files.push((path.to_owned(), content.into()));
+        self
+    }
+
+    pub fn pack(self) -> anyhow::Result<WrappedState> {
+        if !self.files.iter().any(|(path, _)| path == "index.html") {
+            bail!("web bundle without entry point (index.html)");
+        }
+        let mut archive = tar::Builder::new(Cursor::new(Vec::new()));
+        for (path, content) in &self.files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            archive.append_data(&mut header, path, content.as_slice())?;
+        }
+        let state = WebApp::from_data(self.metadata, archive)?.pack()?;
+        Ok(WrappedState::from(state))
+    }
+}
+
+/// Compiles a contract crate of the `tests` directory of the repository.
+pub fn load_contract(
+    crate_path: &str,
+    params: Parameters<'static>,
+) -> anyhow::Result<ContractContainer> {
+    let code = compile_contract(crate_path)?;
+    let contract = WrappedContract::new(Arc::new(ContractCode::from(code)), params);
+    Ok(ContractContainer::Wasm(ContractWasmAPIVersion::V1(
+        contract,
+    )))
+}
+
+fn compile_contract(crate_path: &str) -> anyhow::Result<Vec<u8>> {
+    const TESTS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../tests/");
+    let crate_dir = PathBuf::from(TESTS_DIR).join(crate_path);
+    let target =
+        std::env::var(TARGET_DIR_VAR).map_err(|_| anyhow!("{TARGET_DIR_VAR} should be set"))?;
+    let status = Command::new("cargo")
+        .args(["build", "--lib", "--target", WASM_TARGET])
+        .args(["--features", "freenet-main-contract"])
+        .current_dir(&crate_dir)
+        .status()
+        .context("failed running cargo")?;
+    if !status.success() {
+        bail!("failed compiling {crate_path}: {status}");
+    }
+
+    let manifest = std::fs::read_to_string(crate_dir.join("Cargo.toml"))?;
+    let name = manifest
+        .lines()
+        .find_map(|line| {
+            let name = line.trim().strip_prefix("name")?.trim().strip_prefix('=')?;
+            Some(name.trim().trim_matches('"').replace('-', "_"))
+        })
+        .ok_or_else(|| anyhow!("missing crate name in {crate_path}"))?;
+    let output = Path::new(&target)
+        .join(WASM_TARGET)
+        .join("debug")
+        .join(name)
+        .with_extension("wasm");
+    std::fs::read(&output).with_context(|| format!("failed reading {}", output.display()))
+}
@@ -0,0 +1,85 @@
+//! Web apps published to a node and served by its HTTP gateway.
+
+use freenet_e2e::{load_contract, recv, TestNode, WebBundle};
+use freenet_stdlib::{
+    client_api::{ClientRequest, ContractRequest, ContractResponse, HostResponse},
+    prelude::*,
+};
+use reqwest::{header, StatusCode};
+use testresult::TestResult;
+
+/// Container contract accepting any web bundle as its state.
+const WEB_CONTAINER: &str = "test-app-1/container";
+const INDEX: &str = "<!doctype html><html><body><p>e2e web app</p></body></html>";
+const SCRIPT: &str = "console.log('e2e web app');";
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serve_published_web_app() -> TestResult {
+    let node = TestNode::start().await?;
+    let contract = load_contract(WEB_CONTAINER, Parameters::from(vec![]))?;
+    let state = WebBundle::new()
+        .file("index.html", INDEX)
+        .file("app.js", SCRIPT)
+        .pack()?;
+    let key = node.publish(contract, state.clone()).await?;
+
+    // the state is available through the client API
+    let mut client = node.client().await?;
+    client
+        .send(ClientRequest::ContractOp(ContractRequest::Get {
+            key,
+            return_contract_code: false,
+        }))
+        .await?;
+    match recv(&mut client).await? {
+        HostResponse::ContractResponse(ContractResponse::GetResponse {
+            state: fetched, ..
+        }) => assert_eq!(fetched, state),
+        other => panic!("unexpected response to get: {other:?}"),
+    }
+
+    // the app entry point is unpacked and served by the gateway
+    let home = node.http().get(node.web_app_url(&key, "")).send().await?;
+    assert_eq!(home.status(), StatusCode::OK);
+    let etag = home
+        .headers()
+        .get(header::ETAG)
+        .cloned()
+        .expect("entry point etag");
+    assert!(home.headers().contains_key(header::CACHE_CONTROL));
+    assert_eq!(home.text().await?, INDEX);
+
+    let revalidated = node
+        .http()
+        .get(node.web_app_url(&key, ""))
+        .header(header::IF_NONE_MATCH, etag)
+        .send()
+        .await?;
+    assert_eq!(revalidated.status(), StatusCode::NOT_MODIFIED);
+
+    // and so are its assets, once unpacked
+    let script = node
+        .http()
+        .get(node.web_app_url(&key, "app.js"))
+        .send()
+        .await?;
+    assert_eq!(script.status(), StatusCode::OK);
+    assert_eq!(script.headers()[header::CONTENT_TYPE], "text/javascript");
+    assert_eq!(script.text().await?, SCRIPT);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reject_invalid_contract_key() -> TestResult {
+    let node = TestNode::start().await?;
+    let response = node
+        .http()
+        .get(node.gateway_url("/v1/contract/web/not-a-key/"))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = response.json().await?;
+    assert_eq!(body["code"], "invalid-param");
+    Ok(())
+}
@@ -44,6 +44,7 @@ pub enum SubCommand {
     Publish(PutConfig),
     /// Query the local node for information. Currently only shows open connections.
     Query {},
+    Trace(crate::trace::TraceConfig),
     WasmRuntime(ExecutorConfig),
     Execute(RunCliConfig),
     Test(crate::testing::TestConfig),
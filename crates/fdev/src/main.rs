@@ -11,6 +11,7 @@ pub(crate) mod network_metrics_server;
 mod new_package;
 mod query;
 mod testing;
+mod trace;
 mod util;
 mod wasm_runtime;
 
@@ -71,6 +72,10 @@ fn main() -> anyhow::Result<()> {
                 query::query(config.additional).await?;
                 Ok(())
             }
+            SubCommand::Trace(trace_config) => {
+                trace::trace(trace_config).await?;
+                Ok(())
+            }
         };
         // todo: make all commands return concrete `thiserror` compatible errors so we can use anyhow
         r.map_err(|e| anyhow::format_err!(e))
@@ -0,0 +1,94 @@
+use std::net::SocketAddr;
+
+use chrono::{DateTime, FixedOffset};
+use prettytable::{Cell, Row, Table};
+use serde::Deserialize;
+
+/// Reconstructs the path followed by a transaction across peers.
+///
+/// Queries the admin API of each of the given nodes for the events of the transaction they
+/// recorded, and prints all of them in order.
+#[derive(clap::Parser, Clone)]
+pub struct TraceConfig {
+    /// Id of the transaction to trace.
+    pub(crate) transaction: String,
+    /// Admin API address of the nodes to query.
+    #[arg(long = "admin-api", required = true)]
+    pub(crate) admin_apis: Vec<SocketAddr>,
+    /// Admin token of each of the nodes, in the same order as their admin API addresses. A node
+    /// writes its token to the `admin-token` file of its config directory.
+    #[arg(long = "admin-token", required = true)]
+    pub(crate) admin_tokens: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct TraceEvent {
+    datetime: String,
+    peer: String,
+    event: String,
+    from: Option<String>,
+    to: Option<String>,
+    contract: Option<String>,
+}
+
+pub async fn trace(config: TraceConfig) -> anyhow::Result<()> {
+    if config.admin_tokens.len() != config.admin_apis.len() {
+        anyhow::bail!("expected one admin token for each admin API address");
+    }
+    let client = reqwest::Client::new();
+    let mut events: Vec<(DateTime<FixedOffset>, TraceEvent)> = vec![];
+    for (admin_api, admin_token) in config.admin_apis.iter().zip(&config.admin_tokens) {
+        let url = format!(
+            "http://{admin_api}/v1/admin/transactions/{}/trace",
+            config.transaction
+        );
+        let response = match client
+            .get(&url)
+            .bearer_auth(admin_token.trim())
+            .send()
+            .await
+        {
+            Ok(response) => response.error_for_status(),
+            Err(err) => Err(err),
+        };
+        let node_events: Vec<TraceEvent> = match response {
+            Ok(response) => response.json().await?,
+            Err(err) => {
+                tracing::warn!(%admin_api, "Failed querying node: {err}");
+                continue;
+            }
+        };
+        for event in node_events {
+            let datetime = DateTime::parse_from_rfc3339(&event.datetime)?;
+            events.push((datetime, event));
+        }
+    }
+    events.sort_by_key(|(datetime, _)| *datetime);
+
+    let mut table = Table::new();
+    table.add_row(Row::new(vec![
+        Cell::new("Time"),
+        Cell::new("Peer"),
+        Cell::new("Event"),
+        Cell::new("From"),
+        Cell::new("To"),
+        Cell::new("Contract"),
+    ]));
+    let start = events.first().map(|(datetime, _)| *datetime);
+    for (datetime, event) in &events {
+        let elapsed = start
+            .map(|start| (*datetime - start).num_milliseconds())
+            .unwrap_or_default();
+        table.add_row(Row::new(vec![
+            Cell::new(&format!("+{elapsed}ms")),
+            Cell::new(&event.peer),
+            Cell::new(&event.event),
+            Cell::new(event.from.as_deref().unwrap_or("-")),
+            Cell::new(event.to.as_deref().unwrap_or("-")),
+            Cell::new(event.contract.as_deref().unwrap_or("-")),
+        ]));
+    }
+    table.printstd();
+    Ok(())
+}